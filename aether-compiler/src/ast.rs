@@ -3,23 +3,31 @@
 //! Complete AST for all Aether constructs
 
 use std::fmt;
+use serde::Serialize;
 
 /// Source location
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, Serialize)]
 pub struct Span {
     pub line: usize,
     pub col: usize,
 }
 
 /// Type representation
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub enum Type {
     /// Named type: Int, String, MyStruct
     Named(String),
-    /// Pointer type: *T
-    Ptr(Box<Type>),
+    /// Reference with an elided lifetime: &T, or &mut T when the flag is set
+    Ptr(Box<Type>, bool),
+    /// Raw pointer: *T (dereferenced only in unsafe code)
+    RawPtr(Box<Type>),
+    /// Reference with an explicit lifetime: &'a T, or &'a mut T when the flag is set
+    Ref(String, Box<Type>, bool),
     /// Array type: [T; N] or [T]
     Array(Box<Type>, Option<usize>),
+    /// Array whose length is a constant expression: [T; SIZE * 2]
+    /// (replaced by `Array` during constant evaluation)
+    ConstArray(Box<Type>, Box<Expr>),
     /// Generic type: Option<T>, Result<T, E>
     Generic(String, Vec<Type>),
     /// Function type: func(A, B) -> C
@@ -38,15 +46,66 @@ impl Type {
     pub fn is_bool(&self) -> bool {
         matches!(self, Type::Named(n) if n == "Bool")
     }
+
+    /// Float or Float32, which travel as the bits of a double
+    pub fn is_float(&self) -> bool {
+        matches!(self, Type::Named(n) if n == "Float" || n == "Float32")
+    }
+    
+    /// Type a pointer or reference points at
+    pub fn pointee(&self) -> Option<&Type> {
+        match self {
+            Type::Ptr(inner, _) | Type::Ref(_, inner, _) | Type::RawPtr(inner) => Some(inner),
+            _ => None,
+        }
+    }
+    
+    /// `&mut T` or `&'a mut T`
+    pub fn is_mut_ref(&self) -> bool {
+        matches!(self, Type::Ptr(_, true) | Type::Ref(_, _, true))
+    }
+    
+    /// Value type of an atomic type: `AtomicInt` -> `Int`, `Atomic<Bool>` -> `Bool`
+    pub fn atomic_value(&self) -> Option<Type> {
+        match self {
+            Type::Named(n) => {
+                let inner = n.strip_prefix("Atomic")?;
+                matches!(inner, "Int" | "Int8" | "Int16" | "Int32" | "Int64" |
+                    "UInt8" | "UInt16" | "UInt32" | "UInt64" | "Bool")
+                    .then(|| Type::Named(inner.to_string()))
+            }
+            Type::Generic(n, args) if n == "Atomic" && args.len() == 1 => Some(args[0].clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Replace `Self` with the implementing type
+pub fn with_self(ty: &Type, self_type: &str) -> Type {
+    let sub = |t: &Type| Box::new(with_self(t, self_type));
+    match ty {
+        Type::Named(n) if n == "Self" => Type::Named(self_type.to_string()),
+        Type::Ptr(t, m) => Type::Ptr(sub(t), *m),
+        Type::RawPtr(t) => Type::RawPtr(sub(t)),
+        Type::Ref(l, t, m) => Type::Ref(l.clone(), sub(t), *m),
+        Type::Array(t, n) => Type::Array(sub(t), *n),
+        Type::Generic(n, args) => Type::Generic(n.clone(), args.iter().map(|a| with_self(a, self_type)).collect()),
+        other => other.clone(),
+    }
 }
 
 impl fmt::Display for Type {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Type::Named(n) => write!(f, "{}", n),
-            Type::Ptr(t) => write!(f, "*{}", t),
+            Type::Ptr(t, false) => write!(f, "&{}", t),
+            Type::Ptr(t, true) => write!(f, "&mut {}", t),
+            Type::RawPtr(t) => write!(f, "*{}", t),
+            Type::Ref(lifetime, t, false) => write!(f, "&{} {}", lifetime, t),
+            Type::Ref(lifetime, t, true) => write!(f, "&{} mut {}", lifetime, t),
             Type::Array(t, Some(n)) => write!(f, "[{}; {}]", t, n),
             Type::Array(t, None) => write!(f, "[{}]", t),
+            Type::ConstArray(t, n) => write!(f, "[{}; {}]", t, crate::tooling::fmt::print_expr(n)),
             Type::Generic(name, args) => {
                 write!(f, "{}<", name)?;
                 for (i, arg) in args.iter().enumerate() {
@@ -74,7 +133,7 @@ impl fmt::Display for Type {
 }
 
 /// Binary operators
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub enum BinOp {
     Add, Sub, Mul, Div, Mod,
     Eq, Ne, Lt, Le, Gt, Ge,
@@ -82,13 +141,13 @@ pub enum BinOp {
 }
 
 /// Unary operators
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub enum UnOp {
-    Neg, Not, BitNot, Ref, Deref,
+    Neg, Not, BitNot, Ref, RefMut, Deref,
 }
 
 /// Expression
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub enum Expr {
     /// Integer literal
     Int(i64, Span),
@@ -126,6 +185,10 @@ pub enum Expr {
     Path(Vec<String>, Span),
     /// Spawn thread: spawn(func, args)
     Spawn(Box<Expr>, Vec<Expr>, Span),
+    /// Compile-time block: comptime { ... }
+    Comptime(Box<Block>, Span),
+    /// Unsafe block: unsafe { ... }
+    Unsafe(Box<Block>, Span),
 }
 
 impl Expr {
@@ -136,13 +199,14 @@ impl Expr {
             Expr::Unary(_, _, s) | Expr::Call(_, _, s) | Expr::Field(_, _, s) |
             Expr::Index(_, _, s) | Expr::Array(_, s) | Expr::Struct(_, _, s) |
             Expr::If(_, _, _, s) | Expr::Lambda(_, _, _, s) | Expr::Match(_, _, s) |
-            Expr::MethodCall(_, _, _, s) | Expr::Path(_, s) | Expr::Spawn(_, _, s) => *s,
+            Expr::MethodCall(_, _, _, s) | Expr::Path(_, s) | Expr::Spawn(_, _, s) |
+            Expr::Comptime(_, s) | Expr::Unsafe(_, s) => *s,
         }
     }
 }
 
 /// Match arm
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct MatchArm {
     pub pattern: Pattern,
     pub guard: Option<Expr>,
@@ -150,7 +214,7 @@ pub struct MatchArm {
 }
 
 /// Pattern for matching
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub enum Pattern {
     Wildcard,
     Ident(String),
@@ -161,16 +225,20 @@ pub enum Pattern {
 }
 
 /// Function parameter
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Param {
     pub name: String,
     pub ty: Type,
     pub default: Option<Expr>,
+    /// `comptime` parameter: the argument must be known at compile time
+    pub comptime: bool,
+    /// `mut x: T` (may be reassigned), or `&mut self`
+    pub mutable: bool,
     pub span: Span,
 }
 
 /// Statement
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub enum Stmt {
     /// Let binding
     Let {
@@ -201,14 +269,14 @@ pub enum Stmt {
 }
 
 /// Block of statements
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Block {
     pub stmts: Vec<Stmt>,
     pub span: Span,
 }
 
 /// Struct field
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Field {
     pub name: String,
     pub ty: Type,
@@ -217,15 +285,34 @@ pub struct Field {
 }
 
 /// Enum variant
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Variant {
     pub name: String,
     pub fields: Vec<Type>,
     pub span: Span,
 }
 
+/// Attribute: #[export], #[name(arg, ...)]
+#[derive(Debug, Clone, Serialize)]
+pub struct Attribute {
+    pub name: String,
+    pub args: Vec<String>,
+    pub span: Span,
+}
+
+/// Foreign function signature inside an `extern` block
+#[derive(Debug, Clone, Serialize)]
+pub struct ExternFunc {
+    pub name: String,
+    pub params: Vec<Param>,
+    pub ret: Option<Type>,
+    /// Trailing `...` (C varargs)
+    pub variadic: bool,
+    pub span: Span,
+}
+
 /// Declaration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub enum Decl {
     /// Function declaration
     Func {
@@ -235,6 +322,11 @@ pub enum Decl {
         ret: Option<Type>,
         body: Block,
         public: bool,
+        /// `const func`: callable during constant evaluation
+        constant: bool,
+        /// `unsafe func`: callable only from unsafe code
+        is_unsafe: bool,
+        attrs: Vec<Attribute>,
         span: Span,
     },
     /// Struct declaration
@@ -243,6 +335,7 @@ pub enum Decl {
         generics: Vec<String>,
         fields: Vec<Field>,
         public: bool,
+        attrs: Vec<Attribute>,
         span: Span,
     },
     /// Enum declaration
@@ -267,6 +360,8 @@ pub enum Decl {
         type_name: String,
         generics: Vec<String>,
         methods: Vec<Decl>,
+        /// `unsafe impl`: vouches for what the compiler cannot check (`Send`, `Sync`)
+        is_unsafe: bool,
         span: Span,
     },
     /// Constant
@@ -298,6 +393,14 @@ pub enum Decl {
         value: Option<Expr>,
         mutable: bool,
         public: bool,
+        /// `#[thread_local]`
+        attrs: Vec<Attribute>,
+        span: Span,
+    },
+    /// Foreign function block: extern "C" { func f(x: Int32) -> Int32 }
+    Extern {
+        abi: Option<String>,
+        funcs: Vec<ExternFunc>,
         span: Span,
     },
 }
@@ -310,14 +413,109 @@ impl Decl {
             Decl::Const { name, .. } | Decl::TypeAlias { name, .. } |
             Decl::Static { name, .. } => Some(name),
             Decl::Impl { type_name, .. } => Some(type_name),
-            Decl::Import { .. } => None,
+            Decl::Import { .. } | Decl::Extern { .. } => None,
         }
     }
+    
+    pub fn attrs(&self) -> &[Attribute] {
+        match self {
+            Decl::Func { attrs, .. } | Decl::Struct { attrs, .. } | Decl::Static { attrs, .. } => attrs,
+            _ => &[],
+        }
+    }
+    
+    pub fn has_attr(&self, name: &str) -> bool {
+        self.attrs().iter().any(|a| a.name == name)
+    }
 }
 
 /// Module (compilation unit)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Module {
     pub decls: Vec<Decl>,
     pub span: Span,
 }
+
+/// `name = name op v` with `op` one of `+ - & | ^`, or `v op name` when `op`
+/// commutes: the operator and `v`. Backends lower this update of an atomic
+/// static to one atomic read-modify-write.
+pub fn atomic_update<'a>(name: &str, value: &'a Expr) -> Option<(BinOp, &'a Expr)> {
+    let Expr::Binary(op, l, r, _) = value else { return None };
+    let is_name = |e: &Expr| matches!(e, Expr::Ident(n, _) if n == name);
+    match op {
+        BinOp::Add | BinOp::Sub | BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor if is_name(l) => Some((*op, r)),
+        BinOp::Add | BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor if is_name(r) => Some((*op, l)),
+        _ => None,
+    }
+}
+
+/// Identifiers an expression mentions, in order of first use
+pub fn free_idents(e: &Expr, out: &mut Vec<String>) {
+    let mut add = |name: &String| {
+        if !out.contains(name) {
+            out.push(name.clone());
+        }
+    };
+    match e {
+        Expr::Ident(name, _) => add(name),
+        Expr::Binary(_, l, r, _) | Expr::Index(l, r, _) => {
+            free_idents(l, out);
+            free_idents(r, out);
+        }
+        Expr::Unary(_, e, _) | Expr::Field(e, _, _) | Expr::Lambda(_, _, e, _) => free_idents(e, out),
+        Expr::Call(f, args, _) | Expr::Spawn(f, args, _) => {
+            free_idents(f, out);
+            args.iter().for_each(|a| free_idents(a, out));
+        }
+        Expr::MethodCall(obj, _, args, _) => {
+            free_idents(obj, out);
+            args.iter().for_each(|a| free_idents(a, out));
+        }
+        Expr::Array(elems, _) => elems.iter().for_each(|a| free_idents(a, out)),
+        Expr::Struct(_, fields, _) => fields.iter().for_each(|(_, a)| free_idents(a, out)),
+        Expr::If(cond, then_block, else_block, _) => {
+            free_idents(cond, out);
+            free_idents_block(then_block, out);
+            if let Some(b) = else_block {
+                free_idents_block(b, out);
+            }
+        }
+        Expr::Match(scrutinee, arms, _) => {
+            free_idents(scrutinee, out);
+            for arm in arms {
+                if let Some(guard) = &arm.guard {
+                    free_idents(guard, out);
+                }
+                free_idents(&arm.body, out);
+            }
+        }
+        Expr::Unsafe(b, _) | Expr::Comptime(b, _) => free_idents_block(b, out),
+        _ => {}
+    }
+}
+
+/// Identifiers the statements of a block mention, in order of first use
+pub fn free_idents_block(block: &Block, out: &mut Vec<String>) {
+    for stmt in &block.stmts {
+        match stmt {
+            Stmt::Let { init: Some(e), .. } | Stmt::Expr(e, _) | Stmt::Return(Some(e), _) => free_idents(e, out),
+            Stmt::Assign(target, value, _) => {
+                free_idents(target, out);
+                free_idents(value, out);
+            }
+            Stmt::If(cond, then_block, else_block, _) => {
+                free_idents(cond, out);
+                free_idents_block(then_block, out);
+                if let Some(b) = else_block {
+                    free_idents_block(b, out);
+                }
+            }
+            Stmt::While(cond, body, _) | Stmt::For(_, cond, body, _) => {
+                free_idents(cond, out);
+                free_idents_block(body, out);
+            }
+            Stmt::Block(b, _) => free_idents_block(b, out),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,550 @@
+//! AArch64 instruction encoding
+
+use anyhow::{anyhow, bail, Result};
+use crate::binary::object::RelocKind;
+use super::{parse_int, parse_symbol, Encoded, Fixup};
+
+/// General-purpose register: number 31 is `sp` or the zero register by context
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Reg {
+    num: u32,
+    wide: bool,
+    sp: bool,
+}
+
+fn register(name: &str) -> Option<Reg> {
+    let name = name.trim().to_ascii_lowercase();
+    let reg = |num, wide, sp| Some(Reg { num, wide, sp });
+    match name.as_str() {
+        "sp" => return reg(31, true, true),
+        "wsp" => return reg(31, false, true),
+        "xzr" => return reg(31, true, false),
+        "wzr" => return reg(31, false, false),
+        "fp" => return reg(29, true, false),
+        "lr" => return reg(30, true, false),
+        _ => {}
+    }
+    let wide = match name.as_bytes().first()? {
+        b'x' => true,
+        b'w' => false,
+        _ => return None,
+    };
+    let num = name[1..].parse::<u32>().ok().filter(|&n| n < 31)?;
+    reg(num, wide, false)
+}
+
+/// Floating-point register `dN` or `sN` -> (number, double)
+fn fp_register(name: &str) -> Option<(u32, bool)> {
+    let name = name.trim();
+    let double = match name.as_bytes().first()? {
+        b'd' => true,
+        b's' => false,
+        _ => return None,
+    };
+    name[1..].parse::<u32>().ok().filter(|&n| n < 32).map(|n| (n, double))
+}
+
+fn reg(text: &str) -> Result<Reg> {
+    register(text).ok_or_else(|| anyhow!("expected a register, found `{}`", text))
+}
+
+/// `#imm` (the `#` is optional)
+fn immediate(text: &str) -> Result<i64> {
+    parse_int(text.trim().trim_start_matches('#'))
+}
+
+fn condition(cc: &str) -> Result<u32> {
+    Ok(match cc {
+        "eq" => 0,
+        "ne" => 1,
+        "cs" | "hs" => 2,
+        "cc" | "lo" => 3,
+        "mi" => 4,
+        "pl" => 5,
+        "vs" => 6,
+        "vc" => 7,
+        "hi" => 8,
+        "ls" => 9,
+        "ge" => 10,
+        "lt" => 11,
+        "gt" => 12,
+        "le" => 13,
+        "al" => 14,
+        _ => bail!("unknown condition {}", cc),
+    })
+}
+
+/// Operand with a relocation modifier: `:lo12:sym`, `#:tprel_hi12:sym`, ...
+fn modifier(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim().trim_start_matches('#').strip_prefix(':')?;
+    text.split_once(':')
+}
+
+/// `N:immr:imms` encoding of a logical immediate
+fn bitmask(value: u64, wide: bool) -> Option<u32> {
+    let width = if wide { 64 } else { 32 };
+    let value = if wide { value } else { value & 0xffff_ffff };
+    let all = if wide { u64::MAX } else { 0xffff_ffff };
+    if value == 0 || value == all {
+        return None;
+    }
+    let mut size = width;
+    while size > 2 {
+        let half = size / 2;
+        let mask = (1u64 << half) - 1;
+        if value & mask != (value >> half) & mask {
+            break;
+        }
+        size = half;
+    }
+    let mask = if size == 64 { u64::MAX } else { (1u64 << size) - 1 };
+    let elem = value & mask;
+    let ones = elem.count_ones();
+    // Rotation that turns the element into a run of ones starting at bit 0
+    let rotate = (0..size).find(|&r| {
+        let rotated = if r == 0 { elem } else { ((elem >> r) | (elem << (size - r))) & mask };
+        rotated == (1u64 << ones) - 1
+    })?;
+    let immr = (size - rotate) % size;
+    let n = u32::from(size == 64);
+    let imms = ((!(size - 1) << 1) | (ones - 1)) & 0x3f;
+    Some(n << 22 | immr << 16 | imms << 10)
+}
+
+struct Out {
+    enc: Encoded,
+}
+
+impl Out {
+    fn word(&mut self, insn: u32) {
+        self.enc.bytes.extend_from_slice(&insn.to_le_bytes());
+    }
+
+    fn fixup(&mut self, kind: RelocKind, target: &str) -> Result<()> {
+        let (symbol, addend) = parse_symbol(target)?;
+        self.enc.fixups.push(Fixup { offset: self.enc.bytes.len(), kind, symbol, addend });
+        Ok(())
+    }
+}
+
+fn sf(r: Reg) -> u32 {
+    u32::from(r.wide) << 31
+}
+
+/// `movz`/`movn`/`orr` for `mov rd, #imm`
+fn mov_imm(rd: Reg, value: i64) -> Result<u32> {
+    let bits = if rd.wide { value as u64 } else { value as u64 & 0xffff_ffff };
+    let halves = if rd.wide { 4 } else { 2 };
+    let mask = if rd.wide { u64::MAX } else { 0xffff_ffff };
+    for hw in 0..halves {
+        let shift = 16 * hw;
+        if bits & !(0xffff << shift) == 0 {
+            return Ok(sf(rd) | 0x5280_0000 | hw << 21 | ((bits >> shift) as u32 & 0xffff) << 5 | rd.num);
+        }
+        let inverted = !bits & mask;
+        if inverted & !(0xffff << shift) == 0 {
+            return Ok(sf(rd) | 0x1280_0000 | hw << 21 | ((inverted >> shift) as u32 & 0xffff) << 5 | rd.num);
+        }
+    }
+    match bitmask(bits, rd.wide) {
+        Some(enc) => Ok(sf(rd) | 0x3200_0000 | enc | 31 << 5 | rd.num),
+        None => bail!("immediate {} needs more than one instruction", value),
+    }
+}
+
+/// Optional `lsl #n` / `asr #n` / `lsr #n` operand of shifted-register forms -> (type, amount)
+fn shift(op: Option<&str>) -> Result<(u32, u32)> {
+    let Some(op) = op else { return Ok((0, 0)) };
+    let (kind, amount) = op.trim().split_once(char::is_whitespace).ok_or_else(|| anyhow!("bad shift `{}`", op))?;
+    let kind = match kind {
+        "lsl" => 0,
+        "lsr" => 1,
+        "asr" => 2,
+        _ => bail!("bad shift `{}`", op),
+    };
+    Ok((kind, immediate(amount)? as u32 & 63))
+}
+
+/// `[base]`, `[base, #imm]`, `[base, #imm]!`, `[base, reg{, lsl #n}]`, `[base, :lo12:sym]`
+enum Address {
+    Offset(Reg, i64),
+    PreIndex(Reg, i64),
+    Register(Reg, Reg, u32),
+    Lo12(Reg, String),
+}
+
+fn address(text: &str) -> Result<Address> {
+    let text = text.trim();
+    let (text, pre) = match text.strip_suffix('!') {
+        Some(t) => (t, true),
+        None => (text, false),
+    };
+    let inner = text.strip_prefix('[').and_then(|t| t.strip_suffix(']'))
+        .ok_or_else(|| anyhow!("expected an address, found `{}`", text))?;
+    let parts: Vec<&str> = inner.split(',').map(str::trim).collect();
+    let base = reg(parts[0])?;
+    match parts.get(1) {
+        None => Ok(Address::Offset(base, 0)),
+        Some(p) if modifier(p).is_some() => {
+            let (name, sym) = modifier(p).unwrap_or_default();
+            if name != "lo12" {
+                bail!("unsupported modifier :{}:", name);
+            }
+            Ok(Address::Lo12(base, sym.to_string()))
+        }
+        Some(p) if p.starts_with('#') || parse_int(p).is_ok() => {
+            let offset = immediate(p)?;
+            Ok(if pre { Address::PreIndex(base, offset) } else { Address::Offset(base, offset) })
+        }
+        Some(p) => {
+            let index = reg(p)?;
+            let amount = match parts.get(2) {
+                Some(s) => shift(Some(s))?.1,
+                None => 0,
+            };
+            Ok(Address::Register(base, index, amount))
+        }
+    }
+}
+
+/// Loads and stores of one register: (unsigned-offset opcode, log2 of the access size)
+fn load_store(mnemonic: &str, rt: Reg) -> Option<(u32, u32)> {
+    Some(match (mnemonic, rt.wide) {
+        ("ldr", true) => (0xf940_0000, 3),
+        ("ldr", false) => (0xb940_0000, 2),
+        ("str", true) => (0xf900_0000, 3),
+        ("str", false) => (0xb900_0000, 2),
+        ("ldrb", false) => (0x3940_0000, 0),
+        ("strb", false) => (0x3900_0000, 0),
+        ("ldrh", false) => (0x7940_0000, 1),
+        ("strh", false) => (0x7900_0000, 1),
+        ("ldrsb", true) => (0x3980_0000, 0),
+        ("ldrsh", true) => (0x7980_0000, 1),
+        ("ldrsw", true) => (0xb980_0000, 2),
+        _ => return None,
+    })
+}
+
+/// Encode one instruction
+pub fn encode(mnemonic: &str, operands: &[String]) -> Result<Encoded> {
+    let mut out = Out { enc: Encoded::default() };
+    let ops: Vec<&str> = operands.iter().map(|s| s.as_str()).collect();
+    let m = mnemonic.to_ascii_lowercase();
+    match (m.as_str(), ops.as_slice()) {
+        ("mov", [d, s]) => {
+            let rd = reg(d)?;
+            if s.starts_with('#') || parse_int(s).is_ok() {
+                out.word(mov_imm(rd, immediate(s)?)?);
+            } else {
+                let rs = reg(s)?;
+                if rd.sp || rs.sp {
+                    out.word(sf(rd) | 0x1100_0000 | rs.num << 5 | rd.num);
+                } else {
+                    out.word(sf(rd) | 0x2a00_03e0 | rs.num << 16 | rd.num);
+                }
+            }
+        }
+        ("movz" | "movn" | "movk", [d, s, rest @ ..]) => {
+            let rd = reg(d)?;
+            let (_, amount) = shift(rest.first().copied())?;
+            let base = match m.as_str() {
+                "movz" => 0x5280_0000,
+                "movn" => 0x1280_0000,
+                _ => 0x7280_0000,
+            };
+            let value = immediate(s)?;
+            if !(0..=0xffff).contains(&value) || amount % 16 != 0 {
+                bail!("bad wide immediate");
+            }
+            out.word(sf(rd) | base | (amount / 16) << 21 | (value as u32) << 5 | rd.num);
+        }
+        ("add" | "sub" | "adds" | "subs" | "cmp" | "cmn", _) => {
+            let (op, setflags, rd, rn, rm, rest) = match (m.as_str(), ops.as_slice()) {
+                ("cmp", [n, rm, rest @ ..]) => (1, 1, Reg { num: 31, wide: reg(n)?.wide, sp: false }, reg(n)?, *rm, rest),
+                ("cmn", [n, rm, rest @ ..]) => (0, 1, Reg { num: 31, wide: reg(n)?.wide, sp: false }, reg(n)?, *rm, rest),
+                (name, [d, n, rm, rest @ ..]) => {
+                    let op = u32::from(name.starts_with("sub"));
+                    (op, u32::from(name.ends_with('s')), reg(d)?, reg(n)?, *rm, rest)
+                }
+                _ => bail!("expected three operands"),
+            };
+            let base = sf(rd) | op << 30 | setflags << 29;
+            if let Some((name, sym)) = modifier(rm) {
+                let (kind, lsl12) = match name {
+                    "lo12" => (RelocKind::AddLo12, false),
+                    "tprel_hi12" => (RelocKind::TpHi12, true),
+                    "tprel_lo12_nc" | "tprel_lo12" => (RelocKind::TpLo12, false),
+                    _ => bail!("unsupported modifier :{}:", name),
+                };
+                out.fixup(kind, sym)?;
+                out.word(base | 0x1100_0000 | u32::from(lsl12) << 22 | rn.num << 5 | rd.num);
+            } else if rm.starts_with('#') || parse_int(rm).is_ok() {
+                let mut value = immediate(rm)?;
+                let (_, amount) = shift(rest.first().copied())?;
+                let mut op = op;
+                if value < 0 {
+                    value = -value;
+                    op ^= 1;
+                }
+                let mut lsl12 = amount == 12;
+                if !lsl12 && value > 0xfff && value & 0xfff == 0 {
+                    value >>= 12;
+                    lsl12 = true;
+                }
+                if value > 0xfff {
+                    bail!("immediate {} out of range", value);
+                }
+                out.word(sf(rd) | op << 30 | setflags << 29 | 0x1100_0000 | u32::from(lsl12) << 22 | (value as u32) << 10 | rn.num << 5 | rd.num);
+            } else {
+                let rm = reg(rm)?;
+                if rd.sp || rn.sp {
+                    // Extended register form (UXTX), which reads and writes sp
+                    let (_, amount) = shift(rest.first().copied())?;
+                    out.word(base | 0x0b20_6000 | rm.num << 16 | amount << 10 | rn.num << 5 | rd.num);
+                } else {
+                    let (kind, amount) = shift(rest.first().copied())?;
+                    out.word(base | 0x0b00_0000 | kind << 22 | rm.num << 16 | amount << 10 | rn.num << 5 | rd.num);
+                }
+            }
+        }
+        ("neg", [d, s]) => {
+            let rd = reg(d)?;
+            out.word(sf(rd) | 0x4b00_03e0 | reg(s)?.num << 16 | rd.num);
+        }
+        ("mvn", [d, s]) => {
+            let rd = reg(d)?;
+            out.word(sf(rd) | 0x2a20_03e0 | reg(s)?.num << 16 | rd.num);
+        }
+        ("and" | "orr" | "eor" | "ands" | "tst", _) => {
+            let (rd, rn, rm, rest) = match (m.as_str(), ops.as_slice()) {
+                ("tst", [n, rm, rest @ ..]) => (Reg { num: 31, wide: reg(n)?.wide, sp: false }, reg(n)?, *rm, rest),
+                (_, [d, n, rm, rest @ ..]) => (reg(d)?, reg(n)?, *rm, rest),
+                _ => bail!("expected three operands"),
+            };
+            let opc = match m.as_str() {
+                "and" => 0,
+                "orr" => 1,
+                "eor" => 2,
+                _ => 3,
+            };
+            if rm.starts_with('#') || parse_int(rm).is_ok() {
+                let value = immediate(rm)?;
+                let enc = bitmask(value as u64, rd.wide).ok_or_else(|| anyhow!("{:#x} is not a logical immediate", value))?;
+                out.word(sf(rd) | opc << 29 | 0x1200_0000 | enc | rn.num << 5 | rd.num);
+            } else {
+                let (kind, amount) = shift(rest.first().copied())?;
+                out.word(sf(rd) | opc << 29 | 0x0a00_0000 | kind << 22 | reg(rm)?.num << 16 | amount << 10 | rn.num << 5 | rd.num);
+            }
+        }
+        ("lsl" | "lsr" | "asr", [d, n, s]) => {
+            let (rd, rn) = (reg(d)?, reg(n)?);
+            if s.starts_with('#') || parse_int(s).is_ok() {
+                let width = if rd.wide { 64 } else { 32 };
+                let amount = immediate(s)? as u32 & (width - 1);
+                let n_bit = u32::from(rd.wide) << 22;
+                let (opc, immr, imms) = match m.as_str() {
+                    "lsl" => (2, (width - amount) % width, width - 1 - amount),
+                    "lsr" => (2, amount, width - 1),
+                    _ => (0, amount, width - 1),
+                };
+                out.word(sf(rd) | opc << 29 | 0x1300_0000 | n_bit | immr << 16 | imms << 10 | rn.num << 5 | rd.num);
+            } else {
+                let op2 = match m.as_str() {
+                    "lsl" => 0x2000,
+                    "lsr" => 0x2400,
+                    _ => 0x2800,
+                };
+                out.word(sf(rd) | 0x1ac0_0000 | op2 | reg(s)?.num << 16 | rn.num << 5 | rd.num);
+            }
+        }
+        ("sxtb" | "sxth" | "sxtw" | "uxtb" | "uxth", [d, n]) => {
+            let (rd, rn) = (reg(d)?, reg(n)?);
+            let imms = match m.as_str() {
+                "sxtb" | "uxtb" => 7,
+                "sxth" | "uxth" => 15,
+                _ => 31,
+            };
+            let (opc, n_bit) = if m.starts_with('s') { (0, u32::from(rd.wide) << 22) } else { (2, 0) };
+            let sf = if m.starts_with('s') { sf(rd) } else { 0 };
+            out.word(sf | opc << 29 | 0x1300_0000 | n_bit | imms << 10 | rn.num << 5 | rd.num);
+        }
+        ("mul" | "sdiv" | "udiv", [d, n, s]) => {
+            let rd = reg(d)?;
+            let op = match m.as_str() {
+                "mul" => 0x1b00_7c00,
+                "sdiv" => 0x1ac0_0c00,
+                _ => 0x1ac0_0800,
+            };
+            out.word(sf(rd) | op | reg(s)?.num << 16 | reg(n)?.num << 5 | rd.num);
+        }
+        ("madd" | "msub", [d, n, s, a]) => {
+            let rd = reg(d)?;
+            let o0 = u32::from(m == "msub") << 15;
+            out.word(sf(rd) | 0x1b00_0000 | o0 | reg(s)?.num << 16 | reg(a)?.num << 10 | reg(n)?.num << 5 | rd.num);
+        }
+        ("cset", [d, cc]) => {
+            let rd = reg(d)?;
+            let inverted = condition(cc)? ^ 1;
+            out.word(sf(rd) | 0x1a9f_07e0 | inverted << 12 | rd.num);
+        }
+        ("csel" | "csinc", [d, n, s, cc]) => {
+            let rd = reg(d)?;
+            let o2 = u32::from(m == "csinc") << 10;
+            out.word(sf(rd) | 0x1a80_0000 | o2 | reg(s)?.num << 16 | condition(cc)? << 12 | reg(n)?.num << 5 | rd.num);
+        }
+        ("ldp" | "stp", [t1, t2, addr, rest @ ..]) => {
+            let (rt, rt2) = (reg(t1)?, reg(t2)?);
+            let load = u32::from(m == "ldp") << 22;
+            let scale = if rt.wide { 3 } else { 2 };
+            let (mode, base, offset) = match (address(addr)?, rest.first()) {
+                (Address::Offset(base, 0), Some(post)) => (0x0080_0000, base, immediate(post)?),
+                (Address::Offset(base, offset), None) => (0x0100_0000, base, offset),
+                (Address::PreIndex(base, offset), None) => (0x0180_0000, base, offset),
+                _ => bail!("unsupported address"),
+            };
+            if offset % (1 << scale) != 0 || !(-64..64).contains(&(offset >> scale)) {
+                bail!("offset {} out of range", offset);
+            }
+            let opc = if rt.wide { 0x8000_0000 } else { 0 };
+            out.word(opc | 0x2800_0000 | mode | load | ((offset >> scale) as u32 & 0x7f) << 15 | rt2.num << 10 | base.num << 5 | rt.num);
+        }
+        ("ldr" | "str" | "ldrb" | "strb" | "ldrh" | "strh" | "ldrsb" | "ldrsh" | "ldrsw", [t, addr, rest @ ..]) => {
+            let rt = reg(t)?;
+            let (opcode, size) = load_store(&m, rt).ok_or_else(|| anyhow!("wrong register width"))?;
+            // Unscaled, pre- and post-indexed forms clear the unsigned-offset bit
+            let unscaled = opcode & !0x0100_0000;
+            match (address(addr)?, rest.first()) {
+                (Address::Offset(base, 0), Some(post)) => {
+                    let offset = immediate(post)?;
+                    if !(-256..256).contains(&offset) {
+                        bail!("offset {} out of range", offset);
+                    }
+                    out.word(unscaled | (offset as u32 & 0x1ff) << 12 | 0x400 | base.num << 5 | rt.num);
+                }
+                (Address::Offset(base, offset), None) => {
+                    if offset >= 0 && offset % (1 << size) == 0 && offset >> size < 4096 {
+                        out.word(opcode | ((offset >> size) as u32) << 10 | base.num << 5 | rt.num);
+                    } else if (-256..256).contains(&offset) {
+                        out.word(unscaled | (offset as u32 & 0x1ff) << 12 | base.num << 5 | rt.num);
+                    } else {
+                        bail!("offset {} out of range", offset);
+                    }
+                }
+                (Address::PreIndex(base, offset), None) => {
+                    if !(-256..256).contains(&offset) {
+                        bail!("offset {} out of range", offset);
+                    }
+                    out.word(unscaled | (offset as u32 & 0x1ff) << 12 | 0xc00 | base.num << 5 | rt.num);
+                }
+                (Address::Register(base, index, amount), None) => {
+                    if amount != 0 && amount != size {
+                        bail!("index shift must be {}", size);
+                    }
+                    let s = u32::from(amount != 0) << 12;
+                    out.word(unscaled | 0x0020_6800 | s | index.num << 16 | base.num << 5 | rt.num);
+                }
+                (Address::Lo12(base, sym), None) => {
+                    out.fixup(RelocKind::LoadLo12 { shift: size as u8 }, &sym)?;
+                    out.word(opcode | base.num << 5 | rt.num);
+                }
+                _ => bail!("unsupported address"),
+            }
+        }
+        ("ldar" | "ldarb" | "ldarh" | "stlr" | "stlrb" | "stlrh" | "ldaxr" | "ldxr", [t, addr]) => {
+            let rt = reg(t)?;
+            let Address::Offset(base, 0) = address(addr)? else { bail!("expected [reg]") };
+            let size: u32 = match m.as_bytes().last() {
+                Some(b'b') => 0,
+                Some(b'h') => 1,
+                _ if rt.wide => 3,
+                _ => 2,
+            };
+            let op = match m.trim_end_matches(['b', 'h']) {
+                "ldar" => 0x08df_fc00,
+                "stlr" => 0x089f_fc00,
+                "ldaxr" => 0x085f_fc00,
+                _ => 0x085f_7c00,
+            };
+            out.word(size << 30 | op | base.num << 5 | rt.num);
+        }
+        ("stxr" | "stlxr", [s, t, addr]) => {
+            let (rs, rt) = (reg(s)?, reg(t)?);
+            let Address::Offset(base, 0) = address(addr)? else { bail!("expected [reg]") };
+            let size = if rt.wide { 3 } else { 2 };
+            let o0 = u32::from(m == "stlxr") << 15;
+            out.word(size << 30 | 0x0800_7c00 | o0 | rs.num << 16 | base.num << 5 | rt.num);
+        }
+        ("b" | "bl", [target]) => {
+            let (kind, op) = if m == "bl" { (RelocKind::Call26, 0x9400_0000) } else { (RelocKind::Jump26, 0x1400_0000) };
+            out.fixup(kind, target)?;
+            out.word(op);
+        }
+        (name, [target]) if name.starts_with("b.") => {
+            let cc = condition(&name[2..])?;
+            out.fixup(RelocKind::CondBr19, target)?;
+            out.word(0x5400_0000 | cc);
+        }
+        ("cbz" | "cbnz", [t, target]) => {
+            let rt = reg(t)?;
+            out.fixup(RelocKind::CondBr19, target)?;
+            out.word(sf(rt) | 0x3400_0000 | u32::from(m == "cbnz") << 24 | rt.num);
+        }
+        ("br" | "blr", [t]) => {
+            let op = if m == "blr" { 0xd63f_0000 } else { 0xd61f_0000 };
+            out.word(op | reg(t)?.num << 5);
+        }
+        ("ret", []) => out.word(0xd65f_03c0),
+        ("ret", [t]) => out.word(0xd65f_0000 | reg(t)?.num << 5),
+        ("adrp", [d, target]) => {
+            let rd = reg(d)?;
+            out.fixup(RelocKind::Page21, target)?;
+            out.word(0x9000_0000 | rd.num);
+        }
+        ("mrs", [d, sysreg]) if sysreg.eq_ignore_ascii_case("tpidr_el0") => out.word(0xd53b_d040 | reg(d)?.num),
+        ("msr", [sysreg, s]) if sysreg.eq_ignore_ascii_case("tpidr_el0") => out.word(0xd51b_d040 | reg(s)?.num),
+        ("svc", [imm]) => out.word(0xd400_0001 | (immediate(imm)? as u32 & 0xffff) << 5),
+        ("nop", []) => out.word(0xd503_201f),
+        ("yield", []) => out.word(0xd503_203f),
+        ("dmb", [opt]) if opt.eq_ignore_ascii_case("ish") => out.word(0xd503_3bbf),
+        ("fmov", [d, s]) => match (fp_register(d), fp_register(s)) {
+            (Some((fd, true)), None) => out.word(0x9e67_0000 | reg(s)?.num << 5 | fd),
+            (None, Some((fs, true))) => out.word(0x9e66_0000 | fs << 5 | reg(d)?.num),
+            (Some((fd, false)), None) => out.word(0x1e27_0000 | reg(s)?.num << 5 | fd),
+            (None, Some((fs, false))) => out.word(0x1e26_0000 | fs << 5 | reg(d)?.num),
+            _ => bail!("unsupported fmov"),
+        },
+        ("fadd" | "fsub" | "fmul" | "fdiv", [d, n, mm]) => {
+            let (Some((fd, true)), Some((fn_, true)), Some((fm, true))) = (fp_register(d), fp_register(n), fp_register(mm)) else {
+                bail!("expected double registers");
+            };
+            let op = match m.as_str() {
+                "fmul" => 0x1e60_0800,
+                "fdiv" => 0x1e60_1800,
+                "fadd" => 0x1e60_2800,
+                _ => 0x1e60_3800,
+            };
+            out.word(op | fm << 16 | fn_ << 5 | fd);
+        }
+        ("fcmp", [n, mm]) => {
+            let (Some((fn_, true)), Some((fm, true))) = (fp_register(n), fp_register(mm)) else {
+                bail!("expected double registers");
+            };
+            out.word(0x1e60_2000 | fm << 16 | fn_ << 5);
+        }
+        ("scvtf", [d, s]) => match fp_register(d) {
+            Some((fd, true)) if reg(s)?.wide => out.word(0x9e62_0000 | reg(s)?.num << 5 | fd),
+            _ => bail!("unsupported scvtf"),
+        },
+        ("fcvtzs", [d, s]) => match fp_register(s) {
+            Some((fs, true)) if reg(d)?.wide => out.word(0x9e78_0000 | fs << 5 | reg(d)?.num),
+            _ => bail!("unsupported fcvtzs"),
+        },
+        ("fcvt", [d, s]) => match (fp_register(d), fp_register(s)) {
+            (Some((fd, true)), Some((fs, false))) => out.word(0x1e22_c000 | fs << 5 | fd),
+            (Some((fd, false)), Some((fs, true))) => out.word(0x1e62_4000 | fs << 5 | fd),
+            _ => bail!("unsupported fcvt"),
+        },
+        _ => bail!("unsupported instruction"),
+    }
+    Ok(out.enc)
+}
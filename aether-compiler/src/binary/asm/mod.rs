@@ -0,0 +1,404 @@
+//! Assembler for the GNU syntax the native backends emit
+//!
+//! Turns `.s` text into an [`Object`] without binutils; the instruction encoders live in submodules.
+
+mod arm64;
+mod x86_64;
+
+use std::collections::{HashMap, HashSet};
+use anyhow::{anyhow, bail, Result};
+use super::object::{apply, Arch, Object, Reloc, RelocKind, SectionKind, SymbolKind, Target};
+
+/// Reference from an encoded instruction to a label or symbol
+#[derive(Debug, Clone)]
+pub struct Fixup {
+    /// Byte offset of the field within the instruction
+    pub offset: usize,
+    pub kind: RelocKind,
+    pub symbol: String,
+    pub addend: i64,
+}
+
+/// Machine code of one instruction
+#[derive(Debug, Default)]
+pub struct Encoded {
+    pub bytes: Vec<u8>,
+    pub fixups: Vec<Fixup>,
+}
+
+/// Assemble `source` for `arch`
+pub fn assemble(source: &str, arch: Arch) -> Result<Object> {
+    let mut asm = Assembler {
+        object: Object::new(arch),
+        current: None,
+        labels: HashMap::new(),
+        globals: HashSet::new(),
+        types: HashMap::new(),
+        sizes: HashMap::new(),
+        fixups: Vec::new(),
+    };
+    asm.current = Some(asm.object.section(".text", SectionKind::Text));
+    for (n, line) in source.lines().enumerate() {
+        asm.line(line, arch).map_err(|e| anyhow!("Cannot assemble `{}` at line {}: {}", line.trim(), n + 1, e))?;
+    }
+    asm.finish()
+}
+
+struct Assembler {
+    object: Object,
+    /// Section being filled; `None` inside sections that are dropped (`.note.GNU-stack`)
+    current: Option<usize>,
+    /// Label -> (section, offset)
+    labels: HashMap<String, (usize, u64)>,
+    globals: HashSet<String>,
+    types: HashMap<String, SymbolKind>,
+    sizes: HashMap<String, u64>,
+    /// (section, offset of the instruction, fixup)
+    fixups: Vec<(usize, u64, Fixup)>,
+}
+
+impl Assembler {
+    fn line(&mut self, line: &str, arch: Arch) -> Result<()> {
+        let mut line = strip_comment(line, arch).trim();
+        // Labels, possibly followed by more on the same line
+        while let Some((label, rest)) = split_label(line) {
+            self.label(&label)?;
+            line = rest.trim();
+        }
+        if line.is_empty() {
+            return Ok(());
+        }
+        let (head, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        if head.starts_with('.') {
+            return self.directive(head, rest, arch);
+        }
+        // `lock` is a prefix byte on the instruction that follows it
+        if arch == Arch::X86_64 && head == "lock" {
+            let section = self.text()?;
+            self.object.sections[section].data.push(0xf0);
+            return self.line(rest, arch);
+        }
+        let operands = split_operands(rest);
+        let encoded = match arch {
+            Arch::X86_64 => x86_64::encode(head, &operands)?,
+            Arch::Aarch64 => arm64::encode(head, &operands)?,
+        };
+        let section = self.text()?;
+        let at = self.object.sections[section].data.len() as u64;
+        self.object.sections[section].data.extend_from_slice(&encoded.bytes);
+        for fixup in encoded.fixups {
+            self.fixups.push((section, at, fixup));
+        }
+        Ok(())
+    }
+
+    /// Section that instructions and data go into
+    fn text(&self) -> Result<usize> {
+        self.current.ok_or_else(|| anyhow!("contents in a discarded section"))
+    }
+
+    fn label(&mut self, name: &str) -> Result<()> {
+        let section = self.text()?;
+        let offset = self.object.sections[section].data.len() as u64;
+        if self.labels.insert(name.to_string(), (section, offset)).is_some() {
+            bail!("label {} defined twice", name);
+        }
+        Ok(())
+    }
+
+    fn directive(&mut self, name: &str, args: &str, arch: Arch) -> Result<()> {
+        match name {
+            ".intel_syntax" | ".file" | ".ident" => {}
+            ".text" => self.current = Some(self.object.section(".text", SectionKind::Text)),
+            ".data" => self.current = Some(self.object.section(".data", SectionKind::Data)),
+            ".section" => {
+                let mut parts = split_operands(args).into_iter();
+                let section = parts.next().unwrap_or_default();
+                let flags = parts.next().map(|f| f.trim_matches('"').to_string()).unwrap_or_default();
+                let kind = if section.starts_with(".note") {
+                    None
+                } else if section.starts_with(".tdata") || flags.contains('T') {
+                    Some(SectionKind::Tls)
+                } else if section.starts_with(".text") || flags.contains('x') {
+                    Some(SectionKind::Text)
+                } else if section.starts_with(".data") || flags.contains('w') {
+                    Some(SectionKind::Data)
+                } else {
+                    Some(SectionKind::ReadOnly)
+                };
+                self.current = kind.map(|kind| self.object.section(&section, kind));
+            }
+            ".globl" | ".global" => {
+                self.globals.insert(symbol_name(args));
+            }
+            ".type" => {
+                let (sym, kind) = args.split_once(',').ok_or_else(|| anyhow!("expected `symbol, type`"))?;
+                let kind = match kind.trim().trim_start_matches(['@', '%']) {
+                    "function" => SymbolKind::Func,
+                    "object" => SymbolKind::Object,
+                    "tls_object" => SymbolKind::Tls,
+                    other => bail!("unknown symbol type {}", other),
+                };
+                self.types.insert(symbol_name(sym), kind);
+            }
+            ".size" => {
+                let (sym, size) = args.split_once(',').ok_or_else(|| anyhow!("expected `symbol, size`"))?;
+                let sym = symbol_name(sym);
+                let size = match size.trim().strip_prefix(".-") {
+                    Some(start) => {
+                        let &(section, offset) = self.labels.get(&symbol_name(start))
+                            .ok_or_else(|| anyhow!("undefined label {}", start))?;
+                        self.object.sections[section].data.len() as u64 - offset
+                    }
+                    None => parse_int(size)? as u64,
+                };
+                self.sizes.insert(sym, size);
+            }
+            ".balign" | ".align" | ".p2align" => {
+                let value = parse_int(args.split(',').next().unwrap_or_default())? as u64;
+                let align = if name == ".p2align" || (name == ".align" && arch == Arch::Aarch64) { 1 << value } else { value };
+                if !align.is_power_of_two() {
+                    bail!("alignment {} is not a power of two", align);
+                }
+                let section = self.text()?;
+                let sec = &mut self.object.sections[section];
+                sec.align = sec.align.max(align);
+                let nop: &[u8] = match (sec.kind, arch) {
+                    (SectionKind::Text, Arch::X86_64) => &[0x90],
+                    (SectionKind::Text, Arch::Aarch64) => &[0x1f, 0x20, 0x03, 0xd5],
+                    _ => &[0],
+                };
+                while !(sec.data.len() as u64).is_multiple_of(align) {
+                    let pad = nop[sec.data.len() % nop.len()];
+                    sec.data.push(pad);
+                }
+            }
+            ".zero" | ".skip" | ".space" => {
+                let count = parse_int(args)? as usize;
+                let section = self.text()?;
+                self.object.sections[section].data.extend(std::iter::repeat_n(0, count));
+            }
+            ".byte" | ".short" | ".hword" | ".2byte" | ".long" | ".4byte" | ".int" => {
+                let width = match name {
+                    ".byte" => 1,
+                    ".short" | ".hword" | ".2byte" => 2,
+                    _ => 4,
+                };
+                let section = self.text()?;
+                for value in split_operands(args) {
+                    let bytes = parse_int(&value)?.to_le_bytes();
+                    self.object.sections[section].data.extend_from_slice(&bytes[..width]);
+                }
+            }
+            ".quad" | ".8byte" | ".xword" => {
+                let section = self.text()?;
+                for value in split_operands(args) {
+                    let at = self.object.sections[section].data.len() as u64;
+                    match parse_int(&value) {
+                        Ok(v) => self.object.sections[section].data.extend_from_slice(&v.to_le_bytes()),
+                        Err(_) => {
+                            let (symbol, addend) = parse_symbol(&value)?;
+                            self.object.sections[section].data.extend_from_slice(&[0; 8]);
+                            self.fixups.push((section, at, Fixup { offset: 0, kind: RelocKind::Abs64, symbol, addend }));
+                        }
+                    }
+                }
+            }
+            ".asciz" | ".string" | ".ascii" => {
+                let section = self.text()?;
+                let mut bytes = parse_string(args)?;
+                if name != ".ascii" {
+                    bytes.push(0);
+                }
+                self.object.sections[section].data.extend_from_slice(&bytes);
+            }
+            _ => bail!("unsupported directive {}", name),
+        }
+        Ok(())
+    }
+
+    /// Define symbols for the named labels and resolve or record every fixup
+    fn finish(mut self) -> Result<Object> {
+        let mut labels: Vec<(&String, &(usize, u64))> = self.labels.iter().collect();
+        labels.sort_by_key(|&(name, &(section, offset))| (section, offset, name.clone()));
+        for (name, &(section, offset)) in labels {
+            if is_local(name) {
+                continue;
+            }
+            let kind = self.types.get(name).copied().unwrap_or(match self.object.sections[section].kind {
+                SectionKind::Tls => SymbolKind::Tls,
+                _ => SymbolKind::None,
+            });
+            let index = self.object.symbol(name);
+            let symbol = &mut self.object.symbols[index];
+            symbol.section = Some(section);
+            symbol.offset = offset;
+            symbol.size = self.sizes.get(name).copied().unwrap_or(0);
+            symbol.global = self.globals.contains(name);
+            symbol.kind = kind;
+        }
+        for name in &self.globals {
+            self.object.symbol(name);
+        }
+        for (section, at, fixup) in std::mem::take(&mut self.fixups) {
+            let offset = at + fixup.offset as u64;
+            let mut reloc = Reloc { offset, target: Target::Section(0), kind: fixup.kind, addend: fixup.addend };
+            if is_local(&fixup.symbol) {
+                // Branches within a section are resolved here; the rest become section-relative
+                let &(label_section, label_offset) = self.labels.get(&fixup.symbol)
+                    .ok_or_else(|| anyhow!("Undefined label {}", fixup.symbol))?;
+                let pc_relative = matches!(fixup.kind, RelocKind::Pc32 | RelocKind::Branch32
+                    | RelocKind::Call26 | RelocKind::Jump26 | RelocKind::CondBr19);
+                if label_section == section && pc_relative {
+                    let field = &mut self.object.sections[section].data[offset as usize..];
+                    apply(fixup.kind, field, label_offset as i64 + fixup.addend, offset as i64)
+                        .map_err(|e| anyhow!("{} (label {})", e, fixup.symbol))?;
+                    continue;
+                }
+                reloc.target = Target::Section(label_section);
+                reloc.addend += label_offset as i64;
+            } else {
+                reloc.target = Target::Symbol(self.object.symbol(&fixup.symbol));
+            }
+            self.object.sections[section].relocs.push(reloc);
+        }
+        Ok(self.object)
+    }
+}
+
+/// Assembler-local labels, which never reach the symbol table
+fn is_local(name: &str) -> bool {
+    name.starts_with(".L")
+}
+
+/// Drop a trailing comment (`#` on x86-64, `//` on AArch64) outside string literals
+fn strip_comment(line: &str, arch: Arch) -> &str {
+    let bytes = line.as_bytes();
+    let mut quoted = false;
+    for (i, &b) in bytes.iter().enumerate() {
+        match b {
+            b'"' if i == 0 || bytes[i - 1] != b'\\' => quoted = !quoted,
+            b'#' if !quoted && arch == Arch::X86_64 => return &line[..i],
+            b'/' if !quoted && bytes.get(i + 1) == Some(&b'/') => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// `label: rest` -> (label, rest)
+fn split_label(line: &str) -> Option<(String, &str)> {
+    if let Some(quoted) = line.strip_prefix('"') {
+        let end = quoted.find('"')?;
+        let rest = quoted[end + 1..].strip_prefix(':')?;
+        return Some((quoted[..end].to_string(), rest));
+    }
+    let end = line.find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$')))?;
+    let rest = line[end..].strip_prefix(':')?;
+    (end > 0).then(|| (line[..end].to_string(), rest))
+}
+
+/// Split operands on top-level commas (not inside brackets or quotes)
+pub fn split_operands(args: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut current = String::new();
+    for c in args.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '[' if !quoted => depth += 1,
+            ']' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                parts.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() {
+        parts.push(current.trim().to_string());
+    }
+    parts
+}
+
+/// Integer literal: decimal, `0x` hex or `0b` binary, optionally negative
+pub fn parse_int(text: &str) -> Result<i64> {
+    let text = text.trim();
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest.trim()),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16)
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        u64::from_str_radix(bin, 2)
+    } else {
+        digits.parse::<u64>()
+    };
+    let value = value.map_err(|_| anyhow!("expected a number, found `{}`", text))? as i64;
+    Ok(if negative { value.wrapping_neg() } else { value })
+}
+
+/// Symbol name, unquoting names that look like registers
+pub fn symbol_name(text: &str) -> String {
+    text.trim().trim_matches('"').to_string()
+}
+
+/// `symbol`, `symbol + 8` or `symbol - 8` -> (name, addend)
+pub fn parse_symbol(text: &str) -> Result<(String, i64)> {
+    let text = text.trim();
+    let end = match text.strip_prefix('"') {
+        Some(quoted) => quoted.find('"').map(|i| i + 2).ok_or_else(|| anyhow!("unterminated symbol name"))?,
+        None => text.find(['+', '-']).filter(|&i| i > 0).unwrap_or(text.len()),
+    };
+    let (name, rest) = text.split_at(end);
+    let addend = if rest.trim().is_empty() { 0 } else { parse_int(&rest.replace(['+', ' '], ""))? };
+    let name = symbol_name(name);
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        bail!("expected a symbol, found `{}`", text);
+    }
+    Ok((name, addend))
+}
+
+/// Contents of a string literal with C-style escapes
+fn parse_string(text: &str) -> Result<Vec<u8>> {
+    let inner = text.trim().strip_prefix('"').and_then(|t| t.strip_suffix('"'))
+        .ok_or_else(|| anyhow!("expected a string literal"))?;
+    let mut out = Vec::new();
+    let bytes = inner.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        i += 1;
+        let Some(&c) = bytes.get(i) else { bail!("unterminated escape") };
+        i += 1;
+        match c {
+            b'n' => out.push(b'\n'),
+            b't' => out.push(b'\t'),
+            b'r' => out.push(b'\r'),
+            b'0'..=b'7' => {
+                let mut value = u32::from(c - b'0');
+                for _ in 0..2 {
+                    match bytes.get(i) {
+                        Some(&d @ b'0'..=b'7') => {
+                            value = value * 8 + u32::from(d - b'0');
+                            i += 1;
+                        }
+                        _ => break,
+                    }
+                }
+                out.push(value as u8);
+            }
+            other => out.push(other),
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,490 @@
+//! x86-64 instruction encoding for Intel-syntax assembly
+
+use anyhow::{anyhow, bail, Result};
+use crate::binary::object::RelocKind;
+use super::{parse_int, parse_symbol, Encoded, Fixup};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Reg {
+    num: u8,
+    /// Width in bytes; 16 for `xmm` registers
+    size: u8,
+}
+
+/// `[base + index*scale + disp]`, `[rip + symbol]` or an absolute `fs:` address
+#[derive(Debug, Clone, Default)]
+struct Mem {
+    size: Option<u8>,
+    base: Option<u8>,
+    index: Option<(u8, u8)>,
+    disp: i64,
+    /// Relocated displacement: `[rip + symbol]` or `[reg + symbol@tpoff]`
+    symbol: Option<(String, RelocKind)>,
+    fs: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Reg(Reg),
+    Imm(i64),
+    Mem(Mem),
+    /// Branch target
+    Label(String, i64),
+}
+
+impl Operand {
+    fn size(&self) -> Option<u8> {
+        match self {
+            Operand::Reg(r) => Some(r.size),
+            Operand::Mem(m) => m.size,
+            _ => None,
+        }
+    }
+}
+
+/// ModRM `reg` field: a register operand or an opcode extension
+#[derive(Clone, Copy)]
+enum Field {
+    Reg(Reg),
+    Ext(u8),
+}
+
+fn register(name: &str) -> Option<Reg> {
+    const R64: [&str; 8] = ["rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi"];
+    const R32: [&str; 8] = ["eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi"];
+    const R16: [&str; 8] = ["ax", "cx", "dx", "bx", "sp", "bp", "si", "di"];
+    const R8: [&str; 8] = ["al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil"];
+    let name = name.to_ascii_lowercase();
+    for (names, size) in [(R64, 8), (R32, 4), (R16, 2), (R8, 1)] {
+        if let Some(num) = names.iter().position(|&n| n == name) {
+            return Some(Reg { num: num as u8, size });
+        }
+    }
+    if let Some(n) = name.strip_prefix("xmm") {
+        return n.parse::<u8>().ok().filter(|&n| n < 16).map(|num| Reg { num, size: 16 });
+    }
+    let rest = name.strip_prefix('r')?;
+    let digits = rest.trim_end_matches(['d', 'w', 'b', 'l']);
+    let num = digits.parse::<u8>().ok().filter(|n| (8..16).contains(n))?;
+    let size = match &rest[digits.len()..] {
+        "" => 8,
+        "d" => 4,
+        "w" => 2,
+        "b" | "l" => 1,
+        _ => return None,
+    };
+    Some(Reg { num, size })
+}
+
+fn operand(text: &str) -> Result<Operand> {
+    let text = text.trim();
+    let mut size = None;
+    let mut rest = text;
+    for (prefix, bytes) in [("qword ptr", 8), ("dword ptr", 4), ("word ptr", 2), ("byte ptr", 1)] {
+        if let Some(r) = text.strip_prefix(prefix) {
+            size = Some(bytes);
+            rest = r.trim();
+        }
+    }
+    if size.is_some() || rest.starts_with('[') || rest.starts_with("fs:") {
+        return memory(rest, size).map(Operand::Mem);
+    }
+    if let Some(reg) = register(rest) {
+        return Ok(Operand::Reg(reg));
+    }
+    if let Ok(value) = parse_int(rest) {
+        return Ok(Operand::Imm(value));
+    }
+    let (name, addend) = parse_symbol(rest.trim_end_matches("@PLT"))?;
+    Ok(Operand::Label(name, addend))
+}
+
+fn memory(text: &str, size: Option<u8>) -> Result<Mem> {
+    let mut mem = Mem { size, ..Mem::default() };
+    let text = match text.strip_prefix("fs:") {
+        Some(rest) => {
+            mem.fs = true;
+            rest.trim()
+        }
+        None => text,
+    };
+    let Some(inner) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) else {
+        mem.disp = parse_int(text)?;
+        return Ok(mem);
+    };
+    // Signed terms of the address expression
+    let mut terms = Vec::new();
+    let mut start = 0;
+    let mut negative = false;
+    for (i, c) in inner.char_indices() {
+        if matches!(c, '+' | '-') && !inner[start..i].trim().is_empty() {
+            terms.push((negative, inner[start..i].trim()));
+            start = i + 1;
+            negative = c == '-';
+        } else if matches!(c, '+' | '-') {
+            start = i + 1;
+            negative = c == '-';
+        }
+    }
+    terms.push((negative, inner[start..].trim()));
+    let mut rip = false;
+    for (negative, term) in terms {
+        if term == "rip" {
+            rip = true;
+        } else if let Some((reg, scale)) = term.split_once('*') {
+            let reg = register(reg.trim()).ok_or_else(|| anyhow!("bad index register {}", reg))?;
+            mem.index = Some((reg.num, parse_int(scale)? as u8));
+        } else if let Some(reg) = register(term) {
+            if mem.base.is_none() {
+                mem.base = Some(reg.num);
+            } else {
+                mem.index = Some((reg.num, 1));
+            }
+        } else if let Ok(value) = parse_int(term) {
+            mem.disp += if negative { -value } else { value };
+        } else {
+            let (name, kind) = match term.strip_suffix("@tpoff") {
+                Some(name) => (name, RelocKind::TpOff32),
+                None => (term, RelocKind::Pc32),
+            };
+            if negative {
+                bail!("cannot subtract symbol {}", name);
+            }
+            mem.symbol = Some((super::symbol_name(name), kind));
+        }
+    }
+    match &mem.symbol {
+        Some((_, RelocKind::Pc32)) if !rip || mem.base.is_some() => bail!("symbol addresses must be rip-relative"),
+        None if rip => bail!("rip-relative address without a symbol"),
+        _ => {}
+    }
+    Ok(mem)
+}
+
+/// Little-endian immediate of `width` bytes, signed or unsigned
+fn imm(value: i64, width: usize) -> Result<Vec<u8>> {
+    let bits = 8 * width as u32;
+    let fits = width == 8 || (value >> (bits - 1) == 0 || value >> (bits - 1) == -1) || (value as u64) >> bits == 0;
+    if !fits {
+        bail!("immediate {} does not fit in {} bits", value, bits);
+    }
+    Ok(value.to_le_bytes()[..width].to_vec())
+}
+
+fn fits_i8(value: i64) -> bool {
+    (-128..=127).contains(&value)
+}
+
+fn fits_i32(value: i64) -> bool {
+    i32::try_from(value).is_ok()
+}
+
+/// Byte registers that need a REX prefix (`spl`, `bpl`, `sil`, `dil`)
+fn needs_rex(reg: Reg) -> bool {
+    reg.size == 1 && (4..8).contains(&reg.num)
+}
+
+/// Prefixes, REX, `opcode`, ModRM/SIB/displacement for `rm`, then `immediate`.
+/// `size` is the operand size: 2 adds the 0x66 prefix, 8 sets REX.W
+fn rm(out: &mut Encoded, prefix: &[u8], size: u8, opcode: &[u8], field: Field, rm: &Operand, immediate: &[u8]) -> Result<()> {
+    let (reg, mut force_rex) = match field {
+        Field::Reg(r) => (r.num, needs_rex(r)),
+        Field::Ext(n) => (n, false),
+    };
+    let mut rex = if size == 8 { 0x48 } else { 0x40 };
+    rex |= (reg >> 3) << 2;
+    let mut body = Vec::new();
+    let mut fixup = None;
+    match rm {
+        Operand::Reg(r) => {
+            force_rex |= needs_rex(*r);
+            rex |= r.num >> 3;
+            body.push(0xc0 | (reg & 7) << 3 | (r.num & 7));
+        }
+        Operand::Mem(m) => {
+            let disp32 = |body: &mut Vec<u8>, value: i64| -> Result<()> {
+                body.extend(imm(value, 4)?);
+                Ok(())
+            };
+            if let Some((symbol, RelocKind::Pc32)) = &m.symbol {
+                body.push((reg & 7) << 3 | 5);
+                fixup = Some((body.len(), RelocKind::Pc32, symbol.clone(), m.disp - 4 - immediate.len() as i64));
+                disp32(&mut body, 0)?;
+            } else if m.base.is_none() {
+                let index = match m.index {
+                    Some((i, scale)) => {
+                        rex |= (i >> 3) << 1;
+                        (scale.trailing_zeros() as u8) << 6 | (i & 7) << 3
+                    }
+                    None => 4 << 3,
+                };
+                body.push((reg & 7) << 3 | 4);
+                body.push(index | 5);
+                disp32(&mut body, m.disp)?;
+            } else {
+                let base = m.base.unwrap_or_default();
+                rex |= base >> 3;
+                let mode = if m.symbol.is_some() || !fits_i8(m.disp) {
+                    2
+                } else if m.disp == 0 && base & 7 != 5 {
+                    0
+                } else {
+                    1
+                };
+                if let Some((i, scale)) = m.index {
+                    if i == 4 {
+                        bail!("rsp cannot be an index register");
+                    }
+                    rex |= (i >> 3) << 1;
+                    body.push(mode << 6 | (reg & 7) << 3 | 4);
+                    body.push((scale.trailing_zeros() as u8) << 6 | (i & 7) << 3 | (base & 7));
+                } else if base & 7 == 4 {
+                    body.push(mode << 6 | (reg & 7) << 3 | 4);
+                    body.push(0x24);
+                } else {
+                    body.push(mode << 6 | (reg & 7) << 3 | (base & 7));
+                }
+                match mode {
+                    1 => body.push(m.disp as u8),
+                    2 => {
+                        if let Some((symbol, kind)) = &m.symbol {
+                            fixup = Some((body.len(), *kind, symbol.clone(), m.disp));
+                        }
+                        disp32(&mut body, m.disp * i64::from(m.symbol.is_none()))?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        _ => bail!("expected a register or memory operand"),
+    }
+    if matches!(rm, Operand::Mem(m) if m.fs) {
+        out.bytes.push(0x64);
+    }
+    if size == 2 {
+        out.bytes.push(0x66);
+    }
+    out.bytes.extend_from_slice(prefix);
+    if rex != 0x40 || force_rex {
+        out.bytes.push(rex);
+    }
+    out.bytes.extend_from_slice(opcode);
+    let at = out.bytes.len();
+    out.bytes.extend(body);
+    out.bytes.extend_from_slice(immediate);
+    if let Some((offset, kind, symbol, addend)) = fixup {
+        out.fixups.push(Fixup { offset: at + offset, kind, symbol, addend });
+    }
+    Ok(())
+}
+
+/// Opcodes that add the register number to the opcode byte (`push`, `mov r, imm`)
+fn plus_reg(out: &mut Encoded, size: u8, opcode: u8, reg: Reg, immediate: &[u8]) {
+    if size == 2 {
+        out.bytes.push(0x66);
+    }
+    let rex = if size == 8 { 0x48 } else { 0x40 } | reg.num >> 3;
+    if rex != 0x40 || needs_rex(reg) {
+        out.bytes.push(rex);
+    }
+    out.bytes.push(opcode + (reg.num & 7));
+    out.bytes.extend_from_slice(immediate);
+}
+
+/// rel32 branch to a label
+fn branch(out: &mut Encoded, opcode: &[u8], name: &str, addend: i64) {
+    out.bytes.extend_from_slice(opcode);
+    out.fixups.push(Fixup { offset: out.bytes.len(), kind: RelocKind::Branch32, symbol: name.to_string(), addend: addend - 4 });
+    out.bytes.extend_from_slice(&[0; 4]);
+}
+
+fn condition(cc: &str) -> Option<u8> {
+    Some(match cc {
+        "o" => 0,
+        "no" => 1,
+        "b" | "c" | "nae" => 2,
+        "ae" | "nb" | "nc" => 3,
+        "e" | "z" => 4,
+        "ne" | "nz" => 5,
+        "be" | "na" => 6,
+        "a" | "nbe" => 7,
+        "s" => 8,
+        "ns" => 9,
+        "p" | "pe" => 10,
+        "np" | "po" => 11,
+        "l" | "nge" => 12,
+        "ge" | "nl" => 13,
+        "le" | "ng" => 14,
+        "g" | "nle" => 15,
+        _ => return None,
+    })
+}
+
+/// Encode one instruction
+pub fn encode(mnemonic: &str, operands: &[String]) -> Result<Encoded> {
+    let ops = operands.iter().map(|o| operand(o)).collect::<Result<Vec<_>>>()?;
+    let mut out = Encoded::default();
+    let byte = |size: u8, wide: u8| if size == 1 { wide - 1 } else { wide };
+    const ALU: [&str; 8] = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+    const UNARY: [(&str, u8); 6] = [("not", 2), ("neg", 3), ("mul", 4), ("imul", 5), ("div", 6), ("idiv", 7)];
+    const SHIFT: [(&str, u8); 6] = [("rol", 0), ("ror", 1), ("shl", 4), ("sal", 4), ("shr", 5), ("sar", 7)];
+
+    match (mnemonic, ops.as_slice()) {
+        (op, [dst, src]) if ALU.contains(&op) => {
+            let n = ALU.iter().position(|&a| a == op).unwrap_or_default() as u8;
+            match (dst, src) {
+                (_, Operand::Imm(k)) => {
+                    let size = dst.size().ok_or_else(|| anyhow!("operand size is ambiguous"))?;
+                    if size == 1 {
+                        rm(&mut out, &[], size, &[0x80], Field::Ext(n), dst, &imm(*k, 1)?)?;
+                    } else if fits_i8(*k) {
+                        rm(&mut out, &[], size, &[0x83], Field::Ext(n), dst, &imm(*k, 1)?)?;
+                    } else {
+                        let width = if size == 2 { 2 } else { 4 };
+                        if size == 8 && !fits_i32(*k) {
+                            bail!("immediate {} does not fit in 32 bits", k);
+                        }
+                        rm(&mut out, &[], size, &[0x81], Field::Ext(n), dst, &imm(*k, width)?)?;
+                    }
+                }
+                (Operand::Reg(_) | Operand::Mem(_), Operand::Reg(r)) => {
+                    rm(&mut out, &[], r.size, &[n * 8 + byte(r.size, 1)], Field::Reg(*r), dst, &[])?;
+                }
+                (Operand::Reg(r), Operand::Mem(_)) => {
+                    rm(&mut out, &[], r.size, &[n * 8 + byte(r.size, 3)], Field::Reg(*r), src, &[])?;
+                }
+                _ => bail!("unsupported operands"),
+            }
+        }
+        ("mov", [dst @ (Operand::Reg(_) | Operand::Mem(_)), Operand::Reg(r)]) => {
+            rm(&mut out, &[], r.size, &[byte(r.size, 0x89)], Field::Reg(*r), dst, &[])?;
+        }
+        ("mov", [Operand::Reg(r), src @ Operand::Mem(_)]) => {
+            rm(&mut out, &[], r.size, &[byte(r.size, 0x8b)], Field::Reg(*r), src, &[])?;
+        }
+        ("mov", [Operand::Reg(r), Operand::Imm(k)]) => match r.size {
+            8 if fits_i32(*k) => rm(&mut out, &[], 8, &[0xc7], Field::Ext(0), &ops[0], &imm(*k, 4)?)?,
+            8 if (*k as u64) >> 32 == 0 => plus_reg(&mut out, 4, 0xb8, *r, &imm(*k, 4)?),
+            8 => plus_reg(&mut out, 8, 0xb8, *r, &k.to_le_bytes()),
+            1 => plus_reg(&mut out, 1, 0xb0, *r, &imm(*k, 1)?),
+            size => plus_reg(&mut out, size, 0xb8, *r, &imm(*k, size as usize)?),
+        },
+        ("mov", [dst @ Operand::Mem(m), Operand::Imm(k)]) => {
+            let size = m.size.ok_or_else(|| anyhow!("operand size is ambiguous"))?;
+            let width = size.min(4) as usize;
+            if size == 8 && !fits_i32(*k) {
+                bail!("immediate {} does not fit in 32 bits", k);
+            }
+            rm(&mut out, &[], size, &[byte(size, 0xc7)], Field::Ext(0), dst, &imm(*k, width)?)?;
+        }
+        ("test", [dst, Operand::Reg(r)]) => rm(&mut out, &[], r.size, &[byte(r.size, 0x85)], Field::Reg(*r), dst, &[])?,
+        ("test", [dst, Operand::Imm(k)]) => {
+            let size = dst.size().ok_or_else(|| anyhow!("operand size is ambiguous"))?;
+            rm(&mut out, &[], size, &[byte(size, 0xf7)], Field::Ext(0), dst, &imm(*k, size.min(4) as usize)?)?;
+        }
+        ("xchg", [a, Operand::Reg(r)]) | ("xchg", [Operand::Reg(r), a @ Operand::Mem(_)]) => {
+            rm(&mut out, &[], r.size, &[byte(r.size, 0x87)], Field::Reg(*r), a, &[])?;
+        }
+        ("imul", [Operand::Reg(r), src]) => rm(&mut out, &[], r.size, &[0x0f, 0xaf], Field::Reg(*r), src, &[])?,
+        ("imul", [Operand::Reg(r), src, Operand::Imm(k)]) => {
+            if fits_i8(*k) {
+                rm(&mut out, &[], r.size, &[0x6b], Field::Reg(*r), src, &imm(*k, 1)?)?;
+            } else {
+                rm(&mut out, &[], r.size, &[0x69], Field::Reg(*r), src, &imm(*k, r.size.min(4) as usize)?)?;
+            }
+        }
+        (op, [dst]) if UNARY.iter().any(|&(u, _)| u == op) => {
+            let n = UNARY.iter().find(|&&(u, _)| u == op).map(|&(_, n)| n).unwrap_or_default();
+            let size = dst.size().ok_or_else(|| anyhow!("operand size is ambiguous"))?;
+            rm(&mut out, &[], size, &[byte(size, 0xf7)], Field::Ext(n), dst, &[])?;
+        }
+        ("inc" | "dec", [dst]) => {
+            let size = dst.size().ok_or_else(|| anyhow!("operand size is ambiguous"))?;
+            rm(&mut out, &[], size, &[byte(size, 0xff)], Field::Ext(u8::from(mnemonic == "dec")), dst, &[])?;
+        }
+        (op, [dst, amount]) if SHIFT.iter().any(|&(s, _)| s == op) => {
+            let n = SHIFT.iter().find(|&&(s, _)| s == op).map(|&(_, n)| n).unwrap_or_default();
+            let size = dst.size().ok_or_else(|| anyhow!("operand size is ambiguous"))?;
+            match amount {
+                Operand::Reg(Reg { num: 1, size: 1 }) => rm(&mut out, &[], size, &[byte(size, 0xd3)], Field::Ext(n), dst, &[])?,
+                Operand::Imm(1) => rm(&mut out, &[], size, &[byte(size, 0xd1)], Field::Ext(n), dst, &[])?,
+                Operand::Imm(k) => rm(&mut out, &[], size, &[byte(size, 0xc1)], Field::Ext(n), dst, &imm(*k, 1)?)?,
+                _ => bail!("shift count must be cl or an immediate"),
+            }
+        }
+        ("movzx" | "movsx", [Operand::Reg(r), src]) => {
+            let base = if mnemonic == "movzx" { 0xb6 } else { 0xbe };
+            let opcode = match src.size() {
+                Some(1) => base,
+                Some(2) => base + 1,
+                _ => bail!("source must be 8 or 16 bits"),
+            };
+            rm(&mut out, &[], r.size, &[0x0f, opcode], Field::Reg(*r), src, &[])?;
+        }
+        ("movsxd", [Operand::Reg(r), src]) => rm(&mut out, &[], 8, &[0x63], Field::Reg(*r), src, &[])?,
+        ("lea", [Operand::Reg(r), src @ Operand::Mem(_)]) => rm(&mut out, &[], r.size, &[0x8d], Field::Reg(*r), src, &[])?,
+        ("push", [Operand::Reg(r)]) => plus_reg(&mut out, 4, 0x50, *r, &[]),
+        ("pop", [Operand::Reg(r)]) => plus_reg(&mut out, 4, 0x58, *r, &[]),
+        ("push", [Operand::Imm(k)]) if fits_i8(*k) => out.bytes.extend([0x6a, *k as u8]),
+        ("push", [Operand::Imm(k)]) => {
+            out.bytes.push(0x68);
+            out.bytes.extend(imm(*k, 4)?);
+        }
+        ("push", [src @ Operand::Mem(_)]) => rm(&mut out, &[], 4, &[0xff], Field::Ext(6), src, &[])?,
+        ("call", [Operand::Label(name, addend)]) => branch(&mut out, &[0xe8], name, *addend),
+        ("jmp", [Operand::Label(name, addend)]) => branch(&mut out, &[0xe9], name, *addend),
+        ("call" | "jmp", [target]) => {
+            let ext = if mnemonic == "call" { 2 } else { 4 };
+            rm(&mut out, &[], 4, &[0xff], Field::Ext(ext), target, &[])?;
+        }
+        (op, [Operand::Label(name, addend)]) if op.starts_with('j') && condition(&op[1..]).is_some() => {
+            let cc = condition(&op[1..]).unwrap_or_default();
+            branch(&mut out, &[0x0f, 0x80 + cc], name, *addend);
+        }
+        (op, [dst]) if op.starts_with("set") && condition(&op[3..]).is_some() => {
+            let cc = condition(&op[3..]).unwrap_or_default();
+            rm(&mut out, &[], 1, &[0x0f, 0x90 + cc], Field::Ext(0), dst, &[])?;
+        }
+        (op, [Operand::Reg(r), src]) if op.starts_with("cmov") && condition(&op[4..]).is_some() => {
+            let cc = condition(&op[4..]).unwrap_or_default();
+            rm(&mut out, &[], r.size, &[0x0f, 0x40 + cc], Field::Reg(*r), src, &[])?;
+        }
+        ("movq", [Operand::Reg(x), src]) if x.size == 16 => rm(&mut out, &[0x66], 8, &[0x0f, 0x6e], Field::Reg(*x), src, &[])?,
+        ("movq", [dst, Operand::Reg(x)]) if x.size == 16 => rm(&mut out, &[0x66], 8, &[0x0f, 0x7e], Field::Reg(*x), dst, &[])?,
+        ("addsd" | "subsd" | "mulsd" | "divsd", [Operand::Reg(x), src]) if x.size == 16 => {
+            let opcode = match mnemonic {
+                "addsd" => 0x58,
+                "mulsd" => 0x59,
+                "subsd" => 0x5c,
+                _ => 0x5e,
+            };
+            rm(&mut out, &[0xf2], 4, &[0x0f, opcode], Field::Reg(*x), src, &[])?;
+        }
+        ("ucomisd", [Operand::Reg(x), src]) if x.size == 16 => rm(&mut out, &[0x66], 4, &[0x0f, 0x2e], Field::Reg(*x), src, &[])?,
+        ("cvtsi2sd", [Operand::Reg(x), src]) if x.size == 16 => rm(&mut out, &[0xf2], 8, &[0x0f, 0x2a], Field::Reg(*x), src, &[])?,
+        ("cvttsd2si", [Operand::Reg(r), src]) => rm(&mut out, &[0xf2], r.size, &[0x0f, 0x2c], Field::Reg(*r), src, &[])?,
+        ("cvtsd2ss" | "cvtss2sd", [Operand::Reg(x), src]) => {
+            let prefix = if mnemonic == "cvtsd2ss" { 0xf2 } else { 0xf3 };
+            rm(&mut out, &[prefix], 4, &[0x0f, 0x5a], Field::Reg(*x), src, &[])?;
+        }
+        ("rep", [Operand::Label(string_op, 0)]) => match string_op.as_str() {
+            "movsb" => out.bytes.extend([0xf3, 0xa4]),
+            "stosb" => out.bytes.extend([0xf3, 0xaa]),
+            "movsq" => out.bytes.extend([0xf3, 0x48, 0xa5]),
+            "stosq" => out.bytes.extend([0xf3, 0x48, 0xab]),
+            _ => bail!("rep needs a string instruction"),
+        },
+        ("leave", []) => out.bytes.push(0xc9),
+        ("ret", []) => out.bytes.push(0xc3),
+        ("cqo", []) => out.bytes.extend([0x48, 0x99]),
+        ("cdq", []) => out.bytes.push(0x99),
+        ("syscall", []) => out.bytes.extend([0x0f, 0x05]),
+        ("nop", []) => out.bytes.push(0x90),
+        ("pause", []) => out.bytes.extend([0xf3, 0x90]),
+        ("hlt", []) => out.bytes.push(0xf4),
+        ("ud2", []) => out.bytes.extend([0x0f, 0x0b]),
+        ("mfence", []) => out.bytes.extend([0x0f, 0xae, 0xf0]),
+        _ => bail!("unsupported instruction"),
+    }
+    Ok(out)
+}
@@ -1,42 +1,675 @@
 //! ELF Binary Format (Linux)
+//!
+//! Relocatable objects, static archives and statically linked executables, written
+//! from [`Object`]s; the system linker is only needed by programs calling into libc.
 
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
-use anyhow::Result;
-
-/// Write ELF binary
-pub fn write(path: &Path, code: &[u8]) -> Result<()> {
-    // For now, write assembly and use system linker
-    let asm_path = path.with_extension("s");
-    std::fs::write(&asm_path, code)?;
-    
+use anyhow::{anyhow, bail, Result};
+use super::{asm, assemble_object, align_up, Put, StringTable};
+use super::object::{apply, Arch, Object, RelocKind, SectionKind, SymbolKind, Target};
+
+/// Syscall-level runtime linked into static executables: entry point, TLS,
+/// threads, heap and the libc functions the native backends call
+const RUNTIME_X86_64: &str = include_str!("runtime/x86_64-linux.s");
+const RUNTIME_AARCH64: &str = include_str!("runtime/aarch64-linux.s");
+
+/// Entry point of static executables, defined by the runtime
+const ENTRY: &str = "__aether_start";
+
+const BASE_ADDRESS: u64 = 0x40_0000;
+const EHDR_SIZE: u64 = 64;
+const PHDR_SIZE: u64 = 56;
+const SHDR_SIZE: u64 = 64;
+const SYM_SIZE: u64 = 24;
+const RELA_SIZE: u64 = 24;
+
+const ET_REL: u16 = 1;
+const ET_EXEC: u16 = 2;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+
+const SHF_WRITE: u64 = 0x1;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+const SHF_INFO_LINK: u64 = 0x40;
+const SHF_TLS: u64 = 0x400;
+
+const PT_LOAD: u32 = 1;
+const PT_TLS: u32 = 7;
+const PT_GNU_STACK: u32 = 0x6474_e551;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+const STT_TLS: u8 = 6;
+
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    addr: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64,
+}
+
+impl SectionHeader {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.u32(self.name);
+        out.u32(self.kind);
+        out.u64(self.flags);
+        out.u64(self.addr);
+        out.u64(self.offset);
+        out.u64(self.size);
+        out.u32(self.link);
+        out.u32(self.info);
+        out.u64(self.align);
+        out.u64(self.entsize);
+    }
+}
+
+/// Symbol table entry
+fn symbol(out: &mut Vec<u8>, name: u32, info: u8, section: u16, value: u64, size: u64) {
+    out.u32(name);
+    out.push(info);
+    out.push(0);
+    out.u16(section);
+    out.u64(value);
+    out.u64(size);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn file_header(out: &mut Vec<u8>, kind: u16, arch: Arch, entry: u64, phnum: u16, shoff: u64, shnum: u16, shstrndx: u16) {
+    out.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    out.extend_from_slice(&[0; 8]);
+    out.u16(kind);
+    out.u16(match arch {
+        Arch::X86_64 => 62,
+        Arch::Aarch64 => 183,
+    });
+    out.u32(1);
+    out.u64(entry);
+    out.u64(if phnum > 0 { EHDR_SIZE } else { 0 });
+    out.u64(shoff);
+    out.u32(0);
+    out.u16(EHDR_SIZE as u16);
+    out.u16(if phnum > 0 { PHDR_SIZE as u16 } else { 0 });
+    out.u16(phnum);
+    out.u16(SHDR_SIZE as u16);
+    out.u16(shnum);
+    out.u16(shstrndx);
+}
+
+fn section_flags(kind: SectionKind) -> u64 {
+    match kind {
+        SectionKind::Text => SHF_ALLOC | SHF_EXECINSTR,
+        SectionKind::ReadOnly => SHF_ALLOC,
+        SectionKind::Data => SHF_ALLOC | SHF_WRITE,
+        SectionKind::Tls => SHF_ALLOC | SHF_WRITE | SHF_TLS,
+    }
+}
+
+fn symbol_type(kind: SymbolKind) -> u8 {
+    match kind {
+        SymbolKind::Func => STT_FUNC,
+        SymbolKind::Object => STT_OBJECT,
+        SymbolKind::Tls => STT_TLS,
+        SymbolKind::None => STT_NOTYPE,
+    }
+}
+
+/// ELF relocation type of `kind`
+fn reloc_type(arch: Arch, kind: RelocKind) -> Result<u32> {
+    Ok(match (arch, kind) {
+        (Arch::X86_64, RelocKind::Abs64) => 1,
+        (Arch::X86_64, RelocKind::Pc32) => 2,
+        (Arch::X86_64, RelocKind::Branch32) => 4,
+        (Arch::X86_64, RelocKind::TpOff32) => 23,
+        (Arch::Aarch64, RelocKind::Abs64) => 257,
+        (Arch::Aarch64, RelocKind::Page21) => 275,
+        (Arch::Aarch64, RelocKind::AddLo12) => 277,
+        (Arch::Aarch64, RelocKind::LoadLo12 { shift: 0 }) => 278,
+        (Arch::Aarch64, RelocKind::CondBr19) => 280,
+        (Arch::Aarch64, RelocKind::Jump26) => 282,
+        (Arch::Aarch64, RelocKind::Call26) => 283,
+        (Arch::Aarch64, RelocKind::LoadLo12 { shift: 1 }) => 284,
+        (Arch::Aarch64, RelocKind::LoadLo12 { shift: 2 }) => 285,
+        (Arch::Aarch64, RelocKind::LoadLo12 { shift: 3 }) => 286,
+        (Arch::Aarch64, RelocKind::LoadLo12 { shift: 4 }) => 299,
+        (Arch::Aarch64, RelocKind::TpHi12) => 549,
+        (Arch::Aarch64, RelocKind::TpLo12) => 551,
+        (arch, kind) => bail!("No ELF relocation for {:?} on {:?}", kind, arch),
+    })
+}
+
+// ========== Relocatable Objects ==========
+
+/// Serialize `object` as an ELF relocatable file (`.o`)
+pub fn write_object(object: &Object) -> Result<Vec<u8>> {
+    let mut shstrtab = StringTable::new();
+    let mut strtab = StringTable::new();
+    let mut headers = vec![SectionHeader {
+        name: 0, kind: 0, flags: 0, addr: 0, offset: 0, size: 0, link: 0, info: 0, align: 0, entsize: 0,
+    }];
+    let mut out = vec![0; EHDR_SIZE as usize];
+
+    // Contents, section i at header index i + 1
+    for section in &object.sections {
+        out.pad_to(section.align);
+        headers.push(SectionHeader {
+            name: shstrtab.add(&section.name),
+            kind: SHT_PROGBITS,
+            flags: section_flags(section.kind),
+            addr: 0,
+            offset: out.len() as u64,
+            size: section.data.len() as u64,
+            link: 0,
+            info: 0,
+            align: section.align,
+            entsize: 0,
+        });
+        out.extend_from_slice(&section.data);
+    }
+
+    // Symbols: null, one per section, locals, then globals and undefined
+    let section_count = object.sections.len();
+    let mut index = vec![0u32; object.symbols.len()];
+    let mut symtab = Vec::new();
+    symbol(&mut symtab, 0, 0, 0, 0, 0);
+    for i in 0..section_count {
+        symbol(&mut symtab, 0, (STB_LOCAL << 4) | STT_SECTION, i as u16 + 1, 0, 0);
+    }
+    let mut count = section_count as u32 + 1;
+    let mut first_global = count;
+    for global in [false, true] {
+        if global {
+            first_global = count;
+        }
+        for (i, sym) in object.symbols.iter().enumerate() {
+            if (sym.global || sym.section.is_none()) != global {
+                continue;
+            }
+            let binding = if global { STB_GLOBAL } else { STB_LOCAL };
+            let section = sym.section.map_or(0, |s| s as u16 + 1);
+            let name = strtab.add(&sym.name);
+            symbol(&mut symtab, name, (binding << 4) | symbol_type(sym.kind), section, sym.offset, sym.size);
+            index[i] = count;
+            count += 1;
+        }
+    }
+
+    let symtab_index = (headers.len() + object.sections.iter().filter(|s| !s.relocs.is_empty()).count() + 1) as u32;
+    for (i, section) in object.sections.iter().enumerate() {
+        if section.relocs.is_empty() {
+            continue;
+        }
+        out.pad_to(8);
+        let offset = out.len() as u64;
+        for reloc in &section.relocs {
+            let sym = match reloc.target {
+                Target::Symbol(s) => index[s],
+                Target::Section(s) => s as u32 + 1,
+            };
+            out.u64(reloc.offset);
+            out.u64(((sym as u64) << 32) | reloc_type(object.arch, reloc.kind)? as u64);
+            out.u64(reloc.addend as u64);
+        }
+        headers.push(SectionHeader {
+            name: shstrtab.add(&format!(".rela{}", section.name)),
+            kind: SHT_RELA,
+            flags: SHF_INFO_LINK,
+            addr: 0,
+            offset,
+            size: out.len() as u64 - offset,
+            link: symtab_index,
+            info: i as u32 + 1,
+            align: 8,
+            entsize: RELA_SIZE,
+        });
+    }
+
+    // Non-executable stack
+    headers.push(SectionHeader {
+        name: shstrtab.add(".note.GNU-stack"),
+        kind: SHT_PROGBITS,
+        flags: 0,
+        addr: 0,
+        offset: out.len() as u64,
+        size: 0,
+        link: 0,
+        info: 0,
+        align: 1,
+        entsize: 0,
+    });
+
+    let strtab_index = symtab_index + 1;
+    write_tables(&mut out, &mut headers, &mut shstrtab, symtab, strtab, strtab_index, first_global);
+    let shoff = out.len() as u64;
+    for header in &headers {
+        header.write(&mut out);
+    }
+    let mut ehdr = Vec::new();
+    file_header(&mut ehdr, ET_REL, object.arch, 0, 0, shoff, headers.len() as u16, headers.len() as u16 - 1);
+    out[..EHDR_SIZE as usize].copy_from_slice(&ehdr);
+    Ok(out)
+}
+
+/// Append `.symtab`, `.strtab` and `.shstrtab` with their headers (padding `out` to 8 first)
+fn write_tables(
+    out: &mut Vec<u8>,
+    headers: &mut Vec<SectionHeader>,
+    shstrtab: &mut StringTable,
+    symtab: Vec<u8>,
+    strtab: StringTable,
+    strtab_index: u32,
+    first_global: u32,
+) {
+    out.pad_to(8);
+    headers.push(SectionHeader {
+        name: shstrtab.add(".symtab"),
+        kind: SHT_SYMTAB,
+        flags: 0,
+        addr: 0,
+        offset: out.len() as u64,
+        size: symtab.len() as u64,
+        link: strtab_index,
+        info: first_global,
+        align: 8,
+        entsize: SYM_SIZE,
+    });
+    out.extend_from_slice(&symtab);
+    headers.push(SectionHeader {
+        name: shstrtab.add(".strtab"),
+        kind: SHT_STRTAB,
+        flags: 0,
+        addr: 0,
+        offset: out.len() as u64,
+        size: strtab.data.len() as u64,
+        link: 0,
+        info: 0,
+        align: 1,
+        entsize: 0,
+    });
+    out.extend_from_slice(&strtab.data);
+    let name = shstrtab.add(".shstrtab");
+    headers.push(SectionHeader {
+        name,
+        kind: SHT_STRTAB,
+        flags: 0,
+        addr: 0,
+        offset: out.len() as u64,
+        size: shstrtab.data.len() as u64,
+        link: 0,
+        info: 0,
+        align: 1,
+        entsize: 0,
+    });
+    out.extend_from_slice(&shstrtab.data);
+    out.pad_to(8);
+}
+
+// ========== Static Linking ==========
+
+/// Segments of an executable in address order, with the section kinds each holds
+const SEGMENTS: [(&[SectionKind], u32); 3] = [
+    (&[SectionKind::ReadOnly], PF_R),
+    (&[SectionKind::Text], PF_R | PF_X),
+    (&[SectionKind::Tls, SectionKind::Data], PF_R | PF_W),
+];
+
+/// Output section names of the merged section kinds
+fn output_name(kind: SectionKind) -> &'static str {
+    match kind {
+        SectionKind::Text => ".text",
+        SectionKind::ReadOnly => ".rodata",
+        SectionKind::Data => ".data",
+        SectionKind::Tls => ".tdata",
+    }
+}
+
+/// Link `objects` into a static executable starting at the global symbol `entry`
+pub fn link(objects: &[Object], entry: &str) -> Result<Vec<u8>> {
+    let arch = objects.first().ok_or_else(|| anyhow!("Nothing to link"))?.arch;
+    if objects.iter().any(|o| o.arch != arch) {
+        bail!("Cannot link objects of different architectures");
+    }
+    let page = match arch {
+        Arch::X86_64 => 0x1000,
+        Arch::Aarch64 => 0x1_0000,
+    };
+
+    // Global symbol table
+    let mut globals: HashMap<&str, (usize, usize)> = HashMap::new();
+    for (o, object) in objects.iter().enumerate() {
+        for (s, sym) in object.symbols.iter().enumerate() {
+            if sym.global && sym.section.is_some() && globals.insert(&sym.name, (o, s)).is_some() {
+                bail!("Duplicate symbol {}", sym.name);
+            }
+        }
+    }
+    let mut missing: Vec<&str> = objects
+        .iter()
+        .flat_map(|o| o.undefined())
+        .filter(|name| !globals.contains_key(name))
+        .collect();
+    missing.sort_unstable();
+    missing.dedup();
+    if !missing.is_empty() {
+        bail!("Undefined symbols: {}", missing.join(", "));
+    }
+
+    // Layout: file offsets and addresses differ by BASE_ADDRESS, headers at the start of the first segment
+    let has = |kind: SectionKind| objects.iter().flat_map(|o| &o.sections).any(|s| s.kind == kind && !s.data.is_empty());
+    let loads: Vec<_> = SEGMENTS
+        .iter()
+        .enumerate()
+        .filter(|(i, (kinds, _))| *i == 0 || kinds.iter().any(|&k| has(k)))
+        .map(|(_, segment)| *segment)
+        .collect();
+    let tls = has(SectionKind::Tls);
+    let phnum = loads.len() + tls as usize + 1;
+    let mut offset = EHDR_SIZE + PHDR_SIZE * phnum as u64;
+    let mut addr: Vec<Vec<u64>> = objects.iter().map(|o| vec![0; o.sections.len()]).collect();
+    let mut segments = Vec::new();
+    let mut ranges: Vec<(SectionKind, u64, u64, u64)> = Vec::new();
+    for (i, (kinds, flags)) in loads.iter().enumerate() {
+        if i > 0 {
+            offset = align_up(offset, page);
+        }
+        let start = if i == 0 { 0 } else { offset };
+        for &kind in kinds.iter() {
+            let mut range: Option<(u64, u64)> = None;
+            let mut align = 1;
+            for (o, object) in objects.iter().enumerate() {
+                for (s, section) in object.sections.iter().enumerate() {
+                    if section.kind != kind {
+                        continue;
+                    }
+                    offset = align_up(offset, section.align);
+                    addr[o][s] = BASE_ADDRESS + offset;
+                    range.get_or_insert((offset, offset));
+                    offset += section.data.len() as u64;
+                    align = align.max(section.align);
+                }
+            }
+            if let Some((begin, _)) = range {
+                ranges.push((kind, begin, offset, align));
+            }
+        }
+        segments.push((start, offset, *flags));
+    }
+    let (tls_start, tls_size, tls_align) = ranges
+        .iter()
+        .find(|r| r.0 == SectionKind::Tls)
+        .map_or((0, 0, 1), |&(_, begin, end, align)| (BASE_ADDRESS + begin, end - begin, align));
+
+    // Symbol addresses
+    let address = |o: usize, target: Target| -> u64 {
+        match target {
+            Target::Section(s) => addr[o][s],
+            Target::Symbol(s) => {
+                let sym = &objects[o].symbols[s];
+                let (o, sym) = match sym.section {
+                    Some(_) => (o, sym),
+                    None => {
+                        let (o, s) = globals[sym.name.as_str()];
+                        (o, &objects[o].symbols[s])
+                    }
+                };
+                addr[o][sym.section.unwrap_or_default()] + sym.offset
+            }
+        }
+    };
+    // Offset of a TLS address from the thread pointer (variant II on x86-64, variant I on AArch64)
+    let tp_offset = |address: u64| -> i64 {
+        let within = (address - tls_start) as i64;
+        match arch {
+            Arch::X86_64 => within - align_up(tls_size, tls_align) as i64,
+            Arch::Aarch64 => within + align_up(16, tls_align) as i64,
+        }
+    };
+
+    let mut image = vec![0u8; offset as usize];
+    for (o, object) in objects.iter().enumerate() {
+        for (s, section) in object.sections.iter().enumerate() {
+            let start = (addr[o][s] - BASE_ADDRESS) as usize;
+            image[start..start + section.data.len()].copy_from_slice(&section.data);
+            for reloc in &section.relocs {
+                let target = address(o, reloc.target);
+                let value = if reloc.kind.is_tls() { tp_offset(target) } else { target as i64 } + reloc.addend;
+                let place = addr[o][s] + reloc.offset;
+                let field = &mut image[start + reloc.offset as usize..];
+                apply(reloc.kind, field, value, place as i64)
+                    .map_err(|e| anyhow!("{} in {} at offset {:#x}", e, section.name, reloc.offset))?;
+            }
+        }
+    }
+    let (o, s) = *globals.get(entry).ok_or_else(|| anyhow!("Entry point {} is not defined", entry))?;
+    let entry = address(o, Target::Symbol(s));
+
+    // Program headers
+    let mut phdrs = Vec::new();
+    let mut program_header = |kind: u32, flags: u32, offset: u64, address: u64, size: u64, align: u64| {
+        phdrs.u32(kind);
+        phdrs.u32(flags);
+        phdrs.u64(offset);
+        phdrs.u64(address);
+        phdrs.u64(address);
+        phdrs.u64(size);
+        phdrs.u64(size);
+        phdrs.u64(align);
+    };
+    for &(start, end, flags) in &segments {
+        program_header(PT_LOAD, flags, start, BASE_ADDRESS + start, end - start, page);
+    }
+    if tls {
+        program_header(PT_TLS, PF_R, tls_start - BASE_ADDRESS, tls_start, tls_size, tls_align);
+    }
+    program_header(PT_GNU_STACK, PF_R | PF_W, 0, 0, 0, 16);
+    image[EHDR_SIZE as usize..EHDR_SIZE as usize + phdrs.len()].copy_from_slice(&phdrs);
+
+    // Section headers and a symbol table for debuggers and objdump
+    let mut shstrtab = StringTable::new();
+    let mut strtab = StringTable::new();
+    let mut headers = vec![SectionHeader {
+        name: 0, kind: 0, flags: 0, addr: 0, offset: 0, size: 0, link: 0, info: 0, align: 0, entsize: 0,
+    }];
+    for &(kind, begin, end, align) in &ranges {
+        headers.push(SectionHeader {
+            name: shstrtab.add(output_name(kind)),
+            kind: SHT_PROGBITS,
+            flags: section_flags(kind),
+            addr: BASE_ADDRESS + begin,
+            offset: begin,
+            size: end - begin,
+            link: 0,
+            info: 0,
+            align,
+            entsize: 0,
+        });
+    }
+    let header_index = |kind: SectionKind| ranges.iter().position(|r| r.0 == kind).map_or(0, |i| i as u16 + 1);
+    let mut symtab = Vec::new();
+    symbol(&mut symtab, 0, 0, 0, 0, 0);
+    let mut count = 1;
+    let mut first_global = 1;
+    for global in [false, true] {
+        if global {
+            first_global = count;
+        }
+        for (o, object) in objects.iter().enumerate() {
+            for sym in object.symbols.iter().filter(|s| s.global == global) {
+                let Some(section) = sym.section else { continue };
+                let kind = object.sections[section].kind;
+                let value = if sym.kind == SymbolKind::Tls {
+                    addr[o][section] + sym.offset - tls_start
+                } else {
+                    addr[o][section] + sym.offset
+                };
+                let binding = if global { STB_GLOBAL } else { STB_LOCAL };
+                let name = strtab.add(&sym.name);
+                symbol(&mut symtab, name, (binding << 4) | symbol_type(sym.kind), header_index(kind), value, sym.size);
+                count += 1;
+            }
+        }
+    }
+    let strtab_index = headers.len() as u32 + 1;
+    write_tables(&mut image, &mut headers, &mut shstrtab, symtab, strtab, strtab_index, first_global);
+    let shoff = image.len() as u64;
+    for header in &headers {
+        header.write(&mut image);
+    }
+    let mut ehdr = Vec::new();
+    file_header(&mut ehdr, ET_EXEC, arch, entry, phnum as u16, shoff, headers.len() as u16, headers.len() as u16 - 1);
+    image[..EHDR_SIZE as usize].copy_from_slice(&ehdr);
+    Ok(image)
+}
+
+// ========== Archives ==========
+
+/// `ar` member header
+fn member_header(out: &mut Vec<u8>, name: &str, size: usize) {
+    out.extend_from_slice(format!("{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n", name, 0, 0, 0, 644, size).as_bytes());
+}
+
+/// Serialize `objects` as a GNU `ar` archive with a symbol index
+pub fn write_archive(objects: &[(String, Object)]) -> Result<Vec<u8>> {
+    let mut members = Vec::new();
+    for (name, object) in objects {
+        if name.len() > 15 {
+            bail!("Archive member name {} is too long", name);
+        }
+        members.push((format!("{}/", name), write_object(object)?, object));
+    }
+    let symbols: Vec<(usize, &str)> = members
+        .iter()
+        .enumerate()
+        .flat_map(|(m, (_, _, object))| {
+            object.symbols.iter().filter(|s| s.global && s.section.is_some()).map(move |s| (m, s.name.as_str()))
+        })
+        .collect();
+
+    let mut index = Vec::new();
+    index.extend_from_slice(&(symbols.len() as u32).to_be_bytes());
+    let names: usize = symbols.iter().map(|(_, name)| name.len() + 1).sum();
+    let index_size = 4 + 4 * symbols.len() + names;
+    let mut offsets = Vec::new();
+    let mut offset = 8 + 60 + align_up(index_size as u64, 2) as usize;
+    for (_, data, _) in &members {
+        offsets.push(offset);
+        offset += 60 + align_up(data.len() as u64, 2) as usize;
+    }
+    for &(m, _) in &symbols {
+        index.extend_from_slice(&(offsets[m] as u32).to_be_bytes());
+    }
+    for &(_, name) in &symbols {
+        index.extend_from_slice(name.as_bytes());
+        index.push(0);
+    }
+
+    let mut out = b"!<arch>\n".to_vec();
+    member_header(&mut out, "/", index.len());
+    out.extend_from_slice(&index);
+    out.pad_to(2);
+    for (name, data, _) in &members {
+        member_header(&mut out, name, data.len());
+        out.extend_from_slice(data);
+        if out.len() % 2 == 1 {
+            out.push(b'\n');
+        }
+    }
+    Ok(out)
+}
+
+// ========== Output Files ==========
+
+/// Assemble GNU assembly for `target` into a relocatable object at `path`
+pub fn assemble(path: &Path, code: &[u8], target: &str) -> Result<()> {
+    let object = assemble_object(code, target)?;
+    std::fs::write(path, write_object(&object)?).map_err(|e| anyhow!("Cannot write {}: {}", path.display(), e))
+}
+
+/// Assemble GNU assembly for `target` into a static library at `path`
+pub fn write_staticlib(path: &Path, code: &[u8], target: &str) -> Result<()> {
+    let object = assemble_object(code, target)?;
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("lib");
+    let name = format!("{}.o", stem.strip_prefix("lib").unwrap_or(stem).chars().take(13).collect::<String>());
+    let archive = write_archive(&[(name, object)])?;
+    std::fs::write(path, archive).map_err(|e| anyhow!("Cannot write {}: {}", path.display(), e))
+}
+
+/// Linker and program interpreter for a Linux target; cross binutils
+/// (`aarch64-linux-gnu-ld`, ...) when it is not the host architecture
+fn toolchain(arch: Arch) -> (String, &'static str) {
+    let (name, interpreter) = match arch {
+        Arch::X86_64 => ("x86_64", "/lib64/ld-linux-x86-64.so.2"),
+        Arch::Aarch64 => ("aarch64", "/lib/ld-linux-aarch64.so.1"),
+    };
+    if name == std::env::consts::ARCH {
+        ("ld".into(), interpreter)
+    } else {
+        (format!("{}-linux-gnu-ld", name), interpreter)
+    }
+}
+
+/// Write ELF executable: statically linked against the built-in runtime, or
+/// dynamically against libc through the system linker when the program calls
+/// C functions the runtime lacks
+pub fn write(path: &Path, code: &[u8], target: &str) -> Result<()> {
+    let object = assemble_object(code, target)?;
+    let runtime = asm::assemble(
+        match object.arch {
+            Arch::X86_64 => RUNTIME_X86_64,
+            Arch::Aarch64 => RUNTIME_AARCH64,
+        },
+        object.arch,
+    )
+    .map_err(|e| anyhow!("Runtime: {}", e))?;
+    let provided: HashSet<&str> = runtime
+        .symbols
+        .iter()
+        .filter(|s| s.global && s.section.is_some())
+        .map(|s| s.name.as_str())
+        .collect();
+    if object.undefined().all(|name| provided.contains(name)) {
+        let image = link(&[object, runtime], ENTRY)?;
+        std::fs::write(path, image).map_err(|e| anyhow!("Cannot write {}: {}", path.display(), e))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+        }
+        return Ok(());
+    }
+
     let obj_path = path.with_extension("o");
-    
-    // Assemble
-    let status = std::process::Command::new("as")
+    std::fs::write(&obj_path, write_object(&object)?)?;
+    let (linker, interpreter) = toolchain(object.arch);
+    let status = std::process::Command::new(&linker)
         .arg("-o")
+        .arg(path)
         .arg(&obj_path)
-        .arg(&asm_path)
-        .status()?;
-    
-    if status.success() {
-        // Link
-        let status = std::process::Command::new("ld")
-            .arg("-o")
-            .arg(path)
-            .arg(&obj_path)
-            .args(["-lc", "-dynamic-linker", "/lib64/ld-linux-x86-64.so.2"])
-            .status()?;
-        
-        // Cleanup
-        let _ = std::fs::remove_file(&obj_path);
-        let _ = std::fs::remove_file(&asm_path);
-        
-        if !status.success() {
-            anyhow::bail!("Linker failed");
-        }
-    } else {
-        anyhow::bail!("Assembler failed");
+        .args(["-lc", "-dynamic-linker", interpreter])
+        .status();
+
+    // Cleanup
+    let _ = std::fs::remove_file(&obj_path);
+
+    if !status.map_err(|e| anyhow!("Cannot run {}: {}", linker, e))?.success() {
+        bail!("Linker failed");
     }
-    
     Ok(())
 }
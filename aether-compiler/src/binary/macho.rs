@@ -1,43 +1,342 @@
 //! Mach-O Binary Format (macOS)
+//!
+//! 64-bit relocatable objects for arm64 and x86_64, written from [`Object`]s on any
+//! host; executables are linked against libSystem by ld64.
 
 use std::path::Path;
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use super::{assemble_object, Put, StringTable};
+use super::object::{Arch, Object, RelocKind, SectionKind, Target};
 
-/// Write Mach-O binary
-pub fn write(path: &Path, code: &[u8]) -> Result<()> {
-    let asm_path = path.with_extension("s");
-    std::fs::write(&asm_path, code)?;
-    
-    let obj_path = path.with_extension("o");
-    
-    // Assemble with xcrun
-    let status = std::process::Command::new("xcrun")
-        .args(["as", "-o"])
-        .arg(&obj_path)
-        .arg(&asm_path)
-        .status()?;
-    
-    if status.success() {
-        // Link with xcrun
-        let status = std::process::Command::new("xcrun")
-            .args(["ld", "-o"])
-            .arg(path)
-            .args(["-e", "_main", "-lSystem"])
-            .args(["-L/Library/Developer/CommandLineTools/SDKs/MacOSX.sdk/usr/lib"])
-            .args(["-syslibroot", "/Library/Developer/CommandLineTools/SDKs/MacOSX.sdk"])
-            .arg(&obj_path)
-            .status()?;
-        
-        // Cleanup
-        let _ = std::fs::remove_file(&obj_path);
-        let _ = std::fs::remove_file(&asm_path);
-        
-        if !status.success() {
-            anyhow::bail!("Linker failed");
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+const MH_OBJECT: u32 = 1;
+const CPU_TYPE_X86_64: u32 = 0x0100_0007;
+const CPU_TYPE_ARM64: u32 = 0x0100_000c;
+const CPU_SUBTYPE_X86_64_ALL: u32 = 3;
+const CPU_SUBTYPE_ARM64_ALL: u32 = 0;
+
+const LC_SYMTAB: u32 = 0x2;
+const LC_DYSYMTAB: u32 = 0xb;
+const LC_SEGMENT_64: u32 = 0x19;
+const LC_BUILD_VERSION: u32 = 0x32;
+const PLATFORM_MACOS: u32 = 1;
+/// Oldest macOS the objects claim to run on (11.0, the first with arm64)
+const MIN_MACOS: u32 = 0x000b_0000;
+const MIN_MACOS_ARG: &str = "11.0";
+
+const HEADER_SIZE: u32 = 32;
+const SEGMENT_SIZE: u32 = 72;
+const SECTION_SIZE: u32 = 80;
+const BUILD_VERSION_SIZE: u32 = 24;
+const SYMTAB_SIZE: u32 = 24;
+const DYSYMTAB_SIZE: u32 = 80;
+
+const S_ATTR_PURE_INSTRUCTIONS: u32 = 0x8000_0000;
+const S_ATTR_SOME_INSTRUCTIONS: u32 = 0x400;
+const S_THREAD_LOCAL_REGULAR: u32 = 0x11;
+
+const N_EXT: u8 = 0x1;
+const N_SECT: u8 = 0xe;
+
+const X86_64_RELOC_UNSIGNED: u8 = 0;
+const X86_64_RELOC_SIGNED: u8 = 1;
+const X86_64_RELOC_BRANCH: u8 = 2;
+const X86_64_RELOC_SIGNED_1: u8 = 6;
+const X86_64_RELOC_SIGNED_2: u8 = 7;
+const X86_64_RELOC_SIGNED_4: u8 = 8;
+const ARM64_RELOC_UNSIGNED: u8 = 0;
+const ARM64_RELOC_BRANCH26: u8 = 2;
+const ARM64_RELOC_PAGE21: u8 = 3;
+const ARM64_RELOC_PAGEOFF12: u8 = 4;
+const ARM64_RELOC_ADDEND: u8 = 10;
+
+/// Segment and section names of a section kind
+fn section_names(kind: SectionKind) -> (&'static str, &'static str, u32) {
+    match kind {
+        SectionKind::Text => ("__TEXT", "__text", S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS),
+        SectionKind::ReadOnly => ("__TEXT", "__const", 0),
+        SectionKind::Data => ("__DATA", "__data", 0),
+        SectionKind::Tls => ("__DATA", "__thread_data", S_THREAD_LOCAL_REGULAR),
+    }
+}
+
+/// Fixed-size, NUL-padded name field
+fn name16(out: &mut Vec<u8>, name: &str) {
+    let mut field = [0u8; 16];
+    field[..name.len()].copy_from_slice(name.as_bytes());
+    out.extend_from_slice(&field);
+}
+
+/// `relocation_info` entry
+fn relocation(out: &mut Vec<u8>, address: u64, symbol: u32, pcrel: bool, length: u32, external: bool, kind: u8) {
+    out.u32(address as u32);
+    out.u32((symbol & 0x00ff_ffff) | (pcrel as u32) << 24 | length << 25 | (external as u32) << 27 | (kind as u32) << 28);
+}
+
+/// `nlist_64` entry
+fn nlist(out: &mut Vec<u8>, name: u32, kind: u8, section: u8, value: u64) {
+    out.u32(name);
+    out.push(kind);
+    out.push(section);
+    out.u16(0);
+    out.u64(value);
+}
+
+// ========== Relocatable Objects ==========
+
+/// Serialize `object` as a Mach-O 64 relocatable file (`.o`). C symbols get
+/// their leading underscore here; local labels become relocations against
+/// `ltmpN`, a local symbol at the start of each section.
+pub fn write_object(object: &Object) -> Result<Vec<u8>> {
+    let sections = &object.sections;
+    if sections.iter().any(|s| s.kind == SectionKind::Tls && !s.data.is_empty())
+        || sections.iter().flat_map(|s| &s.relocs).any(|r| r.kind.is_tls())
+    {
+        bail!("Thread-local variables are not supported in Mach-O objects yet");
+    }
+
+    // Addresses within the object's single segment, sections back to back
+    let commands = SEGMENT_SIZE + SECTION_SIZE * sections.len() as u32 + BUILD_VERSION_SIZE + SYMTAB_SIZE + DYSYMTAB_SIZE;
+    let data_start = (HEADER_SIZE + commands) as u64;
+    let mut addresses = Vec::new();
+    let mut address = 0;
+    for section in sections {
+        address = super::align_up(address, section.align);
+        addresses.push(address);
+        address += section.data.len() as u64;
+    }
+    let vmsize = address;
+
+    // Symbols: section starts and locals, then external definitions and undefined, each sorted by name
+    let mut strtab = StringTable::new();
+    let mut symtab = Vec::new();
+    let mut index = vec![0u32; object.symbols.len()];
+    let mut count = 0u32;
+    for (i, &address) in addresses.iter().enumerate() {
+        nlist(&mut symtab, strtab.add(&format!("ltmp{}", i)), N_SECT, i as u8 + 1, address);
+        count += 1;
+    }
+    let mut groups: [Vec<usize>; 3] = Default::default();
+    for (i, sym) in object.symbols.iter().enumerate() {
+        let group = match (sym.section, sym.global) {
+            (Some(_), false) => 0,
+            (Some(_), true) => 1,
+            (None, _) => 2,
+        };
+        groups[group].push(i);
+    }
+    for group in &mut groups[1..] {
+        group.sort_by(|&a, &b| object.symbols[a].name.cmp(&object.symbols[b].name));
+    }
+    for (g, group) in groups.iter().enumerate() {
+        for &i in group {
+            let sym = &object.symbols[i];
+            let name = strtab.add(&format!("_{}", sym.name));
+            match sym.section {
+                Some(s) => {
+                    let kind = if g == 1 { N_SECT | N_EXT } else { N_SECT };
+                    nlist(&mut symtab, name, kind, s as u8 + 1, addresses[s] + sym.offset);
+                }
+                None => nlist(&mut symtab, name, N_EXT, 0, 0),
+            }
+            index[i] = count;
+            count += 1;
         }
+    }
+    let locals = sections.len() as u32 + groups[0].len() as u32;
+    let (extdefs, undefs) = (groups[1].len() as u32, groups[2].len() as u32);
+
+    // Section contents with implicit addends, and their relocations
+    let mut contents = Vec::new();
+    let mut relocs = Vec::new();
+    let mut reloc_ranges = Vec::new();
+    for (s, section) in sections.iter().enumerate() {
+        contents.resize((addresses[s]) as usize, 0);
+        let start = contents.len();
+        contents.extend_from_slice(&section.data);
+        let first = relocs.len() / 8;
+        // Last to first, like Apple's assembler
+        for reloc in section.relocs.iter().rev() {
+            let symbol = match reloc.target {
+                Target::Symbol(t) => index[t],
+                Target::Section(t) => t as u32,
+            };
+            let field = start + reloc.offset as usize;
+            let put32 = |contents: &mut Vec<u8>, v: i64| -> Result<()> {
+                let v = i32::try_from(v).map_err(|_| anyhow!("Relocation addend {} out of range", v))?;
+                contents[field..field + 4].copy_from_slice(&v.to_le_bytes());
+                Ok(())
+            };
+            match (object.arch, reloc.kind) {
+                (Arch::X86_64, RelocKind::Abs64) => {
+                    contents[field..field + 8].copy_from_slice(&reloc.addend.to_le_bytes());
+                    relocation(&mut relocs, reloc.offset, symbol, false, 3, true, X86_64_RELOC_UNSIGNED);
+                }
+                (Arch::X86_64, RelocKind::Branch32) => {
+                    put32(&mut contents, reloc.addend + 4)?;
+                    relocation(&mut relocs, reloc.offset, symbol, true, 2, true, X86_64_RELOC_BRANCH);
+                }
+                // The SIGNED_n forms mark an n-byte immediate after the displacement
+                (Arch::X86_64, RelocKind::Pc32) => {
+                    let kind = match -4 - reloc.addend {
+                        1 => X86_64_RELOC_SIGNED_1,
+                        2 => X86_64_RELOC_SIGNED_2,
+                        4 => X86_64_RELOC_SIGNED_4,
+                        _ => X86_64_RELOC_SIGNED,
+                    };
+                    put32(&mut contents, reloc.addend + 4)?;
+                    relocation(&mut relocs, reloc.offset, symbol, true, 2, true, kind);
+                }
+                (Arch::Aarch64, RelocKind::Abs64) => {
+                    contents[field..field + 8].copy_from_slice(&reloc.addend.to_le_bytes());
+                    relocation(&mut relocs, reloc.offset, symbol, false, 3, true, ARM64_RELOC_UNSIGNED);
+                }
+                (Arch::Aarch64, kind @ (RelocKind::Call26 | RelocKind::Jump26 | RelocKind::Page21 | RelocKind::AddLo12 | RelocKind::LoadLo12 { .. })) => {
+                    // Instruction fields cannot hold an addend; it goes in a preceding ADDEND entry
+                    if reloc.addend != 0 {
+                        if !(-(1 << 23)..(1 << 23)).contains(&reloc.addend) {
+                            bail!("Relocation addend {} out of range", reloc.addend);
+                        }
+                        relocation(&mut relocs, reloc.offset, reloc.addend as u32, false, 2, false, ARM64_RELOC_ADDEND);
+                    }
+                    let (pcrel, kind) = match kind {
+                        RelocKind::Call26 | RelocKind::Jump26 => (true, ARM64_RELOC_BRANCH26),
+                        RelocKind::Page21 => (true, ARM64_RELOC_PAGE21),
+                        _ => (false, ARM64_RELOC_PAGEOFF12),
+                    };
+                    relocation(&mut relocs, reloc.offset, symbol, pcrel, 2, true, kind);
+                }
+                (arch, kind) => bail!("No Mach-O relocation for {:?} on {:?}", kind, arch),
+            }
+        }
+        reloc_ranges.push((first as u32, (relocs.len() / 8) as u32 - first as u32));
+    }
+    contents.resize(vmsize as usize, 0);
+
+    let reloc_start = super::align_up(data_start + contents.len() as u64, 8);
+    let symtab_start = reloc_start + relocs.len() as u64;
+    let strtab_start = symtab_start + symtab.len() as u64;
+    strtab.data.pad_to(8);
+
+    let mut out = Vec::new();
+    let (cputype, cpusubtype) = match object.arch {
+        Arch::X86_64 => (CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_ALL),
+        Arch::Aarch64 => (CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_ALL),
+    };
+    out.u32(MH_MAGIC_64);
+    out.u32(cputype);
+    out.u32(cpusubtype);
+    out.u32(MH_OBJECT);
+    out.u32(4);
+    out.u32(commands);
+    out.u32(0);
+    out.u32(0);
+
+    out.u32(LC_SEGMENT_64);
+    out.u32(SEGMENT_SIZE + SECTION_SIZE * sections.len() as u32);
+    name16(&mut out, "");
+    out.u64(0);
+    out.u64(vmsize);
+    out.u64(data_start);
+    out.u64(contents.len() as u64);
+    out.u32(7);
+    out.u32(7);
+    out.u32(sections.len() as u32);
+    out.u32(0);
+    for (s, section) in sections.iter().enumerate() {
+        let (segment, name, flags) = section_names(section.kind);
+        let (first, nreloc) = reloc_ranges[s];
+        name16(&mut out, name);
+        name16(&mut out, segment);
+        out.u64(addresses[s]);
+        out.u64(section.data.len() as u64);
+        out.u32((data_start + addresses[s]) as u32);
+        out.u32(section.align.max(1).trailing_zeros());
+        out.u32(if nreloc > 0 { reloc_start as u32 + first * 8 } else { 0 });
+        out.u32(nreloc);
+        out.u32(flags);
+        out.u32(0);
+        out.u32(0);
+        out.u32(0);
+    }
+
+    out.u32(LC_BUILD_VERSION);
+    out.u32(BUILD_VERSION_SIZE);
+    out.u32(PLATFORM_MACOS);
+    out.u32(MIN_MACOS);
+    out.u32(0);
+    out.u32(0);
+
+    out.u32(LC_SYMTAB);
+    out.u32(SYMTAB_SIZE);
+    out.u32(symtab_start as u32);
+    out.u32(count);
+    out.u32(strtab_start as u32);
+    out.u32(strtab.data.len() as u32);
+
+    out.u32(LC_DYSYMTAB);
+    out.u32(DYSYMTAB_SIZE);
+    for field in [0, locals, locals, extdefs, locals + extdefs, undefs] {
+        out.u32(field);
+    }
+    for _ in 0..12 {
+        out.u32(0);
+    }
+
+    out.extend_from_slice(&contents);
+    out.pad_to(8);
+    out.extend_from_slice(&relocs);
+    out.extend_from_slice(&symtab);
+    out.extend_from_slice(&strtab.data);
+    Ok(out)
+}
+
+// ========== Output Files ==========
+
+/// Assemble GNU assembly for `target` into a Mach-O object at `path`
+pub fn assemble(path: &Path, code: &[u8], target: &str) -> Result<()> {
+    let object = assemble_object(code, target)?;
+    std::fs::write(path, write_object(&object)?).map_err(|e| anyhow!("Cannot write {}: {}", path.display(), e))
+}
+
+/// Write Mach-O executable: the object is written in-process, then linked
+/// against libSystem by ld64 (`xcrun ld` on macOS, `ld64.lld` with the SDK in
+/// `SDKROOT` elsewhere)
+pub fn write(path: &Path, code: &[u8], target: &str) -> Result<()> {
+    let object = assemble_object(code, target)?;
+    let obj_path = path.with_extension("o");
+    std::fs::write(&obj_path, write_object(&object)?)?;
+
+    let arch = match object.arch {
+        Arch::X86_64 => "x86_64",
+        Arch::Aarch64 => "arm64",
+    };
+    let (linker, mut command) = if cfg!(target_os = "macos") {
+        let mut command = std::process::Command::new("xcrun");
+        command.arg("ld");
+        ("xcrun ld", command)
     } else {
-        anyhow::bail!("Assembler failed");
+        ("ld64.lld", std::process::Command::new("ld64.lld"))
+    };
+    command
+        .args(["-arch", arch, "-platform_version", "macos", MIN_MACOS_ARG, MIN_MACOS_ARG, "-e", "_main", "-o"])
+        .arg(path)
+        .arg(&obj_path)
+        .arg("-lSystem");
+    let sdk = std::env::var("SDKROOT").ok().or_else(|| {
+        let output = std::process::Command::new("xcrun").arg("--show-sdk-path").output().ok()?;
+        output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    });
+    if let Some(sdk) = sdk {
+        command.arg("-syslibroot").arg(sdk);
+    }
+    let status = command.status();
+
+    // Cleanup
+    let _ = std::fs::remove_file(&obj_path);
+
+    if !status.map_err(|e| anyhow!("Cannot run {}: {}", linker, e))?.success() {
+        bail!("Linker failed");
     }
-    
     Ok(())
 }
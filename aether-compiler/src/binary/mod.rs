@@ -1,28 +1,52 @@
 //! Aether Binary Output - Platform-specific binary formats
 //!
-//! ELF (Linux), Mach-O (macOS), PE (Windows)
+//! ELF (Linux), Mach-O (macOS), PE (Windows), WebAssembly
 
+pub mod asm;
 pub mod elf;
 pub mod macho;
+pub mod object;
 pub mod pe;
+pub mod wasm;
 
+use std::collections::HashMap;
 use std::path::Path;
 use anyhow::{anyhow, Result};
+use object::{Arch, Object};
 
 /// Write binary file in appropriate format for target
 pub fn write(path: &Path, code: &[u8], target: &str) -> Result<()> {
     if target.contains("linux") {
-        elf::write(path, code)
+        elf::write(path, code, target)
     } else if target.contains("darwin") || target.contains("apple") {
-        macho::write(path, code)
+        macho::write(path, code, target)
     } else if target.contains("windows") {
-        pe::write(path, code)
+        pe::write(path, code, target)
+    } else if target.starts_with("wasm32") {
+        wasm::write(path, code)
     } else {
         // Default: write assembly for external assembler
         write_asm(path, code)
     }
 }
 
+/// Assemble GNU assembly into a relocatable object file in the target's format
+pub fn assemble(path: &Path, code: &[u8], target: &str) -> Result<()> {
+    if target.contains("darwin") || target.contains("apple") {
+        macho::assemble(path, code, target)
+    } else if target.contains("windows") {
+        pe::assemble(path, code, target)
+    } else {
+        elf::assemble(path, code, target)
+    }
+}
+
+/// Assemble GNU assembly for `target` into an [`Object`]
+pub fn assemble_object(code: &[u8], target: &str) -> Result<Object> {
+    let source = std::str::from_utf8(code).map_err(|e| anyhow!("Assembly is not UTF-8: {}", e))?;
+    asm::assemble(source, Arch::from_target(target)?)
+}
+
 /// Write assembly file for external assembler/linker
 fn write_asm(path: &Path, code: &[u8]) -> Result<()> {
     let asm_path = path.with_extension("s");
@@ -86,3 +110,58 @@ fn write_asm(path: &Path, code: &[u8]) -> Result<()> {
     
     Ok(())
 }
+
+// ========== Byte Buffers ==========
+
+/// Little-endian appends
+pub(crate) trait Put {
+    fn u16(&mut self, v: u16);
+    fn u32(&mut self, v: u32);
+    fn u64(&mut self, v: u64);
+    fn pad_to(&mut self, align: u64);
+}
+
+impl Put for Vec<u8> {
+    fn u16(&mut self, v: u16) {
+        self.extend_from_slice(&v.to_le_bytes());
+    }
+    fn u32(&mut self, v: u32) {
+        self.extend_from_slice(&v.to_le_bytes());
+    }
+    fn u64(&mut self, v: u64) {
+        self.extend_from_slice(&v.to_le_bytes());
+    }
+    fn pad_to(&mut self, align: u64) {
+        self.resize(align_up(self.len() as u64, align) as usize, 0);
+    }
+}
+
+pub(crate) fn align_up(value: u64, align: u64) -> u64 {
+    value.div_ceil(align.max(1)) * align.max(1)
+}
+
+/// NUL-separated names, deduplicated
+pub(crate) struct StringTable {
+    pub data: Vec<u8>,
+    offsets: HashMap<String, u32>,
+}
+
+impl StringTable {
+    pub fn new() -> Self {
+        StringTable { data: vec![0], offsets: HashMap::new() }
+    }
+
+    pub fn add(&mut self, name: &str) -> u32 {
+        if name.is_empty() {
+            return 0;
+        }
+        if let Some(&offset) = self.offsets.get(name) {
+            return offset;
+        }
+        let offset = self.data.len() as u32;
+        self.data.extend_from_slice(name.as_bytes());
+        self.data.push(0);
+        self.offsets.insert(name.to_string(), offset);
+        offset
+    }
+}
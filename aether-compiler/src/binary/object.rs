@@ -0,0 +1,211 @@
+//! Relocatable objects: machine code, data, symbols and relocations
+//!
+//! Format-neutral; the ELF, Mach-O and COFF writers serialize these.
+
+use anyhow::{bail, Result};
+
+/// Instruction set of an object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    X86_64,
+    Aarch64,
+}
+
+impl Arch {
+    /// Architecture of a target triple (`aarch64-unknown-linux-gnu`, `arm64-apple-darwin`, ...)
+    pub fn from_target(target: &str) -> Result<Arch> {
+        match target.split('-').next().unwrap_or_default() {
+            "x86_64" | "amd64" => Ok(Arch::X86_64),
+            "aarch64" | "arm64" => Ok(Arch::Aarch64),
+            arch => bail!("Unsupported architecture {} in target {}", arch, target),
+        }
+    }
+}
+
+/// What a section holds, which decides its flags and segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionKind {
+    Text,
+    ReadOnly,
+    Data,
+    /// Initial image of thread-local storage
+    Tls,
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub kind: SectionKind,
+    pub align: u64,
+    pub data: Vec<u8>,
+    pub relocs: Vec<Reloc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Func,
+    Object,
+    Tls,
+    /// Undefined, or a label without `.type`
+    None,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    /// Defining section; `None` for undefined symbols
+    pub section: Option<usize>,
+    pub offset: u64,
+    pub size: u64,
+    pub global: bool,
+    pub kind: SymbolKind,
+}
+
+/// What a relocation refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Symbol(usize),
+    /// Start of a section of the same object (local labels)
+    Section(usize),
+}
+
+/// How a relocated field is computed (S = target, A = addend, P = field address)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocKind {
+    /// 64-bit S + A
+    Abs64,
+    /// x86-64 32-bit S + A - P (data and `[rip + sym]`)
+    Pc32,
+    /// x86-64 `call`/`jmp` rel32
+    Branch32,
+    /// x86-64 32-bit offset from the thread pointer (local-exec TLS)
+    TpOff32,
+    /// AArch64 `bl`
+    Call26,
+    /// AArch64 `b`
+    Jump26,
+    /// AArch64 `b.cond`, `cbz` and `cbnz`
+    CondBr19,
+    /// AArch64 `adrp`: page of S + A relative to the page of P
+    Page21,
+    /// AArch64 `add` with the low 12 bits of S + A
+    AddLo12,
+    /// AArch64 load or store with the low 12 bits of S + A, scaled by the access size
+    LoadLo12 { shift: u8 },
+    /// AArch64 `add` with bits 12-23 of the thread-pointer offset
+    TpHi12,
+    /// AArch64 `add` with bits 0-11 of the thread-pointer offset
+    TpLo12,
+}
+
+impl RelocKind {
+    /// Whether the target is a thread-local symbol addressed from the thread pointer
+    pub fn is_tls(self) -> bool {
+        matches!(self, RelocKind::TpOff32 | RelocKind::TpHi12 | RelocKind::TpLo12)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Reloc {
+    pub offset: u64,
+    pub target: Target,
+    pub kind: RelocKind,
+    pub addend: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Object {
+    pub arch: Arch,
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+}
+
+impl Object {
+    pub fn new(arch: Arch) -> Self {
+        Object { arch, sections: Vec::new(), symbols: Vec::new() }
+    }
+
+    /// Index of the section called `name`, created empty if missing
+    pub fn section(&mut self, name: &str, kind: SectionKind) -> usize {
+        if let Some(i) = self.sections.iter().position(|s| s.name == name) {
+            return i;
+        }
+        let align = if kind == SectionKind::Text { 4 } else { 1 };
+        self.sections.push(Section { name: name.to_string(), kind, align, data: Vec::new(), relocs: Vec::new() });
+        self.sections.len() - 1
+    }
+
+    /// Index of the symbol called `name`, added as undefined if missing
+    pub fn symbol(&mut self, name: &str) -> usize {
+        if let Some(i) = self.symbols.iter().position(|s| s.name == name) {
+            return i;
+        }
+        self.symbols.push(Symbol {
+            name: name.to_string(),
+            section: None,
+            offset: 0,
+            size: 0,
+            global: true,
+            kind: SymbolKind::None,
+        });
+        self.symbols.len() - 1
+    }
+
+    /// Names of the symbols referenced but not defined here
+    pub fn undefined(&self) -> impl Iterator<Item = &str> {
+        self.symbols.iter().filter(|s| s.section.is_none()).map(|s| s.name.as_str())
+    }
+}
+
+/// Store the resolved value of a relocation into `field`, `value` being S + A
+/// (or the thread-pointer offset for TLS kinds) and `place` the field's address
+pub fn apply(kind: RelocKind, field: &mut [u8], value: i64, place: i64) -> Result<()> {
+    let put32 = |field: &mut [u8], v: u32| field[..4].copy_from_slice(&v.to_le_bytes());
+    let insn = u32::from_le_bytes([field[0], field[1], field[2], field[3]]);
+    let rel32 = |v: i64| -> Result<u32> {
+        i32::try_from(v).map(|v| v as u32).map_err(|_| anyhow::anyhow!("Relocation out of range ({:#x})", v))
+    };
+    match kind {
+        RelocKind::Abs64 => field[..8].copy_from_slice(&value.to_le_bytes()),
+        RelocKind::Pc32 | RelocKind::Branch32 => put32(field, rel32(value - place)?),
+        RelocKind::TpOff32 => put32(field, rel32(value)?),
+        RelocKind::Call26 | RelocKind::Jump26 => {
+            let delta = value - place;
+            if delta & 3 != 0 || !(-(1 << 27)..(1 << 27)).contains(&delta) {
+                bail!("Branch target out of range ({:#x})", delta);
+            }
+            put32(field, (insn & 0xfc00_0000) | ((delta >> 2) as u32 & 0x03ff_ffff));
+        }
+        RelocKind::CondBr19 => {
+            let delta = value - place;
+            if delta & 3 != 0 || !(-(1 << 20)..(1 << 20)).contains(&delta) {
+                bail!("Branch target out of range ({:#x})", delta);
+            }
+            put32(field, (insn & 0xff00_001f) | (((delta >> 2) as u32 & 0x7ffff) << 5));
+        }
+        RelocKind::Page21 => {
+            let pages = (value >> 12) - (place >> 12);
+            if !(-(1 << 20)..(1 << 20)).contains(&pages) {
+                bail!("Page offset out of range ({:#x})", pages);
+            }
+            let (lo, hi) = (pages as u32 & 3, (pages as u32 >> 2) & 0x7ffff);
+            put32(field, (insn & 0x9f00_001f) | (lo << 29) | (hi << 5));
+        }
+        RelocKind::AddLo12 => put32(field, (insn & 0xffc0_03ff) | (((value & 0xfff) as u32) << 10)),
+        RelocKind::LoadLo12 { shift } => {
+            let low = (value & 0xfff) as u32;
+            if low & ((1 << shift) - 1) != 0 {
+                bail!("Misaligned address {:#x} for a {}-byte access", value, 1 << shift);
+            }
+            put32(field, (insn & 0xffc0_03ff) | ((low >> shift) << 10));
+        }
+        RelocKind::TpHi12 | RelocKind::TpLo12 => {
+            if !(0..(1 << 24)).contains(&value) {
+                bail!("Thread-local offset out of range ({:#x})", value);
+            }
+            let part = if kind == RelocKind::TpHi12 { value >> 12 } else { value & 0xfff } as u32;
+            put32(field, (insn & 0xffc0_03ff) | (part << 10));
+        }
+    }
+    Ok(())
+}
@@ -1,16 +1,450 @@
 //! PE Binary Format (Windows)
+//!
+//! COFF objects and PE32+ console executables for x86-64, written from
+//! [`Object`]s on any host; executables import only from kernel32.dll.
 
+use std::collections::HashMap;
 use std::path::Path;
-use anyhow::Result;
-
-/// Write PE binary (stub - needs Windows toolchain)
-pub fn write(path: &Path, code: &[u8]) -> Result<()> {
-    // Write assembly file
-    let asm_path = path.with_extension("s");
-    std::fs::write(&asm_path, code)?;
-    
-    // On Windows, would use ml64 and link
-    // For now just create the asm file
-    
-    anyhow::bail!("Windows PE output requires Windows toolchain (ml64/link)")
+use anyhow::{anyhow, bail, Result};
+use super::{asm, assemble_object, align_up, Put, StringTable};
+use super::object::{apply, Arch, Object, RelocKind, SectionKind, SymbolKind, Target};
+
+/// kernel32-based runtime linked into executables: entry point, threads, heap
+/// and the libc functions the native backend calls
+const RUNTIME: &str = include_str!("runtime/x86_64-windows.s");
+
+/// Entry point of executables, defined by the runtime
+const ENTRY: &str = "__aether_start";
+
+/// Prefix of the import address table slot of a kernel32 function
+const IMPORT_PREFIX: &str = "__imp_";
+const IMPORT_DLL: &str = "KERNEL32.dll";
+
+const IMAGE_BASE: u64 = 0x1_4000_0000;
+const SECTION_ALIGN: u64 = 0x1000;
+const FILE_ALIGN: u64 = 0x200;
+/// Main thread stack, all committed up front since the backend emits no stack probes
+const STACK_SIZE: u64 = 0x80_0000;
+
+const IMAGE_FILE_MACHINE_AMD64: u16 = 0x8664;
+const IMAGE_FILE_RELOCS_STRIPPED: u16 = 0x0001;
+const IMAGE_FILE_EXECUTABLE_IMAGE: u16 = 0x0002;
+const IMAGE_FILE_LARGE_ADDRESS_AWARE: u16 = 0x0020;
+const IMAGE_SUBSYSTEM_WINDOWS_CUI: u16 = 3;
+const IMAGE_DLLCHARACTERISTICS_NX_COMPAT: u16 = 0x0100;
+const IMAGE_DLLCHARACTERISTICS_TERMINAL_SERVER_AWARE: u16 = 0x8000;
+const IMAGE_DIRECTORY_ENTRY_IMPORT: usize = 1;
+const IMAGE_DIRECTORY_ENTRY_IAT: usize = 12;
+
+const IMAGE_SCN_CNT_CODE: u32 = 0x20;
+const IMAGE_SCN_CNT_INITIALIZED_DATA: u32 = 0x40;
+const IMAGE_SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+const IMAGE_SCN_MEM_READ: u32 = 0x4000_0000;
+const IMAGE_SCN_MEM_WRITE: u32 = 0x8000_0000;
+
+const IMAGE_SYM_CLASS_EXTERNAL: u8 = 2;
+const IMAGE_SYM_CLASS_STATIC: u8 = 3;
+const IMAGE_SYM_DTYPE_FUNCTION: u16 = 0x20;
+
+const IMAGE_REL_AMD64_ADDR64: u16 = 1;
+const IMAGE_REL_AMD64_REL32: u16 = 4;
+
+const DOS_HEADER_SIZE: u64 = 64;
+const FILE_HEADER_SIZE: u64 = 20;
+const OPTIONAL_HEADER_SIZE: u64 = 240;
+const SECTION_HEADER_SIZE: u64 = 40;
+const SYMBOL_SIZE: u64 = 18;
+
+/// Section name and characteristics of a section kind
+fn section_info(kind: SectionKind) -> Result<(&'static str, u32)> {
+    Ok(match kind {
+        SectionKind::Text => (".text", IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ),
+        SectionKind::ReadOnly => (".rdata", IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ),
+        SectionKind::Data => (".data", IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE),
+        SectionKind::Tls => bail!("Thread-local variables are not supported in PE output yet"),
+    })
+}
+
+/// Eight-byte name field, spilling long names to the string table as `/offset`
+/// (sections) or a zero word and offset (symbols)
+fn short_name(out: &mut Vec<u8>, name: &str, strtab: &mut StringTable, section: bool) {
+    let mut field = [0u8; 8];
+    if name.len() <= 8 {
+        field[..name.len()].copy_from_slice(name.as_bytes());
+    } else if section {
+        let text = format!("/{}", strtab.add(name));
+        field[..text.len()].copy_from_slice(text.as_bytes());
+    } else {
+        field[4..].copy_from_slice(&strtab.add(name).to_le_bytes());
+    }
+    out.extend_from_slice(&field);
+}
+
+/// Section header; `address` and `virtual_size` are zero in objects
+#[allow(clippy::too_many_arguments)]
+fn section_header(out: &mut Vec<u8>, name: [u8; 8], virtual_size: u32, address: u32, raw_size: u32, raw: u32, relocs: u32, nrelocs: u16, flags: u32) {
+    out.extend_from_slice(&name);
+    out.u32(virtual_size);
+    out.u32(address);
+    out.u32(raw_size);
+    out.u32(raw);
+    out.u32(relocs);
+    out.u32(0);
+    out.u16(nrelocs);
+    out.u16(0);
+    out.u32(flags);
+}
+
+fn file_header(out: &mut Vec<u8>, sections: u16, symbols_at: u32, symbols: u32, optional: u16, flags: u16) {
+    out.u16(IMAGE_FILE_MACHINE_AMD64);
+    out.u16(sections);
+    out.u32(0);
+    out.u32(symbols_at);
+    out.u32(symbols);
+    out.u16(optional);
+    out.u16(flags);
+}
+
+fn require_x86_64(arch: Arch) -> Result<()> {
+    if arch != Arch::X86_64 {
+        bail!("PE output supports x86_64 only");
+    }
+    Ok(())
+}
+
+// ========== COFF Objects ==========
+
+/// Serialize `object` as a COFF object (`.obj`); addends live in the relocated fields
+pub fn write_object(object: &Object) -> Result<Vec<u8>> {
+    require_x86_64(object.arch)?;
+    let sections = &object.sections;
+    let mut strtab = StringTable::new();
+    // COFF string table offsets count its 4-byte size field
+    strtab.data = vec![0; 4];
+
+    // Symbols: a section symbol (plus auxiliary record) per section, then locals, globals and undefined
+    let mut index = vec![0u32; object.symbols.len()];
+    let mut symbols = Vec::new();
+    for (s, section) in sections.iter().enumerate() {
+        let (name, _) = section_info(section.kind)?;
+        short_name(&mut symbols, name, &mut strtab, false);
+        symbols.u32(0);
+        symbols.u16(s as u16 + 1);
+        symbols.u16(0);
+        symbols.push(IMAGE_SYM_CLASS_STATIC);
+        symbols.push(1);
+        symbols.u32(section.data.len() as u32);
+        symbols.u16(section.relocs.len() as u16);
+        symbols.extend_from_slice(&[0; 12]);
+    }
+    let mut count = 2 * sections.len() as u32;
+    let order = object.symbols.iter().enumerate().filter(|(_, s)| !s.global && s.section.is_some())
+        .chain(object.symbols.iter().enumerate().filter(|(_, s)| s.global || s.section.is_none()));
+    for (i, sym) in order {
+        short_name(&mut symbols, &sym.name, &mut strtab, false);
+        symbols.u32(sym.offset as u32);
+        symbols.u16(sym.section.map_or(0, |s| s as u16 + 1));
+        symbols.u16(if sym.kind == SymbolKind::Func { IMAGE_SYM_DTYPE_FUNCTION } else { 0 });
+        symbols.push(if sym.global || sym.section.is_none() { IMAGE_SYM_CLASS_EXTERNAL } else { IMAGE_SYM_CLASS_STATIC });
+        symbols.push(0);
+        index[i] = count;
+        count += 1;
+    }
+
+    // Section headers, then each section's contents followed by its relocations
+    let mut headers = Vec::new();
+    let mut body = Vec::new();
+    let body_start = FILE_HEADER_SIZE + SECTION_HEADER_SIZE * sections.len() as u64;
+    for section in sections {
+        let (name, flags) = section_info(section.kind)?;
+        let align = (section.align.clamp(1, 8192).trailing_zeros() + 1) << 20;
+        body.pad_to(4);
+        let raw = body_start + body.len() as u64;
+        let mut data = section.data.clone();
+        let mut relocs = Vec::new();
+        for reloc in &section.relocs {
+            let symbol = match reloc.target {
+                Target::Symbol(t) => index[t],
+                Target::Section(t) => 2 * t as u32,
+            };
+            let field = reloc.offset as usize;
+            let kind = match reloc.kind {
+                RelocKind::Abs64 => {
+                    data[field..field + 8].copy_from_slice(&reloc.addend.to_le_bytes());
+                    IMAGE_REL_AMD64_ADDR64
+                }
+                // REL32_n (5 to 9) subtract n more bytes for an immediate after the field
+                RelocKind::Pc32 | RelocKind::Branch32 => {
+                    let extra = match -4 - reloc.addend {
+                        n @ 1..=5 if reloc.kind == RelocKind::Pc32 => n,
+                        _ => 0,
+                    };
+                    let value = i32::try_from(reloc.addend + 4 + extra)
+                        .map_err(|_| anyhow!("Relocation addend {} out of range", reloc.addend))?;
+                    data[field..field + 4].copy_from_slice(&value.to_le_bytes());
+                    IMAGE_REL_AMD64_REL32 + extra as u16
+                }
+                kind => bail!("No COFF relocation for {:?}", kind),
+            };
+            relocs.u32(reloc.offset as u32);
+            relocs.u32(symbol);
+            relocs.u16(kind);
+        }
+        body.extend_from_slice(&data);
+        let relocs_at = if relocs.is_empty() { 0 } else { body_start + body.len() as u64 };
+        body.extend_from_slice(&relocs);
+        let mut field = Vec::new();
+        short_name(&mut field, name, &mut strtab, true);
+        section_header(
+            &mut headers,
+            field.try_into().unwrap_or_default(),
+            0,
+            0,
+            section.data.len() as u32,
+            if section.data.is_empty() { 0 } else { raw as u32 },
+            relocs_at as u32,
+            section.relocs.len() as u16,
+            flags | align,
+        );
+    }
+    body.pad_to(4);
+
+    let symbols_at = body_start + body.len() as u64;
+    let size = strtab.data.len() as u32;
+    strtab.data[..4].copy_from_slice(&size.to_le_bytes());
+    let mut out = Vec::new();
+    file_header(&mut out, sections.len() as u16, symbols_at as u32, count, 0, 0);
+    out.extend_from_slice(&headers);
+    out.extend_from_slice(&body);
+    out.extend_from_slice(&symbols);
+    out.extend_from_slice(&strtab.data);
+    debug_assert_eq!(symbols.len() as u64, count as u64 * SYMBOL_SIZE);
+    Ok(out)
+}
+
+// ========== Linking ==========
+
+/// Import directory, lookup and address tables, hint/name entries and the DLL
+/// name for `imports`, placed at `rva`; returns the contents and the IAT range
+fn import_section(imports: &[&str], rva: u64) -> (Vec<u8>, u64, u64) {
+    let slots = 8 * (imports.len() as u64 + 1);
+    let lookup = rva + 40;
+    let iat = lookup + slots;
+    let mut names = Vec::new();
+    let mut hints = Vec::new();
+    let names_at = iat + slots;
+    for name in imports {
+        hints.push(names_at + names.len() as u64);
+        names.u16(0);
+        names.extend_from_slice(name.as_bytes());
+        names.push(0);
+        names.pad_to(2);
+    }
+    let dll = names_at + names.len() as u64;
+    names.extend_from_slice(IMPORT_DLL.as_bytes());
+    names.push(0);
+
+    let mut out = Vec::new();
+    out.u32(lookup as u32);
+    out.u32(0);
+    out.u32(0);
+    out.u32(dll as u32);
+    out.u32(iat as u32);
+    out.extend_from_slice(&[0; 20]);
+    for _ in 0..2 {
+        for &hint in &hints {
+            out.u64(hint);
+        }
+        out.u64(0);
+    }
+    out.extend_from_slice(&names);
+    (out, iat, slots)
+}
+
+/// Link `objects` into a PE32+ console executable starting at the global
+/// symbol `entry`; undefined `__imp_Name` symbols become kernel32.dll imports
+pub fn link(objects: &[Object], entry: &str) -> Result<Vec<u8>> {
+    for object in objects {
+        require_x86_64(object.arch)?;
+    }
+
+    let mut globals: HashMap<&str, (usize, usize)> = HashMap::new();
+    for (o, object) in objects.iter().enumerate() {
+        for (s, sym) in object.symbols.iter().enumerate() {
+            if sym.global && sym.section.is_some() && globals.insert(&sym.name, (o, s)).is_some() {
+                bail!("Duplicate symbol {}", sym.name);
+            }
+        }
+    }
+    let mut undefined: Vec<&str> = objects
+        .iter()
+        .flat_map(|o| o.undefined())
+        .filter(|name| !globals.contains_key(name))
+        .collect();
+    undefined.sort_unstable();
+    undefined.dedup();
+    let (imports, missing): (Vec<&str>, Vec<&str>) = undefined.into_iter().partition(|name| name.starts_with(IMPORT_PREFIX));
+    if !missing.is_empty() {
+        bail!("Undefined symbols: {}", missing.join(", "));
+    }
+    let imports: Vec<&str> = imports.iter().map(|name| &name[IMPORT_PREFIX.len()..]).collect();
+
+    // Output sections in address order, each holding every input section of its kind
+    let kinds: Vec<SectionKind> = [SectionKind::Text, SectionKind::ReadOnly, SectionKind::Data]
+        .into_iter()
+        .filter(|&k| objects.iter().flat_map(|o| &o.sections).any(|s| s.kind == k && !s.data.is_empty()))
+        .collect();
+    if objects.iter().flat_map(|o| &o.sections).any(|s| s.kind == SectionKind::Tls && !s.data.is_empty()) {
+        section_info(SectionKind::Tls)?;
+    }
+    let count = kinds.len() + !imports.is_empty() as usize;
+    let headers_size = align_up(DOS_HEADER_SIZE + 4 + FILE_HEADER_SIZE + OPTIONAL_HEADER_SIZE + SECTION_HEADER_SIZE * count as u64, FILE_ALIGN);
+
+    let mut rva_of: Vec<Vec<u64>> = objects.iter().map(|o| vec![0; o.sections.len()]).collect();
+    // (name, flags, rva, contents)
+    let mut outputs: Vec<(&str, u32, u64, Vec<u8>)> = Vec::new();
+    let mut rva = SECTION_ALIGN;
+    for &kind in &kinds {
+        let (name, flags) = section_info(kind)?;
+        let mut contents = Vec::new();
+        for (o, object) in objects.iter().enumerate() {
+            for (s, section) in object.sections.iter().enumerate().filter(|(_, s)| s.kind == kind) {
+                contents.pad_to(section.align);
+                rva_of[o][s] = rva + contents.len() as u64;
+                contents.extend_from_slice(&section.data);
+            }
+        }
+        let size = contents.len() as u64;
+        outputs.push((name, flags, rva, contents));
+        rva = align_up(rva + size, SECTION_ALIGN);
+    }
+    let mut iat = (0, 0);
+    if !imports.is_empty() {
+        let (contents, iat_rva, iat_size) = import_section(&imports, rva);
+        iat = (iat_rva, iat_size);
+        let size = contents.len() as u64;
+        outputs.push((".idata", IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE, rva, contents));
+        rva = align_up(rva + size, SECTION_ALIGN);
+    }
+    let image_size = rva;
+
+    // Relocations, applied at the fixed image base
+    let address = |o: usize, target: Target| -> u64 {
+        let (o, sym) = match target {
+            Target::Section(s) => return IMAGE_BASE + rva_of[o][s],
+            Target::Symbol(s) => (o, &objects[o].symbols[s]),
+        };
+        match (sym.section, globals.get(sym.name.as_str())) {
+            (Some(section), _) => IMAGE_BASE + rva_of[o][section] + sym.offset,
+            (None, Some(&(d, s))) => {
+                let def = &objects[d].symbols[s];
+                IMAGE_BASE + rva_of[d][def.section.unwrap_or_default()] + def.offset
+            }
+            (None, None) => {
+                let slot = imports.iter().position(|&name| name == &sym.name[IMPORT_PREFIX.len()..]).unwrap_or_default();
+                IMAGE_BASE + iat.0 + 8 * slot as u64
+            }
+        }
+    };
+    for (o, object) in objects.iter().enumerate() {
+        for (s, section) in object.sections.iter().enumerate() {
+            let Some(output) = outputs.iter_mut().find(|out| section_info(section.kind).is_ok_and(|(n, _)| n == out.0)) else {
+                continue;
+            };
+            let start = (rva_of[o][s] - output.2) as usize;
+            for reloc in &section.relocs {
+                let value = address(o, reloc.target) as i64 + reloc.addend;
+                let place = IMAGE_BASE + rva_of[o][s] + reloc.offset;
+                apply(reloc.kind, &mut output.3[start + reloc.offset as usize..], value, place as i64)
+                    .map_err(|e| anyhow!("{} in {} at offset {:#x}", e, section.name, reloc.offset))?;
+            }
+        }
+    }
+    let (o, s) = *globals.get(entry).ok_or_else(|| anyhow!("Entry point {} is not defined", entry))?;
+    let entry = address(o, Target::Symbol(s)) - IMAGE_BASE;
+
+    // Headers: DOS header pointing straight at the PE signature, COFF header, optional header
+    let mut out = Vec::new();
+    out.extend_from_slice(b"MZ");
+    out.resize(0x3c, 0);
+    out.u32(DOS_HEADER_SIZE as u32);
+    out.extend_from_slice(b"PE\0\0");
+    file_header(
+        &mut out,
+        count as u16,
+        0,
+        0,
+        OPTIONAL_HEADER_SIZE as u16,
+        IMAGE_FILE_RELOCS_STRIPPED | IMAGE_FILE_EXECUTABLE_IMAGE | IMAGE_FILE_LARGE_ADDRESS_AWARE,
+    );
+    let size_of = |flag: u32| -> u32 {
+        outputs.iter().filter(|o| o.1 & flag != 0).map(|o| align_up(o.3.len() as u64, FILE_ALIGN) as u32).sum()
+    };
+    out.u16(0x20b);
+    out.push(14);
+    out.push(0);
+    out.u32(size_of(IMAGE_SCN_CNT_CODE));
+    out.u32(size_of(IMAGE_SCN_CNT_INITIALIZED_DATA));
+    out.u32(0);
+    out.u32(entry as u32);
+    out.u32(outputs.iter().find(|o| o.1 & IMAGE_SCN_CNT_CODE != 0).map_or(0, |o| o.2 as u32));
+    out.u64(IMAGE_BASE);
+    out.u32(SECTION_ALIGN as u32);
+    out.u32(FILE_ALIGN as u32);
+    for version in [6, 0, 0, 0, 6, 0] {
+        out.u16(version);
+    }
+    out.u32(0);
+    out.u32(image_size as u32);
+    out.u32(headers_size as u32);
+    out.u32(0);
+    out.u16(IMAGE_SUBSYSTEM_WINDOWS_CUI);
+    out.u16(IMAGE_DLLCHARACTERISTICS_NX_COMPAT | IMAGE_DLLCHARACTERISTICS_TERMINAL_SERVER_AWARE);
+    out.u64(STACK_SIZE);
+    out.u64(STACK_SIZE);
+    out.u64(0x10_0000);
+    out.u64(0x1000);
+    out.u32(0);
+    out.u32(16);
+    for directory in 0..16 {
+        let (address, size) = match directory {
+            IMAGE_DIRECTORY_ENTRY_IMPORT if !imports.is_empty() => (iat.0 - 8 * (imports.len() as u64 + 1) - 40, 40),
+            IMAGE_DIRECTORY_ENTRY_IAT if !imports.is_empty() => iat,
+            _ => (0, 0),
+        };
+        out.u32(address as u32);
+        out.u32(size as u32);
+    }
+
+    let mut raw = headers_size;
+    for (name, flags, rva, contents) in &outputs {
+        let raw_size = align_up(contents.len() as u64, FILE_ALIGN);
+        let mut field = [0u8; 8];
+        field[..name.len()].copy_from_slice(name.as_bytes());
+        section_header(&mut out, field, contents.len() as u32, *rva as u32, raw_size as u32, raw as u32, 0, 0, *flags);
+        raw += raw_size;
+    }
+    out.pad_to(FILE_ALIGN);
+    for (_, _, _, contents) in &outputs {
+        out.extend_from_slice(contents);
+        out.pad_to(FILE_ALIGN);
+    }
+    Ok(out)
+}
+
+// ========== Output Files ==========
+
+/// Assemble GNU assembly for `target` into a COFF object at `path`
+pub fn assemble(path: &Path, code: &[u8], target: &str) -> Result<()> {
+    let object = assemble_object(code, target)?;
+    std::fs::write(path, write_object(&object)?).map_err(|e| anyhow!("Cannot write {}: {}", path.display(), e))
+}
+
+/// Write PE executable, linked with the kernel32 runtime
+pub fn write(path: &Path, code: &[u8], target: &str) -> Result<()> {
+    let object = assemble_object(code, target)?;
+    require_x86_64(object.arch)?;
+    let runtime = asm::assemble(RUNTIME, object.arch).map_err(|e| anyhow!("Runtime: {}", e))?;
+    let image = link(&[object, runtime], ENTRY)?;
+    std::fs::write(path, image).map_err(|e| anyhow!("Cannot write {}: {}", path.display(), e))
 }
@@ -0,0 +1,441 @@
+// Aether runtime for statically linked AArch64 Linux executables
+//
+// Process entry, thread-local storage, threads, a heap and the C library
+// functions the native backend calls, all on raw system calls.
+    .text
+
+// Entry point: record the PT_TLS segment from the auxiliary vector, give the
+// main thread its TLS block, then continue in the program's _start
+    .globl __aether_start
+    .type __aether_start, %function
+__aether_start:
+    ldr x0, [sp]
+    add x1, sp, #16
+    add x1, x1, x0, lsl #3
+.Lenv:
+    ldr x2, [x1], #8
+    cbnz x2, .Lenv
+    mov x3, #0
+    mov x4, #0
+.Laux:
+    ldr x2, [x1]
+    cbz x2, .Lphdrs
+    cmp x2, #3
+    b.ne .Laux_phnum
+    ldr x3, [x1, #8]
+.Laux_phnum:
+    cmp x2, #5
+    b.ne .Laux_next
+    ldr x4, [x1, #8]
+.Laux_next:
+    add x1, x1, #16
+    b .Laux
+.Lphdrs:
+    adrp x5, __aether_tls
+    add x5, x5, :lo12:__aether_tls
+.Lphdr:
+    cbz x4, .Lmain_tls
+    ldr w2, [x3]
+    cmp w2, #7
+    b.ne .Lphdr_next
+    ldr x2, [x3, #16]
+    str x2, [x5]
+    ldr x2, [x3, #32]
+    str x2, [x5, #8]
+    ldr x2, [x3, #40]
+    str x2, [x5, #16]
+    ldr x2, [x3, #48]
+    str x2, [x5, #24]
+.Lphdr_next:
+    add x3, x3, #56
+    sub x4, x4, #1
+    b .Lphdr
+.Lmain_tls:
+    bl __aether_tls_new
+    msr tpidr_el0, x0
+    b _start
+    .size __aether_start, .-__aether_start
+
+// Allocate and initialize a TLS block; returns the thread pointer, which
+// points at a 16-byte control block followed by the TLS image
+    .type __aether_tls_new, %function
+__aether_tls_new:
+    stp x29, x30, [sp, #-48]!
+    mov x29, sp
+    stp x19, x20, [sp, #16]
+    str x21, [sp, #32]
+    adrp x19, __aether_tls
+    add x19, x19, :lo12:__aether_tls
+    ldr x20, [x19, #24]
+    cmp x20, #16
+    b.hs .Ltls_aligned
+    mov x20, #16
+.Ltls_aligned:
+    ldr x0, [x19, #16]
+    add x0, x0, x20
+    bl __aether_mmap
+    mov x21, x0
+    add x0, x0, x20
+    ldr x1, [x19]
+    ldr x2, [x19, #8]
+    bl memcpy
+    mov x0, x21
+    ldr x21, [sp, #32]
+    ldp x19, x20, [sp, #16]
+    ldp x29, x30, [sp], #48
+    ret
+    .size __aether_tls_new, .-__aether_tls_new
+
+// Zeroed read-write memory of x0 bytes; a negative errno on failure
+    .type __aether_mmap, %function
+__aether_mmap:
+    mov x1, x0
+    mov x0, #0
+    mov x2, #3
+    mov x3, #34
+    mov x4, #-1
+    mov x5, #0
+    mov x8, #222
+    svc #0
+    ret
+    .size __aether_mmap, .-__aether_mmap
+
+// ========== Heap ==========
+// Blocks are powers of two from 16 bytes with a 16-byte header holding the
+// size class; freed blocks go on a list per class. One lock guards it all.
+
+    .globl malloc
+    .type malloc, %function
+malloc:
+    stp x29, x30, [sp, #-32]!
+    mov x29, sp
+    stp x19, x20, [sp, #16]
+    add x0, x0, #16
+    mov x19, #0
+    mov x20, #16
+.Lmalloc_class:
+    cmp x20, x0
+    b.hs .Lmalloc_lock
+    lsl x20, x20, #1
+    add x19, x19, #1
+    b .Lmalloc_class
+.Lmalloc_lock:
+    adrp x9, __aether_heap_lock
+    add x9, x9, :lo12:__aether_heap_lock
+    mov w10, #1
+.Lmalloc_spin:
+    ldaxr w11, [x9]
+    cbnz w11, .Lmalloc_spin
+    stxr w12, w10, [x9]
+    cbnz w12, .Lmalloc_spin
+    adrp x10, __aether_free_lists
+    add x10, x10, :lo12:__aether_free_lists
+    ldr x0, [x10, x19, lsl #3]
+    cbz x0, .Lmalloc_carve
+    ldr x11, [x0, #8]
+    str x11, [x10, x19, lsl #3]
+    b .Lmalloc_done
+.Lmalloc_carve:
+    adrp x10, __aether_heap
+    add x10, x10, :lo12:__aether_heap
+    ldp x0, x11, [x10]
+    sub x11, x11, x0
+    cmp x11, x20
+    b.hs .Lmalloc_bump
+    mov x0, #67108864
+    cmp x0, x20
+    b.hs .Lmalloc_map
+    mov x0, x20
+.Lmalloc_map:
+    str x0, [sp, #-16]!
+    bl __aether_mmap
+    ldr x1, [sp], #16
+    cmn x0, #4095
+    b.hs .Lmalloc_fail
+    adrp x10, __aether_heap
+    add x10, x10, :lo12:__aether_heap
+    add x1, x1, x0
+    str x1, [x10, #8]
+.Lmalloc_bump:
+    add x11, x0, x20
+    str x11, [x10]
+.Lmalloc_done:
+    str x19, [x0]
+    add x0, x0, #16
+.Lmalloc_unlock:
+    adrp x9, __aether_heap_lock
+    add x9, x9, :lo12:__aether_heap_lock
+    stlr wzr, [x9]
+    ldp x19, x20, [sp, #16]
+    ldp x29, x30, [sp], #32
+    ret
+.Lmalloc_fail:
+    mov x0, #0
+    b .Lmalloc_unlock
+    .size malloc, .-malloc
+
+    .globl free
+    .type free, %function
+free:
+    cbz x0, .Lfree_done
+    sub x0, x0, #16
+    adrp x9, __aether_heap_lock
+    add x9, x9, :lo12:__aether_heap_lock
+    mov w10, #1
+.Lfree_spin:
+    ldaxr w11, [x9]
+    cbnz w11, .Lfree_spin
+    stxr w12, w10, [x9]
+    cbnz w12, .Lfree_spin
+    ldr x1, [x0]
+    adrp x10, __aether_free_lists
+    add x10, x10, :lo12:__aether_free_lists
+    ldr x11, [x10, x1, lsl #3]
+    str x11, [x0, #8]
+    str x0, [x10, x1, lsl #3]
+    stlr wzr, [x9]
+.Lfree_done:
+    ret
+    .size free, .-free
+
+    .globl memcpy
+    .type memcpy, %function
+memcpy:
+    mov x3, x0
+    cbz x2, .Lmemcpy_done
+.Lmemcpy_loop:
+    ldrb w4, [x1], #1
+    strb w4, [x3], #1
+    subs x2, x2, #1
+    b.ne .Lmemcpy_loop
+.Lmemcpy_done:
+    ret
+    .size memcpy, .-memcpy
+
+    .globl memset
+    .type memset, %function
+memset:
+    mov x3, x0
+    cbz x2, .Lmemset_done
+.Lmemset_loop:
+    strb w1, [x3], #1
+    subs x2, x2, #1
+    b.ne .Lmemset_loop
+.Lmemset_done:
+    ret
+    .size memset, .-memset
+
+// ========== Threads ==========
+
+// pthread_create(thread, attr, start, arg): a clone sharing the address space,
+// with its own stack and TLS block; the thread exits when start returns
+    .globl pthread_create
+    .type pthread_create, %function
+pthread_create:
+    stp x29, x30, [sp, #-48]!
+    mov x29, sp
+    stp x19, x20, [sp, #16]
+    stp x21, x22, [sp, #32]
+    mov x19, x0
+    mov x20, x2
+    mov x21, x3
+    bl __aether_tls_new
+    mov x22, x0
+    mov x0, #8388608
+    bl __aether_mmap
+    cmn x0, #4095
+    b.hs .Lthread_fail
+    add x1, x0, #8388608
+    sub x1, x1, #16
+    stp x20, x21, [x1]
+    movz x0, #0xd, lsl #16
+    movk x0, #0xf00
+    mov x2, #0
+    mov x3, x22
+    mov x4, #0
+    mov x8, #220
+    svc #0
+    cbz x0, .Lthread_child
+    cmp x0, #0
+    b.lt .Lthread_error
+    str x0, [x19]
+    mov x0, #0
+.Lthread_return:
+    ldp x21, x22, [sp, #32]
+    ldp x19, x20, [sp, #16]
+    ldp x29, x30, [sp], #48
+    ret
+.Lthread_error:
+    neg x0, x0
+    b .Lthread_return
+.Lthread_fail:
+    mov x0, #11
+    b .Lthread_return
+.Lthread_child:
+    mov x29, #0
+    mov x30, #0
+    ldp x1, x0, [sp], #16
+    blr x1
+    mov x0, #0
+    mov x8, #93
+    svc #0
+    .size pthread_create, .-pthread_create
+
+// ========== System calls ==========
+// C-style wrappers: -1 on failure (errno is not kept). Path calls use the
+// *at variants relative to AT_FDCWD, the only ones AArch64 has.
+
+    .type __aether_syscall, %function
+__aether_syscall:
+    svc #0
+    cmn x0, #4095
+    b.hs .Lsyscall_error
+    ret
+.Lsyscall_error:
+    mov x0, #-1
+    ret
+    .size __aether_syscall, .-__aether_syscall
+
+    .globl read
+    .type read, %function
+read:
+    mov x8, #63
+    b __aether_syscall
+    .size read, .-read
+
+    .globl write
+    .type write, %function
+write:
+    mov x8, #64
+    b __aether_syscall
+    .size write, .-write
+
+    .globl open
+    .type open, %function
+open:
+    mov x3, x2
+    mov x2, x1
+    mov x1, x0
+    mov x0, #-100
+    mov x8, #56
+    b __aether_syscall
+    .size open, .-open
+
+    .globl close
+    .type close, %function
+close:
+    mov x8, #57
+    b __aether_syscall
+    .size close, .-close
+
+    .globl lseek
+    .type lseek, %function
+lseek:
+    mov x8, #62
+    b __aether_syscall
+    .size lseek, .-lseek
+
+    .globl socket
+    .type socket, %function
+socket:
+    mov x8, #198
+    b __aether_syscall
+    .size socket, .-socket
+
+    .globl bind
+    .type bind, %function
+bind:
+    mov x8, #200
+    b __aether_syscall
+    .size bind, .-bind
+
+    .globl listen
+    .type listen, %function
+listen:
+    mov x8, #201
+    b __aether_syscall
+    .size listen, .-listen
+
+    .globl accept
+    .type accept, %function
+accept:
+    mov x8, #202
+    b __aether_syscall
+    .size accept, .-accept
+
+    .globl connect
+    .type connect, %function
+connect:
+    mov x8, #203
+    b __aether_syscall
+    .size connect, .-connect
+
+    .globl setsockopt
+    .type setsockopt, %function
+setsockopt:
+    mov x8, #208
+    b __aether_syscall
+    .size setsockopt, .-setsockopt
+
+    .globl rename
+    .type rename, %function
+rename:
+    mov x3, x1
+    mov x1, x0
+    mov x0, #-100
+    mov x2, #-100
+    mov x8, #38
+    b __aether_syscall
+    .size rename, .-rename
+
+    .globl mkdir
+    .type mkdir, %function
+mkdir:
+    mov x2, x1
+    mov x1, x0
+    mov x0, #-100
+    mov x8, #34
+    b __aether_syscall
+    .size mkdir, .-mkdir
+
+    .globl rmdir
+    .type rmdir, %function
+rmdir:
+    mov x1, x0
+    mov x0, #-100
+    mov x2, #512
+    mov x8, #35
+    b __aether_syscall
+    .size rmdir, .-rmdir
+
+    .globl unlink
+    .type unlink, %function
+unlink:
+    mov x1, x0
+    mov x0, #-100
+    mov x2, #0
+    mov x8, #35
+    b __aether_syscall
+    .size unlink, .-unlink
+
+    .globl exit
+    .type exit, %function
+exit:
+    mov x8, #94
+    svc #0
+    .size exit, .-exit
+
+    .data
+    .balign 8
+// PT_TLS image address, file size, memory size and alignment
+__aether_tls:
+    .zero 32
+__aether_heap_lock:
+    .quad 0
+// Next free byte and end of the current arena
+__aether_heap:
+    .zero 16
+__aether_free_lists:
+    .zero 512
+
+    .section .note.GNU-stack,"",%progbits
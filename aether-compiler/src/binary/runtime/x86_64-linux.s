@@ -0,0 +1,425 @@
+# Aether runtime for statically linked x86-64 Linux executables
+#
+# Process entry, thread-local storage, threads, a heap and the C library
+# functions the native backend calls, all on raw system calls.
+    .intel_syntax noprefix
+    .text
+
+# Entry point: record the PT_TLS segment from the auxiliary vector, give the
+# main thread its TLS block, then continue in the program's _start
+    .globl __aether_start
+    .type __aether_start, @function
+__aether_start:
+    mov rax, qword ptr [rsp]
+    lea rcx, [rsp + rax*8 + 16]
+.Lenv:
+    mov rdx, qword ptr [rcx]
+    add rcx, 8
+    test rdx, rdx
+    jne .Lenv
+    xor esi, esi
+    xor edi, edi
+.Laux:
+    mov rdx, qword ptr [rcx]
+    test rdx, rdx
+    je .Lphdrs
+    cmp rdx, 3
+    jne .Laux_phnum
+    mov rsi, qword ptr [rcx + 8]
+.Laux_phnum:
+    cmp rdx, 5
+    jne .Laux_next
+    mov rdi, qword ptr [rcx + 8]
+.Laux_next:
+    add rcx, 16
+    jmp .Laux
+.Lphdrs:
+    lea r8, [rip + __aether_tls]
+.Lphdr:
+    test rdi, rdi
+    je .Lmain_tls
+    cmp dword ptr [rsi], 7
+    jne .Lphdr_next
+    mov rax, qword ptr [rsi + 16]
+    mov qword ptr [r8], rax
+    mov rax, qword ptr [rsi + 32]
+    mov qword ptr [r8 + 8], rax
+    mov rax, qword ptr [rsi + 40]
+    mov qword ptr [r8 + 16], rax
+    mov rax, qword ptr [rsi + 48]
+    mov qword ptr [r8 + 24], rax
+.Lphdr_next:
+    add rsi, 56
+    dec rdi
+    jmp .Lphdr
+.Lmain_tls:
+    call __aether_tls_new
+    mov rsi, rax
+    mov edi, 4098
+    mov eax, 158
+    syscall
+    jmp _start
+    .size __aether_start, .-__aether_start
+
+# Allocate and initialize a TLS block; returns the thread pointer, which
+# points past the block at a word holding its own address
+    .type __aether_tls_new, @function
+__aether_tls_new:
+    push rbx
+    push r12
+    push r13
+    lea rbx, [rip + __aether_tls]
+    mov rcx, qword ptr [rbx + 24]
+    cmp rcx, 1
+    jae .Ltls_aligned
+    mov ecx, 1
+.Ltls_aligned:
+    mov r12, qword ptr [rbx + 16]
+    add r12, rcx
+    dec r12
+    neg rcx
+    and r12, rcx
+    lea rdi, [r12 + 16]
+    call __aether_mmap
+    lea r13, [rax + r12]
+    mov qword ptr [r13], r13
+    mov rdi, rax
+    mov rsi, qword ptr [rbx]
+    mov rdx, qword ptr [rbx + 8]
+    call memcpy
+    mov rax, r13
+    pop r13
+    pop r12
+    pop rbx
+    ret
+    .size __aether_tls_new, .-__aether_tls_new
+
+# Zeroed read-write memory of rdi bytes; a negative errno on failure
+    .type __aether_mmap, @function
+__aether_mmap:
+    mov rsi, rdi
+    xor edi, edi
+    mov edx, 3
+    mov r10d, 34
+    mov r8, -1
+    xor r9d, r9d
+    mov eax, 9
+    syscall
+    ret
+    .size __aether_mmap, .-__aether_mmap
+
+# ========== Heap ==========
+# Blocks are powers of two from 16 bytes with a 16-byte header holding the
+# size class; freed blocks go on a list per class. One lock guards it all.
+
+    .globl malloc
+    .type malloc, @function
+malloc:
+    push rbx
+    push r12
+    lea rax, [rdi + 16]
+    xor ebx, ebx
+    mov r12d, 16
+.Lmalloc_class:
+    cmp r12, rax
+    jae .Lmalloc_lock
+    shl r12, 1
+    inc rbx
+    jmp .Lmalloc_class
+.Lmalloc_lock:
+    mov eax, 1
+    xchg qword ptr [rip + __aether_heap_lock], rax
+    test rax, rax
+    je .Lmalloc_locked
+    pause
+    jmp .Lmalloc_lock
+.Lmalloc_locked:
+    lea rcx, [rip + __aether_free_lists]
+    mov rax, qword ptr [rcx + rbx*8]
+    test rax, rax
+    je .Lmalloc_carve
+    mov rdx, qword ptr [rax + 8]
+    mov qword ptr [rcx + rbx*8], rdx
+    jmp .Lmalloc_done
+.Lmalloc_carve:
+    lea rcx, [rip + __aether_heap]
+    mov rax, qword ptr [rcx]
+    mov rdx, qword ptr [rcx + 8]
+    sub rdx, rax
+    cmp rdx, r12
+    jae .Lmalloc_bump
+    mov edi, 67108864
+    cmp rdi, r12
+    jae .Lmalloc_map
+    mov rdi, r12
+.Lmalloc_map:
+    push rdi
+    call __aether_mmap
+    pop rdi
+    cmp rax, -4095
+    jae .Lmalloc_fail
+    lea rcx, [rip + __aether_heap]
+    add rdi, rax
+    mov qword ptr [rcx + 8], rdi
+.Lmalloc_bump:
+    lea rdx, [rax + r12]
+    mov qword ptr [rcx], rdx
+.Lmalloc_done:
+    mov qword ptr [rax], rbx
+    add rax, 16
+    mov qword ptr [rip + __aether_heap_lock], 0
+    pop r12
+    pop rbx
+    ret
+.Lmalloc_fail:
+    mov qword ptr [rip + __aether_heap_lock], 0
+    xor eax, eax
+    pop r12
+    pop rbx
+    ret
+    .size malloc, .-malloc
+
+    .globl free
+    .type free, @function
+free:
+    test rdi, rdi
+    je .Lfree_done
+    sub rdi, 16
+.Lfree_lock:
+    mov eax, 1
+    xchg qword ptr [rip + __aether_heap_lock], rax
+    test rax, rax
+    je .Lfree_locked
+    pause
+    jmp .Lfree_lock
+.Lfree_locked:
+    mov rcx, qword ptr [rdi]
+    lea rdx, [rip + __aether_free_lists]
+    mov rax, qword ptr [rdx + rcx*8]
+    mov qword ptr [rdi + 8], rax
+    mov qword ptr [rdx + rcx*8], rdi
+    mov qword ptr [rip + __aether_heap_lock], 0
+.Lfree_done:
+    ret
+    .size free, .-free
+
+    .globl memcpy
+    .type memcpy, @function
+memcpy:
+    mov rax, rdi
+    mov rcx, rdx
+    rep movsb
+    ret
+    .size memcpy, .-memcpy
+
+    .globl memset
+    .type memset, @function
+memset:
+    mov r8, rdi
+    mov eax, esi
+    mov rcx, rdx
+    rep stosb
+    mov rax, r8
+    ret
+    .size memset, .-memset
+
+# ========== Threads ==========
+
+# pthread_create(thread, attr, start, arg): a clone sharing the address space,
+# with its own stack and TLS block; the thread exits when start returns
+    .globl pthread_create
+    .type pthread_create, @function
+pthread_create:
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    mov rbx, rdi
+    mov r12, rdx
+    mov r13, rcx
+    call __aether_tls_new
+    mov r14, rax
+    mov edi, 8388608
+    call __aether_mmap
+    cmp rax, -4095
+    jae .Lthread_fail
+    lea rsi, [rax + 8388592]
+    mov qword ptr [rsi], r12
+    mov qword ptr [rsi + 8], r13
+    mov edi, 855808
+    xor edx, edx
+    xor r10d, r10d
+    mov r8, r14
+    mov eax, 56
+    syscall
+    test rax, rax
+    je .Lthread_child
+    jl .Lthread_error
+    mov qword ptr [rbx], rax
+    xor eax, eax
+.Lthread_return:
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    ret
+.Lthread_error:
+    neg rax
+    jmp .Lthread_return
+.Lthread_fail:
+    mov eax, 11
+    jmp .Lthread_return
+.Lthread_child:
+    xor ebp, ebp
+    pop rax
+    pop rdi
+    call rax
+    xor edi, edi
+    mov eax, 60
+    syscall
+    .size pthread_create, .-pthread_create
+
+# ========== System calls ==========
+# C-style wrappers: -1 on failure (errno is not kept)
+
+    .type __aether_syscall, @function
+__aether_syscall:
+    mov r10, rcx
+    syscall
+    cmp rax, -4095
+    jae .Lsyscall_error
+    ret
+.Lsyscall_error:
+    mov rax, -1
+    ret
+    .size __aether_syscall, .-__aether_syscall
+
+    .globl read
+    .type read, @function
+read:
+    xor eax, eax
+    jmp __aether_syscall
+    .size read, .-read
+
+    .globl write
+    .type write, @function
+write:
+    mov eax, 1
+    jmp __aether_syscall
+    .size write, .-write
+
+    .globl open
+    .type open, @function
+open:
+    mov eax, 2
+    jmp __aether_syscall
+    .size open, .-open
+
+    .globl close
+    .type close, @function
+close:
+    mov eax, 3
+    jmp __aether_syscall
+    .size close, .-close
+
+    .globl lseek
+    .type lseek, @function
+lseek:
+    mov eax, 8
+    jmp __aether_syscall
+    .size lseek, .-lseek
+
+    .globl socket
+    .type socket, @function
+socket:
+    mov eax, 41
+    jmp __aether_syscall
+    .size socket, .-socket
+
+    .globl connect
+    .type connect, @function
+connect:
+    mov eax, 42
+    jmp __aether_syscall
+    .size connect, .-connect
+
+    .globl accept
+    .type accept, @function
+accept:
+    mov eax, 43
+    jmp __aether_syscall
+    .size accept, .-accept
+
+    .globl bind
+    .type bind, @function
+bind:
+    mov eax, 49
+    jmp __aether_syscall
+    .size bind, .-bind
+
+    .globl listen
+    .type listen, @function
+listen:
+    mov eax, 50
+    jmp __aether_syscall
+    .size listen, .-listen
+
+    .globl setsockopt
+    .type setsockopt, @function
+setsockopt:
+    mov eax, 54
+    jmp __aether_syscall
+    .size setsockopt, .-setsockopt
+
+    .globl rename
+    .type rename, @function
+rename:
+    mov eax, 82
+    jmp __aether_syscall
+    .size rename, .-rename
+
+    .globl mkdir
+    .type mkdir, @function
+mkdir:
+    mov eax, 83
+    jmp __aether_syscall
+    .size mkdir, .-mkdir
+
+    .globl rmdir
+    .type rmdir, @function
+rmdir:
+    mov eax, 84
+    jmp __aether_syscall
+    .size rmdir, .-rmdir
+
+    .globl unlink
+    .type unlink, @function
+unlink:
+    mov eax, 87
+    jmp __aether_syscall
+    .size unlink, .-unlink
+
+    .globl exit
+    .type exit, @function
+exit:
+    mov eax, 231
+    syscall
+    hlt
+    .size exit, .-exit
+
+    .data
+    .balign 8
+# PT_TLS image address, file size, memory size and alignment
+__aether_tls:
+    .zero 32
+__aether_heap_lock:
+    .quad 0
+# Next free byte and end of the current arena
+__aether_heap:
+    .zero 16
+__aether_free_lists:
+    .zero 512
+
+    .section .note.GNU-stack,"",@progbits
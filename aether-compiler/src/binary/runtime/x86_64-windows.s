@@ -0,0 +1,470 @@
+# Aether runtime for x86-64 Windows executables
+#
+# Process entry, threads, a heap and the C library functions the native
+# backend calls, on kernel32. Everything uses the Microsoft x64 convention,
+# like the generated code: arguments in rcx, rdx, r8 and r9, 32 bytes of
+# shadow space at each call, and rbx, rbp, rdi, rsi and r12-r15 preserved.
+    .intel_syntax noprefix
+    .text
+
+# Entry point: main(argc, argv) on the process heap, then ExitProcess
+    .globl __aether_start
+__aether_start:
+    sub rsp, 40
+    call qword ptr [rip + __imp_GetProcessHeap]
+    mov qword ptr [rip + __aether_heap], rax
+    call __aether_args
+    mov rcx, rax
+    call main
+    mov ecx, eax
+    call qword ptr [rip + __imp_ExitProcess]
+
+# argc in rax and argv in rdx, from GetCommandLineW: split with the Microsoft
+# C runtime's quoting rules, then converted to UTF-8
+__aether_args:
+    push rbx
+    push rsi
+    push rdi
+    push r12
+    push r13
+    push r14
+    sub rsp, 72
+    call qword ptr [rip + __imp_GetCommandLineW]
+    mov rsi, rax
+    xor r14d, r14d
+.Largs_length:
+    cmp word ptr [rsi + r14*2], 0
+    je .Largs_alloc
+    inc r14
+    jmp .Largs_length
+# Split arguments never outgrow the command line; each needs two characters
+.Largs_alloc:
+    lea rcx, [r14*2 + 2]
+    call malloc
+    mov rdi, rax
+    lea rcx, [r14*8 + 16]
+    call malloc
+    mov rbx, rax
+    xor r12d, r12d
+.Largs_skip:
+    movzx eax, word ptr [rsi]
+    cmp eax, 32
+    je .Largs_blank
+    cmp eax, 9
+    jne .Largs_start
+.Largs_blank:
+    add rsi, 2
+    jmp .Largs_skip
+.Largs_start:
+    test eax, eax
+    je .Largs_convert
+    mov qword ptr [rbx + r12*8], rdi
+    inc r12
+    xor r13d, r13d
+# r13d is set inside double quotes, where blanks do not end the argument
+.Largs_char:
+    movzx eax, word ptr [rsi]
+    test eax, eax
+    je .Largs_end
+    cmp eax, 92
+    je .Largs_backslashes
+    cmp eax, 34
+    je .Largs_quote
+    test r13d, r13d
+    jne .Largs_copy
+    cmp eax, 32
+    je .Largs_end
+    cmp eax, 9
+    je .Largs_end
+.Largs_copy:
+    mov word ptr [rdi], ax
+    add rdi, 2
+    add rsi, 2
+    jmp .Largs_char
+# `""` inside quotes is a literal quote
+.Largs_quote:
+    add rsi, 2
+    test r13d, r13d
+    je .Largs_toggle
+    cmp word ptr [rsi], 34
+    jne .Largs_toggle
+    mov word ptr [rdi], ax
+    add rdi, 2
+    add rsi, 2
+    jmp .Largs_char
+.Largs_toggle:
+    xor r13d, 1
+    jmp .Largs_char
+# Backslashes are literal unless a quote follows: then 2n give n and a quote
+# that toggles, 2n+1 give n and a literal quote
+.Largs_backslashes:
+    xor ecx, ecx
+.Largs_count:
+    inc rcx
+    cmp word ptr [rsi + rcx*2], 92
+    je .Largs_count
+    lea rsi, [rsi + rcx*2]
+    cmp word ptr [rsi], 34
+    jne .Largs_literal
+    mov edx, ecx
+    and edx, 1
+    shr rcx, 1
+    je .Largs_escaped
+.Largs_half:
+    mov word ptr [rdi], 92
+    add rdi, 2
+    dec rcx
+    jne .Largs_half
+.Largs_escaped:
+    test edx, edx
+    je .Largs_char
+    mov word ptr [rdi], 34
+    add rdi, 2
+    add rsi, 2
+    jmp .Largs_char
+.Largs_literal:
+    mov word ptr [rdi], 92
+    add rdi, 2
+    dec rcx
+    jne .Largs_literal
+    jmp .Largs_char
+.Largs_end:
+    mov word ptr [rdi], 0
+    add rdi, 2
+    jmp .Largs_skip
+# Replace each UTF-16 argument by its UTF-8 copy
+.Largs_convert:
+    mov qword ptr [rbx + r12*8], 0
+    xor r13d, r13d
+.Largs_next:
+    cmp r13, r12
+    je .Largs_done
+    mov rsi, qword ptr [rbx + r13*8]
+    mov ecx, 65001
+    xor edx, edx
+    mov r8, rsi
+    mov r9d, -1
+    mov qword ptr [rsp + 32], 0
+    mov qword ptr [rsp + 40], 0
+    mov qword ptr [rsp + 48], 0
+    mov qword ptr [rsp + 56], 0
+    call qword ptr [rip + __imp_WideCharToMultiByte]
+    mov r14d, eax
+    mov rcx, r14
+    call malloc
+    mov rdi, rax
+    mov ecx, 65001
+    xor edx, edx
+    mov r8, rsi
+    mov r9d, -1
+    mov qword ptr [rsp + 32], rdi
+    mov qword ptr [rsp + 40], r14
+    mov qword ptr [rsp + 48], 0
+    mov qword ptr [rsp + 56], 0
+    call qword ptr [rip + __imp_WideCharToMultiByte]
+    mov qword ptr [rbx + r13*8], rdi
+    inc r13
+    jmp .Largs_next
+.Largs_done:
+    mov rax, r12
+    mov rdx, rbx
+    add rsp, 72
+    pop r14
+    pop r13
+    pop r12
+    pop rdi
+    pop rsi
+    pop rbx
+    ret
+
+# ========== Heap ==========
+
+    .globl malloc
+malloc:
+    sub rsp, 40
+    mov r8, rcx
+    mov rcx, qword ptr [rip + __aether_heap]
+    xor edx, edx
+    call qword ptr [rip + __imp_HeapAlloc]
+    add rsp, 40
+    ret
+
+    .globl free
+free:
+    test rcx, rcx
+    je .Lfree_done
+    sub rsp, 40
+    mov r8, rcx
+    mov rcx, qword ptr [rip + __aether_heap]
+    xor edx, edx
+    call qword ptr [rip + __imp_HeapFree]
+    add rsp, 40
+.Lfree_done:
+    ret
+
+    .globl memcpy
+memcpy:
+    push rdi
+    push rsi
+    mov rax, rcx
+    mov rdi, rcx
+    mov rsi, rdx
+    mov rcx, r8
+    rep movsb
+    pop rsi
+    pop rdi
+    ret
+
+    .globl memset
+memset:
+    push rdi
+    mov r9, rcx
+    mov rdi, rcx
+    mov eax, edx
+    mov rcx, r8
+    rep stosb
+    mov rax, r9
+    pop rdi
+    ret
+
+# ========== Threads ==========
+
+# pthread_create(thread, attr, start, arg): CreateThread with an 8 MiB
+# committed stack, so large frames need no stack probes
+    .globl pthread_create
+pthread_create:
+    push rbx
+    push r12
+    push r13
+    sub rsp, 48
+    mov rbx, rcx
+    mov r12, r8
+    mov r13, r9
+    mov ecx, 16
+    call malloc
+    test rax, rax
+    je .Lthread_fail
+    mov qword ptr [rax], r12
+    mov qword ptr [rax + 8], r13
+    mov qword ptr [rbx], 0
+    xor ecx, ecx
+    mov edx, 8388608
+    lea r8, [rip + __aether_thread]
+    mov r9, rax
+    mov qword ptr [rsp + 32], 0
+    mov qword ptr [rsp + 40], rbx
+    call qword ptr [rip + __imp_CreateThread]
+    test rax, rax
+    je .Lthread_fail
+    mov rcx, rax
+    call qword ptr [rip + __imp_CloseHandle]
+    xor eax, eax
+.Lthread_return:
+    add rsp, 48
+    pop r13
+    pop r12
+    pop rbx
+    ret
+.Lthread_fail:
+    mov eax, 11
+    jmp .Lthread_return
+
+# Thread entry: rcx points at the start function and its argument; the
+# thread ends with ExitThread when start returns
+__aether_thread:
+    push rbx
+    push r12
+    sub rsp, 40
+    mov rbx, qword ptr [rcx]
+    mov r12, qword ptr [rcx + 8]
+    call free
+    mov rcx, r12
+    call rbx
+    xor ecx, ecx
+    call qword ptr [rip + __imp_ExitThread]
+
+# ========== Files ==========
+# C-style wrappers: -1 on failure (errno is not kept). Descriptors 0-2 are the
+# standard handles; other descriptors are kernel32 handles.
+
+# Handle of descriptor rcx
+__aether_handle:
+    cmp rcx, 2
+    ja .Lhandle_file
+    sub rsp, 40
+    mov eax, -10
+    sub eax, ecx
+    mov ecx, eax
+    call qword ptr [rip + __imp_GetStdHandle]
+    add rsp, 40
+    ret
+.Lhandle_file:
+    mov rax, rcx
+    ret
+
+    .globl read
+read:
+    mov rax, qword ptr [rip + __imp_ReadFile]
+    jmp __aether_transfer
+
+    .globl write
+write:
+    mov rax, qword ptr [rip + __imp_WriteFile]
+    jmp __aether_transfer
+
+# ReadFile or WriteFile (in rax) of r8 bytes at rdx on descriptor rcx
+__aether_transfer:
+    push rbx
+    push r12
+    push r13
+    sub rsp, 48
+    mov rbx, rax
+    mov r12, rdx
+    mov r13, r8
+    call __aether_handle
+    mov rcx, rax
+    mov rdx, r12
+    mov r8, r13
+    lea r9, [rsp + 40]
+    mov qword ptr [rsp + 40], 0
+    mov qword ptr [rsp + 32], 0
+    call rbx
+    test eax, eax
+    je .Ltransfer_error
+    mov rax, qword ptr [rsp + 40]
+    jmp .Ltransfer_done
+.Ltransfer_error:
+    mov rax, -1
+.Ltransfer_done:
+    add rsp, 48
+    pop r13
+    pop r12
+    pop rbx
+    ret
+
+# open(path, flags, mode) with Linux O_* flags
+    .globl open
+open:
+    push rbx
+    sub rsp, 64
+    mov ebx, edx
+    mov edx, 0x80000000
+    test ebx, 3
+    je .Lopen_share
+    mov edx, 0x40000000
+    test ebx, 2
+    je .Lopen_share
+    mov edx, 0xc0000000
+.Lopen_share:
+    mov r8d, 3
+    xor r9d, r9d
+    mov eax, 3
+    test ebx, 0x40
+    je .Lopen_existing
+    mov eax, 4
+    test ebx, 0x200
+    je .Lopen_create
+    mov eax, 2
+    jmp .Lopen_create
+.Lopen_existing:
+    test ebx, 0x200
+    je .Lopen_create
+    mov eax, 5
+.Lopen_create:
+    mov qword ptr [rsp + 32], rax
+    mov qword ptr [rsp + 40], 128
+    mov qword ptr [rsp + 48], 0
+    call qword ptr [rip + __imp_CreateFileA]
+    test ebx, 0x400
+    je .Lopen_done
+    cmp rax, -1
+    je .Lopen_done
+    mov rbx, rax
+    mov rcx, rax
+    xor edx, edx
+    xor r8d, r8d
+    mov r9d, 2
+    call qword ptr [rip + __imp_SetFilePointerEx]
+    mov rax, rbx
+.Lopen_done:
+    add rsp, 64
+    pop rbx
+    ret
+
+    .globl lseek
+lseek:
+    push r12
+    push r13
+    sub rsp, 56
+    mov r12, rdx
+    mov r13, r8
+    call __aether_handle
+    mov rcx, rax
+    mov rdx, r12
+    lea r8, [rsp + 40]
+    mov r9, r13
+    call qword ptr [rip + __imp_SetFilePointerEx]
+    test eax, eax
+    je .Llseek_error
+    mov rax, qword ptr [rsp + 40]
+    jmp .Llseek_done
+.Llseek_error:
+    mov rax, -1
+.Llseek_done:
+    add rsp, 56
+    pop r13
+    pop r12
+    ret
+
+    .globl close
+close:
+    mov rax, qword ptr [rip + __imp_CloseHandle]
+    jmp __aether_call
+
+    .globl unlink
+unlink:
+    mov rax, qword ptr [rip + __imp_DeleteFileA]
+    jmp __aether_call
+
+    .globl rmdir
+rmdir:
+    mov rax, qword ptr [rip + __imp_RemoveDirectoryA]
+    jmp __aether_call
+
+    .globl mkdir
+mkdir:
+    xor edx, edx
+    mov rax, qword ptr [rip + __imp_CreateDirectoryA]
+    jmp __aether_call
+
+# rename replaces an existing target, like POSIX
+    .globl rename
+rename:
+    mov r8d, 1
+    mov rax, qword ptr [rip + __imp_MoveFileExA]
+    jmp __aether_call
+
+# kernel32 function in rax on the caller's arguments: 0 if it returns TRUE,
+# else -1
+__aether_call:
+    sub rsp, 40
+    call rax
+    add rsp, 40
+    test eax, eax
+    je .Lcall_error
+    xor eax, eax
+    ret
+.Lcall_error:
+    mov rax, -1
+    ret
+
+    .globl exit
+exit:
+    sub rsp, 40
+    call qword ptr [rip + __imp_ExitProcess]
+
+    .data
+    .balign 8
+# Process heap handle
+__aether_heap:
+    .quad 0
@@ -0,0 +1,1248 @@
+//! WebAssembly Binary Format
+//!
+//! Module encoding, an instruction builder and a validator for the part of
+//! WebAssembly 2.0 the backend emits (no SIMD, references or exceptions).
+
+use std::collections::HashSet;
+use std::path::Path;
+use anyhow::{anyhow, bail, Result};
+
+pub const PAGE_SIZE: u64 = 0x1_0000;
+const MAX_PAGES: u64 = 0x1_0000;
+const MAGIC: &[u8; 4] = b"\0asm";
+const VERSION: u32 = 1;
+
+const SECTION_CUSTOM: u8 = 0;
+const SECTION_TYPE: u8 = 1;
+const SECTION_IMPORT: u8 = 2;
+const SECTION_FUNCTION: u8 = 3;
+const SECTION_TABLE: u8 = 4;
+const SECTION_MEMORY: u8 = 5;
+const SECTION_GLOBAL: u8 = 6;
+const SECTION_EXPORT: u8 = 7;
+const SECTION_START: u8 = 8;
+const SECTION_ELEMENT: u8 = 9;
+const SECTION_CODE: u8 = 10;
+const SECTION_DATA: u8 = 11;
+const SECTION_DATA_COUNT: u8 = 12;
+
+const FUNC_TYPE: u8 = 0x60;
+const FUNCREF: u8 = 0x70;
+const EXTERNREF: u8 = 0x6f;
+const BLOCK_EMPTY: u8 = 0x40;
+
+const KIND_FUNC: u8 = 0;
+const KIND_TABLE: u8 = 1;
+const KIND_MEMORY: u8 = 2;
+const KIND_GLOBAL: u8 = 3;
+
+// Opcodes
+pub const UNREACHABLE: u8 = 0x00;
+pub const NOP: u8 = 0x01;
+pub const BLOCK: u8 = 0x02;
+pub const LOOP: u8 = 0x03;
+pub const IF: u8 = 0x04;
+pub const ELSE: u8 = 0x05;
+pub const END: u8 = 0x0b;
+pub const BR: u8 = 0x0c;
+pub const BR_IF: u8 = 0x0d;
+pub const BR_TABLE: u8 = 0x0e;
+pub const RETURN: u8 = 0x0f;
+pub const CALL: u8 = 0x10;
+pub const CALL_INDIRECT: u8 = 0x11;
+pub const DROP: u8 = 0x1a;
+pub const SELECT: u8 = 0x1b;
+pub const LOCAL_GET: u8 = 0x20;
+pub const LOCAL_SET: u8 = 0x21;
+pub const LOCAL_TEE: u8 = 0x22;
+pub const GLOBAL_GET: u8 = 0x23;
+pub const GLOBAL_SET: u8 = 0x24;
+pub const I32_LOAD: u8 = 0x28;
+pub const I32_LOAD8_U: u8 = 0x2d;
+pub const I64_LOAD: u8 = 0x29;
+pub const I64_LOAD8_U: u8 = 0x31;
+pub const I64_LOAD16_U: u8 = 0x33;
+pub const I64_LOAD32_U: u8 = 0x35;
+pub const I32_STORE: u8 = 0x36;
+pub const I64_STORE: u8 = 0x37;
+pub const I64_STORE8: u8 = 0x3c;
+pub const I64_STORE16: u8 = 0x3d;
+pub const I64_STORE32: u8 = 0x3e;
+pub const MEMORY_SIZE: u8 = 0x3f;
+pub const MEMORY_GROW: u8 = 0x40;
+pub const I32_CONST: u8 = 0x41;
+pub const I64_CONST: u8 = 0x42;
+pub const F32_CONST: u8 = 0x43;
+pub const F64_CONST: u8 = 0x44;
+pub const I32_EQZ: u8 = 0x45;
+pub const I32_EQ: u8 = 0x46;
+pub const I32_NE: u8 = 0x47;
+pub const I32_LT_U: u8 = 0x49;
+pub const I32_GT_U: u8 = 0x4b;
+pub const I32_GE_U: u8 = 0x4f;
+pub const I64_EQZ: u8 = 0x50;
+pub const I64_EQ: u8 = 0x51;
+pub const I64_NE: u8 = 0x52;
+pub const I64_LT_S: u8 = 0x53;
+pub const I64_LT_U: u8 = 0x54;
+pub const I64_GT_S: u8 = 0x55;
+pub const I64_GT_U: u8 = 0x56;
+pub const I64_LE_S: u8 = 0x57;
+pub const I64_GE_S: u8 = 0x59;
+pub const I32_ADD: u8 = 0x6a;
+pub const I32_SUB: u8 = 0x6b;
+pub const I32_AND: u8 = 0x71;
+pub const I32_SHL: u8 = 0x74;
+pub const I32_SHR_U: u8 = 0x76;
+pub const F64_EQ: u8 = 0x61;
+pub const F64_NE: u8 = 0x62;
+pub const F64_LT: u8 = 0x63;
+pub const F64_GT: u8 = 0x64;
+pub const F64_LE: u8 = 0x65;
+pub const F64_GE: u8 = 0x66;
+pub const I64_ADD: u8 = 0x7c;
+pub const I64_SUB: u8 = 0x7d;
+pub const I64_MUL: u8 = 0x7e;
+pub const I64_DIV_S: u8 = 0x7f;
+pub const I64_REM_S: u8 = 0x81;
+pub const I64_AND: u8 = 0x83;
+pub const I64_OR: u8 = 0x84;
+pub const I64_XOR: u8 = 0x85;
+pub const I64_SHL: u8 = 0x86;
+pub const I64_SHR_U: u8 = 0x88;
+pub const F64_ADD: u8 = 0xa0;
+pub const F64_SUB: u8 = 0xa1;
+pub const F64_MUL: u8 = 0xa2;
+pub const F64_DIV: u8 = 0xa3;
+pub const I32_WRAP_I64: u8 = 0xa7;
+pub const I64_EXTEND_I32_S: u8 = 0xac;
+pub const I64_EXTEND_I32_U: u8 = 0xad;
+pub const I64_TRUNC_F64_S: u8 = 0xb0;
+pub const F32_DEMOTE_F64: u8 = 0xb6;
+pub const F64_CONVERT_I64_S: u8 = 0xb9;
+pub const F64_PROMOTE_F32: u8 = 0xbb;
+pub const I64_REINTERPRET_F64: u8 = 0xbd;
+pub const F64_REINTERPRET_I64: u8 = 0xbf;
+pub const I32_EXTEND8_S: u8 = 0xc0;
+pub const I32_EXTEND16_S: u8 = 0xc1;
+/// Prefix of the bulk memory instructions
+const PREFIX_FC: u8 = 0xfc;
+const MEMORY_COPY: u32 = 10;
+const MEMORY_FILL: u32 = 11;
+
+// ========== Module ==========
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValType {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl ValType {
+    fn byte(self) -> u8 {
+        match self {
+            ValType::I32 => 0x7f,
+            ValType::I64 => 0x7e,
+            ValType::F32 => 0x7d,
+            ValType::F64 => 0x7c,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self> {
+        Ok(match byte {
+            0x7f => ValType::I32,
+            0x7e => ValType::I64,
+            0x7d => ValType::F32,
+            0x7c => ValType::F64,
+            _ => bail!("Invalid value type {:#04x}", byte),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuncType {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Import {
+    pub module: String,
+    pub name: String,
+    /// Type index of the imported function
+    pub ty: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Func {
+    pub ty: u32,
+    /// Locals after the parameters
+    pub locals: Vec<ValType>,
+    /// Body without its final `end`
+    pub code: Code,
+}
+
+#[derive(Debug, Clone)]
+pub struct Global {
+    pub ty: ValType,
+    pub mutable: bool,
+    pub init: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportKind {
+    Func,
+    Memory,
+    Global,
+}
+
+#[derive(Debug, Clone)]
+pub struct Export {
+    pub name: String,
+    pub kind: ExportKind,
+    pub index: u32,
+}
+
+/// A module with one memory and at most one function table
+#[derive(Debug, Clone, Default)]
+pub struct Module {
+    pub types: Vec<FuncType>,
+    /// Function imports; they come first in the function index space
+    pub imports: Vec<Import>,
+    pub funcs: Vec<Func>,
+    /// Functions reachable through `call_indirect`, at table indices from 1 (0 stays null)
+    pub table: Vec<u32>,
+    pub memory_pages: u32,
+    pub globals: Vec<Global>,
+    pub exports: Vec<Export>,
+    pub start: Option<u32>,
+    /// Active data segments: address and contents
+    pub data: Vec<(u32, Vec<u8>)>,
+}
+
+impl Module {
+    /// Index of a function type, added if new
+    pub fn func_type(&mut self, params: Vec<ValType>, results: Vec<ValType>) -> u32 {
+        let ty = FuncType { params, results };
+        match self.types.iter().position(|t| *t == ty) {
+            Some(i) => i as u32,
+            None => {
+                self.types.push(ty);
+                self.types.len() as u32 - 1
+            }
+        }
+    }
+
+    /// Serialize to the binary format
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&VERSION.to_le_bytes());
+
+        let mut types = Vec::new();
+        for ty in &self.types {
+            types.push(FUNC_TYPE);
+            val_types(&mut types, &ty.params);
+            val_types(&mut types, &ty.results);
+        }
+        section(&mut out, SECTION_TYPE, self.types.len(), &types);
+
+        let mut imports = Vec::new();
+        for import in &self.imports {
+            name(&mut imports, &import.module);
+            name(&mut imports, &import.name);
+            imports.push(KIND_FUNC);
+            uleb(&mut imports, import.ty as u64);
+        }
+        section(&mut out, SECTION_IMPORT, self.imports.len(), &imports);
+
+        let mut funcs = Vec::new();
+        for func in &self.funcs {
+            uleb(&mut funcs, func.ty as u64);
+        }
+        section(&mut out, SECTION_FUNCTION, self.funcs.len(), &funcs);
+
+        if !self.table.is_empty() {
+            let size = self.table.len() as u64 + 1;
+            let mut table = vec![FUNCREF, 1];
+            uleb(&mut table, size);
+            uleb(&mut table, size);
+            section(&mut out, SECTION_TABLE, 1, &table);
+        }
+
+        let mut memory = vec![0];
+        uleb(&mut memory, self.memory_pages as u64);
+        section(&mut out, SECTION_MEMORY, 1, &memory);
+
+        let mut globals = Vec::new();
+        for global in &self.globals {
+            globals.push(global.ty.byte());
+            globals.push(global.mutable as u8);
+            let mut init = Code::default();
+            match global.ty {
+                ValType::I64 => init.i64_const(global.init),
+                _ => init.i32_const(global.init as i32),
+            };
+            globals.extend_from_slice(&init.0);
+            globals.push(END);
+        }
+        section(&mut out, SECTION_GLOBAL, self.globals.len(), &globals);
+
+        let mut exports = Vec::new();
+        for export in &self.exports {
+            name(&mut exports, &export.name);
+            exports.push(match export.kind {
+                ExportKind::Func => KIND_FUNC,
+                ExportKind::Memory => KIND_MEMORY,
+                ExportKind::Global => KIND_GLOBAL,
+            });
+            uleb(&mut exports, export.index as u64);
+        }
+        section(&mut out, SECTION_EXPORT, self.exports.len(), &exports);
+
+        if let Some(start) = self.start {
+            let mut body = Vec::new();
+            uleb(&mut body, start as u64);
+            out.push(SECTION_START);
+            uleb(&mut out, body.len() as u64);
+            out.extend_from_slice(&body);
+        }
+
+        if !self.table.is_empty() {
+            let mut elements = vec![0, I32_CONST, 1, END];
+            uleb(&mut elements, self.table.len() as u64);
+            for &f in &self.table {
+                uleb(&mut elements, f as u64);
+            }
+            section(&mut out, SECTION_ELEMENT, 1, &elements);
+        }
+
+        let mut code = Vec::new();
+        for func in &self.funcs {
+            let mut body = Vec::new();
+            // Runs of equal types
+            let mut runs: Vec<(u32, ValType)> = Vec::new();
+            for &ty in &func.locals {
+                match runs.last_mut() {
+                    Some((count, last)) if *last == ty => *count += 1,
+                    _ => runs.push((1, ty)),
+                }
+            }
+            uleb(&mut body, runs.len() as u64);
+            for (count, ty) in runs {
+                uleb(&mut body, count as u64);
+                body.push(ty.byte());
+            }
+            body.extend_from_slice(&func.code.0);
+            body.push(END);
+            uleb(&mut code, body.len() as u64);
+            code.extend_from_slice(&body);
+        }
+        section(&mut out, SECTION_CODE, self.funcs.len(), &code);
+
+        let mut data = Vec::new();
+        for (address, bytes) in &self.data {
+            data.push(0);
+            let mut offset = Code::default();
+            offset.i32_const(*address as i32);
+            data.extend_from_slice(&offset.0);
+            data.push(END);
+            uleb(&mut data, bytes.len() as u64);
+            data.extend_from_slice(bytes);
+        }
+        section(&mut out, SECTION_DATA, self.data.len(), &data);
+        out
+    }
+}
+
+/// Section holding a vector of `count` entries, left out when empty
+fn section(out: &mut Vec<u8>, id: u8, count: usize, entries: &[u8]) {
+    if count == 0 {
+        return;
+    }
+    let mut body = Vec::new();
+    uleb(&mut body, count as u64);
+    body.extend_from_slice(entries);
+    out.push(id);
+    uleb(out, body.len() as u64);
+    out.extend_from_slice(&body);
+}
+
+fn val_types(out: &mut Vec<u8>, types: &[ValType]) {
+    uleb(out, types.len() as u64);
+    out.extend(types.iter().map(|t| t.byte()));
+}
+
+fn name(out: &mut Vec<u8>, name: &str) {
+    uleb(out, name.len() as u64);
+    out.extend_from_slice(name.as_bytes());
+}
+
+pub(crate) fn uleb(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub(crate) fn sleb(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+// ========== Instructions ==========
+
+/// Instruction sequence of a function body or constant expression
+#[derive(Debug, Clone, Default)]
+pub struct Code(pub Vec<u8>);
+
+impl Code {
+    pub fn op(&mut self, op: u8) -> &mut Self {
+        self.0.push(op);
+        self
+    }
+
+    fn op_index(&mut self, op: u8, index: u32) -> &mut Self {
+        self.0.push(op);
+        uleb(&mut self.0, index as u64);
+        self
+    }
+
+    pub fn i32_const(&mut self, value: i32) -> &mut Self {
+        self.0.push(I32_CONST);
+        sleb(&mut self.0, value as i64);
+        self
+    }
+
+    pub fn i64_const(&mut self, value: i64) -> &mut Self {
+        self.0.push(I64_CONST);
+        sleb(&mut self.0, value);
+        self
+    }
+
+    pub fn local_get(&mut self, local: u32) -> &mut Self {
+        self.op_index(LOCAL_GET, local)
+    }
+
+    pub fn local_set(&mut self, local: u32) -> &mut Self {
+        self.op_index(LOCAL_SET, local)
+    }
+
+    pub fn local_tee(&mut self, local: u32) -> &mut Self {
+        self.op_index(LOCAL_TEE, local)
+    }
+
+    pub fn global_get(&mut self, global: u32) -> &mut Self {
+        self.op_index(GLOBAL_GET, global)
+    }
+
+    pub fn global_set(&mut self, global: u32) -> &mut Self {
+        self.op_index(GLOBAL_SET, global)
+    }
+
+    pub fn call(&mut self, func: u32) -> &mut Self {
+        self.op_index(CALL, func)
+    }
+
+    /// Call through table 0
+    pub fn call_indirect(&mut self, ty: u32) -> &mut Self {
+        self.op_index(CALL_INDIRECT, ty);
+        self.0.push(0);
+        self
+    }
+
+    pub fn block(&mut self) -> &mut Self {
+        self.0.extend_from_slice(&[BLOCK, BLOCK_EMPTY]);
+        self
+    }
+
+    pub fn loop_(&mut self) -> &mut Self {
+        self.0.extend_from_slice(&[LOOP, BLOCK_EMPTY]);
+        self
+    }
+
+    pub fn if_(&mut self) -> &mut Self {
+        self.0.extend_from_slice(&[IF, BLOCK_EMPTY]);
+        self
+    }
+
+    pub fn end(&mut self) -> &mut Self {
+        self.op(END)
+    }
+
+    pub fn br(&mut self, depth: u32) -> &mut Self {
+        self.op_index(BR, depth)
+    }
+
+    pub fn br_if(&mut self, depth: u32) -> &mut Self {
+        self.op_index(BR_IF, depth)
+    }
+
+    pub fn br_table(&mut self, depths: &[u32], default: u32) -> &mut Self {
+        self.op_index(BR_TABLE, depths.len() as u32);
+        for &depth in depths {
+            uleb(&mut self.0, depth as u64);
+        }
+        uleb(&mut self.0, default as u64);
+        self
+    }
+
+    /// Load or store with alignment `align` (log2) and a constant offset
+    pub fn mem(&mut self, op: u8, align: u32, offset: u32) -> &mut Self {
+        self.op_index(op, align);
+        uleb(&mut self.0, offset as u64);
+        self
+    }
+
+    pub fn memory_size(&mut self) -> &mut Self {
+        self.0.extend_from_slice(&[MEMORY_SIZE, 0]);
+        self
+    }
+
+    pub fn memory_grow(&mut self) -> &mut Self {
+        self.0.extend_from_slice(&[MEMORY_GROW, 0]);
+        self
+    }
+
+    pub fn memory_copy(&mut self) -> &mut Self {
+        self.op_index(PREFIX_FC, MEMORY_COPY);
+        self.0.extend_from_slice(&[0, 0]);
+        self
+    }
+
+    pub fn memory_fill(&mut self) -> &mut Self {
+        self.op_index(PREFIX_FC, MEMORY_FILL);
+        self.0.push(0);
+        self
+    }
+}
+
+// ========== Validation ==========
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, pos: 0 }
+    }
+
+    fn done(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        let byte = *self.bytes.get(self.pos).ok_or_else(|| anyhow!("Unexpected end of module"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).filter(|&e| e <= self.bytes.len())
+            .ok_or_else(|| anyhow!("Unexpected end of module"))?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn leb(&mut self, bits: u32, signed: bool) -> Result<i128> {
+        let mut value: i128 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as i128) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if signed && shift < 128 && byte & 0x40 != 0 {
+                    value |= -1i128 << shift;
+                }
+                break;
+            }
+            if shift >= bits + 7 {
+                bail!("LEB128 integer too long");
+            }
+        }
+        let (min, max) = if signed { (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1) } else { (0, (1i128 << bits) - 1) };
+        if value < min || value > max {
+            bail!("LEB128 integer out of range");
+        }
+        Ok(value)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(self.leb(32, false)? as u32)
+    }
+
+    fn name(&mut self) -> Result<&'a str> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.take(len)?).map_err(|_| anyhow!("Name is not UTF-8"))
+    }
+
+    fn val_types(&mut self) -> Result<Vec<ValType>> {
+        let count = self.u32()?;
+        (0..count).map(|_| ValType::from_byte(self.u8()?)).collect()
+    }
+
+    /// Limits: minimum and optional maximum
+    fn limits(&mut self, bound: u64) -> Result<(u64, Option<u64>)> {
+        let flags = self.u8()?;
+        let min = self.u32()? as u64;
+        let max = match flags {
+            0 => None,
+            1 => Some(self.u32()? as u64),
+            _ => bail!("Invalid limits flags {:#04x}", flags),
+        };
+        if min > bound || max.is_some_and(|m| m > bound || m < min) {
+            bail!("Limits out of range");
+        }
+        Ok((min, max))
+    }
+}
+
+/// What a module defines, for checking function bodies
+#[derive(Default)]
+struct Context {
+    types: Vec<FuncType>,
+    /// Type index of every function, imports first
+    funcs: Vec<u32>,
+    /// Type and mutability of every global, imports first
+    globals: Vec<(ValType, bool)>,
+    imported_globals: usize,
+    /// Minimum size of each table
+    tables: Vec<u64>,
+    /// Minimum pages of the memory
+    memory: Option<u64>,
+    exports: HashSet<String>,
+}
+
+/// Check that `bytes` is a well-formed module whose function bodies type-check
+pub fn validate(bytes: &[u8]) -> Result<()> {
+    let mut r = Reader::new(bytes);
+    if r.take(4).ok() != Some(MAGIC.as_slice()) {
+        bail!("Not a WebAssembly module");
+    }
+    if r.take(4)? != VERSION.to_le_bytes() {
+        bail!("Unsupported WebAssembly version");
+    }
+    // Position of each known section in the required order
+    let rank = |id: u8| [SECTION_TYPE, SECTION_IMPORT, SECTION_FUNCTION, SECTION_TABLE, SECTION_MEMORY, SECTION_GLOBAL,
+        SECTION_EXPORT, SECTION_START, SECTION_ELEMENT, SECTION_DATA_COUNT, SECTION_CODE, SECTION_DATA]
+        .iter().position(|&s| s == id);
+    let mut cx = Context::default();
+    let mut last = None;
+    let mut defined_funcs = 0;
+    let mut code_seen = false;
+    let mut data_count = None;
+    while !r.done() {
+        let id = r.u8()?;
+        let size = r.u32()? as usize;
+        let body = r.take(size)?;
+        if id == SECTION_CUSTOM {
+            Reader::new(body).name()?;
+            continue;
+        }
+        let position = rank(id).ok_or_else(|| anyhow!("Unknown section id {}", id))?;
+        if last.is_some_and(|l| position <= l) {
+            bail!("Section {} is out of order or repeated", id);
+        }
+        last = Some(position);
+        let mut s = Reader::new(body);
+        match id {
+            SECTION_DATA_COUNT => data_count = Some(s.u32()?),
+            SECTION_START => {
+                let f = s.u32()?;
+                let ty = cx.func_type(f)?;
+                if !ty.params.is_empty() || !ty.results.is_empty() {
+                    bail!("Start function {} must take and return nothing", f);
+                }
+            }
+            _ => {
+                let count = s.u32()?;
+                for i in 0..count {
+                    match id {
+                        SECTION_TYPE => {
+                            if s.u8()? != FUNC_TYPE {
+                                bail!("Type {} is not a function type", i);
+                            }
+                            let params = s.val_types()?;
+                            let results = s.val_types()?;
+                            cx.types.push(FuncType { params, results });
+                        }
+                        SECTION_IMPORT => cx.import(&mut s)?,
+                        SECTION_FUNCTION => {
+                            let ty = s.u32()?;
+                            cx.type_at(ty)?;
+                            cx.funcs.push(ty);
+                            defined_funcs += 1;
+                        }
+                        SECTION_TABLE => cx.table(&mut s)?,
+                        SECTION_MEMORY => cx.memory(&mut s)?,
+                        SECTION_GLOBAL => {
+                            let ty = ValType::from_byte(s.u8()?)?;
+                            let mutable = mutability(s.u8()?)?;
+                            cx.const_expr(&mut s, ty)?;
+                            cx.globals.push((ty, mutable));
+                        }
+                        SECTION_EXPORT => cx.export(&mut s)?,
+                        SECTION_ELEMENT => cx.element(&mut s, i)?,
+                        SECTION_CODE => {
+                            code_seen = true;
+                            if count != defined_funcs {
+                                bail!("{} function bodies for {} functions", count, defined_funcs);
+                            }
+                            let size = s.u32()? as usize;
+                            let index = cx.funcs.len() as u32 - defined_funcs + i;
+                            cx.body(s.take(size)?, index).map_err(|e| anyhow!("Function {}: {}", index, e))?;
+                        }
+                        SECTION_DATA => {
+                            if data_count.is_some_and(|n| n != count) {
+                                bail!("Data count section disagrees with the data section");
+                            }
+                            cx.data(&mut s, i)?;
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+            }
+        }
+        if !s.done() {
+            bail!("Section {} has {} trailing bytes", id, body.len() - s.pos);
+        }
+    }
+    if defined_funcs > 0 && !code_seen {
+        bail!("Functions are declared but have no bodies");
+    }
+    Ok(())
+}
+
+fn mutability(byte: u8) -> Result<bool> {
+    match byte {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => bail!("Invalid mutability {:#04x}", byte),
+    }
+}
+
+impl Context {
+    fn type_at(&self, ty: u32) -> Result<&FuncType> {
+        self.types.get(ty as usize).ok_or_else(|| anyhow!("Unknown type {}", ty))
+    }
+
+    fn func_type(&self, f: u32) -> Result<&FuncType> {
+        let ty = *self.funcs.get(f as usize).ok_or_else(|| anyhow!("Unknown function {}", f))?;
+        self.type_at(ty)
+    }
+
+    fn import(&mut self, s: &mut Reader) -> Result<()> {
+        s.name()?;
+        s.name()?;
+        match s.u8()? {
+            KIND_FUNC => {
+                let ty = s.u32()?;
+                self.type_at(ty)?;
+                self.funcs.push(ty);
+            }
+            KIND_TABLE => self.table(s)?,
+            KIND_MEMORY => self.memory(s)?,
+            KIND_GLOBAL => {
+                let ty = ValType::from_byte(s.u8()?)?;
+                let mutable = mutability(s.u8()?)?;
+                self.globals.push((ty, mutable));
+                self.imported_globals += 1;
+            }
+            kind => bail!("Invalid import kind {}", kind),
+        }
+        Ok(())
+    }
+
+    fn table(&mut self, s: &mut Reader) -> Result<()> {
+        let reftype = s.u8()?;
+        if reftype != FUNCREF && reftype != EXTERNREF {
+            bail!("Invalid table element type {:#04x}", reftype);
+        }
+        let (min, _) = s.limits(u32::MAX as u64)?;
+        self.tables.push(min);
+        Ok(())
+    }
+
+    fn memory(&mut self, s: &mut Reader) -> Result<()> {
+        if self.memory.is_some() {
+            bail!("More than one memory");
+        }
+        let (min, _) = s.limits(MAX_PAGES)?;
+        self.memory = Some(min);
+        Ok(())
+    }
+
+    /// Constant expression of type `ty`; returns its value when it is an integer constant
+    fn const_expr(&mut self, s: &mut Reader, ty: ValType) -> Result<Option<i64>> {
+        let (actual, value) = match s.u8()? {
+            I32_CONST => (ValType::I32, Some(s.leb(32, true)? as i64)),
+            I64_CONST => (ValType::I64, Some(s.leb(64, true)? as i64)),
+            F32_CONST => {
+                s.take(4)?;
+                (ValType::F32, None)
+            }
+            F64_CONST => {
+                s.take(8)?;
+                (ValType::F64, None)
+            }
+            GLOBAL_GET => {
+                let g = s.u32()? as usize;
+                if g >= self.imported_globals || self.globals[g].1 {
+                    bail!("Constant expressions may only read immutable imported globals");
+                }
+                (self.globals[g].0, None)
+            }
+            op => bail!("Opcode {:#04x} is not allowed in a constant expression", op),
+        };
+        if actual != ty {
+            bail!("Constant expression has type {:?}, expected {:?}", actual, ty);
+        }
+        if s.u8()? != END {
+            bail!("Constant expression is not terminated");
+        }
+        Ok(value)
+    }
+
+    fn export(&mut self, s: &mut Reader) -> Result<()> {
+        let name = s.name()?.to_string();
+        let kind = s.u8()?;
+        let index = s.u32()?;
+        let count = match kind {
+            KIND_FUNC => self.funcs.len(),
+            KIND_TABLE => self.tables.len(),
+            KIND_MEMORY => self.memory.is_some() as usize,
+            KIND_GLOBAL => self.globals.len(),
+            _ => bail!("Invalid export kind {} for {}", kind, name),
+        };
+        if index as usize >= count {
+            bail!("Export {} refers to a missing item {}", name, index);
+        }
+        if !self.exports.insert(name.clone()) {
+            bail!("Duplicate export {}", name);
+        }
+        Ok(())
+    }
+
+    fn element(&mut self, s: &mut Reader, i: u32) -> Result<()> {
+        let flags = s.u32()?;
+        if flags != 0 {
+            bail!("Element segment {} uses unsupported flags {}", i, flags);
+        }
+        let offset = self.const_expr(s, ValType::I32)?;
+        let count = s.u32()?;
+        for _ in 0..count {
+            self.func_type(s.u32()?)?;
+        }
+        let table = *self.tables.first().ok_or_else(|| anyhow!("Element segment {} without a table", i))?;
+        if offset.is_some_and(|o| o as u32 as u64 + count as u64 > table) {
+            bail!("Element segment {} does not fit its table", i);
+        }
+        Ok(())
+    }
+
+    fn data(&mut self, s: &mut Reader, i: u32) -> Result<()> {
+        let flags = s.u32()?;
+        let offset = match flags {
+            0 | 2 => {
+                if flags == 2 && s.u32()? != 0 {
+                    bail!("Data segment {} targets a missing memory", i);
+                }
+                let pages = self.memory.ok_or_else(|| anyhow!("Data segment {} without a memory", i))?;
+                self.const_expr(s, ValType::I32)?.map(|o| (o as u32 as u64, pages))
+            }
+            1 => None,
+            _ => bail!("Data segment {} has invalid flags {}", i, flags),
+        };
+        let len = s.u32()? as u64;
+        s.take(len as usize)?;
+        if offset.is_some_and(|(o, pages)| o + len > pages * PAGE_SIZE) {
+            bail!("Data segment {} does not fit in the initial memory", i);
+        }
+        Ok(())
+    }
+
+    fn body(&self, body: &[u8], index: u32) -> Result<()> {
+        let mut s = Reader::new(body);
+        let ty = self.func_type(index)?.clone();
+        let mut locals = ty.params.clone();
+        for _ in 0..s.u32()? {
+            let count = s.u32()? as usize;
+            let ty = ValType::from_byte(s.u8()?)?;
+            if locals.len() + count > 50_000 {
+                bail!("Too many locals");
+            }
+            locals.extend(std::iter::repeat_n(ty, count));
+        }
+        let mut v = Validator {
+            cx: self,
+            locals,
+            results: ty.results.clone(),
+            stack: Vec::new(),
+            frames: vec![Frame { kind: FrameKind::Function, results: ty.results, height: 0, unreachable: false }],
+        };
+        while !v.frames.is_empty() {
+            let op = s.u8()?;
+            v.inst(op, &mut s)?;
+        }
+        if !s.done() {
+            bail!("Code after the end of the body");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameKind {
+    Function,
+    Block,
+    Loop,
+    If,
+    Else,
+}
+
+/// An open control construct
+struct Frame {
+    kind: FrameKind,
+    results: Vec<ValType>,
+    /// Operand stack height on entry
+    height: usize,
+    /// After an unconditional branch: the stack is polymorphic
+    unreachable: bool,
+}
+
+/// Operand stack typing of one function body; `None` is a value of unknown type
+struct Validator<'a> {
+    cx: &'a Context,
+    locals: Vec<ValType>,
+    results: Vec<ValType>,
+    stack: Vec<Option<ValType>>,
+    frames: Vec<Frame>,
+}
+
+use ValType::{F32, F64, I32, I64};
+
+impl Validator<'_> {
+    fn push(&mut self, ty: ValType) {
+        self.stack.push(Some(ty));
+    }
+
+    fn pop(&mut self) -> Result<Option<ValType>> {
+        let frame = self.frames.last().ok_or_else(|| anyhow!("Instruction after the end of the body"))?;
+        if self.stack.len() == frame.height {
+            if frame.unreachable {
+                return Ok(None);
+            }
+            bail!("Operand stack underflow");
+        }
+        Ok(self.stack.pop().flatten())
+    }
+
+    fn pop_expect(&mut self, ty: ValType) -> Result<()> {
+        match self.pop()? {
+            Some(actual) if actual != ty => bail!("Expected {:?} on the stack, found {:?}", ty, actual),
+            _ => Ok(()),
+        }
+    }
+
+    fn pop_all(&mut self, types: &[ValType]) -> Result<()> {
+        for &ty in types.iter().rev() {
+            self.pop_expect(ty)?;
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, params: &[ValType], results: &[ValType]) -> Result<()> {
+        self.pop_all(params)?;
+        for &ty in results {
+            self.push(ty);
+        }
+        Ok(())
+    }
+
+    fn set_unreachable(&mut self) {
+        let frame = self.frames.last_mut().expect("open frame");
+        self.stack.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    /// Types a branch to `depth` carries
+    fn label(&self, depth: u32) -> Result<Vec<ValType>> {
+        let frame = self.frames.len().checked_sub(depth as usize + 1)
+            .map(|i| &self.frames[i])
+            .ok_or_else(|| anyhow!("Branch depth {} out of range", depth))?;
+        Ok(if frame.kind == FrameKind::Loop { Vec::new() } else { frame.results.clone() })
+    }
+
+    fn block_type(&mut self, s: &mut Reader) -> Result<Vec<ValType>> {
+        match s.u8()? {
+            BLOCK_EMPTY => Ok(Vec::new()),
+            byte => Ok(vec![ValType::from_byte(byte).map_err(|_| anyhow!("Unsupported block type {:#04x}", byte))?]),
+        }
+    }
+
+    fn memarg(&mut self, s: &mut Reader, natural: u32) -> Result<()> {
+        if self.cx.memory.is_none() {
+            bail!("Memory access without a memory");
+        }
+        if s.u32()? > natural {
+            bail!("Alignment larger than natural");
+        }
+        s.u32()?;
+        Ok(())
+    }
+
+    fn local(&self, s: &mut Reader) -> Result<ValType> {
+        let i = s.u32()?;
+        self.locals.get(i as usize).copied().ok_or_else(|| anyhow!("Unknown local {}", i))
+    }
+
+    fn inst(&mut self, op: u8, s: &mut Reader) -> Result<()> {
+        match op {
+            UNREACHABLE => self.set_unreachable(),
+            NOP => {}
+            BLOCK | LOOP | IF => {
+                let results = self.block_type(s)?;
+                if op == IF {
+                    self.pop_expect(I32)?;
+                }
+                let kind = match op {
+                    BLOCK => FrameKind::Block,
+                    LOOP => FrameKind::Loop,
+                    _ => FrameKind::If,
+                };
+                self.frames.push(Frame { kind, results, height: self.stack.len(), unreachable: false });
+            }
+            ELSE => {
+                let frame = self.frames.last().ok_or_else(|| anyhow!("else outside of if"))?;
+                if frame.kind != FrameKind::If {
+                    bail!("else outside of if");
+                }
+                let results = frame.results.clone();
+                self.pop_all(&results)?;
+                if self.stack.len() != self.frames.last().expect("open frame").height {
+                    bail!("Values left on the stack at else");
+                }
+                let frame = self.frames.last_mut().expect("open frame");
+                frame.kind = FrameKind::Else;
+                frame.unreachable = false;
+            }
+            END => {
+                let results = self.frames.last().ok_or_else(|| anyhow!("Unbalanced end"))?.results.clone();
+                self.pop_all(&results)?;
+                let frame = self.frames.pop().expect("open frame");
+                if self.stack.len() != frame.height {
+                    bail!("Values left on the stack at end");
+                }
+                if frame.kind == FrameKind::If && !frame.results.is_empty() {
+                    bail!("if without else must not produce values");
+                }
+                for ty in frame.results {
+                    self.push(ty);
+                }
+            }
+            BR => {
+                let label = self.label(s.u32()?)?;
+                self.pop_all(&label)?;
+                self.set_unreachable();
+            }
+            BR_IF => {
+                let label = self.label(s.u32()?)?;
+                self.pop_expect(I32)?;
+                self.apply(&label, &label)?;
+            }
+            BR_TABLE => {
+                let count = s.u32()?;
+                let mut labels = Vec::new();
+                for _ in 0..=count {
+                    labels.push(self.label(s.u32()?)?);
+                }
+                let default = labels.pop().expect("default label");
+                if labels.iter().any(|l| l.len() != default.len()) {
+                    bail!("br_table targets differ in arity");
+                }
+                self.pop_expect(I32)?;
+                self.pop_all(&default)?;
+                self.set_unreachable();
+            }
+            RETURN => {
+                let results = self.results.clone();
+                self.pop_all(&results)?;
+                self.set_unreachable();
+            }
+            CALL => {
+                let ty = self.cx.func_type(s.u32()?)?.clone();
+                self.apply(&ty.params, &ty.results)?;
+            }
+            CALL_INDIRECT => {
+                let ty = self.cx.type_at(s.u32()?)?.clone();
+                let table = s.u32()?;
+                if table as usize >= self.cx.tables.len() {
+                    bail!("call_indirect through a missing table");
+                }
+                self.pop_expect(I32)?;
+                self.apply(&ty.params, &ty.results)?;
+            }
+            DROP => {
+                self.pop()?;
+            }
+            SELECT => {
+                self.pop_expect(I32)?;
+                let a = self.pop()?;
+                let b = self.pop()?;
+                if a.is_some() && b.is_some() && a != b {
+                    bail!("select operands differ in type");
+                }
+                self.stack.push(a.or(b));
+            }
+            LOCAL_GET => {
+                let ty = self.local(s)?;
+                self.push(ty);
+            }
+            LOCAL_SET => {
+                let ty = self.local(s)?;
+                self.pop_expect(ty)?;
+            }
+            LOCAL_TEE => {
+                let ty = self.local(s)?;
+                self.apply(&[ty], &[ty])?;
+            }
+            GLOBAL_GET | GLOBAL_SET => {
+                let g = s.u32()?;
+                let (ty, mutable) = *self.cx.globals.get(g as usize).ok_or_else(|| anyhow!("Unknown global {}", g))?;
+                if op == GLOBAL_GET {
+                    self.push(ty);
+                } else if !mutable {
+                    bail!("global.set of immutable global {}", g);
+                } else {
+                    self.pop_expect(ty)?;
+                }
+            }
+            0x28..=0x35 => {
+                let (natural, ty) = match op {
+                    0x28 => (2, I32),
+                    0x29 => (3, I64),
+                    0x2a => (2, F32),
+                    0x2b => (3, F64),
+                    0x2c | 0x2d => (0, I32),
+                    0x2e | 0x2f => (1, I32),
+                    0x30 | 0x31 => (0, I64),
+                    0x32 | 0x33 => (1, I64),
+                    _ => (2, I64),
+                };
+                self.memarg(s, natural)?;
+                self.apply(&[I32], &[ty])?;
+            }
+            0x36..=0x3e => {
+                let (natural, ty) = match op {
+                    0x36 => (2, I32),
+                    0x37 => (3, I64),
+                    0x38 => (2, F32),
+                    0x39 => (3, F64),
+                    0x3a => (0, I32),
+                    0x3b => (1, I32),
+                    0x3c => (0, I64),
+                    0x3d => (1, I64),
+                    _ => (2, I64),
+                };
+                self.memarg(s, natural)?;
+                self.apply(&[I32, ty], &[])?;
+            }
+            MEMORY_SIZE | MEMORY_GROW => {
+                if s.u8()? != 0 || self.cx.memory.is_none() {
+                    bail!("Memory instruction without a memory");
+                }
+                if op == MEMORY_SIZE {
+                    self.push(I32);
+                } else {
+                    self.apply(&[I32], &[I32])?;
+                }
+            }
+            I32_CONST => {
+                s.leb(32, true)?;
+                self.push(I32);
+            }
+            I64_CONST => {
+                s.leb(64, true)?;
+                self.push(I64);
+            }
+            F32_CONST => {
+                s.take(4)?;
+                self.push(F32);
+            }
+            F64_CONST => {
+                s.take(8)?;
+                self.push(F64);
+            }
+            PREFIX_FC => {
+                let memories = match s.u32()? {
+                    MEMORY_COPY => 2,
+                    MEMORY_FILL => 1,
+                    sub => bail!("Unsupported instruction 0xfc {}", sub),
+                };
+                for _ in 0..memories {
+                    if s.u8()? != 0 || self.cx.memory.is_none() {
+                        bail!("Memory instruction without a memory");
+                    }
+                }
+                self.apply(&[I32, I32, I32], &[])?;
+            }
+            _ => {
+                let (params, results) = numeric(op).ok_or_else(|| anyhow!("Unsupported opcode {:#04x}", op))?;
+                self.apply(params, results)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Operand and result types of the numeric and conversion instructions
+fn numeric(op: u8) -> Option<(&'static [ValType], &'static [ValType])> {
+    Some(match op {
+        0x45 => (&[I32], &[I32]),
+        0x46..=0x4f => (&[I32, I32], &[I32]),
+        0x50 => (&[I64], &[I32]),
+        0x51..=0x5a => (&[I64, I64], &[I32]),
+        0x61..=0x66 => (&[F64, F64], &[I32]),
+        0x67..=0x69 => (&[I32], &[I32]),
+        0x6a..=0x78 => (&[I32, I32], &[I32]),
+        0x79..=0x7b => (&[I64], &[I64]),
+        0x7c..=0x8a => (&[I64, I64], &[I64]),
+        0xa0..=0xa3 => (&[F64, F64], &[F64]),
+        0xa7 => (&[I64], &[I32]),
+        0xac | 0xad => (&[I32], &[I64]),
+        0xb0 => (&[F64], &[I64]),
+        0xb9 => (&[I64], &[F64]),
+        0xb6 => (&[F64], &[F32]),
+        0xbb => (&[F32], &[F64]),
+        0xbd => (&[F64], &[I64]),
+        0xbf => (&[I64], &[F64]),
+        0xc0 | 0xc1 => (&[I32], &[I32]),
+        0xc2..=0xc4 => (&[I64], &[I64]),
+        _ => return None,
+    })
+}
+
+// ========== Output Files ==========
+
+/// Validate an encoded module and write it as a `.wasm` file
+pub fn write(path: &Path, code: &[u8]) -> Result<()> {
+    validate(code).map_err(|e| anyhow!("Invalid WebAssembly module: {}", e))?;
+    std::fs::write(path, code).map_err(|e| anyhow!("Cannot write {}: {}", path.display(), e))
+}
@@ -0,0 +1,643 @@
+//! Control-flow graph lowering for the borrow checker
+//!
+//! Function bodies become basic blocks of variable-level actions
+
+use std::fmt;
+use crate::ast::*;
+use crate::tooling::fmt::print_expr;
+use super::ownership::{has_ref, type_name, Ownership, Signature};
+
+pub type BlockId = usize;
+pub type LoanId = usize;
+
+/// Step from a place to a part of it
+#[derive(Debug, Clone)]
+pub enum Proj {
+    Field(String),
+    /// Element at an index (source text, for diagnostics)
+    Index(String),
+    Deref,
+    /// Implicit dereference of a reference before a field or index: `r.x`
+    AutoDeref,
+}
+
+impl Proj {
+    fn is_deref(&self) -> bool {
+        matches!(self, Proj::Deref | Proj::AutoDeref)
+    }
+}
+
+/// A local or a path into it: `a`, `a.x.y`, `a[i]`, `*p`
+#[derive(Debug, Clone)]
+pub struct Place {
+    pub var: String,
+    pub proj: Vec<Proj>,
+}
+
+impl Place {
+    pub fn local(var: String) -> Self {
+        Place { var, proj: Vec::new() }
+    }
+
+    /// Whether the two places may share memory: one is a prefix of the other.
+    /// Indexes are not compared, so `a[i]` and `a[j]` always overlap.
+    pub fn overlaps(&self, other: &Place) -> bool {
+        self.var == other.var && self.proj.iter().zip(&other.proj).all(|pair| match pair {
+            (Proj::Field(a), Proj::Field(b)) => a == b,
+            (Proj::Index(_), Proj::Index(_)) => true,
+            (a, b) => a.is_deref() && b.is_deref(),
+        })
+    }
+
+    /// Whether the place is reached through a reference, so it outlives the local
+    pub fn through_deref(&self) -> bool {
+        self.proj.iter().any(Proj::is_deref)
+    }
+}
+
+impl fmt::Display for Place {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut text = source_name(&self.var).to_string();
+        for (i, proj) in self.proj.iter().enumerate() {
+            text = match proj {
+                Proj::Field(name) => format!("{}.{}", text, name),
+                Proj::Index(idx) => format!("{}[{}]", text, idx),
+                Proj::Deref if i + 1 == self.proj.len() => format!("*{}", text),
+                Proj::Deref => format!("(*{})", text),
+                Proj::AutoDeref => text,
+            };
+        }
+        write!(f, "{}", text)
+    }
+}
+
+/// A borrow of a place: `&x`, `&mut x.field`
+#[derive(Debug, Clone)]
+pub struct Loan {
+    pub place: Place,
+    pub mutable: bool,
+    pub span: Span,
+    /// Borrow made by the caller for the parameter at this index
+    pub param: Option<usize>,
+}
+
+/// One step of a lowered function body
+#[derive(Debug, Clone)]
+pub enum Action {
+    /// Read a place
+    Read(Place, Span),
+    /// Move a local's value out; it may not be used until reassigned
+    Move(String, Span),
+    /// Overwrite a whole local; it now holds `loans` plus the loans held by `copies`
+    Assign { var: String, loans: Vec<LoanId>, copies: Vec<String>, span: Span },
+    /// Write a field or element of a local, or through a reference. A local
+    /// keeps the loans it holds and gains those of the value.
+    Write { place: Place, loans: Vec<LoanId>, copies: Vec<String>, span: Span },
+    /// Return a value holding `loans` plus the loans held by `copies`
+    Return { loans: Vec<LoanId>, copies: Vec<String>, span: Span },
+    /// Create a loan
+    Borrow(LoanId),
+    /// A local goes out of scope
+    Dead(String, Span),
+}
+
+#[derive(Debug, Default)]
+pub struct BasicBlock {
+    pub actions: Vec<Action>,
+    pub succs: Vec<BlockId>,
+}
+
+/// Lowered function body; block 0 is the entry
+#[derive(Debug, Default)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+    pub loans: Vec<Loan>,
+    /// Lowered names of the parameters
+    pub params: Vec<String>,
+}
+
+impl Cfg {
+    /// Locals an action reads
+    pub fn uses<'a>(&'a self, action: &'a Action) -> Vec<&'a str> {
+        match action {
+            Action::Read(place, _) => vec![&place.var],
+            Action::Write { place, copies, .. } => {
+                std::iter::once(&place.var).chain(copies).map(String::as_str).collect()
+            }
+            Action::Move(var, _) => vec![var],
+            Action::Assign { copies, .. } | Action::Return { copies, .. } => copies.iter().map(String::as_str).collect(),
+            Action::Borrow(loan) => vec![&self.loans[*loan].place.var],
+            Action::Dead(..) => Vec::new(),
+        }
+    }
+
+    pub fn span(&self, action: &Action) -> Span {
+        match action {
+            Action::Read(_, span) | Action::Move(_, span) | Action::Write { span, .. } |
+            Action::Assign { span, .. } | Action::Return { span, .. } | Action::Dead(_, span) => *span,
+            Action::Borrow(loan) => self.loans[*loan].span,
+        }
+    }
+}
+
+/// Source name of a lowered local (`x#3` -> `x`)
+pub fn source_name(var: &str) -> &str {
+    var.split('#').next().unwrap_or(var)
+}
+
+/// Loans carried by the value of an expression
+#[derive(Debug, Default, Clone)]
+struct Value {
+    loans: Vec<LoanId>,
+    /// Locals whose loans the value also holds
+    copies: Vec<String>,
+}
+
+impl Value {
+    fn is_empty(&self) -> bool {
+        self.loans.is_empty() && self.copies.is_empty()
+    }
+
+    fn extend(&mut self, other: Value) {
+        self.loans.extend(other.loans);
+        self.copies.extend(other.copies);
+    }
+}
+
+struct Builder<'a> {
+    cfg: Cfg,
+    current: BlockId,
+    /// Locals in scope, innermost last: (source name, lowered name, type if known)
+    scopes: Vec<Vec<(String, String, Option<Type>)>>,
+    /// Enclosing loops: (continue target, break target)
+    loops: Vec<(BlockId, BlockId)>,
+    own: &'a Ownership,
+    next_id: usize,
+}
+
+/// Lower a function body. Values of non-`Copy` types move when passed by
+/// value, assigned or returned.
+pub fn lower(params: &[Param], body: &Block, own: &Ownership) -> Cfg {
+    let mut builder = Builder {
+        cfg: Cfg::default(),
+        current: 0,
+        scopes: vec![Vec::new()],
+        loops: Vec::new(),
+        own,
+        next_id: 0,
+    };
+    builder.cfg.blocks.push(BasicBlock::default());
+    for (i, param) in params.iter().enumerate() {
+        let var = builder.declare(&param.name, Some(param.ty.clone()));
+        builder.cfg.params.push(var.clone());
+        // A reference parameter holds a borrow made by the caller
+        let mut value = Value::default();
+        if has_ref(&param.ty) {
+            let place = Place { var: var.clone(), proj: vec![Proj::Deref] };
+            value.loans.push(builder.cfg.loans.len());
+            builder.cfg.loans.push(Loan { place, mutable: param.mutable, span: param.span, param: Some(i) });
+        }
+        builder.assign(var, value, param.span);
+    }
+    // The tail expression is the function's result
+    builder.enter();
+    for (i, stmt) in body.stmts.iter().enumerate() {
+        match stmt {
+            Stmt::Expr(expr, span) if i + 1 == body.stmts.len() => {
+                let value = builder.operand(expr);
+                builder.ret(value, *span);
+            }
+            _ => builder.stmt(stmt),
+        }
+    }
+    builder.leave(body.span);
+    builder.cfg
+}
+
+impl Builder<'_> {
+    fn new_block(&mut self) -> BlockId {
+        self.cfg.blocks.push(BasicBlock::default());
+        self.cfg.blocks.len() - 1
+    }
+
+    fn goto(&mut self, target: BlockId) {
+        self.cfg.blocks[self.current].succs.push(target);
+    }
+
+    fn emit(&mut self, action: Action) {
+        self.cfg.blocks[self.current].actions.push(action);
+    }
+
+    fn declare(&mut self, name: &str, ty: Option<Type>) -> String {
+        self.next_id += 1;
+        let var = format!("{}#{}", name, self.next_id);
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push((name.to_string(), var.clone(), ty));
+        }
+        var
+    }
+
+    fn temp(&mut self) -> String {
+        self.next_id += 1;
+        format!("#{}", self.next_id)
+    }
+
+    fn local(&self, name: &str) -> Option<&(String, String, Option<Type>)> {
+        self.scopes.iter().rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(n, _, _)| n == name)
+    }
+
+    fn resolve(&self, name: &str) -> Option<String> {
+        self.local(name).map(|(_, var, _)| var.clone())
+    }
+
+    /// Type of an expression, as far as it can be told without the type checker
+    fn type_of(&self, expr: &Expr) -> Option<Type> {
+        self.own.type_of(expr, &|name: &str| self.local(name).map(|(_, _, ty)| ty.clone()))
+    }
+
+    /// Lower an expression whose value is moved: a whole local of a
+    /// non-`Copy` type is moved out, anything else is just evaluated
+    fn operand(&mut self, expr: &Expr) -> Value {
+        if let Expr::Ident(name, span) = expr {
+            if let Some((_, var, Some(ty))) = self.local(name) {
+                if !self.own.is_copy(ty) {
+                    let var = var.clone();
+                    self.emit(Action::Move(var.clone(), *span));
+                    return Value { loans: Vec::new(), copies: vec![var] };
+                }
+            }
+        }
+        self.expr(expr)
+    }
+
+    fn assign(&mut self, var: String, value: Value, span: Span) {
+        self.emit(Action::Assign { var, loans: value.loans, copies: value.copies, span });
+    }
+
+    fn ret(&mut self, value: Value, span: Span) {
+        self.emit(Action::Return { loans: value.loans, copies: value.copies, span });
+        self.current = self.new_block();
+    }
+
+    /// Keep a value's loans in a temporary until it is read
+    fn hold(&mut self, value: Value, span: Span) -> Value {
+        if value.is_empty() {
+            return value;
+        }
+        let temp = self.temp();
+        self.assign(temp.clone(), value, span);
+        Value { loans: Vec::new(), copies: vec![temp] }
+    }
+
+    fn enter(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    fn leave(&mut self, span: Span) {
+        for (_, var, _) in self.scopes.pop().unwrap_or_default().into_iter().rev() {
+            self.emit(Action::Dead(var, span));
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.scoped(block);
+    }
+
+    /// Lower a block in its own scope, returning the value of its tail expression
+    fn scoped(&mut self, block: &Block) -> Value {
+        self.enter();
+        let mut value = Value::default();
+        for (i, stmt) in block.stmts.iter().enumerate() {
+            match stmt {
+                Stmt::Expr(expr, span) if i + 1 == block.stmts.len() => {
+                    let tail = self.expr(expr);
+                    value = self.hold(tail, *span);
+                }
+                _ => self.stmt(stmt),
+            }
+        }
+        self.leave(block.span);
+        value
+    }
+
+    /// Lower branches that all continue at a common join block
+    fn branch(&mut self, arms: usize) -> (Vec<BlockId>, BlockId) {
+        let starts: Vec<_> = (0..arms).map(|_| self.new_block()).collect();
+        for &start in &starts {
+            self.goto(start);
+        }
+        (starts, self.new_block())
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Let { name, ty, init, span, .. } => {
+                // A value converted to a `Copy` type (`let p: Int = s`) is not moved
+                let copied = ty.as_ref().is_some_and(|t| self.own.is_copy(t));
+                let ty = ty.clone().or_else(|| init.as_ref().and_then(|e| self.type_of(e)));
+                let value = match init {
+                    Some(init) if copied => self.expr(init),
+                    Some(init) => self.operand(init),
+                    None => Value::default(),
+                };
+                let var = self.declare(name, ty);
+                self.assign(var, value, *span);
+            }
+            Stmt::Assign(target, value, span) => {
+                let value = self.operand(value);
+                match target {
+                    Expr::Ident(name, _) => {
+                        if let Some(var) = self.resolve(name) {
+                            self.assign(var, value, *span);
+                        }
+                    }
+                    other => {
+                        if let Some(place) = self.place(other) {
+                            self.emit(Action::Write { place, loans: value.loans, copies: value.copies, span: *span });
+                        }
+                    }
+                }
+            }
+            Stmt::Expr(expr, _) => {
+                self.expr(expr);
+            }
+            Stmt::Return(value, span) => {
+                let value = value.as_ref().map(|v| self.operand(v)).unwrap_or_default();
+                self.ret(value, *span);
+            }
+            Stmt::If(cond, then_block, else_block, _) => {
+                self.expr(cond);
+                let (starts, join) = self.branch(2);
+                self.current = starts[0];
+                self.block(then_block);
+                self.goto(join);
+                self.current = starts[1];
+                if let Some(else_block) = else_block {
+                    self.block(else_block);
+                }
+                self.goto(join);
+                self.current = join;
+            }
+            Stmt::While(cond, body, _) => {
+                let header = self.new_block();
+                self.goto(header);
+                self.current = header;
+                self.expr(cond);
+                let (starts, exit) = self.branch(1);
+                self.goto(exit);
+                self.loops.push((header, exit));
+                self.current = starts[0];
+                self.block(body);
+                self.goto(header);
+                self.loops.pop();
+                self.current = exit;
+            }
+            Stmt::For(var, iter, body, span) => {
+                let item_ty = match self.type_of(iter) {
+                    Some(Type::Array(elem, _)) => Some(*elem),
+                    _ => None,
+                };
+                let items = self.expr(iter);
+                let items = self.hold(items, *span);
+                let header = self.new_block();
+                self.goto(header);
+                self.current = header;
+                for temp in &items.copies {
+                    self.emit(Action::Read(Place::local(temp.clone()), *span));
+                }
+                let (starts, exit) = self.branch(1);
+                self.goto(exit);
+                self.loops.push((header, exit));
+                self.current = starts[0];
+                self.enter();
+                let item = self.declare(var, item_ty);
+                self.assign(item, items, *span);
+                self.block(body);
+                self.leave(body.span);
+                self.goto(header);
+                self.loops.pop();
+                self.current = exit;
+            }
+            Stmt::Break(_) | Stmt::Continue(_) => {
+                if let Some(&(cont, exit)) = self.loops.last() {
+                    self.goto(if matches!(stmt, Stmt::Break(_)) { exit } else { cont });
+                }
+                self.current = self.new_block();
+            }
+            Stmt::Block(block, _) => {
+                self.block(block);
+            }
+        }
+    }
+
+    /// The place an expression names when it is rooted at a local. Index
+    /// operands are lowered; any other expression is lowered as a value.
+    fn place(&mut self, expr: &Expr) -> Option<Place> {
+        let (inner, proj) = match expr {
+            // Globals are not owned by the function
+            Expr::Ident(name, _) => return self.resolve(name).map(Place::local),
+            Expr::Field(inner, field, _) => (inner, Proj::Field(field.clone())),
+            Expr::Index(inner, idx, _) => (inner, Proj::Index(print_expr(idx))),
+            Expr::Unary(UnOp::Deref, inner, _) => (inner, Proj::Deref),
+            other => {
+                self.expr(other);
+                return None;
+            }
+        };
+        // Fields and elements of a reference are reached through it
+        let auto_deref = !matches!(proj, Proj::Deref)
+            && self.type_of(inner).is_some_and(|t| t.pointee().is_some());
+        let place = self.place(inner);
+        if let Expr::Index(_, idx, _) = expr {
+            self.expr(idx);
+        }
+        let mut place = place?;
+        if auto_deref {
+            place.proj.push(Proj::AutoDeref);
+        }
+        place.proj.push(proj);
+        Some(place)
+    }
+
+    fn borrow(&mut self, place: &Expr, mutable: bool, span: Span) -> Value {
+        let Some(place) = self.place(place) else { return Value::default() };
+        // Reborrowing through a reference keeps the reference's loans alive
+        let copies = if place.through_deref() { vec![place.var.clone()] } else { Vec::new() };
+        let loan = self.cfg.loans.len();
+        self.cfg.loans.push(Loan { place, mutable, span, param: None });
+        self.emit(Action::Borrow(loan));
+        Value { loans: vec![loan], copies }
+    }
+
+    /// Arguments stay borrowed until the call. Arguments for parameters of
+    /// known non-`Copy` types are moved. Returns what each argument holds.
+    fn call<'e>(&mut self, args: impl IntoIterator<Item = &'e Expr>, params: Option<&[Type]>, span: Span) -> Vec<Value> {
+        let mut held = Vec::new();
+        for (i, arg) in args.into_iter().enumerate() {
+            let by_value = params.and_then(|p| p.get(i)).is_some_and(|t| !self.own.is_copy(t));
+            let value = if by_value { self.operand(arg) } else { self.expr(arg) };
+            held.push(self.hold(value, span));
+        }
+        for temp in held.iter().flat_map(|v| &v.copies) {
+            self.emit(Action::Read(Place::local(temp.clone()), span));
+        }
+        held
+    }
+
+    /// The result of a call holds the loans of the arguments its signature
+    /// says it borrows from (all of them when that is ambiguous)
+    fn result(sig: Option<&Signature>, held: &[Value]) -> Value {
+        let Some(sig) = sig else { return Value::default() };
+        let borrowed = sig.borrowed().unwrap_or_else(|| (0..held.len()).collect());
+        let mut value = Value::default();
+        for i in borrowed {
+            if let Some(arg) = held.get(i) {
+                value.extend(arg.clone());
+            }
+        }
+        value
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Value {
+        match expr {
+            Expr::Ident(..) | Expr::Field(..) | Expr::Index(..) | Expr::Unary(UnOp::Deref, _, _) => {
+                match self.place(expr) {
+                    Some(place) => {
+                        // Only a whole local carries the loans it holds
+                        let copies = if place.proj.is_empty() { vec![place.var.clone()] } else { Vec::new() };
+                        self.emit(Action::Read(place, expr.span()));
+                        Value { loans: Vec::new(), copies }
+                    }
+                    None => Value::default(),
+                }
+            }
+            Expr::Unary(UnOp::Ref, place, span) => self.borrow(place, false, *span),
+            Expr::Unary(UnOp::RefMut, place, span) => self.borrow(place, true, *span),
+            Expr::Unary(_, inner, _) => {
+                self.expr(inner);
+                Value::default()
+            }
+            Expr::Binary(_, left, right, _) => {
+                self.expr(left);
+                self.expr(right);
+                Value::default()
+            }
+            Expr::Call(callee, args, span) | Expr::Spawn(callee, args, span) => {
+                let own = self.own;
+                let sig = match callee.as_ref() {
+                    Expr::Ident(name, _) if self.local(name).is_none() => own.func(name),
+                    // `Type::method(receiver, ..)` passes `self` explicitly
+                    Expr::Path(path, _) if path.len() == 2 => own.method(&path[0], &path[1]),
+                    _ => None,
+                };
+                self.expr(callee);
+                let held = self.call(args, sig.map(|s| s.params.as_slice()), *span);
+                Self::result(sig, &held)
+            }
+            Expr::MethodCall(obj, method, args, span) => {
+                let own = self.own;
+                let obj_ty = self.type_of(obj);
+                let sig = obj_ty.as_ref()
+                    .and_then(type_name)
+                    .and_then(|t| own.method(&t, method));
+                // The receiver is borrowed, never moved. A result borrowed from
+                // `self` borrows the receiver itself unless it is a reference.
+                let autoref = sig.is_some_and(|s| s.has_self && s.borrowed().is_some_and(|b| b.contains(&0)))
+                    && obj_ty.as_ref().is_some_and(|t| t.pointee().is_none());
+                let receiver = if autoref {
+                    let mutable = sig.is_some_and(|s| s.self_mut);
+                    self.borrow(obj, mutable, *span)
+                } else {
+                    self.expr(obj)
+                };
+                let mut held = vec![self.hold(receiver, *span)];
+                held.extend(self.call(args, sig.map(Signature::args), *span));
+                for temp in &held[0].copies {
+                    self.emit(Action::Read(Place::local(temp.clone()), *span));
+                }
+                match sig {
+                    Some(sig) if sig.has_self => Self::result(Some(sig), &held),
+                    _ => Self::result(sig, &held[1..]),
+                }
+            }
+            Expr::Array(elems, _) => {
+                let mut value = Value::default();
+                for elem in elems {
+                    value.extend(self.operand(elem));
+                }
+                value
+            }
+            Expr::Struct(_, fields, _) => {
+                let mut value = Value::default();
+                for (_, field) in fields {
+                    value.extend(self.operand(field));
+                }
+                value
+            }
+            Expr::If(cond, then_block, else_block, span) => {
+                self.expr(cond);
+                let result = self.temp();
+                let (starts, join) = self.branch(2);
+                self.current = starts[0];
+                let value = self.scoped(then_block);
+                self.assign(result.clone(), value, *span);
+                self.goto(join);
+                self.current = starts[1];
+                let value = else_block.as_ref().map(|b| self.scoped(b)).unwrap_or_default();
+                self.assign(result.clone(), value, *span);
+                self.goto(join);
+                self.current = join;
+                Value { loans: Vec::new(), copies: vec![result] }
+            }
+            Expr::Match(scrutinee, arms, span) => {
+                let value = self.expr(scrutinee);
+                let value = self.hold(value, *span);
+                let result = self.temp();
+                let (starts, join) = self.branch(arms.len());
+                for (arm, start) in arms.iter().zip(starts) {
+                    self.current = start;
+                    self.enter();
+                    let mut names = Vec::new();
+                    pattern_bindings(&arm.pattern, &mut names);
+                    for name in names {
+                        let var = self.declare(name, None);
+                        let copies = value.copies.clone();
+                        self.assign(var, Value { loans: Vec::new(), copies }, *span);
+                    }
+                    if let Some(guard) = &arm.guard {
+                        self.expr(guard);
+                    }
+                    let body = self.expr(&arm.body);
+                    self.assign(result.clone(), body, *span);
+                    self.leave(*span);
+                    self.goto(join);
+                }
+                self.current = join;
+                Value { loans: Vec::new(), copies: vec![result] }
+            }
+            Expr::Unsafe(block, _) | Expr::Comptime(block, _) => self.scoped(block),
+            Expr::Int(..) | Expr::Float(..) | Expr::String(..) | Expr::Bool(..) |
+            Expr::Path(..) | Expr::Lambda(..) => Value::default(),
+        }
+    }
+}
+
+/// Names a pattern binds
+fn pattern_bindings<'p>(pattern: &'p Pattern, names: &mut Vec<&'p str>) {
+    match pattern {
+        Pattern::Ident(name) => names.push(name),
+        Pattern::Tuple(items) | Pattern::Enum(_, _, items) => {
+            for item in items {
+                pattern_bindings(item, names);
+            }
+        }
+        Pattern::Struct(_, fields) => {
+            for (_, field) in fields {
+                pattern_bindings(field, names);
+            }
+        }
+        Pattern::Wildcard | Pattern::Literal(_) => {}
+    }
+}
@@ -0,0 +1,77 @@
+//! Lifetimes at function boundaries: references must not outlive what they borrow
+//!
+//! Returned references may only borrow from parameters the signature ties to the result
+
+use std::collections::HashSet;
+use super::cfg::{source_name, Action, Cfg, Place};
+use super::nll::{holders, transfer_held, value_loans};
+use super::ownership::{has_ref, Signature};
+
+/// Check a lowered function for references that escape it
+pub fn check(cfg: &Cfg, sig: &Signature, name: &str) -> Vec<String> {
+    let held_in = holders(cfg);
+    let mut errors = Vec::new();
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        let mut held = held_in[b].clone();
+        for action in &block.actions {
+            match action {
+                Action::Return { loans, copies, span } => {
+                    let mut loans: Vec<_> = value_loans(loans, copies, &held).into_iter().collect();
+                    loans.sort_unstable();
+                    // Branches may borrow the same variable separately: report it once
+                    let mut reported = HashSet::new();
+                    for loan in loans {
+                        let info = &cfg.loans[loan];
+                        let msg = if let Some(param) = info.param {
+                            escaping_param(cfg, sig, name, param, span.line)
+                        } else if !info.place.through_deref() {
+                            Some(format!("Cannot return a reference to local variable {} at line {}\n  borrow of {} created at line {}",
+                                source_name(&info.place.var), span.line, info.place, info.span.line))
+                        } else {
+                            None
+                        };
+                        if let Some(msg) = msg.filter(|_| reported.insert(&info.place.var)) {
+                            errors.push(msg);
+                        }
+                    }
+                }
+                Action::Write { place, loans, copies, span } if outlives_body(cfg, place) => {
+                    let mut loans: Vec<_> = value_loans(loans, copies, &held).into_iter().collect();
+                    loans.sort_unstable();
+                    let local = loans.into_iter()
+                        .map(|loan| &cfg.loans[loan])
+                        .find(|info| info.param.is_none() && !info.place.through_deref());
+                    if let Some(info) = local {
+                        errors.push(format!("Cannot store a reference to local {} in {} at line {}, which outlives the function\n  borrow of {} created at line {}",
+                            source_name(&info.place.var), place, span.line, info.place, info.span.line));
+                    }
+                }
+                _ => {}
+            }
+            transfer_held(action, &mut held);
+        }
+    }
+    errors
+}
+
+/// A returned borrow of parameter `param` must be one the signature names
+fn escaping_param(cfg: &Cfg, sig: &Signature, name: &str, param: usize, line: usize) -> Option<String> {
+    if !sig.ret.as_ref().is_some_and(has_ref) {
+        return None;
+    }
+    let param_name = source_name(&cfg.params[param]);
+    match sig.borrowed() {
+        None => Some(format!(
+            "Function {} returns a reference borrowed from parameter {} at line {}, but its return type does not say which parameter it borrows from (name one with a lifetime, e.g. `&'a T`)",
+            name, param_name, line)),
+        Some(borrowed) if !borrowed.contains(&param) => Some(format!(
+            "Function {} returns a reference borrowed from parameter {} at line {}, but {} does not share a lifetime with the return type",
+            name, param_name, line, param_name)),
+        Some(_) => None,
+    }
+}
+
+/// Whether a place is reached through a reference parameter, so it lives in the caller
+fn outlives_body(cfg: &Cfg, place: &Place) -> bool {
+    place.through_deref() && cfg.params.contains(&place.var)
+}
@@ -0,0 +1,613 @@
+//! Aether Borrow Checker - Memory safety analysis
+//!
+//! Ensures no use-after-free, no double-free, no data races
+
+pub mod cfg;
+pub mod lifetimes;
+pub mod moves;
+pub mod nll;
+pub mod ownership;
+
+use std::collections::{HashMap, HashSet};
+use crate::ast::*;
+use crate::typechecker::TypedModule;
+use ownership::{type_name, Blocker, Marker, Ownership, Signature};
+use crate::tooling::fmt::print_expr;
+use anyhow::{anyhow, Result};
+
+/// Builtins that read or write arbitrary addresses
+const RAW_MEMORY: &[&str] = &[
+    "__builtin_load8", "__builtin_load16", "__builtin_load32", "__builtin_load64",
+    "__builtin_store8", "__builtin_store16", "__builtin_store32", "__builtin_store64",
+    "__builtin_memcpy", "__builtin_memset", "__builtin_free", "__builtin_munmap",
+];
+
+/// Builtins that call foreign code
+const FFI: &[&str] = &[
+    "__builtin_ffi_call0", "__builtin_ffi_call1", "__builtin_ffi_call2", "__builtin_ffi_call3",
+    "__builtin_ffi_call4", "__builtin_objc_msg",
+];
+
+/// What a local binding allows
+#[derive(Debug, Clone, Copy)]
+struct Binding {
+    /// `let mut` / `mut` parameter: may be reassigned
+    mutable: bool,
+    /// Reference: `Some(true)` if its target may be written through it
+    /// (`&mut T`, `&mut self`, raw pointers), `Some(false)` for `&T` and `&self`
+    by_ref: Option<bool>,
+    param: bool,
+    /// Holds a raw pointer (`*T`)
+    raw_ptr: bool,
+    /// Line of the declaration
+    line: usize,
+}
+
+/// Borrow checker context
+pub struct BorrowChecker {
+    /// Local variables and parameters in scope
+    bindings: HashMap<String, Binding>,
+    /// Errors found
+    errors: Vec<String>,
+    /// `let mut` statics without an atomic type
+    mut_statics: HashSet<String>,
+    /// `let mut` statics with an atomic type
+    atomic_statics: HashSet<String>,
+    /// Nesting depth of `unsafe` blocks
+    unsafe_depth: usize,
+    /// Mutable statics already reported in the current statement
+    reported_statics: HashSet<String>,
+    /// Statics declared without `mut`
+    immutable_statics: HashSet<String>,
+    /// Methods per type: whether each takes `&mut self`
+    methods: HashMap<String, HashMap<String, bool>>,
+    /// Declared type of each binding in scope, when it names a type
+    types: HashMap<String, String>,
+    /// Type of the impl being checked
+    self_type: Option<String>,
+    /// Which types move, and the signatures of functions and methods
+    own: Ownership,
+    /// `unsafe func`s
+    unsafe_funcs: HashSet<String>,
+    /// Functions declared in `extern` blocks
+    foreign_funcs: HashSet<String>,
+    /// `unsafe func` methods, by type
+    unsafe_methods: HashSet<(String, String)>,
+    /// `--forbid-unsafe`: reject every unsafe block, function and impl
+    forbid_unsafe: bool,
+}
+
+impl BorrowChecker {
+    pub fn new(forbid_unsafe: bool) -> Self {
+        BorrowChecker {
+            bindings: HashMap::new(),
+            errors: Vec::new(),
+            mut_statics: HashSet::new(),
+            atomic_statics: HashSet::new(),
+            unsafe_depth: 0,
+            reported_statics: HashSet::new(),
+            immutable_statics: HashSet::new(),
+            methods: HashMap::new(),
+            types: HashMap::new(),
+            self_type: None,
+            own: Ownership::default(),
+            unsafe_funcs: HashSet::new(),
+            foreign_funcs: HashSet::new(),
+            unsafe_methods: HashSet::new(),
+            forbid_unsafe,
+        }
+    }
+    
+    fn error(&mut self, msg: String) {
+        self.errors.push(msg);
+    }
+    
+    fn define(&mut self, name: &str, mutable: bool, ty: Option<&Type>, span: Span) {
+        let raw_ptr = matches!(ty, Some(Type::RawPtr(_)));
+        let by_ref = ty.and_then(ref_access);
+        self.bindings.insert(name.to_string(), Binding { mutable, by_ref, param: false, raw_ptr, line: span.line });
+        match ty.and_then(type_name) {
+            Some(t) => self.types.insert(name.to_string(), t),
+            None => self.types.remove(name),
+        };
+    }
+    
+    fn define_param(&mut self, param: &Param) {
+        self.define(&param.name, param.mutable, Some(&param.ty), param.span);
+        let line = param.span.line;
+        let binding = if param.name == "self" {
+            if let Some(t) = self.self_type.clone() {
+                self.types.insert("self".into(), t);
+            }
+            Binding { mutable: false, by_ref: Some(param.mutable), param: true, raw_ptr: false, line }
+        } else {
+            let by_ref = ref_access(&param.ty);
+            let raw_ptr = matches!(param.ty, Type::RawPtr(_));
+            Binding { mutable: param.mutable, by_ref, param: true, raw_ptr, line }
+        };
+        self.bindings.insert(param.name.clone(), binding);
+    }
+    
+    /// Whether `obj.method(..)` takes `&mut self`, when the receiver's type is known
+    fn takes_mut_self(&self, obj: &Expr, method: &str) -> bool {
+        let Expr::Ident(name, _) = obj else { return false };
+        self.types.get(name)
+            .and_then(|t| self.methods.get(t))
+            .and_then(|m| m.get(method))
+            .copied()
+            .unwrap_or(false)
+    }
+    
+    /// A `let mut` static not shadowed by a local
+    fn is_mut_static(&self, name: &str) -> bool {
+        !self.bindings.contains_key(name) && self.mut_statics.contains(name)
+    }
+    
+    /// Only `X = X op v` is one atomic instruction; any other update of an
+    /// atomic static that reads it can lose writes from other threads
+    fn check_atomic_update(&mut self, name: &str, value: &Expr, span: Span) {
+        if self.bindings.contains_key(name) || !self.atomic_statics.contains(name) {
+            return;
+        }
+        let mut read = Vec::new();
+        free_idents(atomic_update(name, value).map_or(value, |(_, v)| v), &mut read);
+        if read.iter().any(|n| n == name) {
+            self.error(format!("Update of atomic static {} at line {} is a separate load and store (write it as `{} = {} op v` with op one of + - & | ^, which is one atomic instruction)",
+                name, span.line, name, name));
+        }
+    }
+    
+    /// Check that `place` may be written. `action` describes the write for
+    /// diagnostics; `through` is set when the write goes into the value a
+    /// reference points at (`&mut place`, calling a `&mut self` method).
+    fn check_mutable(&mut self, place: &Expr, action: &str, through: bool, span: Span) {
+        // Walk field, index and deref projections down to the variable
+        let mut root = place;
+        let mut projected = false;
+        let mut deref = false;
+        loop {
+            root = match root {
+                Expr::Field(inner, _, _) | Expr::Index(inner, _, _) => {
+                    projected = true;
+                    inner
+                }
+                Expr::Unary(UnOp::Deref, inner, _) => {
+                    deref = true;
+                    inner
+                }
+                _ => break,
+            };
+        }
+        // Writes through call results are not tracked
+        let Expr::Ident(name, _) = root else { return };
+        let through = through || projected || deref;
+
+        match self.bindings.get(name).copied() {
+            Some(Binding { by_ref: Some(false), .. }) if through && name == "self" => {
+                self.error(format!("Cannot {} through `&self` at line {} (take `&mut self` instead)", action, span.line));
+            }
+            Some(Binding { by_ref: Some(false), line, .. }) if through => {
+                self.error(format!("Cannot {} through shared reference {} at line {} (make it a `&mut` reference at line {})",
+                    action, name, span.line, line));
+            }
+            Some(Binding { by_ref: Some(true), .. }) if through => {}
+            // `*p` of an untracked pointer value
+            Some(_) if deref => {}
+            Some(Binding { mutable: false, param, line, .. }) => {
+                let hint = if param {
+                    format!("declare the parameter as `mut {}` at line {}", name, line)
+                } else {
+                    format!("declare it with `let mut {}` at line {}", name, line)
+                };
+                self.error(format!("Cannot {}, as {} is not declared mutable at line {} ({})", action, name, span.line, hint));
+            }
+            Some(_) => {}
+            None if self.immutable_statics.contains(name) => {
+                self.error(format!("Cannot {}, as static {} is not declared mutable at line {} (declare it with `let mut {}`)",
+                    action, name, span.line, name));
+            }
+            None => {}
+        }
+    }
+    
+    /// Mutable statics are shared by every thread, so touching one is unsafe;
+    /// each static is reported once per statement
+    fn check_static(&mut self, name: &str, span: Span) {
+        if self.unsafe_depth == 0 && self.is_mut_static(name) && self.reported_statics.insert(name.to_string()) {
+            self.error(format!("Use of mutable static {} requires an unsafe block at line {} (or give it an atomic type such as AtomicInt)",
+                name, span.line));
+        }
+    }
+    
+    fn use_var(&mut self, name: &str, span: Span) {
+        self.check_static(name, span);
+    }
+    
+    /// Report an operation that needs an unsafe block when outside one
+    fn require_unsafe(&mut self, what: &str, span: Span) {
+        if self.unsafe_depth == 0 {
+            self.error(format!("{} requires an unsafe block at line {}", what, span.line));
+        }
+    }
+    
+    /// Report unsafe code when `--forbid-unsafe` is set
+    fn check_forbidden(&mut self, what: &str, span: Span) {
+        if self.forbid_unsafe {
+            self.error(format!("{} is forbidden by --forbid-unsafe at line {}", what, span.line));
+        }
+    }
+    
+    /// Whether an expression is a raw pointer: a local or parameter of type
+    /// `*T`, or a call to a function returning one
+    fn is_raw_ptr(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Ident(name, _) => self.bindings.get(name).is_some_and(|b| b.raw_ptr),
+            Expr::Call(callee, _, _) => match callee.as_ref() {
+                Expr::Ident(name, _) => self.own.func(name).is_some_and(|sig| matches!(sig.ret, Some(Type::RawPtr(_)))),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+    
+    /// Raw memory builtins, foreign functions and `unsafe func`s
+    fn check_call(&mut self, callee: &Expr, span: Span) {
+        let Expr::Ident(name, _) = callee else { return };
+        if self.bindings.contains_key(name) {
+            return;
+        }
+        if RAW_MEMORY.contains(&name.as_str()) {
+            self.require_unsafe(&format!("Raw memory access with {}", name), span);
+        } else if FFI.contains(&name.as_str()) {
+            self.require_unsafe(&format!("Foreign call with {}", name), span);
+        } else if self.foreign_funcs.contains(name) {
+            self.require_unsafe(&format!("Call to foreign function {}", name), span);
+        } else if self.unsafe_funcs.contains(name) {
+            self.require_unsafe(&format!("Call to unsafe function {}", name), span);
+        }
+    }
+    
+    fn check_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Ident(name, span) => {
+                self.use_var(name, *span);
+            }
+            Expr::Binary(_, left, right, _) => {
+                self.check_expr(left);
+                self.check_expr(right);
+            }
+            Expr::Unary(op, operand, span) => {
+                match op {
+                    UnOp::RefMut => {
+                        let action = format!("borrow {} as mutable", print_expr(operand));
+                        self.check_mutable(operand, &action, true, *span);
+                        self.check_expr(operand);
+                    }
+                    UnOp::Deref => {
+                        if self.is_raw_ptr(operand) {
+                            self.require_unsafe(&format!("Dereference of raw pointer {}", print_expr(operand)), *span);
+                        }
+                        self.check_expr(operand);
+                    }
+                    _ => {
+                        self.check_expr(operand);
+                    }
+                }
+            }
+            Expr::Call(callee, args, span) => {
+                self.check_call(callee, *span);
+                self.check_expr(callee);
+                for arg in args {
+                    self.check_expr(arg);
+                }
+            }
+            Expr::Field(obj, _, _) => {
+                self.check_expr(obj);
+            }
+            Expr::Index(arr, idx, _) => {
+                self.check_expr(arr);
+                self.check_expr(idx);
+            }
+            Expr::Array(elems, _) => {
+                for elem in elems {
+                    self.check_expr(elem);
+                }
+            }
+            Expr::If(cond, then_block, else_block, _) => {
+                self.check_expr(cond);
+                self.check_block(then_block);
+                if let Some(eb) = else_block {
+                    self.check_block(eb);
+                }
+            }
+            Expr::Unsafe(block, span) => {
+                self.check_forbidden("Unsafe block", *span);
+                self.unsafe_depth += 1;
+                self.check_block(block);
+                self.unsafe_depth -= 1;
+            }
+            Expr::Spawn(func, args, span) => {
+                self.check_expr(func);
+                let params = match func.as_ref() {
+                    Expr::Ident(name, _) => self.own.func(name).map(|sig| sig.params.clone()),
+                    _ => None,
+                };
+                for (i, arg) in args.iter().enumerate() {
+                    self.check_send(arg, params.as_ref().and_then(|p| p.get(i)), *span);
+                }
+            }
+            Expr::MethodCall(obj, method, args, span) => {
+                if self.takes_mut_self(obj, method) {
+                    let action = format!("call {}, which takes `&mut self`, on {}", method, print_expr(obj));
+                    self.check_mutable(obj, &action, true, *span);
+                }
+                let receiver = match obj.as_ref() {
+                    Expr::Ident(name, _) => self.types.get(name).cloned(),
+                    _ => None,
+                };
+                if receiver.is_some_and(|t| self.unsafe_methods.contains(&(t, method.clone()))) {
+                    self.require_unsafe(&format!("Call to unsafe method {}", method), *span);
+                }
+                self.check_expr(obj);
+                for arg in args {
+                    self.check_expr(arg);
+                }
+            }
+            _ => {}
+        }
+    }
+    
+    /// An argument of `spawn` moves to another thread: it must be `Send`, and
+    /// a reference must point at memory every thread can safely reach
+    fn check_send(&mut self, arg: &Expr, param: Option<&Type>, span: Span) {
+        if let Expr::Unary(UnOp::Ref | UnOp::RefMut, place, _) = arg {
+            let mut root = place.as_ref();
+            while let Expr::Field(inner, _, _) | Expr::Index(inner, _, _) = root {
+                root = inner;
+            }
+            if let Expr::Ident(name, _) = root {
+                if self.bindings.contains_key(name) {
+                    self.error(format!("Cannot send a reference to local variable {} to a spawned thread at line {} (the thread may outlive it; pass the value instead)",
+                        name, span.line));
+                    return;
+                }
+                if self.is_mut_static(name) {
+                    if self.unsafe_depth == 0 {
+                        self.error(format!("Cannot share mutable static {} with a spawned thread at line {}: it is not atomic (give it an atomic type such as AtomicInt)",
+                            name, span.line));
+                    }
+                    return;
+                }
+                // Immutable and atomic statics live for the whole program
+                self.check_expr(arg);
+                return;
+            }
+        }
+        self.check_expr(arg);
+        let Some(ty) = param.filter(|_| self.unsafe_depth == 0) else { return };
+        let at = |path: &str| if path.is_empty() { String::new() } else { format!(" in field {}", path) };
+        match self.own.blocker(ty, Marker::Send) {
+            Some(Blocker::RawPtr(path)) => {
+                self.error(format!("Cannot send {} to a spawned thread at line {}: {} is not Send, as it holds a raw pointer{} (spawn inside an unsafe block, or declare `unsafe impl Send` for the type)",
+                    print_expr(arg), span.line, ty, at(&path)));
+            }
+            Some(Blocker::Ref(path)) => {
+                self.error(format!("Cannot send {} to a spawned thread at line {}: {} is not Send, as it holds a reference{} that may outlive what it points at (store the value itself instead)",
+                    print_expr(arg), span.line, ty, at(&path)));
+            }
+            None => {}
+        }
+    }
+    
+    fn check_stmt(&mut self, stmt: &Stmt) {
+        self.reported_statics.clear();
+        match stmt {
+            Stmt::Let { name, ty, init, mutable, span } => {
+                if let Some(init) = init {
+                    self.check_expr(init);
+                }
+                // `let x: T` without a value is initialized by a later assignment
+                self.define(name, *mutable || init.is_none(), ty.as_ref(), *span);
+                if ty.is_none() {
+                    let by_ref = match init {
+                        Some(Expr::Unary(UnOp::Ref, _, _)) => Some(false),
+                        Some(Expr::Unary(UnOp::RefMut, _, _)) => Some(true),
+                        _ => None,
+                    };
+                    if let Some(binding) = self.bindings.get_mut(name) {
+                        binding.by_ref = by_ref;
+                    }
+                }
+                if ty.is_none() && init.as_ref().is_some_and(|e| self.is_raw_ptr(e)) {
+                    if let Some(binding) = self.bindings.get_mut(name) {
+                        binding.raw_ptr = true;
+                    }
+                }
+                // A struct literal gives the receiver type for method checks
+                if let (None, Some(Expr::Struct(type_name, _, _))) = (ty, init) {
+                    self.types.insert(name.clone(), type_name.clone());
+                }
+            }
+            Stmt::Assign(target, value, span) => {
+                self.check_expr(value);
+                let action = format!("assign to {}", print_expr(target));
+                self.check_mutable(target, &action, false, *span);
+                match target {
+                    Expr::Ident(name, _) => {
+                        self.use_var(name, *span);
+                        self.check_atomic_update(name, value, *span);
+                    }
+                    other => self.check_expr(other),
+                }
+            }
+            Stmt::Expr(expr, _) => {
+                self.check_expr(expr);
+            }
+            Stmt::If(cond, then_block, else_block, _) => {
+                self.check_expr(cond);
+                self.check_block(&then_block);
+                if let Some(eb) = else_block {
+                    self.check_block(eb);
+                }
+            }
+            Stmt::While(cond, body, _) => {
+                self.check_expr(cond);
+                self.check_block(&body);
+            }
+            Stmt::For(var, iter, body, span) => {
+                self.check_expr(iter);
+                self.define(var, false, None, *span);
+                self.check_block(body);
+            }
+            Stmt::Return(val, _) => {
+                if let Some(v) = val {
+                    self.check_expr(v);
+                }
+            }
+            Stmt::Block(block, _) => {
+                self.check_block(block);
+            }
+            _ => {}
+        }
+    }
+    
+    fn check_block(&mut self, block: &Block) {
+        let saved_bindings = self.bindings.clone();
+        let saved_types = self.types.clone();
+        for stmt in &block.stmts {
+            self.check_stmt(stmt);
+        }
+        // Restore bindings after block (variables go out of scope)
+        self.bindings = saved_bindings;
+        self.types = saved_types;
+    }
+    
+    fn check_decl(&mut self, decl: &Decl) {
+        match decl {
+            Decl::Func { name, params, ret, body, is_unsafe, span, .. } => {
+                self.bindings.clear();
+                self.types.clear();
+                
+                for param in params {
+                    self.define_param(param);
+                }
+                
+                // The body of an `unsafe func` is an unsafe context
+                if *is_unsafe {
+                    self.check_forbidden(&format!("Unsafe function {}", name), *span);
+                }
+                self.unsafe_depth += usize::from(*is_unsafe);
+                self.check_block(body);
+                self.unsafe_depth -= usize::from(*is_unsafe);
+                // Moves and conflicting borrows are found on the lowered body
+                let cfg = cfg::lower(params, body, &self.own);
+                self.errors.extend(moves::check(&cfg));
+                self.errors.extend(nll::check(&cfg));
+                self.errors.extend(lifetimes::check(&cfg, &Signature::new(params, ret), name));
+            }
+            Decl::Impl { trait_name, type_name: name, methods, is_unsafe, span, .. } => {
+                if *is_unsafe {
+                    self.check_forbidden(&format!("Unsafe impl for {}", name), *span);
+                }
+                self.check_impl_safety(trait_name.as_deref(), name, methods, *is_unsafe, *span);
+                self.check_methods(name, methods);
+            }
+            Decl::Trait { name, methods, .. } => {
+                self.check_methods(name, methods);
+            }
+            Decl::Static { value: Some(value), .. } => {
+                self.bindings.clear();
+                self.check_expr(value);
+            }
+            _ => {}
+        }
+    }
+    
+    fn check_methods(&mut self, self_type: &str, methods: &[Decl]) {
+        self.self_type = Some(self_type.to_string());
+        for method in methods {
+            self.check_decl(method);
+        }
+        self.self_type = None;
+    }
+    
+    /// `Send` and `Sync` cannot be checked for a type that opts out of the
+    /// structural rules, so implementing them takes `unsafe impl`
+    fn check_impl_safety(&mut self, trait_name: Option<&str>, type_name: &str, methods: &[Decl], is_unsafe: bool, span: Span) {
+        match trait_name.filter(|t| Marker::from_name(t).is_some()) {
+            Some(marker) if !is_unsafe => {
+                self.error(format!("Implementing {} for {} requires `unsafe impl` at line {}", marker, type_name, span.line));
+            }
+            Some(marker) if !methods.is_empty() => {
+                self.error(format!("Marker trait {} has no methods, but its impl for {} defines some at line {}", marker, type_name, span.line));
+            }
+            Some(_) => {}
+            None if is_unsafe => {
+                self.error(format!("`unsafe impl` is only allowed for the marker traits Send and Sync at line {}", span.line));
+            }
+            None => {}
+        }
+    }
+    
+    pub fn check_module(&mut self, module: &TypedModule) -> Result<()> {
+        self.own = Ownership::from_module(module);
+        for typed_decl in &module.decls {
+            match &typed_decl.decl {
+                Decl::Static { name, mutable: true, .. } if typed_decl.ty.atomic_value().is_none() => {
+                    self.mut_statics.insert(name.clone());
+                }
+                Decl::Static { name, mutable: true, .. } => {
+                    self.atomic_statics.insert(name.clone());
+                }
+                Decl::Static { name, mutable: false, .. } => {
+                    self.immutable_statics.insert(name.clone());
+                }
+                Decl::Func { name, is_unsafe: true, .. } => {
+                    self.unsafe_funcs.insert(name.clone());
+                }
+                Decl::Extern { funcs, .. } => {
+                    self.foreign_funcs.extend(funcs.iter().map(|f| f.name.clone()));
+                }
+                Decl::Impl { type_name: ty, methods, .. } | Decl::Trait { name: ty, methods, .. } => {
+                    let table = self.methods.entry(ty.clone()).or_default();
+                    for method in methods {
+                        if let Decl::Func { name, params, is_unsafe, .. } = method {
+                            if let Some(receiver) = params.first().filter(|p| p.name == "self") {
+                                table.insert(name.clone(), receiver.mutable);
+                            }
+                            if *is_unsafe {
+                                self.unsafe_methods.insert((ty.clone(), name.clone()));
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        for typed_decl in &module.decls {
+            self.check_decl(&typed_decl.decl);
+        }
+        
+        if !self.errors.is_empty() {
+            return Err(anyhow!("Borrow check errors:\n{}", self.errors.join("\n")));
+        }
+        
+        Ok(())
+    }
+}
+
+/// Whether a binding of this type may write what it points at: `Some(false)`
+/// for `&T`, `Some(true)` for `&mut T` and raw pointers
+fn ref_access(ty: &Type) -> Option<bool> {
+    match ty {
+        Type::Ptr(_, mutable) | Type::Ref(_, _, mutable) => Some(*mutable),
+        Type::RawPtr(_) => Some(true),
+        _ => None,
+    }
+}
+
+/// `forbid_unsafe` rejects all unsafe code, for crates that should need none
+pub fn check(module: &TypedModule, forbid_unsafe: bool) -> Result<()> {
+    let mut checker = BorrowChecker::new(forbid_unsafe);
+    checker.check_module(module)
+}
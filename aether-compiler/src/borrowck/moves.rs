@@ -0,0 +1,92 @@
+//! Use-after-move detection
+//!
+//! A forward dataflow over the CFG tracks the moves that may have happened to each local
+
+use std::collections::{BTreeSet, HashMap};
+use super::cfg::{source_name, Action, Cfg};
+
+/// Positions (line, column) of the moves that may have emptied each local
+type Moved = HashMap<String, BTreeSet<(usize, usize)>>;
+
+/// Check a lowered function for locals used after their value was moved
+pub fn check(cfg: &Cfg) -> Vec<String> {
+    let moved_in = moved(cfg);
+    let mut errors = Vec::new();
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        let mut moved = moved_in[b].clone();
+        for action in &block.actions {
+            if let Some(msg) = use_after_move(cfg, action, &moved) {
+                errors.push(msg);
+            }
+            transfer(action, &mut moved);
+        }
+    }
+    errors
+}
+
+fn use_after_move(cfg: &Cfg, action: &Action, moved: &Moved) -> Option<String> {
+    let (var, what) = match action {
+        Action::Read(place, _) => (&place.var, "Use of moved value"),
+        Action::Move(var, _) => (var, "Value already moved"),
+        Action::Write { place, .. } => (&place.var, "Assignment to part of moved value"),
+        Action::Borrow(loan) => (&cfg.loans[*loan].place.var, "Cannot borrow moved value"),
+        Action::Assign { .. } | Action::Return { .. } | Action::Dead(..) => return None,
+    };
+    let moves = moved.get(var)?;
+    let span = cfg.span(action);
+    let mut msg = format!("{}: {} at line {}", what, source_name(var), span.line);
+    for &(line, col) in moves {
+        msg.push_str(&format!("\n  value moved at line {}", line));
+        // A move at or after the use can only reach it around a loop
+        if (line, col) >= (span.line, span.col) {
+            msg.push_str(", in the previous iteration of the loop");
+        }
+    }
+    Some(msg)
+}
+
+fn transfer(action: &Action, moved: &mut Moved) {
+    match action {
+        Action::Move(var, span) => {
+            moved.insert(var.clone(), BTreeSet::from([(span.line, span.col)]));
+        }
+        Action::Assign { var, .. } | Action::Dead(var, _) => {
+            moved.remove(var);
+        }
+        _ => {}
+    }
+}
+
+/// Forward dataflow: moves that may have happened on entry to each block
+fn moved(cfg: &Cfg) -> Vec<Moved> {
+    let n = cfg.blocks.len();
+    let mut preds = vec![Vec::new(); n];
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        for &succ in &block.succs {
+            preds[succ].push(b);
+        }
+    }
+    let mut moved_in = vec![Moved::new(); n];
+    let mut moved_out = vec![Moved::new(); n];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in 0..n {
+            let mut moved = Moved::new();
+            for &p in &preds[b] {
+                for (var, moves) in &moved_out[p] {
+                    moved.entry(var.clone()).or_default().extend(moves);
+                }
+            }
+            moved_in[b] = moved.clone();
+            for action in &cfg.blocks[b].actions {
+                transfer(action, &mut moved);
+            }
+            if moved != moved_out[b] {
+                moved_out[b] = moved;
+                changed = true;
+            }
+        }
+    }
+    moved_in
+}
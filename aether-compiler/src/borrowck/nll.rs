@@ -0,0 +1,223 @@
+//! Non-lexical lifetimes: a loan lasts until the last use of a reference holding it
+//!
+//! Liveness of locals and the loans each local may hold are computed over the CFG
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use super::cfg::{source_name, Action, Cfg, Loan, LoanId};
+
+/// Locals that are read later
+type Live = HashSet<String>;
+/// Loans each local may hold
+pub(super) type Holders = HashMap<String, HashSet<LoanId>>;
+
+/// Check a lowered function for accesses that conflict with a live loan
+pub fn check(cfg: &Cfg) -> Vec<String> {
+    let live_out = liveness(cfg);
+    let held_in = holders(cfg);
+    let mut errors = Vec::new();
+
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        // Locals live after each action
+        let mut live = live_out[b].clone();
+        let mut live_after = vec![Live::new(); block.actions.len()];
+        for (i, action) in block.actions.iter().enumerate().rev() {
+            live_after[i] = live.clone();
+            transfer_live(cfg, action, &mut live);
+        }
+
+        let mut held = held_in[b].clone();
+        for (i, action) in block.actions.iter().enumerate() {
+            transfer_held(action, &mut held);
+            // Borrows made by the caller cannot conflict with the body
+            let mut live_loans: Vec<LoanId> = live_after[i].iter()
+                .filter_map(|var| held.get(var))
+                .flatten()
+                .copied()
+                .filter(|&loan| cfg.loans[loan].param.is_none())
+                .collect();
+            live_loans.sort_unstable();
+            live_loans.dedup();
+            let conflict = live_loans.into_iter()
+                .find_map(|loan| conflict(cfg, action, &cfg.loans[loan]).map(|msg| (loan, msg)));
+            if let Some((loan, msg)) = conflict {
+                errors.push(explain(cfg, &held_in, msg, loan, (b, i), &held));
+            }
+        }
+    }
+    errors
+}
+
+/// Error for an action that touches the place of a live loan
+fn conflict(cfg: &Cfg, action: &Action, loan: &Loan) -> Option<String> {
+    let held = &loan.place;
+    match action {
+        Action::Read(place, span) if loan.mutable && place.overlaps(held) => {
+            Some(format!("Cannot use {} because it is mutably borrowed at line {}", place, span.line))
+        }
+        Action::Write { place, span, .. } if place.overlaps(held) => {
+            Some(format!("Cannot assign to {} because it is borrowed at line {}", place, span.line))
+        }
+        // Overwriting or dropping a reference leaves what it points at borrowed
+        Action::Assign { var, span, .. } if *var == held.var && !held.through_deref() => {
+            Some(format!("Cannot assign to {} because it is borrowed at line {}", source_name(var), span.line))
+        }
+        Action::Move(var, span) if *var == held.var => {
+            Some(format!("Cannot move out of {} because it is borrowed at line {}", source_name(var), span.line))
+        }
+        Action::Borrow(new) => {
+            let new = &cfg.loans[*new];
+            if !new.place.overlaps(held) {
+                return None;
+            }
+            let (place, line) = (new.place.to_string(), new.span.line);
+            let other = if place == held.to_string() { "it".to_string() } else { held.to_string() };
+            match (new.mutable, loan.mutable) {
+                (true, true) => Some(format!("Cannot borrow {} as mutable more than once at a time at line {}", place, line)),
+                (true, false) => Some(format!("Cannot borrow {} as mutable because {} is also borrowed as immutable at line {}", place, other, line)),
+                (false, true) => Some(format!("Cannot borrow {} as immutable because {} is also borrowed as mutable at line {}", place, other, line)),
+                (false, false) => None,
+            }
+        }
+        Action::Dead(var, span) if *var == held.var && !held.through_deref() => {
+            Some(format!("{} does not live long enough: it goes out of scope at the end of the block starting at line {} while still borrowed",
+                source_name(var), span.line))
+        }
+        _ => None,
+    }
+}
+
+/// Add where the loan was created and where it is used after the conflict
+fn explain(cfg: &Cfg, held_in: &[Holders], msg: String, loan: LoanId, at: (usize, usize), held: &Holders) -> String {
+    let info = &cfg.loans[loan];
+    let kind = if info.mutable { "mutable borrow" } else { "borrow" };
+    let mut msg = format!("{}\n  {} of {} created at line {}", msg, kind, info.place, info.span.line);
+    if let Some(line) = later_use(cfg, held_in, loan, at, held) {
+        msg.push_str(&format!("\n  borrow later used at line {}", line));
+    }
+    msg
+}
+
+/// First line after `at` that reads a local holding `loan`
+fn later_use(cfg: &Cfg, held_in: &[Holders], loan: LoanId, at: (usize, usize), held: &Holders) -> Option<usize> {
+    let mut queue = VecDeque::from([(at.0, at.1 + 1, held.clone())]);
+    let mut seen = HashSet::new();
+    while let Some((b, start, mut held)) = queue.pop_front() {
+        for action in &cfg.blocks[b].actions[start..] {
+            let uses = cfg.uses(action);
+            if uses.iter().any(|var| held.get(*var).is_some_and(|loans| loans.contains(&loan))) {
+                return Some(cfg.span(action).line);
+            }
+            transfer_held(action, &mut held);
+        }
+        for &succ in &cfg.blocks[b].succs {
+            if seen.insert(succ) {
+                queue.push_back((succ, 0, held_in[succ].clone()));
+            }
+        }
+    }
+    None
+}
+
+fn transfer_live(cfg: &Cfg, action: &Action, live: &mut Live) {
+    if let Action::Assign { var, .. } = action {
+        live.remove(var);
+    }
+    for var in cfg.uses(action) {
+        live.insert(var.to_string());
+    }
+}
+
+/// Backward dataflow: locals live on exit from each block
+fn liveness(cfg: &Cfg) -> Vec<Live> {
+    let n = cfg.blocks.len();
+    let mut live_in = vec![Live::new(); n];
+    let mut live_out = vec![Live::new(); n];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in (0..n).rev() {
+            let out: Live = cfg.blocks[b].succs.iter()
+                .flat_map(|&s| live_in[s].iter().cloned())
+                .collect();
+            let mut live = out.clone();
+            for action in cfg.blocks[b].actions.iter().rev() {
+                transfer_live(cfg, action, &mut live);
+            }
+            live_out[b] = out;
+            if live != live_in[b] {
+                live_in[b] = live;
+                changed = true;
+            }
+        }
+    }
+    live_out
+}
+
+/// Loans of a value made of `loans` plus the loans held by `copies`
+pub(super) fn value_loans(loans: &[LoanId], copies: &[String], held: &Holders) -> HashSet<LoanId> {
+    let mut set: HashSet<LoanId> = loans.iter().copied().collect();
+    for copy in copies {
+        if let Some(loans) = held.get(copy) {
+            set.extend(loans);
+        }
+    }
+    set
+}
+
+pub(super) fn transfer_held(action: &Action, held: &mut Holders) {
+    match action {
+        Action::Assign { var, loans, copies, .. } => {
+            let set = value_loans(loans, copies, held);
+            if set.is_empty() {
+                held.remove(var);
+            } else {
+                held.insert(var.clone(), set);
+            }
+        }
+        // Storing into a local's field adds to what it holds
+        Action::Write { place, loans, copies, .. } if !place.through_deref() => {
+            let set = value_loans(loans, copies, held);
+            if !set.is_empty() {
+                held.entry(place.var.clone()).or_default().extend(set);
+            }
+        }
+        Action::Dead(var, _) => {
+            held.remove(var);
+        }
+        _ => {}
+    }
+}
+
+/// Forward dataflow: loans each local may hold on entry to each block
+pub(super) fn holders(cfg: &Cfg) -> Vec<Holders> {
+    let n = cfg.blocks.len();
+    let mut preds = vec![Vec::new(); n];
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        for &succ in &block.succs {
+            preds[succ].push(b);
+        }
+    }
+    let mut held_in = vec![Holders::new(); n];
+    let mut held_out = vec![Holders::new(); n];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in 0..n {
+            let mut held = Holders::new();
+            for &p in &preds[b] {
+                for (var, loans) in &held_out[p] {
+                    held.entry(var.clone()).or_default().extend(loans);
+                }
+            }
+            held_in[b] = held.clone();
+            for action in &cfg.blocks[b].actions {
+                transfer_held(action, &mut held);
+            }
+            if held != held_out[b] {
+                held_out[b] = held;
+                changed = true;
+            }
+        }
+    }
+    held_in
+}
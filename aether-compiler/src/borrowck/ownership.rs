@@ -0,0 +1,389 @@
+//! Ownership of types: which values are `Copy` and which move, and which
+//! may cross threads (`Send`) or be shared between them (`Sync`)
+//!
+//! Built from the declarations in a `TypedModule`
+
+use std::collections::{HashMap, HashSet};
+use crate::ast::*;
+use crate::typechecker::TypedModule;
+
+/// Library types that own heap memory
+const OWNED: &[&str] = &["String", "Vec", "HashMap", "Map", "Set", "Box", "List"];
+
+/// Built-in marker traits for thread safety
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Marker {
+    /// Values may be moved to another thread
+    Send,
+    /// References may be shared between threads
+    Sync,
+}
+
+impl Marker {
+    pub fn from_name(name: &str) -> Option<Marker> {
+        match name {
+            "Send" => Some(Marker::Send),
+            "Sync" => Some(Marker::Sync),
+            _ => None,
+        }
+    }
+}
+
+/// What keeps a type from implementing a marker trait, with the path of the
+/// field that holds it (empty for the type itself)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Blocker {
+    /// A raw pointer `*T`
+    RawPtr(String),
+    /// A reference with an elided lifetime, which may point into a stack frame
+    Ref(String),
+}
+
+/// Parameter types and return type of a function
+#[derive(Debug, Clone)]
+pub struct Signature {
+    /// Parameter types, `self` first for methods
+    pub params: Vec<Type>,
+    pub ret: Option<Type>,
+    pub has_self: bool,
+    /// Takes `&mut self`
+    pub self_mut: bool,
+}
+
+impl Signature {
+    pub fn new(params: &[Param], ret: &Option<Type>) -> Self {
+        Signature {
+            params: params.iter().map(|p| p.ty.clone()).collect(),
+            ret: ret.clone(),
+            has_self: params.first().is_some_and(|p| p.name == "self"),
+            self_mut: params.first().is_some_and(|p| p.name == "self" && p.mutable),
+        }
+    }
+
+    /// Parameters after `self`
+    pub fn args(&self) -> &[Type] {
+        &self.params[usize::from(self.has_self)..]
+    }
+
+    /// Parameters whose borrows the result may hold: those sharing a lifetime
+    /// with the result or, when it is elided, `self` or the only reference
+    /// parameter. `None` when an elided lifetime has no single source.
+    pub fn borrowed(&self) -> Option<Vec<usize>> {
+        let mut named = Vec::new();
+        let mut elided = false;
+        if let Some(ret) = &self.ret {
+            lifetimes(ret, &mut named, &mut elided);
+        }
+        let mut borrowed: Vec<usize> = (0..self.params.len()).filter(|&i| {
+            let mut own = Vec::new();
+            lifetimes(&self.params[i], &mut own, &mut false);
+            own.iter().any(|l| named.contains(l))
+        }).collect();
+        if elided {
+            let refs: Vec<usize> = (0..self.params.len()).filter(|&i| has_ref(&self.params[i])).collect();
+            match refs.as_slice() {
+                _ if self.has_self && refs.first() == Some(&0) => borrowed.push(0),
+                [only] => borrowed.push(*only),
+                [] => {}
+                _ => return None,
+            }
+        }
+        Some(borrowed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Ownership {
+    structs: HashMap<String, Vec<(String, Type)>>,
+    /// Payload types of every variant
+    enums: HashMap<String, Vec<Type>>,
+    aliases: HashMap<String, Type>,
+    funcs: HashMap<String, Signature>,
+    methods: HashMap<String, HashMap<String, Signature>>,
+    /// Types with an `unsafe impl Send` or `unsafe impl Sync`
+    markers: HashSet<(Marker, String)>,
+    /// Types with an `impl Drop`
+    drops: HashSet<String>,
+}
+
+impl Ownership {
+    pub fn from_module(module: &TypedModule) -> Self {
+        let mut own = Ownership::default();
+        for typed_decl in &module.decls {
+            own.add(&typed_decl.decl);
+        }
+        own
+    }
+
+    /// Record the types, functions and impls a declaration introduces
+    pub fn add(&mut self, decl: &Decl) {
+        match decl {
+            Decl::Struct { name, fields, .. } => {
+                self.structs.insert(name.clone(), fields.iter().map(|f| (f.name.clone(), f.ty.clone())).collect());
+            }
+            Decl::Enum { name, variants, .. } => {
+                self.enums.insert(name.clone(), variants.iter().flat_map(|v| v.fields.clone()).collect());
+            }
+            Decl::TypeAlias { name, ty, .. } => {
+                self.aliases.insert(name.clone(), ty.clone());
+            }
+            Decl::Func { name, params, ret, .. } => {
+                self.funcs.insert(name.clone(), Signature::new(params, ret));
+            }
+            Decl::Impl { trait_name, type_name, methods, is_unsafe, .. } => {
+                if let Some(marker) = trait_name.as_deref().and_then(Marker::from_name).filter(|_| *is_unsafe) {
+                    self.markers.insert((marker, type_name.clone()));
+                }
+                if trait_name.as_deref() == Some("Drop") {
+                    self.drops.insert(type_name.clone());
+                }
+                let table = self.methods.entry(type_name.clone()).or_default();
+                for method in methods {
+                    if let Decl::Func { name, params, ret, .. } = method {
+                        table.insert(name.clone(), Signature::new(params, ret));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether values of `ty` are copied rather than moved. Unknown types
+    /// (generic parameters, foreign types) count as `Copy`; types with a
+    /// destructor never do.
+    pub fn is_copy(&self, ty: &Type) -> bool {
+        self.copy(ty, &mut HashSet::new())
+    }
+
+    fn copy(&self, ty: &Type, seen: &mut HashSet<String>) -> bool {
+        match ty {
+            Type::Named(name) | Type::Generic(name, _) if OWNED.contains(&name.as_str()) || self.drops.contains(name) => false,
+            Type::Named(name) | Type::Generic(name, _) if !seen.insert(name.clone()) => true,
+            Type::Named(name) => {
+                if let Some(fields) = self.structs.get(name) {
+                    fields.iter().all(|(_, f)| self.copy(f, seen))
+                } else if let Some(payloads) = self.enums.get(name) {
+                    payloads.iter().all(|p| self.copy(p, seen))
+                } else if let Some(alias) = self.aliases.get(name) {
+                    self.copy(alias, seen)
+                } else {
+                    true
+                }
+            }
+            Type::Generic(name, args) => {
+                let fields = self.structs.get(name).into_iter().flatten().map(|(_, f)| f);
+                let payloads = self.enums.get(name).into_iter().flatten();
+                fields.chain(payloads).chain(args).all(|t| self.copy(t, seen))
+            }
+            Type::Array(elem, _) | Type::ConstArray(elem, _) => self.copy(elem, seen),
+            Type::Ptr(..) | Type::RawPtr(_) | Type::Ref(..) | Type::Func(..) | Type::Infer | Type::Unit => true,
+        }
+    }
+
+    /// Whether `ty` implements a marker trait, derived from its fields unless
+    /// declared with `unsafe impl`. Raw pointers and `&T` are neither `Send`
+    /// nor `Sync`; `&'a T` is both when `T` is `Sync`; atomics are both.
+    pub fn implements(&self, ty: &Type, marker: Marker) -> bool {
+        self.blocker(ty, marker).is_none()
+    }
+
+    /// The raw pointer or reference that keeps `ty` from implementing `marker`
+    pub fn blocker(&self, ty: &Type, marker: Marker) -> Option<Blocker> {
+        self.marker(ty, marker, &mut HashSet::new())
+    }
+
+    fn marker(&self, ty: &Type, marker: Marker, seen: &mut HashSet<String>) -> Option<Blocker> {
+        // Prefix the blocking field's name onto the path
+        let field = |name: &str, blocker: Blocker| match blocker {
+            Blocker::RawPtr(path) => Blocker::RawPtr(join(name, &path)),
+            Blocker::Ref(path) => Blocker::Ref(join(name, &path)),
+        };
+        match ty {
+            _ if ty.atomic_value().is_some() => None,
+            Type::Named(name) | Type::Generic(name, _) if self.markers.contains(&(marker, name.clone())) => None,
+            Type::Named(name) | Type::Generic(name, _) if !seen.insert(name.clone()) => None,
+            Type::Named(name) => {
+                if let Some(fields) = self.structs.get(name) {
+                    fields.iter().find_map(|(n, f)| self.marker(f, marker, seen).map(|b| field(n, b)))
+                } else if let Some(payloads) = self.enums.get(name) {
+                    payloads.iter().find_map(|p| self.marker(p, marker, seen))
+                } else if let Some(alias) = self.aliases.get(name) {
+                    self.marker(alias, marker, seen)
+                } else {
+                    None
+                }
+            }
+            Type::Generic(name, args) => {
+                let mut fields = self.structs.get(name).into_iter().flatten();
+                let payloads = self.enums.get(name).into_iter().flatten();
+                fields.find_map(|(n, f)| self.marker(f, marker, seen).map(|b| field(n, b)))
+                    .or_else(|| payloads.chain(args).find_map(|t| self.marker(t, marker, seen)))
+            }
+            Type::Array(elem, _) | Type::ConstArray(elem, _) => self.marker(elem, marker, seen),
+            Type::Ref(_, inner, _) => self.marker(inner, Marker::Sync, seen),
+            Type::Ptr(..) => Some(Blocker::Ref(String::new())),
+            Type::RawPtr(_) => Some(Blocker::RawPtr(String::new())),
+            Type::Func(..) | Type::Infer | Type::Unit => None,
+        }
+    }
+
+    /// Whether a type has an `impl Drop`
+    pub fn has_drop(&self, name: &str) -> bool {
+        self.drops.contains(name)
+    }
+
+    pub fn alias(&self, name: &str) -> Option<&Type> {
+        self.aliases.get(name)
+    }
+
+    pub fn func(&self, name: &str) -> Option<&Signature> {
+        self.funcs.get(name)
+    }
+
+    pub fn method(&self, ty: &str, name: &str) -> Option<&Signature> {
+        self.methods.get(ty).and_then(|m| m.get(name))
+    }
+
+    /// Fields of a struct in declaration order
+    pub fn fields(&self, ty: &str) -> Option<&[(String, Type)]> {
+        self.structs.get(ty).map(Vec::as_slice)
+    }
+
+    pub fn field(&self, ty: &str, field: &str) -> Option<&Type> {
+        self.structs.get(ty)?.iter().find(|(name, _)| name == field).map(|(_, ty)| ty)
+    }
+
+    /// Named type behind references and aliases, with the number of references
+    pub fn named(&self, ty: &Type) -> Option<(String, usize)> {
+        let mut ty = ty.clone();
+        let mut derefs = 0;
+        // Bounded in case of cyclic aliases
+        for _ in 0..64 {
+            match ty {
+                Type::Ptr(inner, _) | Type::RawPtr(inner) | Type::Ref(_, inner, _) => {
+                    derefs += 1;
+                    ty = *inner;
+                }
+                Type::Named(n) | Type::Generic(n, _) => match self.alias(&n) {
+                    Some(alias) => ty = alias.clone(),
+                    None => return Some((n, derefs)),
+                },
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    /// Type of an expression, as far as the declarations tell without the type
+    /// checker. `local` gives a local variable's type: `Some(None)` for a local
+    /// of unknown type, `None` for a name that is not a local.
+    pub fn type_of(&self, expr: &Expr, local: &impl Fn(&str) -> Option<Option<Type>>) -> Option<Type> {
+        match expr {
+            Expr::Ident(name, _) => local(name)?,
+            Expr::Int(..) => Some(Type::Named("Int".into())),
+            Expr::Float(..) => Some(Type::Named("Float".into())),
+            Expr::Bool(..) => Some(Type::Named("Bool".into())),
+            Expr::String(..) => Some(Type::Named("String".into())),
+            Expr::Struct(name, _, _) => Some(Type::Named(name.clone())),
+            // `E::V`: a variant without payload
+            Expr::Path(path, _) if path.len() == 2 && self.enums.contains_key(&path[0]) => Some(Type::Named(path[0].clone())),
+            Expr::Array(elems, _) => {
+                let elem = elems.first().and_then(|e| self.type_of(e, local)).unwrap_or(Type::Infer);
+                Some(Type::Array(Box::new(elem), Some(elems.len())))
+            }
+            Expr::Call(callee, _, _) => match callee.as_ref() {
+                Expr::Ident(name, _) if local(name).is_none() => self.func(name)?.ret.clone(),
+                // `Type::new(..)`
+                Expr::Path(path, _) if path.len() == 2 => match self.method(&path[0], &path[1]) {
+                    Some(sig) => sig.ret.as_ref().map(|ret| with_self(ret, &path[0])),
+                    None => Some(Type::Named(path[0].clone())),
+                },
+                _ => None,
+            },
+            Expr::MethodCall(obj, method, _, _) => {
+                let (ty, _) = self.named(&self.type_of(obj, local)?)?;
+                Some(with_self(self.method(&ty, method)?.ret.as_ref()?, &ty))
+            }
+            Expr::Field(obj, field, _) => {
+                let (ty, _) = self.named(&self.type_of(obj, local)?)?;
+                self.field(&ty, field).cloned()
+            }
+            Expr::Index(arr, _, _) => match self.type_of(arr, local)? {
+                Type::Array(elem, _) => Some(*elem),
+                _ => None,
+            },
+            Expr::Unary(op @ (UnOp::Ref | UnOp::RefMut), inner, _) => {
+                Some(Type::Ptr(Box::new(self.type_of(inner, local).unwrap_or(Type::Infer)), *op == UnOp::RefMut))
+            }
+            Expr::Unary(UnOp::Deref, inner, _) => self.type_of(inner, local)?.pointee().cloned(),
+            Expr::Unary(UnOp::Not, _, _) => Some(Type::Named("Bool".into())),
+            Expr::Unary(_, inner, _) => self.type_of(inner, local),
+            // Arithmetic with a Float operand is Float arithmetic
+            Expr::Binary(op, left, right, _) => match op {
+                BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge | BinOp::And | BinOp::Or => {
+                    Some(Type::Named("Bool".into()))
+                }
+                BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Mod if self.float_operands(left, right, local) => {
+                    Some(Type::Named("Float".into()))
+                }
+                _ => self.type_of(left, local),
+            },
+            Expr::If(_, block, _, _) | Expr::Unsafe(block, _) | Expr::Comptime(block, _) => match block.stmts.last()? {
+                Stmt::Expr(tail, _) => self.type_of(tail, local),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether a binary operation works on doubles: either operand is a Float
+    pub fn float_operands(&self, left: &Expr, right: &Expr, local: &impl Fn(&str) -> Option<Option<Type>>) -> bool {
+        [left, right].iter().any(|e| self.type_of(e, local).is_some_and(|t| t.is_float()))
+    }
+}
+
+/// Name of the type a value holds, looking through references
+pub fn type_name(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Named(n) | Type::Generic(n, _) => Some(n.clone()),
+        Type::Ptr(inner, _) | Type::RawPtr(inner) | Type::Ref(_, inner, _) => type_name(inner),
+        _ => None,
+    }
+}
+
+/// `outer.inner` field path
+fn join(outer: &str, inner: &str) -> String {
+    if inner.is_empty() { outer.to_string() } else { format!("{}.{}", outer, inner) }
+}
+
+/// Whether a type holds a reference
+pub fn has_ref(ty: &Type) -> bool {
+    let mut named = Vec::new();
+    let mut elided = false;
+    lifetimes(ty, &mut named, &mut elided);
+    elided || !named.is_empty()
+}
+
+/// Collect the lifetimes of the references in a type; `elided` is set for
+/// references without one
+fn lifetimes(ty: &Type, named: &mut Vec<String>, elided: &mut bool) {
+    match ty {
+        Type::Ptr(inner, _) => {
+            *elided = true;
+            lifetimes(inner, named, elided);
+        }
+        Type::Ref(lifetime, inner, _) => {
+            named.push(lifetime.clone());
+            lifetimes(inner, named, elided);
+        }
+        Type::Array(inner, _) | Type::ConstArray(inner, _) => lifetimes(inner, named, elided),
+        // Lifetime arguments of generic types parse as named types: Parser<'a>
+        Type::Generic(_, args) => {
+            for arg in args {
+                lifetimes(arg, named, elided);
+            }
+        }
+        Type::Named(name) if name.starts_with('\'') => named.push(name.clone()),
+        Type::Named(_) | Type::RawPtr(_) | Type::Func(..) | Type::Infer | Type::Unit => {}
+    }
+}
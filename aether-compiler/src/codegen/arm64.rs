@@ -1,94 +1,806 @@
-/// Emit optimized function prologue - Safe + Registers
-/// Saves ALL callee-saved registers to allow register allocation
-pub fn emit_prologue(asm: &mut String) {
-    // Save/Restore all callee-saved registers (x19-x28)
-    // This allows us to use them for variables freely
-    asm.push_str("    stp x29, x30, [sp, #-96]!\n");
-    asm.push_str("    stp x19, x20, [sp, #16]\n");
-    asm.push_str("    stp x21, x22, [sp, #32]\n");
-    asm.push_str("    stp x23, x24, [sp, #48]\n");
-    asm.push_str("    stp x25, x26, [sp, #64]\n");
-    asm.push_str("    stp x27, x28, [sp, #80]\n");
-    asm.push_str("    mov x29, sp\n");
-    // Allocate space for locals (safe buffer)
-    asm.push_str("    sub sp, sp, #128\n");
+//! AArch64 code generation: AAPCS64 assembly for the GNU assembler
+//!
+//! Selects instructions from the mid-level IR and assigns its virtual registers by
+//! linear scan; values that do not fit in registers are spilled to the frame.
+
+use anyhow::{bail, Result};
+use crate::ast::BinOp;
+use super::header::CScalar;
+use super::mir::*;
+
+/// Allocatable registers a call may clobber
+const CALLER_SAVED: [u8; 7] = [9, 10, 11, 12, 13, 14, 15];
+/// Allocatable registers preserved across calls; the prologue saves the ones in use
+const CALLEE_SAVED: [u8; 10] = [19, 20, 21, 22, 23, 24, 25, 26, 27, 28];
+/// Never allocated: spilled operands, large immediates and addresses go through these
+const SCRATCH: [u8; 3] = [16, 17, 8];
+/// Largest byte offset of a scaled 64-bit `ldr`/`str`
+const MAX_OFFSET: i64 = 32760;
+
+/// Whether the assembler would read a symbol as a register
+fn reserved(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    let numbered = |prefix: &str| name.strip_prefix(prefix)
+        .is_some_and(|n| !n.is_empty() && n.parse::<u8>().is_ok());
+    ["sp", "wsp", "xzr", "wzr", "lr", "fp", "ip0", "ip1"].contains(&name.as_str())
+        || ["x", "w", "v", "b", "h", "s", "d", "q", "z", "p"].iter().any(|p| numbered(p))
 }
 
-/// Emit optimized function epilogue
-pub fn emit_epilogue(asm: &mut String, _stack_size: i32) {
-    asm.push_str("    add sp, sp, #128\n");
-    asm.push_str("    ldp x27, x28, [sp, #80]\n");
-    asm.push_str("    ldp x25, x26, [sp, #64]\n");
-    asm.push_str("    ldp x23, x24, [sp, #48]\n");
-    asm.push_str("    ldp x21, x22, [sp, #32]\n");
-    asm.push_str("    ldp x19, x20, [sp, #16]\n");
-    asm.push_str("    ldp x29, x30, [sp], #96\n");
-    asm.push_str("    ret\n");
+/// Symbol as written in instructions and directives
+fn sym(name: &str) -> String {
+    if reserved(name) { format!("\"{}\"", name) } else { name.to_string() }
 }
 
+fn x(reg: u8) -> String {
+    if reg == 31 { "xzr".into() } else { format!("x{}", reg) }
+}
+
+fn w(reg: u8) -> String {
+    if reg == 31 { "wzr".into() } else { format!("w{}", reg) }
+}
 
-/// Emit leaf function prologue (no calls, minimal overhead)
-pub fn emit_leaf_prologue(asm: &mut String) {
-    asm.push_str("    stp x29, x30, [sp, #-16]!\n");
-    asm.push_str("    mov x29, sp\n");
+/// Whether a value is encodable as a logical (bitmask) immediate
+fn logical_imm(value: i64) -> bool {
+    let value = value as u64;
+    if value == 0 || value == u64::MAX {
+        return false;
+    }
+    // Smallest element size the pattern repeats with
+    let mut size = 64;
+    while size > 2 {
+        let half = size / 2;
+        let mask = (1u64 << half) - 1;
+        if value & mask != (value >> half) & mask {
+            break;
+        }
+        size = half;
+    }
+    let mask = if size == 64 { u64::MAX } else { (1u64 << size) - 1 };
+    let elem = value & mask;
+    // The element must be a rotated run of ones
+    (0..size).any(|r| {
+        let rotated = if r == 0 { elem } else { ((elem >> r) | (elem << (size - r))) & mask };
+        rotated & rotated.wrapping_add(1) == 0
+    })
 }
 
-/// Emit leaf function epilogue
-pub fn emit_leaf_epilogue(asm: &mut String) {
-    asm.push_str("    ldp x29, x30, [sp], #16\n");
-    asm.push_str("    ret\n");
+fn condition(op: BinOp) -> Option<(&'static str, &'static str)> {
+    // Condition and its inverse
+    Some(match op {
+        BinOp::Eq => ("eq", "ne"),
+        BinOp::Ne => ("ne", "eq"),
+        BinOp::Lt => ("lt", "ge"),
+        BinOp::Le => ("le", "gt"),
+        BinOp::Gt => ("gt", "le"),
+        BinOp::Ge => ("ge", "lt"),
+        _ => return None,
+    })
 }
 
-/// Emit recursive function prologue (minimal for speed)
-pub fn emit_recursive_prologue(asm: &mut String) {
-    // Ultra-minimal for recursive calls - just save lr and fp
-    asm.push_str("    stp x29, x30, [sp, #-32]!\n");
-    asm.push_str("    stp x19, x20, [sp, #16]\n");
-    asm.push_str("    mov x29, sp\n");
+// ========== Module ==========
+
+/// Generate the assembly for a lowered module
+pub fn generate(program: &Program) -> Result<String> {
+    let mut asm = String::from("// AArch64 assembly generated by Aether Compiler\n");
+    asm.push_str("    .text\n");
+    for (i, f) in program.functions.iter().enumerate() {
+        asm.push_str(&FunctionGen::new(f, i)?.generate()?);
+    }
+    for (name, sig) in &program.exports {
+        asm.push_str(&export_wrapper(name, sig)?);
+    }
+    if program.entry {
+        asm.push('\n');
+        define_symbol(&mut asm, "_start", true, "function");
+        asm.push_str("    mov x29, #0\n");
+        asm.push_str("    mov x30, #0\n");
+        asm.push_str("    ldr x0, [sp]\n");
+        asm.push_str("    add x1, sp, #8\n");
+        asm.push_str("    bl main\n");
+        asm.push_str("    bl exit\n");
+    }
+    for (kind, section, object) in [
+        (DataKind::ReadOnly, ".section .rodata", "object"),
+        (DataKind::Mutable, ".data", "object"),
+        (DataKind::ThreadLocal, ".section .tdata,\"awT\",%progbits", "tls_object"),
+    ] {
+        let items: Vec<&Data> = program.data.iter().filter(|d| d.kind == kind).collect();
+        if items.is_empty() {
+            continue;
+        }
+        asm.push_str(&format!("\n    {}\n", section));
+        for data in items {
+            match &data.init {
+                Init::Str(s) => asm.push_str(&format!("{}:\n    .asciz \"{}\"\n", data.name, escape(s))),
+                Init::Words(words) => {
+                    asm.push_str("    .balign 8\n");
+                    define_symbol(&mut asm, &data.name, data.global, object);
+                    if words.is_empty() {
+                        asm.push_str("    .zero 8\n");
+                    } else {
+                        let words: Vec<String> = words.iter().map(|w| w.to_string()).collect();
+                        asm.push_str(&format!("    .quad {}\n", words.join(", ")));
+                    }
+                }
+            }
+        }
+    }
+    asm.push_str("\n    .section .note.GNU-stack,\"\",%progbits\n");
+    Ok(asm)
 }
 
-/// Emit recursive function epilogue
-pub fn emit_recursive_epilogue(asm: &mut String) {
-    asm.push_str("    ldp x19, x20, [sp, #16]\n");
-    asm.push_str("    ldp x29, x30, [sp], #32\n");
-    asm.push_str("    ret\n");
+fn define_symbol(out: &mut String, name: &str, global: bool, kind: &str) {
+    if global {
+        out.push_str(&format!("    .globl {}\n", sym(name)));
+    }
+    out.push_str(&format!("    .type {}, %{}\n", sym(name), kind));
+    out.push_str(&format!("{}:\n", sym(name)));
 }
 
-pub fn emit_mov_imm(asm: &mut String, reg: &str, val: i64) {
-    if val >= 0 && val <= 65535 {
-        asm.push_str(&format!("    mov {}, #{}\n", reg, val));
-    } else if val < 0 && val >= -65536 {
-        asm.push_str(&format!("    mov {}, #{}\n", reg, val));
-    } else {
-        // Handle large immediates with movz/movk sequence
-        asm.push_str(&format!("    movz {}, #{}\n", reg, (val & 0xFFFF) as u16));
-        if (val >> 16) & 0xFFFF != 0 {
-            asm.push_str(&format!("    movk {}, #{}, lsl #16\n", reg, ((val >> 16) & 0xFFFF) as u16));
+/// C ABI entry point of an `#[export]` function: convert the arguments to words,
+/// call the body and convert the result back
+fn export_wrapper(name: &str, sig: &ExportSig) -> Result<String> {
+    if sig.params.len() > 8 {
+        bail!("Exported function {} has more than 8 parameters", name);
+    }
+    let mut out = String::from("\n    .balign 4\n");
+    define_symbol(&mut out, name, true, "function");
+    out.push_str("    stp x29, x30, [sp, #-16]!\n");
+    out.push_str("    mov x29, sp\n");
+    // Where each parameter arrives: general or floating-point register index
+    let mut ints = 0;
+    let mut floats = 0;
+    let mut sources = Vec::new();
+    for ty in &sig.params {
+        if is_float(*ty) {
+            sources.push(floats);
+            floats += 1;
+        } else {
+            sources.push(ints);
+            ints += 1;
         }
-        if (val >> 32) & 0xFFFF != 0 {
-            asm.push_str(&format!("    movk {}, #{}, lsl #32\n", reg, ((val >> 32) & 0xFFFF) as u16));
+    }
+    // Backwards: parameter i only ever arrives in a register numbered i or lower
+    for i in (0..sig.params.len()).rev() {
+        let (dst, src) = (i as u8, sources[i] as u8);
+        let line = match sig.params[i].map(|t| (t.llvm, t.signed)) {
+            None | Some(("i64", _)) if src == dst => continue,
+            None | Some(("i64", _)) => format!("mov {}, {}", x(dst), x(src)),
+            Some(("i32", true)) => format!("sxtw {}, {}", x(dst), w(src)),
+            Some(("i32", false)) => format!("mov {}, {}", w(dst), w(src)),
+            Some(("i16", true)) => format!("sxth {}, {}", x(dst), w(src)),
+            Some(("i16", false)) => format!("and {}, {}, #0xffff", x(dst), x(src)),
+            Some(("i8", true)) => format!("sxtb {}, {}", x(dst), w(src)),
+            Some(("i8", false)) | Some(("i1", _)) => format!("and {}, {}, #0xff", x(dst), x(src)),
+            Some(("float", _)) => format!("fcvt d{}, s{}\n    fmov {}, d{}", src, src, x(dst), src),
+            Some(_) => format!("fmov {}, d{}", x(dst), src),
+        };
+        out.push_str(&format!("    {}\n", line));
+    }
+    out.push_str(&format!("    bl {}\n", sym(&format!("{}.body", name))));
+    match sig.ret.flatten().map(|t| t.llvm) {
+        Some("i1") => {
+            out.push_str("    cmp x0, #0\n");
+            out.push_str("    cset w0, ne\n");
         }
-        if (val >> 48) & 0xFFFF != 0 {
-            asm.push_str(&format!("    movk {}, #{}, lsl #48\n", reg, ((val >> 48) & 0xFFFF) as u16));
+        Some("double") => out.push_str("    fmov d0, x0\n"),
+        Some("float") => {
+            out.push_str("    fmov d0, x0\n");
+            out.push_str("    fcvt s0, d0\n");
         }
+        _ => {}
     }
+    out.push_str("    ldp x29, x30, [sp], #16\n");
+    out.push_str("    ret\n");
+    out.push_str(&format!("    .size {}, .-{}\n", sym(name), sym(name)));
+    Ok(out)
 }
 
-pub fn emit_load_local(asm: &mut String, reg: &str, offset: i32) {
-    asm.push_str(&format!("    ldur {}, [x29, #-{}]\n", reg, offset));
-}
+// ========== Functions ==========
 
-pub fn emit_store_local(asm: &mut String, reg: &str, offset: i32) {
-    asm.push_str(&format!("    stur {}, [x29, #-{}]\n", reg, offset));
+/// Frame below the saved `x29`/`x30` pair, from `sp` upwards: outgoing stack
+/// arguments, saved callee-saved registers, then slots (the IR's, then spills)
+struct Frame {
+    saved: Vec<u8>,
+    saved_at: i64,
+    slots_at: i64,
+    size: i64,
 }
 
-/// Emit optimized loop structure
-pub fn emit_loop_header(asm: &mut String, counter_reg: &str, limit_reg: &str, end_label: &str) {
-    asm.push_str(&format!("    cmp {}, {}\n", counter_reg, limit_reg));
-    asm.push_str(&format!("    b.ge {}\n", end_label));
+struct FunctionGen<'a> {
+    f: &'a Function,
+    locs: Vec<Option<Loc>>,
+    /// Number of uses of each virtual register
+    uses: Vec<u32>,
+    frame: Frame,
+    /// No calls, frame, stack parameters or atomic updates: no frame record either
+    leaf: bool,
+    /// Value of the current instruction computed straight into an argument register
+    placed: Option<(VReg, u8)>,
+    ret_label: String,
+    index: usize,
+    out: String,
 }
 
-/// Emit optimized increment
-pub fn emit_increment(asm: &mut String, reg: &str) {
-    asm.push_str(&format!("    add {}, {}, #1\n", reg, reg));
+impl<'a> FunctionGen<'a> {
+    fn new(f: &'a Function, index: usize) -> Result<Self> {
+        let (locs, spills) = allocate(f, &CALLER_SAVED, &CALLEE_SAVED);
+        let mut uses = vec![0; f.vregs as usize];
+        let mut outgoing = 0;
+        let mut calls = false;
+        // The loop of an atomic update takes its status register from the saved x30
+        let mut rmw = false;
+        for inst in &f.insts {
+            rmw |= matches!(inst, Inst::AtomicRmw { .. });
+            for v in inst.uses() {
+                uses[v as usize] += 1;
+            }
+            if let Inst::Call { args, abi, .. } = inst {
+                calls = true;
+                let ints = (0..args.len())
+                    .filter(|&k| !is_float(abi.as_ref().and_then(|a| a.params.get(k).copied().flatten())))
+                    .count();
+                outgoing = outgoing.max(8 * ints.saturating_sub(8) as i64);
+            }
+        }
+        let saved: Vec<u8> = CALLEE_SAVED.iter().copied()
+            .filter(|r| locs.contains(&Some(Loc::Reg(*r))))
+            .collect();
+        let saved_at = outgoing;
+        let slots_at = saved_at + 8 * saved.len() as i64;
+        let size = (slots_at + 8 * (f.slots + spills) as i64 + 15) & !15;
+        if size - 8 > MAX_OFFSET {
+            bail!("Frame of {} is too large ({} bytes)", f.name, size);
+        }
+        Ok(FunctionGen {
+            f,
+            locs,
+            uses,
+            leaf: !calls && !rmw && size == 0 && f.params.len() <= 8,
+            frame: Frame { saved, saved_at, slots_at, size },
+            placed: None,
+            ret_label: format!(".Lret{}", index),
+            index,
+            out: String::new(),
+        })
+    }
+
+    fn emit(&mut self, line: &str) {
+        self.out.push_str("    ");
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    fn slot_offset(&self, slot: u32) -> i64 {
+        self.frame.slots_at + 8 * slot as i64
+    }
+
+    fn loc(&self, v: VReg) -> Option<Loc> {
+        self.locs.get(v as usize).copied().flatten()
+    }
+
+    /// Register holding `v` for reading; spilled values are loaded into a scratch register
+    fn read(&mut self, v: VReg, scratch: usize) -> u8 {
+        match self.loc(v) {
+            Some(Loc::Reg(r)) => r,
+            Some(Loc::Spill(s)) => {
+                let r = SCRATCH[scratch];
+                let offset = self.slot_offset(s);
+                self.emit(&format!("ldr {}, [sp, #{}]", x(r), offset));
+                r
+            }
+            None => 31,
+        }
+    }
+
+    /// Copy `v` into a specific register
+    fn read_into(&mut self, v: VReg, reg: u8) {
+        if self.placed == Some((v, reg)) {
+            return;
+        }
+        match self.loc(v) {
+            Some(Loc::Reg(r)) if r == reg => {}
+            Some(Loc::Reg(r)) => self.emit(&format!("mov {}, {}", x(reg), x(r))),
+            Some(Loc::Spill(s)) => {
+                let offset = self.slot_offset(s);
+                self.emit(&format!("ldr {}, [sp, #{}]", x(reg), offset));
+            }
+            None => self.emit(&format!("mov {}, xzr", x(reg))),
+        }
+    }
+
+    /// Register to compute `v` into; finish with `write_back`
+    fn target(&self, v: VReg) -> u8 {
+        if let Some((_, reg)) = self.placed.filter(|&(p, _)| p == v) {
+            return reg;
+        }
+        match self.loc(v) {
+            Some(Loc::Reg(r)) => r,
+            _ => SCRATCH[0],
+        }
+    }
+
+    fn write_back(&mut self, v: VReg) {
+        if self.placed.is_some_and(|(p, _)| p == v) {
+            return;
+        }
+        if let Some(Loc::Spill(s)) = self.loc(v) {
+            let offset = self.slot_offset(s);
+            self.emit(&format!("str {}, [sp, #{}]", x(SCRATCH[0]), offset));
+        }
+    }
+
+    /// Store the value in `reg` as `v`
+    fn write_from(&mut self, v: VReg, reg: u8) {
+        match self.loc(v) {
+            Some(Loc::Reg(r)) if r != reg => self.emit(&format!("mov {}, {}", x(r), x(reg))),
+            Some(Loc::Spill(s)) => {
+                let offset = self.slot_offset(s);
+                self.emit(&format!("str {}, [sp, #{}]", x(reg), offset));
+            }
+            _ => {}
+        }
+    }
+
+    /// Load a 64-bit constant with `mov`/`movz`/`movk`
+    fn mov_imm(&mut self, reg: u8, value: i64) {
+        if (0..=0xffff).contains(&value) || (-0x10000..0).contains(&value) {
+            return self.emit(&format!("mov {}, #{}", x(reg), value));
+        }
+        let mut first = true;
+        for shift in [0, 16, 32, 48] {
+            let chunk = (value as u64 >> shift) & 0xffff;
+            if chunk == 0 {
+                continue;
+            }
+            let op = if first { "movz" } else { "movk" };
+            self.emit(&format!("{} {}, #{}, lsl #{}", op, x(reg), chunk, shift));
+            first = false;
+        }
+    }
+
+    /// `dst = base + value`, with `sp` as base when `base` is 31
+    fn add_imm(&mut self, dst: u8, base: u8, value: i64) {
+        let base = if base == 31 { "sp".to_string() } else { x(base) };
+        if (0..=4095).contains(&value) {
+            self.emit(&format!("add {}, {}, #{}", x(dst), base, value));
+        } else if (-4095..0).contains(&value) {
+            self.emit(&format!("sub {}, {}, #{}", x(dst), base, -value));
+        } else {
+            self.mov_imm(SCRATCH[2], value);
+            self.emit(&format!("add {}, {}, {}", x(dst), base, x(SCRATCH[2])));
+        }
+    }
+
+    /// Memory operand `[base, #offset]`, going through `x8` when the offset does not fit
+    fn mem(&mut self, base: u8, offset: i64, width: Width) -> String {
+        let bytes = width.bytes();
+        if offset == 0 {
+            format!("[{}]", x(base))
+        } else if offset % bytes == 0 && (0..=4095 * bytes).contains(&offset) {
+            format!("[{}, #{}]", x(base), offset)
+        } else {
+            self.mov_imm(SCRATCH[2], offset);
+            format!("[{}, {}]", x(base), x(SCRATCH[2]))
+        }
+    }
+
+    fn generate(mut self) -> Result<String> {
+        let f = self.f;
+        self.prologue();
+        for (i, &p) in f.params.iter().enumerate() {
+            if i < 8 {
+                self.write_from(p, i as u8);
+            } else if self.loc(p).is_some() {
+                self.emit(&format!("ldr {}, [x29, #{}]", x(SCRATCH[0]), 16 + 8 * (i - 8)));
+                self.write_from(p, SCRATCH[0]);
+            }
+        }
+        let mut i = 0;
+        while i < f.insts.len() {
+            self.placed = self.placement(i);
+            i += self.inst(i)?;
+            // Kept until the consuming call or return has read it
+            if self.placed.is_some() {
+                i += self.inst(i)?;
+                self.placed = None;
+            }
+        }
+        self.epilogue();
+        let mut out = String::from("\n    .balign 4\n");
+        define_symbol(&mut out, &f.name, f.global, "function");
+        out.push_str(&self.out);
+        out.push_str(&format!("    .size {}, .-{}\n", sym(&f.name), sym(&f.name)));
+        Ok(out)
+    }
+
+    /// Argument or result register to compute `insts[i]` into, when its value is
+    /// used only by the call or return that follows
+    fn placement(&self, i: usize) -> Option<(VReg, u8)> {
+        let inst = &self.f.insts[i];
+        let d = inst.def().filter(|&d| self.uses[d as usize] == 1 && !matches!(inst, Inst::Call { .. }))?;
+        match self.f.insts.get(i + 1)? {
+            Inst::Ret(v) => (*v == d).then_some((d, 0)),
+            Inst::Call { callee, args, abi, .. } => {
+                if matches!(callee, Callee::Indirect(f) if *f == d) {
+                    return None;
+                }
+                let mut ints = 0;
+                for (k, &arg) in args.iter().enumerate() {
+                    let float = is_float(abi.as_ref().and_then(|a| a.params.get(k).copied().flatten()));
+                    if arg == d {
+                        return (!float && ints < 8).then_some((d, ints));
+                    }
+                    ints += u8::from(!float);
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn prologue(&mut self) {
+        if self.leaf {
+            return;
+        }
+        self.emit("stp x29, x30, [sp, #-16]!");
+        self.emit("mov x29, sp");
+        let size = self.frame.size;
+        if size > 4095 {
+            self.mov_imm(SCRATCH[0], size);
+            self.emit(&format!("sub sp, sp, {}", x(SCRATCH[0])));
+        } else if size > 0 {
+            self.emit(&format!("sub sp, sp, #{}", size));
+        }
+        for line in self.saved_regs("stp", "str") {
+            self.emit(&line);
+        }
+    }
+
+    fn epilogue(&mut self) {
+        let label = self.ret_label.clone();
+        self.out.push_str(&format!("{}:\n", label));
+        if !self.leaf {
+            for line in self.saved_regs("ldp", "ldr") {
+                self.emit(&line);
+            }
+            self.emit("mov sp, x29");
+            self.emit("ldp x29, x30, [sp], #16");
+        }
+        self.emit("ret");
+    }
+
+    /// Save or restore the callee-saved registers in use, in pairs where possible
+    fn saved_regs(&self, pair: &str, single: &str) -> Vec<String> {
+        self.frame.saved.chunks(2).enumerate()
+            .map(|(k, regs)| {
+                let offset = self.frame.saved_at + 16 * k as i64;
+                match regs {
+                    [a, b] if offset <= 504 => format!("{} {}, {}, [sp, #{}]", pair, x(*a), x(*b), offset),
+                    [a, b] => format!("{} {}, [sp, #{}]\n    {} {}, [sp, #{}]", single, x(*a), offset, single, x(*b), offset + 8),
+                    _ => format!("{} {}, [sp, #{}]", single, x(regs[0]), offset),
+                }
+            })
+            .collect()
+    }
+
+    /// Select instructions for `insts[i]`; returns how many IR instructions were consumed
+    fn inst(&mut self, i: usize) -> Result<usize> {
+        let f = self.f;
+        match &f.insts[i] {
+            Inst::Imm(d, value) => {
+                let r = self.target(*d);
+                self.mov_imm(r, *value);
+                self.write_back(*d);
+            }
+            Inst::Copy(d, s) => {
+                let r = self.read(*s, 0);
+                self.write_from(*d, r);
+            }
+            Inst::Bin(op, d, a, b) => return self.binary(i, *op, *d, *a, *b),
+            Inst::Neg(d, a) | Inst::BitNot(d, a) => {
+                let ra = self.read(*a, 0);
+                let rd = self.target(*d);
+                let op = if matches!(f.insts[i], Inst::Neg(..)) { "neg" } else { "mvn" };
+                self.emit(&format!("{} {}, {}", op, x(rd), x(ra)));
+                self.write_back(*d);
+            }
+            // Doubles in d0 and d1; the conditions after `fcmp` are false for NaN, except `ne`
+            Inst::FBin(op, d, a, b) => {
+                let ra = self.read(*a, 0);
+                let rb = self.read(*b, 1);
+                self.emit(&format!("fmov d0, {}", x(ra)));
+                self.emit(&format!("fmov d1, {}", x(rb)));
+                let rd = self.target(*d);
+                let arith = match op {
+                    BinOp::Add => Some("fadd"),
+                    BinOp::Sub => Some("fsub"),
+                    BinOp::Mul => Some("fmul"),
+                    BinOp::Div => Some("fdiv"),
+                    _ => None,
+                };
+                if let Some(arith) = arith {
+                    self.emit(&format!("{} d0, d0, d1", arith));
+                    self.emit(&format!("fmov {}, d0", x(rd)));
+                } else {
+                    let cond = match op {
+                        BinOp::Lt => "mi",
+                        BinOp::Le => "ls",
+                        BinOp::Gt => "gt",
+                        BinOp::Ge => "ge",
+                        BinOp::Eq => "eq",
+                        _ => "ne",
+                    };
+                    self.emit("fcmp d0, d1");
+                    self.emit(&format!("cset {}, {}", x(rd), cond));
+                }
+                self.write_back(*d);
+            }
+            Inst::IntToFloat(d, a) => {
+                let ra = self.read(*a, 0);
+                self.emit(&format!("scvtf d0, {}", x(ra)));
+                let rd = self.target(*d);
+                self.emit(&format!("fmov {}, d0", x(rd)));
+                self.write_back(*d);
+            }
+            Inst::FloatToInt(d, a) => {
+                let ra = self.read(*a, 0);
+                self.emit(&format!("fmov d0, {}", x(ra)));
+                let rd = self.target(*d);
+                self.emit(&format!("fcvtzs {}, d0", x(rd)));
+                self.write_back(*d);
+            }
+            Inst::Load { width, dst, base, offset, ordered } => {
+                let rb = self.read(*base, 0);
+                let rd = self.target(*dst);
+                let reg = if *width == Width::W64 { x(rd) } else { w(rd) };
+                let suffix = match width {
+                    Width::W8 => "b",
+                    Width::W16 => "h",
+                    _ => "",
+                };
+                if *ordered {
+                    let addr = self.ordered_address(rb, *offset);
+                    self.emit(&format!("ldar{} {}, [{}]", suffix, reg, x(addr)));
+                } else {
+                    let mem = self.mem(rb, *offset, *width);
+                    self.emit(&format!("ldr{} {}, {}", suffix, reg, mem));
+                }
+                self.write_back(*dst);
+            }
+            Inst::Store { width, src, base, offset, ordered } => {
+                let rs = self.read(*src, 0);
+                let rb = self.read(*base, 1);
+                let reg = if *width == Width::W64 { x(rs) } else { w(rs) };
+                let suffix = match width {
+                    Width::W8 => "b",
+                    Width::W16 => "h",
+                    _ => "",
+                };
+                if *ordered {
+                    let addr = self.ordered_address(rb, *offset);
+                    self.emit(&format!("stlr{} {}, [{}]", suffix, reg, x(addr)));
+                } else {
+                    let mem = self.mem(rb, *offset, *width);
+                    self.emit(&format!("str{} {}, {}", suffix, reg, mem));
+                }
+            }
+            Inst::AtomicRmw { op, src, base } => {
+                let rs = self.read(*src, 0);
+                let rb = self.read(*base, 1);
+                let op = match op {
+                    BinOp::Add => "add",
+                    BinOp::Sub => "sub",
+                    BinOp::BitAnd => "and",
+                    BinOp::BitOr => "orr",
+                    _ => "eor",
+                };
+                let (value, status) = (SCRATCH[2], 30);
+                let label = format!(".Lrmw{}_{}", self.index, i);
+                self.out.push_str(&format!("{}:\n", label));
+                self.emit(&format!("ldaxr {}, [{}]", x(value), x(rb)));
+                self.emit(&format!("{} {}, {}, {}", op, x(value), x(value), x(rs)));
+                self.emit(&format!("stlxr {}, {}, [{}]", w(status), x(value), x(rb)));
+                self.emit(&format!("cbnz {}, {}", w(status), label));
+            }
+            Inst::LoadSlot(d, slot) => {
+                let rd = self.target(*d);
+                let offset = self.slot_offset(*slot);
+                self.emit(&format!("ldr {}, [sp, #{}]", x(rd), offset));
+                self.write_back(*d);
+            }
+            Inst::StoreSlot(v, slot) => {
+                let r = self.read(*v, 0);
+                let offset = self.slot_offset(*slot);
+                self.emit(&format!("str {}, [sp, #{}]", x(r), offset));
+            }
+            Inst::SlotAddr(d, slot) => {
+                let rd = self.target(*d);
+                let offset = self.slot_offset(*slot);
+                self.add_imm(rd, 31, offset);
+                self.write_back(*d);
+            }
+            Inst::Addr(d, name, kind) => {
+                let rd = self.target(*d);
+                let name = sym(name);
+                match kind {
+                    SymKind::ThreadLocal => {
+                        self.emit(&format!("mrs {}, tpidr_el0", x(rd)));
+                        self.emit(&format!("add {}, {}, #:tprel_hi12:{}, lsl #12", x(rd), x(rd), name));
+                        self.emit(&format!("add {}, {}, #:tprel_lo12_nc:{}", x(rd), x(rd), name));
+                    }
+                    _ => {
+                        self.emit(&format!("adrp {}, {}", x(rd), name));
+                        self.emit(&format!("add {}, {}, :lo12:{}", x(rd), x(rd), name));
+                    }
+                }
+                self.write_back(*d);
+            }
+            Inst::Call { dst, callee, args, abi } => self.call(*dst, callee, args, abi.as_ref())?,
+            Inst::Label(l) => self.out.push_str(&format!(".L{}:\n", l)),
+            Inst::Jump(l) => {
+                if !matches!(f.insts.get(i + 1), Some(Inst::Label(next)) if next == l) {
+                    self.emit(&format!("b .L{}", l));
+                }
+            }
+            Inst::Branch(v, zero, l) => {
+                let r = self.read(*v, 0);
+                self.emit(&format!("{} {}, .L{}", if *zero { "cbz" } else { "cbnz" }, x(r), l));
+            }
+            Inst::Ret(v) => {
+                self.read_into(*v, 0);
+                if i + 1 < f.insts.len() {
+                    let label = self.ret_label.clone();
+                    self.emit(&format!("b {}", label));
+                }
+            }
+        }
+        Ok(1)
+    }
+
+    /// Base register for `ldar`/`stlr`, which take no offset
+    fn ordered_address(&mut self, base: u8, offset: i64) -> u8 {
+        if offset == 0 {
+            return base;
+        }
+        self.add_imm(SCRATCH[2], base, offset);
+        SCRATCH[2]
+    }
+
+    /// Arithmetic, logic and comparisons; a comparison feeding the next branch becomes `b.cond`
+    fn binary(&mut self, i: usize, op: BinOp, d: VReg, a: VReg, b: Operand) -> Result<usize> {
+        let ra = self.read(a, 0);
+        if let Some((cond, inverse)) = condition(op) {
+            match b {
+                Operand::Imm(k) if (0..=4095).contains(&k) => self.emit(&format!("cmp {}, #{}", x(ra), k)),
+                Operand::Imm(k) if (-4095..0).contains(&k) => self.emit(&format!("cmn {}, #{}", x(ra), -k)),
+                _ => {
+                    let rb = self.operand(b);
+                    self.emit(&format!("cmp {}, {}", x(ra), x(rb)));
+                }
+            }
+            if let Some(Inst::Branch(v, zero, l)) = self.f.insts.get(i + 1) {
+                if *v == d && self.uses[d as usize] == 1 {
+                    self.emit(&format!("b.{} .L{}", if *zero { inverse } else { cond }, l));
+                    return Ok(2);
+                }
+            }
+            let rd = self.target(d);
+            self.emit(&format!("cset {}, {}", x(rd), cond));
+            self.write_back(d);
+            return Ok(1);
+        }
+        let rd = self.target(d);
+        let name = match op {
+            BinOp::Add => "add",
+            BinOp::Sub => "sub",
+            BinOp::Mul => "mul",
+            BinOp::Div | BinOp::Mod => "sdiv",
+            BinOp::And | BinOp::BitAnd => "and",
+            BinOp::Or | BinOp::BitOr => "orr",
+            BinOp::BitXor => "eor",
+            BinOp::Shl => "lsl",
+            _ => "lsr",
+        };
+        match (op, b) {
+            (BinOp::Add | BinOp::Sub, Operand::Imm(k)) if (-4095..=4095).contains(&k) => {
+                let k = if op == BinOp::Sub { -k } else { k };
+                let (name, k) = if k < 0 { ("sub", -k) } else { ("add", k) };
+                self.emit(&format!("{} {}, {}, #{}", name, x(rd), x(ra), k));
+            }
+            (BinOp::Shl | BinOp::Shr, Operand::Imm(k)) => {
+                self.emit(&format!("{} {}, {}, #{}", name, x(rd), x(ra), k & 63));
+            }
+            (BinOp::And | BinOp::BitAnd | BinOp::Or | BinOp::BitOr | BinOp::BitXor, Operand::Imm(k)) if logical_imm(k) => {
+                self.emit(&format!("{} {}, {}, #{:#x}", name, x(rd), x(ra), k));
+            }
+            (BinOp::Mod, _) => {
+                let rb = self.operand(b);
+                let q = SCRATCH[2];
+                self.emit(&format!("sdiv {}, {}, {}", x(q), x(ra), x(rb)));
+                self.emit(&format!("msub {}, {}, {}, {}", x(rd), x(q), x(rb), x(ra)));
+            }
+            _ => {
+                let rb = self.operand(b);
+                self.emit(&format!("{} {}, {}, {}", name, x(rd), x(ra), x(rb)));
+            }
+        }
+        self.write_back(d);
+        Ok(1)
+    }
+
+    /// Right-hand operand in a register (the second scratch for immediates and spills)
+    fn operand(&mut self, b: Operand) -> u8 {
+        match b {
+            Operand::Reg(v) => self.read(v, 1),
+            Operand::Imm(0) => 31,
+            Operand::Imm(k) => {
+                self.mov_imm(SCRATCH[1], k);
+                SCRATCH[1]
+            }
+        }
+    }
+
+    /// AAPCS64 call: words in x0-x7 then on the stack, C floats in d0-d7
+    fn call(&mut self, dst: Option<VReg>, callee: &Callee, args: &[VReg], abi: Option<&CAbi>) -> Result<()> {
+        let class = |k: usize| abi.and_then(|a| a.params.get(k).copied().flatten());
+        let mut ints = 0;
+        let mut floats = 0;
+        let mut stack = 0;
+        for (k, &arg) in args.iter().enumerate() {
+            match class(k) {
+                Some(ty) if is_float(Some(ty)) => {
+                    if floats == 8 {
+                        bail!("Too many floating-point arguments in a call from {}", self.f.name);
+                    }
+                    let r = self.read(arg, 0);
+                    self.emit(&format!("fmov d{}, {}", floats, x(r)));
+                    if ty.llvm == "float" {
+                        self.emit(&format!("fcvt s{}, d{}", floats, floats));
+                    }
+                    floats += 1;
+                }
+                _ if ints < 8 => {
+                    self.read_into(arg, ints);
+                    ints += 1;
+                }
+                _ => {
+                    let r = self.read(arg, 0);
+                    self.emit(&format!("str {}, [sp, #{}]", x(r), 8 * stack));
+                    stack += 1;
+                }
+            }
+        }
+        match callee {
+            Callee::Direct(name) => self.emit(&format!("bl {}", sym(name))),
+            Callee::Indirect(f) => {
+                let r = self.read(*f, 0);
+                self.emit(&format!("blr {}", x(r)));
+            }
+        }
+        let Some(d) = dst else { return Ok(()) };
+        if let Some(ret) = abi.and_then(|a| a.ret) {
+            self.extend_result(ret);
+        }
+        self.write_from(d, 0);
+        Ok(())
+    }
+
+    /// Widen a C result in `x0`/`d0` to a word in `x0`
+    fn extend_result(&mut self, ty: CScalar) {
+        match (ty.llvm, ty.signed) {
+            ("i32", true) => self.emit("sxtw x0, w0"),
+            ("i32", false) => self.emit("mov w0, w0"),
+            ("i16", true) => self.emit("sxth x0, w0"),
+            ("i16", false) => self.emit("and x0, x0, #0xffff"),
+            ("i8", true) => self.emit("sxtb x0, w0"),
+            ("i8", false) | ("i1", _) => self.emit("and x0, x0, #0xff"),
+            ("double", _) => self.emit("fmov x0, d0"),
+            ("float", _) => {
+                self.emit("fcvt d0, s0");
+                self.emit("fmov x0, d0");
+            }
+            _ => {}
+        }
+    }
 }
@@ -0,0 +1,1845 @@
+//! C source generation: lowers the typed AST to portable C99
+//!
+//! Values are `int64_t` words as in the other backends; structs, enum payloads and
+//! closure environments are heap records declared as C structs.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use anyhow::{anyhow, bail, Result};
+use crate::ast::*;
+use crate::borrowck::ownership::Ownership;
+use crate::typechecker::TypedModule;
+use super::header::{c_scalar, CScalar};
+use super::mir::{atomic_op, export_scalar, for_source, is_float_op, method_symbol, with_defaults, ExportSig, ForSource};
+
+/// Builtin that maps to a libc function
+struct LibcFn {
+    name: &'static str,
+    ret: &'static str,
+    params: &'static [&'static str],
+    /// The last parameter is passed through `...`
+    variadic: bool,
+}
+
+const fn libc(name: &'static str, ret: &'static str, params: &'static [&'static str]) -> LibcFn {
+    LibcFn { name, ret, params, variadic: false }
+}
+
+/// libc functions behind `__builtin_*`, declared without system headers (`long` stands in for `ssize_t`/`off_t`)
+const LIBC: &[LibcFn] = &[
+    libc("malloc", "void *", &["size_t"]),
+    libc("free", "void", &["void *"]),
+    libc("memcpy", "void *", &["void *", "const void *", "size_t"]),
+    libc("memset", "void *", &["void *", "int", "size_t"]),
+    libc("write", "long", &["int", "const void *", "size_t"]),
+    libc("read", "long", &["int", "void *", "size_t"]),
+    libc("lseek", "long", &["int", "long", "int"]),
+    libc("exit", "void", &["int"]),
+    LibcFn { name: "open", ret: "int", params: &["const char *", "int", "int"], variadic: true },
+    libc("close", "int", &["int"]),
+    libc("unlink", "int", &["const char *"]),
+    libc("mkdir", "int", &["const char *", "unsigned"]),
+    libc("rmdir", "int", &["const char *"]),
+    libc("rename", "int", &["const char *", "const char *"]),
+    libc("socket", "int", &["int", "int", "int"]),
+    libc("connect", "int", &["int", "const void *", "unsigned"]),
+    libc("bind", "int", &["int", "const void *", "unsigned"]),
+    libc("listen", "int", &["int", "int"]),
+    libc("accept", "int", &["int", "void *", "void *"]),
+    libc("setsockopt", "int", &["int", "int", "int", "const void *", "unsigned"]),
+    // Used by generated code itself
+    libc("abort", "void", &[]),
+    libc("strcmp", "int", &["const char *", "const char *"]),
+];
+
+const KEYWORDS: &[&str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else", "enum",
+    "extern", "float", "for", "goto", "if", "inline", "int", "long", "register", "restrict", "return",
+    "short", "signed", "sizeof", "static", "struct", "switch", "typedef", "union", "unsigned", "void",
+    "volatile", "while", "_Bool", "_Complex", "_Imaginary", "bool", "true", "false", "NULL", "main",
+    "int64_t", "uint64_t", "intptr_t", "size_t", "pthread_t", "pthread_create",
+];
+
+/// Helpers emitted ahead of the code when used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Helper {
+    FloatBits,
+    Print,
+    ThreadLocal,
+    Atomic,
+    Closures,
+    Threads,
+}
+
+/// Where the value of a block or expression goes
+#[derive(Debug, Clone)]
+enum Target {
+    Discard,
+    Assign(String),
+    Return,
+}
+
+/// A call, as a statement and as a value (`None` for `void` functions)
+struct CallText {
+    call: String,
+    value: Option<String>,
+    /// Only reads memory (builtin loads): needs no temporary
+    pure: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Local {
+    c_name: String,
+    ty: Option<Type>,
+}
+
+#[derive(Debug, Clone)]
+struct StaticInfo {
+    c_name: String,
+    /// Initialized on first access through `name__addr()`
+    lazy: bool,
+    atomic: bool,
+    ty: Option<Type>,
+}
+
+#[derive(Debug, Clone)]
+struct EnumInfo {
+    variants: Vec<Variant>,
+    /// Some variant has a payload: values are pointers to a tagged union
+    tagged: bool,
+}
+
+/// State of the function being generated
+#[derive(Default)]
+struct FnState {
+    /// C name, the prefix of its closures
+    name: String,
+    out: String,
+    indent: usize,
+    scopes: Vec<HashMap<String, Local>>,
+    /// Every C name declared in the function
+    used: HashSet<String>,
+    locals: HashSet<String>,
+    temps: HashSet<String>,
+    next_temp: usize,
+    loops: usize,
+}
+
+/// Generate a C translation unit; `entry` adds a C `main`, `hide_private` makes non-`pub` functions `static`
+pub fn generate(module: &TypedModule, entry: bool, hide_private: bool) -> Result<String> {
+    let mut gen = CGen {
+        own: Ownership::from_module(module),
+        funcs: HashMap::new(),
+        names: HashMap::new(),
+        externs: HashMap::new(),
+        exports: Vec::new(),
+        consts: HashMap::new(),
+        statics: HashMap::new(),
+        enums: HashMap::new(),
+        reserved: HashSet::new(),
+        hide_private,
+        helpers: BTreeSet::new(),
+        libc_used: BTreeSet::new(),
+        arities: BTreeSet::new(),
+        thunks: BTreeSet::new(),
+        threads: BTreeSet::new(),
+        undeclared: Vec::new(),
+        lambdas: 0,
+        types: String::new(),
+        protos: String::new(),
+        globals: String::new(),
+        defs: String::new(),
+        f: FnState::default(),
+        self_type: None,
+    };
+    gen.reserved.extend(KEYWORDS.iter().map(|k| k.to_string()));
+    gen.reserved.extend(LIBC.iter().map(|f| f.name.to_string()));
+    for typed_decl in &module.decls {
+        gen.declare(&typed_decl.decl)?;
+    }
+    gen.module(module)?;
+    if entry {
+        gen.entry()?;
+    }
+    Ok(gen.finish())
+}
+
+struct CGen {
+    own: Ownership,
+    /// Parameters and return type of every function and method, by symbol
+    funcs: HashMap<String, (Vec<Param>, Option<Type>)>,
+    /// C names of functions, by symbol
+    names: HashMap<String, String>,
+    externs: HashMap<String, ExternFunc>,
+    exports: Vec<(String, ExportSig)>,
+    /// Constants as C expressions
+    consts: HashMap<String, String>,
+    statics: HashMap<String, StaticInfo>,
+    enums: HashMap<String, EnumInfo>,
+    /// Names taken at file scope
+    reserved: HashSet<String>,
+    hide_private: bool,
+    helpers: BTreeSet<Helper>,
+    libc_used: BTreeSet<&'static str>,
+    /// Closure call signatures in use, by parameter count
+    arities: BTreeSet<usize>,
+    /// Functions used as values (`name__closure`)
+    thunks: BTreeSet<String>,
+    /// Functions started with `spawn` (`name__thread`)
+    threads: BTreeSet<String>,
+    /// Functions called without a declaration, with their argument count if it never varies
+    undeclared: Vec<(String, Option<usize>)>,
+    lambdas: usize,
+    // Output sections
+    types: String,
+    protos: String,
+    globals: String,
+    defs: String,
+    f: FnState,
+    self_type: Option<String>,
+}
+
+impl CGen {
+    // ========== Declarations ==========
+
+    fn declare(&mut self, decl: &Decl) -> Result<()> {
+        match decl {
+            Decl::Func { name, params, ret, .. } => {
+                self.funcs.insert(name.clone(), (params.clone(), ret.clone()));
+                let c_name = if name == "main" { "aether_main".to_string() } else { mangle(name) };
+                if decl.has_attr("export") {
+                    let sig = ExportSig {
+                        params: params.iter().map(|p| export_scalar(&p.ty)).collect(),
+                        ret: match ret {
+                            None | Some(Type::Unit) => None,
+                            Some(t) => Some(export_scalar(t)),
+                        },
+                    };
+                    self.exports.push((name.clone(), sig));
+                    self.reserved.insert(name.clone());
+                    self.names.insert(name.clone(), format!("{}__body", c_name));
+                } else {
+                    self.names.insert(name.clone(), c_name);
+                }
+                self.reserved.insert(self.names[name].clone());
+            }
+            // `impl Drop` is hoisted to `T__drop` during drop elaboration
+            Decl::Impl { trait_name, type_name, methods, .. } if trait_name.as_deref() != Some("Drop") => {
+                for method in methods {
+                    if let Decl::Func { name, params, ret, .. } = method {
+                        let symbol = method_symbol(type_name, name);
+                        self.funcs.insert(symbol.clone(), (params.clone(), ret.clone()));
+                        self.names.insert(symbol.clone(), mangle(&symbol));
+                        self.reserved.insert(mangle(&symbol));
+                    }
+                }
+            }
+            Decl::Struct { name, fields, .. } => {
+                let mut text = format!("struct {} {{", name);
+                for field in fields {
+                    text.push_str(&format!(" int64_t {};", c_ident(&field.name)));
+                }
+                if fields.is_empty() {
+                    text.push_str(" char unused;");
+                }
+                self.types.push_str(&text);
+                self.types.push_str(" };\n");
+            }
+            Decl::Enum { name, variants, .. } => {
+                let tagged = variants.iter().any(|v| !v.fields.is_empty());
+                let tags: Vec<String> = variants.iter().map(|v| format!("{}_{}", name, v.name)).collect();
+                self.reserved.extend(tags.iter().cloned());
+                if tagged {
+                    self.types.push_str(&format!("enum {{ {} }};\n", tags.join(", ")));
+                    self.types.push_str(&format!("struct {} {{\n    int64_t tag;\n    union {{\n", name));
+                    for variant in variants.iter().filter(|v| !v.fields.is_empty()) {
+                        let fields: String = (0..variant.fields.len()).map(|i| format!(" int64_t _{};", i)).collect();
+                        self.types.push_str(&format!("        struct {{{} }} {};\n", fields, variant.name));
+                    }
+                    self.types.push_str("    } as;\n};\n");
+                } else {
+                    self.types.push_str(&format!("enum {} {{ {} }};\n", name, tags.join(", ")));
+                }
+                self.enums.insert(name.clone(), EnumInfo { variants: variants.clone(), tagged });
+            }
+            Decl::Extern { funcs, .. } => {
+                for f in funcs {
+                    self.extern_proto(f)?;
+                    self.reserved.insert(f.name.clone());
+                    self.externs.insert(f.name.clone(), f.clone());
+                }
+            }
+            Decl::Const { name, value, public, .. } => {
+                let c_name = mangle(name);
+                let value = match value {
+                    Expr::Int(v, _) => int_lit(*v),
+                    Expr::Bool(b, _) => (*b as i64).to_string(),
+                    Expr::Float(f, _) => {
+                        self.helpers.insert(Helper::FloatBits);
+                        float_lit(*f)
+                    }
+                    Expr::String(s, _) => format!("AE_WORD(\"{}\")", escape(s)),
+                    Expr::Array(elems, _) => {
+                        let words: Option<Vec<String>> = elems.iter()
+                            .map(|e| match e {
+                                Expr::Int(v, _) => Some(int_lit(*v)),
+                                Expr::Bool(b, _) => Some((*b as i64).to_string()),
+                                _ => None,
+                            })
+                            .collect();
+                        let Some(words) = words else { return Ok(()) };
+                        let storage = if *public { "" } else { "static " };
+                        let words = if words.is_empty() { "0".to_string() } else { words.join(", ") };
+                        self.globals.push_str(&format!("{}const int64_t {}[] = {{ {} }};\n", storage, c_name, words));
+                        self.reserved.insert(c_name.clone());
+                        format!("AE_WORD({})", c_name)
+                    }
+                    _ => return Ok(()),
+                };
+                // Scalars are inlined; `pub` ones also get a symbol
+                if *public && matches!(value_kind(&value), ValueKind::Int) {
+                    self.globals.push_str(&format!("const int64_t {} = {};\n", c_name, value));
+                    self.reserved.insert(c_name);
+                }
+                self.consts.insert(name.clone(), value);
+            }
+            Decl::Static { name, ty, value, public, .. } => {
+                let c_name = mangle(name);
+                let init = match value {
+                    None => Some(0),
+                    Some(Expr::Int(v, _)) => Some(*v),
+                    Some(Expr::Bool(b, _)) => Some(*b as i64),
+                    Some(_) => None,
+                };
+                let thread_local = decl.has_attr("thread_local");
+                let atomic = ty.as_ref().is_some_and(|t| t.atomic_value().is_some());
+                let mut storage = if *public && init.is_some() { String::new() } else { "static ".to_string() };
+                if thread_local {
+                    self.helpers.insert(Helper::ThreadLocal);
+                    storage.push_str("AE_THREAD_LOCAL ");
+                }
+                if atomic {
+                    self.helpers.insert(Helper::Atomic);
+                }
+                match init {
+                    Some(v) => self.globals.push_str(&format!("{}int64_t {} = {};\n", storage, c_name, int_lit(v))),
+                    None => {
+                        self.globals.push_str(&format!("{}int64_t {};\n", storage, c_name));
+                        self.globals.push_str(&format!("{}int {}__ready;\n", storage, c_name));
+                        self.protos.push_str(&format!("static int64_t *{}__addr(void);\n", c_name));
+                    }
+                }
+                self.reserved.insert(c_name.clone());
+                self.statics.insert(name.clone(), StaticInfo {
+                    c_name,
+                    lazy: init.is_none(),
+                    atomic,
+                    ty: ty.as_ref().map(|t| t.atomic_value().unwrap_or_else(|| t.clone())),
+                });
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Prototype of a foreign function with its C types
+    fn extern_proto(&mut self, f: &ExternFunc) -> Result<()> {
+        let mut params = Vec::new();
+        for p in &f.params {
+            params.push(c_type(&p.ty).ok_or_else(|| {
+                anyhow!("Parameter {} of foreign function {} cannot be expressed in C (line {})", p.name, f.name, p.span.line)
+            })?);
+        }
+        if f.variadic {
+            params.push("...".to_string());
+        }
+        let ret = match &f.ret {
+            None | Some(Type::Unit) => "void".to_string(),
+            Some(t) => c_type(t).ok_or_else(|| anyhow!("Return type of foreign function {} cannot be expressed in C", f.name))?,
+        };
+        self.protos.push_str(&format!("{};\n", declarator(&ret, &f.name, &params)));
+        Ok(())
+    }
+
+    fn module(&mut self, module: &TypedModule) -> Result<()> {
+        for typed_decl in &module.decls {
+            match &typed_decl.decl {
+                Decl::Func { name, params, body, public, .. } => {
+                    let c_name = self.names[name].clone();
+                    let exported = self.exports.iter().any(|(n, _)| n == name);
+                    let global = !exported && (!self.hide_private || *public || name == "main");
+                    self.function(&c_name, params, body, global)?;
+                    if let Some((_, sig)) = self.exports.iter().find(|(n, _)| n == name).cloned() {
+                        self.export_wrapper(name, params, &sig);
+                    }
+                }
+                Decl::Impl { trait_name, type_name, methods, .. } if trait_name.as_deref() != Some("Drop") => {
+                    self.self_type = Some(type_name.clone());
+                    for method in methods {
+                        if let Decl::Func { name, params, body, public, .. } = method {
+                            let global = !self.hide_private || *public;
+                            let c_name = self.names[&method_symbol(type_name, name)].clone();
+                            self.function(&c_name, params, body, global)?;
+                        }
+                    }
+                    self.self_type = None;
+                }
+                Decl::Static { name, value: Some(value), .. } if self.statics[name].lazy => {
+                    self.static_init(name, value)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// C `main` calling the program's `main` with `argc` and `argv` if it takes them
+    fn entry(&mut self) -> Result<()> {
+        let Some((params, _)) = self.funcs.get("main") else {
+            bail!("No main function");
+        };
+        self.defs.push_str(&match params.len() {
+            0 => "int main(void) {\n    return (int)aether_main();\n}\n".to_string(),
+            2 => "int main(int argc, char **argv) {\n    return (int)aether_main(argc, AE_WORD(argv));\n}\n".to_string(),
+            n => bail!("main takes no parameters or (argc, argv), not {}", n),
+        });
+        Ok(())
+    }
+
+    /// Assemble the translation unit
+    fn finish(mut self) -> String {
+        let mut out = String::from("/* Generated by aetherc: portable C99, every value is a 64-bit word */\n");
+        out.push_str("#include <stddef.h>\n#include <stdint.h>\n");
+        if self.helpers.contains(&Helper::Threads) {
+            out.push_str("#include <pthread.h>\n");
+        }
+        out.push_str("\n#define AE_PTR(T, w) ((T *)(intptr_t)(w))\n");
+        out.push_str("#define AE_WORD(p) ((int64_t)(intptr_t)(p))\n");
+        out.push_str("/* Wrapping arithmetic: signed overflow is undefined in C */\n");
+        for (name, op) in [("ADD", "+"), ("SUB", "-"), ("MUL", "*")] {
+            out.push_str(&format!("#define AE_{}(a, b) ((int64_t)((uint64_t)(a) {} (uint64_t)(b)))\n", name, op));
+        }
+        out.push_str("#define AE_NEG(a) ((int64_t)(0 - (uint64_t)(a)))\n");
+        out.push_str("#define AE_SHL(a, b) ((int64_t)((uint64_t)(a) << ((b) & 63)))\n");
+        out.push_str("#define AE_SHR(a, b) ((int64_t)((uint64_t)(a) >> ((b) & 63)))\n");
+        if self.helpers.contains(&Helper::ThreadLocal) {
+            out.push_str("#if defined(__STDC_VERSION__) && __STDC_VERSION__ >= 201112L\n#define AE_THREAD_LOCAL _Thread_local\n");
+            out.push_str("#elif defined(_MSC_VER)\n#define AE_THREAD_LOCAL __declspec(thread)\n");
+            out.push_str("#else\n#define AE_THREAD_LOCAL __thread\n#endif\n");
+        }
+        if self.helpers.contains(&Helper::Atomic) {
+            out.push_str("#if defined(__GNUC__) || defined(__clang__)\n");
+            out.push_str("#define AE_LOAD(p) __atomic_load_n((p), __ATOMIC_SEQ_CST)\n");
+            out.push_str("#define AE_STORE(p, v) __atomic_store_n((p), (v), __ATOMIC_SEQ_CST)\n");
+            out.push_str("#define AE_FETCH(op, p, v) ((void)__atomic_fetch_##op((p), (v), __ATOMIC_SEQ_CST))\n");
+            out.push_str("#elif defined(_MSC_VER)\n#include <intrin.h>\n");
+            out.push_str("#define AE_LOAD(p) _InterlockedOr64((volatile long long *)(p), 0)\n");
+            out.push_str("#define AE_STORE(p, v) ((void)_InterlockedExchange64((volatile long long *)(p), (v)))\n");
+            out.push_str("#define AE_FETCH(op, p, v) ((void)AE_FETCH_##op((volatile long long *)(p), (v)))\n");
+            out.push_str("#define AE_FETCH_add _InterlockedExchangeAdd64\n");
+            out.push_str("#define AE_FETCH_sub(p, v) _InterlockedExchangeAdd64((p), AE_NEG(v))\n");
+            out.push_str("#define AE_FETCH_and _InterlockedAnd64\n#define AE_FETCH_or _InterlockedOr64\n");
+            out.push_str("#define AE_FETCH_xor _InterlockedXor64\n");
+            out.push_str("#else\n#error \"atomic statics need GCC, Clang or MSVC\"\n#endif\n");
+        }
+        if self.helpers.contains(&Helper::Closures) {
+            out.push_str("/* Closures are records whose first word is the code, called with the record first */\n");
+            out.push_str("typedef void (*aether_fn)(void);\n");
+            for n in &self.arities {
+                let params: String = (0..*n).map(|_| ", int64_t").collect();
+                out.push_str(&format!("typedef int64_t (*aether_fn{})(void *{});\n", n, params));
+            }
+            out.push_str("#define AE_CALL(n, f) ((aether_fn##n)*AE_PTR(aether_fn, f))\n");
+        }
+        if !self.types.is_empty() {
+            out.push_str("\n/* Types */\n");
+            out.push_str(&self.types);
+        }
+
+        let declared: HashSet<&String> = self.externs.keys().collect();
+        let mut libc_protos = String::new();
+        for f in LIBC.iter().filter(|f| self.libc_used.contains(f.name) && !declared.contains(&f.name.to_string())) {
+            let mut params: Vec<String> = f.params.iter().map(|p| p.to_string()).collect();
+            if f.variadic {
+                params.pop();
+                params.push("...".to_string());
+            }
+            libc_protos.push_str(&format!("{};\n", declarator(f.ret, f.name, &params)));
+        }
+        for (name, argc) in &self.undeclared {
+            match argc {
+                Some(argc) => {
+                    let params: Vec<String> = (0..*argc).map(|_| "int64_t".to_string()).collect();
+                    libc_protos.push_str(&format!("{};\n", declarator("int64_t", name, &params)));
+                }
+                // Called with different argument counts: no prototype
+                None => libc_protos.push_str(&format!("int64_t {}();\n", name)),
+            }
+        }
+        if !libc_protos.is_empty() || !self.protos.is_empty() {
+            out.push_str("\n/* Declarations */\n");
+            out.push_str(&libc_protos);
+            out.push_str(&self.protos);
+        }
+
+        let mut helpers = String::new();
+        if self.helpers.contains(&Helper::FloatBits) {
+            helpers.push_str("/* Floats travel as their bit patterns */\n");
+            helpers.push_str("static inline int64_t aether_bits(double d) {\n    union { double d; int64_t w; } u;\n    u.d = d;\n    return u.w;\n}\n");
+            helpers.push_str("static inline double aether_double(int64_t w) {\n    union { double d; int64_t w; } u;\n    u.w = w;\n    return u.d;\n}\n");
+            // Truncating like the native backends' `%`, without needing libm
+            helpers.push_str("static inline double aether_fmod(double a, double b) {\n    return a - b * (double)(int64_t)(a / b);\n}\n");
+        }
+        if self.helpers.contains(&Helper::Print) {
+            helpers.push_str("static inline void aether_print(int64_t c) {\n    unsigned char b = (unsigned char)c;\n    write(1, &b, 1);\n}\n");
+        }
+        if !helpers.is_empty() {
+            out.push('\n');
+            out.push_str(&helpers);
+        }
+        for name in std::mem::take(&mut self.thunks) {
+            let c_name = self.names[&name].clone();
+            let argc = self.funcs[&name].0.len();
+            let params: String = (0..argc).map(|i| format!(", int64_t a{}", i)).collect();
+            let args: Vec<String> = (0..argc).map(|i| format!("a{}", i)).collect();
+            out.push_str(&format!("\nstatic int64_t {}__thunk(void *env{}) {{\n    (void)env;\n    return {}({});\n}}\n",
+                c_name, params, c_name, args.join(", ")));
+            out.push_str(&format!("static const aether_fn {}__closure = (aether_fn){}__thunk;\n", c_name, c_name));
+        }
+        for name in std::mem::take(&mut self.threads) {
+            let c_name = self.names[&name].clone();
+            let call = match self.funcs[&name].0.len() {
+                0 => format!("(void)arg;\n    return (void *)(intptr_t){}();", c_name),
+                _ => format!("return (void *)(intptr_t){}(AE_WORD(arg));", c_name),
+            };
+            out.push_str(&format!("\nstatic void *{}__thread(void *arg) {{\n    {}\n}}\n", c_name, call));
+        }
+        if !self.globals.is_empty() {
+            out.push_str("\n/* Globals */\n");
+            out.push_str(&self.globals);
+        }
+        out.push_str(&self.defs);
+        out
+    }
+
+    // ========== Functions ==========
+
+    fn begin_function(&mut self, c_name: &str) {
+        self.f = FnState { name: c_name.to_string(), indent: 1, scopes: vec![HashMap::new()], ..FnState::default() };
+    }
+
+    fn function(&mut self, c_name: &str, params: &[Param], body: &Block, global: bool) -> Result<()> {
+        self.begin_function(c_name);
+        let mut c_params = Vec::new();
+        for param in params {
+            let ty = self.resolve_self(&param.ty);
+            let name = self.bind(&param.name, Some(ty));
+            c_params.push(format!("int64_t {}", name));
+        }
+        self.block_body(body, &Target::Return)?;
+        let storage = if global { "" } else { "static " };
+        let signature = declarator("int64_t", c_name, &c_params);
+        self.protos.push_str(&format!("{}{};\n", storage, signature));
+        let body = std::mem::take(&mut self.f.out);
+        self.defs.push_str(&format!("\n{}{} {{\n{}}}\n", storage, signature, body));
+        Ok(())
+    }
+
+    /// `#[export]`: C-typed wrapper around `name__body`
+    fn export_wrapper(&mut self, name: &str, params: &[Param], sig: &ExportSig) {
+        let mut c_params = Vec::new();
+        let mut args = Vec::new();
+        for (param, scalar) in params.iter().zip(&sig.params) {
+            let p = c_ident(&param.name);
+            c_params.push(format!("{} {}", scalar_c_name(*scalar), p));
+            args.push(match scalar {
+                Some(s) if s.llvm == "double" => format!("aether_bits({})", p),
+                Some(s) if s.llvm == "float" => format!("aether_bits((double){})", p),
+                Some(_) => format!("(int64_t){}", p),
+                None => p,
+            });
+        }
+        let call = format!("{}({})", self.names[name], args.join(", "));
+        let (ret, body) = match sig.ret {
+            None => ("void".to_string(), format!("{};", call)),
+            Some(s) => (scalar_c_name(s), format!("return {};", word_to_scalar(&call, s))),
+        };
+        if sig.params.iter().chain(sig.ret.iter()).any(|s| is_float(*s)) {
+            self.helpers.insert(Helper::FloatBits);
+        }
+        let signature = declarator(&ret, name, &c_params);
+        self.protos.push_str(&format!("{};\n", signature));
+        self.defs.push_str(&format!("\n{} {{\n    {}\n}}\n", signature, body));
+    }
+
+    /// `name__addr()`: initialize a lazy static once and return its address
+    fn static_init(&mut self, name: &str, value: &Expr) -> Result<()> {
+        let c_name = self.statics[name].c_name.clone();
+        self.begin_function(&format!("{}__addr", c_name));
+        self.line(&format!("if (!{}__ready) {{", c_name));
+        self.f.indent += 1;
+        let v = self.expr(value)?;
+        self.line(&format!("{} = {};", c_name, v));
+        self.line(&format!("{}__ready = 1;", c_name));
+        self.f.indent -= 1;
+        self.line("}");
+        self.line(&format!("return &{};", c_name));
+        let body = std::mem::take(&mut self.f.out);
+        self.defs.push_str(&format!("\nstatic int64_t *{}__addr(void) {{\n{}}}\n", c_name, body));
+        Ok(())
+    }
+
+    // ========== Locals, temporaries and types ==========
+
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.f.indent {
+            self.f.out.push_str("    ");
+        }
+        self.f.out.push_str(text);
+        self.f.out.push('\n');
+    }
+
+    /// C name not yet used in this function or at file scope
+    fn fresh(&mut self, base: &str) -> String {
+        let base = c_ident(base);
+        let mut name = base.clone();
+        let mut n = 0;
+        while self.f.used.contains(&name) || self.reserved.contains(&name) {
+            n += 1;
+            name = format!("{}_{}", base, n);
+        }
+        self.f.used.insert(name.clone());
+        name
+    }
+
+    fn temp_name(&mut self) -> String {
+        loop {
+            self.f.next_temp += 1;
+            let name = format!("t{}", self.f.next_temp);
+            if !self.f.used.contains(&name) && !self.reserved.contains(&name) {
+                self.f.used.insert(name.clone());
+                self.f.temps.insert(name.clone());
+                return name;
+            }
+        }
+    }
+
+    /// Word temporary holding `value`
+    fn temp(&mut self, value: &str) -> String {
+        let t = self.temp_name();
+        self.line(&format!("int64_t {} = {};", t, value));
+        t
+    }
+
+    /// Bring a new local into scope; returns its C name
+    fn bind(&mut self, name: &str, ty: Option<Type>) -> String {
+        let c_name = self.fresh(name);
+        self.f.locals.insert(c_name.clone());
+        if let Some(scope) = self.f.scopes.last_mut() {
+            scope.insert(name.to_string(), Local { c_name: c_name.clone(), ty });
+        }
+        c_name
+    }
+
+    fn local(&self, name: &str) -> Option<&Local> {
+        self.f.scopes.iter().rev().find_map(|s| s.get(name))
+    }
+
+    /// Keep `v` from changing while `later` is evaluated
+    fn stable(&mut self, v: String, later: &[&Expr]) -> String {
+        let fixed = self.f.temps.contains(&v)
+            || matches!(value_kind(&v), ValueKind::Int | ValueKind::Constant)
+            || (self.f.locals.contains(&v) && !later.iter().any(|e| has_block(e)));
+        if fixed || !later.iter().any(|e| has_effects(e)) {
+            v
+        } else {
+            self.temp(&v)
+        }
+    }
+
+    fn resolve_self(&self, ty: &Type) -> Type {
+        match &self.self_type {
+            Some(s) => with_self(ty, s),
+            None => ty.clone(),
+        }
+    }
+
+    fn float_operands(&self, left: &Expr, right: &Expr) -> bool {
+        self.own.float_operands(left, right, &|name| match self.local(name) {
+            Some(local) => Some(local.ty.clone()),
+            None => self.statics.get(name).map(|s| s.ty.clone()),
+        })
+    }
+
+    /// C double for an operand of Float arithmetic whose word is `v`; Ints are converted
+    fn double(&self, e: &Expr, v: String) -> String {
+        match e {
+            Expr::Float(f, _) if f.is_finite() => format!("{:?}", f),
+            _ if self.type_of(e).is_some_and(|t| t.is_float()) => format!("aether_double({})", v),
+            _ => format!("(double){}", v),
+        }
+    }
+
+    /// Static type of an expression, where generation needs it (fields, methods, `for`)
+    fn type_of(&self, expr: &Expr) -> Option<Type> {
+        self.own.type_of(expr, &|name| match self.local(name) {
+            Some(local) => Some(local.ty.clone()),
+            None => self.statics.get(name).map(|s| s.ty.clone()),
+        })
+    }
+
+    // ========== Blocks and statements ==========
+
+    /// Statements of a block in a new scope, without braces
+    fn block_body(&mut self, block: &Block, target: &Target) -> Result<()> {
+        self.f.scopes.push(HashMap::new());
+        let result = self.stmts(&block.stmts, target);
+        self.f.scopes.pop();
+        result
+    }
+
+    /// `{ ... }` around a block
+    fn braced(&mut self, block: &Block, target: &Target) -> Result<()> {
+        self.line("{");
+        self.f.indent += 1;
+        let result = self.block_body(block, target);
+        self.f.indent -= 1;
+        self.line("}");
+        result
+    }
+
+    /// Send a value to its target
+    fn deliver(&mut self, v: &str, target: &Target) {
+        match target {
+            Target::Discard => {}
+            Target::Assign(dst) => self.line(&format!("{} = {};", dst, v)),
+            Target::Return => self.line(&format!("return {};", v)),
+        }
+    }
+
+    /// The block's value is its final expression, or 0
+    fn stmts(&mut self, stmts: &[Stmt], target: &Target) -> Result<()> {
+        let Some((last, rest)) = stmts.split_last() else {
+            self.deliver("0", target);
+            return Ok(());
+        };
+        for stmt in rest {
+            self.stmt(stmt)?;
+        }
+        match last {
+            Stmt::Expr(e, _) => self.value(e, target),
+            Stmt::Block(b, _) => self.braced(b, target),
+            Stmt::Return(..) | Stmt::Break(..) | Stmt::Continue(..) => self.stmt(last),
+            _ => {
+                self.stmt(last)?;
+                self.deliver("0", target);
+                Ok(())
+            }
+        }
+    }
+
+    /// Evaluate an expression into a target; blocks deliver from each branch
+    fn value(&mut self, e: &Expr, target: &Target) -> Result<()> {
+        match (e, target) {
+            (_, Target::Discard) => self.effect(e),
+            (Expr::If(cond, then_block, else_block, _), _) => self.branch(cond, then_block, else_block.as_deref(), target),
+            (Expr::Match(scrutinee, arms, span), _) => self.match_expr(scrutinee, arms, *span, target),
+            (Expr::Unsafe(block, _) | Expr::Comptime(block, _), _) if single_expr(block).is_none() => self.braced(block, target),
+            _ => {
+                let v = match self.call_text(e)? {
+                    Some(call) => self.call_value_inline(call),
+                    None => self.expr(e)?,
+                };
+                self.deliver(&v, target);
+                Ok(())
+            }
+        }
+    }
+
+    /// Evaluate an expression for its side effects only
+    fn effect(&mut self, e: &Expr) -> Result<()> {
+        match e {
+            Expr::If(cond, then_block, else_block, _) => self.branch(cond, then_block, else_block.as_deref(), &Target::Discard),
+            Expr::Match(scrutinee, arms, span) => self.match_expr(scrutinee, arms, *span, &Target::Discard),
+            Expr::Unsafe(block, _) | Expr::Comptime(block, _) if single_expr(block).is_none() => self.braced(block, &Target::Discard),
+            Expr::Unsafe(block, _) | Expr::Comptime(block, _) => self.effect(single_expr(block).unwrap_or(e)),
+            _ => match self.call_text(e)? {
+                Some(call) => {
+                    self.line(&format!("{};", call.call));
+                    Ok(())
+                }
+                None => self.expr(e).map(|_| ()),
+            },
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<()> {
+        match stmt {
+            Stmt::Let { name, ty, init, .. } => {
+                let ty = match ty {
+                    Some(Type::Infer) | None => init.as_ref().and_then(|e| self.type_of(e)),
+                    Some(t) => Some(self.resolve_self(t)),
+                };
+                // The new name comes into scope after its initializer
+                let c_name = self.fresh(name);
+                match init {
+                    Some(e @ (Expr::If(..) | Expr::Match(..) | Expr::Unsafe(..) | Expr::Comptime(..))) => {
+                        self.line(&format!("int64_t {};", c_name));
+                        self.value(e, &Target::Assign(c_name.clone()))?;
+                    }
+                    Some(e) => {
+                        let v = match self.call_text(e)? {
+                            Some(call) => self.call_value_inline(call),
+                            None => self.expr(e)?,
+                        };
+                        self.line(&format!("int64_t {} = {};", c_name, v));
+                    }
+                    None => self.line(&format!("int64_t {} = 0;", c_name)),
+                }
+                self.f.locals.insert(c_name.clone());
+                if let Some(scope) = self.f.scopes.last_mut() {
+                    scope.insert(name.clone(), Local { c_name, ty });
+                }
+            }
+
+            Stmt::Assign(target, value, span) => self.assign(target, value, *span)?,
+
+            Stmt::Expr(e, _) => self.effect(e)?,
+
+            Stmt::Return(e, _) => match e {
+                Some(e) => self.value(e, &Target::Return)?,
+                None => self.line("return 0;"),
+            },
+
+            Stmt::If(cond, then_block, else_block, _) => {
+                self.branch(cond, then_block, else_block.as_ref(), &Target::Discard)?;
+            }
+
+            Stmt::While(cond, body, _) => {
+                // A condition that needs statements is checked at the top of the body
+                let outer = std::mem::take(&mut self.f.out);
+                self.f.indent += 1;
+                let c = self.expr(cond);
+                self.f.indent -= 1;
+                let setup = std::mem::replace(&mut self.f.out, outer);
+                let c = c?;
+                if setup.is_empty() {
+                    self.line(&format!("while ({}) {{", unparen(&c)));
+                } else {
+                    self.line("for (;;) {");
+                    self.f.out.push_str(&setup);
+                    self.f.indent += 1;
+                    self.line(&format!("if (!{}) break;", c));
+                    self.f.indent -= 1;
+                }
+                self.loop_body(body)?;
+                self.line("}");
+            }
+
+            Stmt::For(var, iter, body, span) => self.for_loop(var, iter, body, *span)?,
+
+            Stmt::Break(span) | Stmt::Continue(span) => {
+                let keyword = if matches!(stmt, Stmt::Break(_)) { "break" } else { "continue" };
+                if self.f.loops == 0 {
+                    bail!("`{}` outside of a loop at line {}", keyword, span.line);
+                }
+                self.line(&format!("{};", keyword));
+            }
+
+            Stmt::Block(block, _) => self.braced(block, &Target::Discard)?,
+        }
+        Ok(())
+    }
+
+    fn loop_body(&mut self, body: &Block) -> Result<()> {
+        self.f.indent += 1;
+        self.f.loops += 1;
+        let result = self.block_body(body, &Target::Discard);
+        self.f.loops -= 1;
+        self.f.indent -= 1;
+        result
+    }
+
+    /// `if` as a statement or expression; each branch delivers its value
+    fn branch(&mut self, cond: &Expr, then_block: &Block, else_block: Option<&Block>, target: &Target) -> Result<()> {
+        let c = self.expr(cond)?;
+        self.line(&format!("if ({}) {{", unparen(&c)));
+        self.f.indent += 1;
+        self.block_body(then_block, target)?;
+        self.f.indent -= 1;
+        match else_block {
+            Some(b) => {
+                self.line("} else {");
+                self.f.indent += 1;
+                self.block_body(b, target)?;
+                self.f.indent -= 1;
+            }
+            None if !matches!(target, Target::Discard) => {
+                self.line("} else {");
+                self.f.indent += 1;
+                self.deliver("0", target);
+                self.f.indent -= 1;
+            }
+            None => {}
+        }
+        self.line("}");
+        Ok(())
+    }
+
+    /// `for x in xs` over a fixed-size array or a vector (`[data, len, cap]`)
+    fn for_loop(&mut self, var: &str, iter: &Expr, body: &Block, span: Span) -> Result<()> {
+        let (source, elem_ty) = for_source(self.type_of(iter), span)?;
+        let (data, len) = match source {
+            ForSource::Array(n) => {
+                let base = self.expr(iter)?;
+                let base = if self.f.temps.contains(&base) { base } else { self.temp(&base) };
+                (base, n.to_string())
+            }
+            ForSource::Vec => {
+                let vec = self.expr(iter)?;
+                let len = self.temp(&format!("AE_PTR(int64_t, {})[1]", vec));
+                let data = self.temp(&format!("AE_PTR(int64_t, {})[0]", vec));
+                (data, len)
+            }
+        };
+        let index = self.fresh("i");
+        self.line(&format!("for (int64_t {i} = 0; {i} < {}; {i}++) {{", len, i = index));
+        self.f.scopes.push(HashMap::new());
+        self.f.indent += 1;
+        let c_var = self.bind(var, elem_ty);
+        self.line(&format!("int64_t {} = AE_PTR(int64_t, {})[{}];", c_var, data, index));
+        self.f.indent -= 1;
+        let result = self.loop_body(body);
+        self.f.scopes.pop();
+        result?;
+        self.line("}");
+        Ok(())
+    }
+
+    fn assign(&mut self, target: &Expr, value: &Expr, span: Span) -> Result<()> {
+        if let Expr::Ident(name, _) = target {
+            if let Some(c_name) = self.local(name).map(|l| l.c_name.clone()) {
+                return self.value(value, &Target::Assign(c_name));
+            }
+            let Some(info) = self.statics.get(name).cloned() else {
+                bail!("Undefined variable {} at line {}", name, span.line);
+            };
+            let place = self.static_place(&info);
+            if let Some((op, operand)) = atomic_update(name, value).filter(|_| info.atomic) {
+                let v = self.expr(operand)?;
+                self.line(&format!("AE_FETCH({}, &{}, {});", atomic_op(op), place, v));
+                return Ok(());
+            }
+            let v = self.expr(value)?;
+            if info.atomic {
+                self.line(&format!("AE_STORE(&{}, {});", place, v));
+            } else {
+                self.line(&format!("{} = {};", place, v));
+            }
+            return Ok(());
+        }
+        if !matches!(target, Expr::Field(..) | Expr::Index(..) | Expr::Unary(UnOp::Deref, ..)) {
+            bail!("Invalid assignment target at line {}", span.line);
+        }
+        let v = self.expr(value)?;
+        let v = self.stable(v, &[target]);
+        let place = self.place(target)?;
+        self.line(&format!("{} = {};", place, v));
+        Ok(())
+    }
+
+    // ========== Expressions ==========
+
+    /// C expression for the value; statements it needs are emitted first
+    fn expr(&mut self, expr: &Expr) -> Result<String> {
+        let v = match expr {
+            Expr::Int(v, _) => int_lit(*v),
+            Expr::Bool(b, _) => (*b as i64).to_string(),
+            Expr::Float(f, _) => {
+                self.helpers.insert(Helper::FloatBits);
+                float_lit(*f)
+            }
+            Expr::String(s, _) => format!("AE_WORD(\"{}\")", escape(s)),
+
+            Expr::Ident(name, span) => {
+                if let Some(local) = self.local(name) {
+                    local.c_name.clone()
+                } else if let Some(v) = self.consts.get(name) {
+                    v.clone()
+                } else if let Some(info) = self.statics.get(name).cloned() {
+                    let place = self.static_place(&info);
+                    if info.atomic { format!("AE_LOAD(&{})", place) } else { place }
+                } else if self.funcs.contains_key(name) {
+                    self.helpers.insert(Helper::Closures);
+                    self.thunks.insert(name.clone());
+                    format!("AE_WORD(&{}__closure)", self.names[name])
+                } else {
+                    bail!("Undefined variable {} at line {}", name, span.line);
+                }
+            }
+
+            Expr::Binary(op, left, right, _) if is_float_op(*op) && self.float_operands(left, right) => {
+                self.helpers.insert(Helper::FloatBits);
+                let a = self.expr(left)?;
+                let a = self.stable(a, &[right]);
+                let a = self.double(left, a);
+                let b = self.expr(right)?;
+                let b = self.double(right, b);
+                let op = match op {
+                    BinOp::Add => "+",
+                    BinOp::Sub => "-",
+                    BinOp::Mul => "*",
+                    BinOp::Div => "/",
+                    BinOp::Mod => return Ok(format!("aether_bits(aether_fmod({}, {}))", a, b)),
+                    BinOp::Eq => return Ok(format!("({} == {})", a, b)),
+                    BinOp::Ne => return Ok(format!("({} != {})", a, b)),
+                    BinOp::Lt => return Ok(format!("({} < {})", a, b)),
+                    BinOp::Le => return Ok(format!("({} <= {})", a, b)),
+                    BinOp::Gt => return Ok(format!("({} > {})", a, b)),
+                    _ => return Ok(format!("({} >= {})", a, b)),
+                };
+                format!("aether_bits({} {} {})", a, op, b)
+            }
+            Expr::Binary(op, left, right, _) => {
+                let a = self.expr(left)?;
+                let a = self.stable(a, &[right]);
+                let b = self.expr(right)?;
+                match op {
+                    BinOp::Add => format!("AE_ADD({}, {})", a, b),
+                    BinOp::Sub => format!("AE_SUB({}, {})", a, b),
+                    BinOp::Mul => format!("AE_MUL({}, {})", a, b),
+                    BinOp::Shl => format!("AE_SHL({}, {})", a, b),
+                    BinOp::Shr => format!("AE_SHR({}, {})", a, b),
+                    // `&&` and `||` evaluate both sides, as in the other backends
+                    BinOp::And | BinOp::BitAnd => format!("({} & {})", a, b),
+                    BinOp::Or | BinOp::BitOr => format!("({} | {})", a, b),
+                    _ => {
+                        let op = match op {
+                            BinOp::Div => "/",
+                            BinOp::Mod => "%",
+                            BinOp::Eq => "==",
+                            BinOp::Ne => "!=",
+                            BinOp::Lt => "<",
+                            BinOp::Le => "<=",
+                            BinOp::Gt => ">",
+                            BinOp::Ge => ">=",
+                            _ => "^",
+                        };
+                        format!("({} {} {})", a, op, b)
+                    }
+                }
+            }
+
+            Expr::Unary(UnOp::Ref | UnOp::RefMut, inner, _) => self.address(inner)?,
+
+            Expr::Unary(op, inner, _) => {
+                let a = self.expr(inner)?;
+                match op {
+                    UnOp::Neg if self.type_of(inner).is_some_and(|t| t.is_float()) => {
+                        format!("aether_bits(-{})", self.double(inner, a))
+                    }
+                    UnOp::Neg => match a.parse::<i64>() {
+                        Ok(v) if v != i64::MIN => int_lit(-v),
+                        _ => format!("AE_NEG({})", a),
+                    },
+                    UnOp::Not => format!("({} ^ 1)", a),
+                    UnOp::BitNot => format!("~{}", a),
+                    _ => format!("*AE_PTR(int64_t, {})", a),
+                }
+            }
+
+            Expr::Call(func, args, span) => match func.as_ref() {
+                Expr::Path(path, _) if self.is_variant(func) => self.variant(&path[0], &path[1], args, *span)?,
+                _ => {
+                    let call = self.call_expr(func, args, *span)?;
+                    self.call_value(call)
+                }
+            },
+
+            Expr::MethodCall(obj, method, args, span) => {
+                let call = self.method_call(obj, method, args, *span)?;
+                self.call_value(call)
+            }
+
+            Expr::Field(..) | Expr::Index(..) => self.place(expr)?,
+
+            // Arrays are heap blocks of words
+            Expr::Array(elems, _) => {
+                self.libc_used.insert("malloc");
+                let t = self.temp_name();
+                self.line(&format!("int64_t *{} = malloc({} * sizeof *{});", t, elems.len(), t));
+                for (i, elem) in elems.iter().enumerate() {
+                    let v = self.expr(elem)?;
+                    self.line(&format!("{}[{}] = {};", t, i, v));
+                }
+                format!("AE_WORD({})", t)
+            }
+
+            // Structs are heap records with a word per field in declaration order
+            Expr::Struct(name, fields, span) => {
+                let layout: Vec<String> = self.own.fields(name)
+                    .ok_or_else(|| anyhow!("Unknown struct {} at line {}", name, span.line))?
+                    .iter().map(|(f, _)| f.clone()).collect();
+                let mut values = Vec::new();
+                for field in &layout {
+                    match fields.iter().find(|(f, _)| f == field) {
+                        Some((_, value)) => values.push(value),
+                        None => bail!("Missing field {} in {} literal at line {}", field, name, span.line),
+                    }
+                }
+                if let Some((extra, _)) = fields.iter().find(|(f, _)| !layout.contains(f)) {
+                    bail!("Unknown field {} in {} literal at line {}", extra, name, span.line);
+                }
+                let (type_name, _) = self.own.named(&Type::Named(name.clone())).unwrap_or((name.clone(), 0));
+                let t = self.alloc(&format!("struct {}", type_name));
+                for (field, value) in layout.iter().zip(values) {
+                    let v = self.expr(value)?;
+                    self.line(&format!("{}->{} = {};", t, c_ident(field), v));
+                }
+                format!("AE_WORD({})", t)
+            }
+
+            Expr::Unsafe(block, _) | Expr::Comptime(block, _) if single_expr(block).is_some() => {
+                self.expr(single_expr(block).unwrap_or(expr))?
+            }
+
+            Expr::If(..) | Expr::Match(..) | Expr::Unsafe(..) | Expr::Comptime(..) => {
+                let t = self.temp_name();
+                self.line(&format!("int64_t {};", t));
+                self.value(expr, &Target::Assign(t.clone()))?;
+                t
+            }
+
+            Expr::Path(path, span) => {
+                if path.len() != 2 {
+                    bail!("Unknown value {} at line {}", path.join("::"), span.line);
+                }
+                self.variant(&path[0], &path[1], &[], *span)?
+            }
+
+            Expr::Spawn(func, args, span) => {
+                let Expr::Ident(name, _) = func.as_ref() else {
+                    bail!("spawn needs a function name at line {}", span.line);
+                };
+                if args.len() > 1 {
+                    bail!("spawn passes at most one argument to the thread at line {}", span.line);
+                }
+                if !self.funcs.contains_key(name) {
+                    bail!("Unknown function {} at line {}", name, span.line);
+                }
+                let arg = match args.first() {
+                    Some(arg) => self.expr(arg)?,
+                    None => "0".to_string(),
+                };
+                self.helpers.insert(Helper::Threads);
+                self.threads.insert(name.clone());
+                let tid = self.temp_name();
+                self.line(&format!("pthread_t {};", tid));
+                self.line(&format!("pthread_create(&{}, NULL, {}__thread, AE_PTR(void, {}));", tid, self.names[name], arg));
+                self.temp(&format!("AE_WORD({})", tid))
+            }
+
+            Expr::Lambda(params, _, body, _) => self.lambda(params, body)?,
+        };
+        Ok(v)
+    }
+
+    /// Heap record for a C type, in a pointer temporary
+    fn alloc(&mut self, c_type: &str) -> String {
+        self.libc_used.insert("malloc");
+        let t = self.temp_name();
+        self.line(&format!("{} *{} = malloc(sizeof *{});", c_type, t, t));
+        t
+    }
+
+    /// `E::V` naming an enum variant
+    fn is_variant(&self, e: &Expr) -> bool {
+        matches!(e, Expr::Path(path, _) if path.len() == 2 && self.enums.contains_key(&path[0]))
+    }
+
+    /// Enum variant: a discriminant, or a tagged union record with its payload
+    fn variant(&mut self, enum_name: &str, variant: &str, args: &[Expr], span: Span) -> Result<String> {
+        let Some(info) = self.enums.get(enum_name).cloned() else {
+            bail!("Unknown value {}::{} at line {}", enum_name, variant, span.line);
+        };
+        let Some(v) = info.variants.iter().find(|v| v.name == variant) else {
+            bail!("No variant {} in {} at line {}", variant, enum_name, span.line);
+        };
+        if args.len() != v.fields.len() {
+            bail!("Wrong number of values for {}::{} at line {}: expected {}, got {}",
+                enum_name, variant, span.line, v.fields.len(), args.len());
+        }
+        let tag = format!("{}_{}", enum_name, variant);
+        if !info.tagged {
+            return Ok(tag);
+        }
+        let t = self.alloc(&format!("struct {}", enum_name));
+        self.line(&format!("{}->tag = {};", t, tag));
+        for (i, arg) in args.iter().enumerate() {
+            let value = self.expr(arg)?;
+            self.line(&format!("{}->as.{}._{} = {};", t, variant, i, value));
+        }
+        Ok(format!("AE_WORD({})", t))
+    }
+
+    /// Storage of a static, running its initializer if needed
+    fn static_place(&mut self, info: &StaticInfo) -> String {
+        if info.lazy {
+            format!("(*{}__addr())", info.c_name)
+        } else {
+            info.c_name.clone()
+        }
+    }
+
+    /// Lvalue of a field, element or dereference
+    fn place(&mut self, place: &Expr) -> Result<String> {
+        match place {
+            Expr::Field(obj, field, span) => {
+                let Some((type_name, derefs)) = self.type_of(obj).and_then(|t| self.own.named(&t))
+                    .filter(|(n, _)| self.own.field(n, field).is_some()) else {
+                    bail!("Unknown field {} at line {}", field, span.line);
+                };
+                let mut base = self.expr(obj)?;
+                for _ in 0..derefs {
+                    base = format!("*AE_PTR(int64_t, {})", base);
+                }
+                Ok(format!("AE_PTR(struct {}, {})->{}", type_name, base, c_ident(field)))
+            }
+            Expr::Index(arr, idx, _) => {
+                let base = self.expr(arr)?;
+                let base = self.stable(base, &[idx]);
+                let i = self.expr(idx)?;
+                Ok(format!("AE_PTR(int64_t, {})[{}]", base, i))
+            }
+            Expr::Unary(UnOp::Deref, inner, _) => Ok(format!("*AE_PTR(int64_t, {})", self.expr(inner)?)),
+            _ => bail!("Invalid place at line {}", place.span().line),
+        }
+    }
+
+    /// Address of a place; other values are copied to a temporary
+    fn address(&mut self, place: &Expr) -> Result<String> {
+        match place {
+            Expr::Ident(name, _) if self.local(name).is_some() => {
+                Ok(format!("AE_WORD(&{})", self.local(name).map(|l| l.c_name.clone()).unwrap_or_default()))
+            }
+            Expr::Ident(name, _) if self.statics.contains_key(name) => {
+                let info = self.statics[name].clone();
+                Ok(if info.lazy { format!("AE_WORD({}__addr())", info.c_name) } else { format!("AE_WORD(&{})", info.c_name) })
+            }
+            Expr::Unary(UnOp::Deref, inner, _) => self.expr(inner),
+            Expr::Field(..) | Expr::Index(..) => Ok(format!("AE_WORD(&{})", self.place(place)?)),
+            _ => {
+                let v = self.expr(place)?;
+                let t = self.temp(&v);
+                Ok(format!("AE_WORD(&{})", t))
+            }
+        }
+    }
+
+    // ========== Calls ==========
+
+    /// Calls of functions and methods; `None` for other expressions
+    fn call_text(&mut self, e: &Expr) -> Result<Option<CallText>> {
+        match e {
+            Expr::Call(func, args, span) if !self.is_variant(func) => self.call_expr(func, args, *span).map(Some),
+            Expr::MethodCall(obj, method, args, span) => self.method_call(obj, method, args, *span).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Result of a call as a temporary (`0` for `void`)
+    fn call_value(&mut self, call: CallText) -> String {
+        let temporary = call.value.is_some() && !call.pure;
+        let v = self.call_value_inline(call);
+        if temporary { self.temp(&v) } else { v }
+    }
+
+    /// Result of a call as an initializer (`0` for `void`)
+    fn call_value_inline(&mut self, call: CallText) -> String {
+        match call.value {
+            Some(v) => v,
+            None => {
+                self.line(&format!("{};", call.call));
+                "0".to_string()
+            }
+        }
+    }
+
+    /// Evaluate arguments left to right
+    fn args(&mut self, args: &[Expr]) -> Result<Vec<String>> {
+        let mut values = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            let v = self.expr(arg)?;
+            let later: Vec<&Expr> = args[i + 1..].iter().collect();
+            values.push(self.stable(v, &later));
+        }
+        Ok(values)
+    }
+
+    /// Record a call of an external function that has no declaration
+    fn undeclared_call(&mut self, name: &str, argc: usize) {
+        match self.undeclared.iter_mut().find(|(n, _)| n == name) {
+            Some((_, arity)) if *arity != Some(argc) => *arity = None,
+            Some(_) => {}
+            None => self.undeclared.push((name.to_string(), Some(argc))),
+        }
+    }
+
+    fn direct(c_name: &str, args: &[String]) -> CallText {
+        let call = format!("{}({})", c_name, args.join(", "));
+        CallText { value: Some(call.clone()), call, pure: false }
+    }
+
+    fn call_expr(&mut self, func: &Expr, args: &[Expr], span: Span) -> Result<CallText> {
+        match func {
+            Expr::Ident(name, _) if self.local(name).is_none() => {
+                if let Some(builtin) = name.strip_prefix("__builtin_") {
+                    return self.builtin(builtin, args, span);
+                }
+                if let Some((params, _)) = self.funcs.get(name).cloned() {
+                    let args = with_defaults(name, &params, args, span)?;
+                    let values = self.args(&args)?;
+                    return Ok(Self::direct(&self.names[name].clone(), &values));
+                }
+                if let Some(f) = self.externs.get(name).cloned() {
+                    return self.extern_call(&f, args, span);
+                }
+                // Undeclared: an external function taking and returning words
+                let values = self.args(args)?;
+                self.undeclared_call(name, values.len());
+                Ok(Self::direct(name, &values))
+            }
+            Expr::Path(path, _) if path.len() == 2 => {
+                let symbol = method_symbol(&path[0], &path[1]);
+                let Some((params, _)) = self.funcs.get(&symbol).cloned() else {
+                    bail!("Unknown function {} at line {}", path.join("::"), span.line);
+                };
+                let args = with_defaults(&path.join("::"), &params, args, span)?;
+                let values = self.args(&args)?;
+                Ok(Self::direct(&self.names[&symbol].clone(), &values))
+            }
+            // A closure record
+            _ => {
+                let f = self.expr(func)?;
+                let later: Vec<&Expr> = args.iter().collect();
+                let f = self.stable(f, &later);
+                let mut values = vec![format!("AE_PTR(void, {})", f)];
+                values.extend(self.args(args)?);
+                self.helpers.insert(Helper::Closures);
+                self.arities.insert(args.len());
+                Ok(Self::direct(&format!("AE_CALL({}, {})", args.len(), f), &values))
+            }
+        }
+    }
+
+    fn method_call(&mut self, obj: &Expr, method: &str, args: &[Expr], span: Span) -> Result<CallText> {
+        let Some((type_name, derefs)) = self.type_of(obj).and_then(|t| self.own.named(&t)) else {
+            bail!("Cannot tell the type of the receiver of {} at line {}", method, span.line);
+        };
+        let symbol = method_symbol(&type_name, method);
+        let Some((params, _)) = self.funcs.get(&symbol).cloned() else {
+            bail!("No method {} on {} at line {}", method, type_name, span.line);
+        };
+        if params.first().is_none_or(|p| p.name != "self") {
+            bail!("{}::{} takes no self; call it as {}::{}() at line {}", type_name, method, type_name, method, span.line);
+        }
+        // Receiver: borrow it for `&self`, otherwise pass the value behind any references
+        let by_ref = params[0].ty.pointee().is_some();
+        let mut receiver = obj.clone();
+        if by_ref && derefs == 0 {
+            receiver = Expr::Unary(UnOp::Ref, Box::new(receiver), span);
+        } else {
+            for _ in 0..derefs - usize::from(by_ref) {
+                receiver = Expr::Unary(UnOp::Deref, Box::new(receiver), span);
+            }
+        }
+        let mut full = vec![receiver];
+        full.extend(with_defaults(&format!("{}::{}", type_name, method), &params[1..], args, span)?);
+        let values = self.args(&full)?;
+        Ok(Self::direct(&self.names[&symbol].clone(), &values))
+    }
+
+    /// Call a foreign function, converting words to and from its C types
+    fn extern_call(&mut self, f: &ExternFunc, args: &[Expr], span: Span) -> Result<CallText> {
+        if args.len() < f.params.len() || (args.len() > f.params.len() && !f.variadic) {
+            bail!("Wrong number of arguments to {} at line {}: expected {}, got {}", f.name, span.line, f.params.len(), args.len());
+        }
+        let values = self.args(args)?;
+        let mut converted = Vec::new();
+        for (i, v) in values.iter().enumerate() {
+            converted.push(match f.params.get(i) {
+                Some(p) => self.arg_to_c(v, &p.ty),
+                // Variadic arguments are passed as words
+                None if self.f.locals.contains(v) || self.f.temps.contains(v) => v.clone(),
+                None => format!("(int64_t){}", v),
+            });
+        }
+        let call = format!("{}({})", f.name, converted.join(", "));
+        let value = match &f.ret {
+            None | Some(Type::Unit) => None,
+            Some(t) => Some(self.result_from_c(&call, t)),
+        };
+        Ok(CallText { call, value, pure: false })
+    }
+
+    /// Word to a C argument of the given Aether type
+    fn arg_to_c(&mut self, v: &str, ty: &Type) -> String {
+        match export_scalar(ty) {
+            Some(s) if s.llvm == "double" || s.llvm == "float" => {
+                self.helpers.insert(Helper::FloatBits);
+                let d = format!("aether_double({})", v);
+                if s.llvm == "float" { format!("(float){}", d) } else { d }
+            }
+            Some(s) if s.llvm == "i1" => format!("({} != 0)", v),
+            Some(s) => format!("({}){}", s.c_name, v),
+            None => match c_type(ty).as_deref() {
+                Some(t) if t.ends_with('*') => format!("AE_PTR({}, {})", t.trim_end_matches(" *"), v),
+                _ => v.to_string(),
+            },
+        }
+    }
+
+    /// C result of the given Aether type to a word
+    fn result_from_c(&mut self, call: &str, ty: &Type) -> String {
+        match export_scalar(ty) {
+            Some(s) if s.llvm == "double" || s.llvm == "float" => {
+                self.helpers.insert(Helper::FloatBits);
+                if s.llvm == "float" { format!("aether_bits((double){})", call) } else { format!("aether_bits({})", call) }
+            }
+            _ if c_type(ty).is_some_and(|t| t.ends_with('*')) => format!("AE_WORD({})", call),
+            _ => call.to_string(),
+        }
+    }
+
+    fn builtin(&mut self, builtin: &str, args: &[Expr], span: Span) -> Result<CallText> {
+        let arity = match builtin {
+            "load8" | "load16" | "load32" | "load64" | "print" => 1,
+            "store8" | "store16" | "store32" | "store64" => 2,
+            _ => 0,
+        };
+        if args.len() < arity {
+            bail!("Wrong number of arguments to __builtin_{} at line {}: expected {}, got {}", builtin, span.line, arity, args.len());
+        }
+        let width = |bits: &str| match bits {
+            "8" => "uint8_t",
+            "16" => "uint16_t",
+            "32" => "uint32_t",
+            _ => "int64_t",
+        };
+        if let Some(bits) = builtin.strip_prefix("load") {
+            let base = self.expr(&args[0])?;
+            let load = match width(bits) {
+                "int64_t" => format!("*AE_PTR(int64_t, {})", base),
+                w => format!("(int64_t)*AE_PTR({}, {})", w, base),
+            };
+            return Ok(CallText { call: load.clone(), value: Some(load), pure: true });
+        }
+        if let Some(bits) = builtin.strip_prefix("store") {
+            let base = self.expr(&args[0])?;
+            let base = self.stable(base, &[&args[1]]);
+            let v = self.expr(&args[1])?;
+            let store = match width(bits) {
+                "int64_t" => format!("*AE_PTR(int64_t, {}) = {}", base, v),
+                w => format!("*AE_PTR({w}, {}) = ({w}){}", base, v, w = w),
+            };
+            return Ok(CallText { call: store, value: None, pure: false });
+        }
+        if builtin == "print" {
+            // One byte to stdout
+            let v = self.expr(&args[0])?;
+            self.helpers.insert(Helper::Print);
+            self.libc_used.insert("write");
+            return Ok(CallText { call: format!("aether_print({})", v), value: None, pure: false });
+        }
+        // Others stay external calls by name, as with the other backends
+        let Some(f) = LIBC.iter().find(|f| f.name == builtin && !matches!(f.name, "abort" | "strcmp")) else {
+            let name = format!("__builtin_{}", builtin);
+            let values = self.args(args)?;
+            if let Some(c_name) = self.names.get(&name) {
+                return Ok(Self::direct(&c_name.clone(), &values));
+            }
+            self.undeclared_call(&name, values.len());
+            return Ok(Self::direct(&name, &values));
+        };
+        let mut args = args.to_vec();
+        if f.name == "open" && args.len() == 2 {
+            args.push(Expr::Int(0, span));
+        }
+        if args.len() != f.params.len() {
+            bail!("Wrong number of arguments to __builtin_{} at line {}: expected {}, got {}", builtin, span.line, f.params.len(), args.len());
+        }
+        self.libc_used.insert(f.name);
+        let values = self.args(&args)?;
+        let converted: Vec<String> = values.iter().zip(f.params)
+            .map(|(v, ty)| match ty.strip_suffix(" *") {
+                Some(pointee) => format!("AE_PTR({}, {})", pointee, v),
+                None => format!("({}){}", ty, v),
+            })
+            .collect();
+        let call = format!("{}({})", f.name, converted.join(", "));
+        let value = match f.ret {
+            "void" => None,
+            "void *" => Some(format!("AE_WORD({})", call)),
+            _ => Some(call.clone()),
+        };
+        Ok(CallText { call, value, pure: false })
+    }
+
+    // ========== Closures ==========
+
+    /// Closure record with the captured locals copied in, and its code as a static function
+    fn lambda(&mut self, params: &[Param], body: &Expr) -> Result<String> {
+        let mut names = Vec::new();
+        free_idents(body, &mut names);
+        let captures: Vec<(String, Local)> = names.into_iter()
+            .filter(|n| !params.iter().any(|p| &p.name == n))
+            .filter_map(|n| self.local(&n).cloned().map(|l| (n, l)))
+            .collect();
+        let name = format!("{}__lambda{}", self.f.name, self.lambdas);
+        self.lambdas += 1;
+        self.helpers.insert(Helper::Closures);
+        self.arities.insert(params.len());
+
+        let mut record = format!("struct {}_env {{ aether_fn code;", name);
+        for (_, local) in &captures {
+            record.push_str(&format!(" int64_t {};", local.c_name));
+        }
+        self.types.push_str(&record);
+        self.types.push_str(" };\n");
+        let t = self.alloc(&format!("struct {}_env", name));
+        self.line(&format!("{}->code = (aether_fn){};", t, name));
+        for (_, local) in &captures {
+            self.line(&format!("{}->{c} = {c};", t, c = local.c_name));
+        }
+
+        let outer = std::mem::take(&mut self.f);
+        self.begin_function(&name);
+        let env = self.fresh("env");
+        let mut c_params = vec![format!("void *{}", env)];
+        for param in params {
+            let ty = match &param.ty {
+                Type::Infer => None,
+                t => Some(self.resolve_self(t)),
+            };
+            let c_name = self.bind(&param.name, ty);
+            c_params.push(format!("int64_t {}", c_name));
+        }
+        if captures.is_empty() {
+            self.line(&format!("(void){};", env));
+        }
+        for (n, local) in &captures {
+            let c_name = self.bind(n, local.ty.clone());
+            self.line(&format!("int64_t {} = ((struct {}_env *){})->{};", c_name, name, env, local.c_name));
+        }
+        let result = self.value(body, &Target::Return);
+        let code = std::mem::replace(&mut self.f, outer);
+        result?;
+        let signature = declarator("int64_t", &name, &c_params);
+        self.protos.push_str(&format!("static {};\n", signature));
+        self.defs.push_str(&format!("\nstatic {} {{\n{}}}\n", signature, code.out));
+        Ok(format!("AE_WORD({})", t))
+    }
+
+    // ========== Patterns ==========
+
+    /// `match`: arms become an `if` chain; guards jump to the end once an arm runs
+    fn match_expr(&mut self, scrutinee: &Expr, arms: &[MatchArm], span: Span, target: &Target) -> Result<()> {
+        let ty = self.type_of(scrutinee);
+        let s = self.expr(scrutinee)?;
+        let s = if self.f.temps.contains(&s) || self.f.locals.contains(&s) { s } else { self.temp(&s) };
+        let guarded = arms.iter().any(|a| a.guard.is_some());
+        let end = guarded.then(|| self.fresh("match_end"));
+        let mut jumps = false;
+        let mut exhaustive = false;
+        for (i, arm) in arms.iter().enumerate() {
+            let mut conds = Vec::new();
+            let mut binds = Vec::new();
+            self.pattern(&arm.pattern, &s, ty.clone(), &mut conds, &mut binds, span)?;
+            let test = conds.join(" && ");
+            match (guarded || i == 0, conds.is_empty()) {
+                (true, true) => self.line("{"),
+                (true, false) => self.line(&format!("if ({}) {{", test)),
+                (false, true) => self.line("} else {"),
+                (false, false) => self.line(&format!("}} else if ({}) {{", test)),
+            }
+            self.f.indent += 1;
+            self.f.scopes.push(HashMap::new());
+            for (name, value, ty) in binds {
+                let c_name = self.bind(&name, ty);
+                self.line(&format!("int64_t {} = {};", c_name, value));
+            }
+            let jump = guarded && !matches!(target, Target::Return);
+            if let Some(guard) = &arm.guard {
+                let g = self.expr(guard)?;
+                self.line(&format!("if ({}) {{", unparen(&g)));
+                self.f.indent += 1;
+            }
+            self.value(&arm.body, target)?;
+            if jump {
+                self.line(&format!("goto {};", end.clone().unwrap_or_default()));
+                jumps = true;
+            }
+            if arm.guard.is_some() {
+                self.f.indent -= 1;
+                self.line("}");
+            }
+            self.f.scopes.pop();
+            self.f.indent -= 1;
+            if guarded {
+                self.line("}");
+            }
+            if conds.is_empty() && arm.guard.is_none() {
+                exhaustive = true;
+                break;
+            }
+        }
+        if !guarded {
+            if !exhaustive {
+                self.libc_used.insert("abort");
+                self.line("} else {");
+                self.line("    abort();");
+            }
+            self.line("}");
+        } else if !exhaustive {
+            self.libc_used.insert("abort");
+            self.line("abort();");
+        }
+        if let Some(end) = end.filter(|_| jumps) {
+            self.f.indent -= 1;
+            self.line(&format!("{}:;", end));
+            self.f.indent += 1;
+        }
+        Ok(())
+    }
+
+    /// Tests and bindings of a pattern against the word `s`
+    fn pattern(&mut self, pattern: &Pattern, s: &str, ty: Option<Type>, conds: &mut Vec<String>,
+               binds: &mut Vec<(String, String, Option<Type>)>, span: Span) -> Result<()> {
+        match pattern {
+            Pattern::Wildcard => {}
+            Pattern::Ident(name) => binds.push((name.clone(), s.to_string(), ty)),
+            Pattern::Literal(Expr::String(text, _)) => {
+                self.libc_used.insert("strcmp");
+                conds.push(format!("strcmp(AE_PTR(const char, {}), \"{}\") == 0", s, escape(text)));
+            }
+            Pattern::Literal(e) => {
+                let v = self.expr(e)?;
+                conds.push(format!("{} == {}", s, v));
+            }
+            Pattern::Enum(enum_name, variant, subs) => {
+                let Some(info) = self.enums.get(enum_name).cloned() else {
+                    bail!("Unknown enum {} in pattern at line {}", enum_name, span.line);
+                };
+                let Some(v) = info.variants.iter().find(|v| &v.name == variant) else {
+                    bail!("No variant {} in {} at line {}", variant, enum_name, span.line);
+                };
+                if subs.len() != v.fields.len() {
+                    bail!("Pattern {}::{} at line {} needs {} values, got {}", enum_name, variant, span.line, v.fields.len(), subs.len());
+                }
+                let tag = format!("{}_{}", enum_name, variant);
+                if !info.tagged {
+                    conds.push(format!("{} == {}", s, tag));
+                    return Ok(());
+                }
+                let record = format!("AE_PTR(struct {}, {})", enum_name, s);
+                conds.push(format!("{}->tag == {}", record, tag));
+                for (i, (sub, field_ty)) in subs.iter().zip(&v.fields).enumerate() {
+                    let field = format!("{}->as.{}._{}", record, variant, i);
+                    self.pattern(sub, &field, Some(field_ty.clone()), conds, binds, span)?;
+                }
+            }
+            Pattern::Struct(name, fields) => {
+                let Some((type_name, _)) = self.own.named(&Type::Named(name.clone())).filter(|(n, _)| self.own.fields(n).is_some()) else {
+                    bail!("Unknown struct {} in pattern at line {}", name, span.line);
+                };
+                for (field, sub) in fields {
+                    let Some(field_ty) = self.own.field(&type_name, field).cloned() else {
+                        bail!("No field {} in {} at line {}", field, type_name, span.line);
+                    };
+                    let place = format!("AE_PTR(struct {}, {})->{}", type_name, s, c_ident(field));
+                    self.pattern(sub, &place, Some(field_ty), conds, binds, span)?;
+                }
+            }
+            Pattern::Tuple(_) => bail!("Tuple patterns need tuple values, which the C backend does not have (line {})", span.line),
+        }
+        Ok(())
+    }
+}
+
+// ========== C spelling ==========
+
+/// Identifier that is not a C keyword or a name the generated code uses
+/// (compiler-made names such as `x.drop.1` become `x_drop_1`)
+fn c_ident(name: &str) -> String {
+    if name.contains(|c: char| !c.is_ascii_alphanumeric() && c != '_') {
+        return c_ident(&name.replace(|c: char| !c.is_ascii_alphanumeric() && c != '_', "_"));
+    }
+    if name.starts_with("aether_") || name.starts_with("ae_") || name.starts_with("AE_") {
+        format!("v_{}", name)
+    } else if KEYWORDS.contains(&name) || LIBC.iter().any(|f| f.name == name) {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// C symbol of a function, method, constant or static of the program, kept
+/// apart from libc and whatever else the program links with (`#[export]`
+/// wrappers and the C `main` keep their names)
+fn mangle(name: &str) -> String {
+    format!("ae_{}", name.replace(|c: char| !c.is_ascii_alphanumeric() && c != '_', "_"))
+}
+
+/// `ret name(params)`, with `void` for no parameters
+fn declarator(ret: &str, name: &str, params: &[String]) -> String {
+    let params = if params.is_empty() { "void".to_string() } else { params.join(", ") };
+    if ret.ends_with('*') {
+        format!("{}{}({})", ret, name, params)
+    } else {
+        format!("{} {}({})", ret, name, params)
+    }
+}
+
+/// C type of an Aether type at a foreign boundary
+fn c_type(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Named(n) if n == "String" => Some("const char *".to_string()),
+        Type::Named(n) => match c_scalar(n) {
+            Some(s) => Some(scalar_c_name(Some(s))),
+            None => Some("void *".to_string()),
+        },
+        Type::Func(..) | Type::Unit | Type::Infer => None,
+        _ => Some("void *".to_string()),
+    }
+}
+
+/// C spelling of an export scalar; plain words are `int64_t`
+fn scalar_c_name(scalar: Option<CScalar>) -> String {
+    match scalar {
+        Some(s) if s.c_name == "bool" => "_Bool".to_string(),
+        Some(s) => s.c_name.to_string(),
+        None => "int64_t".to_string(),
+    }
+}
+
+fn is_float(scalar: Option<CScalar>) -> bool {
+    scalar.is_some_and(|s| matches!(s.llvm, "double" | "float"))
+}
+
+/// A word returned by a body function, as the wrapper's C return type
+fn word_to_scalar(call: &str, scalar: Option<CScalar>) -> String {
+    match scalar {
+        Some(s) if s.llvm == "double" => format!("aether_double({})", call),
+        Some(s) if s.llvm == "float" => format!("(float)aether_double({})", call),
+        Some(s) if s.llvm == "i1" => format!("{} != 0", call),
+        Some(s) => format!("({}){}", s.c_name, call),
+        None => call.to_string(),
+    }
+}
+
+fn int_lit(v: i64) -> String {
+    if v == i64::MIN {
+        "INT64_MIN".to_string()
+    } else if i32::try_from(v).is_ok() {
+        v.to_string()
+    } else {
+        format!("INT64_C({})", v)
+    }
+}
+
+/// Float literal as its bit pattern
+fn float_lit(f: f64) -> String {
+    if f.is_finite() {
+        format!("aether_bits({:?})", f)
+    } else {
+        format!("{} /* {} */", int_lit(f.to_bits() as i64), f)
+    }
+}
+
+/// Body of a C string literal
+fn escape(s: &str) -> String {
+    let mut out = String::new();
+    for b in s.bytes() {
+        match b {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            // Keeps `??` from forming trigraphs
+            b'?' => out.push_str("\\?"),
+            b'\n' => out.push_str("\\n"),
+            b'\t' => out.push_str("\\t"),
+            b'\r' => out.push_str("\\r"),
+            0x20..=0x7e => out.push(b as char),
+            _ => out.push_str(&format!("\\{:03o}", b)),
+        }
+    }
+    out
+}
+
+enum ValueKind {
+    Int,
+    /// Literal or address that cannot change
+    Constant,
+    Other,
+}
+
+fn value_kind(v: &str) -> ValueKind {
+    let plain = |s: &str| s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if v.parse::<i64>().is_ok() || v == "INT64_MIN" || (v.starts_with("INT64_C(") && !v.contains(' ')) {
+        ValueKind::Int
+    } else if v.starts_with("AE_WORD(\"") || v.starts_with("aether_bits(")
+        || v.strip_prefix("AE_WORD(").and_then(|r| r.strip_suffix(')')).is_some_and(plain) {
+        ValueKind::Constant
+    } else {
+        ValueKind::Other
+    }
+}
+
+/// `c` without one pair of enclosing parentheses
+fn unparen(c: &str) -> &str {
+    let Some(inner) = c.strip_prefix('(').and_then(|r| r.strip_suffix(')')) else {
+        return c;
+    };
+    let mut depth = 0;
+    for ch in inner.chars() {
+        match ch {
+            '(' => depth += 1,
+            ')' if depth == 0 => return c,
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    inner
+}
+
+/// The expression of a block that is nothing else
+fn single_expr(block: &Block) -> Option<&Expr> {
+    match block.stmts.as_slice() {
+        [Stmt::Expr(e, _)] => Some(e),
+        _ => None,
+    }
+}
+
+/// Whether evaluating the expression runs a block, which could assign locals
+fn has_block(e: &Expr) -> bool {
+    match e {
+        Expr::If(..) | Expr::Unsafe(..) | Expr::Comptime(..) | Expr::Match(..) => true,
+        Expr::Binary(_, l, r, _) | Expr::Index(l, r, _) => has_block(l) || has_block(r),
+        Expr::Unary(_, e, _) | Expr::Field(e, _, _) => has_block(e),
+        Expr::Call(f, args, _) | Expr::Spawn(f, args, _) => has_block(f) || args.iter().any(has_block),
+        Expr::MethodCall(obj, _, args, _) => has_block(obj) || args.iter().any(has_block),
+        Expr::Array(elems, _) => elems.iter().any(has_block),
+        Expr::Struct(_, fields, _) => fields.iter().any(|(_, e)| has_block(e)),
+        _ => false,
+    }
+}
+
+/// Whether evaluating the expression could change locals or memory
+fn has_effects(e: &Expr) -> bool {
+    match e {
+        Expr::Call(..) | Expr::MethodCall(..) | Expr::Spawn(..) => true,
+        Expr::Binary(_, l, r, _) | Expr::Index(l, r, _) => has_effects(l) || has_effects(r),
+        Expr::Unary(_, e, _) | Expr::Field(e, _, _) => has_effects(e),
+        Expr::Array(elems, _) => elems.iter().any(has_effects),
+        Expr::Struct(_, fields, _) => fields.iter().any(|(_, e)| has_effects(e)),
+        _ => has_block(e),
+    }
+}
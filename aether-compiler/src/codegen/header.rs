@@ -0,0 +1,227 @@
+//! C Header Generator for Aether
+//! Emits a `.h` file describing every public `#[export]` function and struct,
+//! so compiled Aether objects can be called from C, C++ and Rust.
+//!
+//! Structs mirror the backends' layout: every field is an 8-byte word, and a
+//! struct value (field, parameter or return) is a pointer to its heap block.
+
+use std::collections::BTreeSet;
+use anyhow::{anyhow, Result};
+use crate::ast::{Decl, Type};
+use crate::typechecker::TypedModule;
+
+/// C ABI description of a scalar Aether type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CScalar {
+    /// C spelling: int32_t, double, ...
+    pub c_name: &'static str,
+    /// LLVM type used at the C boundary
+    pub llvm: &'static str,
+    pub signed: bool,
+}
+
+/// Scalar types with a fixed C representation
+pub fn c_scalar(name: &str) -> Option<CScalar> {
+    let (c_name, llvm, signed) = match name {
+        "Int" | "Int64" => ("int64_t", "i64", true),
+        "UInt64" => ("uint64_t", "i64", false),
+        "Int32" => ("int32_t", "i32", true),
+        "UInt32" => ("uint32_t", "i32", false),
+        "Int16" => ("int16_t", "i16", true),
+        "UInt16" => ("uint16_t", "i16", false),
+        "Int8" => ("int8_t", "i8", true),
+        "UInt8" => ("uint8_t", "i8", false),
+        "Char" => ("uint32_t", "i32", false),
+        "Bool" => ("bool", "i1", false),
+        "Float" => ("double", "double", true),
+        "Float32" => ("float", "float", true),
+        _ => return None,
+    };
+    Some(CScalar { c_name, llvm, signed })
+}
+
+/// Where a type appears; decides how scalars are spelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Position {
+    /// Parameter or return: scalars use their C ABI type
+    Signature,
+    /// In memory (struct field, pointee): every value is an 8-byte word
+    Word,
+}
+
+/// C spelling of a scalar held in an 8-byte word
+fn word_name(scalar: CScalar) -> &'static str {
+    match scalar.llvm {
+        // Float32 is widened to a double when stored
+        "double" | "float" => "double",
+        _ if scalar.signed => "int64_t",
+        _ => "uint64_t",
+    }
+}
+
+/// Header generator state
+struct HeaderGen {
+    /// Struct names visible to C (exported)
+    known_structs: BTreeSet<String>,
+    /// Structs referenced without being exported
+    opaque: BTreeSet<String>,
+}
+
+impl HeaderGen {
+    /// Format `ty name` as a C declaration (handles function pointers)
+    fn declare(&mut self, ty: &Type, name: &str, pos: Position) -> Result<String> {
+        let decl = match ty {
+            Type::Func(params, ret) => {
+                let mut ps = Vec::new();
+                for p in params {
+                    ps.push(self.c_type(p, Position::Signature)?);
+                }
+                if ps.is_empty() {
+                    ps.push("void".into());
+                }
+                let ret = match ret.as_ref() {
+                    Some(r) => self.c_type(r, Position::Signature)?,
+                    None => "void".into(),
+                };
+                format!("{} (*{})({})", ret, name, ps.join(", "))
+            }
+            _ => {
+                let c_ty = self.c_type(ty, pos)?;
+                let sep = if c_ty.ends_with('*') { "" } else { " " };
+                format!("{}{}{}", c_ty, sep, name)
+            }
+        };
+        Ok(decl)
+    }
+
+    /// C spelling of a type
+    fn c_type(&mut self, ty: &Type, pos: Position) -> Result<String> {
+        Ok(match ty {
+            Type::Unit => "void".into(),
+            Type::Named(n) if n == "String" => "const char *".into(),
+            Type::Named(n) => match c_scalar(n) {
+                Some(s) if pos == Position::Word => word_name(s).into(),
+                Some(s) => s.c_name.into(),
+                None => {
+                    // Struct values are pointers to heap blocks of words
+                    if !self.known_structs.contains(n) {
+                        self.opaque.insert(n.clone());
+                    }
+                    format!("{} *", n)
+                }
+            },
+            // Arrays are pointers to their first word
+            Type::Ptr(inner, _) | Type::RawPtr(inner) | Type::Ref(_, inner, _) | Type::Array(inner, _) | Type::ConstArray(inner, _) => {
+                let inner = self.c_type(inner, Position::Word)?;
+                if inner.ends_with('*') { format!("{}*", inner) } else { format!("{} *", inner) }
+            }
+            Type::Func(..) => {
+                // Function pointer without a name: int64_t (*)(int64_t)
+                self.declare(ty, "", pos)?
+            }
+            Type::Generic(name, _) => {
+                return Err(anyhow!("generic type {} cannot cross the C boundary", name));
+            }
+            Type::Infer => return Err(anyhow!("exported items need explicit types")),
+        })
+    }
+}
+
+fn is_export(decl: &Decl) -> Result<bool> {
+    if !decl.has_attr("export") {
+        return Ok(false);
+    }
+    match decl {
+        Decl::Func { name, public, generics, span, .. } | Decl::Struct { name, public, generics, span, .. } => {
+            if !public {
+                return Err(anyhow!("#[export] item {} must be pub at line {}", name, span.line));
+            }
+            if !generics.is_empty() {
+                return Err(anyhow!("#[export] item {} cannot be generic at line {}", name, span.line));
+            }
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Generate a C header for all exported items of a module
+pub fn generate(module: &TypedModule, guard_name: &str) -> Result<String> {
+    let mut gen = HeaderGen {
+        known_structs: BTreeSet::new(),
+        opaque: BTreeSet::new(),
+    };
+
+    let mut exported = Vec::new();
+    for typed_decl in &module.decls {
+        if is_export(&typed_decl.decl)? {
+            if let Decl::Struct { name, .. } = &typed_decl.decl {
+                gen.known_structs.insert(name.clone());
+            }
+            exported.push(&typed_decl.decl);
+        }
+    }
+
+    let mut ordered: Vec<&Decl> = exported.iter().copied().filter(|d| matches!(d, Decl::Struct { .. })).collect();
+    ordered.extend(exported.iter().copied().filter(|d| matches!(d, Decl::Func { .. })));
+
+    let mut structs = String::new();
+    let mut funcs = String::new();
+    for decl in ordered {
+        match decl {
+            Decl::Struct { name, fields, .. } => {
+                structs.push_str(&format!("struct {} {{\n", name));
+                for field in fields {
+                    let line = gen.declare(&field.ty, &field.name, Position::Word)
+                        .map_err(|e| anyhow!("{}.{}: {}", name, field.name, e))?;
+                    structs.push_str(&format!("    {};\n", line));
+                }
+                structs.push_str("};\n\n");
+            }
+            Decl::Func { name, params, ret, .. } => {
+                let mut ps = Vec::new();
+                for p in params {
+                    let line = gen.declare(&p.ty, &p.name, Position::Signature)
+                        .map_err(|e| anyhow!("{}({}): {}", name, p.name, e))?;
+                    ps.push(line);
+                }
+                if ps.is_empty() {
+                    ps.push("void".into());
+                }
+                let ret = match ret {
+                    Some(r) => gen.c_type(r, Position::Signature).map_err(|e| anyhow!("{}: {}", name, e))?,
+                    None => "void".into(),
+                };
+                let sep = if ret.ends_with('*') { "" } else { " " };
+                funcs.push_str(&format!("{}{}{}({});\n", ret, sep, name, ps.join(", ")));
+            }
+            _ => {}
+        }
+    }
+
+    let guard: String = guard_name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+
+    let mut out = String::new();
+    out.push_str("/* Generated by aetherc --emit=c-header. Do not edit. */\n");
+    out.push_str(&format!("#ifndef {}_H\n#define {}_H\n\n", guard, guard));
+    out.push_str("#include <stdbool.h>\n#include <stdint.h>\n\n");
+    out.push_str("#ifdef __cplusplus\nextern \"C\" {\n#endif\n\n");
+    // Forward typedefs let structs refer to themselves and to opaque types
+    let typedefs: BTreeSet<&String> = gen.known_structs.iter().chain(&gen.opaque).collect();
+    for name in &typedefs {
+        out.push_str(&format!("typedef struct {} {};\n", name, name));
+    }
+    if !typedefs.is_empty() {
+        out.push('\n');
+    }
+    out.push_str(&structs);
+    out.push_str(&funcs);
+    if !funcs.is_empty() {
+        out.push('\n');
+    }
+    out.push_str("#ifdef __cplusplus\n}\n#endif\n\n");
+    out.push_str(&format!("#endif /* {}_H */\n", guard));
+    Ok(out)
+}
@@ -7,6 +7,25 @@
 //! - Linked with `clang`
 
 use std::collections::HashMap;
+use crate::borrowck::ownership::Ownership;
+use super::header::{c_scalar, CScalar};
+
+/// C ABI signature of an `#[export]` function (`None` = passed as i64)
+#[derive(Debug, Clone)]
+struct ExportSig {
+    params: Vec<Option<CScalar>>,
+    /// Return type; `None` in the outer option means `void`
+    ret: Option<Option<CScalar>>,
+}
+
+/// Storage of a `static`
+#[derive(Debug, Clone, Copy)]
+struct StaticInfo {
+    /// Initialized on first access through `@name.addr()`
+    lazy: bool,
+    /// Declared with an atomic type: accessed with seq_cst loads, stores and `atomicrmw`
+    atomic: bool,
+}
 
 /// LLVM IR Generator
 pub struct LLVMCodeGen {
@@ -26,6 +45,22 @@ pub struct LLVMCodeGen {
     label_counter: usize,
     /// Current function return type
     current_ret_type: String,
+    /// Exported functions with C-typed signatures
+    exports: HashMap<String, ExportSig>,
+    /// C return type of the current exported function, if narrower than i64
+    current_ret_abi: Option<CScalar>,
+    /// Give non-`pub` functions internal linkage (library crate types)
+    hide_private: bool,
+    /// Folded `const` values, used as immediates
+    consts: HashMap<String, i64>,
+    /// `const` arrays emitted as globals (name -> length)
+    const_arrays: HashMap<String, usize>,
+    /// Module-level statics
+    statics: HashMap<String, StaticInfo>,
+    /// Struct layouts and function signatures
+    own: Ownership,
+    /// Declared or inferred types of the current function's locals
+    local_types: HashMap<String, Option<Type>>,
 }
 
 impl LLVMCodeGen {
@@ -39,6 +74,14 @@ impl LLVMCodeGen {
             locals: HashMap::new(),
             label_counter: 0,
             current_ret_type: "i64".to_string(),
+            exports: HashMap::new(),
+            current_ret_abi: None,
+            hide_private: false,
+            consts: HashMap::new(),
+            const_arrays: HashMap::new(),
+            statics: HashMap::new(),
+            own: Ownership::default(),
+            local_types: HashMap::new(),
         }
     }
     
@@ -88,18 +131,24 @@ impl LLVMCodeGen {
         self.emit_raw("");
     }
     
+    /// Only `pub` functions stay visible outside the object (for libraries)
+    pub fn set_hide_private(&mut self, hide: bool) {
+        self.hide_private = hide;
+    }
+    
     /// Generate function start
-    pub fn emit_func_start(&mut self, name: &str, params: &[(&str, &str)], ret_type: &str) {
+    pub fn emit_func_start(&mut self, name: &str, params: &[(&str, &str)], ret_type: &str, linkage: &str) {
         self.var_counter = 0;
         self.locals.clear();
         self.current_ret_type = ret_type.to_string();
+        self.current_ret_abi = None;
         
         let params_str: Vec<String> = params.iter()
             .map(|(name, ty)| format!("{} %{}", ty, name))
             .collect();
         
-        self.emit_raw(&format!("define {} @{}({}) {{", 
-            ret_type, name, params_str.join(", ")));
+        self.emit_raw(&format!("define {}{} @{}({}) {{", 
+            linkage, ret_type, name, params_str.join(", ")));
         self.emit_raw("entry:");
         
         // Allocate space for mutable parameters
@@ -122,7 +171,51 @@ impl LLVMCodeGen {
     
     /// Generate return statement
     pub fn emit_return(&mut self, value: &str) {
-        self.emit(&format!("ret {} {}", self.current_ret_type, value));
+        if self.current_ret_type == "void" {
+            self.emit("ret void");
+        } else if let Some(abi) = self.current_ret_abi {
+            // C return type of an exported function
+            let v = self.lower_to_c_abi(value, abi);
+            self.emit(&format!("ret {} {}", abi.llvm, v));
+        } else {
+            self.emit(&format!("ret {} {}", self.current_ret_type, value));
+        }
+    }
+    
+    /// Convert an i64 Aether value to its C ABI representation
+    fn lower_to_c_abi(&mut self, value: &str, ty: CScalar) -> String {
+        let result = self.new_var();
+        match ty.llvm {
+            "i64" => return value.to_string(),
+            "i1" => self.emit(&format!("{} = icmp ne i64 {}, 0", result, value)),
+            "double" => self.emit(&format!("{} = bitcast i64 {} to double", result, value)),
+            "float" => {
+                let d = self.new_var();
+                self.emit(&format!("{} = bitcast i64 {} to double", d, value));
+                self.emit(&format!("{} = fptrunc double {} to float", result, d));
+            }
+            int => self.emit(&format!("{} = trunc i64 {} to {}", result, value, int)),
+        }
+        result
+    }
+    
+    /// Convert a C ABI value back to an i64 Aether value
+    fn lift_from_c_abi(&mut self, value: &str, ty: CScalar) -> String {
+        let result = self.new_var();
+        match ty.llvm {
+            "i64" => return value.to_string(),
+            "double" => self.emit(&format!("{} = bitcast double {} to i64", result, value)),
+            "float" => {
+                let d = self.new_var();
+                self.emit(&format!("{} = fpext float {} to double", d, value));
+                self.emit(&format!("{} = bitcast double {} to i64", result, d));
+            }
+            int => {
+                let ext = if ty.signed && int != "i1" { "sext" } else { "zext" };
+                self.emit(&format!("{} = {} {} {} to i64", result, ext, int, value));
+            }
+        }
+        result
     }
     
     /// Generate integer constant
@@ -190,6 +283,35 @@ impl LLVMCodeGen {
     
     /// Generate function call
     pub fn emit_call(&mut self, name: &str, args: &[&str], ret_type: &str) -> String {
+        // Exported functions use C types at the boundary
+        if let Some(sig) = self.exports.get(name).cloned() {
+            let mut c_args = Vec::new();
+            for (i, arg) in args.iter().enumerate() {
+                match sig.params.get(i).copied().flatten() {
+                    Some(ty) => {
+                        let v = self.lower_to_c_abi(arg, ty);
+                        c_args.push(format!("{} {}", ty.llvm, v));
+                    }
+                    None => c_args.push(format!("i64 {}", arg)),
+                }
+            }
+            return match sig.ret {
+                None => {
+                    self.emit(&format!("call void @{}({})", name, c_args.join(", ")));
+                    "0".to_string()
+                }
+                Some(ret) => {
+                    let ret_ty = ret.map_or("i64", |r| r.llvm);
+                    let result = self.new_var();
+                    self.emit(&format!("{} = call {} @{}({})", result, ret_ty, name, c_args.join(", ")));
+                    match ret {
+                        Some(r) => self.lift_from_c_abi(&result, r),
+                        None => result,
+                    }
+                }
+            };
+        }
+        
         // Track external usage
         if !name.starts_with("llvm.") {
             self.external_funcs.insert(name.to_string());
@@ -346,7 +468,25 @@ impl LLVMCodeGen {
 // AST-BASED CODE GENERATION
 // ============================================================================
 
-use crate::ast::{Expr, Stmt, Block, BinOp, UnOp, Decl};
+use crate::ast::{atomic_update, Expr, Stmt, Block, BinOp, UnOp, Decl, Type};
+use super::mir::atomic_op;
+
+/// Scalar C ABI type of an Aether type, if it differs from a plain i64
+fn export_scalar(ty: &Type) -> Option<CScalar> {
+    match ty {
+        Type::Named(n) => c_scalar(n).filter(|s| s.llvm != "i64"),
+        _ => None,
+    }
+}
+
+/// Parameter attribute clang uses for small integers in the C ABI
+fn abi_ext_attr(ty: CScalar) -> &'static str {
+    match ty.llvm {
+        "i1" | "i8" | "i16" if ty.signed => " signext",
+        "i1" | "i8" | "i16" => " zeroext",
+        _ => "",
+    }
+}
 
 impl LLVMCodeGen {
     /// Generate code for expression, returns SSA value
@@ -359,6 +499,21 @@ impl LLVMCodeGen {
             Expr::Ident(name, _) => {
                 if let Some(ptr) = self.locals.get(name).cloned() {
                     self.emit_load(&ptr, "i64")
+                } else if let Some(value) = self.consts.get(name) {
+                    value.to_string()
+                } else if let Some(len) = self.const_arrays.get(name).copied() {
+                    let result = self.new_var();
+                    self.emit(&format!("{} = ptrtoint [{} x i64]* @{} to i64", result, len, name));
+                    result
+                } else if let Some(info) = self.statics.get(name).copied() {
+                    let ptr = self.static_ptr(name, info);
+                    if info.atomic {
+                        let result = self.new_var();
+                        self.emit(&format!("{} = load atomic i64, i64* {} seq_cst, align 8", result, ptr));
+                        result
+                    } else {
+                        self.emit_load(&ptr, "i64")
+                    }
                 } else {
                     // Assume it's a parameter
                     format!("%{}", name)
@@ -426,6 +581,17 @@ impl LLVMCodeGen {
                 }
             }
             
+            // Address of a static
+            Expr::Unary(UnOp::Ref | UnOp::RefMut, inner, _) if matches!(inner.as_ref(), Expr::Ident(n, _)
+                if !self.locals.contains_key(n) && self.statics.contains_key(n)) => {
+                let Expr::Ident(name, _) = inner.as_ref() else { unreachable!() };
+                let info = self.statics[name];
+                let ptr = self.static_ptr(name, info);
+                let result = self.new_var();
+                self.emit(&format!("{} = ptrtoint i64* {} to i64", result, ptr));
+                result
+            }
+            
             Expr::Unary(op, inner, _) => {
                 let v = self.gen_expr(inner);
                 match op {
@@ -452,6 +618,13 @@ impl LLVMCodeGen {
                             let size = self.gen_expr(&args[0]);
                             self.emit_malloc(&size)
                         }
+                        "__builtin_free" => {
+                            let ptr = self.gen_expr(&args[0]);
+                            let ptr_cast = self.new_var();
+                            self.emit(&format!("{} = inttoptr i64 {} to i8*", ptr_cast, ptr));
+                            self.emit(&format!("call void @free(i8* {})", ptr_cast));
+                            "0".to_string()
+                        }
                         "__builtin_store8" => {
                             let ptr = self.gen_expr(&args[0]);
                             let val = self.gen_expr(&args[1]);
@@ -571,6 +744,44 @@ impl LLVMCodeGen {
                 then_val
             }
             
+            // Arrays are heap blocks of i64 elements
+            Expr::Array(elems, _) => {
+                let arr = self.emit_malloc(&(elems.len() * 8).to_string());
+                for (i, elem) in elems.iter().enumerate() {
+                    let val = self.gen_expr(elem);
+                    let ptr = self.emit_add(&arr, &(i * 8).to_string());
+                    self.emit_store64(&ptr, &val);
+                }
+                arr
+            }
+            
+            Expr::Index(arr, idx, _) => {
+                let ptr = self.gen_element_ptr(arr, idx);
+                self.emit_load64(&ptr)
+            }
+            
+            // Structs are heap records of 8-byte words in declaration order
+            Expr::Struct(name, fields, _) => {
+                let layout: Vec<String> = self.own.fields(name)
+                    .map(|f| f.iter().map(|(n, _)| n.clone()).collect())
+                    .unwrap_or_default();
+                let record = self.emit_malloc(&(layout.len().max(1) * 8).to_string());
+                for (field, value) in fields {
+                    let val = self.gen_expr(value);
+                    let index = layout.iter().position(|n| n == field).unwrap_or(0);
+                    let ptr = self.emit_add(&record, &(index * 8).to_string());
+                    self.emit_store64(&ptr, &val);
+                }
+                record
+            }
+            
+            Expr::Field(obj, field, _) => match self.gen_field_ptr(obj, field) {
+                Some(ptr) => self.emit_load64(&ptr),
+                None => "0".to_string(),
+            },
+            
+            Expr::Unsafe(block, _) => self.gen_block(block),
+            
             Expr::Spawn(func, args, _) => {
                 if let Expr::Ident(name, _) = func.as_ref() {
                     // Currently assume single argument for v1 simple spawn
@@ -590,6 +801,37 @@ impl LLVMCodeGen {
         }
     }
     
+    /// Pointer to a static's storage, running its initializer if needed
+    fn static_ptr(&mut self, name: &str, info: StaticInfo) -> String {
+        if !info.lazy {
+            return format!("@{}", name);
+        }
+        let ptr = self.new_var();
+        self.emit(&format!("{} = call i64* @{}.addr()", ptr, name));
+        ptr
+    }
+    
+    /// Address of `obj.field`, if the struct of `obj` is known
+    fn gen_field_ptr(&mut self, obj: &Expr, field: &str) -> Option<String> {
+        let ty = self.type_of(obj)?;
+        let (name, _) = self.own.named(&ty)?;
+        let index = self.own.fields(&name)?.iter().position(|(n, _)| n == field)?;
+        let base = self.gen_expr(obj);
+        Some(self.emit_add(&base, &(index * 8).to_string()))
+    }
+    
+    fn type_of(&self, expr: &Expr) -> Option<Type> {
+        self.own.type_of(expr, &|name| self.local_types.get(name).cloned())
+    }
+    
+    /// Address of `arr[idx]`
+    fn gen_element_ptr(&mut self, arr: &Expr, idx: &Expr) -> String {
+        let base = self.gen_expr(arr);
+        let i = self.gen_expr(idx);
+        let offset = self.emit_mul(&i, "8");
+        self.emit_add(&base, &offset)
+    }
+    
     /// Generate code for block, returns last expression value
     pub fn gen_block(&mut self, block: &Block) -> String {
         let mut last = "0".to_string();
@@ -602,7 +844,9 @@ impl LLVMCodeGen {
     /// Generate code for statement
     pub fn gen_stmt(&mut self, stmt: &Stmt) -> String {
         match stmt {
-            Stmt::Let { name, init, .. } => {
+            Stmt::Let { name, ty, init, .. } => {
+                let known = ty.clone().or_else(|| init.as_ref().and_then(|e| self.type_of(e)));
+                self.local_types.insert(name.clone(), known);
                 let ptr = self.alloc_local(name);
                 if let Some(expr) = init {
                     let val = self.gen_expr(expr);
@@ -616,6 +860,30 @@ impl LLVMCodeGen {
                     if let Some(ptr) = self.locals.get(name).cloned() {
                         let val = self.gen_expr(value);
                         self.emit_store(&val, &ptr, "i64");
+                    } else if let Some(info) = self.statics.get(name).copied() {
+                        if let Some((op, operand)) = atomic_update(name, value).filter(|_| info.atomic) {
+                            let val = self.gen_expr(operand);
+                            let ptr = self.static_ptr(name, info);
+                            let old = self.new_var();
+                            self.emit(&format!("{} = atomicrmw {} i64* {}, i64 {} seq_cst", old, atomic_op(op), ptr, val));
+                            return "0".to_string();
+                        }
+                        let val = self.gen_expr(value);
+                        let ptr = self.static_ptr(name, info);
+                        if info.atomic {
+                            self.emit(&format!("store atomic i64 {}, i64* {} seq_cst, align 8", val, ptr));
+                        } else {
+                            self.emit_store(&val, &ptr, "i64");
+                        }
+                    }
+                } else if let Expr::Index(arr, idx, _) = target {
+                    let val = self.gen_expr(value);
+                    let ptr = self.gen_element_ptr(arr, idx);
+                    self.emit_store64(&ptr, &val);
+                } else if let Expr::Field(obj, field, _) = target {
+                    let val = self.gen_expr(value);
+                    if let Some(ptr) = self.gen_field_ptr(obj, field) {
+                        self.emit_store64(&ptr, &val);
                     }
                 }
                 "0".to_string()
@@ -697,19 +965,164 @@ impl LLVMCodeGen {
         }
     }
     
+    /// Record the struct layouts and signatures a declaration introduces
+    pub fn declare_types(&mut self, decl: &Decl) {
+        self.own.add(decl);
+    }
+    
+    /// Record the C signature of an `#[export]` function before any calls are generated
+    pub fn declare_export(&mut self, decl: &Decl) {
+        if let Decl::Func { name, params, ret, .. } = decl {
+            if decl.has_attr("export") {
+                let sig = ExportSig {
+                    params: params.iter().map(|p| export_scalar(&p.ty)).collect(),
+                    ret: match ret {
+                        None | Some(Type::Unit) => None,
+                        Some(t) => Some(export_scalar(t)),
+                    },
+                };
+                self.exports.insert(name.clone(), sig);
+            }
+        }
+    }
+    
+    /// Record a folded constant; `pub` constants also get a global symbol
+    pub fn declare_const(&mut self, decl: &Decl) {
+        if let Decl::Const { name, value, public, .. } = decl {
+            let value = match value {
+                Expr::Int(v, _) => *v,
+                Expr::Bool(b, _) => *b as i64,
+                // Literal tables (e.g. from comptime) become read-only globals
+                Expr::Array(elems, _) => {
+                    let words: Option<Vec<String>> = elems.iter()
+                        .map(|e| match e {
+                            Expr::Int(v, _) => Some(format!("i64 {}", v)),
+                            Expr::Bool(b, _) => Some(format!("i64 {}", *b as i64)),
+                            _ => None,
+                        })
+                        .collect();
+                    if let Some(words) = words {
+                        let linkage = if *public { "" } else { "internal " };
+                        self.emit_raw(&format!("@{} = {}constant [{} x i64] [{}]", name, linkage, words.len(), words.join(", ")));
+                        self.const_arrays.insert(name.clone(), words.len());
+                    }
+                    return;
+                }
+                // Not folded (Float, String): left to the runtime as before
+                _ => return,
+            };
+            self.consts.insert(name.clone(), value);
+            if *public {
+                self.emit_raw(&format!("@{} = constant i64 {}", name, value));
+            }
+        }
+    }
+    
+    /// Generate an exported function: C-typed signature, i64 internals
+    fn gen_export_function(&mut self, name: &str, params: &[crate::ast::Param], body: &Block, sig: ExportSig) {
+        self.var_counter = 0;
+        self.locals.clear();
+        self.local_types = params.iter().map(|p| (p.name.clone(), Some(p.ty.clone()))).collect();
+        
+        let ret_ty = match sig.ret {
+            None => "void".to_string(),
+            Some(r) => r.map_or("i64", |r| r.llvm).to_string(),
+        };
+        let ret_attr = sig.ret.flatten().map_or("", abi_ext_attr);
+        self.current_ret_type = ret_ty.clone();
+        self.current_ret_abi = sig.ret.flatten();
+        
+        let params_str: Vec<String> = params.iter().zip(&sig.params)
+            .map(|(p, ty)| match ty {
+                Some(t) => format!("{}{} %{}", t.llvm, abi_ext_attr(*t), p.name),
+                None => format!("i64 %{}", p.name),
+            })
+            .collect();
+        self.emit_raw(&format!("define{} {} @{}({}) {{", ret_attr, ret_ty, name, params_str.join(", ")));
+        self.emit_raw("entry:");
+        
+        for (p, ty) in params.iter().zip(&sig.params) {
+            let value = match ty {
+                Some(t) => self.lift_from_c_abi(&format!("%{}", p.name), *t),
+                None => format!("%{}", p.name),
+            };
+            let ptr = format!("%{}.addr", p.name);
+            self.emit(&format!("{} = alloca i64", ptr));
+            self.emit(&format!("store i64 {}, i64* {}", value, ptr));
+            self.locals.insert(p.name.clone(), ptr);
+        }
+        self.defined_funcs.insert(name.to_string());
+        
+        let result = self.gen_block(body);
+        self.emit_return(&result);
+        self.emit_func_end();
+    }
+    
     /// Generate full function from Decl
+    /// Emit the global for a `static`; initializers that are not literals run on first access
+    pub fn declare_static(&mut self, decl: &Decl) {
+        if let Decl::Static { name, ty, value, public, .. } = decl {
+            let init = match value {
+                None => Some(0),
+                Some(Expr::Int(v, _)) => Some(*v),
+                Some(Expr::Bool(b, _)) => Some(*b as i64),
+                Some(_) => None,
+            };
+            let linkage = if *public { "" } else { "internal " };
+            let tls = if decl.has_attr("thread_local") { "thread_local " } else { "" };
+            self.emit_raw(&format!("@{} = {}{}global i64 {}, align 8", name, linkage, tls, init.unwrap_or(0)));
+            if init.is_none() {
+                self.emit_raw(&format!("@{}.ready = internal {}global i8 0", name, tls));
+            }
+            let atomic = ty.as_ref().is_some_and(|t| t.atomic_value().is_some());
+            self.statics.insert(name.clone(), StaticInfo { lazy: init.is_none(), atomic });
+        }
+    }
+    
+    /// `@name.addr()`: initialize a lazy static once and return its address
+    fn gen_static_init(&mut self, name: &str, value: &Expr) {
+        self.emit_func_start(&format!("{}.addr", name), &[], "i64*", "internal ");
+        let init_label = self.new_label();
+        let done_label = self.new_label();
+        let ready = self.new_var();
+        self.emit(&format!("{} = load atomic i8, i8* @{}.ready acquire, align 1", ready, name));
+        let is_ready = self.new_var();
+        self.emit(&format!("{} = icmp ne i8 {}, 0", is_ready, ready));
+        self.emit_cond_br(&is_ready, &done_label, &init_label);
+        self.emit_label(&init_label);
+        let val = self.gen_expr(value);
+        self.emit_store(&val, &format!("@{}", name), "i64");
+        self.emit(&format!("store atomic i8 1, i8* @{}.ready release, align 1", name));
+        self.emit_br(&done_label);
+        self.emit_label(&done_label);
+        self.emit(&format!("ret i64* @{}", name));
+        self.emit_func_end();
+    }
+    
     pub fn gen_function(&mut self, decl: &Decl) {
-        if let Decl::Func { name, params, body, ret, .. } = decl {
+        if let Decl::Static { name, value: Some(value), .. } = decl {
+            if self.statics.get(name).is_some_and(|s| s.lazy) {
+                self.gen_static_init(name, value);
+            }
+        }
+        if let Decl::Func { name, params, body, ret, public, .. } = decl {
+            if let Some(sig) = self.exports.get(name).cloned() {
+                self.gen_export_function(name, params, body, sig);
+                return;
+            }
+            
             // Reset state
             self.var_counter = 0;
             self.locals.clear();
+            self.local_types = params.iter().map(|p| (p.name.clone(), Some(p.ty.clone()))).collect();
             
             // Build params list
             let params_vec: Vec<(&str, &str)> = params.iter()
                 .map(|p| (p.name.as_str(), "i64"))
                 .collect();
             
-            self.emit_func_start(name, &params_vec, "i64");
+            let linkage = if self.hide_private && !public { "internal " } else { "" };
+            self.emit_func_start(name, &params_vec, "i64", linkage);
             
             // Generate body
             let result = self.gen_block(body);
@@ -0,0 +1,1839 @@
+//! Mid-level IR: three-address code over virtual registers
+//!
+//! Lowered from the typed AST with structs, methods, statics and builtins resolved;
+//! register-allocating backends select instructions from it.
+
+use std::collections::{HashMap, HashSet};
+use anyhow::{anyhow, bail, Result};
+use crate::ast::*;
+use crate::borrowck::ownership::Ownership;
+use crate::typechecker::TypedModule;
+use super::header::{c_scalar, CScalar};
+
+/// Builtins that call the libc function of the same name, and whether it returns a C `int`
+pub const LIBC: &[(&str, bool)] = &[
+    ("malloc", false), ("free", false), ("memcpy", false), ("memset", false),
+    ("write", false), ("read", false), ("lseek", false), ("exit", false),
+    ("open", true), ("close", true), ("unlink", true), ("mkdir", true), ("rmdir", true), ("rename", true),
+    ("socket", true), ("connect", true), ("bind", true), ("listen", true), ("accept", true), ("setsockopt", true),
+];
+
+/// Name of the function a method is emitted as (`Point::len` -> `Point__len`)
+pub fn method_symbol(type_name: &str, method: &str) -> String {
+    format!("{}__{}", type_name, method)
+}
+
+/// Arguments with defaults filled in for the missing trailing ones
+pub fn with_defaults(name: &str, params: &[Param], args: &[Expr], span: Span) -> Result<Vec<Expr>> {
+    let mut full = args.to_vec();
+    for param in params.iter().skip(args.len()) {
+        match &param.default {
+            Some(default) => full.push(default.clone()),
+            None => break,
+        }
+    }
+    if full.len() != params.len() {
+        bail!("Wrong number of arguments to {} at line {}: expected {}, got {}", name, span.line, params.len(), args.len());
+    }
+    Ok(full)
+}
+
+/// What `for x in xs` walks
+pub enum ForSource {
+    /// A fixed-size array: the value is the address of its first element
+    Array(usize),
+    /// A vector: the value points at `[data, len, cap]`
+    Vec,
+}
+
+/// How to walk a value of type `ty` in a `for` loop, and the element type
+pub fn for_source(ty: Option<Type>, span: Span) -> Result<(ForSource, Option<Type>)> {
+    match ty {
+        Some(Type::Array(elem, Some(n))) => Ok((ForSource::Array(n), Some(*elem))),
+        Some(Type::Named(n)) if n == "Vec" || n == "List" => Ok((ForSource::Vec, None)),
+        Some(Type::Generic(n, args)) if n == "Vec" || n == "List" => Ok((ForSource::Vec, args.into_iter().next())),
+        _ => bail!("for loops need an array of known length or a Vec at line {}", span.line),
+    }
+}
+
+/// Operator of an `atomic_update` as named by C's `__atomic_fetch_*`, LLVM's
+/// `atomicrmw` and x86's `lock`-prefixed instructions
+pub fn atomic_op(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "add",
+        BinOp::Sub => "sub",
+        BinOp::BitAnd => "and",
+        BinOp::BitOr => "or",
+        _ => "xor",
+    }
+}
+
+/// Scalar C ABI type of an Aether type, if it differs from a plain word
+pub fn export_scalar(ty: &Type) -> Option<CScalar> {
+    match ty {
+        Type::Named(n) => c_scalar(n).filter(|s| s.llvm != "i64"),
+        _ => None,
+    }
+}
+
+/// Whether a C scalar travels in a floating-point register
+pub fn is_float(ty: Option<CScalar>) -> bool {
+    ty.is_some_and(|t| matches!(t.llvm, "double" | "float"))
+}
+
+/// Escape bytes for an `.asciz` directive
+pub fn escape(s: &str) -> String {
+    let mut out = String::new();
+    for b in s.bytes() {
+        match b {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7e => out.push(b as char),
+            _ => out.push_str(&format!("\\{:03o}", b)),
+        }
+    }
+    out
+}
+
+/// C ABI signature of an `#[export]` function (`None` = passed as a word)
+#[derive(Debug, Clone)]
+pub struct ExportSig {
+    pub params: Vec<Option<CScalar>>,
+    /// Return type; `None` in the outer option means `void`
+    pub ret: Option<Option<CScalar>>,
+}
+
+// ========== IR ==========
+
+pub type VReg = u32;
+pub type Label = u32;
+
+/// Width of a memory access; narrower loads zero-extend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    W8,
+    W16,
+    W32,
+    W64,
+}
+
+impl Width {
+    pub fn bytes(self) -> i64 {
+        match self {
+            Width::W8 => 1,
+            Width::W16 => 2,
+            Width::W32 => 4,
+            Width::W64 => 8,
+        }
+    }
+}
+
+/// Right-hand side of a binary operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Reg(VReg),
+    Imm(i64),
+}
+
+/// What a symbol names, which decides how its address is formed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymKind {
+    Code,
+    Data,
+    ThreadLocal,
+}
+
+#[derive(Debug, Clone)]
+pub enum Callee {
+    Direct(String),
+    /// Function pointer
+    Indirect(VReg),
+}
+
+/// C types of a foreign call's arguments and result (`None` = a plain word)
+#[derive(Debug, Clone)]
+pub struct CAbi {
+    pub params: Vec<Option<CScalar>>,
+    pub ret: Option<CScalar>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Inst {
+    Imm(VReg, i64),
+    Copy(VReg, VReg),
+    /// `dst = a op b`; comparisons are signed and give 0 or 1, `&&` and `||` are bitwise
+    Bin(BinOp, VReg, VReg, Operand),
+    Neg(VReg, VReg),
+    BitNot(VReg, VReg),
+    /// `dst = a op b` on the bits of doubles: `+ - * /`, or a comparison giving 0 or 1
+    FBin(BinOp, VReg, VReg, VReg),
+    /// The bits of the double nearest an Int
+    IntToFloat(VReg, VReg),
+    /// The Int part of a double, rounded toward zero
+    FloatToInt(VReg, VReg),
+    /// `dst = *(base + offset)`; `ordered` accesses are acquire loads and release stores
+    Load { width: Width, dst: VReg, base: VReg, offset: i64, ordered: bool },
+    Store { width: Width, src: VReg, base: VReg, offset: i64, ordered: bool },
+    /// `*base = *base op src` as one sequentially consistent read-modify-write
+    AtomicRmw { op: BinOp, src: VReg, base: VReg },
+    /// 8-byte frame slots, for locals whose address is taken
+    LoadSlot(VReg, u32),
+    StoreSlot(VReg, u32),
+    SlotAddr(VReg, u32),
+    Addr(VReg, String, SymKind),
+    Call { dst: Option<VReg>, callee: Callee, args: Vec<VReg>, abi: Option<CAbi> },
+    Label(Label),
+    Jump(Label),
+    /// Jump when the register is zero (`true`) or non-zero (`false`)
+    Branch(VReg, bool, Label),
+    Ret(VReg),
+}
+
+impl Inst {
+    pub fn def(&self) -> Option<VReg> {
+        match self {
+            Inst::Imm(d, _) | Inst::Copy(d, _) | Inst::Bin(_, d, _, _) | Inst::Neg(d, _) | Inst::BitNot(d, _)
+            | Inst::FBin(_, d, _, _) | Inst::IntToFloat(d, _) | Inst::FloatToInt(d, _)
+            | Inst::LoadSlot(d, _) | Inst::SlotAddr(d, _) | Inst::Addr(d, _, _) => Some(*d),
+            Inst::Load { dst, .. } => Some(*dst),
+            Inst::Call { dst, .. } => *dst,
+            _ => None,
+        }
+    }
+
+    pub fn uses(&self) -> Vec<VReg> {
+        match self {
+            Inst::Copy(_, s) | Inst::Neg(_, s) | Inst::BitNot(_, s) | Inst::IntToFloat(_, s) | Inst::FloatToInt(_, s)
+            | Inst::StoreSlot(s, _) | Inst::Branch(s, _, _) | Inst::Ret(s) => vec![*s],
+            Inst::Bin(_, _, a, Operand::Reg(b)) | Inst::FBin(_, _, a, b) => vec![*a, *b],
+            Inst::Bin(_, _, a, Operand::Imm(_)) => vec![*a],
+            Inst::Load { base, .. } => vec![*base],
+            Inst::Store { src, base, .. } | Inst::AtomicRmw { src, base, .. } => vec![*src, *base],
+            Inst::Call { callee, args, .. } => {
+                let mut uses = args.clone();
+                if let Callee::Indirect(f) = callee {
+                    uses.push(*f);
+                }
+                uses
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// No effect besides defining its register
+    fn is_pure(&self) -> bool {
+        matches!(self, Inst::Imm(..) | Inst::Copy(..) | Inst::Bin(..) | Inst::Neg(..) | Inst::BitNot(..)
+            | Inst::FBin(..) | Inst::IntToFloat(..) | Inst::FloatToInt(..) | Inst::LoadSlot(..) | Inst::SlotAddr(..) | Inst::Addr(..) | Inst::Load { ordered: false, .. })
+    }
+
+    fn set_def(&mut self, v: VReg) {
+        match self {
+            Inst::Imm(d, _) | Inst::Copy(d, _) | Inst::Bin(_, d, _, _) | Inst::Neg(d, _) | Inst::BitNot(d, _)
+            | Inst::FBin(_, d, _, _) | Inst::IntToFloat(d, _) | Inst::FloatToInt(d, _)
+            | Inst::LoadSlot(d, _) | Inst::SlotAddr(d, _) | Inst::Addr(d, _, _) => *d = v,
+            Inst::Load { dst, .. } => *dst = v,
+            Inst::Call { dst, .. } => *dst = Some(v),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    pub global: bool,
+    /// Registers holding the parameters on entry
+    pub params: Vec<VReg>,
+    pub insts: Vec<Inst>,
+    pub vregs: u32,
+    pub slots: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataKind {
+    ReadOnly,
+    Mutable,
+    ThreadLocal,
+}
+
+#[derive(Debug, Clone)]
+pub enum Init {
+    Words(Vec<i64>),
+    /// NUL-terminated string
+    Str(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Data {
+    pub name: String,
+    pub global: bool,
+    pub kind: DataKind,
+    pub init: Init,
+}
+
+/// A lowered module
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub functions: Vec<Function>,
+    pub data: Vec<Data>,
+    /// `#[export]` functions: C ABI wrappers named `name` around `name.body`
+    pub exports: Vec<(String, ExportSig)>,
+    /// Needs a `_start` that calls `main`
+    pub entry: bool,
+}
+
+// ========== Register allocation ==========
+
+/// Where a virtual register lives for its whole lifetime
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Loc {
+    Reg(u8),
+    /// Frame slot, numbered after the function's own slots
+    Spill(u32),
+}
+
+/// Live range in instruction positions: parameters are defined at 0, instruction `i` sits at `i + 1`
+#[derive(Debug, Clone)]
+struct Interval {
+    vreg: VReg,
+    start: usize,
+    end: usize,
+    /// Live across a call: needs a callee-saved register
+    across_call: bool,
+}
+
+/// Live ranges from a dataflow liveness pass over the basic blocks
+fn intervals(f: &Function) -> Vec<Interval> {
+    let insts = &f.insts;
+    let mut blocks = Vec::new();
+    let mut start = 0;
+    for (i, inst) in insts.iter().enumerate() {
+        if matches!(inst, Inst::Label(_)) && i > start {
+            blocks.push((start, i));
+            start = i;
+        }
+        if matches!(inst, Inst::Jump(_) | Inst::Branch(..) | Inst::Ret(_)) {
+            blocks.push((start, i + 1));
+            start = i + 1;
+        }
+    }
+    if start < insts.len() {
+        blocks.push((start, insts.len()));
+    }
+    let block_of: HashMap<Label, usize> = blocks.iter().enumerate()
+        .filter_map(|(b, &(s, _))| match insts[s] {
+            Inst::Label(l) => Some((l, b)),
+            _ => None,
+        })
+        .collect();
+    let succs: Vec<Vec<usize>> = blocks.iter().enumerate()
+        .map(|(b, &(_, e))| {
+            let fall = (b + 1 < blocks.len()).then_some(b + 1);
+            match &insts[e - 1] {
+                Inst::Jump(l) => vec![block_of[l]],
+                Inst::Branch(_, _, l) => std::iter::once(block_of[l]).chain(fall).collect(),
+                Inst::Ret(_) => Vec::new(),
+                _ => fall.into_iter().collect(),
+            }
+        })
+        .collect();
+
+    // Upward-exposed uses and definitions of each block
+    let mut gen = vec![HashSet::new(); blocks.len()];
+    let mut kill = vec![HashSet::new(); blocks.len()];
+    for (b, &(s, e)) in blocks.iter().enumerate() {
+        for inst in &insts[s..e] {
+            for v in inst.uses() {
+                if !kill[b].contains(&v) {
+                    gen[b].insert(v);
+                }
+            }
+            if let Some(d) = inst.def() {
+                kill[b].insert(d);
+            }
+        }
+    }
+    let mut live_in: Vec<HashSet<VReg>> = gen.clone();
+    let mut live_out: Vec<HashSet<VReg>> = vec![HashSet::new(); blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in (0..blocks.len()).rev() {
+            let out: HashSet<VReg> = succs[b].iter().flat_map(|&s| live_in[s].iter().copied()).collect();
+            let new_in: HashSet<VReg> = gen[b].iter().copied()
+                .chain(out.iter().copied().filter(|v| !kill[b].contains(v)))
+                .collect();
+            if new_in.len() != live_in[b].len() || out.len() != live_out[b].len() {
+                changed = true;
+            }
+            live_in[b] = new_in;
+            live_out[b] = out;
+        }
+    }
+
+    let mut ranges: Vec<Option<(usize, usize)>> = vec![None; f.vregs as usize];
+    let mut extend = |v: VReg, pos: usize| {
+        let r = ranges[v as usize].get_or_insert((pos, pos));
+        r.0 = r.0.min(pos);
+        r.1 = r.1.max(pos);
+    };
+    for &p in &f.params {
+        extend(p, 0);
+    }
+    for (b, &(s, e)) in blocks.iter().enumerate() {
+        for &v in &live_in[b] {
+            extend(v, s + 1);
+        }
+        for &v in &live_out[b] {
+            extend(v, e);
+        }
+    }
+    let mut calls = Vec::new();
+    for (i, inst) in insts.iter().enumerate() {
+        for v in inst.uses().into_iter().chain(inst.def()) {
+            extend(v, i + 1);
+        }
+        if matches!(inst, Inst::Call { .. }) {
+            calls.push(i + 1);
+        }
+    }
+    ranges.iter().enumerate()
+        .filter_map(|(v, r)| r.map(|(start, end)| Interval {
+            vreg: v as VReg,
+            start,
+            end,
+            across_call: calls.iter().any(|&c| start < c && c < end),
+        }))
+        .collect()
+}
+
+/// Linear scan: registers for the intervals in order of their start, spilling the one
+/// that ends last when none is free. Values live across a call only get `callee_saved`
+/// registers; returns each register's location and the number of spill slots
+pub fn allocate(f: &Function, caller_saved: &[u8], callee_saved: &[u8]) -> (Vec<Option<Loc>>, u32) {
+    let mut intervals = intervals(f);
+    intervals.sort_by_key(|iv| (iv.start, iv.vreg));
+    let mut locs = vec![None; f.vregs as usize];
+    let mut spills = 0;
+    // (end, vreg, register)
+    let mut active: Vec<(usize, VReg, u8)> = Vec::new();
+    for iv in &intervals {
+        active.retain(|&(end, _, _)| end > iv.start);
+        let busy: HashSet<u8> = active.iter().map(|&(_, _, r)| r).collect();
+        let pool: Vec<u8> = if iv.across_call {
+            callee_saved.to_vec()
+        } else {
+            caller_saved.iter().chain(callee_saved).copied().collect()
+        };
+        if let Some(&reg) = pool.iter().find(|r| !busy.contains(r)) {
+            locs[iv.vreg as usize] = Some(Loc::Reg(reg));
+            active.push((iv.end, iv.vreg, reg));
+            continue;
+        }
+        let victim = active.iter().enumerate()
+            .filter(|(_, &(_, _, r))| pool.contains(&r))
+            .max_by_key(|(_, &(end, _, _))| end)
+            .map(|(i, &a)| (i, a));
+        match victim {
+            Some((i, (end, vreg, reg))) if end > iv.end => {
+                locs[vreg as usize] = Some(Loc::Spill(f.slots + spills));
+                locs[iv.vreg as usize] = Some(Loc::Reg(reg));
+                active[i] = (iv.end, iv.vreg, reg);
+            }
+            _ => locs[iv.vreg as usize] = Some(Loc::Spill(f.slots + spills)),
+        }
+        spills += 1;
+    }
+    (locs, spills)
+}
+
+// ========== Lowering ==========
+
+/// Where a local lives
+#[derive(Debug, Clone, Copy)]
+enum Home {
+    Reg(VReg),
+    /// Its address is taken somewhere in the function
+    Slot(u32),
+}
+
+#[derive(Debug, Clone)]
+struct Local {
+    home: Home,
+    ty: Option<Type>,
+}
+
+/// Storage of a `static`
+#[derive(Debug, Clone)]
+struct StaticInfo {
+    /// Initialized on first access through `name.addr`
+    lazy: bool,
+    thread_local: bool,
+    atomic: bool,
+    ty: Option<Type>,
+}
+
+/// Variants of an enum; when some carry a payload, every value is a
+/// record with the variant index at offset 0 and payload word i at 8 + 8i
+#[derive(Debug, Clone)]
+struct EnumInfo {
+    variants: Vec<Variant>,
+    tagged: bool,
+}
+
+/// A closure body, lowered once the enclosing function is done
+struct Lambda {
+    symbol: String,
+    params: Vec<Param>,
+    captures: Vec<(String, Option<Type>)>,
+    body: Expr,
+    self_type: Option<String>,
+}
+
+/// Lower a module; `entry` asks for `_start`, `hide_private` keeps non-`pub` functions local
+pub fn lower(module: &TypedModule, entry: bool, hide_private: bool) -> Result<Program> {
+    let mut lowerer = Lowerer {
+        own: Ownership::from_module(module),
+        funcs: HashMap::new(),
+        externs: HashMap::new(),
+        exports: Vec::new(),
+        consts: HashMap::new(),
+        const_arrays: HashSet::new(),
+        statics: HashMap::new(),
+        enums: HashMap::new(),
+        hide_private,
+        functions: Vec::new(),
+        data: Vec::new(),
+        strings: HashMap::new(),
+        labels: 0,
+        insts: Vec::new(),
+        vregs: 0,
+        slots: 0,
+        scopes: Vec::new(),
+        loops: Vec::new(),
+        local_regs: HashSet::new(),
+        addressed: HashSet::new(),
+        self_type: None,
+        current: String::new(),
+        lambdas: Vec::new(),
+        lambda_count: 0,
+        thunks: Vec::new(),
+    };
+    for typed_decl in &module.decls {
+        lowerer.declare(&typed_decl.decl);
+    }
+    lowerer.module(module)?;
+    Ok(Program {
+        functions: lowerer.functions,
+        data: lowerer.data,
+        exports: lowerer.exports,
+        entry,
+    })
+}
+
+struct Lowerer {
+    own: Ownership,
+    /// Parameters and return type of every function and method, by symbol
+    funcs: HashMap<String, (Vec<Param>, Option<Type>)>,
+    externs: HashMap<String, CAbi>,
+    exports: Vec<(String, ExportSig)>,
+    consts: HashMap<String, i64>,
+    const_arrays: HashSet<String>,
+    statics: HashMap<String, StaticInfo>,
+    enums: HashMap<String, EnumInfo>,
+    hide_private: bool,
+    functions: Vec<Function>,
+    data: Vec<Data>,
+    /// Interned string literals (text -> label)
+    strings: HashMap<String, String>,
+    /// Labels are numbered across the module
+    labels: Label,
+    // Current function
+    insts: Vec<Inst>,
+    vregs: u32,
+    slots: u32,
+    scopes: Vec<HashMap<String, Local>>,
+    /// `continue` and `break` targets
+    loops: Vec<(Label, Label)>,
+    /// Registers that hold a local variable rather than a temporary
+    local_regs: HashSet<VReg>,
+    /// Locals whose address is taken, kept in frame slots
+    addressed: HashSet<String>,
+    self_type: Option<String>,
+    /// Symbol of the function being lowered, the prefix of its closures
+    current: String,
+    lambdas: Vec<Lambda>,
+    lambda_count: u32,
+    /// Functions used as values, which get a `name.thunk` taking the closure record
+    thunks: Vec<String>,
+}
+
+impl Lowerer {
+    // ========== Declarations ==========
+
+    fn declare(&mut self, decl: &Decl) {
+        match decl {
+            Decl::Func { name, params, ret, .. } => {
+                self.funcs.insert(name.clone(), (params.clone(), ret.clone()));
+                if decl.has_attr("export") {
+                    let sig = ExportSig {
+                        params: params.iter().map(|p| export_scalar(&p.ty)).collect(),
+                        ret: match ret {
+                            None | Some(Type::Unit) => None,
+                            Some(t) => Some(export_scalar(t)),
+                        },
+                    };
+                    self.exports.push((name.clone(), sig));
+                }
+            }
+            // `impl Drop` is hoisted to `T__drop` during drop elaboration
+            Decl::Impl { trait_name, type_name, methods, .. } if trait_name.as_deref() != Some("Drop") => {
+                for method in methods {
+                    if let Decl::Func { name, params, ret, .. } = method {
+                        self.funcs.insert(method_symbol(type_name, name), (params.clone(), ret.clone()));
+                    }
+                }
+            }
+            Decl::Enum { name, variants, .. } => {
+                let tagged = variants.iter().any(|v| !v.fields.is_empty());
+                self.enums.insert(name.clone(), EnumInfo { variants: variants.clone(), tagged });
+            }
+            Decl::Extern { funcs, .. } => {
+                for f in funcs {
+                    let abi = CAbi {
+                        params: f.params.iter().map(|p| export_scalar(&p.ty)).collect(),
+                        ret: f.ret.as_ref().and_then(export_scalar),
+                    };
+                    self.externs.insert(f.name.clone(), abi);
+                }
+            }
+            Decl::Const { name, value, public, .. } => {
+                let words = match value {
+                    Expr::Int(v, _) => {
+                        self.consts.insert(name.clone(), *v);
+                        vec![*v]
+                    }
+                    Expr::Bool(b, _) => {
+                        self.consts.insert(name.clone(), *b as i64);
+                        vec![*b as i64]
+                    }
+                    Expr::Array(elems, _) => {
+                        let words: Option<Vec<i64>> = elems.iter()
+                            .map(|e| match e {
+                                Expr::Int(v, _) => Some(*v),
+                                Expr::Bool(b, _) => Some(*b as i64),
+                                _ => None,
+                            })
+                            .collect();
+                        let Some(words) = words else { return };
+                        self.const_arrays.insert(name.clone());
+                        words
+                    }
+                    // Not folded (Float, String)
+                    _ => return,
+                };
+                // Scalars are immediates; only `pub` ones need a symbol
+                if *public || self.const_arrays.contains(name) {
+                    self.data.push(Data { name: name.clone(), global: *public, kind: DataKind::ReadOnly, init: Init::Words(words) });
+                }
+            }
+            Decl::Static { name, ty, value, public, .. } => {
+                let init = match value {
+                    None => Some(0),
+                    Some(Expr::Int(v, _)) => Some(*v),
+                    Some(Expr::Bool(b, _)) => Some(*b as i64),
+                    Some(_) => None,
+                };
+                let thread_local = decl.has_attr("thread_local");
+                let kind = if thread_local { DataKind::ThreadLocal } else { DataKind::Mutable };
+                self.data.push(Data { name: name.clone(), global: *public, kind, init: Init::Words(vec![init.unwrap_or(0)]) });
+                if init.is_none() {
+                    self.data.push(Data { name: format!("{}.ready", name), global: false, kind, init: Init::Words(vec![0]) });
+                }
+                self.statics.insert(name.clone(), StaticInfo {
+                    lazy: init.is_none(),
+                    thread_local,
+                    atomic: ty.as_ref().is_some_and(|t| t.atomic_value().is_some()),
+                    ty: ty.as_ref().map(|t| t.atomic_value().unwrap_or_else(|| t.clone())),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    fn module(&mut self, module: &TypedModule) -> Result<()> {
+        for typed_decl in &module.decls {
+            match &typed_decl.decl {
+                Decl::Func { name, params, body, public, .. } => {
+                    let global = !self.hide_private || *public || name == "main";
+                    if self.exports.iter().any(|(n, _)| n == name) {
+                        self.function(&format!("{}.body", name), params, body, false)?;
+                    } else {
+                        self.function(name, params, body, global)?;
+                    }
+                }
+                Decl::Impl { trait_name, type_name, methods, .. } if trait_name.as_deref() != Some("Drop") => {
+                    self.self_type = Some(type_name.clone());
+                    for method in methods {
+                        if let Decl::Func { name, params, body, public, .. } = method {
+                            let global = !self.hide_private || *public;
+                            self.function(&method_symbol(type_name, name), params, body, global)?;
+                        }
+                    }
+                    self.self_type = None;
+                }
+                Decl::Static { name, value: Some(value), .. } if self.statics[name].lazy => {
+                    self.static_init(name, value)?;
+                }
+                _ => {}
+            }
+            while let Some(lambda) = self.lambdas.pop() {
+                self.lambda_body(lambda)?;
+            }
+        }
+        for name in std::mem::take(&mut self.thunks) {
+            self.thunk(&name);
+        }
+        Ok(())
+    }
+
+    // ========== Functions ==========
+
+    fn begin_function(&mut self) {
+        self.insts.clear();
+        self.vregs = 0;
+        self.slots = 0;
+        self.scopes = vec![HashMap::new()];
+        self.loops.clear();
+        self.local_regs.clear();
+        self.addressed.clear();
+    }
+
+    /// Drop unreachable code and dead computations, and emit the function
+    fn finish_function(&mut self, name: &str, global: bool, params: Vec<VReg>) {
+        let mut insts = std::mem::take(&mut self.insts);
+        let mut reachable = true;
+        insts.retain(|inst| {
+            reachable |= matches!(inst, Inst::Label(_));
+            let keep = reachable;
+            reachable &= !matches!(inst, Inst::Jump(_) | Inst::Ret(_));
+            keep
+        });
+        loop {
+            let mut used = vec![false; self.vregs as usize];
+            for inst in &insts {
+                for v in inst.uses() {
+                    used[v as usize] = true;
+                }
+            }
+            let before = insts.len();
+            insts.retain(|inst| !(inst.is_pure() && inst.def().is_some_and(|d| !used[d as usize])));
+            for inst in &mut insts {
+                if let Inst::Call { dst, .. } = inst {
+                    if dst.is_some_and(|d| !used[d as usize]) {
+                        *dst = None;
+                    }
+                }
+            }
+            if insts.len() == before {
+                break;
+            }
+        }
+        self.functions.push(Function { name: name.to_string(), global, params, insts, vregs: self.vregs, slots: self.slots });
+    }
+
+    fn function(&mut self, symbol: &str, params: &[Param], body: &Block, global: bool) -> Result<()> {
+        self.begin_function();
+        self.current = symbol.to_string();
+        addressed_block(body, &mut self.addressed);
+        let mut regs = Vec::new();
+        for param in params {
+            let v = self.vreg();
+            regs.push(v);
+            let ty = self.resolve_self(&param.ty);
+            self.bind(&param.name, v, Some(ty));
+        }
+        let result = self.block(body)?;
+        self.push(Inst::Ret(result));
+        self.finish_function(symbol, global, regs);
+        Ok(())
+    }
+
+    /// `name.addr`: initialize a lazy static once and return its address
+    fn static_init(&mut self, name: &str, value: &Expr) -> Result<()> {
+        self.begin_function();
+        let done = self.label();
+        let kind = if self.statics[name].thread_local { SymKind::ThreadLocal } else { SymKind::Data };
+        let ready_sym = format!("{}.ready", name);
+        let ready = self.addr(&ready_sym, kind);
+        let flag = self.vreg();
+        self.push(Inst::Load { width: Width::W64, dst: flag, base: ready, offset: 0, ordered: true });
+        self.push(Inst::Branch(flag, false, done));
+        let v = self.expr(value)?;
+        let slot = self.addr(name, kind);
+        self.push(Inst::Store { width: Width::W64, src: v, base: slot, offset: 0, ordered: false });
+        let one = self.imm(1);
+        let ready = self.addr(&ready_sym, kind);
+        self.push(Inst::Store { width: Width::W64, src: one, base: ready, offset: 0, ordered: true });
+        self.push(Inst::Label(done));
+        let result = self.addr(name, kind);
+        self.push(Inst::Ret(result));
+        self.finish_function(&format!("{}.addr", name), false, Vec::new());
+        Ok(())
+    }
+
+    // ========== Registers, locals and types ==========
+
+    fn vreg(&mut self) -> VReg {
+        self.vregs += 1;
+        self.vregs - 1
+    }
+
+    fn label(&mut self) -> Label {
+        self.labels += 1;
+        self.labels - 1
+    }
+
+    fn slot(&mut self) -> u32 {
+        self.slots += 1;
+        self.slots - 1
+    }
+
+    fn push(&mut self, inst: Inst) {
+        self.insts.push(inst);
+    }
+
+    fn imm(&mut self, value: i64) -> VReg {
+        let d = self.vreg();
+        self.push(Inst::Imm(d, value));
+        d
+    }
+
+    fn addr(&mut self, symbol: &str, kind: SymKind) -> VReg {
+        let d = self.vreg();
+        self.push(Inst::Addr(d, symbol.to_string(), kind));
+        d
+    }
+
+    /// Fresh register with the value of `v`
+    fn copy(&mut self, v: VReg) -> VReg {
+        let d = self.vreg();
+        self.push(Inst::Copy(d, v));
+        d
+    }
+
+    /// Copy a local's register if evaluating `later` could reassign it first
+    fn stable(&mut self, v: VReg, later: &Expr) -> VReg {
+        if self.local_regs.contains(&v) && has_block(later) {
+            self.copy(v)
+        } else {
+            v
+        }
+    }
+
+    /// Store `v` into a local's register, retargeting the instruction that just computed it
+    fn assign_to(&mut self, local: VReg, v: VReg) {
+        if !self.local_regs.contains(&v) {
+            if let Some(last) = self.insts.last_mut().filter(|i| i.def() == Some(v)) {
+                last.set_def(local);
+                return;
+            }
+        }
+        if local != v {
+            self.push(Inst::Copy(local, v));
+        }
+    }
+
+    /// Bring a new local into scope holding `v`
+    fn bind(&mut self, name: &str, v: VReg, ty: Option<Type>) {
+        let home = if self.addressed.contains(name) {
+            let slot = self.slot();
+            self.push(Inst::StoreSlot(v, slot));
+            Home::Slot(slot)
+        } else if self.local_regs.contains(&v) {
+            // Another variable's register: the new one needs its own
+            let d = self.copy(v);
+            self.local_regs.insert(d);
+            Home::Reg(d)
+        } else {
+            self.local_regs.insert(v);
+            Home::Reg(v)
+        };
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), Local { home, ty });
+        }
+    }
+
+    fn local(&self, name: &str) -> Option<&Local> {
+        self.scopes.iter().rev().find_map(|s| s.get(name))
+    }
+
+    fn resolve_self(&self, ty: &Type) -> Type {
+        match &self.self_type {
+            Some(s) => with_self(ty, s),
+            None => ty.clone(),
+        }
+    }
+
+    /// Static type of an expression, where lowering needs it (fields, methods, `for`)
+    fn type_of(&self, expr: &Expr) -> Option<Type> {
+        self.own.type_of(expr, &|name| match self.local(name) {
+            Some(local) => Some(local.ty.clone()),
+            None => self.statics.get(name).map(|s| s.ty.clone()),
+        })
+    }
+
+    fn float_operands(&self, left: &Expr, right: &Expr) -> bool {
+        self.own.float_operands(left, right, &|name| match self.local(name) {
+            Some(local) => Some(local.ty.clone()),
+            None => self.statics.get(name).map(|s| s.ty.clone()),
+        })
+    }
+
+    /// An operand of Float arithmetic as the bits of a double; Ints are converted
+    fn double(&mut self, e: &Expr) -> Result<VReg> {
+        if let Expr::Int(v, _) = e {
+            return Ok(self.imm((*v as f64).to_bits() as i64));
+        }
+        let v = self.expr(e)?;
+        if self.type_of(e).is_some_and(|t| t.is_float()) {
+            return Ok(v);
+        }
+        let d = self.vreg();
+        self.push(Inst::IntToFloat(d, v));
+        Ok(d)
+    }
+
+    /// Symbol of a function of this module or a foreign one
+    fn symbol(&self, name: &str) -> String {
+        if self.exports.iter().any(|(n, _)| n == name) {
+            format!("{}.body", name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Address of a static, running its initializer if needed
+    fn static_address(&mut self, name: &str) -> VReg {
+        let info = &self.statics[name];
+        if info.lazy {
+            let d = self.vreg();
+            self.push(Inst::Call { dst: Some(d), callee: Callee::Direct(format!("{}.addr", name)), args: Vec::new(), abi: None });
+            d
+        } else {
+            let kind = if info.thread_local { SymKind::ThreadLocal } else { SymKind::Data };
+            self.addr(name, kind)
+        }
+    }
+
+    fn string_label(&mut self, s: &str) -> String {
+        if let Some(label) = self.strings.get(s) {
+            return label.clone();
+        }
+        let label = format!(".Lstr{}", self.strings.len());
+        self.data.push(Data { name: label.clone(), global: false, kind: DataKind::ReadOnly, init: Init::Str(s.to_string()) });
+        self.strings.insert(s.to_string(), label.clone());
+        label
+    }
+
+    // ========== Blocks and statements ==========
+
+    /// Value of the block's final expression, or 0
+    fn block(&mut self, block: &Block) -> Result<VReg> {
+        self.scopes.push(HashMap::new());
+        let result = self.stmts(&block.stmts);
+        self.scopes.pop();
+        result
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) -> Result<VReg> {
+        let Some((last, rest)) = stmts.split_last() else {
+            return Ok(self.imm(0));
+        };
+        for stmt in rest {
+            self.stmt(stmt)?;
+        }
+        match last {
+            Stmt::Expr(e, _) => self.expr(e),
+            Stmt::Block(b, _) => self.block(b),
+            _ => {
+                self.stmt(last)?;
+                Ok(self.imm(0))
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<()> {
+        match stmt {
+            Stmt::Let { name, ty, init, .. } => {
+                let ty = match ty {
+                    Some(Type::Infer) | None => init.as_ref().and_then(|e| self.type_of(e)),
+                    Some(t) => Some(self.resolve_self(t)),
+                };
+                let v = match init {
+                    Some(e) => self.expr(e)?,
+                    None => self.imm(0),
+                };
+                self.bind(name, v, ty);
+            }
+
+            Stmt::Assign(target, value, span) => self.assign(target, value, *span)?,
+
+            Stmt::Expr(e, _) => {
+                self.expr(e)?;
+            }
+
+            Stmt::Return(e, _) => {
+                let v = match e {
+                    Some(e) => self.expr(e)?,
+                    None => self.imm(0),
+                };
+                self.push(Inst::Ret(v));
+            }
+
+            Stmt::If(cond, then_block, else_block, _) => {
+                self.branch(cond, then_block, else_block.as_ref())?;
+            }
+
+            Stmt::While(cond, body, _) => {
+                let top = self.label();
+                let exit = self.label();
+                self.push(Inst::Label(top));
+                let c = self.expr(cond)?;
+                self.push(Inst::Branch(c, true, exit));
+                self.loops.push((top, exit));
+                self.block(body)?;
+                self.loops.pop();
+                self.push(Inst::Jump(top));
+                self.push(Inst::Label(exit));
+            }
+
+            Stmt::For(var, iter, body, span) => self.for_loop(var, iter, body, *span)?,
+
+            Stmt::Break(span) | Stmt::Continue(span) => {
+                let is_break = matches!(stmt, Stmt::Break(_));
+                let Some(&(next, exit)) = self.loops.last() else {
+                    bail!("`{}` outside of a loop at line {}", if is_break { "break" } else { "continue" }, span.line);
+                };
+                self.push(Inst::Jump(if is_break { exit } else { next }));
+            }
+
+            Stmt::Block(block, _) => {
+                self.block(block)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `if` as a statement or expression; evaluates to the taken branch's value
+    fn branch(&mut self, cond: &Expr, then_block: &Block, else_block: Option<&Block>) -> Result<VReg> {
+        let else_label = self.label();
+        let end = self.label();
+        let result = self.vreg();
+        let c = self.expr(cond)?;
+        self.push(Inst::Branch(c, true, else_label));
+        let v = self.block(then_block)?;
+        self.push(Inst::Copy(result, v));
+        self.push(Inst::Jump(end));
+        self.push(Inst::Label(else_label));
+        let v = match else_block {
+            Some(b) => self.block(b)?,
+            None => self.imm(0),
+        };
+        self.push(Inst::Copy(result, v));
+        self.push(Inst::Label(end));
+        Ok(result)
+    }
+
+    /// `for x in xs` over a fixed-size array or a vector (`[data, len, cap]`)
+    fn for_loop(&mut self, var: &str, iter: &Expr, body: &Block, span: Span) -> Result<()> {
+        let (source, elem_ty) = for_source(self.type_of(iter), span)?;
+        let (base, len) = match source {
+            ForSource::Array(n) => (self.expr(iter)?, Operand::Imm(n as i64)),
+            ForSource::Vec => {
+                let vec = self.expr(iter)?;
+                let len = self.vreg();
+                self.push(Inst::Load { width: Width::W64, dst: len, base: vec, offset: 8, ordered: false });
+                let base = self.vreg();
+                self.push(Inst::Load { width: Width::W64, dst: base, base: vec, offset: 0, ordered: false });
+                (base, Operand::Reg(len))
+            }
+        };
+        let top = self.label();
+        let next = self.label();
+        let exit = self.label();
+        let index = self.imm(0);
+        self.push(Inst::Label(top));
+        let more = self.vreg();
+        self.push(Inst::Bin(BinOp::Lt, more, index, len));
+        self.push(Inst::Branch(more, true, exit));
+        let offset = self.vreg();
+        self.push(Inst::Bin(BinOp::Shl, offset, index, Operand::Imm(3)));
+        let addr = self.vreg();
+        self.push(Inst::Bin(BinOp::Add, addr, base, Operand::Reg(offset)));
+        let elem = self.vreg();
+        self.push(Inst::Load { width: Width::W64, dst: elem, base: addr, offset: 0, ordered: false });
+        self.scopes.push(HashMap::new());
+        self.bind(var, elem, elem_ty);
+        self.loops.push((next, exit));
+        let result = self.block(body);
+        self.loops.pop();
+        self.scopes.pop();
+        result?;
+        self.push(Inst::Label(next));
+        self.push(Inst::Bin(BinOp::Add, index, index, Operand::Imm(1)));
+        self.push(Inst::Jump(top));
+        self.push(Inst::Label(exit));
+        Ok(())
+    }
+
+    fn assign(&mut self, target: &Expr, value: &Expr, span: Span) -> Result<()> {
+        if let Expr::Ident(name, _) = target {
+            if let Some(home) = self.local(name).map(|l| l.home) {
+                let v = self.expr(value)?;
+                match home {
+                    Home::Reg(local) => self.assign_to(local, v),
+                    Home::Slot(slot) => self.push(Inst::StoreSlot(v, slot)),
+                }
+                return Ok(());
+            }
+            let Some(atomic) = self.statics.get(name).map(|s| s.atomic) else {
+                bail!("Undefined variable {} at line {}", name, span.line);
+            };
+            if let Some((op, operand)) = atomic_update(name, value).filter(|_| atomic) {
+                let v = self.expr(operand)?;
+                let base = self.static_address(name);
+                self.push(Inst::AtomicRmw { op, src: v, base });
+                return Ok(());
+            }
+            let v = self.expr(value)?;
+            let base = self.static_address(name);
+            self.push(Inst::Store { width: Width::W64, src: v, base, offset: 0, ordered: atomic });
+            return Ok(());
+        }
+        if !matches!(target, Expr::Field(..) | Expr::Index(..) | Expr::Unary(UnOp::Deref, ..)) {
+            bail!("Invalid assignment target at line {}", span.line);
+        }
+        let v = self.expr(value)?;
+        let v = self.stable(v, target);
+        let (base, offset) = self.place(target)?;
+        self.push(Inst::Store { width: Width::W64, src: v, base, offset, ordered: false });
+        Ok(())
+    }
+
+    // ========== Expressions ==========
+
+    fn expr(&mut self, expr: &Expr) -> Result<VReg> {
+        let v = match expr {
+            Expr::Int(v, _) => self.imm(*v),
+            Expr::Bool(b, _) => self.imm(*b as i64),
+            // Floats travel as their bit patterns
+            Expr::Float(f, _) => self.imm(f.to_bits() as i64),
+            Expr::String(s, _) => {
+                let label = self.string_label(s);
+                self.addr(&label, SymKind::Data)
+            }
+
+            Expr::Ident(name, span) => {
+                if let Some(home) = self.local(name).map(|l| l.home) {
+                    match home {
+                        Home::Reg(v) => v,
+                        Home::Slot(slot) => {
+                            let d = self.vreg();
+                            self.push(Inst::LoadSlot(d, slot));
+                            d
+                        }
+                    }
+                } else if let Some(v) = self.consts.get(name).copied() {
+                    self.imm(v)
+                } else if self.const_arrays.contains(name) {
+                    self.addr(name, SymKind::Data)
+                } else if let Some(atomic) = self.statics.get(name).map(|s| s.atomic) {
+                    let base = self.static_address(name);
+                    let d = self.vreg();
+                    self.push(Inst::Load { width: Width::W64, dst: d, base, offset: 0, ordered: atomic });
+                    d
+                } else if self.funcs.contains_key(name) {
+                    self.function_value(name)?
+                } else {
+                    bail!("Undefined variable {} at line {}", name, span.line);
+                }
+            }
+
+            Expr::Binary(op, left, right, _) if is_float_op(*op) && self.float_operands(left, right) => {
+                let a = self.double(left)?;
+                let a = self.stable(a, right);
+                let b = self.double(right)?;
+                if *op != BinOp::Mod {
+                    let d = self.vreg();
+                    self.push(Inst::FBin(*op, d, a, b));
+                    return Ok(d);
+                }
+                // a - b * trunc(a / b)
+                let (q, t, tf, p, d) = (self.vreg(), self.vreg(), self.vreg(), self.vreg(), self.vreg());
+                self.push(Inst::FBin(BinOp::Div, q, a, b));
+                self.push(Inst::FloatToInt(t, q));
+                self.push(Inst::IntToFloat(tf, t));
+                self.push(Inst::FBin(BinOp::Mul, p, b, tf));
+                self.push(Inst::FBin(BinOp::Sub, d, a, p));
+                d
+            }
+
+            Expr::Binary(op, left, right, _) => {
+                let a = self.expr(left)?;
+                let a = self.stable(a, right);
+                let b = match right.as_ref() {
+                    Expr::Int(v, _) => Operand::Imm(*v),
+                    _ => Operand::Reg(self.expr(right)?),
+                };
+                let d = self.vreg();
+                self.push(Inst::Bin(*op, d, a, b));
+                d
+            }
+
+            Expr::Unary(UnOp::Ref | UnOp::RefMut, inner, _) => self.address(inner)?,
+
+            Expr::Unary(op, inner, _) => {
+                let a = self.expr(inner)?;
+                let d = self.vreg();
+                self.push(match op {
+                    // Flip the sign bit
+                    UnOp::Neg if self.type_of(inner).is_some_and(|t| t.is_float()) => Inst::Bin(BinOp::BitXor, d, a, Operand::Imm(i64::MIN)),
+                    UnOp::Neg => Inst::Neg(d, a),
+                    UnOp::Not => Inst::Bin(BinOp::BitXor, d, a, Operand::Imm(1)),
+                    UnOp::BitNot => Inst::BitNot(d, a),
+                    _ => Inst::Load { width: Width::W64, dst: d, base: a, offset: 0, ordered: false },
+                });
+                d
+            }
+
+            Expr::Call(func, args, span) => self.call_expr(func, args, *span)?,
+
+            Expr::MethodCall(obj, method, args, span) => self.method_call(obj, method, args, *span)?,
+
+            Expr::Field(..) | Expr::Index(..) => {
+                let (base, offset) = self.place(expr)?;
+                let d = self.vreg();
+                self.push(Inst::Load { width: Width::W64, dst: d, base, offset, ordered: false });
+                d
+            }
+
+            // Arrays are heap blocks of 8-byte elements
+            Expr::Array(elems, span) => {
+                let values: Vec<&Expr> = elems.iter().collect();
+                self.heap_block(&values, *span)?
+            }
+
+            // Structs are heap blocks of 8-byte fields in declaration order
+            Expr::Struct(name, fields, span) => {
+                let layout: Vec<String> = self.own.fields(name)
+                    .ok_or_else(|| anyhow!("Unknown struct {} at line {}", name, span.line))?
+                    .iter().map(|(f, _)| f.clone()).collect();
+                let mut values = Vec::new();
+                for field in &layout {
+                    match fields.iter().find(|(f, _)| f == field) {
+                        Some((_, value)) => values.push(value),
+                        None => bail!("Missing field {} in {} literal at line {}", field, name, span.line),
+                    }
+                }
+                if let Some((extra, _)) = fields.iter().find(|(f, _)| !layout.contains(f)) {
+                    bail!("Unknown field {} in {} literal at line {}", extra, name, span.line);
+                }
+                self.heap_block(&values, *span)?
+            }
+
+            Expr::If(cond, then_block, else_block, _) => self.branch(cond, then_block, else_block.as_deref())?,
+
+            Expr::Unsafe(block, _) | Expr::Comptime(block, _) => self.block(block)?,
+
+            Expr::Path(path, span) => {
+                if path.len() != 2 || !self.enums.contains_key(&path[0]) {
+                    bail!("Unknown value {} at line {}", path.join("::"), span.line);
+                }
+                self.variant(&path[0], &path[1], &[], *span)?
+            }
+
+            Expr::Spawn(func, args, span) => {
+                let Expr::Ident(name, _) = func.as_ref() else {
+                    bail!("spawn needs a function name at line {}", span.line);
+                };
+                if args.len() > 1 {
+                    bail!("spawn passes at most one argument to the thread at line {}", span.line);
+                }
+                let arg = match args.first() {
+                    Some(arg) => self.expr(arg)?,
+                    None => self.imm(0),
+                };
+                let tid = self.slot();
+                let tid_addr = self.vreg();
+                self.push(Inst::SlotAddr(tid_addr, tid));
+                let attr = self.imm(0);
+                let sym = self.symbol(name);
+                let entry = self.addr(&sym, SymKind::Code);
+                let args = vec![tid_addr, attr, entry, arg];
+                self.push(Inst::Call { dst: None, callee: Callee::Direct("pthread_create".into()), args, abi: None });
+                let d = self.vreg();
+                self.push(Inst::LoadSlot(d, tid));
+                d
+            }
+
+            Expr::Lambda(params, _, body, span) => self.lambda(params, body, *span)?,
+
+            Expr::Match(scrutinee, arms, span) => self.match_expr(scrutinee, arms, *span)?,
+        };
+        Ok(v)
+    }
+
+    /// Allocate a block of words and fill it with `values` in order
+    fn heap_block(&mut self, values: &[&Expr], span: Span) -> Result<VReg> {
+        let block = self.call(Callee::Direct("malloc".into()), &[Expr::Int(8 * values.len() as i64, span)], None)?;
+        for (i, value) in values.iter().enumerate() {
+            let v = self.expr(value)?;
+            self.push(Inst::Store { width: Width::W64, src: v, base: block, offset: 8 * i as i64, ordered: false });
+        }
+        Ok(block)
+    }
+
+    /// Allocate a record holding `words`
+    fn record(&mut self, words: &[VReg], span: Span) -> Result<VReg> {
+        let block = self.call(Callee::Direct("malloc".into()), &[Expr::Int(8 * words.len() as i64, span)], None)?;
+        for (i, &v) in words.iter().enumerate() {
+            self.push(Inst::Store { width: Width::W64, src: v, base: block, offset: 8 * i as i64, ordered: false });
+        }
+        Ok(block)
+    }
+
+    /// Enum variant: its index, or a tagged record with the payload
+    fn variant(&mut self, enum_name: &str, variant: &str, args: &[Expr], span: Span) -> Result<VReg> {
+        let info = self.enums[enum_name].clone();
+        let Some(index) = info.variants.iter().position(|v| v.name == variant) else {
+            bail!("No variant {} in {} at line {}", variant, enum_name, span.line);
+        };
+        let fields = info.variants[index].fields.len();
+        if args.len() != fields {
+            bail!("Wrong number of values for {}::{} at line {}: expected {}, got {}", enum_name, variant, span.line, fields, args.len());
+        }
+        if !info.tagged {
+            return Ok(self.imm(index as i64));
+        }
+        let mut words = vec![self.imm(index as i64)];
+        words.extend(self.args(args)?);
+        self.record(&words, span)
+    }
+
+    /// Memory location of a field, element or dereference as base and byte offset
+    fn place(&mut self, place: &Expr) -> Result<(VReg, i64)> {
+        match place {
+            Expr::Field(obj, field, span) => {
+                let Some((type_name, derefs)) = self.type_of(obj).and_then(|t| self.own.named(&t))
+                    .filter(|(n, _)| self.own.field(n, field).is_some()) else {
+                    bail!("Unknown field {} at line {}", field, span.line);
+                };
+                let index = self.own.fields(&type_name).into_iter().flatten()
+                    .position(|(f, _)| f == field).unwrap_or(0);
+                let mut base = self.expr(obj)?;
+                for _ in 0..derefs {
+                    let d = self.vreg();
+                    self.push(Inst::Load { width: Width::W64, dst: d, base, offset: 0, ordered: false });
+                    base = d;
+                }
+                Ok((base, 8 * index as i64))
+            }
+            Expr::Index(arr, idx, _) => {
+                let base = self.expr(arr)?;
+                if let Expr::Int(i, _) = idx.as_ref() {
+                    return Ok((base, 8 * i));
+                }
+                let base = self.stable(base, idx);
+                let i = self.expr(idx)?;
+                let offset = self.vreg();
+                self.push(Inst::Bin(BinOp::Shl, offset, i, Operand::Imm(3)));
+                let d = self.vreg();
+                self.push(Inst::Bin(BinOp::Add, d, base, Operand::Reg(offset)));
+                Ok((d, 0))
+            }
+            Expr::Unary(UnOp::Deref, inner, _) => Ok((self.expr(inner)?, 0)),
+            _ => Ok((self.address(place)?, 0)),
+        }
+    }
+
+    /// Address of a place; other values are copied to a fresh slot
+    fn address(&mut self, place: &Expr) -> Result<VReg> {
+        match place {
+            Expr::Ident(name, _) if matches!(self.local(name).map(|l| l.home), Some(Home::Slot(_))) => {
+                let Some(Home::Slot(slot)) = self.local(name).map(|l| l.home) else { unreachable!() };
+                let d = self.vreg();
+                self.push(Inst::SlotAddr(d, slot));
+                Ok(d)
+            }
+            Expr::Ident(name, _) if self.local(name).is_none() && self.statics.contains_key(name) => {
+                Ok(self.static_address(name))
+            }
+            Expr::Field(..) | Expr::Index(..) | Expr::Unary(UnOp::Deref, ..) => {
+                let (base, offset) = self.place(place)?;
+                if offset == 0 {
+                    return Ok(base);
+                }
+                let d = self.vreg();
+                self.push(Inst::Bin(BinOp::Add, d, base, Operand::Imm(offset)));
+                Ok(d)
+            }
+            _ => {
+                let v = self.expr(place)?;
+                let slot = self.slot();
+                self.push(Inst::StoreSlot(v, slot));
+                let d = self.vreg();
+                self.push(Inst::SlotAddr(d, slot));
+                Ok(d)
+            }
+        }
+    }
+
+    // ========== Calls ==========
+
+    fn call_expr(&mut self, func: &Expr, args: &[Expr], span: Span) -> Result<VReg> {
+        match func {
+            Expr::Ident(name, _) => {
+                if self.local(name).is_some() {
+                    let f = self.expr(func)?;
+                    return self.closure_call(f, args);
+                }
+                if let Some(builtin) = name.strip_prefix("__builtin_") {
+                    return self.builtin(builtin, args, span);
+                }
+                if let Some((params, _)) = self.funcs.get(name).cloned() {
+                    let args = with_defaults(name, &params, args, span)?;
+                    return self.call(Callee::Direct(self.symbol(name)), &args, None);
+                }
+                let abi = self.externs.get(name).cloned();
+                self.call(Callee::Direct(name.clone()), args, abi)
+            }
+            Expr::Path(path, _) if path.len() == 2 && self.enums.contains_key(&path[0]) => {
+                self.variant(&path[0], &path[1], args, span)
+            }
+            Expr::Path(path, _) if path.len() == 2 => {
+                let symbol = method_symbol(&path[0], &path[1]);
+                let Some((params, _)) = self.funcs.get(&symbol).cloned() else {
+                    bail!("Unknown function {} at line {}", path.join("::"), span.line);
+                };
+                let args = with_defaults(&path.join("::"), &params, args, span)?;
+                self.call(Callee::Direct(symbol), &args, None)
+            }
+            // A closure record
+            _ => {
+                let f = self.expr(func)?;
+                self.closure_call(f, args)
+            }
+        }
+    }
+
+    fn method_call(&mut self, obj: &Expr, method: &str, args: &[Expr], span: Span) -> Result<VReg> {
+        let Some((type_name, derefs)) = self.type_of(obj).and_then(|t| self.own.named(&t)) else {
+            bail!("Cannot tell the type of the receiver of {} at line {}", method, span.line);
+        };
+        let symbol = method_symbol(&type_name, method);
+        let Some((params, _)) = self.funcs.get(&symbol).cloned() else {
+            bail!("No method {} on {} at line {}", method, type_name, span.line);
+        };
+        if params.first().is_none_or(|p| p.name != "self") {
+            bail!("{}::{} takes no self; call it as {}::{}() at line {}", type_name, method, type_name, method, span.line);
+        }
+        // Receiver: borrow it for `&self`, otherwise pass the value behind any references
+        let by_ref = params[0].ty.pointee().is_some();
+        let mut receiver = obj.clone();
+        if by_ref && derefs == 0 {
+            receiver = Expr::Unary(UnOp::Ref, Box::new(receiver), span);
+        } else {
+            for _ in 0..derefs - usize::from(by_ref) {
+                receiver = Expr::Unary(UnOp::Deref, Box::new(receiver), span);
+            }
+        }
+        let mut full = vec![receiver];
+        full.extend(with_defaults(&format!("{}::{}", type_name, method), &params[1..], args, span)?);
+        self.call(Callee::Direct(symbol), &full, None)
+    }
+
+    /// Evaluate the arguments left to right and call
+    fn call(&mut self, callee: Callee, args: &[Expr], abi: Option<CAbi>) -> Result<VReg> {
+        let regs = self.args(args)?;
+        let d = self.vreg();
+        self.push(Inst::Call { dst: Some(d), callee, args: regs, abi });
+        Ok(d)
+    }
+
+    /// Values of the arguments, left to right, unaffected by later assignments
+    fn args(&mut self, args: &[Expr]) -> Result<Vec<VReg>> {
+        let mut regs = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            let v = self.expr(arg)?;
+            regs.push(v);
+            if args[i + 1..].iter().any(has_block) && self.local_regs.contains(&v) {
+                regs[i] = self.copy(v);
+            }
+        }
+        Ok(regs)
+    }
+
+    /// Call the code of closure record `f`, passing the record first
+    fn closure_call(&mut self, f: VReg, args: &[Expr]) -> Result<VReg> {
+        let f = if args.iter().any(has_block) && self.local_regs.contains(&f) { self.copy(f) } else { f };
+        let mut regs = vec![f];
+        regs.extend(self.args(args)?);
+        let code = self.vreg();
+        self.push(Inst::Load { width: Width::W64, dst: code, base: f, offset: 0, ordered: false });
+        let d = self.vreg();
+        self.push(Inst::Call { dst: Some(d), callee: Callee::Indirect(code), args: regs, abi: None });
+        Ok(d)
+    }
+
+    fn builtin(&mut self, builtin: &str, args: &[Expr], span: Span) -> Result<VReg> {
+        let arity = match builtin {
+            "load8" | "load16" | "load32" | "load64" | "print" => 1,
+            "store8" | "store16" | "store32" | "store64" => 2,
+            _ => 0,
+        };
+        if args.len() < arity {
+            bail!("Wrong number of arguments to __builtin_{} at line {}: expected {}, got {}", builtin, span.line, arity, args.len());
+        }
+        let width = |suffix: &str| match suffix {
+            "8" => Width::W8,
+            "16" => Width::W16,
+            "32" => Width::W32,
+            _ => Width::W64,
+        };
+        if let Some(bits) = builtin.strip_prefix("load") {
+            let base = self.expr(&args[0])?;
+            let d = self.vreg();
+            self.push(Inst::Load { width: width(bits), dst: d, base, offset: 0, ordered: false });
+            return Ok(d);
+        }
+        if let Some(bits) = builtin.strip_prefix("store") {
+            let base = self.expr(&args[0])?;
+            let base = self.stable(base, &args[1]);
+            let v = self.expr(&args[1])?;
+            self.push(Inst::Store { width: width(bits), src: v, base, offset: 0, ordered: false });
+            return Ok(self.imm(0));
+        }
+        if builtin == "print" {
+            // One byte to stdout
+            let v = self.expr(&args[0])?;
+            let slot = self.slot();
+            let buf = self.vreg();
+            self.push(Inst::SlotAddr(buf, slot));
+            self.push(Inst::Store { width: Width::W8, src: v, base: buf, offset: 0, ordered: false });
+            let one = self.imm(1);
+            self.push(Inst::Call { dst: None, callee: Callee::Direct("write".into()), args: vec![one, buf, one], abi: None });
+            return Ok(self.imm(0));
+        }
+        // Others stay external calls by name, as with the LLVM backend
+        let Some(&(name, c_int)) = LIBC.iter().find(|(name, _)| *name == builtin) else {
+            return self.call(Callee::Direct(format!("__builtin_{}", builtin)), args, None);
+        };
+        let mut args = args.to_vec();
+        if name == "open" && args.len() == 2 {
+            args.push(Expr::Int(0, span));
+        }
+        let abi = c_int.then(|| CAbi { params: vec![None; args.len()], ret: c_scalar("Int32") });
+        self.call(Callee::Direct(name.to_string()), &args, abi)
+    }
+
+    // ========== Closures ==========
+
+    /// A named function as a value: a closure record for its thunk, which drops the record argument
+    fn function_value(&mut self, name: &str) -> Result<VReg> {
+        if !self.thunks.iter().any(|t| t == name) {
+            self.thunks.push(name.to_string());
+        }
+        let code = self.addr(&format!("{}.thunk", self.symbol(name)), SymKind::Code);
+        self.record(&[code], Span::default())
+    }
+
+    fn thunk(&mut self, name: &str) {
+        self.begin_function();
+        let symbol = self.symbol(name);
+        let argc = self.funcs[name].0.len();
+        let params: Vec<VReg> = (0..=argc).map(|_| self.vreg()).collect();
+        let d = self.vreg();
+        self.push(Inst::Call { dst: Some(d), callee: Callee::Direct(symbol.clone()), args: params[1..].to_vec(), abi: None });
+        self.push(Inst::Ret(d));
+        self.finish_function(&format!("{}.thunk", symbol), false, params);
+    }
+
+    /// Closure record with its code and the captured locals copied in; the
+    /// body becomes a function taking the record, lowered after this one
+    fn lambda(&mut self, params: &[Param], body: &Expr, span: Span) -> Result<VReg> {
+        let mut names = Vec::new();
+        free_idents(body, &mut names);
+        let captures: Vec<(String, Option<Type>)> = names.into_iter()
+            .filter(|n| !params.iter().any(|p| &p.name == n))
+            .filter_map(|n| self.local(&n).map(|l| l.ty.clone()).map(|ty| (n, ty)))
+            .collect();
+        let symbol = format!("{}.lambda{}", self.current, self.lambda_count);
+        self.lambda_count += 1;
+        let mut words = vec![self.addr(&symbol, SymKind::Code)];
+        for (name, _) in &captures {
+            words.push(self.expr(&Expr::Ident(name.clone(), span))?);
+        }
+        self.lambdas.push(Lambda {
+            symbol,
+            params: params.to_vec(),
+            captures,
+            body: body.clone(),
+            self_type: self.self_type.clone(),
+        });
+        self.record(&words, span)
+    }
+
+    fn lambda_body(&mut self, lambda: Lambda) -> Result<()> {
+        self.begin_function();
+        self.current = lambda.symbol.clone();
+        self.self_type = lambda.self_type;
+        addressed_expr(&lambda.body, &mut self.addressed);
+        let env = self.vreg();
+        let mut regs = vec![env];
+        for param in &lambda.params {
+            let v = self.vreg();
+            regs.push(v);
+            let ty = match &param.ty {
+                Type::Infer => None,
+                t => Some(self.resolve_self(t)),
+            };
+            self.bind(&param.name, v, ty);
+        }
+        for (i, (name, ty)) in lambda.captures.into_iter().enumerate() {
+            let d = self.vreg();
+            self.push(Inst::Load { width: Width::W64, dst: d, base: env, offset: 8 + 8 * i as i64, ordered: false });
+            self.bind(&name, d, ty);
+        }
+        let result = self.expr(&lambda.body);
+        self.self_type = None;
+        let result = result?;
+        self.push(Inst::Ret(result));
+        self.finish_function(&lambda.symbol, false, regs);
+        Ok(())
+    }
+
+    // ========== Patterns ==========
+
+    /// `match`: arms are tried in order; a value no arm matches stops the
+    /// program, as `abort()` does in the C backend
+    fn match_expr(&mut self, scrutinee: &Expr, arms: &[MatchArm], span: Span) -> Result<VReg> {
+        let ty = self.type_of(scrutinee);
+        let s = self.expr(scrutinee)?;
+        let s = if self.local_regs.contains(&s) { self.copy(s) } else { s };
+        let result = self.vreg();
+        let end = self.label();
+        let mut exhaustive = false;
+        for arm in arms {
+            let next = self.label();
+            self.scopes.push(HashMap::new());
+            let value = self.arm(arm, s, ty.clone(), next, span);
+            self.scopes.pop();
+            let (tests, v) = value?;
+            self.push(Inst::Copy(result, v));
+            self.push(Inst::Jump(end));
+            self.push(Inst::Label(next));
+            if !tests && arm.guard.is_none() {
+                exhaustive = true;
+                break;
+            }
+        }
+        if !exhaustive {
+            let code = self.imm(134);
+            self.push(Inst::Call { dst: None, callee: Callee::Direct("exit".into()), args: vec![code], abi: None });
+        }
+        self.push(Inst::Label(end));
+        Ok(result)
+    }
+
+    /// Whether the arm's pattern tests anything, and its value once it matched
+    fn arm(&mut self, arm: &MatchArm, s: VReg, ty: Option<Type>, next: Label, span: Span) -> Result<(bool, VReg)> {
+        let tests = self.pattern(&arm.pattern, s, ty, next, span)?;
+        if let Some(guard) = &arm.guard {
+            let g = self.expr(guard)?;
+            self.push(Inst::Branch(g, true, next));
+        }
+        Ok((tests, self.expr(&arm.body)?))
+    }
+
+    /// Test the word `s` against a pattern, jumping to `fail` if it does not
+    /// match, and bind its names; whether it tests anything
+    fn pattern(&mut self, pattern: &Pattern, s: VReg, ty: Option<Type>, fail: Label, span: Span) -> Result<bool> {
+        match pattern {
+            Pattern::Wildcard => Ok(false),
+            Pattern::Ident(name) => {
+                let v = self.copy(s);
+                self.bind(name, v, ty);
+                Ok(false)
+            }
+            Pattern::Literal(Expr::String(text, _)) => {
+                // Byte by byte, up to the terminating NUL
+                let label = self.string_label(text);
+                let a = self.copy(s);
+                let b = self.addr(&label, SymKind::Data);
+                let top = self.label();
+                let matched = self.label();
+                self.push(Inst::Label(top));
+                let (x, y, ne) = (self.vreg(), self.vreg(), self.vreg());
+                self.push(Inst::Load { width: Width::W8, dst: x, base: a, offset: 0, ordered: false });
+                self.push(Inst::Load { width: Width::W8, dst: y, base: b, offset: 0, ordered: false });
+                self.push(Inst::Bin(BinOp::Ne, ne, x, Operand::Reg(y)));
+                self.push(Inst::Branch(ne, false, fail));
+                self.push(Inst::Branch(x, true, matched));
+                self.push(Inst::Bin(BinOp::Add, a, a, Operand::Imm(1)));
+                self.push(Inst::Bin(BinOp::Add, b, b, Operand::Imm(1)));
+                self.push(Inst::Jump(top));
+                self.push(Inst::Label(matched));
+                Ok(true)
+            }
+            Pattern::Literal(e) => {
+                let v = match e {
+                    Expr::Int(v, _) => Operand::Imm(*v),
+                    _ => Operand::Reg(self.expr(e)?),
+                };
+                let eq = self.vreg();
+                self.push(Inst::Bin(BinOp::Eq, eq, s, v));
+                self.push(Inst::Branch(eq, true, fail));
+                Ok(true)
+            }
+            Pattern::Enum(enum_name, variant, subs) => {
+                let Some(info) = self.enums.get(enum_name).cloned() else {
+                    bail!("Unknown enum {} in pattern at line {}", enum_name, span.line);
+                };
+                let Some(index) = info.variants.iter().position(|v| &v.name == variant) else {
+                    bail!("No variant {} in {} at line {}", variant, enum_name, span.line);
+                };
+                let fields = &info.variants[index].fields;
+                if subs.len() != fields.len() {
+                    bail!("Pattern {}::{} at line {} needs {} values, got {}", enum_name, variant, span.line, fields.len(), subs.len());
+                }
+                let tag = if info.tagged {
+                    let d = self.vreg();
+                    self.push(Inst::Load { width: Width::W64, dst: d, base: s, offset: 0, ordered: false });
+                    d
+                } else {
+                    s
+                };
+                let eq = self.vreg();
+                self.push(Inst::Bin(BinOp::Eq, eq, tag, Operand::Imm(index as i64)));
+                self.push(Inst::Branch(eq, true, fail));
+                for (i, (sub, field_ty)) in subs.iter().zip(fields).enumerate() {
+                    let field = self.vreg();
+                    self.push(Inst::Load { width: Width::W64, dst: field, base: s, offset: 8 + 8 * i as i64, ordered: false });
+                    self.pattern(sub, field, Some(field_ty.clone()), fail, span)?;
+                }
+                Ok(true)
+            }
+            Pattern::Struct(name, fields) => {
+                let Some((type_name, _)) = self.own.named(&Type::Named(name.clone())).filter(|(n, _)| self.own.fields(n).is_some()) else {
+                    bail!("Unknown struct {} in pattern at line {}", name, span.line);
+                };
+                let layout: Vec<(String, Type)> = self.own.fields(&type_name).into_iter().flatten().cloned().collect();
+                let mut tests = false;
+                for (field, sub) in fields {
+                    let Some(index) = layout.iter().position(|(f, _)| f == field) else {
+                        bail!("No field {} in {} at line {}", field, type_name, span.line);
+                    };
+                    let v = self.vreg();
+                    self.push(Inst::Load { width: Width::W64, dst: v, base: s, offset: 8 * index as i64, ordered: false });
+                    tests |= self.pattern(sub, v, Some(layout[index].1.clone()), fail, span)?;
+                }
+                Ok(tests)
+            }
+            Pattern::Tuple(_) => bail!("Tuple patterns need tuple values, which the native backends do not have (line {})", span.line),
+        }
+    }
+}
+
+/// Operators that work on doubles when an operand is a Float
+pub fn is_float_op(op: BinOp) -> bool {
+    !matches!(op, BinOp::And | BinOp::Or | BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::Shl | BinOp::Shr)
+}
+
+/// Whether evaluating the expression runs a block, which could assign locals
+fn has_block(e: &Expr) -> bool {
+    match e {
+        Expr::If(..) | Expr::Unsafe(..) | Expr::Comptime(..) | Expr::Match(..) => true,
+        Expr::Binary(_, l, r, _) | Expr::Index(l, r, _) => has_block(l) || has_block(r),
+        Expr::Unary(_, e, _) | Expr::Field(e, _, _) => has_block(e),
+        Expr::Call(f, args, _) | Expr::Spawn(f, args, _) => has_block(f) || args.iter().any(has_block),
+        Expr::MethodCall(obj, _, args, _) => has_block(obj) || args.iter().any(has_block),
+        Expr::Array(elems, _) => elems.iter().any(has_block),
+        Expr::Struct(_, fields, _) => fields.iter().any(|(_, e)| has_block(e)),
+        _ => false,
+    }
+}
+
+/// Collect locals whose address may be taken: `&x` and method receivers (`&self`)
+fn addressed_block(block: &Block, out: &mut HashSet<String>) {
+    for stmt in &block.stmts {
+        match stmt {
+            Stmt::Let { init: Some(e), .. } | Stmt::Expr(e, _) | Stmt::Return(Some(e), _) => addressed_expr(e, out),
+            Stmt::Assign(target, value, _) => {
+                addressed_expr(target, out);
+                addressed_expr(value, out);
+            }
+            Stmt::If(cond, then_block, else_block, _) => {
+                addressed_expr(cond, out);
+                addressed_block(then_block, out);
+                if let Some(b) = else_block {
+                    addressed_block(b, out);
+                }
+            }
+            Stmt::While(cond, body, _) | Stmt::For(_, cond, body, _) => {
+                addressed_expr(cond, out);
+                addressed_block(body, out);
+            }
+            Stmt::Block(b, _) => addressed_block(b, out),
+            _ => {}
+        }
+    }
+}
+
+fn addressed_expr(e: &Expr, out: &mut HashSet<String>) {
+    match e {
+        Expr::Unary(UnOp::Ref | UnOp::RefMut, inner, _) | Expr::MethodCall(inner, _, _, _)
+            if matches!(inner.as_ref(), Expr::Ident(..)) =>
+        {
+            if let Expr::Ident(name, _) = inner.as_ref() {
+                out.insert(name.clone());
+            }
+            if let Expr::MethodCall(_, _, args, _) = e {
+                args.iter().for_each(|a| addressed_expr(a, out));
+            }
+        }
+        Expr::Binary(_, l, r, _) | Expr::Index(l, r, _) => {
+            addressed_expr(l, out);
+            addressed_expr(r, out);
+        }
+        Expr::Unary(_, e, _) | Expr::Field(e, _, _) => addressed_expr(e, out),
+        Expr::Call(f, args, _) | Expr::Spawn(f, args, _) => {
+            addressed_expr(f, out);
+            args.iter().for_each(|a| addressed_expr(a, out));
+        }
+        Expr::MethodCall(obj, _, args, _) => {
+            addressed_expr(obj, out);
+            args.iter().for_each(|a| addressed_expr(a, out));
+        }
+        Expr::Array(elems, _) => elems.iter().for_each(|a| addressed_expr(a, out)),
+        Expr::Struct(_, fields, _) => fields.iter().for_each(|(_, a)| addressed_expr(a, out)),
+        Expr::If(cond, then_block, else_block, _) => {
+            addressed_expr(cond, out);
+            addressed_block(then_block, out);
+            if let Some(b) = else_block {
+                addressed_block(b, out);
+            }
+        }
+        Expr::Unsafe(b, _) | Expr::Comptime(b, _) => addressed_block(b, out),
+        Expr::Match(scrutinee, arms, _) => {
+            addressed_expr(scrutinee, out);
+            for arm in arms {
+                if let Some(guard) = &arm.guard {
+                    addressed_expr(guard, out);
+                }
+                addressed_expr(&arm.body, out);
+            }
+        }
+        _ => {}
+    }
+}
@@ -1 +1,7 @@
 pub mod llvm;
+pub mod mir;
+pub mod x86_64;
+pub mod arm64;
+pub mod header;
+pub mod wasm;
+pub mod c;
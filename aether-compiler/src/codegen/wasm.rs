@@ -0,0 +1,888 @@
+//! WebAssembly backend
+//!
+//! Translates the mid-level IR into a wasm32 module: words are i64 locals,
+//! pointers index one linear memory and frame slots live on a shadow stack.
+
+use std::collections::HashMap;
+use anyhow::{anyhow, bail, Result};
+use crate::ast::BinOp;
+use crate::binary::align_up;
+use crate::binary::wasm::*;
+use super::header::CScalar;
+use super::mir::{CAbi, Callee, ExportSig, Function, Init, Inst, Operand, Program, SymKind, Width};
+
+/// Address of the first data item; the page below it stays unused so null is never valid data
+const DATA_BASE: u64 = 1024;
+/// Shadow stack for frame slots, between the data and the heap
+const STACK_SIZE: u64 = 1 << 20;
+/// malloc size classes: blocks of `16 << class` bytes, including a 16-byte header
+const SIZE_CLASSES: u64 = 32;
+/// Largest malloc request
+const MAX_ALLOC: i64 = 1 << 30;
+
+const WASI_MODULE: &str = "wasi_snapshot_preview1";
+const ENV_MODULE: &str = "env";
+
+// Globals
+const STACK_POINTER: u32 = 0;
+const HEAP_NEXT: u32 = 1;
+
+/// How a function takes its arguments and returns its result
+#[derive(Debug, Clone)]
+enum Sig {
+    /// Aether words in and out, like every function the IR defines
+    Words(usize),
+    /// A host function with C scalar types
+    C(CAbi),
+    /// A fixed wasm signature, for WASI functions called by the runtime
+    Raw(Vec<ValType>, Vec<ValType>),
+}
+
+/// Runtime functions defined in the module instead of being imported, and
+/// whether they exist only on WASI
+const RUNTIME: &[(&str, bool)] = &[
+    ("malloc", false), ("free", false), ("memcpy", false), ("memset", false),
+    ("write", true), ("read", true), ("lseek", true), ("close", true), ("exit", true),
+    ("open", true), ("unlink", true), ("mkdir", true), ("rmdir", true), ("rename", true),
+];
+
+/// Directory that relative paths resolve against: the first one the host preopens
+const PREOPEN_FD: i32 = 3;
+/// Every file and directory right `wasi_snapshot_preview1` defines, asked for
+/// when opening files (the socket rights would make hosts refuse)
+const ALL_RIGHTS: i64 = (1 << 28) - 1;
+
+/// WASI functions the runtime calls, with their wasm signatures
+fn wasi_import(name: &str) -> (Vec<ValType>, Vec<ValType>) {
+    use ValType::{I32, I64};
+    match name {
+        "fd_write" | "fd_read" => (vec![I32, I32, I32, I32], vec![I32]),
+        "fd_seek" => (vec![I32, I64, I32, I32], vec![I32]),
+        "fd_close" => (vec![I32], vec![I32]),
+        "path_open" => (vec![I32, I32, I32, I32, I32, I64, I64, I32, I32], vec![I32]),
+        "path_unlink_file" | "path_create_directory" | "path_remove_directory" => (vec![I32, I32, I32], vec![I32]),
+        "path_rename" => (vec![I32, I32, I32, I32, I32, I32], vec![I32]),
+        _ => (vec![I32], Vec::new()),
+    }
+}
+
+/// WASI functions used by each runtime function
+fn wasi_deps(runtime: &str) -> &'static [&'static str] {
+    match runtime {
+        "write" => &["fd_write"],
+        "read" => &["fd_read"],
+        "lseek" => &["fd_seek"],
+        "close" => &["fd_close"],
+        "exit" => &["proc_exit"],
+        "open" => &["path_open"],
+        "unlink" => &["path_unlink_file"],
+        "mkdir" => &["path_create_directory"],
+        "rmdir" => &["path_remove_directory"],
+        "rename" => &["path_rename"],
+        _ => &[],
+    }
+}
+
+/// Wasm type of a C scalar
+fn scalar_type(ty: CScalar) -> ValType {
+    match ty.llvm {
+        "i64" => ValType::I64,
+        "double" => ValType::F64,
+        "float" => ValType::F32,
+        _ => ValType::I32,
+    }
+}
+
+/// Convert the word on the stack to a C scalar
+fn from_word(code: &mut Code, ty: CScalar) {
+    match ty.llvm {
+        "i64" => {}
+        "i1" => {
+            code.i64_const(0).op(I64_NE);
+        }
+        "double" => {
+            code.op(F64_REINTERPRET_I64);
+        }
+        "float" => {
+            code.op(F64_REINTERPRET_I64).op(F32_DEMOTE_F64);
+        }
+        _ => {
+            code.op(I32_WRAP_I64);
+        }
+    }
+}
+
+/// Widen the C scalar on the stack to a word
+fn to_word(code: &mut Code, ty: CScalar) {
+    match (ty.llvm, ty.signed) {
+        ("i64", _) => {}
+        ("i32", true) => {
+            code.op(I64_EXTEND_I32_S);
+        }
+        ("i16", true) => {
+            code.op(I32_EXTEND16_S).op(I64_EXTEND_I32_S);
+        }
+        ("i8", true) => {
+            code.op(I32_EXTEND8_S).op(I64_EXTEND_I32_S);
+        }
+        ("i16", false) => {
+            code.i32_const(0xffff).op(I32_AND).op(I64_EXTEND_I32_U);
+        }
+        ("i8", false) | ("i1", _) => {
+            code.i32_const(0xff).op(I32_AND).op(I64_EXTEND_I32_U);
+        }
+        ("double", _) => {
+            code.op(I64_REINTERPRET_F64);
+        }
+        ("float", _) => {
+            code.op(F64_PROMOTE_F32).op(I64_REINTERPRET_F64);
+        }
+        _ => {
+            code.op(I64_EXTEND_I32_U);
+        }
+    }
+}
+
+// ========== Module ==========
+
+/// Generate the binary module for a lowered program; `wasi` selects
+/// `wasm32-wasi` (WASI runtime and a `_start` export) over `wasm32-unknown-unknown`
+pub fn generate(program: &Program, wasi: bool) -> Result<Vec<u8>> {
+    let mut gen = ModuleGen {
+        module: Module::default(),
+        funcs: HashMap::new(),
+        table: HashMap::new(),
+        data: HashMap::new(),
+        runtime: Vec::new(),
+    };
+    gen.layout(program)?;
+    gen.declare(program, wasi)?;
+    for f in &program.functions {
+        let code = FunctionGen::new(&gen, f).generate()?;
+        gen.define(f.params.len(), code);
+    }
+    for (name, sig) in &program.exports {
+        gen.export_wrapper(name, sig)?;
+    }
+    for name in std::mem::take(&mut gen.runtime) {
+        gen.runtime(&name)?;
+    }
+    if program.entry && wasi {
+        gen.start()?;
+    }
+    Ok(gen.module.encode())
+}
+
+struct ModuleGen {
+    module: Module,
+    /// Function index and calling convention of every callable name
+    funcs: HashMap<String, (u32, Sig)>,
+    /// Table index of every function whose address is taken
+    table: HashMap<String, u32>,
+    /// Address of every data symbol
+    data: HashMap<String, u64>,
+    /// Runtime functions to define after the export wrappers, in index order
+    runtime: Vec<String>,
+}
+
+impl ModuleGen {
+    /// Place the data, free lists, shadow stack and heap in linear memory
+    fn layout(&mut self, program: &Program) -> Result<()> {
+        let mut bytes = Vec::new();
+        for data in &program.data {
+            // Modules are single-threaded: thread-locals are ordinary data
+            match &data.init {
+                Init::Str(s) => {
+                    self.data.insert(data.name.clone(), DATA_BASE + bytes.len() as u64);
+                    bytes.extend_from_slice(s.as_bytes());
+                    bytes.push(0);
+                }
+                Init::Words(words) => {
+                    bytes.resize(align_up(bytes.len() as u64, 8) as usize, 0);
+                    self.data.insert(data.name.clone(), DATA_BASE + bytes.len() as u64);
+                    if words.is_empty() {
+                        bytes.extend_from_slice(&[0; 8]);
+                    }
+                    for word in words {
+                        bytes.extend_from_slice(&word.to_le_bytes());
+                    }
+                }
+            }
+        }
+        bytes.resize(align_up(bytes.len() as u64, 16) as usize, 0);
+        let free_lists = DATA_BASE + bytes.len() as u64;
+        self.data.insert(".free_lists".into(), free_lists);
+        let stack_top = align_up(free_lists + 4 * SIZE_CLASSES, 16) + STACK_SIZE;
+        let pages = stack_top.div_ceil(PAGE_SIZE);
+        if pages > u16::MAX as u64 {
+            bail!("Static data does not fit in a 32-bit linear memory");
+        }
+        self.module.memory_pages = pages as u32;
+        self.module.globals.push(Global { ty: ValType::I32, mutable: true, init: stack_top as i64 });
+        self.module.globals.push(Global { ty: ValType::I32, mutable: true, init: stack_top as i64 });
+        self.module.exports.push(Export { name: "memory".into(), kind: ExportKind::Memory, index: 0 });
+        if !bytes.iter().all(|&b| b == 0) {
+            self.module.data.push((DATA_BASE as u32, bytes));
+        }
+        Ok(())
+    }
+
+    /// Assign function indices: imports, then the program's functions, export
+    /// wrappers, the runtime functions it uses and `_start`
+    fn declare(&mut self, program: &Program, wasi: bool) -> Result<()> {
+        let mut defined: Vec<(String, Sig, bool)> = Vec::new();
+        for f in &program.functions {
+            // `main` is the entry point of plain modules; WASI commands use `_start`
+            let global = f.global && (f.name != "main" || (program.entry && !wasi));
+            defined.push((f.name.clone(), Sig::Words(f.params.len()), global));
+        }
+        for (name, sig) in &program.exports {
+            let abi = CAbi { params: sig.params.clone(), ret: sig.ret.flatten() };
+            defined.push((name.clone(), Sig::C(abi), true));
+        }
+
+        // Everything called or referenced that the program does not define
+        let mut wanted: Vec<(String, Option<CAbi>, usize)> = Vec::new();
+        for f in &program.functions {
+            for inst in &f.insts {
+                let (name, abi, args) = match inst {
+                    Inst::Call { callee: Callee::Direct(name), abi, args, .. } => (name, abi.clone(), args.len()),
+                    Inst::Addr(_, name, SymKind::Code) => (name, None, 0),
+                    _ => continue,
+                };
+                if name == "pthread_create" {
+                    bail!("spawn needs threads, which WebAssembly targets do not have yet (in {})", f.name);
+                }
+                if defined.iter().any(|(n, _, _)| n == name) {
+                    continue;
+                }
+                match wanted.iter().find(|(n, _, _)| n == name) {
+                    Some((_, _, count)) if matches!(inst, Inst::Call { .. }) && *count != args && *count != 0 => {
+                        bail!("Calls to {} pass different numbers of arguments", name);
+                    }
+                    Some(_) => {}
+                    None => wanted.push((name.clone(), abi, args)),
+                }
+            }
+        }
+        let mut imports: Vec<(String, String, Sig)> = Vec::new();
+        let mut runtime = Vec::new();
+        for (name, abi, args) in wanted {
+            if RUNTIME.iter().any(|&(r, wasi_only)| r == name && (wasi || !wasi_only)) {
+                for dep in wasi_deps(&name) {
+                    let (params, results) = wasi_import(dep);
+                    imports.push((WASI_MODULE.into(), dep.to_string(), Sig::Raw(params, results)));
+                }
+                self.runtime.push(name.clone());
+                runtime.push((name, Sig::Words(args), false));
+            } else {
+                let sig = abi.map_or(Sig::Words(args), Sig::C);
+                imports.push((ENV_MODULE.into(), name, sig));
+            }
+        }
+        if program.entry && wasi && !imports.iter().any(|(_, n, _)| n == "proc_exit") {
+            let (params, results) = wasi_import("proc_exit");
+            imports.push((WASI_MODULE.into(), "proc_exit".into(), Sig::Raw(params, results)));
+        }
+
+        for (module, name, sig) in imports {
+            let ty = self.sig_type(&sig);
+            let key = if module == WASI_MODULE { format!("{}::{}", WASI_MODULE, name) } else { name.clone() };
+            self.funcs.insert(key, (self.module.imports.len() as u32, sig));
+            self.module.imports.push(Import { module, name, ty });
+        }
+        let base = self.module.imports.len() as u32;
+        for (i, (name, sig, global)) in defined.into_iter().chain(runtime).enumerate() {
+            let index = base + i as u32;
+            if global {
+                self.module.exports.push(Export { name: name.clone(), kind: ExportKind::Func, index });
+            }
+            if self.funcs.insert(name.clone(), (index, sig)).is_some() {
+                bail!("Duplicate function {}", name);
+            }
+        }
+
+        // Table slots for function pointers, from 1, and the types indirect calls check
+        for f in &program.functions {
+            for inst in &f.insts {
+                match inst {
+                    Inst::Addr(_, name, SymKind::Code) if !self.table.contains_key(name) => {
+                        let (index, _) = self.funcs[name];
+                        self.module.table.push(index);
+                        self.table.insert(name.clone(), self.module.table.len() as u32);
+                    }
+                    Inst::Call { callee: Callee::Indirect(_), args, .. } => {
+                        self.sig_type(&Sig::Words(args.len()));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn sig_type(&mut self, sig: &Sig) -> u32 {
+        let (params, results) = match sig {
+            Sig::Words(n) => (vec![ValType::I64; *n], vec![ValType::I64]),
+            Sig::C(abi) => (
+                abi.params.iter().map(|p| p.map_or(ValType::I64, scalar_type)).collect(),
+                vec![abi.ret.map_or(ValType::I64, scalar_type)],
+            ),
+            Sig::Raw(params, results) => (params.clone(), results.clone()),
+        };
+        self.module.func_type(params, results)
+    }
+
+    /// Add the next defined function, taking `params` words
+    fn define(&mut self, params: usize, (locals, code): (Vec<ValType>, Code)) {
+        let ty = self.module.func_type(vec![ValType::I64; params], vec![ValType::I64]);
+        self.module.funcs.push(Func { ty, locals, code });
+    }
+
+    fn func(&self, name: &str) -> Result<&(u32, Sig)> {
+        self.funcs.get(name).ok_or_else(|| anyhow!("Unknown function {}", name))
+    }
+
+    /// C ABI entry point of an `#[export]` function, around `name.body`
+    fn export_wrapper(&mut self, name: &str, sig: &ExportSig) -> Result<()> {
+        let mut code = Code::default();
+        for (i, ty) in sig.params.iter().enumerate() {
+            code.local_get(i as u32);
+            if let Some(ty) = ty {
+                to_word(&mut code, *ty);
+            }
+        }
+        code.call(self.func(&format!("{}.body", name))?.0);
+        match sig.ret {
+            None => {
+                code.op(DROP);
+            }
+            Some(Some(ty)) => from_word(&mut code, ty),
+            Some(None) => {}
+        }
+        let params = sig.params.iter().map(|p| p.map_or(ValType::I64, scalar_type)).collect();
+        let results = match sig.ret {
+            None => Vec::new(),
+            Some(ty) => vec![ty.map_or(ValType::I64, scalar_type)],
+        };
+        let ty = self.module.func_type(params, results);
+        self.module.funcs.push(Func { ty, locals: Vec::new(), code });
+        Ok(())
+    }
+
+    /// `_start` for WASI commands: `proc_exit(main())`
+    fn start(&mut self) -> Result<()> {
+        let (main, sig) = self.func("main")?.clone();
+        let Sig::Words(params) = sig else { bail!("main cannot be #[export]") };
+        let mut code = Code::default();
+        for _ in 0..params {
+            code.i64_const(0);
+        }
+        code.call(main).op(I32_WRAP_I64);
+        code.call(self.func(&format!("{}::proc_exit", WASI_MODULE))?.0);
+        let ty = self.module.func_type(Vec::new(), Vec::new());
+        let index = (self.module.imports.len() + self.module.funcs.len()) as u32;
+        self.module.funcs.push(Func { ty, locals: Vec::new(), code });
+        self.module.exports.push(Export { name: "_start".into(), kind: ExportKind::Func, index });
+        Ok(())
+    }
+
+    // ========== Runtime ==========
+
+    /// Body of a runtime function; all take and return words
+    fn runtime(&mut self, name: &str) -> Result<()> {
+        let free_lists = self.data[".free_lists"] as u32;
+        let wasi = |f: &str| self.func(&format!("{}::{}", WASI_MODULE, f)).map(|(index, _)| *index);
+        let mut c = Code::default();
+        let (params, locals) = match name {
+            // Power-of-two blocks with the size class in a 16-byte header; freed
+            // blocks go on a list per class, new ones come from the top of the heap
+            "malloc" => {
+                let (n, size, class, p, end) = (0, 1, 2, 3, 4);
+                c.local_get(n).i64_const(MAX_ALLOC).op(I64_GT_U).if_().i64_const(0).op(RETURN).end();
+                c.local_get(n).op(I32_WRAP_I64).i32_const(16).op(I32_ADD).local_set(end);
+                c.i32_const(16).local_set(size);
+                c.block().loop_();
+                c.local_get(size).local_get(end).op(I32_GE_U).br_if(1);
+                c.local_get(size).i32_const(1).op(I32_SHL).local_set(size);
+                c.local_get(class).i32_const(1).op(I32_ADD).local_set(class);
+                c.br(0).end().end();
+                c.local_get(class).i32_const(2).op(I32_SHL).mem(I32_LOAD, 2, free_lists).local_tee(p);
+                c.if_();
+                c.local_get(class).i32_const(2).op(I32_SHL).local_get(p).mem(I32_LOAD, 2, 8).mem(I32_STORE, 2, free_lists);
+                c.op(ELSE);
+                c.global_get(HEAP_NEXT).local_tee(p).local_get(size).op(I32_ADD).local_tee(end);
+                c.local_get(p).op(I32_LT_U).if_().i64_const(0).op(RETURN).end();
+                // Grow to cover `end`
+                c.local_get(end).i32_const(1).op(I32_SUB).i32_const(16).op(I32_SHR_U).memory_size().op(I32_GE_U);
+                c.if_();
+                c.local_get(end).i32_const(1).op(I32_SUB).i32_const(16).op(I32_SHR_U).i32_const(1).op(I32_ADD);
+                c.memory_size().op(I32_SUB).memory_grow().i32_const(-1).op(I32_EQ);
+                c.if_().i64_const(0).op(RETURN).end();
+                c.end();
+                c.local_get(end).global_set(HEAP_NEXT);
+                c.end();
+                c.local_get(p).local_get(class).mem(I32_STORE, 2, 0);
+                c.local_get(p).i32_const(16).op(I32_ADD).op(I64_EXTEND_I32_U);
+                (1, vec![ValType::I32; 4])
+            }
+            "free" => {
+                let (ptr, block, head) = (0, 1, 2);
+                c.local_get(ptr).op(I64_EQZ).if_().i64_const(0).op(RETURN).end();
+                c.local_get(ptr).op(I32_WRAP_I64).i32_const(16).op(I32_SUB).local_tee(block);
+                c.mem(I32_LOAD, 2, 0).i32_const(2).op(I32_SHL).local_set(head);
+                c.local_get(block).local_get(head).mem(I32_LOAD, 2, free_lists).mem(I32_STORE, 2, 8);
+                c.local_get(head).local_get(block).mem(I32_STORE, 2, free_lists);
+                c.i64_const(0);
+                (1, vec![ValType::I32; 2])
+            }
+            "memcpy" | "memset" => {
+                for arg in 0..3 {
+                    c.local_get(arg).op(I32_WRAP_I64);
+                }
+                if name == "memcpy" {
+                    c.memory_copy();
+                } else {
+                    c.memory_fill();
+                }
+                c.local_get(0);
+                (3, Vec::new())
+            }
+            // One iovec on the shadow stack; the count comes back after it
+            "write" | "read" => {
+                let (fd, buf, len, io, errno) = (0, 1, 2, 3, 4);
+                let call = wasi(if name == "write" { "fd_write" } else { "fd_read" })?;
+                c.global_get(STACK_POINTER).i32_const(16).op(I32_SUB).local_tee(io).global_set(STACK_POINTER);
+                c.local_get(io).local_get(buf).op(I32_WRAP_I64).mem(I32_STORE, 2, 0);
+                c.local_get(io).local_get(len).op(I32_WRAP_I64).mem(I32_STORE, 2, 4);
+                c.local_get(fd).op(I32_WRAP_I64).local_get(io).i32_const(1).local_get(io).i32_const(8).op(I32_ADD);
+                c.call(call).local_set(errno);
+                c.local_get(io).i32_const(16).op(I32_ADD).global_set(STACK_POINTER);
+                c.local_get(errno).if_().i64_const(-1).op(RETURN).end();
+                c.local_get(io).mem(I64_LOAD32_U, 2, 8);
+                (3, vec![ValType::I32; 2])
+            }
+            "lseek" => {
+                let (fd, offset, whence, io, errno) = (0, 1, 2, 3, 4);
+                c.global_get(STACK_POINTER).i32_const(16).op(I32_SUB).local_tee(io).global_set(STACK_POINTER);
+                c.local_get(fd).op(I32_WRAP_I64).local_get(offset).local_get(whence).op(I32_WRAP_I64).local_get(io);
+                c.call(wasi("fd_seek")?).local_set(errno);
+                c.local_get(io).i32_const(16).op(I32_ADD).global_set(STACK_POINTER);
+                c.local_get(errno).if_().i64_const(-1).op(RETURN).end();
+                c.local_get(io).mem(I64_LOAD, 3, 0);
+                (3, vec![ValType::I32; 2])
+            }
+            "close" => {
+                c.local_get(0).op(I32_WRAP_I64).call(wasi("fd_close")?);
+                c.if_().i64_const(-1).op(RETURN).end();
+                c.i64_const(0);
+                (1, Vec::new())
+            }
+            "exit" => {
+                c.local_get(0).op(I32_WRAP_I64).call(wasi("proc_exit")?).op(UNREACHABLE);
+                (1, Vec::new())
+            }
+            // Linux open flags become WASI oflags (O_CREAT, O_DIRECTORY, O_EXCL,
+            // O_TRUNC) and fdflags (O_APPEND); the mode is up to the host
+            "open" => {
+                let (path, flags, len, io, errno) = (0, 1, 3, 4, 5);
+                c.global_get(STACK_POINTER).i32_const(16).op(I32_SUB).local_tee(io).global_set(STACK_POINTER);
+                c.i32_const(PREOPEN_FD).i32_const(0);
+                path_arg(&mut c, path, len);
+                for (bit, oflag) in [(6, 0), (16, 1), (7, 2), (9, 3)] {
+                    c.local_get(flags).i64_const(bit).op(I64_SHR_U).i64_const(1).op(I64_AND).i64_const(oflag).op(I64_SHL);
+                    if oflag > 0 {
+                        c.op(I64_OR);
+                    }
+                }
+                c.op(I32_WRAP_I64).i64_const(ALL_RIGHTS).i64_const(ALL_RIGHTS);
+                c.local_get(flags).i64_const(10).op(I64_SHR_U).i64_const(1).op(I64_AND).op(I32_WRAP_I64);
+                c.local_get(io).call(wasi("path_open")?).local_set(errno);
+                c.local_get(io).i32_const(16).op(I32_ADD).global_set(STACK_POINTER);
+                c.local_get(errno).if_().i64_const(-1).op(RETURN).end();
+                c.local_get(io).mem(I64_LOAD32_U, 2, 0);
+                (3, vec![ValType::I32; 3])
+            }
+            "unlink" | "mkdir" | "rmdir" => {
+                let call = match name {
+                    "unlink" => "path_unlink_file",
+                    "mkdir" => "path_create_directory",
+                    _ => "path_remove_directory",
+                };
+                let params: usize = if name == "mkdir" { 2 } else { 1 };
+                let len = params as u32;
+                c.i32_const(PREOPEN_FD);
+                path_arg(&mut c, 0, len);
+                c.call(wasi(call)?).if_().i64_const(-1).op(RETURN).end();
+                c.i64_const(0);
+                (params, vec![ValType::I32])
+            }
+            "rename" => {
+                c.i32_const(PREOPEN_FD);
+                path_arg(&mut c, 0, 2);
+                c.i32_const(PREOPEN_FD);
+                path_arg(&mut c, 1, 3);
+                c.call(wasi("path_rename")?).if_().i64_const(-1).op(RETURN).end();
+                c.i64_const(0);
+                (2, vec![ValType::I32; 2])
+            }
+            _ => bail!("No runtime function {}", name),
+        };
+        let (index, sig) = self.func(name)?;
+        if let Sig::Words(args) = sig {
+            if *args != params {
+                bail!("{} takes {} arguments, called with {}", name, params, args);
+            }
+        }
+        debug_assert_eq!(*index as usize, self.module.imports.len() + self.module.funcs.len());
+        self.define(params, (locals, c));
+        Ok(())
+    }
+}
+
+/// Push the address and length of the C string in word local `path`, using
+/// i32 local `len` as the cursor
+fn path_arg(c: &mut Code, path: u32, len: u32) {
+    c.local_get(path).op(I32_WRAP_I64).local_set(len);
+    c.block().loop_();
+    c.local_get(len).mem(I32_LOAD8_U, 0, 0).op(I32_EQZ).br_if(1);
+    c.local_get(len).i32_const(1).op(I32_ADD).local_set(len);
+    c.br(0).end().end();
+    c.local_get(path).op(I32_WRAP_I64);
+    c.local_get(len).local_get(path).op(I32_WRAP_I64).op(I32_SUB);
+}
+
+// ========== Functions ==========
+
+struct FunctionGen<'a> {
+    module: &'a ModuleGen,
+    f: &'a Function,
+    /// Local index of every virtual register
+    locals: Vec<u32>,
+    /// Block index of every label
+    blocks: HashMap<u32, u32>,
+    /// Dispatch block counter and frame base
+    pc: u32,
+    fp: u32,
+    /// Bytes of frame slots on the shadow stack
+    frame: u32,
+    code: Code,
+}
+
+impl<'a> FunctionGen<'a> {
+    fn new(module: &'a ModuleGen, f: &'a Function) -> Self {
+        let mut locals = vec![0; f.vregs as usize];
+        for (i, &p) in f.params.iter().enumerate() {
+            locals[p as usize] = i as u32;
+        }
+        let mut next = f.params.len() as u32;
+        for v in 0..f.vregs {
+            if !f.params.contains(&v) {
+                locals[v as usize] = next;
+                next += 1;
+            }
+        }
+        let mut blocks = HashMap::new();
+        for inst in &f.insts {
+            if let Inst::Label(l) = inst {
+                blocks.insert(*l, blocks.len() as u32 + 1);
+            }
+        }
+        let frame = align_up(8 * f.slots as u64, 16) as u32;
+        FunctionGen { module, f, locals, blocks, pc: next, fp: next + 1, frame, code: Code::default() }
+    }
+
+    /// Locals and body. Control flow runs through a loop around one block per
+    /// label: a jump stores the target in `pc` and branches back to a `br_table`.
+    fn generate(mut self) -> Result<(Vec<ValType>, Code)> {
+        let f = self.f;
+        if self.frame > 0 {
+            self.code.global_get(STACK_POINTER).i32_const(self.frame as i32).op(I32_SUB).local_tee(self.fp).global_set(STACK_POINTER);
+        }
+        let count = self.blocks.len() as u32 + 1;
+        if count > 1 {
+            self.code.loop_();
+            for _ in 0..count {
+                self.code.block();
+            }
+            let depths: Vec<u32> = (0..count).collect();
+            self.code.local_get(self.pc).br_table(&depths, 0).end();
+        }
+        let mut block = 0;
+        for (i, inst) in f.insts.iter().enumerate() {
+            if let Inst::Label(l) = inst {
+                block = self.blocks[l];
+                self.code.end();
+                continue;
+            }
+            // Branches back to the loop cross the blocks of the labels still to come
+            let depth = count - 1 - block;
+            let next = match f.insts.get(i + 1) {
+                Some(Inst::Label(l)) => Some(*l),
+                _ => None,
+            };
+            self.inst(inst, depth, next)?;
+        }
+        if count > 1 {
+            self.code.end();
+        }
+        self.code.op(UNREACHABLE);
+        let mut locals = vec![ValType::I64; f.vregs as usize - f.params.len()];
+        locals.extend([ValType::I32, ValType::I32]);
+        Ok((locals, self.code))
+    }
+
+    fn get(&mut self, v: u32) {
+        self.code.local_get(self.locals[v as usize]);
+    }
+
+    fn set(&mut self, v: u32) {
+        self.code.local_set(self.locals[v as usize]);
+    }
+
+    /// 32-bit address of the word in `base` plus `offset`, and the memarg offset left over
+    fn address(&mut self, base: u32, offset: i64) -> u32 {
+        self.get(base);
+        self.code.op(I32_WRAP_I64);
+        match u32::try_from(offset) {
+            Ok(offset) => offset,
+            Err(_) => {
+                self.code.i32_const(offset as i32).op(I32_ADD);
+                0
+            }
+        }
+    }
+
+    fn jump(&mut self, label: u32, depth: u32) {
+        self.code.i32_const(self.blocks[&label] as i32).local_set(self.pc).br(depth);
+    }
+
+    fn inst(&mut self, inst: &Inst, depth: u32, next: Option<u32>) -> Result<()> {
+        match inst {
+            Inst::Imm(d, value) => {
+                self.code.i64_const(*value);
+                self.set(*d);
+            }
+            Inst::Copy(d, s) => {
+                self.get(*s);
+                self.set(*d);
+            }
+            Inst::Bin(op, d, a, b) => {
+                self.get(*a);
+                match b {
+                    Operand::Reg(b) => self.get(*b),
+                    Operand::Imm(k) => {
+                        self.code.i64_const(*k);
+                    }
+                }
+                let (opcode, compare) = match op {
+                    BinOp::Add => (I64_ADD, false),
+                    BinOp::Sub => (I64_SUB, false),
+                    BinOp::Mul => (I64_MUL, false),
+                    BinOp::Div => (I64_DIV_S, false),
+                    BinOp::Mod => (I64_REM_S, false),
+                    BinOp::And | BinOp::BitAnd => (I64_AND, false),
+                    BinOp::Or | BinOp::BitOr => (I64_OR, false),
+                    BinOp::BitXor => (I64_XOR, false),
+                    BinOp::Shl => (I64_SHL, false),
+                    BinOp::Shr => (I64_SHR_U, false),
+                    BinOp::Eq => (I64_EQ, true),
+                    BinOp::Ne => (I64_NE, true),
+                    BinOp::Lt => (I64_LT_S, true),
+                    BinOp::Le => (I64_LE_S, true),
+                    BinOp::Gt => (I64_GT_S, true),
+                    BinOp::Ge => (I64_GE_S, true),
+                };
+                self.code.op(opcode);
+                if compare {
+                    self.code.op(I64_EXTEND_I32_U);
+                }
+                self.set(*d);
+            }
+            // Floats live in i64 locals as the bits of a double
+            Inst::FBin(op, d, a, b) => {
+                self.get(*a);
+                self.code.op(F64_REINTERPRET_I64);
+                self.get(*b);
+                self.code.op(F64_REINTERPRET_I64);
+                let (opcode, compare) = match op {
+                    BinOp::Add => (F64_ADD, false),
+                    BinOp::Sub => (F64_SUB, false),
+                    BinOp::Mul => (F64_MUL, false),
+                    BinOp::Div => (F64_DIV, false),
+                    BinOp::Eq => (F64_EQ, true),
+                    BinOp::Ne => (F64_NE, true),
+                    BinOp::Lt => (F64_LT, true),
+                    BinOp::Le => (F64_LE, true),
+                    BinOp::Gt => (F64_GT, true),
+                    BinOp::Ge => (F64_GE, true),
+                    _ => bail!("No double instruction for {:?}", op),
+                };
+                self.code.op(opcode);
+                self.code.op(if compare { I64_EXTEND_I32_U } else { I64_REINTERPRET_F64 });
+                self.set(*d);
+            }
+            Inst::IntToFloat(d, a) => {
+                self.get(*a);
+                self.code.op(F64_CONVERT_I64_S).op(I64_REINTERPRET_F64);
+                self.set(*d);
+            }
+            Inst::FloatToInt(d, a) => {
+                self.get(*a);
+                self.code.op(F64_REINTERPRET_I64).op(I64_TRUNC_F64_S);
+                self.set(*d);
+            }
+            Inst::Neg(d, a) => {
+                self.code.i64_const(0);
+                self.get(*a);
+                self.code.op(I64_SUB);
+                self.set(*d);
+            }
+            Inst::BitNot(d, a) => {
+                self.get(*a);
+                self.code.i64_const(-1).op(I64_XOR);
+                self.set(*d);
+            }
+            // Modules are single-threaded, so ordered accesses need no fences
+            Inst::Load { width, dst, base, offset, .. } => {
+                let offset = self.address(*base, *offset);
+                let (op, align) = match width {
+                    Width::W8 => (I64_LOAD8_U, 0),
+                    Width::W16 => (I64_LOAD16_U, 1),
+                    Width::W32 => (I64_LOAD32_U, 2),
+                    Width::W64 => (I64_LOAD, 3),
+                };
+                self.code.mem(op, align, offset);
+                self.set(*dst);
+            }
+            Inst::Store { width, src, base, offset, .. } => {
+                let offset = self.address(*base, *offset);
+                self.get(*src);
+                let (op, align) = match width {
+                    Width::W8 => (I64_STORE8, 0),
+                    Width::W16 => (I64_STORE16, 1),
+                    Width::W32 => (I64_STORE32, 2),
+                    Width::W64 => (I64_STORE, 3),
+                };
+                self.code.mem(op, align, offset);
+            }
+            Inst::AtomicRmw { op, src, base } => {
+                self.address(*base, 0);
+                self.address(*base, 0);
+                self.code.mem(I64_LOAD, 3, 0);
+                self.get(*src);
+                self.code.op(match op {
+                    BinOp::Add => I64_ADD,
+                    BinOp::Sub => I64_SUB,
+                    BinOp::BitAnd => I64_AND,
+                    BinOp::BitOr => I64_OR,
+                    _ => I64_XOR,
+                });
+                self.code.mem(I64_STORE, 3, 0);
+            }
+            Inst::LoadSlot(d, slot) => {
+                self.code.local_get(self.fp).mem(I64_LOAD, 3, 8 * slot);
+                self.set(*d);
+            }
+            Inst::StoreSlot(v, slot) => {
+                self.code.local_get(self.fp);
+                self.get(*v);
+                self.code.mem(I64_STORE, 3, 8 * slot);
+            }
+            Inst::SlotAddr(d, slot) => {
+                self.code.local_get(self.fp).i32_const(8 * *slot as i32).op(I32_ADD).op(I64_EXTEND_I32_U);
+                self.set(*d);
+            }
+            Inst::Addr(d, name, kind) => {
+                let value = match kind {
+                    SymKind::Code => self.module.table[name] as u64,
+                    // Modules are single-threaded: thread-locals are ordinary data
+                    SymKind::Data | SymKind::ThreadLocal => *self.module.data.get(name)
+                        .ok_or_else(|| anyhow!("Unknown symbol {} in {}", name, self.f.name))?,
+                };
+                self.code.i64_const(value as i64);
+                self.set(*d);
+            }
+            Inst::Call { dst, callee, args, .. } => self.call(*dst, callee, args)?,
+            Inst::Label(_) => unreachable!("labels end blocks"),
+            Inst::Jump(l) => {
+                // The next block is where control falls through to anyway
+                if next != Some(*l) {
+                    self.jump(*l, depth);
+                }
+            }
+            Inst::Branch(v, zero, l) => {
+                self.get(*v);
+                self.code.op(I64_EQZ);
+                if !zero {
+                    self.code.op(I32_EQZ);
+                }
+                self.code.if_();
+                self.jump(*l, depth + 1);
+                self.code.end();
+            }
+            Inst::Ret(v) => {
+                self.get(*v);
+                if self.frame > 0 {
+                    self.code.local_get(self.fp).i32_const(self.frame as i32).op(I32_ADD).global_set(STACK_POINTER);
+                }
+                self.code.op(RETURN);
+            }
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, dst: Option<u32>, callee: &Callee, args: &[u32]) -> Result<()> {
+        let returns = match callee {
+            Callee::Direct(name) => {
+                let (index, sig) = self.module.func(name)?.clone();
+                match &sig {
+                    Sig::Words(n) if *n != args.len() => {
+                        bail!("{} takes {} arguments, called with {} in {}", name, n, args.len(), self.f.name);
+                    }
+                    Sig::C(abi) if abi.params.len() != args.len() => {
+                        bail!("{} takes {} arguments, called with {} in {}", name, abi.params.len(), args.len(), self.f.name);
+                    }
+                    _ => {}
+                }
+                for (k, &arg) in args.iter().enumerate() {
+                    self.get(arg);
+                    if let Sig::C(abi) = &sig {
+                        if let Some(ty) = abi.params[k] {
+                            from_word(&mut self.code, ty);
+                        }
+                    }
+                }
+                self.code.call(index);
+                if let Sig::C(CAbi { ret: Some(ty), .. }) = sig {
+                    to_word(&mut self.code, ty);
+                }
+                !matches!(sig, Sig::Raw(_, ref results) if results.is_empty())
+            }
+            Callee::Indirect(f) => {
+                for &arg in args {
+                    self.get(arg);
+                }
+                self.get(*f);
+                self.code.op(I32_WRAP_I64);
+                let ty = self.module.module.types.iter()
+                    .position(|t| t.params.len() == args.len() && t.params.iter().all(|&p| p == ValType::I64) && t.results == [ValType::I64])
+                    .ok_or_else(|| anyhow!("No function takes {} arguments for an indirect call in {}", args.len(), self.f.name))?;
+                self.code.call_indirect(ty as u32);
+                true
+            }
+        };
+        match (dst, returns) {
+            (Some(d), true) => self.set(d),
+            (Some(d), false) => {
+                self.code.i64_const(0);
+                self.set(d);
+            }
+            (None, true) => {
+                self.code.op(DROP);
+            }
+            (None, false) => {}
+        }
+        Ok(())
+    }
+}
@@ -1,29 +1,913 @@
-//! x86-64 code generation helpers
+//! x86-64 code generation: assembly for the GNU assembler (Intel syntax), with
+//! the System V calling convention, or the Microsoft x64 one for Windows
+//!
+//! Selects instructions from the mid-level IR; virtual registers are assigned by
+//! the linear scan shared with the AArch64 backend and spilled to the frame.
 
-pub fn emit_prologue(asm: &mut String) {
-    asm.push_str("    push rbp\n");
-    asm.push_str("    mov rbp, rsp\n");
-    asm.push_str("    sub rsp, 256\n");
+use std::collections::HashSet;
+use anyhow::{anyhow, bail, Result};
+use crate::ast::BinOp;
+use super::header::CScalar;
+use super::mir::*;
+
+/// Registers by number with their 32, 16 and 8-bit names
+const NAMES: [[&str; 4]; 16] = [
+    ["rax", "eax", "ax", "al"],
+    ["rcx", "ecx", "cx", "cl"],
+    ["rdx", "edx", "dx", "dl"],
+    ["rbx", "ebx", "bx", "bl"],
+    ["rsp", "esp", "sp", "spl"],
+    ["rbp", "ebp", "bp", "bpl"],
+    ["rsi", "esi", "si", "sil"],
+    ["rdi", "edi", "di", "dil"],
+    ["r8", "r8d", "r8w", "r8b"],
+    ["r9", "r9d", "r9w", "r9b"],
+    ["r10", "r10d", "r10w", "r10b"],
+    ["r11", "r11d", "r11w", "r11b"],
+    ["r12", "r12d", "r12w", "r12b"],
+    ["r13", "r13d", "r13w", "r13b"],
+    ["r14", "r14d", "r14w", "r14b"],
+    ["r15", "r15d", "r15w", "r15b"],
+];
+
+const RAX: u8 = 0;
+const RCX: u8 = 1;
+const RDX: u8 = 2;
+
+/// System V integer argument registers
+const ARG_REGS: [u8; 6] = [7, 6, 2, 1, 8, 9];
+/// Microsoft x64 argument registers; floats use `xmm` of the same position
+const WIN64_ARG_REGS: [u8; 4] = [1, 2, 8, 9];
+
+/// Allocatable registers a call may clobber; argument registers are never allocated,
+/// so arguments and parameters move without conflicts
+const CALLER_SAVED: [u8; 2] = [10, 11];
+/// Allocatable registers preserved across calls; the prologue saves the ones in use
+const CALLEE_SAVED: [u8; 5] = [3, 12, 13, 14, 15];
+/// Microsoft x64 also preserves `rsi` and `rdi`
+const WIN64_CALLEE_SAVED: [u8; 7] = [3, 6, 7, 12, 13, 14, 15];
+/// Never allocated: spilled operands, division, shift counts and large immediates use these
+const SCRATCH: [u8; 3] = [RAX, RCX, RDX];
+
+/// Register home area a Microsoft x64 caller reserves below the stack arguments
+const SHADOW_SPACE: i64 = 32;
+
+/// Calling convention of the generated code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Abi {
+    SysV,
+    /// Microsoft x64: four register arguments and 32 bytes of shadow space
+    Win64,
+}
+
+impl Abi {
+    fn arg_regs(self) -> &'static [u8] {
+        match self {
+            Abi::SysV => &ARG_REGS,
+            Abi::Win64 => &WIN64_ARG_REGS,
+        }
+    }
+
+    fn callee_saved(self) -> &'static [u8] {
+        match self {
+            Abi::SysV => &CALLEE_SAVED,
+            Abi::Win64 => &WIN64_CALLEE_SAVED,
+        }
+    }
+
+    /// Bytes the caller reserves below the stack arguments
+    fn shadow_space(self) -> i64 {
+        if self == Abi::Win64 { SHADOW_SPACE } else { 0 }
+    }
+}
+
+/// Whether the Intel-syntax parser would read a symbol as a register or an operator
+fn reserved(name: &str) -> bool {
+    const WORDS: &[&str] = &[
+        "and", "or", "xor", "not", "shl", "shr", "mod", "offset", "ptr", "byte", "word", "dword", "qword",
+        "tbyte", "oword", "xmmword", "ymmword", "zmmword", "flat", "short", "near", "far", "rip", "eip",
+        "eq", "ne", "lt", "le", "gt", "ge", "st", "cs", "ds", "es", "fs", "gs", "ss",
+        "al", "bl", "cl", "dl", "ah", "bh", "ch", "dh", "sil", "dil", "bpl", "spl",
+    ];
+    let name = name.to_ascii_lowercase();
+    let base = name.strip_prefix(['r', 'e']).unwrap_or(&name);
+    let numbered = |prefix: &str| name.strip_prefix(prefix)
+        .is_some_and(|n| !n.is_empty() && n.trim_end_matches(['d', 'w', 'b']).parse::<u8>().is_ok());
+    WORDS.contains(&name.as_str())
+        || ["ax", "bx", "cx", "dx", "si", "di", "bp", "sp"].contains(&base)
+        || ["r", "xmm", "ymm", "zmm", "mm", "st", "cr", "dr", "k"].iter().any(|p| numbered(p))
+}
+
+/// Symbol as an instruction operand; reserved names go through an alias label
+fn operand(name: &str) -> String {
+    if reserved(name) { format!("{}.sym", name) } else { name.to_string() }
+}
+
+/// Symbol in directives and label definitions
+fn quoted(name: &str) -> String {
+    if reserved(name) { format!("\"{}\"", name) } else { name.to_string() }
+}
+
+fn r64(reg: u8) -> &'static str {
+    NAMES[reg as usize][0]
+}
+
+fn r32(reg: u8) -> &'static str {
+    NAMES[reg as usize][1]
+}
+
+/// Register name for an access of `width`
+fn sized(reg: u8, width: Width) -> &'static str {
+    let names = &NAMES[reg as usize];
+    match width {
+        Width::W8 => names[3],
+        Width::W16 => names[2],
+        Width::W32 => names[1],
+        Width::W64 => names[0],
+    }
+}
+
+fn ptr(width: Width) -> &'static str {
+    match width {
+        Width::W8 => "byte ptr",
+        Width::W16 => "word ptr",
+        Width::W32 => "dword ptr",
+        Width::W64 => "qword ptr",
+    }
+}
+
+fn fits_i32(value: i64) -> bool {
+    i32::try_from(value).is_ok()
+}
+
+/// `[base + offset]`
+fn mem(base: &str, offset: i64) -> String {
+    match offset {
+        0 => format!("[{}]", base),
+        k if k < 0 => format!("[{} - {}]", base, -k),
+        k => format!("[{} + {}]", base, k),
+    }
+}
+
+/// Condition code of a comparison and of its inverse
+fn condition(op: BinOp) -> Option<(&'static str, &'static str)> {
+    Some(match op {
+        BinOp::Eq => ("e", "ne"),
+        BinOp::Ne => ("ne", "e"),
+        BinOp::Lt => ("l", "ge"),
+        BinOp::Le => ("le", "g"),
+        BinOp::Gt => ("g", "le"),
+        BinOp::Ge => ("ge", "l"),
+        _ => return None,
+    })
+}
+
+/// Label of a symbol, with its alias for reserved names
+fn define_symbol(out: &mut String, name: &str, global: bool, kind: &str) {
+    if global {
+        out.push_str(&format!("    .globl {}\n", quoted(name)));
+    }
+    out.push_str(&format!("    .type {}, @{}\n", quoted(name), kind));
+    out.push_str(&format!("{}:\n", quoted(name)));
+    if reserved(name) {
+        out.push_str(&format!("{}:\n", operand(name)));
+    }
+}
+
+// ========== Module ==========
+
+/// Generate the assembly for a lowered module
+pub fn generate(program: &Program, abi: Abi) -> Result<String> {
+    // Everything else is called through the PLT
+    let local: HashSet<&str> = program.functions.iter().map(|f| f.name.as_str())
+        .chain(program.exports.iter().map(|(n, _)| n.as_str()))
+        .collect();
+    let mut asm = String::from("# x86-64 assembly generated by Aether Compiler\n");
+    asm.push_str("    .intel_syntax noprefix\n");
+    asm.push_str("    .text\n");
+    for (i, f) in program.functions.iter().enumerate() {
+        asm.push_str(&FunctionGen::new(f, i, abi, &local).generate()?);
+    }
+    for (name, sig) in &program.exports {
+        asm.push_str(&export_wrapper(name, sig, abi)?);
+    }
+    // The Windows runtime brings its own entry point
+    if program.entry && abi == Abi::SysV {
+        asm.push('\n');
+        define_symbol(&mut asm, "_start", true, "function");
+        asm.push_str("    xor ebp, ebp\n");
+        asm.push_str("    mov rdi, qword ptr [rsp]\n");
+        asm.push_str("    lea rsi, [rsp + 8]\n");
+        asm.push_str("    and rsp, -16\n");
+        asm.push_str(&format!("    call {}\n", operand("main")));
+        asm.push_str("    mov rdi, rax\n");
+        asm.push_str("    call exit@PLT\n");
+    }
+    for (kind, section, object) in [
+        (DataKind::ReadOnly, ".section .rodata", "object"),
+        (DataKind::Mutable, ".data", "object"),
+        (DataKind::ThreadLocal, ".section .tdata,\"awT\",@progbits", "tls_object"),
+    ] {
+        let items: Vec<&Data> = program.data.iter().filter(|d| d.kind == kind).collect();
+        if items.is_empty() {
+            continue;
+        }
+        asm.push_str(&format!("\n    {}\n", section));
+        for data in items {
+            match &data.init {
+                Init::Str(s) => asm.push_str(&format!("{}:\n    .asciz \"{}\"\n", data.name, escape(s))),
+                Init::Words(words) => {
+                    asm.push_str("    .balign 8\n");
+                    define_symbol(&mut asm, &data.name, data.global, object);
+                    if words.is_empty() {
+                        asm.push_str("    .zero 8\n");
+                    } else {
+                        let words: Vec<String> = words.iter().map(|w| w.to_string()).collect();
+                        asm.push_str(&format!("    .quad {}\n", words.join(", ")));
+                    }
+                }
+            }
+        }
+    }
+    asm.push_str("\n    .section .note.GNU-stack,\"\",@progbits\n");
+    Ok(asm)
 }
 
-pub fn emit_epilogue(asm: &mut String, _stack_size: i32) {
-    asm.push_str("    add rsp, 256\n");
-    asm.push_str("    pop rbp\n");
-    asm.push_str("    ret\n");
+/// C ABI entry point of an `#[export]` function: convert the arguments to
+/// words, call the body and convert the result back
+fn export_wrapper(name: &str, sig: &ExportSig, abi: Abi) -> Result<String> {
+    let regs = abi.arg_regs();
+    if sig.params.len() > regs.len() {
+        bail!("Exported function {} has more than {} parameters", name, regs.len());
+    }
+    let mut out = String::from("\n");
+    define_symbol(&mut out, name, true, "function");
+    out.push_str("    push rbp\n");
+    out.push_str("    mov rbp, rsp\n");
+    // Where each parameter arrives: integer or SSE register index. System V
+    // counts each class separately; Microsoft x64 uses the position for both
+    let mut ints = 0;
+    let mut floats = 0;
+    let mut sources = Vec::new();
+    for (i, ty) in sig.params.iter().enumerate() {
+        if abi == Abi::Win64 {
+            sources.push(i);
+        } else if is_float(*ty) {
+            sources.push(floats);
+            floats += 1;
+        } else {
+            sources.push(ints);
+            ints += 1;
+        }
+    }
+    // Backwards: parameter i only ever arrives in a register numbered i or lower
+    for i in (0..sig.params.len()).rev() {
+        let (dst, src) = (regs[i] as usize, sources[i]);
+        let from = NAMES[regs[src] as usize];
+        let line = match sig.params[i].map(|t| (t.llvm, t.signed)) {
+            None | Some(("i64", _)) if src == i => continue,
+            None | Some(("i64", _)) => format!("mov {}, {}", NAMES[dst][0], from[0]),
+            Some(("i32", true)) => format!("movsxd {}, {}", NAMES[dst][0], from[1]),
+            Some(("i32", false)) => format!("mov {}, {}", NAMES[dst][1], from[1]),
+            Some(("i16", true)) => format!("movsx {}, {}", NAMES[dst][0], from[2]),
+            Some(("i16", false)) => format!("movzx {}, {}", NAMES[dst][1], from[2]),
+            Some(("i8", true)) => format!("movsx {}, {}", NAMES[dst][0], from[3]),
+            Some(("i8", false)) | Some(("i1", _)) => format!("movzx {}, {}", NAMES[dst][1], from[3]),
+            Some(("float", _)) => format!("cvtss2sd xmm{}, xmm{}\n    movq {}, xmm{}", src, src, NAMES[dst][0], src),
+            Some(_) => format!("movq {}, xmm{}", NAMES[dst][0], src),
+        };
+        out.push_str(&format!("    {}\n", line));
+    }
+    if abi == Abi::Win64 {
+        out.push_str(&format!("    sub rsp, {}\n", SHADOW_SPACE));
+    }
+    out.push_str(&format!("    call {}\n", operand(&format!("{}.body", name))));
+    match sig.ret.flatten().map(|t| t.llvm) {
+        Some("i1") => {
+            out.push_str("    test rax, rax\n");
+            out.push_str("    setne al\n");
+            out.push_str("    movzx eax, al\n");
+        }
+        Some("double") => out.push_str("    movq xmm0, rax\n"),
+        Some("float") => {
+            out.push_str("    movq xmm0, rax\n");
+            out.push_str("    cvtsd2ss xmm0, xmm0\n");
+        }
+        _ => {}
+    }
+    out.push_str("    leave\n");
+    out.push_str("    ret\n");
+    out.push_str(&format!("    .size {}, .-{}\n", quoted(name), operand(name)));
+    Ok(out)
+}
+
+/// Where a call passes an argument
+#[derive(Debug, Clone, Copy)]
+enum ArgLoc {
+    Reg(u8),
+    /// `xmm` register, for C floats
+    Sse(usize),
+    /// Byte offset from `rsp` at the call
+    Stack(i64),
 }
 
-pub fn emit_mov_imm(asm: &mut String, reg: &str, val: i64) {
-    if val == 0 {
-        asm.push_str(&format!("    xor {}, {}\n", reg, reg));
-    } else {
-        asm.push_str(&format!("    mov {}, {}\n", reg, val));
+/// Argument placement: System V numbers integer and SSE registers separately,
+/// Microsoft x64 by position; the rest go on the stack above the shadow space.
+/// `None` when a C `float` would have to go on the stack
+fn arg_locs(abi: Abi, n: usize, class: impl Fn(usize) -> Option<CScalar>) -> Option<Vec<ArgLoc>> {
+    let regs = abi.arg_regs();
+    let sse = if abi == Abi::Win64 { 4 } else { 8 };
+    let mut ints = 0;
+    let mut floats = 0;
+    let mut stack = 0;
+    let mut locs = Vec::new();
+    for k in 0..n {
+        let float = is_float(class(k));
+        let index = match (abi, float) {
+            (Abi::Win64, _) => k,
+            (_, true) => floats,
+            _ => ints,
+        };
+        if float && index < sse {
+            locs.push(ArgLoc::Sse(index));
+            floats += 1;
+        } else if !float && index < regs.len() {
+            locs.push(ArgLoc::Reg(regs[index]));
+            ints += 1;
+        } else if class(k).is_some_and(|t| t.llvm == "float") {
+            return None;
+        } else {
+            locs.push(ArgLoc::Stack(abi.shadow_space() + 8 * stack));
+            stack += 1;
+        }
     }
+    Some(locs)
+}
+
+// ========== Functions ==========
+
+/// Frame below the saved `rbp`: saved callee-saved registers, slots (the IR's,
+/// then spills), and at the bottom the outgoing shadow space and stack arguments
+struct Frame {
+    saved: Vec<u8>,
+    /// Bytes subtracted from `rsp` after the registers are pushed
+    size: i64,
 }
 
-pub fn emit_load_local(asm: &mut String, reg: &str, offset: i32) {
-    asm.push_str(&format!("    mov {}, [rbp - {}]\n", reg, offset));
+struct FunctionGen<'a> {
+    f: &'a Function,
+    abi: Abi,
+    /// Functions defined in this module
+    local: &'a HashSet<&'a str>,
+    locs: Vec<Option<Loc>>,
+    /// Number of uses of each virtual register
+    uses: Vec<u32>,
+    frame: Frame,
+    /// Value of the current instruction computed straight into an argument register
+    placed: Option<(VReg, u8)>,
+    ret_label: String,
+    out: String,
 }
 
-pub fn emit_store_local(asm: &mut String, reg: &str, offset: i32) {
-    asm.push_str(&format!("    mov [rbp - {}], {}\n", offset, reg));
+impl<'a> FunctionGen<'a> {
+    fn new(f: &'a Function, index: usize, abi: Abi, local: &'a HashSet<&'a str>) -> Self {
+        let (locs, spills) = allocate(f, &CALLER_SAVED, abi.callee_saved());
+        let mut uses = vec![0; f.vregs as usize];
+        let mut outgoing = None;
+        for inst in &f.insts {
+            for v in inst.uses() {
+                uses[v as usize] += 1;
+            }
+            if let Inst::Call { args, abi: c, .. } = inst {
+                let class = |k: usize| c.as_ref().and_then(|a| a.params.get(k).copied().flatten());
+                let stack = arg_locs(abi, args.len(), class).unwrap_or_default().iter()
+                    .filter(|l| matches!(l, ArgLoc::Stack(_)))
+                    .count() as i64;
+                outgoing = Some(outgoing.unwrap_or(0).max(abi.shadow_space() + 8 * stack));
+            }
+        }
+        let saved: Vec<u8> = abi.callee_saved().iter().copied()
+            .filter(|r| locs.contains(&Some(Loc::Reg(*r))))
+            .collect();
+        // `rsp` is 16-byte aligned once `rbp` is pushed
+        let below = 8 * (saved.len() as i64 + (f.slots + spills) as i64) + outgoing.unwrap_or(0);
+        let size = ((below + 15) & !15) - 8 * saved.len() as i64;
+        FunctionGen {
+            f,
+            abi,
+            local,
+            locs,
+            uses,
+            frame: Frame { saved, size },
+            placed: None,
+            ret_label: format!(".Lret{}", index),
+            out: String::new(),
+        }
+    }
+
+    fn emit(&mut self, line: &str) {
+        self.out.push_str("    ");
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    /// Memory operand of a frame slot
+    fn slot(&self, slot: u32) -> String {
+        format!("qword ptr [rbp - {}]", 8 * (self.frame.saved.len() as i64 + slot as i64 + 1))
+    }
+
+    fn loc(&self, v: VReg) -> Option<Loc> {
+        self.locs.get(v as usize).copied().flatten()
+    }
+
+    /// Register holding `v` for reading; spilled values are loaded into a scratch register
+    fn read(&mut self, v: VReg, scratch: usize) -> u8 {
+        match self.loc(v) {
+            Some(Loc::Reg(r)) => r,
+            Some(Loc::Spill(s)) => {
+                let r = SCRATCH[scratch];
+                let slot = self.slot(s);
+                self.emit(&format!("mov {}, {}", r64(r), slot));
+                r
+            }
+            None => {
+                let r = SCRATCH[scratch];
+                self.emit(&format!("xor {}, {}", r32(r), r32(r)));
+                r
+            }
+        }
+    }
+
+    /// Copy `v` into a specific register
+    fn read_into(&mut self, v: VReg, reg: u8) {
+        if self.placed == Some((v, reg)) {
+            return;
+        }
+        match self.loc(v) {
+            Some(Loc::Reg(r)) if r == reg => {}
+            Some(Loc::Reg(r)) => self.emit(&format!("mov {}, {}", r64(reg), r64(r))),
+            Some(Loc::Spill(s)) => {
+                let slot = self.slot(s);
+                self.emit(&format!("mov {}, {}", r64(reg), slot));
+            }
+            None => self.emit(&format!("xor {}, {}", r32(reg), r32(reg))),
+        }
+    }
+
+    /// Register to compute `v` into; finish with `write_back`
+    fn target(&self, v: VReg) -> u8 {
+        if let Some((_, reg)) = self.placed.filter(|&(p, _)| p == v) {
+            return reg;
+        }
+        match self.loc(v) {
+            Some(Loc::Reg(r)) => r,
+            _ => SCRATCH[0],
+        }
+    }
+
+    fn write_back(&mut self, v: VReg) {
+        if self.placed.is_some_and(|(p, _)| p == v) {
+            return;
+        }
+        if let Some(Loc::Spill(s)) = self.loc(v) {
+            let slot = self.slot(s);
+            self.emit(&format!("mov {}, {}", slot, r64(SCRATCH[0])));
+        }
+    }
+
+    /// Store the value in `reg` as `v`
+    fn write_from(&mut self, v: VReg, reg: u8) {
+        if let Some((_, r)) = self.placed.filter(|&(p, _)| p == v) {
+            if r != reg {
+                self.emit(&format!("mov {}, {}", r64(r), r64(reg)));
+            }
+            return;
+        }
+        match self.loc(v) {
+            Some(Loc::Reg(r)) if r != reg => self.emit(&format!("mov {}, {}", r64(r), r64(reg))),
+            Some(Loc::Spill(s)) => {
+                let slot = self.slot(s);
+                self.emit(&format!("mov {}, {}", slot, r64(reg)));
+            }
+            _ => {}
+        }
+    }
+
+    fn mov_imm(&mut self, reg: u8, value: i64) {
+        if value == 0 {
+            self.emit(&format!("xor {}, {}", r32(reg), r32(reg)));
+        } else {
+            self.emit(&format!("mov {}, {}", r64(reg), value));
+        }
+    }
+
+    /// Symbol to call: functions outside the module go through the PLT
+    fn callee(&self, name: &str) -> String {
+        if self.local.contains(name) { operand(name) } else { format!("{}@PLT", name) }
+    }
+
+    fn generate(mut self) -> Result<String> {
+        let f = self.f;
+        self.emit("push rbp");
+        self.emit("mov rbp, rsp");
+        for reg in self.frame.saved.clone() {
+            self.emit(&format!("push {}", r64(reg)));
+        }
+        if self.frame.size > 0 {
+            let size = self.frame.size;
+            self.emit(&format!("sub rsp, {}", size));
+        }
+        let regs = self.abi.arg_regs();
+        for (i, &p) in f.params.iter().enumerate() {
+            if let Some(&reg) = regs.get(i) {
+                self.write_from(p, reg);
+            } else if self.loc(p).is_some() {
+                // Stack arguments sit above the return address and shadow space
+                let at = 16 + self.abi.shadow_space() + 8 * (i - regs.len()) as i64;
+                let r = self.target(p);
+                self.emit(&format!("mov {}, qword ptr [rbp + {}]", r64(r), at));
+                self.write_back(p);
+            }
+        }
+        let mut i = 0;
+        while i < f.insts.len() {
+            self.placed = self.placement(i);
+            i += self.inst(i)?;
+            // Kept until the consuming call or return has read it
+            if self.placed.is_some() {
+                i += self.inst(i)?;
+                self.placed = None;
+            }
+        }
+        let label = self.ret_label.clone();
+        self.out.push_str(&format!("{}:\n", label));
+        for (k, reg) in self.frame.saved.clone().into_iter().enumerate() {
+            self.emit(&format!("mov {}, qword ptr [rbp - {}]", r64(reg), 8 * (k + 1)));
+        }
+        self.emit("leave");
+        self.emit("ret");
+        let mut out = String::from("\n");
+        define_symbol(&mut out, &f.name, f.global, "function");
+        out.push_str(&self.out);
+        out.push_str(&format!("    .size {}, .-{}\n", quoted(&f.name), operand(&f.name)));
+        Ok(out)
+    }
+
+    /// Argument or result register to compute `insts[i]` into, when its value is used
+    /// only by the call or return that follows. Only for instructions that need no
+    /// scratch register, as argument registers double as scratch ones
+    fn placement(&self, i: usize) -> Option<(VReg, u8)> {
+        let inst = &self.f.insts[i];
+        if !matches!(inst, Inst::Imm(..) | Inst::Copy(..) | Inst::Load { .. } | Inst::LoadSlot(..)
+            | Inst::SlotAddr(..) | Inst::Addr(..)) {
+            return None;
+        }
+        let d = inst.def().filter(|&d| self.uses[d as usize] == 1)?;
+        match self.f.insts.get(i + 1)? {
+            Inst::Ret(v) => (*v == d).then_some((d, RAX)),
+            Inst::Call { callee, args, abi, .. } => {
+                if matches!(callee, Callee::Indirect(f) if *f == d) {
+                    return None;
+                }
+                let class = |k: usize| abi.as_ref().and_then(|a| a.params.get(k).copied().flatten());
+                let k = args.iter().position(|&a| a == d)?;
+                match arg_locs(self.abi, args.len(), class)?[k] {
+                    ArgLoc::Reg(reg) => Some((d, reg)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Select instructions for `insts[i]`; returns how many IR instructions were consumed
+    fn inst(&mut self, i: usize) -> Result<usize> {
+        let f = self.f;
+        match &f.insts[i] {
+            Inst::Imm(d, value) => {
+                let r = self.target(*d);
+                self.mov_imm(r, *value);
+                self.write_back(*d);
+            }
+            Inst::Copy(d, s) => {
+                let r = self.read(*s, 0);
+                self.write_from(*d, r);
+            }
+            Inst::Bin(op, d, a, b) => return self.binary(i, *op, *d, *a, *b),
+            Inst::Neg(d, a) | Inst::BitNot(d, a) => {
+                let ra = self.read(*a, 0);
+                let rd = self.target(*d);
+                if ra != rd {
+                    self.emit(&format!("mov {}, {}", r64(rd), r64(ra)));
+                }
+                let op = if matches!(f.insts[i], Inst::Neg(..)) { "neg" } else { "not" };
+                self.emit(&format!("{} {}", op, r64(rd)));
+                self.write_back(*d);
+            }
+            Inst::FBin(op, d, a, b) => self.double(*op, *d, *a, *b),
+            Inst::IntToFloat(d, a) => {
+                let ra = self.read(*a, 0);
+                self.emit(&format!("cvtsi2sd xmm0, {}", r64(ra)));
+                let rd = self.target(*d);
+                self.emit(&format!("movq {}, xmm0", r64(rd)));
+                self.write_back(*d);
+            }
+            Inst::FloatToInt(d, a) => {
+                let ra = self.read(*a, 0);
+                self.emit(&format!("movq xmm0, {}", r64(ra)));
+                let rd = self.target(*d);
+                self.emit(&format!("cvttsd2si {}, xmm0", r64(rd)));
+                self.write_back(*d);
+            }
+            // x86 loads already have acquire ordering
+            Inst::Load { width, dst, base, offset, .. } => {
+                let rb = self.read(*base, 0);
+                let rd = self.target(*dst);
+                let at = mem(r64(rb), *offset);
+                match width {
+                    Width::W8 | Width::W16 => self.emit(&format!("movzx {}, {} {}", r32(rd), ptr(*width), at)),
+                    Width::W32 => self.emit(&format!("mov {}, dword ptr {}", r32(rd), at)),
+                    Width::W64 => self.emit(&format!("mov {}, qword ptr {}", r64(rd), at)),
+                }
+                self.write_back(*dst);
+            }
+            // Ordered stores are sequentially consistent with `xchg`, which takes the old value
+            Inst::Store { width, src, base, offset, ordered } => {
+                let rb = self.read(*base, 1);
+                let at = mem(r64(rb), *offset);
+                if *ordered {
+                    self.read_into(*src, RAX);
+                    self.emit(&format!("xchg {} {}, {}", ptr(*width), at, sized(RAX, *width)));
+                } else {
+                    let rs = self.read(*src, 0);
+                    self.emit(&format!("mov {} {}, {}", ptr(*width), at, sized(rs, *width)));
+                }
+            }
+            Inst::AtomicRmw { op, src, base } => {
+                let rs = self.read(*src, 0);
+                let rb = self.read(*base, 1);
+                self.emit(&format!("lock {} qword ptr [{}], {}", atomic_op(*op), r64(rb), r64(rs)));
+            }
+            Inst::LoadSlot(d, slot) => {
+                let rd = self.target(*d);
+                let slot = self.slot(*slot);
+                self.emit(&format!("mov {}, {}", r64(rd), slot));
+                self.write_back(*d);
+            }
+            Inst::StoreSlot(v, slot) => {
+                let r = self.read(*v, 0);
+                let slot = self.slot(*slot);
+                self.emit(&format!("mov {}, {}", slot, r64(r)));
+            }
+            Inst::SlotAddr(d, slot) => {
+                let rd = self.target(*d);
+                let slot = self.slot(*slot);
+                self.emit(&format!("lea {}, {}", r64(rd), slot.trim_start_matches("qword ptr ")));
+                self.write_back(*d);
+            }
+            Inst::Addr(d, name, kind) => {
+                let rd = self.target(*d);
+                match kind {
+                    SymKind::ThreadLocal => {
+                        self.emit(&format!("mov {}, qword ptr fs:0", r64(rd)));
+                        self.emit(&format!("lea {}, [{} + {}@tpoff]", r64(rd), r64(rd), operand(name)));
+                    }
+                    _ => self.emit(&format!("lea {}, [rip + {}]", r64(rd), operand(name))),
+                }
+                self.write_back(*d);
+            }
+            Inst::Call { dst, callee, args, abi } => self.call(*dst, callee, args, abi.as_ref())?,
+            Inst::Label(l) => self.out.push_str(&format!(".L{}:\n", l)),
+            Inst::Jump(l) => {
+                if !matches!(f.insts.get(i + 1), Some(Inst::Label(next)) if next == l) {
+                    self.emit(&format!("jmp .L{}", l));
+                }
+            }
+            Inst::Branch(v, zero, l) => {
+                let r = self.read(*v, 0);
+                self.emit(&format!("test {}, {}", r64(r), r64(r)));
+                self.emit(&format!("{} .L{}", if *zero { "je" } else { "jne" }, l));
+            }
+            Inst::Ret(v) => {
+                self.read_into(*v, RAX);
+                if i + 1 < f.insts.len() {
+                    let label = self.ret_label.clone();
+                    self.emit(&format!("jmp {}", label));
+                }
+            }
+        }
+        Ok(1)
+    }
+
+    /// Right-hand operand as an immediate or a register (the second scratch for
+    /// spills and immediates wider than 32 bits)
+    fn operand(&mut self, b: Operand) -> String {
+        match b {
+            Operand::Imm(k) if fits_i32(k) => k.to_string(),
+            Operand::Imm(k) => {
+                self.mov_imm(SCRATCH[1], k);
+                r64(SCRATCH[1]).to_string()
+            }
+            Operand::Reg(v) => r64(self.read(v, 1)).to_string(),
+        }
+    }
+
+    /// Double arithmetic in `xmm0` and `xmm1`. `ucomisd` sets the carry flag for
+    /// less-than and for NaN, so `<` and `<=` swap their operands to test
+    /// above; `==` and `!=` also check the parity flag, which NaN sets
+    fn double(&mut self, op: BinOp, d: VReg, a: VReg, b: VReg) {
+        let ra = self.read(a, 0);
+        self.emit(&format!("movq xmm0, {}", r64(ra)));
+        let rb = self.read(b, 0);
+        self.emit(&format!("movq xmm1, {}", r64(rb)));
+        let rd = self.target(d);
+        let arith = match op {
+            BinOp::Add => Some("addsd"),
+            BinOp::Sub => Some("subsd"),
+            BinOp::Mul => Some("mulsd"),
+            BinOp::Div => Some("divsd"),
+            _ => None,
+        };
+        if let Some(arith) = arith {
+            self.emit(&format!("{} xmm0, xmm1", arith));
+            self.emit(&format!("movq {}, xmm0", r64(rd)));
+        } else {
+            match op {
+                BinOp::Lt | BinOp::Le => self.emit("ucomisd xmm1, xmm0"),
+                _ => self.emit("ucomisd xmm0, xmm1"),
+            }
+            match op {
+                BinOp::Lt | BinOp::Gt => self.emit("seta al"),
+                BinOp::Le | BinOp::Ge => self.emit("setae al"),
+                BinOp::Eq => {
+                    self.emit("sete al");
+                    self.emit("setnp cl");
+                    self.emit("and al, cl");
+                }
+                _ => {
+                    self.emit("setne al");
+                    self.emit("setp cl");
+                    self.emit("or al, cl");
+                }
+            }
+            self.emit(&format!("movzx {}, al", r32(rd)));
+        }
+        self.write_back(d);
+    }
+
+    /// Arithmetic, logic and comparisons; a comparison feeding the next branch becomes `jcc`
+    fn binary(&mut self, i: usize, op: BinOp, d: VReg, a: VReg, b: Operand) -> Result<usize> {
+        if let Some((cond, inverse)) = condition(op) {
+            let ra = self.read(a, 0);
+            let rb = self.operand(b);
+            self.emit(&format!("cmp {}, {}", r64(ra), rb));
+            if let Some(Inst::Branch(v, zero, l)) = self.f.insts.get(i + 1) {
+                if *v == d && self.uses[d as usize] == 1 {
+                    self.emit(&format!("j{} .L{}", if *zero { inverse } else { cond }, l));
+                    return Ok(2);
+                }
+            }
+            let rd = self.target(d);
+            self.emit(&format!("set{} al", cond));
+            self.emit(&format!("movzx {}, al", r32(rd)));
+            self.write_back(d);
+            return Ok(1);
+        }
+        match op {
+            BinOp::Div | BinOp::Mod => {
+                // `idiv` divides rdx:rax, leaving the quotient in rax and the remainder in rdx
+                match b {
+                    Operand::Imm(k) => self.mov_imm(RCX, k),
+                    Operand::Reg(v) => self.read_into(v, RCX),
+                }
+                self.read_into(a, RAX);
+                self.emit("cqo");
+                self.emit("idiv rcx");
+                self.write_from(d, if op == BinOp::Div { RAX } else { RDX });
+                return Ok(1);
+            }
+            BinOp::Shl | BinOp::Shr => {
+                let name = if op == BinOp::Shl { "shl" } else { "shr" };
+                let count = match b {
+                    Operand::Imm(k) => (k & 63).to_string(),
+                    Operand::Reg(v) => {
+                        self.read_into(v, RCX);
+                        "cl".to_string()
+                    }
+                };
+                let ra = self.read(a, 0);
+                let rd = self.target(d);
+                if ra != rd {
+                    self.emit(&format!("mov {}, {}", r64(rd), r64(ra)));
+                }
+                self.emit(&format!("{} {}, {}", name, r64(rd), count));
+                self.write_back(d);
+                return Ok(1);
+            }
+            _ => {}
+        }
+        let name = match op {
+            BinOp::Add => "add",
+            BinOp::Sub => "sub",
+            BinOp::Mul => "imul",
+            BinOp::And | BinOp::BitAnd => "and",
+            BinOp::Or | BinOp::BitOr => "or",
+            _ => "xor",
+        };
+        let ra = self.read(a, 0);
+        let rd = self.target(d);
+        if let (BinOp::Mul, Operand::Imm(k)) = (op, b) {
+            if fits_i32(k) {
+                self.emit(&format!("imul {}, {}, {}", r64(rd), r64(ra), k));
+                self.write_back(d);
+                return Ok(1);
+            }
+        }
+        let rb = self.operand(b);
+        if rb == r64(rd) && ra != rd {
+            if op == BinOp::Sub {
+                // rd holds b: compute in rax
+                self.emit(&format!("mov rax, {}", r64(ra)));
+                self.emit(&format!("sub rax, {}", rb));
+                self.write_from(d, RAX);
+                return Ok(1);
+            }
+            // Commutative: rd already holds b
+            self.emit(&format!("{} {}, {}", name, r64(rd), r64(ra)));
+        } else {
+            if ra != rd {
+                self.emit(&format!("mov {}, {}", r64(rd), r64(ra)));
+            }
+            self.emit(&format!("{} {}, {}", name, r64(rd), rb));
+        }
+        self.write_back(d);
+        Ok(1)
+    }
+
+    /// Call with arguments where [`arg_locs`] puts them
+    fn call(&mut self, dst: Option<VReg>, callee: &Callee, args: &[VReg], abi: Option<&CAbi>) -> Result<()> {
+        let class = |k: usize| abi.and_then(|a| a.params.get(k).copied().flatten());
+        let locs = arg_locs(self.abi, args.len(), class)
+            .ok_or_else(|| anyhow!("Too many floating-point arguments in a call from {}", self.f.name))?;
+        let mut floats = 0;
+        for (k, (&arg, loc)) in args.iter().zip(locs).enumerate() {
+            match loc {
+                ArgLoc::Reg(reg) => self.read_into(arg, reg),
+                ArgLoc::Sse(xmm) => {
+                    let r = self.read(arg, 0);
+                    self.emit(&format!("movq xmm{}, {}", xmm, r64(r)));
+                    if class(k).is_some_and(|t| t.llvm == "float") {
+                        self.emit(&format!("cvtsd2ss xmm{}, xmm{}", xmm, xmm));
+                    }
+                    floats += 1;
+                }
+                ArgLoc::Stack(at) => {
+                    let r = self.read(arg, 0);
+                    self.emit(&format!("mov qword ptr {}, {}", mem("rsp", at), r64(r)));
+                }
+            }
+        }
+        match callee {
+            Callee::Direct(name) => {
+                let target = self.callee(name);
+                // `al` bounds the vector registers a variadic System V callee reads
+                if target.ends_with("@PLT") && self.abi == Abi::SysV {
+                    self.emit(&format!("mov eax, {}", floats));
+                }
+                self.emit(&format!("call {}", target));
+            }
+            Callee::Indirect(f) => match self.loc(*f) {
+                Some(Loc::Reg(r)) => self.emit(&format!("call {}", r64(r))),
+                Some(Loc::Spill(s)) => {
+                    let slot = self.slot(s);
+                    self.emit(&format!("call {}", slot));
+                }
+                None => bail!("Call through an undefined function pointer in {}", self.f.name),
+            },
+        }
+        let Some(d) = dst else { return Ok(()) };
+        if let Some(ret) = abi.and_then(|a| a.ret) {
+            self.extend_result(ret);
+        }
+        self.write_from(d, RAX);
+        Ok(())
+    }
+
+    /// Widen a C result in `rax`/`xmm0` to a word in `rax`
+    fn extend_result(&mut self, ty: CScalar) {
+        match (ty.llvm, ty.signed) {
+            ("i32", true) => self.emit("movsxd rax, eax"),
+            ("i32", false) => self.emit("mov eax, eax"),
+            ("i16", true) => self.emit("movsx rax, ax"),
+            ("i16", false) => self.emit("movzx eax, ax"),
+            ("i8", true) => self.emit("movsx rax, al"),
+            ("i8", false) | ("i1", _) => self.emit("movzx eax, al"),
+            ("double", _) => self.emit("movq rax, xmm0"),
+            ("float", _) => {
+                self.emit("cvtss2sd xmm0, xmm0");
+                self.emit("movq rax, xmm0");
+            }
+            _ => {}
+        }
+    }
 }
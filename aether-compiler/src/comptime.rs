@@ -0,0 +1,413 @@
+//! Compile-time evaluation - `comptime { }` blocks and `comptime` parameters
+//! Blocks run in a sandboxed interpreter and are replaced by literals; functions
+//! with comptime parameters are specialized for each distinct argument list.
+
+use std::collections::{HashMap, HashSet};
+use anyhow::{anyhow, bail, Result};
+use crate::ast::*;
+use crate::interp::{self, Interpreter};
+
+/// Specializations allowed per function with comptime parameters
+const MAX_INSTANCES: usize = 1000;
+
+/// Values of the comptime parameters in scope
+type Env = [(String, i64)];
+
+struct Expander {
+    interp: Interpreter,
+    /// Functions with comptime parameters, removed from the module
+    templates: HashMap<String, Decl>,
+    /// Instance names already generated
+    done: HashSet<String>,
+    /// Instance count per template
+    counts: HashMap<String, usize>,
+    /// Instances to append to the module
+    generated: Vec<Decl>,
+    /// Return type of the function being expanded
+    ret: Option<Type>,
+    /// Return types of every function, to type comptime results
+    rets: HashMap<String, Type>,
+    /// Struct names; their values are heap addresses
+    structs: HashSet<String>,
+}
+
+impl Expander {
+    fn decl(&mut self, decl: &mut Decl) -> Result<()> {
+        match decl {
+            Decl::Func { params, ret, body, .. } => {
+                for p in params.iter_mut() {
+                    if let Some(default) = &mut p.default {
+                        self.expr(default, Some(&p.ty), &[])?;
+                    }
+                }
+                self.ret = ret.clone();
+                self.block(body, ret.as_ref(), &[])
+            }
+            Decl::Const { ty, value, .. } => self.expr(value, Some(ty), &[]),
+            Decl::Static { ty, value: Some(value), .. } => self.expr(value, ty.as_ref(), &[]),
+            Decl::Impl { methods, .. } | Decl::Trait { methods, .. } => {
+                methods.iter_mut().try_for_each(|m| self.decl(m))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// `tail` is the type expected of the block's final expression
+    fn block(&mut self, block: &mut Block, tail: Option<&Type>, env: &Env) -> Result<()> {
+        let last = block.stmts.len().saturating_sub(1);
+        for (i, stmt) in block.stmts.iter_mut().enumerate() {
+            match stmt {
+                Stmt::Expr(e, _) if i == last => self.expr(e, tail, env)?,
+                _ => self.stmt(stmt, env)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &mut Stmt, env: &Env) -> Result<()> {
+        match stmt {
+            Stmt::Let { ty, init: Some(init), .. } => self.expr(init, ty.as_ref(), env),
+            Stmt::Expr(e, _) => self.expr(e, None, env),
+            Stmt::Assign(target, value, _) => {
+                self.expr(target, None, env)?;
+                self.expr(value, None, env)
+            }
+            Stmt::Return(Some(e), _) => {
+                let ret = self.ret.clone();
+                self.expr(e, ret.as_ref(), env)
+            }
+            Stmt::If(cond, then_block, else_block, _) => {
+                self.expr(cond, None, env)?;
+                self.block(then_block, None, env)?;
+                match else_block {
+                    Some(b) => self.block(b, None, env),
+                    None => Ok(()),
+                }
+            }
+            Stmt::While(cond, body, _) | Stmt::For(_, cond, body, _) => {
+                self.expr(cond, None, env)?;
+                self.block(body, None, env)
+            }
+            Stmt::Block(body, _) => self.block(body, None, env),
+            _ => Ok(()),
+        }
+    }
+
+    /// `hint` is the type the context expects, used to shape comptime results
+    fn expr(&mut self, expr: &mut Expr, hint: Option<&Type>, env: &Env) -> Result<()> {
+        let hint = hint.filter(|t| !matches!(t, Type::Infer));
+        match expr {
+            Expr::Comptime(block, span) => {
+                let span = *span;
+                let value = self.interp.eval_block(env, block)
+                    .map_err(|e| anyhow!("Error in comptime block at line {}: {}", span.line, e))?;
+                // An address stays an address even where an Int is expected;
+                // only String and array contexts copy the data it points at
+                let natural = block_type(block, &self.rets);
+                let ty = match hint {
+                    Some(Type::Named(n)) if n != "String" && self.is_address(&natural) => natural,
+                    Some(hint) => hint.clone(),
+                    None => natural,
+                };
+                *expr = self.literal(value, &ty, span)?;
+            }
+            Expr::Call(func, args, span) => {
+                for arg in args.iter_mut() {
+                    self.expr(arg, None, env)?;
+                }
+                if let Expr::Ident(name, _) = func.as_mut() {
+                    if self.templates.contains_key(name.as_str()) {
+                        let (instance, rest) = self.instantiate(name, args, *span, env)?;
+                        *name = instance;
+                        *args = rest;
+                    }
+                }
+            }
+            Expr::Binary(_, left, right, _) | Expr::Index(left, right, _) => {
+                self.expr(left, None, env)?;
+                self.expr(right, None, env)?;
+            }
+            Expr::Unary(_, inner, _) | Expr::Field(inner, _, _) => self.expr(inner, None, env)?,
+            Expr::MethodCall(obj, _, args, _) => {
+                self.expr(obj, None, env)?;
+                for arg in args.iter_mut() {
+                    self.expr(arg, None, env)?;
+                }
+            }
+            Expr::Spawn(_, args, _) => {
+                for arg in args.iter_mut() {
+                    self.expr(arg, None, env)?;
+                }
+            }
+            Expr::Array(elems, _) => {
+                let elem = match hint {
+                    Some(Type::Array(elem, _)) | Some(Type::ConstArray(elem, _)) => Some(elem.as_ref().clone()),
+                    _ => None,
+                };
+                for e in elems.iter_mut() {
+                    self.expr(e, elem.as_ref(), env)?;
+                }
+            }
+            Expr::Struct(_, fields, _) => {
+                for (_, value) in fields.iter_mut() {
+                    self.expr(value, None, env)?;
+                }
+            }
+            Expr::Unsafe(block, _) => self.block(block, hint, env)?,
+            Expr::If(cond, then_block, else_block, _) => {
+                self.expr(cond, None, env)?;
+                self.block(then_block, hint, env)?;
+                if let Some(b) = else_block {
+                    self.block(b, hint, env)?;
+                }
+            }
+            Expr::Lambda(_, ret, body, _) => {
+                let ret = ret.clone();
+                self.expr(body, ret.as_ref(), env)?;
+            }
+            Expr::Match(scrutinee, arms, _) => {
+                self.expr(scrutinee, None, env)?;
+                for arm in arms.iter_mut() {
+                    if let Some(guard) = &mut arm.guard {
+                        self.expr(guard, None, env)?;
+                    }
+                    self.expr(&mut arm.body, hint, env)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Evaluate an expression at compile time
+    fn eval(&mut self, expr: &Expr, env: &Env) -> Result<i64> {
+        let span = expr.span();
+        self.interp.eval_block(env, &Block { stmts: vec![Stmt::Expr(expr.clone(), span)], span })
+    }
+
+    /// Turn an interpreter value into a literal of type `ty`
+    fn literal(&mut self, value: i64, ty: &Type, span: Span) -> Result<Expr> {
+        Ok(match ty {
+            Type::Named(n) if n == "Bool" => Expr::Bool(value != 0, span),
+            Type::Named(n) if n == "String" => Expr::String(self.interp.read_string(value)?, span),
+            // The address of a compile-time allocation means nothing at run time
+            ty if self.is_address(ty) => bail!(
+                "Compile-time value at line {} is a {} pointing into compile-time memory, which does not exist at run time (produce an Int, Bool, String or fixed-size array instead)",
+                span.line, ty),
+            Type::Array(elem, Some(len)) => self.array(value, elem, *len, span)?,
+            Type::ConstArray(elem, len) => {
+                let len = self.eval(len, &[])?;
+                if len < 0 {
+                    bail!("Array length must be a non-negative Int, found {} at line {}", len, span.line);
+                }
+                self.array(value, elem, len as usize, span)?
+            }
+            _ => Expr::Int(value, span),
+        })
+    }
+
+    /// Whether values of `ty` are addresses rather than data
+    fn is_address(&self, ty: &Type) -> bool {
+        match ty {
+            Type::Ptr(..) | Type::RawPtr(_) | Type::Ref(..) | Type::Array(_, None) | Type::Func(..) | Type::Generic(..) => true,
+            // Structs are heap blocks
+            Type::Named(n) => self.structs.contains(n),
+            _ => false,
+        }
+    }
+
+    fn array(&mut self, addr: i64, elem: &Type, len: usize, span: Span) -> Result<Expr> {
+        let words = self.interp.read_words(addr, len)?;
+        let elems = words.into_iter()
+            .map(|w| self.literal(w, elem, span))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Expr::Array(elems, span))
+    }
+
+    /// Specialize a call to a function with comptime parameters; returns the
+    /// instance name and the remaining runtime arguments
+    fn instantiate(&mut self, name: &str, args: &[Expr], span: Span, env: &Env) -> Result<(String, Vec<Expr>)> {
+        let template = self.templates[name].clone();
+        let Decl::Func { params, .. } = &template else {
+            unreachable!("templates are functions");
+        };
+        if args.len() > params.len() {
+            bail!("{} takes {} arguments but {} were given at line {}", name, params.len(), args.len(), span.line);
+        }
+
+        let mut values = Vec::new();
+        let mut rest = Vec::new();
+        for (i, p) in params.iter().enumerate() {
+            if !p.comptime {
+                rest.extend(args.get(i).cloned());
+                continue;
+            }
+            let arg = args.get(i).or(p.default.as_ref())
+                .ok_or_else(|| anyhow!("Missing comptime argument {} of {} at line {}", p.name, name, span.line))?;
+            let v = self.eval(arg, env)
+                .map_err(|e| anyhow!("Argument {} of {} must be known at compile time (line {}): {}", p.name, name, span.line, e))?;
+            values.push((p, v));
+        }
+
+        let suffix: Vec<String> = values.iter()
+            .map(|(_, v)| if *v < 0 { format!("m{}", v.unsigned_abs()) } else { v.to_string() })
+            .collect();
+        let instance = format!("{}__ct_{}", name, suffix.join("_"));
+        if self.done.contains(&instance) {
+            return Ok((instance, rest));
+        }
+        let count = self.counts.entry(name.to_string()).or_insert(0);
+        *count += 1;
+        if *count > MAX_INSTANCES {
+            bail!("Too many instances of {} (more than {}) at line {}; is a comptime argument unbounded?",
+                name, MAX_INSTANCES, span.line);
+        }
+        self.done.insert(instance.clone());
+
+        let Decl::Func { generics, params, ret, mut body, attrs, span: decl_span, .. } = template.clone() else {
+            unreachable!("templates are functions");
+        };
+        // Comptime parameters become local constants of the instance
+        let mut prelude = Vec::new();
+        for (p, v) in &values {
+            prelude.push(Stmt::Let {
+                name: p.name.clone(),
+                ty: Some(p.ty.clone()),
+                init: Some(self.literal(*v, &p.ty, p.span)?),
+                mutable: false,
+                span: p.span,
+            });
+        }
+        prelude.append(&mut body.stmts);
+        body.stmts = prelude;
+
+        let inner_env: Vec<(String, i64)> = values.iter().map(|(p, v)| (p.name.clone(), *v)).collect();
+        let saved_ret = std::mem::replace(&mut self.ret, ret.clone());
+        let result = self.block(&mut body, ret.as_ref(), &inner_env);
+        self.ret = saved_ret;
+        result?;
+
+        self.generated.push(Decl::Func {
+            name: instance.clone(),
+            generics,
+            params: params.into_iter().filter(|p| !p.comptime).collect(),
+            ret,
+            body,
+            public: false,
+            constant: false,
+            is_unsafe: false,
+            attrs,
+            span: decl_span,
+        });
+        Ok((instance, rest))
+    }
+}
+
+/// Builtins whose result is a fresh allocation
+const ALLOC_BUILTINS: [&str; 2] = ["__builtin_malloc", "__builtin_realloc"];
+
+/// Type of a comptime block's value when the context does not say
+fn block_type(block: &Block, rets: &HashMap<String, Type>) -> Type {
+    // Lets the tail may name, typed from their annotation or initializer
+    let mut locals = HashMap::new();
+    let last = block.stmts.len().saturating_sub(1);
+    for (i, stmt) in block.stmts.iter().enumerate() {
+        match stmt {
+            Stmt::Let { name, ty, init, .. } => {
+                let ty = ty.clone().or_else(|| init.as_ref().map(|e| value_type(e, rets, &locals)));
+                locals.insert(name.clone(), ty.unwrap_or(Type::Infer));
+            }
+            Stmt::Expr(e, _) if i == last => return value_type(e, rets, &locals),
+            _ => {}
+        }
+    }
+    Type::Named("Int".into())
+}
+
+fn value_type(expr: &Expr, rets: &HashMap<String, Type>, locals: &HashMap<String, Type>) -> Type {
+    match expr {
+        Expr::String(..) => Type::Named("String".into()),
+        Expr::Bool(..) | Expr::Unary(UnOp::Not, _, _) => Type::Named("Bool".into()),
+        Expr::Binary(BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge |
+            BinOp::And | BinOp::Or, _, _, _) => Type::Named("Bool".into()),
+        Expr::Array(elems, _) => {
+            let elem = elems.first().map(|e| value_type(e, rets, locals)).unwrap_or_else(|| Type::Named("Int".into()));
+            Type::Array(Box::new(elem), Some(elems.len()))
+        }
+        Expr::Struct(name, _, _) => Type::Named(name.clone()),
+        Expr::Unary(op @ (UnOp::Ref | UnOp::RefMut), inner, _) => {
+            Type::Ptr(Box::new(value_type(inner, rets, locals)), *op == UnOp::RefMut)
+        }
+        Expr::Call(func, _, _) => match func.as_ref() {
+            Expr::Ident(name, _) if ALLOC_BUILTINS.contains(&name.as_str()) => Type::RawPtr(Box::new(Type::Named("UInt8".into()))),
+            Expr::Ident(name, _) => rets.get(name).cloned().unwrap_or_else(|| Type::Named("Int".into())),
+            Expr::Path(path, _) if path.len() == 2 && path[1] == "new" => match path[0].as_str() {
+                "Box" => Type::Generic("Box".into(), vec![Type::Infer]),
+                name => Type::Named(name.to_string()),
+            },
+            _ => Type::Named("Int".into()),
+        },
+        Expr::Ident(name, _) => match locals.get(name) {
+            Some(Type::Infer) | None => Type::Named("Int".into()),
+            Some(ty) => ty.clone(),
+        },
+        Expr::If(_, then_block, _, _) | Expr::Comptime(then_block, _) | Expr::Unsafe(then_block, _) => block_type(then_block, rets),
+        _ => Type::Named("Int".into()),
+    }
+}
+
+fn is_template(decl: &Decl) -> bool {
+    matches!(decl, Decl::Func { params, .. } if params.iter().any(|p| p.comptime))
+}
+
+/// Evaluate every `comptime` block and specialize functions with comptime parameters
+pub fn expand_module(module: &mut Module, allow_io: bool) -> Result<()> {
+    // Compile-time code may recurse as deeply as interpreted code
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .name("aether-comptime".into())
+            .stack_size(interp::STACK_SIZE)
+            .spawn_scoped(scope, || expand(module, allow_io))?
+            .join()
+            .map_err(|_| anyhow!("Compile-time evaluation panicked"))?
+    })
+}
+
+fn expand(module: &mut Module, allow_io: bool) -> Result<()> {
+    let mut interp = Interpreter::sandboxed(allow_io);
+    let rets = module.decls.iter()
+        .filter_map(|d| match d {
+            Decl::Func { name, ret: Some(ret), .. } => Some((name.clone(), ret.clone())),
+            _ => None,
+        })
+        .collect();
+    let structs = module.decls.iter()
+        .filter_map(|d| match d {
+            Decl::Struct { name, .. } => Some(name.clone()),
+            _ => None,
+        })
+        .collect();
+    interp.load_decls(&module.decls);
+
+    let (templates, decls): (Vec<Decl>, Vec<Decl>) = std::mem::take(&mut module.decls)
+        .into_iter()
+        .partition(is_template);
+    let mut expander = Expander {
+        interp,
+        templates: templates.into_iter()
+            .filter_map(|d| Some((d.name()?.to_string(), d)))
+            .collect(),
+        done: HashSet::new(),
+        counts: HashMap::new(),
+        generated: Vec::new(),
+        ret: None,
+        rets,
+        structs,
+    };
+    module.decls = decls;
+    for decl in module.decls.iter_mut() {
+        expander.decl(decl)?;
+    }
+    module.decls.append(&mut expander.generated);
+    Ok(())
+}
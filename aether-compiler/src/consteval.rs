@@ -0,0 +1,575 @@
+//! Aether Const Evaluation - Compile-time folding of constants
+//!
+//! Evaluates `const` initializers and array lengths, including calls to
+//! `const func`s, and replaces them with literals before type checking.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use crate::ast::*;
+use crate::tooling::fmt::print_expr;
+use anyhow::{anyhow, Result};
+
+/// Statements and loop iterations one constant may take before we give up
+const MAX_STEPS: usize = 1_000_000;
+
+/// Nested `const func` calls allowed in one evaluation
+const MAX_DEPTH: usize = 256;
+
+/// Value of a constant expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstValue {
+    Int(i64),
+    Bool(bool),
+}
+
+impl ConstValue {
+    fn type_name(self) -> &'static str {
+        match self {
+            ConstValue::Int(_) => "Int",
+            ConstValue::Bool(_) => "Bool",
+        }
+    }
+
+    /// Literal expression with this value
+    pub fn to_expr(self, span: Span) -> Expr {
+        match self {
+            ConstValue::Int(v) => Expr::Int(v, span),
+            ConstValue::Bool(b) => Expr::Bool(b, span),
+        }
+    }
+}
+
+impl fmt::Display for ConstValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConstValue::Int(v) => write!(f, "{}", v),
+            ConstValue::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+/// Range of the integer types constants can have
+fn int_range(ty: &str) -> Option<(i64, i64)> {
+    Some(match ty {
+        "Int" | "Int64" => (i64::MIN, i64::MAX),
+        "UInt64" => (0, i64::MAX),
+        "Int32" => (i32::MIN as i64, i32::MAX as i64),
+        "UInt32" | "Char" => (0, u32::MAX as i64),
+        "Int16" => (i16::MIN as i64, i16::MAX as i64),
+        "UInt16" => (0, u16::MAX as i64),
+        "Int8" => (i8::MIN as i64, i8::MAX as i64),
+        "UInt8" => (0, u8::MAX as i64),
+        _ => return None,
+    })
+}
+
+/// Non-local control flow inside `const func` bodies
+enum Unwind {
+    Return(ConstValue),
+    Break,
+    Continue,
+    Error(anyhow::Error),
+}
+
+impl From<anyhow::Error> for Unwind {
+    fn from(e: anyhow::Error) -> Self {
+        Unwind::Error(e)
+    }
+}
+
+type Eval<T> = std::result::Result<T, Unwind>;
+
+fn fail<T>(msg: String) -> Eval<T> {
+    Err(Unwind::Error(anyhow!(msg)))
+}
+
+/// Constant declared but not evaluated yet
+struct Pending {
+    ty: Type,
+    value: Expr,
+    span: Span,
+}
+
+/// Body of a `const func`
+struct ConstFunc {
+    params: Vec<Param>,
+    body: Block,
+}
+
+/// Const evaluator; keeps results so later declarations (or REPL inputs) can use them
+pub struct ConstEvaluator {
+    /// Evaluated constants
+    values: HashMap<String, ConstValue>,
+    /// Declared constants, evaluated on first use so order doesn't matter
+    pending: HashMap<String, Pending>,
+    /// Constants being evaluated, to report cycles
+    active: Vec<String>,
+    funcs: HashMap<String, Rc<ConstFunc>>,
+    /// Locals of the `const func` calls in progress, innermost scope last
+    scopes: Vec<HashMap<String, ConstValue>>,
+    /// First scope of the current call
+    frame: usize,
+    depth: usize,
+    steps: usize,
+}
+
+impl ConstEvaluator {
+    pub fn new() -> Self {
+        ConstEvaluator {
+            values: HashMap::new(),
+            pending: HashMap::new(),
+            active: Vec::new(),
+            funcs: HashMap::new(),
+            scopes: Vec::new(),
+            frame: 0,
+            depth: 0,
+            steps: 0,
+        }
+    }
+
+    /// Value of an evaluated constant
+    pub fn value(&self, name: &str) -> Option<ConstValue> {
+        self.values.get(name).copied()
+    }
+
+    /// Evaluate every constant and array length in `decls`, replacing them with literals
+    pub fn fold_decls(&mut self, decls: &mut [Decl]) -> Result<()> {
+        for decl in decls.iter() {
+            match decl {
+                Decl::Const { name, ty, value, span, .. } => {
+                    self.values.remove(name);
+                    self.pending.insert(name.clone(), Pending { ty: ty.clone(), value: value.clone(), span: *span });
+                }
+                Decl::Func { name, params, body, constant: true, .. } => {
+                    self.funcs.insert(name.clone(), Rc::new(ConstFunc { params: params.clone(), body: body.clone() }));
+                }
+                _ => {}
+            }
+        }
+        for decl in decls.iter_mut() {
+            if let Decl::Const { name, value, ty, span, .. } = decl {
+                if let Some(v) = self.constant(name, *span)? {
+                    *value = v.to_expr(value.span());
+                }
+                self.fold_type(ty)?;
+            }
+            // Static initializers that are constant become global initializers;
+            // the rest are computed at run time on first use
+            if let Decl::Static { value: Some(value), .. } = decl {
+                if let Ok(v) = self.eval(value) {
+                    *value = v.to_expr(value.span());
+                }
+            }
+            self.fold_decl_types(decl)?;
+        }
+        Ok(())
+    }
+
+    /// Resolve array lengths in the types a statement mentions
+    pub fn fold_stmt(&mut self, stmt: &mut Stmt) -> Result<()> {
+        match stmt {
+            Stmt::Let { ty: Some(ty), .. } => self.fold_type(ty),
+            Stmt::If(_, then_block, else_block, _) => {
+                self.fold_block(then_block)?;
+                match else_block {
+                    Some(b) => self.fold_block(b),
+                    None => Ok(()),
+                }
+            }
+            Stmt::While(_, body, _) | Stmt::For(_, _, body, _) | Stmt::Block(body, _) => self.fold_block(body),
+            _ => Ok(()),
+        }
+    }
+
+    /// Evaluate a constant expression on its own
+    pub fn eval(&mut self, expr: &Expr) -> Result<ConstValue> {
+        self.steps = 0;
+        self.finish(|ev| ev.eval_expr(expr))
+    }
+
+    fn finish(&mut self, f: impl FnOnce(&mut Self) -> Eval<ConstValue>) -> Result<ConstValue> {
+        let saved = (self.scopes.len(), self.frame, self.depth);
+        let result = f(self);
+        self.scopes.truncate(saved.0);
+        self.frame = saved.1;
+        self.depth = saved.2;
+        match result {
+            Ok(v) | Err(Unwind::Return(v)) => Ok(v),
+            Err(Unwind::Break) => Err(anyhow!("`break` outside of a loop in constant expression")),
+            Err(Unwind::Continue) => Err(anyhow!("`continue` outside of a loop in constant expression")),
+            Err(Unwind::Error(e)) => Err(e),
+        }
+    }
+
+    /// Evaluate a declared constant; `None` for types the evaluator doesn't handle (Float, String, ...)
+    fn constant(&mut self, name: &str, span: Span) -> Result<Option<ConstValue>> {
+        if let Some(v) = self.values.get(name) {
+            return Ok(Some(*v));
+        }
+        let Some(pending) = self.pending.get(name) else {
+            return Err(anyhow!("Unknown constant {} at line {}", name, span.line));
+        };
+        let ty_name = match &pending.ty {
+            Type::Named(n) if n == "Bool" || int_range(n).is_some() => n.clone(),
+            _ => return Ok(None),
+        };
+        if let Some(start) = self.active.iter().position(|a| a == name) {
+            let mut cycle = self.active[start..].to_vec();
+            cycle.push(name.to_string());
+            return Err(anyhow!("Constant {} depends on itself ({}) at line {}", name, cycle.join(" -> "), pending.span.line));
+        }
+
+        let (value, decl_span) = (pending.value.clone(), pending.span);
+        self.active.push(name.to_string());
+        // Evaluate outside any caller's const func frame
+        let outer = std::mem::take(&mut self.scopes);
+        let outer_frame = std::mem::replace(&mut self.frame, 0);
+        let outer_steps = std::mem::replace(&mut self.steps, 0);
+        let result = self.finish(|ev| ev.eval_expr(&value));
+        self.scopes = outer;
+        self.frame = outer_frame;
+        self.steps = outer_steps;
+        self.active.pop();
+        let v = result?;
+
+        match (ty_name.as_str(), v) {
+            ("Bool", ConstValue::Bool(_)) => {}
+            (ty, ConstValue::Int(n)) if int_range(ty).is_some() => {
+                let (lo, hi) = int_range(ty).expect("checked above");
+                if n < lo || n > hi {
+                    return Err(anyhow!("Constant {} = {} does not fit in {} at line {}", name, n, ty, decl_span.line));
+                }
+            }
+            (ty, v) => {
+                return Err(anyhow!("Constant {} is declared {} but its value is {} ({}) at line {}",
+                    name, ty, v.type_name(), v, decl_span.line));
+            }
+        }
+        self.values.insert(name.to_string(), v);
+        Ok(Some(v))
+    }
+
+    // ========== Types ==========
+
+    fn fold_type(&mut self, ty: &mut Type) -> Result<()> {
+        match ty {
+            Type::ConstArray(elem, size) => {
+                self.fold_type(elem)?;
+                let line = size.span().line;
+                let n = match self.eval(size)? {
+                    ConstValue::Int(n) if n >= 0 => n as usize,
+                    v => return Err(anyhow!("Array length must be a non-negative Int, found {} at line {}", v, line)),
+                };
+                let elem = std::mem::replace(elem.as_mut(), Type::Infer);
+                *ty = Type::Array(Box::new(elem), Some(n));
+                Ok(())
+            }
+            Type::Ptr(inner, _) | Type::RawPtr(inner) | Type::Ref(_, inner, _) | Type::Array(inner, _) => self.fold_type(inner),
+            Type::Generic(_, args) => args.iter_mut().try_for_each(|a| self.fold_type(a)),
+            Type::Func(params, ret) => {
+                params.iter_mut().try_for_each(|p| self.fold_type(p))?;
+                match ret.as_mut() {
+                    Some(r) => self.fold_type(r),
+                    None => Ok(()),
+                }
+            }
+            Type::Named(_) | Type::Infer | Type::Unit => Ok(()),
+        }
+    }
+
+    fn fold_params(&mut self, params: &mut [Param]) -> Result<()> {
+        params.iter_mut().try_for_each(|p| self.fold_type(&mut p.ty))
+    }
+
+    fn fold_block(&mut self, block: &mut Block) -> Result<()> {
+        block.stmts.iter_mut().try_for_each(|s| self.fold_stmt(s))
+    }
+
+    fn fold_decl_types(&mut self, decl: &mut Decl) -> Result<()> {
+        match decl {
+            Decl::Func { params, ret, body, .. } => {
+                self.fold_params(params)?;
+                if let Some(r) = ret {
+                    self.fold_type(r)?;
+                }
+                self.fold_block(body)
+            }
+            Decl::Struct { fields, .. } => fields.iter_mut().try_for_each(|f| self.fold_type(&mut f.ty)),
+            Decl::Enum { variants, .. } => variants.iter_mut()
+                .try_for_each(|v| v.fields.iter_mut().try_for_each(|t| self.fold_type(t))),
+            Decl::Trait { methods, .. } | Decl::Impl { methods, .. } => {
+                methods.iter_mut().try_for_each(|m| self.fold_decl_types(m))
+            }
+            Decl::TypeAlias { ty, .. } | Decl::Static { ty: Some(ty), .. } => self.fold_type(ty),
+            Decl::Extern { funcs, .. } => funcs.iter_mut().try_for_each(|f| {
+                self.fold_params(&mut f.params)?;
+                match &mut f.ret {
+                    Some(r) => self.fold_type(r),
+                    None => Ok(()),
+                }
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    // ========== Evaluation ==========
+
+    fn step(&mut self, span: Span) -> Eval<()> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return fail(format!("Constant evaluation took more than {} steps at line {} (infinite loop?)", MAX_STEPS, span.line));
+        }
+        Ok(())
+    }
+
+    fn int(&mut self, expr: &Expr) -> Eval<i64> {
+        match self.eval_expr(expr)? {
+            ConstValue::Int(v) => Ok(v),
+            v => fail(format!("Expected Int in constant expression, found {} at line {}", v.type_name(), expr.span().line)),
+        }
+    }
+
+    fn bool(&mut self, expr: &Expr) -> Eval<bool> {
+        match self.eval_expr(expr)? {
+            ConstValue::Bool(b) => Ok(b),
+            // Conditions may be integers, as at runtime
+            ConstValue::Int(v) => Ok(v != 0),
+        }
+    }
+
+    fn eval_expr(&mut self, expr: &Expr) -> Eval<ConstValue> {
+        match expr {
+            Expr::Int(v, _) => Ok(ConstValue::Int(*v)),
+
+            Expr::Bool(b, _) => Ok(ConstValue::Bool(*b)),
+
+            Expr::Ident(name, span) => {
+                let local = self.scopes[self.frame..].iter().rev().find_map(|s| s.get(name).copied());
+                if let Some(v) = local {
+                    return Ok(v);
+                }
+                if !self.pending.contains_key(name) && !self.values.contains_key(name) {
+                    return fail(format!("Cannot use non-constant {} in a constant expression at line {}", name, span.line));
+                }
+                match self.constant(name, *span)? {
+                    Some(v) => Ok(v),
+                    None => fail(format!("Constant {} cannot be used in constant expressions (only Int and Bool) at line {}", name, span.line)),
+                }
+            }
+
+            Expr::Binary(op, left, right, span) => self.binary(expr, *op, left, right, *span),
+
+            Expr::Unary(op, inner, span) => match op {
+                UnOp::Neg => match self.int(inner)?.checked_neg() {
+                    Some(v) => Ok(ConstValue::Int(v)),
+                    None => fail(format!("Integer overflow in constant expression {} at line {}", print_expr(expr), span.line)),
+                },
+                UnOp::Not => match self.eval_expr(inner)? {
+                    ConstValue::Bool(b) => Ok(ConstValue::Bool(!b)),
+                    ConstValue::Int(v) => Ok(ConstValue::Int(v ^ 1)),
+                },
+                UnOp::BitNot => Ok(ConstValue::Int(!self.int(inner)?)),
+                UnOp::Ref | UnOp::RefMut | UnOp::Deref => fail(format!("Pointers are not allowed in constant expressions at line {}", span.line)),
+            },
+
+            Expr::If(cond, then_block, else_block, _) => {
+                if self.bool(cond)? {
+                    self.scoped(then_block)
+                } else if let Some(eb) = else_block {
+                    self.scoped(eb)
+                } else {
+                    Ok(ConstValue::Int(0))
+                }
+            }
+
+            Expr::Call(func, args, span) => {
+                let name = match func.as_ref() {
+                    Expr::Ident(name, _) => name,
+                    _ => return fail(format!("Only const funcs can be called in constant expressions at line {}", span.line)),
+                };
+                let Some(f) = self.funcs.get(name).cloned() else {
+                    return fail(format!("Cannot call non-const function {} in a constant expression at line {}", name, span.line));
+                };
+                let mut values = Vec::new();
+                for arg in args {
+                    values.push(self.eval_expr(arg)?);
+                }
+                self.call(name, &f, values, *span)
+            }
+
+            other => fail(format!("{} is not allowed in a constant expression at line {}", print_expr(other), other.span().line)),
+        }
+    }
+
+    fn binary(&mut self, expr: &Expr, op: BinOp, left: &Expr, right: &Expr, span: Span) -> Eval<ConstValue> {
+        // Logical operators short-circuit so guards like `n != 0 && X / n > 1` work
+        if matches!(op, BinOp::And | BinOp::Or) {
+            let l = self.bool(left)?;
+            if l == (op == BinOp::Or) {
+                return Ok(ConstValue::Bool(l));
+            }
+            return Ok(ConstValue::Bool(self.bool(right)?));
+        }
+
+        let l = self.eval_expr(left)?;
+        let r = self.eval_expr(right)?;
+        let (a, b) = match (l, r) {
+            (ConstValue::Int(a), ConstValue::Int(b)) => (a, b),
+            (ConstValue::Bool(a), ConstValue::Bool(b)) => {
+                return match op {
+                    BinOp::Eq => Ok(ConstValue::Bool(a == b)),
+                    BinOp::Ne => Ok(ConstValue::Bool(a != b)),
+                    BinOp::BitAnd => Ok(ConstValue::Bool(a & b)),
+                    BinOp::BitOr => Ok(ConstValue::Bool(a | b)),
+                    BinOp::BitXor => Ok(ConstValue::Bool(a ^ b)),
+                    _ => fail(format!("Cannot apply {} to Bool values at line {}", print_expr(expr), span.line)),
+                };
+            }
+            _ => {
+                return fail(format!("Mismatched types in constant expression {} ({} and {}) at line {}",
+                    print_expr(expr), l.type_name(), r.type_name(), span.line));
+            }
+        };
+
+        let overflow = || anyhow!("Integer overflow in constant expression {} at line {}", print_expr(expr), span.line);
+        let value = match op {
+            BinOp::Add => a.checked_add(b).ok_or_else(overflow)?,
+            BinOp::Sub => a.checked_sub(b).ok_or_else(overflow)?,
+            BinOp::Mul => a.checked_mul(b).ok_or_else(overflow)?,
+            BinOp::Div | BinOp::Mod if b == 0 => {
+                return fail(format!("Division by zero in constant expression {} at line {}", print_expr(expr), span.line));
+            }
+            BinOp::Div => a.checked_div(b).ok_or_else(overflow)?,
+            BinOp::Mod => a.checked_rem(b).ok_or_else(overflow)?,
+            BinOp::Shl | BinOp::Shr if !(0..64).contains(&b) => {
+                return fail(format!("Shift amount {} out of range in {} at line {}", b, print_expr(expr), span.line));
+            }
+            BinOp::Shl => a << b,
+            // Logical shift, matching the generated code
+            BinOp::Shr => ((a as u64) >> b) as i64,
+            BinOp::BitAnd => a & b,
+            BinOp::BitOr => a | b,
+            BinOp::BitXor => a ^ b,
+            BinOp::Lt => return Ok(ConstValue::Bool(a < b)),
+            BinOp::Le => return Ok(ConstValue::Bool(a <= b)),
+            BinOp::Gt => return Ok(ConstValue::Bool(a > b)),
+            BinOp::Ge => return Ok(ConstValue::Bool(a >= b)),
+            BinOp::Eq => return Ok(ConstValue::Bool(a == b)),
+            BinOp::Ne => return Ok(ConstValue::Bool(a != b)),
+            BinOp::And | BinOp::Or => unreachable!("handled above"),
+        };
+        Ok(ConstValue::Int(value))
+    }
+
+    fn call(&mut self, name: &str, func: &ConstFunc, args: Vec<ConstValue>, span: Span) -> Eval<ConstValue> {
+        if args.len() != func.params.len() {
+            return fail(format!("Wrong number of arguments to {} at line {}: expected {}, got {}",
+                name, span.line, func.params.len(), args.len()));
+        }
+        if self.depth >= MAX_DEPTH {
+            return fail(format!("const func calls nested deeper than {} in {} at line {}", MAX_DEPTH, name, span.line));
+        }
+        let saved_frame = self.frame;
+        self.frame = self.scopes.len();
+        self.scopes.push(func.params.iter().map(|p| p.name.clone()).zip(args).collect());
+        self.depth += 1;
+        let result = self.exec_block(&func.body);
+        self.depth -= 1;
+        self.scopes.truncate(self.frame);
+        self.frame = saved_frame;
+        match result {
+            Ok(v) | Err(Unwind::Return(v)) => Ok(v),
+            Err(Unwind::Break) | Err(Unwind::Continue) => fail(format!("`break` or `continue` outside of a loop in {}", name)),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn scoped(&mut self, block: &Block) -> Eval<ConstValue> {
+        self.scopes.push(HashMap::new());
+        let result = self.exec_block(block);
+        self.scopes.pop();
+        result
+    }
+
+    fn exec_block(&mut self, block: &Block) -> Eval<ConstValue> {
+        let mut last = ConstValue::Int(0);
+        for stmt in &block.stmts {
+            last = self.exec_stmt(stmt)?;
+        }
+        Ok(last)
+    }
+
+    fn exec_stmt(&mut self, stmt: &Stmt) -> Eval<ConstValue> {
+        let unit = ConstValue::Int(0);
+        match stmt {
+            Stmt::Let { name, init, span, .. } => {
+                self.step(*span)?;
+                let v = match init {
+                    Some(e) => self.eval_expr(e)?,
+                    None => unit,
+                };
+                self.scopes.last_mut().expect("const func scope").insert(name.clone(), v);
+                Ok(unit)
+            }
+            Stmt::Assign(target, value, span) => {
+                self.step(*span)?;
+                let v = self.eval_expr(value)?;
+                let Expr::Ident(name, _) = target else {
+                    return fail(format!("Only local variables can be assigned in a const func (line {})", span.line));
+                };
+                let frame = self.frame;
+                match self.scopes[frame..].iter_mut().rev().find_map(|s| s.get_mut(name)) {
+                    Some(slot) => *slot = v,
+                    None => return fail(format!("Cannot assign to {} in a const func at line {}", name, span.line)),
+                }
+                Ok(unit)
+            }
+            Stmt::Return(expr, _) => {
+                let v = match expr {
+                    Some(e) => self.eval_expr(e)?,
+                    None => unit,
+                };
+                Err(Unwind::Return(v))
+            }
+            Stmt::If(cond, then_block, else_block, _) => {
+                if self.bool(cond)? {
+                    self.scoped(then_block)?;
+                } else if let Some(eb) = else_block {
+                    self.scoped(eb)?;
+                }
+                Ok(unit)
+            }
+            Stmt::While(cond, body, span) => {
+                while self.bool(cond)? {
+                    self.step(*span)?;
+                    match self.scoped(body) {
+                        Ok(_) | Err(Unwind::Continue) => {}
+                        Err(Unwind::Break) => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(unit)
+            }
+            Stmt::For(_, _, _, span) => fail(format!("for loops are not supported in const funcs yet (line {})", span.line)),
+            Stmt::Break(_) => Err(Unwind::Break),
+            Stmt::Continue(_) => Err(Unwind::Continue),
+            Stmt::Expr(expr, span) => {
+                self.step(*span)?;
+                self.eval_expr(expr)
+            }
+            Stmt::Block(block, _) => self.scoped(block),
+        }
+    }
+}
+
+impl Default for ConstEvaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Fold the constants and array lengths of a whole module
+pub fn fold_module(module: &mut Module) -> Result<()> {
+    ConstEvaluator::new().fold_decls(&mut module.decls)
+}
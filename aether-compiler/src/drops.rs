@@ -0,0 +1,848 @@
+//! Drop elaboration - destructor calls for owned locals
+//! Owned locals are dropped in reverse order when their scope ends, before
+//! `return`, `break` and `continue`, and before reassignment. Locals that may
+//! be moved or start uninitialized carry a runtime drop flag. Heap buffers
+//! (`String`, `Box`, `Vec`) are owned only where they were allocated: string
+//! literals live in static data and are never freed.
+//! Structs and enums whose fields or payloads need dropping get generated
+//! glue that drops them in declaration order, after the type's own `drop`.
+//! A heap field is freed only if every value stored into it was allocated
+//! there; fields of the type's own type are not followed, as they may be null.
+//! Moving a field out moves its whole owner, whose other fields then leak.
+
+use std::collections::{HashMap, HashSet};
+use anyhow::{bail, Result};
+use crate::ast::*;
+use crate::borrowck::ownership::Ownership;
+use crate::typechecker::{TypedDecl, TypedModule};
+
+/// How a value is destroyed
+#[derive(Debug, Clone)]
+enum Glue {
+    /// `impl Drop`: call the hoisted `drop` method
+    Call(String),
+    /// One heap buffer (`String`, `Box`)
+    Free,
+    /// Runtime vector layout `[data, len, cap]`: the data buffer, then the header
+    FreeVec,
+}
+
+impl Glue {
+    /// Frees memory rather than calling a destructor
+    fn is_heap(&self) -> bool {
+        !matches!(self, Glue::Call(_))
+    }
+}
+
+/// Builtins that return fresh heap memory
+const ALLOC_BUILTINS: &[&str] = &["__builtin_malloc", "__builtin_realloc"];
+
+/// An owned local waiting to be dropped
+#[derive(Debug, Clone)]
+struct Local {
+    name: String,
+    glue: Glue,
+    /// Set while the local holds a value, when that can change at run time
+    flag: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Scope {
+    /// Owned locals in declaration order
+    locals: Vec<Local>,
+    /// Every local declared in the scope, with its type when known
+    types: HashMap<String, Option<Type>>,
+    /// Body of a loop: `break` and `continue` leave it
+    is_loop: bool,
+}
+
+struct Elaborator<'a> {
+    own: &'a Ownership,
+    /// Variants of every enum with their payload types
+    variants: HashMap<String, Vec<(String, Vec<Type>)>>,
+    /// Heap fields (`field`) and payloads (`Variant.i`) of a type that some
+    /// value not allocated there was stored into
+    borrowed: HashSet<(String, String)>,
+    scopes: Vec<Scope>,
+    /// Names of locals moved anywhere in the current function
+    moved: HashSet<String>,
+    /// Names of locals assigned anywhere in the current function
+    assigned: HashSet<String>,
+    /// Functions whose every returned value is a fresh heap allocation
+    allocators: HashSet<String>,
+    /// Counter for drop flags and temporaries
+    next_id: usize,
+}
+
+/// Name of the function a type's `drop` method is hoisted to
+fn drop_fn(type_name: &str) -> String {
+    format!("{}__drop", type_name)
+}
+
+/// Name of the generated function that drops a type's fields or payloads
+fn glue_fn(type_name: &str) -> String {
+    format!("{}__glue", type_name)
+}
+
+impl Elaborator<'_> {
+    fn glue(&self, ty: &Type) -> Option<Glue> {
+        self.glue_in(ty, &mut Vec::new())
+    }
+
+    /// Glue of `ty` inside the types in `outer`, which are not entered again
+    fn glue_in(&self, ty: &Type, outer: &mut Vec<String>) -> Option<Glue> {
+        match ty {
+            Type::Named(name) if outer.contains(name) => None,
+            Type::Named(name) if self.structural(name, outer) => Some(Glue::Call(glue_fn(name))),
+            Type::Named(name) | Type::Generic(name, _) if self.own.has_drop(name) => Some(Glue::Call(drop_fn(name))),
+            Type::Named(name) | Type::Generic(name, _) => match name.as_str() {
+                "String" | "Box" => Some(Glue::Free),
+                "Vec" | "List" => Some(Glue::FreeVec),
+                _ => self.own.alias(name).and_then(|t| self.glue_in(t, outer)),
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether some field or payload of a struct or enum needs dropping
+    fn structural(&self, name: &str, outer: &mut Vec<String>) -> bool {
+        outer.push(name.to_string());
+        let found = self.members(name).iter().any(|(_, ty)| self.glue_in(ty, outer).is_some());
+        outer.pop();
+        found
+    }
+
+    /// Fields of a struct, or payloads of an enum keyed `Variant.i`
+    fn members(&self, name: &str) -> Vec<(String, Type)> {
+        if let Some(fields) = self.own.fields(name) {
+            return fields.to_vec();
+        }
+        self.variants.get(name).into_iter().flatten()
+            .flat_map(|(variant, payloads)| payloads.iter().enumerate().map(move |(i, ty)| (format!("{}.{}", variant, i), ty.clone())))
+            .collect()
+    }
+
+    /// Note a value stored into a heap field or payload it was not allocated for
+    fn store(&mut self, type_name: &str, member: &str, value: &Expr) {
+        let ty = self.members(type_name).into_iter().find(|(m, _)| m == member).map(|(_, ty)| ty);
+        let heap = ty.and_then(|ty| self.glue(&ty)).is_some_and(|g| g.is_heap());
+        if heap && !self.allocates(value, &|n: &str| self.owns_heap(n)) {
+            self.borrowed.insert((type_name.to_string(), member.to_string()));
+        }
+    }
+
+    /// Body of a type's glue, which drops its parameter `value`
+    fn glue_body(&self, name: &str, span: Span) -> Vec<Stmt> {
+        let value = || Expr::Ident("value".to_string(), span);
+        let mut stmts = Vec::new();
+        if self.own.has_drop(name) {
+            stmts.push(Stmt::Expr(call(&drop_fn(name), value(), span), span));
+        }
+        for (member, ty) in self.members(name) {
+            let Some(glue) = self.glue_in(&ty, &mut vec![name.to_string()]) else { continue };
+            if glue.is_heap() && self.borrowed.contains(&(name.to_string(), member.clone())) {
+                continue;
+            }
+            match member.split_once('.') {
+                // One `match` per call: each arm body is a single expression
+                Some((variant, index)) => {
+                    let count = self.variants[name].iter().find(|(v, _)| v == variant).map_or(0, |(_, p)| p.len());
+                    let patterns: Vec<Pattern> = (0..count)
+                        .map(|i| if i.to_string() == index { Pattern::Ident("payload".to_string()) } else { Pattern::Wildcard })
+                        .collect();
+                    for drop in drop_calls(&glue, Expr::Ident("payload".to_string(), span), span) {
+                        let arms = vec![
+                            MatchArm { pattern: Pattern::Enum(name.to_string(), variant.to_string(), patterns.clone()), guard: None, body: drop },
+                            MatchArm { pattern: Pattern::Wildcard, guard: None, body: Expr::Int(0, span) },
+                        ];
+                        stmts.push(Stmt::Expr(Expr::Match(Box::new(value()), arms, span), span));
+                    }
+                }
+                None => {
+                    let field = Expr::Field(Box::new(value()), member, span);
+                    stmts.extend(drop_calls(&glue, field, span).into_iter().map(|c| Stmt::Expr(c, span)));
+                }
+            }
+        }
+        stmts
+    }
+
+    fn fresh(&mut self, what: &str) -> String {
+        self.next_id += 1;
+        format!("{}.{}", what, self.next_id)
+    }
+
+    fn type_of(&self, expr: &Expr) -> Option<Type> {
+        self.own.type_of(expr, &|name: &str| self.scopes.iter().rev().find_map(|s| s.types.get(name)).cloned())
+    }
+
+    /// Whether `expr` yields heap memory its new owner must free: a fresh
+    /// allocation, a call to an allocator function, or a value moved out of a
+    /// local for which `owned` holds
+    fn allocates(&self, expr: &Expr, owned: &impl Fn(&str) -> bool) -> bool {
+        let tail = |block: &Block| matches!(block.stmts.last(), Some(Stmt::Expr(e, _)) if self.allocates(e, owned));
+        match expr {
+            Expr::Call(callee, _, _) => match callee.as_ref() {
+                Expr::Ident(name, _) => ALLOC_BUILTINS.contains(&name.as_str()) || self.allocators.contains(name),
+                Expr::Path(path, _) => matches!(path.as_slice(), [ty, new] if ty == "Box" && new == "new"),
+                _ => false,
+            },
+            Expr::Ident(name, _) => owned(name),
+            Expr::If(_, then_block, Some(else_block), _) => tail(then_block) && tail(else_block),
+            Expr::Unsafe(block, _) => tail(block),
+            _ => false,
+        }
+    }
+
+    /// Whether `name` is a local that owns a heap buffer
+    fn owns_heap(&self, name: &str) -> bool {
+        self.local(name).is_some_and(|l| l.glue.is_heap())
+    }
+
+    /// Whether every value a function body returns is a fresh allocation,
+    /// following locals through their initializers and assignments
+    fn returns_allocation(&self, body: &Block) -> bool {
+        fn walk(e: &Elaborator, block: &Block, fresh: &mut HashMap<String, bool>, all: &mut bool, is_body: bool) {
+            let last = block.stmts.len().saturating_sub(1);
+            for (i, stmt) in block.stmts.iter().enumerate() {
+                let owned = |fresh: &HashMap<String, bool>, value: &Expr| {
+                    e.allocates(value, &|n: &str| fresh.get(n).copied().unwrap_or(false))
+                };
+                match stmt {
+                    Stmt::Let { name, init, .. } => {
+                        let value = init.as_ref().is_some_and(|v| owned(fresh, v));
+                        fresh.insert(name.clone(), value);
+                    }
+                    Stmt::Assign(Expr::Ident(name, _), value, _) => {
+                        let value = owned(fresh, value);
+                        if let Some(known) = fresh.get_mut(name) {
+                            *known &= value;
+                        }
+                    }
+                    Stmt::Return(Some(value), _) => *all &= owned(fresh, value),
+                    Stmt::Expr(value, _) if is_body && i == last => *all &= owned(fresh, value),
+                    Stmt::If(_, then_block, else_block, _) => {
+                        walk(e, then_block, fresh, all, false);
+                        if let Some(b) = else_block {
+                            walk(e, b, fresh, all, false);
+                        }
+                    }
+                    Stmt::While(_, body, _) | Stmt::For(_, _, body, _) | Stmt::Block(body, _) => walk(e, body, fresh, all, false),
+                    _ => {}
+                }
+            }
+        }
+        let mut all = true;
+        walk(self, body, &mut HashMap::new(), &mut all, true);
+        all && body.stmts.iter().any(|s| matches!(s, Stmt::Return(Some(_), _) | Stmt::Expr(..)))
+    }
+
+    /// Innermost owned local called `name`, unless a plain local shadows it
+    fn local(&self, name: &str) -> Option<Local> {
+        let scope = self.scopes.iter().rev().find(|s| s.types.contains_key(name))?;
+        scope.locals.iter().rev().find(|l| l.name == name).cloned()
+    }
+
+    // ========== Functions ==========
+
+    /// `owns_params` is false for hoisted `drop` methods, whose receiver is
+    /// being destroyed already
+    fn func(&mut self, params: &[Param], body: &mut Block, owns_params: bool) {
+        self.moved.clear();
+        moved_in_block(self.own, body, &mut self.moved);
+        self.assigned.clear();
+        assigned_in_block(body, &mut self.assigned);
+        let mut scope = Scope::default();
+        let mut flags = Vec::new();
+        for param in params {
+            scope.types.insert(param.name.clone(), Some(param.ty.clone()));
+            // A heap parameter may be a string literal: only its allocator frees it
+            let Some(glue) = self.glue(&param.ty).filter(|g| owns_params && !g.is_heap()) else { continue };
+            let flag = self.moved.contains(&param.name).then(|| self.fresh(&format!("{}.drop", param.name)));
+            if let Some(flag) = &flag {
+                flags.push(flag_let(flag, 1, param.span));
+            }
+            scope.locals.push(Local { name: param.name.clone(), glue, flag });
+        }
+        self.block(body, true, scope);
+        body.stmts.splice(0..0, flags);
+    }
+
+    // ========== Blocks and statements ==========
+
+    /// `used` is set when the block's final expression is its value
+    fn block(&mut self, block: &mut Block, used: bool, scope: Scope) {
+        self.scopes.push(scope);
+        let stmts = std::mem::take(&mut block.stmts);
+        let last = stmts.len().saturating_sub(1);
+        let mut out = Vec::new();
+        for (i, stmt) in stmts.into_iter().enumerate() {
+            self.stmt(stmt, used && i == last, &mut out);
+        }
+        let scope = self.scopes.pop().expect("scope pushed above");
+        let exits = matches!(out.last(), Some(Stmt::Return(..) | Stmt::Break(_) | Stmt::Continue(_)));
+        if !exits && !scope.locals.is_empty() {
+            // Keep the block's value in a temporary while its locals are dropped
+            let tail = match out.last() {
+                Some(Stmt::Expr(..)) if used => out.pop(),
+                _ => None,
+            };
+            let tail = tail.map(|stmt| match stmt {
+                Stmt::Expr(value, span) => {
+                    let temp = self.fresh("tail");
+                    out.push(temp_let(&temp, value, span));
+                    Stmt::Expr(Expr::Ident(temp, span), span)
+                }
+                other => other,
+            });
+            for local in scope.locals.iter().rev() {
+                out.extend(drop_local(local, block.span));
+            }
+            out.extend(tail);
+        }
+        block.stmts = out;
+    }
+
+    fn stmt(&mut self, stmt: Stmt, tail: bool, out: &mut Vec<Stmt>) {
+        match stmt {
+            Stmt::Let { name, ty, mut init, mutable, span } => {
+                let fresh = init.as_ref().is_some_and(|e| self.allocates(e, &|n: &str| self.owns_heap(n)));
+                let mut moves = Vec::new();
+                if let Some(init) = &mut init {
+                    self.expr(init, true);
+                    let copy = ty.as_ref().is_some_and(|t| self.own.is_copy(t));
+                    if copy { moved_by(self.own, init, &mut moves) } else { operand(self.own, init, &mut moves) }
+                }
+                self.clear_flags(&moves, span, out);
+                let decl_ty = ty.clone().or_else(|| init.as_ref().and_then(|e| self.type_of(e)));
+                // The shadowed value can no longer be named, so it is dropped now
+                let shadowed = self.scopes.last().and_then(|s| s.locals.iter().position(|l| l.name == name));
+                if let Some(index) = shadowed {
+                    let old = self.scopes.last_mut().expect("in a scope").locals.remove(index);
+                    let init_value = init.take().map(|value| {
+                        let temp = self.fresh("init");
+                        out.push(temp_let(&temp, value, span));
+                        Expr::Ident(temp, span)
+                    });
+                    out.extend(drop_local(&old, span));
+                    init = init_value;
+                }
+                let initialized = init.is_some();
+                out.push(Stmt::Let { name: name.clone(), ty, init, mutable, span });
+                let glue = decl_ty.as_ref().and_then(|t| self.glue(t));
+                let scope = self.scopes.last_mut().expect("in a scope");
+                scope.types.insert(name.clone(), decl_ty);
+                if let Some(glue) = glue {
+                    // A heap value that was not allocated here (a literal, a
+                    // parameter) is borrowed until an assignment gives it one
+                    let reassigned = glue.is_heap() && self.assigned.contains(&name);
+                    let owned = initialized && (fresh || !glue.is_heap());
+                    if owned || !initialized || reassigned {
+                        let flag = (!owned || reassigned || self.moved.contains(&name)).then(|| self.fresh(&format!("{}.drop", name)));
+                        if let Some(flag) = &flag {
+                            out.push(flag_let(flag, owned as i64, span));
+                        }
+                        self.scopes.last_mut().expect("in a scope").locals.push(Local { name, glue, flag });
+                    }
+                }
+            }
+            Stmt::Assign(target, mut value, span) => {
+                let fresh = self.allocates(&value, &|n: &str| self.owns_heap(n));
+                if let Expr::Field(obj, field, _) = &target {
+                    if let Some((name, _)) = self.type_of(obj).and_then(|t| self.own.named(&t)) {
+                        self.store(&name, field, &value);
+                    }
+                }
+                self.expr(&mut value, true);
+                let mut moves = Vec::new();
+                operand(self.own, &value, &mut moves);
+                self.clear_flags(&moves, span, out);
+                let owned = match &target {
+                    Expr::Ident(name, _) => self.local(name),
+                    _ => None,
+                };
+                match owned {
+                    // The old value is dropped once the new one is computed
+                    Some(local) => {
+                        let temp = self.fresh("value");
+                        out.push(temp_let(&temp, value, span));
+                        out.extend(drop_local(&local, span));
+                        out.push(Stmt::Assign(target, Expr::Ident(temp, span), span));
+                        if let Some(flag) = &local.flag {
+                            out.push(set_flag(flag, (fresh || !local.glue.is_heap()) as i64, span));
+                        }
+                    }
+                    None => out.push(Stmt::Assign(target, value, span)),
+                }
+            }
+            Stmt::Return(value, span) => {
+                let value = value.map(|mut value| {
+                    self.expr(&mut value, true);
+                    let mut moves = Vec::new();
+                    operand(self.own, &value, &mut moves);
+                    self.clear_flags(&moves, span, out);
+                    value
+                });
+                let drops = self.exit_drops(self.scopes.len(), span);
+                if drops.is_empty() {
+                    out.push(Stmt::Return(value, span));
+                    return;
+                }
+                let value = value.map(|value| {
+                    let temp = self.fresh("ret");
+                    out.push(temp_let(&temp, value, span));
+                    Expr::Ident(temp, span)
+                });
+                out.extend(drops);
+                out.push(Stmt::Return(value, span));
+            }
+            Stmt::Break(span) | Stmt::Continue(span) => {
+                let depth = self.scopes.iter().rev().position(|s| s.is_loop).map_or(0, |i| i + 1);
+                out.extend(self.exit_drops(depth, span));
+                out.push(stmt);
+            }
+            Stmt::If(mut cond, mut then_block, mut else_block, span) => {
+                self.expr(&mut cond, true);
+                self.clear_moves_in(&cond, span, out);
+                self.block(&mut then_block, false, Scope::default());
+                if let Some(b) = &mut else_block {
+                    self.block(b, false, Scope::default());
+                }
+                out.push(Stmt::If(cond, then_block, else_block, span));
+            }
+            Stmt::While(mut cond, mut body, span) => {
+                self.expr(&mut cond, true);
+                self.clear_moves_in(&cond, span, out);
+                self.block(&mut body, false, Scope { is_loop: true, ..Scope::default() });
+                out.push(Stmt::While(cond, body, span));
+            }
+            Stmt::For(var, mut iter, mut body, span) => {
+                self.expr(&mut iter, true);
+                let mut moves = Vec::new();
+                operand(self.own, &iter, &mut moves);
+                self.clear_flags(&moves, span, out);
+                let mut scope = Scope { is_loop: true, ..Scope::default() };
+                scope.types.insert(var.clone(), None);
+                self.block(&mut body, false, scope);
+                out.push(Stmt::For(var, iter, body, span));
+            }
+            Stmt::Block(mut block, span) => {
+                self.block(&mut block, false, Scope::default());
+                out.push(Stmt::Block(block, span));
+            }
+            Stmt::Expr(mut expr, span) => {
+                self.expr(&mut expr, tail);
+                let mut moves = Vec::new();
+                if tail { operand(self.own, &expr, &mut moves) } else { moved_by(self.own, &expr, &mut moves) }
+                self.clear_flags(&moves, span, out);
+                out.push(Stmt::Expr(expr, span));
+            }
+        }
+    }
+
+    /// Elaborate the blocks nested in an expression
+    fn expr(&mut self, expr: &mut Expr, used: bool) {
+        match expr {
+            Expr::If(cond, then_block, else_block, _) => {
+                self.expr(cond, true);
+                self.block(then_block, used, Scope::default());
+                if let Some(b) = else_block {
+                    self.block(b, used, Scope::default());
+                }
+            }
+            Expr::Unsafe(block, _) | Expr::Comptime(block, _) => self.block(block, used, Scope::default()),
+            Expr::Binary(_, left, right, _) | Expr::Index(left, right, _) => {
+                self.expr(left, true);
+                self.expr(right, true);
+            }
+            Expr::Unary(_, inner, _) | Expr::Field(inner, _, _) => self.expr(inner, true),
+            Expr::Call(func, args, _) | Expr::Spawn(func, args, _) => {
+                if let Expr::Path(path, _) = func.as_ref() {
+                    if let [ty, variant] = path.as_slice() {
+                        for (i, arg) in args.iter().enumerate() {
+                            self.store(ty, &format!("{}.{}", variant, i), arg);
+                        }
+                    }
+                }
+                self.expr(func, true);
+                args.iter_mut().for_each(|a| self.expr(a, true));
+            }
+            Expr::MethodCall(obj, _, args, _) => {
+                self.expr(obj, true);
+                args.iter_mut().for_each(|a| self.expr(a, true));
+            }
+            Expr::Array(elems, _) => elems.iter_mut().for_each(|e| self.expr(e, true)),
+            Expr::Struct(name, fields, _) => {
+                for (field, value) in fields.iter() {
+                    self.store(name, field, value);
+                }
+                fields.iter_mut().for_each(|(_, e)| self.expr(e, true));
+            }
+            Expr::Match(scrutinee, arms, _) => {
+                self.expr(scrutinee, true);
+                arms.iter_mut().for_each(|arm| self.expr(&mut arm.body, used));
+            }
+            _ => {}
+        }
+    }
+
+    // ========== Drops ==========
+
+    /// Clear the drop flags of owned locals that `names` moves out of; a
+    /// field path `a.b` moves `a` when the field is not `Copy`
+    fn clear_flags(&self, names: &[String], span: Span, out: &mut Vec<Stmt>) {
+        for name in names {
+            let mut path = name.split('.');
+            let root = path.next().unwrap_or_default();
+            if name.contains('.') {
+                let mut ty = self.type_of(&Expr::Ident(root.to_string(), span));
+                for field in path {
+                    ty = ty.and_then(|t| self.own.named(&t)).and_then(|(n, _)| self.own.field(&n, field).cloned());
+                }
+                if ty.is_none_or(|t| self.own.is_copy(&t)) {
+                    continue;
+                }
+            }
+            if let Some(flag) = self.local(root).and_then(|l| l.flag) {
+                out.push(set_flag(&flag, 0, span));
+            }
+        }
+    }
+
+    fn clear_moves_in(&self, expr: &Expr, span: Span, out: &mut Vec<Stmt>) {
+        let mut moves = Vec::new();
+        moved_by(self.own, expr, &mut moves);
+        self.clear_flags(&moves, span, out);
+    }
+
+    /// Drops for the owned locals of the innermost `depth` scopes, innermost
+    /// first. Locals hidden by an inner declaration cannot be named and leak.
+    fn exit_drops(&self, depth: usize, span: Span) -> Vec<Stmt> {
+        let mut drops = Vec::new();
+        let mut hidden: HashSet<&str> = HashSet::new();
+        for scope in self.scopes.iter().rev().take(depth) {
+            for local in scope.locals.iter().rev() {
+                if !hidden.contains(local.name.as_str()) {
+                    drops.extend(drop_local(local, span));
+                }
+            }
+            hidden.extend(scope.types.keys().map(String::as_str));
+        }
+        drops
+    }
+}
+
+// ========== Moves ==========
+
+/// Locals an expression evaluated for its value moves out of, with the
+/// path of a field moved out of a local
+fn operand(own: &Ownership, expr: &Expr, out: &mut Vec<String>) {
+    match expr {
+        Expr::Ident(name, _) => out.push(name.clone()),
+        Expr::Field(..) => match field_path(expr) {
+            Some(path) => out.push(path),
+            None => moved_by(own, expr, out),
+        },
+        other => moved_by(own, other, out),
+    }
+}
+
+/// `a.b.c` for a field of a local
+fn field_path(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Ident(name, _) => Some(name.clone()),
+        Expr::Field(inner, field, _) => Some(format!("{}.{}", field_path(inner)?, field)),
+        _ => None,
+    }
+}
+
+/// The local a move path starts from
+fn root(path: String) -> String {
+    match path.split_once('.') {
+        Some((local, _)) => local.to_string(),
+        None => path,
+    }
+}
+
+/// Locals moved by an expression, following the borrow checker: arguments
+/// move into parameters of known non-`Copy` types, and array and struct
+/// literals take their elements. Nested blocks track their own moves.
+fn moved_by(own: &Ownership, expr: &Expr, out: &mut Vec<String>) {
+    match expr {
+        Expr::Call(func, args, _) | Expr::Spawn(func, args, _) => {
+            let params = match func.as_ref() {
+                Expr::Ident(name, _) => own.func(name).map(|sig| sig.params.clone()),
+                _ => None,
+            };
+            for (i, arg) in args.iter().enumerate() {
+                let by_value = params.as_ref().and_then(|p| p.get(i)).is_some_and(|t| !own.is_copy(t));
+                if by_value { operand(own, arg, out) } else { moved_by(own, arg, out) }
+            }
+        }
+        Expr::MethodCall(obj, _, args, _) => {
+            moved_by(own, obj, out);
+            args.iter().for_each(|a| operand(own, a, out));
+        }
+        Expr::Array(elems, _) => elems.iter().for_each(|e| operand(own, e, out)),
+        Expr::Struct(_, fields, _) => fields.iter().for_each(|(_, e)| operand(own, e, out)),
+        Expr::Binary(_, left, right, _) | Expr::Index(left, right, _) => {
+            moved_by(own, left, out);
+            moved_by(own, right, out);
+        }
+        Expr::Unary(_, inner, _) | Expr::Field(inner, _, _) => moved_by(own, inner, out),
+        Expr::If(cond, _, _, _) => moved_by(own, cond, out),
+        Expr::Match(scrutinee, _, _) => operand(own, scrutinee, out),
+        _ => {}
+    }
+}
+
+/// Every local a block may move, in any nested block: these need drop flags
+fn moved_in_block(own: &Ownership, block: &Block, out: &mut HashSet<String>) {
+    let last = block.stmts.len().saturating_sub(1);
+    for (i, stmt) in block.stmts.iter().enumerate() {
+        let mut moves = Vec::new();
+        let mut exprs: Vec<&Expr> = Vec::new();
+        match stmt {
+            Stmt::Let { init: Some(e), .. } | Stmt::Assign(_, e, _) | Stmt::Return(Some(e), _) | Stmt::For(_, e, _, _) => {
+                operand(own, e, &mut moves);
+                exprs.push(e);
+            }
+            Stmt::Expr(e, _) => {
+                if i == last { operand(own, e, &mut moves) } else { moved_by(own, e, &mut moves) }
+                exprs.push(e);
+            }
+            Stmt::If(cond, then_block, else_block, _) => {
+                moved_by(own, cond, &mut moves);
+                exprs.push(cond);
+                moved_in_block(own, then_block, out);
+                if let Some(b) = else_block {
+                    moved_in_block(own, b, out);
+                }
+            }
+            Stmt::While(cond, body, _) => {
+                moved_by(own, cond, &mut moves);
+                exprs.push(cond);
+                moved_in_block(own, body, out);
+            }
+            _ => {}
+        }
+        if let Stmt::For(_, _, body, _) | Stmt::Block(body, _) = stmt {
+            moved_in_block(own, body, out);
+        }
+        out.extend(moves.into_iter().map(root));
+        for expr in exprs {
+            moved_in_expr(own, expr, out);
+        }
+    }
+}
+
+/// Every local a block assigns to, in any nested block
+fn assigned_in_block(block: &Block, out: &mut HashSet<String>) {
+    fn expr(e: &Expr, out: &mut HashSet<String>) {
+        match e {
+            Expr::If(_, then_block, else_block, _) => {
+                assigned_in_block(then_block, out);
+                if let Some(b) = else_block {
+                    assigned_in_block(b, out);
+                }
+            }
+            Expr::Unsafe(block, _) | Expr::Comptime(block, _) => assigned_in_block(block, out),
+            Expr::Match(_, arms, _) => arms.iter().for_each(|arm| expr(&arm.body, out)),
+            _ => {}
+        }
+    }
+    for stmt in &block.stmts {
+        match stmt {
+            Stmt::Assign(Expr::Ident(name, _), value, _) => {
+                out.insert(name.clone());
+                expr(value, out);
+            }
+            Stmt::Let { init: Some(e), .. } | Stmt::Assign(_, e, _) | Stmt::Expr(e, _) | Stmt::Return(Some(e), _) => expr(e, out),
+            Stmt::If(_, then_block, else_block, _) => {
+                assigned_in_block(then_block, out);
+                if let Some(b) = else_block {
+                    assigned_in_block(b, out);
+                }
+            }
+            Stmt::While(_, body, _) | Stmt::For(_, _, body, _) | Stmt::Block(body, _) => assigned_in_block(body, out),
+            _ => {}
+        }
+    }
+}
+
+/// Moves inside the blocks nested in an expression
+fn moved_in_expr(own: &Ownership, expr: &Expr, out: &mut HashSet<String>) {
+    match expr {
+        Expr::If(cond, then_block, else_block, _) => {
+            moved_in_expr(own, cond, out);
+            moved_in_block(own, then_block, out);
+            if let Some(b) = else_block {
+                moved_in_block(own, b, out);
+            }
+        }
+        Expr::Unsafe(block, _) | Expr::Comptime(block, _) => moved_in_block(own, block, out),
+        Expr::Binary(_, left, right, _) | Expr::Index(left, right, _) => {
+            moved_in_expr(own, left, out);
+            moved_in_expr(own, right, out);
+        }
+        Expr::Unary(_, inner, _) | Expr::Field(inner, _, _) => moved_in_expr(own, inner, out),
+        Expr::Call(func, args, _) | Expr::Spawn(func, args, _) => {
+            moved_in_expr(own, func, out);
+            args.iter().for_each(|a| moved_in_expr(own, a, out));
+        }
+        Expr::MethodCall(obj, _, args, _) => {
+            moved_in_expr(own, obj, out);
+            args.iter().for_each(|a| moved_in_expr(own, a, out));
+        }
+        Expr::Array(elems, _) => elems.iter().for_each(|e| moved_in_expr(own, e, out)),
+        Expr::Struct(_, fields, _) => fields.iter().for_each(|(_, e)| moved_in_expr(own, e, out)),
+        Expr::Match(scrutinee, arms, _) => {
+            moved_in_expr(own, scrutinee, out);
+            for arm in arms {
+                let mut moves = Vec::new();
+                operand(own, &arm.body, &mut moves);
+                out.extend(moves.into_iter().map(root));
+                moved_in_expr(own, &arm.body, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+// ========== Generated statements ==========
+
+fn call(name: &str, arg: Expr, span: Span) -> Expr {
+    Expr::Call(Box::new(Expr::Ident(name.to_string(), span)), vec![arg], span)
+}
+
+fn temp_let(name: &str, value: Expr, span: Span) -> Stmt {
+    Stmt::Let { name: name.to_string(), ty: None, init: Some(value), mutable: false, span }
+}
+
+fn flag_let(flag: &str, value: i64, span: Span) -> Stmt {
+    Stmt::Let { name: flag.to_string(), ty: Some(Type::Named("Int".into())), init: Some(Expr::Int(value, span)), mutable: true, span }
+}
+
+fn set_flag(flag: &str, value: i64, span: Span) -> Stmt {
+    Stmt::Assign(Expr::Ident(flag.to_string(), span), Expr::Int(value, span), span)
+}
+
+/// Calls that destroy `value`
+fn drop_calls(glue: &Glue, value: Expr, span: Span) -> Vec<Expr> {
+    match glue {
+        Glue::Call(func) => vec![call(func, value, span)],
+        Glue::Free => vec![call("__builtin_free", value, span)],
+        Glue::FreeVec => vec![
+            call("__builtin_free", call("__builtin_load64", value.clone(), span), span),
+            call("__builtin_free", value, span),
+        ],
+    }
+}
+
+/// Destroy a local, if its drop flag says it holds a value
+fn drop_local(local: &Local, span: Span) -> Vec<Stmt> {
+    let value = Expr::Ident(local.name.clone(), span);
+    let stmts: Vec<Stmt> = drop_calls(&local.glue, value, span).into_iter().map(|c| Stmt::Expr(c, span)).collect();
+    match &local.flag {
+        Some(flag) => vec![Stmt::If(Expr::Ident(flag.clone(), span), Block { stmts, span }, None, span)],
+        None => stmts,
+    }
+}
+
+// ========== Module ==========
+
+/// Hoist `Drop` impls to functions and insert drops into every function body
+pub fn elaborate(module: &mut TypedModule) -> Result<()> {
+    let mut hoisted = Vec::new();
+    for typed_decl in &module.decls {
+        let Decl::Impl { trait_name: Some(trait_name), type_name, methods, span, .. } = &typed_decl.decl else { continue };
+        if trait_name != "Drop" {
+            continue;
+        }
+        match methods.as_slice() {
+            [Decl::Func { name, params, ret: None, body, .. }] if name == "drop" && params.len() == 1 && params[0].name == "self" => {
+                let params = vec![Param { ty: Type::Named(type_name.clone()), ..params[0].clone() }];
+                hoisted.push(TypedDecl {
+                    ty: Type::Func(vec![Type::Named(type_name.clone())], Box::new(None)),
+                    decl: Decl::Func {
+                        name: drop_fn(type_name),
+                        generics: Vec::new(),
+                        params,
+                        ret: None,
+                        body: body.clone(),
+                        public: false,
+                        constant: false,
+                        is_unsafe: false,
+                        attrs: Vec::new(),
+                        span: *span,
+                    },
+                });
+            }
+            _ => bail!("impl Drop for {} must define exactly one method, `func drop(&mut self)`, at line {}", type_name, span.line),
+        }
+    }
+    let drop_fns: HashSet<String> = hoisted.iter().filter_map(|d| d.decl.name().map(String::from)).collect();
+    module.decls.extend(hoisted);
+
+    let own = Ownership::from_module(module);
+    let variants = module.decls.iter().filter_map(|d| match &d.decl {
+        Decl::Enum { name, variants, .. } => Some((name.clone(), variants.iter().map(|v| (v.name.clone(), v.fields.clone())).collect())),
+        _ => None,
+    }).collect();
+    let mut elaborator = Elaborator {
+        own: &own,
+        variants,
+        borrowed: HashSet::new(),
+        scopes: Vec::new(),
+        moved: HashSet::new(),
+        assigned: HashSet::new(),
+        allocators: HashSet::new(),
+        next_id: 0,
+    };
+    // Functions returning fresh heap buffers, to a fixed point over calls
+    loop {
+        let found: Vec<String> = module.decls.iter().filter_map(|d| match &d.decl {
+            Decl::Func { name, ret: Some(ret), body, .. }
+                if !elaborator.allocators.contains(name)
+                    && elaborator.glue(ret).is_some_and(|g| g.is_heap())
+                    && elaborator.returns_allocation(body) => Some(name.clone()),
+            _ => None,
+        }).collect();
+        if found.is_empty() {
+            break;
+        }
+        elaborator.allocators.extend(found);
+    }
+    for typed_decl in module.decls.iter_mut() {
+        if let Decl::Func { name, params, body, .. } = &mut typed_decl.decl {
+            let owns_params = !drop_fns.contains(name.as_str());
+            elaborator.func(params, body, owns_params);
+        }
+    }
+    // Glue is generated last, once every store into a heap field is known
+    let mut glue = Vec::new();
+    for typed_decl in &module.decls {
+        let (Decl::Struct { name, span, .. } | Decl::Enum { name, span, .. }) = &typed_decl.decl else { continue };
+        if !elaborator.structural(name, &mut Vec::new()) {
+            continue;
+        }
+        let param = Param { name: "value".to_string(), ty: Type::Named(name.clone()), default: None, comptime: false, mutable: false, span: *span };
+        glue.push(TypedDecl {
+            ty: Type::Func(vec![Type::Named(name.clone())], Box::new(None)),
+            decl: Decl::Func {
+                name: glue_fn(name),
+                generics: Vec::new(),
+                params: vec![param],
+                ret: None,
+                body: Block { stmts: elaborator.glue_body(name, *span), span: *span },
+                public: false,
+                constant: false,
+                is_unsafe: false,
+                attrs: Vec::new(),
+                span: *span,
+            },
+        });
+    }
+    module.decls.extend(glue);
+    Ok(())
+}
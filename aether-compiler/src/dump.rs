@@ -0,0 +1,124 @@
+//! Aether Compiler Dumps - Stable views of intermediate stages
+//!
+//! JSON (via serde) for tooling and diffing, plus a readable tree form.
+
+use serde::Serialize;
+use serde_json::Value;
+use anyhow::Result;
+use crate::lexer::Token;
+
+/// Output format for `--emit=tokens,ast,typed-ast`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    Json,
+    Tree,
+}
+
+/// Serialize any stage output to pretty JSON
+pub fn to_json<T: Serialize>(value: &T) -> Result<String> {
+    let mut s = serde_json::to_string_pretty(value)?;
+    s.push('\n');
+    Ok(s)
+}
+
+/// Render any stage output as an indented tree
+pub fn to_tree<T: Serialize>(value: &T) -> Result<String> {
+    let value = serde_json::to_value(value)?;
+    let mut out = String::new();
+    render(&value, 0, &mut out);
+    Ok(out)
+}
+
+/// Render a token stream as one token per line
+pub fn tokens_to_tree(tokens: &[Token]) -> String {
+    let mut out = String::new();
+    for tok in tokens {
+        let pos = format!("{}:{}", tok.line, tok.col);
+        out.push_str(&format!("{:<8} {:<12} {:?}\n", pos, format!("{:?}", tok.kind), tok.lexeme));
+    }
+    out
+}
+
+/// Dump a stage in the requested format
+pub fn dump<T: Serialize>(value: &T, format: DumpFormat) -> Result<String> {
+    match format {
+        DumpFormat::Json => to_json(value),
+        DumpFormat::Tree => to_tree(value),
+    }
+}
+
+/// Short form for values that fit on one line
+fn inline(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => Some("none".into()),
+        Value::Bool(_) | Value::Number(_) | Value::String(_) => Some(value.to_string()),
+        Value::Object(map) => {
+            // Spans print as @line:col
+            if map.len() == 2 {
+                if let (Some(line), Some(col)) = (map.get("line"), map.get("col")) {
+                    return Some(format!("@{}:{}", line, col));
+                }
+            }
+            if map.is_empty() {
+                return Some("{}".into());
+            }
+            None
+        }
+        Value::Array(items) => {
+            let parts: Option<Vec<String>> = items.iter().map(inline).collect();
+            parts.map(|p| format!("[{}]", p.join(", ")))
+        }
+    }
+}
+
+fn render(value: &Value, indent: usize, out: &mut String) {
+    let pad = "  ".repeat(indent);
+    if let Some(s) = inline(value) {
+        out.push_str(&format!("{}{}\n", pad, s));
+        return;
+    }
+    match value {
+        Value::Object(map) if map.len() == 1 => {
+            // Enum variant: Name followed by its payload
+            let (name, payload) = map.iter().next().expect("one entry");
+            match inline(payload) {
+                Some(s) => out.push_str(&format!("{}{} {}\n", pad, name, s)),
+                None => {
+                    out.push_str(&format!("{}{}\n", pad, name));
+                    render_children(payload, indent + 1, out);
+                }
+            }
+        }
+        _ => render_children(value, indent, out),
+    }
+}
+
+fn render_children(value: &Value, indent: usize, out: &mut String) {
+    let pad = "  ".repeat(indent);
+    match value {
+        Value::Object(map) => {
+            for (key, field) in map {
+                match inline(field) {
+                    Some(s) => out.push_str(&format!("{}{}: {}\n", pad, key, s)),
+                    None => {
+                        out.push_str(&format!("{}{}:\n", pad, key));
+                        render(field, indent + 1, out);
+                    }
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                match item {
+                    // Struct elements (params, fields, arms) get a bullet to keep them apart
+                    Value::Object(map) if map.len() > 1 && inline(item).is_none() => {
+                        out.push_str(&format!("{}-\n", pad));
+                        render_children(item, indent + 1, out);
+                    }
+                    _ => render(item, indent, out),
+                }
+            }
+        }
+        _ => render(value, indent, out),
+    }
+}
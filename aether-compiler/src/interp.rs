@@ -0,0 +1,1360 @@
+//! Aether Interpreter - Tree-walking execution of the typed AST
+//!
+//! Runs programs without a C toolchain and serves as a reference oracle for
+//! the LLVM backend: every value is an i64, exactly like the generated code.
+//! Structs, arrays, tagged enums and closures are heap records of 8-byte
+//! words laid out as the backends lay them out, and floats are bit patterns.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+use crate::ast::*;
+use crate::borrowck::ownership::Ownership;
+use crate::codegen::mir::{for_source, method_symbol, ForSource};
+use crate::runtime::Runtime;
+use crate::typechecker::TypedModule;
+use anyhow::{anyhow, Result};
+
+/// Initial interpreter heap; it grows on demand
+const HEAP_SIZE: usize = 1 << 20;
+
+/// Calls deeper than this are reported instead of overflowing the host stack
+const MAX_CALL_DEPTH: usize = 10_000;
+
+/// Host stack for the interpreter thread, sized for MAX_CALL_DEPTH
+pub(crate) const STACK_SIZE: usize = 512 << 20;
+
+/// Loop iterations and calls a sandboxed (compile-time) evaluation may take
+const SANDBOX_FUEL: u64 = 10_000_000;
+
+// Linux open(2) flags, as used by the runtime library
+const O_ACCMODE: i64 = 0o3;
+const O_WRONLY: i64 = 0o1;
+const O_RDWR: i64 = 0o2;
+const O_CREAT: i64 = 0o100;
+const O_TRUNC: i64 = 0o1000;
+const O_APPEND: i64 = 0o2000;
+
+/// Non-local control flow, carried on the error path so `?` unwinds it
+enum Unwind {
+    Return(i64),
+    Break,
+    Continue,
+    Exit(i64),
+    Error(anyhow::Error),
+}
+
+impl From<anyhow::Error> for Unwind {
+    fn from(e: anyhow::Error) -> Self {
+        Unwind::Error(e)
+    }
+}
+
+type Exec<T> = std::result::Result<T, Unwind>;
+
+fn fail<T>(msg: String) -> Exec<T> {
+    Err(Unwind::Error(anyhow!(msg)))
+}
+
+/// Declared type with `Self` resolved; `None` when left to inference
+fn declared(ty: &Type, self_type: Option<&str>) -> Option<Type> {
+    match (ty, self_type) {
+        (Type::Infer, _) => None,
+        (ty, Some(s)) => Some(with_self(ty, s)),
+        (ty, None) => Some(ty.clone()),
+    }
+}
+
+/// Function body and parameters, shared by all calls
+struct Function {
+    params: Vec<Param>,
+    body: Block,
+    /// Type of the impl a method belongs to
+    self_type: Option<String>,
+}
+
+/// Code a closure record runs; the record is its index followed by the captures
+enum Code {
+    /// A named function used as a value
+    Func(String),
+    Lambda {
+        params: Vec<Param>,
+        body: Rc<Expr>,
+        /// Locals copied into the record, with their types
+        captures: Vec<(String, Option<Type>)>,
+        self_type: Option<String>,
+    },
+}
+
+/// Where a variable lives: in its scope, or in a heap cell once its address is taken
+#[derive(Debug, Clone, Copy)]
+enum Slot {
+    Value(i64),
+    Cell(i64),
+}
+
+struct Local {
+    slot: Slot,
+    ty: Option<Type>,
+}
+
+/// Variables of a block, and the cells it allocated (freed when it ends)
+#[derive(Default)]
+struct Scope {
+    vars: HashMap<String, Local>,
+    cells: Vec<i64>,
+}
+
+/// Tree-walking interpreter
+pub struct Interpreter {
+    /// Heap backing every pointer the program sees
+    runtime: Runtime,
+    /// Top-level functions by name
+    funcs: HashMap<String, Rc<Function>>,
+    /// Functions declared in `extern` blocks
+    externs: Vec<String>,
+    /// Struct layouts and signatures, for field offsets and static types
+    own: Ownership,
+    /// Enum variants in declaration order (a variant's index is its tag)
+    enums: HashMap<String, Vec<Variant>>,
+    /// Constants and statics
+    globals: HashMap<String, Slot>,
+    global_types: HashMap<String, Type>,
+    /// Constants and statics not initialized yet; evaluated on first use
+    pending: HashMap<String, Rc<Expr>>,
+    /// Lexical scopes of all active calls, innermost last
+    scopes: Vec<Scope>,
+    /// First scope of the current call
+    frame: usize,
+    /// Impl type of the running method, for `Self`
+    self_type: Option<String>,
+    /// Code of every closure value made so far
+    codes: Vec<Rc<Code>>,
+    /// Code index of each lambda expression evaluated (by address, so cleared
+    /// when the statement or block being run is done)
+    lambda_sites: HashMap<*const Expr, i64>,
+    /// Closure records of functions used as values
+    fn_values: HashMap<String, i64>,
+    /// Current call depth
+    depth: usize,
+    /// Interned string literals (NUL-terminated, on the heap)
+    strings: HashMap<String, i64>,
+    /// Files opened by the program, by descriptor
+    files: HashMap<i64, File>,
+    next_fd: i64,
+    next_thread: i64,
+    /// Allow builtins that touch files, stdio or the process
+    allow_io: bool,
+    /// Remaining steps, when limited
+    fuel: Option<u64>,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        let mut runtime = Runtime::new(HEAP_SIZE);
+        // Keep address 0 unused so it can act as a null pointer
+        runtime.malloc(8);
+        Interpreter {
+            runtime,
+            funcs: HashMap::new(),
+            externs: Vec::new(),
+            own: Ownership::default(),
+            enums: HashMap::new(),
+            globals: HashMap::new(),
+            global_types: HashMap::new(),
+            pending: HashMap::new(),
+            scopes: Vec::new(),
+            frame: 0,
+            self_type: None,
+            codes: Vec::new(),
+            lambda_sites: HashMap::new(),
+            fn_values: HashMap::new(),
+            depth: 0,
+            strings: HashMap::new(),
+            files: HashMap::new(),
+            next_fd: 3,
+            next_thread: 1,
+            allow_io: true,
+            fuel: None,
+        }
+    }
+
+    /// Interpreter for compile-time code: bounded run time, and no I/O unless allowed
+    pub fn sandboxed(allow_io: bool) -> Self {
+        Interpreter {
+            allow_io,
+            fuel: Some(SANDBOX_FUEL),
+            ..Self::new()
+        }
+    }
+
+    /// Register functions, constants and statics
+    pub fn load_module(&mut self, module: &TypedModule) -> Result<()> {
+        self.load_decls(module.decls.iter().map(|t| &t.decl));
+        Ok(())
+    }
+
+    /// Register declarations that have not been type checked (compile-time evaluation)
+    pub fn load_decls<'d>(&mut self, decls: impl IntoIterator<Item = &'d Decl>) {
+        for decl in decls {
+            self.own.add(decl);
+            match decl {
+                Decl::Func { name, params, body, .. } => {
+                    self.funcs.insert(name.clone(), Rc::new(Function { params: params.clone(), body: body.clone(), self_type: None }));
+                }
+                // Drop elaboration turns `impl Drop` into top-level functions
+                Decl::Impl { trait_name, type_name, methods, .. } if trait_name.as_deref() != Some("Drop") => {
+                    for method in methods {
+                        if let Decl::Func { name, params, body, .. } = method {
+                            let func = Function { params: params.clone(), body: body.clone(), self_type: Some(type_name.clone()) };
+                            self.funcs.insert(method_symbol(type_name, name), Rc::new(func));
+                        }
+                    }
+                }
+                Decl::Enum { name, variants, .. } => {
+                    self.enums.insert(name.clone(), variants.clone());
+                }
+                Decl::Extern { funcs, .. } => {
+                    self.externs.extend(funcs.iter().map(|f| f.name.clone()));
+                }
+                Decl::Const { name, ty, value, .. } => self.declare_global(name, Some(ty), Some(value)),
+                Decl::Static { name, ty, value, .. } => self.declare_global(name, ty.as_ref(), value.as_ref()),
+                _ => {}
+            }
+        }
+    }
+
+    fn declare_global(&mut self, name: &str, ty: Option<&Type>, value: Option<&Expr>) {
+        match ty {
+            Some(ty) => self.global_types.insert(name.to_string(), ty.clone()),
+            None => self.global_types.remove(name),
+        };
+        match value {
+            Some(value) => {
+                self.globals.remove(name);
+                self.pending.insert(name.to_string(), Rc::new(value.clone()));
+            }
+            None => {
+                self.globals.insert(name.to_string(), Slot::Value(0));
+            }
+        }
+    }
+
+    /// Evaluate a block in a fresh scope holding `bindings`
+    pub fn eval_block(&mut self, bindings: &[(String, i64)], block: &Block) -> Result<i64> {
+        let saved_frame = self.frame;
+        self.frame = self.scopes.len();
+        self.scopes.push(Scope::default());
+        for (name, value) in bindings {
+            self.define(name, *value, None);
+        }
+        let result = self.finish(|interp| interp.exec_block(block));
+        self.pop_scopes(self.frame);
+        self.frame = saved_frame;
+        self.lambda_sites.clear();
+        self.flush();
+        result
+    }
+
+    /// Call a function by name with integer arguments
+    pub fn call(&mut self, name: &str, args: &[i64]) -> Result<i64> {
+        self.finish(|interp| interp.call_function(name, args.to_vec(), Span::default()))
+    }
+
+    /// Execute a statement in the persistent top-level scope (REPL input)
+    pub fn exec(&mut self, stmt: &Stmt) -> Result<i64> {
+        if self.scopes.is_empty() {
+            self.scopes.push(Scope::default());
+        }
+        let result = self.finish(|interp| interp.exec_stmt(stmt));
+        self.lambda_sites.clear();
+        self.flush();
+        result
+    }
+
+    /// Read a NUL-terminated string the program placed on the heap
+    pub fn read_string(&self, addr: i64) -> Result<String> {
+        let mut out = Vec::new();
+        let mut p = addr as usize;
+        while let Some(b) = self.runtime.load(p, 1).filter(|_| addr > 0) {
+            if b == 0 {
+                return Ok(String::from_utf8_lossy(&out).into_owned());
+            }
+            out.push(b as u8);
+            p += 1;
+        }
+        Err(anyhow!("Invalid string address {:#x}", addr))
+    }
+
+    /// Read `len` 8-byte elements of an array the program built
+    pub fn read_words(&self, addr: i64, len: usize) -> Result<Vec<i64>> {
+        (0..len)
+            .map(|i| self.runtime.load(addr as usize + i * 8, 8).filter(|_| addr > 0).map(|v| v as i64))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| anyhow!("Invalid array address {:#x}", addr))
+    }
+
+    /// Run `main(argc, argv)` and return the process exit code
+    pub fn run_main(&mut self, args: &[String]) -> Result<i64> {
+        let main_params = match self.funcs.get("main") {
+            Some(f) => f.params.len(),
+            None => return Err(anyhow!("No main function found")),
+        };
+        let argv = self.alloc_argv(args)?;
+        let call_args = [args.len() as i64, argv];
+        let result = self.finish(|interp| interp.call_function("main", call_args[..main_params.min(2)].to_vec(), Span::default()));
+        self.flush();
+        result
+    }
+
+    /// Resolve top-level unwinding: `exit` becomes a normal result
+    fn finish(&mut self, f: impl FnOnce(&mut Self) -> Exec<i64>) -> Result<i64> {
+        match f(self) {
+            Ok(v) | Err(Unwind::Exit(v)) | Err(Unwind::Return(v)) => Ok(v),
+            Err(Unwind::Break) => Err(anyhow!("`break` outside of a loop")),
+            Err(Unwind::Continue) => Err(anyhow!("`continue` outside of a loop")),
+            Err(Unwind::Error(e)) => Err(e),
+        }
+    }
+
+    fn flush(&mut self) {
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+    }
+
+    // ========== Memory ==========
+
+    fn alloc(&mut self, size: i64) -> i64 {
+        self.runtime.malloc(size.max(0) as usize) as i64
+    }
+
+    fn load(&self, addr: i64, width: usize, span: Span) -> Exec<i64> {
+        match self.runtime.load(addr as usize, width) {
+            Some(v) if addr > 0 => Ok(v as i64),
+            _ => fail(format!("Invalid {}-byte load from address {:#x} at line {}", width, addr, span.line)),
+        }
+    }
+
+    fn store(&mut self, addr: i64, width: usize, value: i64, span: Span) -> Exec<()> {
+        if addr > 0 && self.runtime.store(addr as usize, width, value as u64).is_some() {
+            return Ok(());
+        }
+        fail(format!("Invalid {}-byte store to address {:#x} at line {}", width, addr, span.line))
+    }
+
+    fn heap_bytes(&self, addr: i64, len: i64, span: Span) -> Exec<&[u8]> {
+        match self.runtime.bytes(addr as usize, len.max(0) as usize) {
+            Some(b) if addr > 0 || len == 0 => Ok(b),
+            _ => fail(format!("Invalid buffer {:#x}..+{} at line {}", addr, len, span.line)),
+        }
+    }
+
+    fn heap_bytes_mut(&mut self, addr: i64, len: i64, span: Span) -> Exec<&mut [u8]> {
+        match self.runtime.bytes_mut(addr as usize, len.max(0) as usize) {
+            Some(b) if addr > 0 || len == 0 => Ok(b),
+            _ => fail(format!("Invalid buffer {:#x}..+{} at line {}", addr, len, span.line)),
+        }
+    }
+
+    /// Copy a string onto the heap as a NUL-terminated C string
+    fn alloc_cstr(&mut self, s: &str) -> i64 {
+        let ptr = self.alloc(s.len() as i64 + 1);
+        let bytes = self.runtime.bytes_mut(ptr as usize, s.len() + 1).expect("fresh allocation");
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        bytes[s.len()] = 0;
+        ptr
+    }
+
+    /// Read a NUL-terminated string argument
+    fn read_cstr(&self, addr: i64, span: Span) -> Exec<String> {
+        match self.read_string(addr) {
+            Ok(s) => Ok(s),
+            Err(e) => fail(format!("{} at line {}", e, span.line)),
+        }
+    }
+
+    fn alloc_argv(&mut self, args: &[String]) -> Result<i64> {
+        let argv = self.alloc((args.len() as i64 + 1) * 8);
+        for (i, arg) in args.iter().enumerate() {
+            let s = self.alloc_cstr(arg);
+            self.runtime.store(argv as usize + i * 8, 8, s as u64).expect("fresh allocation");
+        }
+        self.runtime.store(argv as usize + args.len() * 8, 8, 0).expect("fresh allocation");
+        Ok(argv)
+    }
+
+    // ========== Variables ==========
+
+    fn local(&self, name: &str) -> Option<&Local> {
+        self.scopes[self.frame..].iter().rev().find_map(|s| s.vars.get(name))
+    }
+
+    fn read_slot(&self, slot: Slot, span: Span) -> Exec<i64> {
+        match slot {
+            Slot::Value(v) => Ok(v),
+            Slot::Cell(addr) => self.load(addr, 8, span),
+        }
+    }
+
+    fn lookup(&mut self, name: &str, span: Span) -> Exec<Option<i64>> {
+        if let Some(slot) = self.local(name).map(|l| l.slot) {
+            return self.read_slot(slot, span).map(Some);
+        }
+        self.global(name, span)
+    }
+
+    /// Value of a constant or static, initializing it on first use
+    fn global(&mut self, name: &str, span: Span) -> Exec<Option<i64>> {
+        if let Some(slot) = self.global_slot(name, span)? {
+            return self.read_slot(slot, span).map(Some);
+        }
+        Ok(None)
+    }
+
+    fn global_slot(&mut self, name: &str, span: Span) -> Exec<Option<Slot>> {
+        if let Some(slot) = self.globals.get(name) {
+            return Ok(Some(*slot));
+        }
+        let Some(init) = self.pending.remove(name) else {
+            return Ok(None);
+        };
+        // Initializers see only other globals, not the caller's locals
+        let saved_frame = std::mem::replace(&mut self.frame, self.scopes.len());
+        let saved_self = self.self_type.take();
+        let result = self.eval_expr(&init);
+        self.frame = saved_frame;
+        self.self_type = saved_self;
+        match result {
+            Ok(v) => {
+                self.globals.insert(name.to_string(), Slot::Value(v));
+                Ok(Some(Slot::Value(v)))
+            }
+            Err(Unwind::Error(e)) => fail(format!("{} (initializing {}, used at line {})", e, name, span.line)),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn define(&mut self, name: &str, value: i64, ty: Option<Type>) {
+        let ty = ty.filter(|t| !matches!(t, Type::Infer));
+        let scope = self.scopes.last_mut().expect("active scope");
+        scope.vars.insert(name.to_string(), Local { slot: Slot::Value(value), ty });
+    }
+
+    fn assign(&mut self, name: &str, value: i64, span: Span) -> Exec<()> {
+        if self.local(name).is_none() && self.global_slot(name, span)?.is_none() {
+            return fail(format!("Assignment to undefined variable {} at line {}", name, span.line));
+        }
+        let frame = self.frame;
+        let slot = match self.scopes[frame..].iter_mut().rev().find_map(|s| s.vars.get_mut(name)) {
+            Some(local) => &mut local.slot,
+            None => self.globals.get_mut(name).expect("initialized above"),
+        };
+        match slot {
+            Slot::Value(v) => {
+                *v = value;
+                Ok(())
+            }
+            Slot::Cell(addr) => {
+                let addr = *addr;
+                self.store(addr, 8, value, span)
+            }
+        }
+    }
+
+    /// Static type of an expression, where running it needs one (fields, methods, `for`)
+    fn type_of(&self, expr: &Expr) -> Option<Type> {
+        let local = |name: &str| match self.local(name) {
+            Some(local) => Some(local.ty.clone()),
+            None => self.global_types.get(name).map(|ty| Some(ty.clone())),
+        };
+        self.own.type_of(expr, &local)
+    }
+
+    /// Whether the expression is a Float, held as the bits of a double
+    fn is_float(&self, expr: &Expr) -> bool {
+        self.type_of(expr).is_some_and(|t| t.is_float())
+    }
+
+    /// An operand of Float arithmetic as a double; Ints are converted
+    fn double(&self, expr: &Expr, v: i64) -> f64 {
+        if self.is_float(expr) { f64::from_bits(v as u64) } else { v as f64 }
+    }
+
+    /// Address of a place; a variable moves to a heap cell the first time.
+    /// Other values are copied to a cell that lives as long as the block.
+    fn address(&mut self, place: &Expr) -> Exec<i64> {
+        match place {
+            Expr::Ident(name, span) => {
+                let frame = self.frame;
+                if let Some(scope) = self.scopes[frame..].iter().rposition(|s| s.vars.contains_key(name)) {
+                    let scope = &mut self.scopes[frame + scope];
+                    let local = scope.vars.get_mut(name).expect("found above");
+                    if let Slot::Value(v) = local.slot {
+                        let cell = self.runtime.malloc(8) as i64;
+                        self.runtime.store(cell as usize, 8, v as u64).expect("fresh allocation");
+                        local.slot = Slot::Cell(cell);
+                        scope.cells.push(cell);
+                    }
+                    let Slot::Cell(addr) = local.slot else { unreachable!("moved to a cell above") };
+                    return Ok(addr);
+                }
+                match self.global_slot(name, *span)? {
+                    Some(Slot::Cell(addr)) => Ok(addr),
+                    Some(Slot::Value(v)) => {
+                        let cell = self.alloc(8);
+                        self.store(cell, 8, v, *span)?;
+                        self.globals.insert(name.clone(), Slot::Cell(cell));
+                        Ok(cell)
+                    }
+                    None => fail(format!("Undefined variable {} at line {}", name, span.line)),
+                }
+            }
+            Expr::Unary(UnOp::Deref, inner, _) => self.eval_expr(inner),
+            Expr::Field(obj, field, span) => self.field(obj, field, *span),
+            Expr::Index(arr, idx, _) => self.element(arr, idx),
+            _ => {
+                let v = self.eval_expr(place)?;
+                let cell = self.alloc(8);
+                self.store(cell, 8, v, place.span())?;
+                self.scopes.last_mut().expect("active scope").cells.push(cell);
+                Ok(cell)
+            }
+        }
+    }
+
+    /// Address of `arr[idx]`
+    fn element(&mut self, arr: &Expr, idx: &Expr) -> Exec<i64> {
+        let base = self.eval_expr(arr)?;
+        let i = self.eval_expr(idx)?;
+        Ok(base.wrapping_add(i.wrapping_mul(8)))
+    }
+
+    /// Address of `obj.field`: structs are records of words in declaration order
+    fn field(&mut self, obj: &Expr, field: &str, span: Span) -> Exec<i64> {
+        let layout = self.type_of(obj).and_then(|t| self.own.named(&t))
+            .and_then(|(ty, derefs)| Some((self.own.fields(&ty)?.iter().position(|(f, _)| f == field)?, derefs)));
+        let Some((index, derefs)) = layout else {
+            return fail(format!("Unknown field {} at line {}", field, span.line));
+        };
+        let mut base = self.eval_expr(obj)?;
+        for _ in 0..derefs {
+            base = self.load(base, 8, span)?;
+        }
+        Ok(base.wrapping_add(index as i64 * 8))
+    }
+
+    /// Leave the scopes above `len`, freeing their cells
+    fn pop_scopes(&mut self, len: usize) {
+        while self.scopes.len() > len {
+            let scope = self.scopes.pop().expect("checked above");
+            for cell in scope.cells {
+                self.runtime.free(cell as usize);
+            }
+        }
+    }
+
+    /// Charge one step against the sandbox limit
+    fn burn(&mut self, span: Span) -> Exec<()> {
+        match &mut self.fuel {
+            Some(0) => fail(format!("Compile-time evaluation exceeded {} steps at line {} (infinite loop?)", SANDBOX_FUEL, span.line)),
+            Some(fuel) => {
+                *fuel -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn scoped(&mut self, block: &Block) -> Exec<i64> {
+        let depth = self.scopes.len();
+        self.scopes.push(Scope::default());
+        let result = self.exec_block(block);
+        self.pop_scopes(depth);
+        result
+    }
+
+    // ========== Calls ==========
+
+    fn call_function(&mut self, name: &str, mut args: Vec<i64>, span: Span) -> Exec<i64> {
+        let func = match self.funcs.get(name) {
+            Some(f) => Rc::clone(f),
+            None => return self.call_builtin(name, &args, span),
+        };
+        let params = &func.params;
+        if args.len() > params.len() || params[args.len()..].iter().any(|p| p.default.is_none()) {
+            return fail(format!("Wrong number of arguments to {} at line {}: expected {}, got {}",
+                name, span.line, params.len(), args.len()));
+        }
+        // Defaults see the caller's scope, like an argument expression would
+        for param in &params[args.len()..] {
+            let default = param.default.as_ref().expect("checked above");
+            args.push(self.eval_expr(default)?);
+        }
+        let self_type = func.self_type.as_deref();
+        let bindings = params.iter().zip(args).map(|(p, v)| (p.name.clone(), v, declared(&p.ty, self_type))).collect();
+        self.enter(name, span, func.self_type.clone(), bindings, |interp| interp.exec_block(&func.body))
+    }
+
+    /// Run `body` as a new call whose first scope holds `bindings`
+    fn enter(&mut self, name: &str, span: Span, self_type: Option<String>, bindings: Vec<(String, i64, Option<Type>)>,
+             body: impl FnOnce(&mut Self) -> Exec<i64>) -> Exec<i64> {
+        if self.depth >= MAX_CALL_DEPTH {
+            return fail(format!("Call depth exceeded {} in {} at line {}", MAX_CALL_DEPTH, name, span.line));
+        }
+        self.burn(span)?;
+
+        let saved_frame = self.frame;
+        let saved_self = std::mem::replace(&mut self.self_type, self_type);
+        self.frame = self.scopes.len();
+        self.scopes.push(Scope::default());
+        for (param, value, ty) in bindings {
+            self.define(&param, value, ty);
+        }
+        self.depth += 1;
+        let result = body(self);
+        self.depth -= 1;
+        self.pop_scopes(self.frame);
+        self.frame = saved_frame;
+        self.self_type = saved_self;
+
+        match result {
+            Ok(v) | Err(Unwind::Return(v)) => Ok(v),
+            Err(Unwind::Break) => fail(format!("`break` outside of a loop in {}", name)),
+            Err(Unwind::Continue) => fail(format!("`continue` outside of a loop in {}", name)),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn call_method(&mut self, obj: &Expr, method: &str, args: &[Expr], span: Span) -> Exec<i64> {
+        let Some((type_name, derefs)) = self.type_of(obj).and_then(|t| self.own.named(&t)) else {
+            return fail(format!("Cannot tell the type of the receiver of {} at line {}", method, span.line));
+        };
+        let symbol = method_symbol(&type_name, method);
+        let Some(func) = self.funcs.get(&symbol).cloned() else {
+            return fail(format!("No method {} on {} at line {}", method, type_name, span.line));
+        };
+        if func.params.first().is_none_or(|p| p.name != "self") {
+            return fail(format!("{}::{} takes no self; call it as {}::{}() at line {}", type_name, method, type_name, method, span.line));
+        }
+        // Receiver: borrow it for `&self`, otherwise pass the value behind any references
+        let by_ref = func.params[0].ty.pointee().is_some();
+        let receiver = if by_ref && derefs == 0 {
+            self.address(obj)?
+        } else {
+            let mut v = self.eval_expr(obj)?;
+            for _ in 0..derefs - usize::from(by_ref) {
+                v = self.load(v, 8, span)?;
+            }
+            v
+        };
+        let mut values = vec![receiver];
+        for arg in args {
+            values.push(self.eval_expr(arg)?);
+        }
+        self.call_function(&symbol, values, span)
+    }
+
+    /// Call through a closure record
+    fn call_closure(&mut self, record: i64, args: Vec<i64>, span: Span) -> Exec<i64> {
+        let index = self.load(record, 8, span)?;
+        let Some(code) = usize::try_from(index).ok().and_then(|i| self.codes.get(i)).cloned() else {
+            return fail(format!("Call of a value that is not a function at line {}", span.line));
+        };
+        match &*code {
+            Code::Func(name) => self.call_function(name, args, span),
+            Code::Lambda { params, body, captures, self_type } => {
+                if args.len() != params.len() {
+                    return fail(format!("Wrong number of arguments to closure at line {}: expected {}, got {}",
+                        span.line, params.len(), args.len()));
+                }
+                let mut bindings = Vec::new();
+                for (i, (name, ty)) in captures.iter().enumerate() {
+                    let v = self.load(record + 8 + i as i64 * 8, 8, span)?;
+                    bindings.push((name.clone(), v, ty.clone()));
+                }
+                let declared_self = self_type.as_deref();
+                bindings.extend(params.iter().zip(args).map(|(p, v)| (p.name.clone(), v, declared(&p.ty, declared_self))));
+                self.enter("closure", span, self_type.clone(), bindings, |interp| interp.eval_expr(body))
+            }
+        }
+    }
+
+    /// Closure record `[code]` of a function used as a value
+    fn fn_value(&mut self, name: &str) -> i64 {
+        if let Some(&record) = self.fn_values.get(name) {
+            return record;
+        }
+        let record = self.alloc(8);
+        self.runtime.store(record as usize, 8, self.codes.len() as u64).expect("fresh allocation");
+        self.codes.push(Rc::new(Code::Func(name.to_string())));
+        self.fn_values.insert(name.to_string(), record);
+        record
+    }
+
+    /// Closure record `[code, captures...]`; locals the body mentions are copied in
+    fn lambda(&mut self, site: &Expr, params: &[Param], body: &Expr, span: Span) -> Exec<i64> {
+        let code = match self.lambda_sites.get(&(site as *const Expr)) {
+            Some(&code) => code,
+            None => {
+                let mut names = Vec::new();
+                free_idents(body, &mut names);
+                let captures = names.into_iter()
+                    .filter(|n| !params.iter().any(|p| &p.name == n))
+                    .filter_map(|n| self.local(&n).map(|l| l.ty.clone()).map(|ty| (n, ty)))
+                    .collect();
+                let self_type = self.self_type.clone();
+                self.codes.push(Rc::new(Code::Lambda { params: params.to_vec(), body: Rc::new(body.clone()), captures, self_type }));
+                let code = self.codes.len() as i64 - 1;
+                self.lambda_sites.insert(site, code);
+                code
+            }
+        };
+        let Code::Lambda { captures, .. } = &*Rc::clone(&self.codes[code as usize]) else {
+            unreachable!("lambda sites map to lambdas");
+        };
+        let record = self.alloc(8 + captures.len() as i64 * 8);
+        self.store(record, 8, code, span)?;
+        for (i, (name, _)) in captures.iter().enumerate() {
+            let v = self.lookup(name, span)?.unwrap_or_default();
+            self.store(record + 8 + i as i64 * 8, 8, v, span)?;
+        }
+        Ok(record)
+    }
+
+    /// Enum variant: its index, or a record of the index and the payload when
+    /// any variant of the enum carries one
+    fn variant(&mut self, enum_name: &str, variant: &str, args: &[Expr], span: Span) -> Exec<i64> {
+        let Some(variants) = self.enums.get(enum_name) else {
+            return fail(format!("Unknown value {}::{} at line {}", enum_name, variant, span.line));
+        };
+        let Some(tag) = variants.iter().position(|v| v.name == variant) else {
+            return fail(format!("No variant {} in {} at line {}", variant, enum_name, span.line));
+        };
+        let arity = variants[tag].fields.len();
+        let tagged = variants.iter().any(|v| !v.fields.is_empty());
+        if args.len() != arity {
+            return fail(format!("Wrong number of values for {}::{} at line {}: expected {}, got {}",
+                enum_name, variant, span.line, arity, args.len()));
+        }
+        if !tagged {
+            return Ok(tag as i64);
+        }
+        let mut values = vec![tag as i64];
+        for arg in args {
+            values.push(self.eval_expr(arg)?);
+        }
+        let record = self.alloc(values.len() as i64 * 8);
+        for (i, v) in values.into_iter().enumerate() {
+            self.store(record + i as i64 * 8, 8, v, span)?;
+        }
+        Ok(record)
+    }
+
+    fn call_builtin(&mut self, name: &str, args: &[i64], span: Span) -> Exec<i64> {
+        if self.externs.iter().any(|e| e == name) {
+            return fail(format!("Cannot call foreign function {} from the interpreter (line {})", name, span.line));
+        }
+        // libc functions the LLVM header declares resolve to the matching builtins
+        let builtin = match name {
+            "malloc" | "free" | "write" | "read" | "open" | "close" => name,
+            _ => match name.strip_prefix("__builtin_") {
+                Some(b) => b,
+                None => return fail(format!("Undefined function {} at line {}", name, span.line)),
+            },
+        };
+        let arity = match builtin {
+            "fork" => 0,
+            "malloc" | "free" | "load8" | "load16" | "load32" | "load64" | "print" |
+            "close" | "exit" | "unlink" | "rmdir" => 1,
+            "store8" | "store16" | "store32" | "store64" | "mkdir" | "rename" => 2,
+            "open" => 2,
+            _ => 3,
+        };
+        if args.len() < arity {
+            return fail(format!("Wrong number of arguments to {} at line {}: expected {}, got {}",
+                name, span.line, arity, args.len()));
+        }
+        if !self.allow_io && matches!(builtin, "print" | "write" | "read" | "open" | "close" | "lseek" |
+            "unlink" | "mkdir" | "rmdir" | "rename") {
+            return fail(format!("{} is not allowed at compile time without --allow-comptime-io (line {})", name, span.line));
+        }
+        if self.fuel.is_some() && builtin == "exit" {
+            return fail(format!("{} called at compile time (line {})", name, span.line));
+        }
+        let width = |b: &str| b.trim_start_matches(|c: char| c.is_alphabetic()).parse::<usize>().map_or(8, |bits| bits / 8);
+
+        match builtin {
+            "malloc" => Ok(self.alloc(args[0])),
+            // String literals live as long as the program
+            "free" if self.strings.values().any(|&p| p == args[0]) => Ok(0),
+            "free" => {
+                self.runtime.free(args[0] as usize);
+                Ok(0)
+            }
+            "load8" | "load16" | "load32" | "load64" => self.load(args[0], width(builtin), span),
+            "store8" | "store16" | "store32" | "store64" => {
+                self.store(args[0], width(builtin), args[1], span)?;
+                Ok(0)
+            }
+            "memcpy" => {
+                let src = self.heap_bytes(args[1], args[2], span)?.to_vec();
+                self.heap_bytes_mut(args[0], args[2], span)?.copy_from_slice(&src);
+                Ok(0)
+            }
+            "memset" => {
+                self.heap_bytes_mut(args[0], args[2], span)?.fill(args[1] as u8);
+                Ok(0)
+            }
+            "print" => {
+                self.write_fd(1, &[args[0] as u8], span)?;
+                Ok(0)
+            }
+            "write" => {
+                let data = self.heap_bytes(args[1], args[2], span)?.to_vec();
+                self.write_fd(args[0], &data, span)
+            }
+            "read" => self.read_fd(args[0], args[1], args[2], span),
+            "open" => {
+                let path = self.read_cstr(args[0], span)?;
+                let mode = args.get(2).copied().unwrap_or(0);
+                Ok(self.open(&path, args[1], mode))
+            }
+            "close" => Ok(if self.files.remove(&args[0]).is_some() || (0..3).contains(&args[0]) { 0 } else { -1 }),
+            "lseek" => {
+                let pos = match args[2] {
+                    0 => SeekFrom::Start(args[1] as u64),
+                    1 => SeekFrom::Current(args[1]),
+                    _ => SeekFrom::End(args[1]),
+                };
+                Ok(self.files.get_mut(&args[0]).and_then(|f| f.seek(pos).ok()).map_or(-1, |p| p as i64))
+            }
+            "exit" => {
+                self.flush();
+                Err(Unwind::Exit(args[0]))
+            }
+            "unlink" => {
+                let path = self.read_cstr(args[0], span)?;
+                Ok(std::fs::remove_file(path).map_or(-1, |_| 0))
+            }
+            "mkdir" => {
+                let path = self.read_cstr(args[0], span)?;
+                Ok(std::fs::create_dir(path).map_or(-1, |_| 0))
+            }
+            "rmdir" => {
+                let path = self.read_cstr(args[0], span)?;
+                Ok(std::fs::remove_dir(path).map_or(-1, |_| 0))
+            }
+            "rename" => {
+                let from = self.read_cstr(args[0], span)?;
+                let to = self.read_cstr(args[1], span)?;
+                Ok(std::fs::rename(from, to).map_or(-1, |_| 0))
+            }
+            _ => fail(format!("{} is not available in the interpreter (line {})", name, span.line)),
+        }
+    }
+
+    // ========== I/O ==========
+
+    fn write_fd(&mut self, fd: i64, data: &[u8], span: Span) -> Exec<i64> {
+        let written = match fd {
+            1 => std::io::stdout().write_all(data),
+            2 => {
+                // Keep stdout and stderr interleaved in program order
+                let _ = std::io::stdout().flush();
+                std::io::stderr().write_all(data)
+            }
+            _ => match self.files.get_mut(&fd) {
+                Some(f) => f.write_all(data),
+                None => return Ok(-1),
+            },
+        };
+        match written {
+            Ok(()) => Ok(data.len() as i64),
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Err(Unwind::Exit(141)),
+            Err(e) => fail(format!("write to fd {} failed at line {}: {}", fd, span.line, e)),
+        }
+    }
+
+    fn read_fd(&mut self, fd: i64, buf: i64, len: i64, span: Span) -> Exec<i64> {
+        let mut data = vec![0u8; len.max(0) as usize];
+        let n = match fd {
+            0 => {
+                let _ = std::io::stdout().flush();
+                std::io::stdin().read(&mut data)
+            }
+            _ => match self.files.get_mut(&fd) {
+                Some(f) => f.read(&mut data),
+                None => return Ok(-1),
+            },
+        };
+        match n {
+            Ok(n) => {
+                self.heap_bytes_mut(buf, n as i64, span)?.copy_from_slice(&data[..n]);
+                Ok(n as i64)
+            }
+            Err(_) => Ok(-1),
+        }
+    }
+
+    fn open(&mut self, path: &str, flags: i64, mode: i64) -> i64 {
+        let mut opts = OpenOptions::new();
+        match flags & O_ACCMODE {
+            O_WRONLY => opts.write(true),
+            O_RDWR => opts.read(true).write(true),
+            _ => opts.read(true),
+        };
+        opts.create(flags & O_CREAT != 0)
+            .truncate(flags & O_TRUNC != 0)
+            .append(flags & O_APPEND != 0);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            opts.mode(if mode == 0 { 0o644 } else { mode as u32 });
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+        match opts.open(path) {
+            Ok(f) => {
+                let fd = self.next_fd;
+                self.next_fd += 1;
+                self.files.insert(fd, f);
+                fd
+            }
+            Err(_) => -1,
+        }
+    }
+
+    // ========== Statements ==========
+
+    /// Execute statements in the current scope; the value is that of the last one
+    fn exec_block(&mut self, block: &Block) -> Exec<i64> {
+        let mut last = 0;
+        for stmt in &block.stmts {
+            last = self.exec_stmt(stmt)?;
+        }
+        Ok(last)
+    }
+
+    fn exec_stmt(&mut self, stmt: &Stmt) -> Exec<i64> {
+        match stmt {
+            Stmt::Let { name, ty, init, .. } => {
+                let ty = match (ty, init) {
+                    (Some(ty), _) if !matches!(ty, Type::Infer) => declared(ty, self.self_type.as_deref()),
+                    (_, Some(e)) => self.type_of(e),
+                    _ => None,
+                };
+                let v = match init {
+                    Some(e) => self.eval_expr(e)?,
+                    None => 0,
+                };
+                self.define(name, v, ty);
+                Ok(0)
+            }
+
+            Stmt::Assign(target, value, span) => {
+                let v = self.eval_expr(value)?;
+                match target {
+                    Expr::Ident(name, _) => self.assign(name, v, *span)?,
+                    Expr::Unary(UnOp::Deref, ptr, _) => {
+                        let addr = self.eval_expr(ptr)?;
+                        self.store(addr, 8, v, *span)?;
+                    }
+                    Expr::Index(arr, idx, _) => {
+                        let addr = self.element(arr, idx)?;
+                        self.store(addr, 8, v, *span)?;
+                    }
+                    Expr::Field(obj, field, _) => {
+                        let addr = self.field(obj, field, *span)?;
+                        self.store(addr, 8, v, *span)?;
+                    }
+                    _ => return fail(format!("Unsupported assignment target at line {}", span.line)),
+                }
+                Ok(0)
+            }
+
+            Stmt::Return(expr, _) => {
+                let v = match expr {
+                    Some(e) => self.eval_expr(e)?,
+                    None => 0,
+                };
+                Err(Unwind::Return(v))
+            }
+
+            Stmt::If(cond, then_block, else_block, _) => {
+                if self.eval_expr(cond)? != 0 {
+                    self.scoped(then_block)?;
+                } else if let Some(eb) = else_block {
+                    self.scoped(eb)?;
+                }
+                Ok(0)
+            }
+
+            Stmt::While(cond, body, span) => {
+                while self.eval_expr(cond)? != 0 {
+                    self.burn(*span)?;
+                    match self.scoped(body) {
+                        Ok(_) | Err(Unwind::Continue) => {}
+                        Err(Unwind::Break) => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(0)
+            }
+
+            Stmt::For(var, iter, body, span) => self.for_loop(var, iter, body, *span),
+
+            Stmt::Break(_) => Err(Unwind::Break),
+
+            Stmt::Continue(_) => Err(Unwind::Continue),
+
+            Stmt::Expr(expr, _) => self.eval_expr(expr),
+
+            Stmt::Block(block, _) => self.scoped(block),
+        }
+    }
+
+    /// `for x in xs` over a fixed-size array or a vector (`[data, len, cap]`)
+    fn for_loop(&mut self, var: &str, iter: &Expr, body: &Block, span: Span) -> Exec<i64> {
+        let (source, elem) = for_source(self.type_of(iter), span)?;
+        let (data, len) = match source {
+            ForSource::Array(n) => (self.eval_expr(iter)?, n as i64),
+            ForSource::Vec => {
+                let vec = self.eval_expr(iter)?;
+                (self.load(vec, 8, span)?, self.load(vec + 8, 8, span)?)
+            }
+        };
+        for i in 0..len {
+            self.burn(span)?;
+            let v = self.load(data + i * 8, 8, span)?;
+            let depth = self.scopes.len();
+            self.scopes.push(Scope::default());
+            self.define(var, v, elem.clone());
+            let result = self.exec_block(body);
+            self.pop_scopes(depth);
+            match result {
+                Ok(_) | Err(Unwind::Continue) => {}
+                Err(Unwind::Break) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(0)
+    }
+
+    // ========== Expressions ==========
+
+    fn eval_expr(&mut self, expr: &Expr) -> Exec<i64> {
+        match expr {
+            Expr::Int(v, _) => Ok(*v),
+
+            Expr::Float(f, _) => Ok(f.to_bits() as i64),
+
+            Expr::Bool(b, _) => Ok(*b as i64),
+
+            Expr::String(s, _) => {
+                if let Some(&ptr) = self.strings.get(s) {
+                    return Ok(ptr);
+                }
+                let ptr = self.alloc_cstr(s);
+                self.strings.insert(s.clone(), ptr);
+                Ok(ptr)
+            }
+
+            Expr::Ident(name, span) => match self.lookup(name, *span)? {
+                Some(v) => Ok(v),
+                None if self.funcs.contains_key(name) => Ok(self.fn_value(name)),
+                None => fail(format!("Undefined variable {} at line {}", name, span.line)),
+            },
+
+            // Both operands are always evaluated, as in the generated code
+            Expr::Binary(op, left, right, span) => {
+                let l = self.eval_expr(left)?;
+                let r = self.eval_expr(right)?;
+                if !matches!(op, BinOp::And | BinOp::Or | BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::Shl | BinOp::Shr)
+                    && (self.is_float(left) || self.is_float(right))
+                {
+                    let (a, b) = (self.double(left, l), self.double(right, r));
+                    return Ok(match op {
+                        BinOp::Add => (a + b).to_bits() as i64,
+                        BinOp::Sub => (a - b).to_bits() as i64,
+                        BinOp::Mul => (a * b).to_bits() as i64,
+                        BinOp::Div => (a / b).to_bits() as i64,
+                        BinOp::Mod => (a % b).to_bits() as i64,
+                        BinOp::Lt => (a < b) as i64,
+                        BinOp::Le => (a <= b) as i64,
+                        BinOp::Gt => (a > b) as i64,
+                        BinOp::Ge => (a >= b) as i64,
+                        BinOp::Eq => (a == b) as i64,
+                        _ => (a != b) as i64,
+                    });
+                }
+                Ok(match op {
+                    BinOp::Add => l.wrapping_add(r),
+                    BinOp::Sub => l.wrapping_sub(r),
+                    BinOp::Mul => l.wrapping_mul(r),
+                    BinOp::Div | BinOp::Mod if r == 0 => {
+                        return fail(format!("Division by zero at line {}", span.line));
+                    }
+                    BinOp::Div => l.wrapping_div(r),
+                    BinOp::Mod => l.wrapping_rem(r),
+                    BinOp::Lt => (l < r) as i64,
+                    BinOp::Le => (l <= r) as i64,
+                    BinOp::Gt => (l > r) as i64,
+                    BinOp::Ge => (l >= r) as i64,
+                    BinOp::Eq => (l == r) as i64,
+                    BinOp::Ne => (l != r) as i64,
+                    BinOp::And | BinOp::BitAnd => l & r,
+                    BinOp::Or | BinOp::BitOr => l | r,
+                    BinOp::BitXor => l ^ r,
+                    BinOp::Shl => l.wrapping_shl(r as u32),
+                    BinOp::Shr => (l as u64).wrapping_shr(r as u32) as i64,
+                })
+            }
+
+            Expr::Unary(UnOp::Ref | UnOp::RefMut, inner, _) => self.address(inner),
+
+            Expr::Unary(op, inner, span) => {
+                let v = self.eval_expr(inner)?;
+                match op {
+                    UnOp::Neg if self.is_float(inner) => Ok((-f64::from_bits(v as u64)).to_bits() as i64),
+                    UnOp::Neg => Ok(v.wrapping_neg()),
+                    UnOp::Not => Ok(v ^ 1),
+                    UnOp::BitNot => Ok(!v),
+                    _ => self.load(v, 8, *span),
+                }
+            }
+
+            Expr::Call(func, args, span) => {
+                if let Expr::Path(path, _) = func.as_ref() {
+                    if path.len() == 2 && self.enums.contains_key(&path[0]) {
+                        return self.variant(&path[0], &path[1], args, *span);
+                    }
+                }
+                let mut values = Vec::with_capacity(args.len());
+                match func.as_ref() {
+                    Expr::Ident(name, _) if self.local(name).is_none() => {
+                        for arg in args {
+                            values.push(self.eval_expr(arg)?);
+                        }
+                        self.call_function(name, values, *span)
+                    }
+                    // `Type::function(..)`
+                    Expr::Path(path, _) => {
+                        let symbol = method_symbol(&path[0], path.get(1).map_or("", String::as_str));
+                        if path.len() != 2 || !self.funcs.contains_key(&symbol) {
+                            return fail(format!("Unknown function {} at line {}", path.join("::"), span.line));
+                        }
+                        for arg in args {
+                            values.push(self.eval_expr(arg)?);
+                        }
+                        self.call_function(&symbol, values, *span)
+                    }
+                    // A closure record
+                    _ => {
+                        let record = self.eval_expr(func)?;
+                        for arg in args {
+                            values.push(self.eval_expr(arg)?);
+                        }
+                        self.call_closure(record, values, *span)
+                    }
+                }
+            }
+
+            Expr::MethodCall(obj, method, args, span) => self.call_method(obj, method, args, *span),
+
+            Expr::Field(obj, field, span) => {
+                let addr = self.field(obj, field, *span)?;
+                self.load(addr, 8, *span)
+            }
+
+            // Structs are records with a word per field in declaration order
+            Expr::Struct(name, fields, span) => {
+                let layout: Option<Vec<String>> = self.own.named(&Type::Named(name.clone()))
+                    .and_then(|(ty, _)| self.own.fields(&ty))
+                    .map(|fields| fields.iter().map(|(f, _)| f.clone()).collect());
+                let Some(layout) = layout else {
+                    return fail(format!("Unknown struct {} at line {}", name, span.line));
+                };
+                if let Some((extra, _)) = fields.iter().find(|(f, _)| !layout.contains(f)) {
+                    return fail(format!("Unknown field {} in {} literal at line {}", extra, name, span.line));
+                }
+                let record = self.alloc(layout.len() as i64 * 8);
+                for (i, field) in layout.iter().enumerate() {
+                    let Some((_, value)) = fields.iter().find(|(f, _)| f == field) else {
+                        return fail(format!("Missing field {} in {} literal at line {}", field, name, span.line));
+                    };
+                    let v = self.eval_expr(value)?;
+                    self.store(record + i as i64 * 8, 8, v, *span)?;
+                }
+                Ok(record)
+            }
+
+            Expr::Lambda(params, _, body, span) => self.lambda(expr, params, body, *span),
+
+            Expr::Match(scrutinee, arms, span) => self.match_expr(scrutinee, arms, *span),
+
+            Expr::Path(path, span) => {
+                if path.len() != 2 {
+                    return fail(format!("Unknown value {} at line {}", path.join("::"), span.line));
+                }
+                self.variant(&path[0], &path[1], &[], *span)
+            }
+
+            Expr::If(cond, then_block, else_block, _) => {
+                if self.eval_expr(cond)? != 0 {
+                    self.scoped(then_block)
+                } else if let Some(eb) = else_block {
+                    self.scoped(eb)
+                } else {
+                    Ok(0)
+                }
+            }
+
+            // Arrays are heap blocks of 8-byte elements, like the generated code
+            Expr::Array(elems, span) => {
+                let arr = self.alloc(elems.len() as i64 * 8);
+                for (i, elem) in elems.iter().enumerate() {
+                    let v = self.eval_expr(elem)?;
+                    self.store(arr + i as i64 * 8, 8, v, *span)?;
+                }
+                Ok(arr)
+            }
+
+            Expr::Index(arr, idx, span) => {
+                let addr = self.element(arr, idx)?;
+                self.load(addr, 8, *span)
+            }
+
+            Expr::Comptime(block, _) | Expr::Unsafe(block, _) => self.scoped(block),
+
+            // Threads run to completion when spawned; the handle is a fresh id
+            Expr::Spawn(func, args, span) => {
+                let name = match func.as_ref() {
+                    Expr::Ident(name, _) => name,
+                    _ => return fail(format!("spawn needs a function name (line {})", span.line)),
+                };
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(self.eval_expr(arg)?);
+                }
+                self.call_function(name, values, *span)?;
+                let tid = self.next_thread;
+                self.next_thread += 1;
+                Ok(tid)
+            }
+        }
+    }
+
+    // ========== Patterns ==========
+
+    /// `match`: the first arm whose pattern and guard hold runs in its own scope
+    fn match_expr(&mut self, scrutinee: &Expr, arms: &[MatchArm], span: Span) -> Exec<i64> {
+        let ty = self.type_of(scrutinee);
+        let s = self.eval_expr(scrutinee)?;
+        for arm in arms {
+            let mut binds = Vec::new();
+            if !self.pattern(&arm.pattern, s, ty.clone(), &mut binds, span)? {
+                continue;
+            }
+            let depth = self.scopes.len();
+            self.scopes.push(Scope::default());
+            for (name, value, ty) in binds {
+                self.define(&name, value, ty);
+            }
+            let result = match &arm.guard {
+                Some(guard) => match self.eval_expr(guard) {
+                    Ok(0) => None,
+                    Ok(_) => Some(self.eval_expr(&arm.body)),
+                    Err(e) => Some(Err(e)),
+                },
+                None => Some(self.eval_expr(&arm.body)),
+            };
+            self.pop_scopes(depth);
+            if let Some(result) = result {
+                return result;
+            }
+        }
+        fail(format!("No match arm matched at line {}", span.line))
+    }
+
+    /// Whether the word `s` matches; bindings are collected into `binds`
+    fn pattern(&mut self, pattern: &Pattern, s: i64, ty: Option<Type>, binds: &mut Vec<(String, i64, Option<Type>)>,
+               span: Span) -> Exec<bool> {
+        match pattern {
+            Pattern::Wildcard => Ok(true),
+            Pattern::Ident(name) => {
+                binds.push((name.clone(), s, ty));
+                Ok(true)
+            }
+            Pattern::Literal(Expr::String(text, _)) => Ok(self.read_cstr(s, span)? == *text),
+            Pattern::Literal(e) => Ok(self.eval_expr(e)? == s),
+            Pattern::Enum(enum_name, variant, subs) => {
+                let Some(variants) = self.enums.get(enum_name) else {
+                    return fail(format!("Unknown enum {} in pattern at line {}", enum_name, span.line));
+                };
+                let Some(tag) = variants.iter().position(|v| &v.name == variant) else {
+                    return fail(format!("No variant {} in {} at line {}", variant, enum_name, span.line));
+                };
+                let fields = variants[tag].fields.clone();
+                if subs.len() != fields.len() {
+                    return fail(format!("Pattern {}::{} at line {} needs {} values, got {}",
+                        enum_name, variant, span.line, fields.len(), subs.len()));
+                }
+                if !variants.iter().any(|v| !v.fields.is_empty()) {
+                    return Ok(s == tag as i64);
+                }
+                if self.load(s, 8, span)? != tag as i64 {
+                    return Ok(false);
+                }
+                for (i, (sub, field_ty)) in subs.iter().zip(fields).enumerate() {
+                    let v = self.load(s + 8 + i as i64 * 8, 8, span)?;
+                    if !self.pattern(sub, v, Some(field_ty), binds, span)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            Pattern::Struct(name, fields) => {
+                let layout = self.own.named(&Type::Named(name.clone()))
+                    .and_then(|(ty, _)| self.own.fields(&ty).map(<[_]>::to_vec));
+                let Some(layout) = layout else {
+                    return fail(format!("Unknown struct {} in pattern at line {}", name, span.line));
+                };
+                for (field, sub) in fields {
+                    let Some(index) = layout.iter().position(|(f, _)| f == field) else {
+                        return fail(format!("No field {} in {} at line {}", field, name, span.line));
+                    };
+                    let v = self.load(s + index as i64 * 8, 8, span)?;
+                    if !self.pattern(sub, v, Some(layout[index].1.clone()), binds, span)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            Pattern::Tuple(_) => fail(format!("Tuple patterns need tuple values, which the interpreter does not have (line {})", span.line)),
+        }
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Interpret a whole program; `args` becomes `argv` (program name first)
+pub fn run(module: &TypedModule, args: &[String]) -> Result<i64> {
+    // Deep recursion in the program means deep recursion here
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .name("aether-interp".into())
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, || {
+                let mut interp = Interpreter::new();
+                interp.load_module(module)?;
+                interp.run_main(args)
+            })?
+            .join()
+            .map_err(|_| anyhow!("Interpreter panicked"))?
+    })
+}
@@ -3,8 +3,9 @@
 //! Handles all tokens: keywords, operators, literals, identifiers
 
 use std::collections::HashMap;
+use serde::Serialize;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
 pub enum TokenKind {
     // Literals
     Int,
@@ -16,6 +17,8 @@ pub enum TokenKind {
     
     // Identifiers
     Ident,
+    /// Lifetime name: 'a
+    Lifetime,
     
     // Keywords
     Func,
@@ -41,6 +44,9 @@ pub enum TokenKind {
     Match,
     Parallel,
     Spawn,
+    Comptime,
+    Unsafe,
+    Extern,
     
     // Operators
     Plus,
@@ -87,12 +93,15 @@ pub enum TokenKind {
     Hash,
     Question,
     
+    // Trivia (only with keep_trivia)
+    Comment,
+    
     // Special
     Eof,
     Error,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Token {
     pub kind: TokenKind,
     pub lexeme: String,
@@ -125,6 +134,8 @@ pub struct Lexer<'a> {
     start: usize,
     current: usize,
     keywords: HashMap<&'static str, TokenKind>,
+    /// Emit comments as tokens instead of skipping them
+    keep_trivia: bool,
 }
 
 impl<'a> Lexer<'a> {
@@ -155,6 +166,9 @@ impl<'a> Lexer<'a> {
         keywords.insert("match", TokenKind::Match);
         keywords.insert("parallel", TokenKind::Parallel);
         keywords.insert("spawn", TokenKind::Spawn);
+        keywords.insert("comptime", TokenKind::Comptime);
+        keywords.insert("unsafe", TokenKind::Unsafe);
+        keywords.insert("extern", TokenKind::Extern);
         
         Lexer {
             source,
@@ -164,9 +178,15 @@ impl<'a> Lexer<'a> {
             start: 0,
             current: 0,
             keywords,
+            keep_trivia: false,
         }
     }
     
+    /// Keep comments as `TokenKind::Comment` tokens (used by the formatter)
+    pub fn set_keep_trivia(&mut self, keep: bool) {
+        self.keep_trivia = keep;
+    }
+    
     fn peek(&mut self) -> Option<char> {
         self.chars.peek().map(|(_, c)| *c)
     }
@@ -203,7 +223,10 @@ impl<'a> Lexer<'a> {
                 }
                 '/' => {
                     let next = self.source[self.current..].chars().nth(1);
-                    if next == Some('/') {
+                    if self.keep_trivia && matches!(next, Some('/') | Some('*')) {
+                        // Left for next_token to turn into a Comment token
+                        break;
+                    } else if next == Some('/') {
                         // Line comment
                         while self.peek() != Some('\n') && self.peek().is_some() {
                             self.advance();
@@ -227,6 +250,25 @@ impl<'a> Lexer<'a> {
         }
     }
     
+    fn comment(&mut self) -> Token {
+        if self.match_char('/') {
+            while self.peek() != Some('\n') && self.peek().is_some() {
+                self.advance();
+            }
+        } else {
+            self.advance(); // *
+            while let Some(c) = self.advance() {
+                if c == '*' && self.peek() == Some('/') {
+                    self.advance();
+                    break;
+                }
+            }
+        }
+        let mut tok = self.make_token(TokenKind::Comment);
+        tok.lexeme = tok.lexeme.trim_end().to_string();
+        tok
+    }
+    
     fn lexeme(&self) -> &str {
         &self.source[self.start..self.current]
     }
@@ -350,6 +392,7 @@ impl<'a> Lexer<'a> {
                 if self.match_char('=') { self.make_token(TokenKind::StarEq) }
                 else { self.make_token(TokenKind::Star) }
             }
+            '/' if self.keep_trivia && matches!(self.peek(), Some('/') | Some('*')) => self.comment(),
             '/' => {
                 if self.match_char('=') { self.make_token(TokenKind::SlashEq) }
                 else { self.make_token(TokenKind::Slash) }
@@ -391,6 +434,14 @@ impl<'a> Lexer<'a> {
             // Literals
             '"' => self.string(),
             '\'' => {
+                // A lifetime has no closing quote: 'a, 'input
+                let mut rest = self.source[self.current..].chars();
+                if rest.next().is_some_and(|c| c.is_alphabetic() || c == '_') && rest.next() != Some('\'') {
+                    while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+                        self.advance();
+                    }
+                    return self.make_token(TokenKind::Lifetime);
+                }
                 let ch = self.advance();
                 if self.peek() == Some('\'') {
                     self.advance();
@@ -424,3 +475,21 @@ pub fn tokenize(source: &str) -> Vec<Token> {
     
     tokens
 }
+
+/// Tokenize keeping comments as `TokenKind::Comment` tokens
+pub fn tokenize_with_trivia(source: &str) -> Vec<Token> {
+    let mut lexer = Lexer::new(source);
+    lexer.set_keep_trivia(true);
+    let mut tokens = Vec::new();
+    
+    loop {
+        let token = lexer.next_token();
+        let is_eof = token.kind == TokenKind::Eof;
+        tokens.push(token);
+        if is_eof {
+            break;
+        }
+    }
+    
+    tokens
+}
@@ -13,11 +13,66 @@ pub mod binary;
 pub mod runtime;
 pub mod stdlib;
 pub mod tooling;
+pub mod dump;
+pub mod interp;
+pub mod consteval;
+pub mod comptime;
+pub mod drops;
 
-use std::path::PathBuf;
-use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
+use clap::{Parser, Subcommand, ValueEnum};
 
-#[derive(Parser, Debug)]
+/// Kind of artifact produced by a build
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum CrateType {
+    /// Executable (requires `main`)
+    Bin,
+    /// Static library archive (.a)
+    Staticlib,
+    /// C-compatible shared library (.so/.dylib/.dll)
+    Cdylib,
+    /// Relocatable object file (.o)
+    Obj,
+}
+
+/// Compiler stage to write out with --emit
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum EmitKind {
+    /// Token stream (.tokens.json / .tokens.txt)
+    Tokens,
+    /// Parsed AST (.ast.json / .ast.txt)
+    Ast,
+    /// Type-checked AST (.typed-ast.json / .typed-ast.txt)
+    TypedAst,
+    /// LLVM IR (.ll)
+    LlvmIr,
+    /// Native assembly (.s)
+    Asm,
+    /// Object file (.o)
+    Obj,
+    /// Final artifact for --crate-type
+    Bin,
+    /// C header for #[export] items (.h)
+    CHeader,
+}
+
+/// Code generator behind the asm, obj and bin stages
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Backend {
+    /// LLVM IR compiled by clang
+    Llvm,
+    /// Built-in code generator, assembler and linker
+    Native,
+}
+
+/// Format of token and AST dumps
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum EmitFormat {
+    Json,
+    Tree,
+}
+
+#[derive(Parser, Debug, Clone)]
 #[command(name = "aetherc")]
 #[command(about = "Aether Compiler - The fastest, most secure programming language")]
 #[command(version = "1.0.0")]
@@ -33,7 +88,7 @@ struct Cli {
     #[arg(short, long, default_value = "a.out", global = true)]
     output: PathBuf,
 
-    /// Target triple (e.g., aarch64-apple-darwin, x86_64-unknown-linux-gnu)
+    /// Target triple (e.g., aarch64-apple-darwin, x86_64-unknown-linux-gnu), or `c` for C source
     #[arg(short, long, global = true)]
     target: Option<String>,
 
@@ -41,10 +96,26 @@ struct Cli {
     #[arg(short = 'O', long, default_value = "2", global = true)]
     opt_level: u8,
 
-    /// Emit assembly instead of binary (LLVM IR)
+    /// Emit assembly instead of binary (LLVM IR; same as --emit=llvm-ir)
     #[arg(long, global = true)]
     emit_asm: bool,
 
+    /// Stages to write out, comma-separated (default: bin)
+    #[arg(long, value_enum, value_delimiter = ',', global = true)]
+    emit: Vec<EmitKind>,
+
+    /// Format of token and AST dumps
+    #[arg(long, value_enum, default_value = "json", global = true)]
+    emit_format: EmitFormat,
+
+    /// Kind of artifact to build
+    #[arg(long, value_enum, default_value = "bin", global = true)]
+    crate_type: CrateType,
+
+    /// Code generator to use
+    #[arg(long, value_enum, default_value = "llvm", global = true)]
+    backend: Backend,
+
     /// Enable debug info
     #[arg(short = 'g', long, global = true)]
     debug: bool,
@@ -52,15 +123,46 @@ struct Cli {
     /// Verbose output
     #[arg(short, long, global = true)]
     verbose: bool,
+
+    /// Let comptime code use file and console I/O
+    #[arg(long, global = true)]
+    allow_comptime_io: bool,
+
+    /// Reject unsafe blocks, functions and impls (for application crates)
+    #[arg(long, global = true)]
+    forbid_unsafe: bool,
 }
 
-#[derive(Subcommand, Debug)]
+#[derive(Subcommand, Debug, Clone)]
 enum Commands {
     /// Compile source file to binary
     Build {
         /// Input source file
         input: PathBuf,
     },
+    /// Compile and run a program, or interpret it with --interp
+    Run {
+        /// Input source file
+        input: PathBuf,
+        /// Run with the tree-walking interpreter instead of compiling
+        #[arg(long)]
+        interp: bool,
+    },
+    /// Generate Aether extern bindings from a C header
+    Bindgen {
+        /// C header file
+        header: PathBuf,
+    },
+    /// Format source files in place
+    Fmt {
+        /// Source file, or a directory of .aether files
+        path: PathBuf,
+        /// Only report files that are not formatted
+        #[arg(long)]
+        check: bool,
+    },
+    /// Interactive read-eval-print loop
+    Repl,
     /// Start Language Server Protocol (LSP) server
     Lsp,
     /// Aether Package Manager
@@ -70,7 +172,7 @@ enum Commands {
     },
 }
 
-#[derive(Subcommand, Debug)]
+#[derive(Subcommand, Debug, Clone)]
 enum ApmAction {
     /// Install a package from git URL
     Install {
@@ -108,6 +210,29 @@ fn main() -> anyhow::Result<()> {
         Some(Commands::Build { input }) => {
             compile_file(input, &cli)
         }
+        Some(Commands::Run { input, interp }) => {
+            let code = if *interp {
+                interpret_file(input, cli.allow_comptime_io, cli.forbid_unsafe)?
+            } else {
+                run_native(input, &cli)?
+            };
+            std::process::exit(code as i32)
+        }
+        Some(Commands::Bindgen { header }) => {
+            // Default output sits next to the header: foo.h -> foo.aether
+            let output = if cli.output.as_os_str() == "a.out" {
+                header.with_extension("aether")
+            } else {
+                cli.output.clone()
+            };
+            tooling::bindgen::run(header, &output)
+        }
+        Some(Commands::Fmt { path, check }) => {
+            tooling::fmt::run(path, *check)
+        }
+        Some(Commands::Repl) => {
+            tooling::repl::run()
+        }
         None => {
             // Legacy mode: direct file argument
             if let Some(input) = &cli.input {
@@ -116,6 +241,10 @@ fn main() -> anyhow::Result<()> {
                 println!("AETHERC v1.0.0 - World-Class Compiler");
                 println!("=====================================");
                 println!("Usage: aetherc <FILE> or aetherc build <FILE>");
+                println!("       aetherc run [--interp] <FILE> - Compile and run");
+                println!("       aetherc bindgen <HEADER> [-o FILE] - Generate C bindings");
+                println!("       aetherc fmt [--check] <PATH> - Format source files");
+                println!("       aetherc repl    - Interactive REPL");
                 println!("       aetherc lsp     - Start language server");
                 println!("       aetherc apm     - Package manager");
                 Ok(())
@@ -128,6 +257,24 @@ fn compile_file(input: &PathBuf, cli: &Cli) -> anyhow::Result<()> {
     println!("AETHERC v1.0.0 - World-Class Compiler");
     println!("=====================================");
     
+    // Requested stages; --emit-asm is the legacy spelling of llvm-ir
+    let mut emit = cli.emit.clone();
+    if cli.emit_asm && !emit.contains(&EmitKind::LlvmIr) {
+        emit.push(EmitKind::LlvmIr);
+    }
+    if emit.is_empty() {
+        emit.push(EmitKind::Bin);
+    }
+    let wants = |kind: EmitKind| emit.contains(&kind);
+    let format = match cli.emit_format {
+        EmitFormat::Json => dump::DumpFormat::Json,
+        EmitFormat::Tree => dump::DumpFormat::Tree,
+    };
+    let dump_ext = match format {
+        dump::DumpFormat::Json => "json",
+        dump::DumpFormat::Tree => "txt",
+    };
+    
     // Read source
     let source = std::fs::read_to_string(input)?;
     if cli.verbose {
@@ -139,24 +286,75 @@ fn compile_file(input: &PathBuf, cli: &Cli) -> anyhow::Result<()> {
     if cli.verbose {
         println!("      {} tokens", tokens.len());
     }
+    if wants(EmitKind::Tokens) {
+        let text = match format {
+            dump::DumpFormat::Json => dump::to_json(&tokens)?,
+            dump::DumpFormat::Tree => dump::tokens_to_tree(&tokens),
+        };
+        write_emit(&emit_path(input, cli, &format!("tokens.{}", dump_ext)), text, "tokens")?;
+    }
+    let backend = [EmitKind::LlvmIr, EmitKind::Asm, EmitKind::Obj, EmitKind::Bin];
+    if !emit.iter().any(|k| *k != EmitKind::Tokens) {
+        return Ok(());
+    }
     
     // Parse
     if cli.verbose {
         println!("[2/5] Parsing...");
     }
-    let ast = parser::parse(&tokens)?;
+    let mut ast = parser::parse(&tokens)?;
+    if wants(EmitKind::Ast) {
+        write_emit(&emit_path(input, cli, &format!("ast.{}", dump_ext)), dump::dump(&ast, format)?, "AST")?;
+    }
+    if !emit.iter().any(|k| !matches!(k, EmitKind::Tokens | EmitKind::Ast)) {
+        return Ok(());
+    }
     
-    // Type check
+    // Type check (comptime code runs and constants are folded first)
     if cli.verbose {
         println!("[3/5] Type checking...");
     }
-    let typed_ast = typechecker::check(&ast)?;
+    comptime::expand_module(&mut ast, cli.allow_comptime_io)?;
+    consteval::fold_module(&mut ast)?;
+    let mut typed_ast = typechecker::check(&ast)?;
     
     // Borrow check
     if cli.verbose {
         println!("[4/5] Borrow checking...");
     }
-    borrowck::check(&typed_ast)?;
+    borrowck::check(&typed_ast, cli.forbid_unsafe)?;
+    if wants(EmitKind::TypedAst) {
+        write_emit(&emit_path(input, cli, &format!("typed-ast.{}", dump_ext)), dump::dump(&typed_ast, format)?, "typed AST")?;
+    }
+    drops::elaborate(&mut typed_ast)?;
+    
+    // C header for #[export] items
+    if wants(EmitKind::CHeader) {
+        let h_path = emit_path(input, cli, "h");
+        let guard = h_path.file_stem().map_or("aether".into(), |s| s.to_string_lossy());
+        let header = codegen::header::generate(&typed_ast, &guard)?;
+        std::fs::write(&h_path, header)?;
+        println!("✓ Generated C header: {}", h_path.display());
+    }
+    if !backend.iter().any(|k| wants(*k)) {
+        return Ok(());
+    }
+    
+    // Executables need an entry point; libraries don't
+    let has_main = typed_ast.decls.iter().any(|d| matches!(&d.decl, ast::Decl::Func { name, .. } if name == "main"));
+    if wants(EmitKind::Bin) && cli.crate_type == CrateType::Bin && !has_main {
+        anyhow::bail!("No main function found in {} (use --crate-type=staticlib|cdylib|obj for libraries)", input.display());
+    }
+    
+    if cli.target.as_deref() == Some("c") {
+        return emit_c(input, cli, &typed_ast, &emit);
+    }
+    if cli.target.as_deref().is_some_and(|t| t.starts_with("wasm32")) {
+        return emit_wasm(input, cli, &typed_ast, &emit);
+    }
+    if cli.backend == Backend::Native {
+        return emit_direct(input, cli, &typed_ast, &emit);
+    }
     
     // LLVM Code Generation
     if cli.verbose {
@@ -164,65 +362,308 @@ fn compile_file(input: &PathBuf, cli: &Cli) -> anyhow::Result<()> {
     }
     
     let mut llvm_gen = codegen::llvm::LLVMCodeGen::new();
+    llvm_gen.set_hide_private(cli.crate_type != CrateType::Bin);
     llvm_gen.emit_header();
     
+    for typed_decl in &typed_ast.decls {
+        llvm_gen.declare_types(&typed_decl.decl);
+        llvm_gen.declare_export(&typed_decl.decl);
+        llvm_gen.declare_const(&typed_decl.decl);
+        llvm_gen.declare_static(&typed_decl.decl);
+    }
     for typed_decl in &typed_ast.decls {
         llvm_gen.gen_function(&typed_decl.decl);
     }
     
     let ir = llvm_gen.get_ir();
     
-    // Output handling
-    if cli.emit_asm {
-        // Just write .ll file if emit_asm requested (treating IR as assembly for now)
-        let ll_path = cli.output.with_extension("ll");
-        std::fs::write(&ll_path, ir)?;
+    // Output handling: every native stage goes through the .ll file
+    let keep_ll = wants(EmitKind::LlvmIr);
+    let ll_path = if keep_ll { emit_path(input, cli, "ll") } else { output_path(input, cli).with_extension("ll") };
+    std::fs::write(&ll_path, ir)?;
+    if keep_ll {
         println!("✓ Generated LLVM IR: {}", ll_path.display());
-    } else {
-        // Compile using system clang
-        // 1. Write temp .ll
-        let ll_path = cli.output.with_extension("ll");
-        std::fs::write(&ll_path, ir)?;
-        
-        if cli.verbose {
-            println!("      Compiling with system clang -O3...");
-        }
-        
-        // 2. Invoke clang
-        let mut cmd = std::process::Command::new("clang");
-        cmd.arg("-O3")
-           .arg(&ll_path)
-           .arg("-o")
-           .arg(&cli.output);
-           
-        // Target settings if needed, but clang usually auto-detects host
-        if let Some(target) = &cli.target {
-            cmd.arg("--target").arg(target);
+    }
+    
+    let result = emit_native(input, cli, &ll_path, &emit);
+    if let Err(e) = result {
+        println!("  Check generated IR at: {}", ll_path.display());
+        return Err(e);
+    }
+    
+    // Cleanup intermediate file unless verbose
+    if !keep_ll && !cli.verbose && !cli.debug {
+        let _ = std::fs::remove_file(ll_path);
+    }
+    
+    Ok(())
+}
+
+/// Interpret a program and return its exit code
+fn interpret_file(input: &Path, allow_comptime_io: bool, forbid_unsafe: bool) -> anyhow::Result<i64> {
+    let source = std::fs::read_to_string(input)?;
+    let tokens = lexer::tokenize(&source);
+    let mut ast = parser::parse(&tokens)?;
+    comptime::expand_module(&mut ast, allow_comptime_io)?;
+    consteval::fold_module(&mut ast)?;
+    let mut typed_ast = typechecker::check(&ast)?;
+    borrowck::check(&typed_ast, forbid_unsafe)?;
+    drops::elaborate(&mut typed_ast)?;
+    interp::run(&typed_ast, &[input.display().to_string()])
+}
+
+/// Build an executable next to the source and run it
+fn run_native(input: &Path, cli: &Cli) -> anyhow::Result<i64> {
+    let mut build = cli.clone();
+    build.emit = vec![EmitKind::Bin];
+    build.crate_type = CrateType::Bin;
+    if build.output.as_os_str() == "a.out" {
+        build.output = input.with_extension("");
+    }
+    compile_file(&input.to_path_buf(), &build)?;
+    let program = std::path::absolute(&build.output)?;
+    let status = std::process::Command::new(&program).status()
+        .map_err(|e| anyhow::anyhow!("Cannot run {}: {}", program.display(), e))?;
+    Ok(status.code().unwrap_or(1) as i64)
+}
+
+/// Run clang for the asm, obj and bin stages
+fn emit_native(input: &Path, cli: &Cli, ll_path: &Path, emit: &[EmitKind]) -> anyhow::Result<()> {
+    if emit.contains(&EmitKind::Asm) {
+        let s_path = emit_path(input, cli, "s");
+        run_clang(ll_path, &s_path, &["-S"], cli)?;
+        println!("✓ Generated assembly: {}", s_path.display());
+    }
+    if emit.contains(&EmitKind::Obj) {
+        let windows = cli.target.as_deref().is_some_and(|t| t.contains("windows"));
+        let o_path = emit_path(input, cli, if windows { "obj" } else { "o" });
+        run_clang(ll_path, &o_path, &["-c"], cli)?;
+        println!("✓ Generated object file: {}", o_path.display());
+    }
+    if !emit.contains(&EmitKind::Bin) {
+        return Ok(());
+    }
+    
+    let output = output_path(input, cli);
+    if cli.verbose {
+        println!("      Compiling {:?} with system clang -O3...", cli.crate_type);
+    }
+    
+    match cli.crate_type {
+        CrateType::Bin => run_clang(ll_path, &output, &[], cli)?,
+        CrateType::Obj => run_clang(ll_path, &output, &["-c"], cli)?,
+        CrateType::Cdylib => {
+            let is_darwin = cli.target.as_deref().map_or(cfg!(target_os = "macos"), |t| t.contains("darwin") || t.contains("apple"));
+            let shared = if is_darwin { "-dynamiclib" } else { "-shared" };
+            run_clang(ll_path, &output, &[shared, "-fPIC"], cli)?
         }
-        
-        let output = cmd.output();
-        
-        match output {
-            Ok(out) => {
-                if !out.status.success() {
-                    let err = String::from_utf8_lossy(&out.stderr);
-                    anyhow::bail!("Clang compilation failed:\n{}", err);
-                }
-                println!("✓ Binary written to: {}", cli.output.display());
-                
-                // Cleanup intermediate file unless verbose
-                if !cli.verbose && !cli.debug {
-                    let _ = std::fs::remove_file(ll_path);
-                }
-            }
-            Err(e) => {
-                println!("! Error invoking clang: {}", e);
-                println!("  Ensure clang is installed and in PATH.");
-                println!("  Check generated IR at: {}", ll_path.display());
-                return Err(e.into());
+        CrateType::Staticlib => {
+            // Archive a single object: clang -c, then ar
+            let obj_path = output.with_extension("o");
+            run_clang(ll_path, &obj_path, &["-c", "-fPIC"], cli)?;
+            let _ = std::fs::remove_file(&output);
+            let status = std::process::Command::new("ar")
+                .arg("rcs")
+                .arg(&output)
+                .arg(&obj_path)
+                .status()?;
+            let _ = std::fs::remove_file(&obj_path);
+            if !status.success() {
+                anyhow::bail!("ar failed to create {}", output.display());
             }
         }
     }
     
+    let kind = match cli.crate_type {
+        CrateType::Bin => "Binary",
+        CrateType::Staticlib => "Static library",
+        CrateType::Cdylib => "Shared library",
+        CrateType::Obj => "Object file",
+    };
+    println!("✓ {} written to: {}", kind, output.display());
+    Ok(())
+}
+
+/// Native backend: generate assembly, then assemble and link it in-process
+fn emit_direct(input: &Path, cli: &Cli, module: &typechecker::TypedModule, emit: &[EmitKind]) -> anyhow::Result<()> {
+    let target = cli.target.clone().unwrap_or_else(|| format!("{}-unknown-linux-gnu", std::env::consts::ARCH));
+    let arch = target.split('-').next().unwrap_or_default();
+    let darwin = target.contains("darwin") || target.contains("apple");
+    let windows = target.contains("windows");
+    let supported = match arch {
+        "x86_64" => target.contains("linux") || darwin || windows,
+        "aarch64" | "arm64" => target.contains("linux") || darwin,
+        _ => false,
+    };
+    if !supported {
+        anyhow::bail!("The native backend does not support target {} yet (use --backend=llvm)", target);
+    }
+    if emit.contains(&EmitKind::LlvmIr) {
+        anyhow::bail!("--emit=llvm-ir needs --backend=llvm");
+    }
+    let entry = cli.crate_type == CrateType::Bin;
+    let program = codegen::mir::lower(module, entry, cli.crate_type != CrateType::Bin)?;
+    let asm = if arch == "x86_64" {
+        if cli.verbose {
+            println!("[5/5] Generating x86-64 assembly...");
+        }
+        let abi = if windows { codegen::x86_64::Abi::Win64 } else { codegen::x86_64::Abi::SysV };
+        codegen::x86_64::generate(&program, abi)?
+    } else {
+        if cli.verbose {
+            println!("[5/5] Generating AArch64 assembly...");
+        }
+        codegen::arm64::generate(&program)?
+    };
+    
+    if emit.contains(&EmitKind::Asm) {
+        let s_path = emit_path(input, cli, "s");
+        std::fs::write(&s_path, &asm)?;
+        println!("✓ Generated assembly: {}", s_path.display());
+    }
+    if emit.contains(&EmitKind::Obj) {
+        let o_path = emit_path(input, cli, if windows { "obj" } else { "o" });
+        binary::assemble(&o_path, asm.as_bytes(), &target)?;
+        println!("✓ Generated object file: {}", o_path.display());
+    }
+    if !emit.contains(&EmitKind::Bin) {
+        return Ok(());
+    }
+    
+    let output = output_path(input, cli);
+    match cli.crate_type {
+        CrateType::Bin => binary::write(&output, asm.as_bytes(), &target)?,
+        CrateType::Obj => binary::assemble(&output, asm.as_bytes(), &target)?,
+        CrateType::Staticlib if darwin || windows => anyhow::bail!("The native backend cannot build macOS or Windows static libraries yet (use --crate-type=obj)"),
+        CrateType::Staticlib => binary::elf::write_staticlib(&output, asm.as_bytes(), &target)?,
+        CrateType::Cdylib => anyhow::bail!("The native backend cannot build shared libraries yet (use --backend=llvm)"),
+    }
+    
+    let kind = match cli.crate_type {
+        CrateType::Bin => "Binary",
+        CrateType::Staticlib => "Static library",
+        CrateType::Cdylib => "Shared library",
+        CrateType::Obj => "Object file",
+    };
+    println!("✓ {} written to: {}", kind, output.display());
+    Ok(())
+}
+
+/// WebAssembly targets: lower to the mid-level IR and encode a module directly
+fn emit_wasm(input: &Path, cli: &Cli, module: &typechecker::TypedModule, emit: &[EmitKind]) -> anyhow::Result<()> {
+    let target = cli.target.clone().unwrap_or_default();
+    if !matches!(target.as_str(), "wasm32-unknown-unknown" | "wasm32-wasi" | "wasm32-wasip1") {
+        anyhow::bail!("Unsupported WebAssembly target {} (use wasm32-unknown-unknown or wasm32-wasi)", target);
+    }
+    if emit.iter().any(|k| matches!(k, EmitKind::LlvmIr | EmitKind::Asm | EmitKind::Obj)) {
+        anyhow::bail!("--emit=llvm-ir, asm and obj are not available for WebAssembly targets (use --emit=bin)");
+    }
+    if !emit.contains(&EmitKind::Bin) {
+        return Ok(());
+    }
+    if matches!(cli.crate_type, CrateType::Staticlib | CrateType::Obj) {
+        anyhow::bail!("WebAssembly targets build modules only (use --crate-type=bin or cdylib)");
+    }
+    if cli.verbose {
+        println!("[5/5] Generating WebAssembly...");
+    }
+    // Only `pub` functions, `#[export]` wrappers and the entry point are exported
+    let entry = cli.crate_type == CrateType::Bin;
+    let program = codegen::mir::lower(module, entry, true)?;
+    let bytes = codegen::wasm::generate(&program, target.contains("wasi"))?;
+    let output = output_path(input, cli);
+    binary::write(&output, &bytes, &target)?;
+    println!("✓ WebAssembly module written to: {}", output.display());
+    Ok(())
+}
+
+/// `--target c`: portable C99 source for any C compiler
+fn emit_c(input: &Path, cli: &Cli, module: &typechecker::TypedModule, emit: &[EmitKind]) -> anyhow::Result<()> {
+    if emit.iter().any(|k| matches!(k, EmitKind::LlvmIr | EmitKind::Asm | EmitKind::Obj)) {
+        anyhow::bail!("--emit=llvm-ir, asm and obj are not available for the C target (use --emit=bin)");
+    }
+    if !emit.contains(&EmitKind::Bin) {
+        return Ok(());
+    }
+    if cli.verbose {
+        println!("[5/5] Generating C...");
+    }
+    let entry = cli.crate_type == CrateType::Bin;
+    let source = codegen::c::generate(module, entry, !entry)?;
+    let output = output_path(input, cli);
+    std::fs::write(&output, source).map_err(|e| anyhow::anyhow!("Cannot write {}: {}", output.display(), e))?;
+    println!("✓ C source written to: {}", output.display());
+    Ok(())
+}
+
+/// Path for an --emit stage: --output (or the input's stem) with a stage extension
+fn emit_path(input: &Path, cli: &Cli, ext: &str) -> PathBuf {
+    let base = if cli.output.as_os_str() == "a.out" {
+        PathBuf::from(input.file_stem().unwrap_or(input.as_os_str()))
+    } else {
+        cli.output.clone()
+    };
+    base.with_extension(ext)
+}
+
+/// Write a frontend dump and report it
+fn write_emit(path: &Path, text: String, what: &str) -> anyhow::Result<()> {
+    std::fs::write(path, text).map_err(|e| anyhow::anyhow!("Cannot write {}: {}", path.display(), e))?;
+    println!("✓ Generated {}: {}", what, path.display());
     Ok(())
 }
+
+/// Output path: --output, or a name derived from the input for library crate types
+fn output_path(input: &Path, cli: &Cli) -> PathBuf {
+    let stem = input.file_stem().map_or("aether".into(), |s| s.to_string_lossy());
+    if cli.output.as_os_str() == "a.out" && cli.target.as_deref() == Some("c") {
+        return PathBuf::from(format!("{}.c", stem));
+    }
+    if cli.output.as_os_str() != "a.out" || cli.crate_type == CrateType::Bin {
+        return cli.output.clone();
+    }
+    let target = cli.target.as_deref().unwrap_or(if cfg!(target_os = "macos") { "apple" } else { "linux" });
+    let windows = target.contains("windows");
+    let darwin = target.contains("darwin") || target.contains("apple");
+    match cli.crate_type {
+        _ if target.starts_with("wasm32") => PathBuf::from(format!("{}.wasm", stem)),
+        CrateType::Obj if windows => PathBuf::from(format!("{}.obj", stem)),
+        CrateType::Obj => PathBuf::from(format!("{}.o", stem)),
+        CrateType::Staticlib if windows => PathBuf::from(format!("{}.lib", stem)),
+        CrateType::Staticlib => PathBuf::from(format!("lib{}.a", stem)),
+        CrateType::Cdylib if windows => PathBuf::from(format!("{}.dll", stem)),
+        CrateType::Cdylib if darwin => PathBuf::from(format!("lib{}.dylib", stem)),
+        CrateType::Cdylib => PathBuf::from(format!("lib{}.so", stem)),
+        CrateType::Bin => cli.output.clone(),
+    }
+}
+
+/// Invoke clang on an IR file with extra flags
+fn run_clang(ll_path: &Path, output: &Path, flags: &[&str], cli: &Cli) -> anyhow::Result<()> {
+    let mut cmd = std::process::Command::new("clang");
+    cmd.arg("-O3")
+       .args(flags)
+       .arg(ll_path)
+       .arg("-o")
+       .arg(output);
+       
+    // Target settings if needed, but clang usually auto-detects host
+    if let Some(target) = &cli.target {
+        cmd.arg("--target").arg(target);
+    }
+    
+    match cmd.output() {
+        Ok(out) => {
+            if !out.status.success() {
+                let err = String::from_utf8_lossy(&out.stderr);
+                anyhow::bail!("Clang compilation failed:\n{}", err);
+            }
+            Ok(())
+        }
+        Err(e) => {
+            println!("! Error invoking clang: {}", e);
+            println!("  Ensure clang is installed and in PATH.");
+            Err(e.into())
+        }
+    }
+}
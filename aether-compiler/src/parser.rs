@@ -24,6 +24,11 @@ impl<'a> Parser<'a> {
         self.peek().kind
     }
     
+    /// Kind of the token after the next one
+    fn peek_second_kind(&self) -> TokenKind {
+        self.tokens.get(self.pos + 1).map_or(TokenKind::Eof, |t| t.kind)
+    }
+    
     fn advance(&mut self) -> Token {
         let tok = self.peek().clone();
         if self.pos < self.tokens.len() - 1 {
@@ -53,6 +58,20 @@ impl<'a> Parser<'a> {
         }
     }
     
+    /// True once only Eof is left
+    pub fn at_end(&self) -> bool {
+        self.check(TokenKind::Eof)
+    }
+    
+    /// True if the next token starts a top-level declaration rather than a statement
+    pub fn at_decl(&self) -> bool {
+        matches!(self.peek_kind(),
+            TokenKind::Func | TokenKind::Struct | TokenKind::Enum | TokenKind::Import |
+            TokenKind::Const | TokenKind::Trait | TokenKind::Impl | TokenKind::Type |
+            TokenKind::Extern | TokenKind::Pub | TokenKind::Hash)
+            || (self.check(TokenKind::Unsafe) && matches!(self.peek_second_kind(), TokenKind::Impl | TokenKind::Func))
+    }
+    
     fn span(&self) -> Span {
         let tok = self.peek();
         Span { line: tok.line, col: tok.col }
@@ -61,23 +80,26 @@ impl<'a> Parser<'a> {
     // ========== TYPE PARSING ==========
     
     fn parse_type(&mut self) -> Result<Type> {
-        // Pointer: *Type
+        // Raw pointer: *Type
         if self.match_tok(TokenKind::Star) {
             let inner = self.parse_type()?;
-            return Ok(Type::Ptr(Box::new(inner)));
+            return Ok(Type::RawPtr(Box::new(inner)));
         }
         
         // Array: [Type] or [Type; N]
         if self.match_tok(TokenKind::LBrack) {
             let elem = self.parse_type()?;
-            let size = if self.match_tok(TokenKind::Semi) {
-                let tok = self.expect(TokenKind::Int)?;
-                Some(tok.int_value.unwrap_or(0) as usize)
-            } else {
-                None
-            };
+            if self.match_tok(TokenKind::Semi) {
+                // Literal lengths are resolved here, anything else by const evaluation
+                let size = self.parse_expr()?;
+                self.expect(TokenKind::RBrack)?;
+                return Ok(match size {
+                    Expr::Int(n, _) if n >= 0 => Type::Array(Box::new(elem), Some(n as usize)),
+                    size => Type::ConstArray(Box::new(elem), Box::new(size)),
+                });
+            }
             self.expect(TokenKind::RBrack)?;
-            return Ok(Type::Array(Box::new(elem), size));
+            return Ok(Type::Array(Box::new(elem), None));
         }
         
         // Function type: func(A, B) -> C
@@ -99,11 +121,24 @@ impl<'a> Parser<'a> {
             return Ok(Type::Func(params, Box::new(ret)));
         }
         
-        // Reference type: &Type or &mut Type
+        // Reference type: &Type, &mut Type, &'a Type
         if self.match_tok(TokenKind::Amp) {
-            let _mutable = self.match_tok(TokenKind::Mut);
-            let inner = self.parse_type()?;
-            return Ok(Type::Ptr(Box::new(inner)));
+            let lifetime = if self.check(TokenKind::Lifetime) {
+                Some(self.advance().lexeme.clone())
+            } else {
+                None
+            };
+            let mutable = self.match_tok(TokenKind::Mut);
+            let inner = Box::new(self.parse_type()?);
+            return Ok(match lifetime {
+                Some(lifetime) => Type::Ref(lifetime, inner, mutable),
+                None => Type::Ptr(inner, mutable),
+            });
+        }
+        
+        // Lifetime argument of a generic type: Parser<'a>
+        if self.check(TokenKind::Lifetime) {
+            return Ok(Type::Named(self.advance().lexeme.clone()));
         }
         
         // Named type
@@ -127,24 +162,27 @@ impl<'a> Parser<'a> {
         Err(anyhow!("Expected type at line {}", self.peek().line))
     }
     
+    /// Generic parameter: a type name `T` or a lifetime `'a`
+    fn parse_generic_param(&mut self) -> Result<String> {
+        if self.check(TokenKind::Lifetime) {
+            return Ok(self.advance().lexeme.clone());
+        }
+        Ok(self.expect(TokenKind::Ident)?.lexeme.clone())
+    }
+    
     // ========== EXPRESSION PARSING ==========
     
     fn parse_primary(&mut self) -> Result<Expr> {
         let span = self.span();
         
-        // Closure: |arg1, arg2| { body }
-        if self.match_tok(TokenKind::Pipe) {
-            let mut params = Vec::new();
-            if !self.check(TokenKind::Pipe) {
-                params.push(self.expect(TokenKind::Ident)?.lexeme.clone());
-                while self.match_tok(TokenKind::Comma) {
-                    params.push(self.expect(TokenKind::Ident)?.lexeme.clone());
-                }
-            }
-            self.expect(TokenKind::Pipe)?;
-            let body = self.parse_block()?;
-            // Return a call expression that represents the closure
-            return Ok(Expr::Ident(format!("closure_{}", params.len()), span));
+        // Closure: |a, b: Int| -> Int body
+        if self.check(TokenKind::Pipe) || self.check(TokenKind::PipePipe) {
+            return self.parse_lambda();
+        }
+        
+        // Match expression: match value { pattern if guard => body, ... }
+        if self.match_tok(TokenKind::Match) {
+            return self.parse_match(span);
         }
         
         // Literals
@@ -186,6 +224,22 @@ impl<'a> Parser<'a> {
                 return Ok(Expr::Path(path, span));
             }
             
+            // Struct literal: Point { x: 1, y: 2 }
+            if name.starts_with(|c: char| c.is_ascii_uppercase()) && self.check(TokenKind::LBrace)
+                && self.peek_second_kind() == TokenKind::Ident
+                && self.tokens.get(self.pos + 2).is_some_and(|t| t.kind == TokenKind::Colon) {
+                self.advance();
+                let mut fields = Vec::new();
+                while !self.check(TokenKind::RBrace) {
+                    let field = self.expect(TokenKind::Ident)?.lexeme.clone();
+                    self.expect(TokenKind::Colon)?;
+                    fields.push((field, self.parse_expr()?));
+                    if !self.match_tok(TokenKind::Comma) { break; }
+                }
+                self.expect(TokenKind::RBrace)?;
+                return Ok(Expr::Struct(name, fields, span));
+            }
+            
             return Ok(Expr::Ident(name, span));
         }
         
@@ -233,6 +287,18 @@ impl<'a> Parser<'a> {
             }
         }
         
+        // Compile-time block: comptime { ... }
+        if self.match_tok(TokenKind::Comptime) {
+            let block = self.parse_block()?;
+            return Ok(Expr::Comptime(Box::new(block), span));
+        }
+        
+        // Unsafe block: unsafe { ... }
+        if self.match_tok(TokenKind::Unsafe) {
+            let block = self.parse_block()?;
+            return Ok(Expr::Unsafe(Box::new(block), span));
+        }
+        
         // If expression
         if self.check(TokenKind::If) {
             return self.parse_if_expr();
@@ -313,8 +379,9 @@ impl<'a> Parser<'a> {
             return Ok(Expr::Unary(UnOp::BitNot, Box::new(operand), span));
         }
         if self.match_tok(TokenKind::Amp) {
+            let op = if self.match_tok(TokenKind::Mut) { UnOp::RefMut } else { UnOp::Ref };
             let operand = self.parse_unary()?;
-            return Ok(Expr::Unary(UnOp::Ref, Box::new(operand), span));
+            return Ok(Expr::Unary(op, Box::new(operand), span));
         }
         if self.match_tok(TokenKind::Star) {
             let operand = self.parse_unary()?;
@@ -385,7 +452,7 @@ impl<'a> Parser<'a> {
         Ok(left)
     }
     
-    fn parse_expr(&mut self) -> Result<Expr> {
+    pub fn parse_expr(&mut self) -> Result<Expr> {
         self.parse_binary(1)
     }
     
@@ -410,6 +477,100 @@ impl<'a> Parser<'a> {
         Ok(Expr::If(Box::new(cond), Box::new(then_block), else_block, span))
     }
     
+    /// `{ ... }` where an expression is expected: the block's value, as `if true { ... }`
+    fn parse_block_expr(&mut self) -> Result<Expr> {
+        let span = self.span();
+        let block = self.parse_block()?;
+        Ok(Expr::If(Box::new(Expr::Bool(true, span)), Box::new(block), None, span))
+    }
+    
+    fn parse_lambda(&mut self) -> Result<Expr> {
+        let span = self.span();
+        let mut params = Vec::new();
+        if !self.match_tok(TokenKind::PipePipe) {
+            self.expect(TokenKind::Pipe)?;
+            while !self.check(TokenKind::Pipe) {
+                let param_span = self.span();
+                let name = self.expect(TokenKind::Ident)?.lexeme.clone();
+                let ty = if self.match_tok(TokenKind::Colon) { self.parse_type()? } else { Type::Infer };
+                params.push(Param { name, ty, default: None, comptime: false, mutable: false, span: param_span });
+                if !self.match_tok(TokenKind::Comma) { break; }
+            }
+            self.expect(TokenKind::Pipe)?;
+        }
+        let ret = if self.match_tok(TokenKind::Arrow) { Some(self.parse_type()?) } else { None };
+        let body = if self.check(TokenKind::LBrace) { self.parse_block_expr()? } else { self.parse_expr()? };
+        Ok(Expr::Lambda(params, ret, Box::new(body), span))
+    }
+    
+    fn parse_match(&mut self, span: Span) -> Result<Expr> {
+        let scrutinee = self.parse_expr()?;
+        self.expect(TokenKind::LBrace)?;
+        let mut arms = Vec::new();
+        while !self.check(TokenKind::RBrace) && !self.check(TokenKind::Eof) {
+            let pattern = self.parse_pattern()?;
+            let guard = if self.match_tok(TokenKind::If) { Some(self.parse_expr()?) } else { None };
+            self.expect(TokenKind::FatArrow)?;
+            let body = if self.check(TokenKind::LBrace) { self.parse_block_expr()? } else { self.parse_expr()? };
+            arms.push(MatchArm { pattern, guard, body });
+            self.match_tok(TokenKind::Comma);
+        }
+        self.expect(TokenKind::RBrace)?;
+        Ok(Expr::Match(Box::new(scrutinee), arms, span))
+    }
+    
+    fn parse_pattern(&mut self) -> Result<Pattern> {
+        let span = self.span();
+        if self.match_tok(TokenKind::LParen) {
+            let mut items = Vec::new();
+            while !self.check(TokenKind::RParen) {
+                items.push(self.parse_pattern()?);
+                if !self.match_tok(TokenKind::Comma) { break; }
+            }
+            self.expect(TokenKind::RParen)?;
+            return Ok(Pattern::Tuple(items));
+        }
+        if self.check(TokenKind::Ident) {
+            let name = self.advance().lexeme.clone();
+            if name == "_" {
+                return Ok(Pattern::Wildcard);
+            }
+            // Enum::Variant or Enum::Variant(a, b)
+            if self.match_tok(TokenKind::ColonColon) {
+                let variant = self.expect(TokenKind::Ident)?.lexeme.clone();
+                let mut items = Vec::new();
+                if self.match_tok(TokenKind::LParen) {
+                    while !self.check(TokenKind::RParen) {
+                        items.push(self.parse_pattern()?);
+                        if !self.match_tok(TokenKind::Comma) { break; }
+                    }
+                    self.expect(TokenKind::RParen)?;
+                }
+                return Ok(Pattern::Enum(name, variant, items));
+            }
+            // Point { x: a, y: _ }
+            if name.starts_with(|c: char| c.is_ascii_uppercase()) && self.match_tok(TokenKind::LBrace) {
+                let mut fields = Vec::new();
+                while !self.check(TokenKind::RBrace) {
+                    let field = self.expect(TokenKind::Ident)?.lexeme.clone();
+                    let pattern = if self.match_tok(TokenKind::Colon) { self.parse_pattern()? } else { Pattern::Ident(field.clone()) };
+                    fields.push((field, pattern));
+                    if !self.match_tok(TokenKind::Comma) { break; }
+                }
+                self.expect(TokenKind::RBrace)?;
+                return Ok(Pattern::Struct(name, fields));
+            }
+            return Ok(Pattern::Ident(name));
+        }
+        match self.parse_unary()? {
+            literal @ (Expr::Int(..) | Expr::Float(..) | Expr::String(..) | Expr::Bool(..)) => Ok(Pattern::Literal(literal)),
+            Expr::Unary(UnOp::Neg, inner, _) if matches!(inner.as_ref(), Expr::Int(..) | Expr::Float(..)) => {
+                Ok(Pattern::Literal(Expr::Unary(UnOp::Neg, inner, span)))
+            }
+            _ => Err(anyhow!("Expected a pattern at line {}", span.line)),
+        }
+    }
+    
     // ========== STATEMENT PARSING ==========
     
     fn parse_block(&mut self) -> Result<Block> {
@@ -425,7 +586,7 @@ impl<'a> Parser<'a> {
         Ok(Block { stmts, span })
     }
     
-    fn parse_stmt(&mut self) -> Result<Stmt> {
+    pub fn parse_stmt(&mut self) -> Result<Stmt> {
         let span = self.span();
         
         // Let
@@ -504,33 +665,6 @@ impl<'a> Parser<'a> {
             return Ok(Stmt::Break(span));
         }
         
-        // Match expression/statement
-        if self.match_tok(TokenKind::Match) {
-            let scrutinee = self.parse_expr()?;
-            self.expect(TokenKind::LBrace)?;
-            
-            // Parse match arms: Pattern => { ... }
-            let mut arms = Vec::new();
-            while !self.check(TokenKind::RBrace) {
-                // Pattern - could be path like Message::Query(x) or simple ident
-                let pattern = self.parse_expr()?;
-                self.expect(TokenKind::FatArrow)?;
-                let body = self.parse_block()?;
-                arms.push((pattern, body));
-            }
-            self.expect(TokenKind::RBrace)?;
-            
-            // Return match as a block with if-else chain for now
-            // In a full implementation, this would be Stmt::Match
-            let mut stmts = vec![];
-            for (pattern, body) in arms {
-                for s in body.stmts {
-                    stmts.push(s);
-                }
-            }
-            return Ok(Stmt::Block(Block { stmts, span }, span));
-        }
-        
         // Continue
         if self.match_tok(TokenKind::Continue) {
             return Ok(Stmt::Continue(span));
@@ -571,7 +705,7 @@ impl<'a> Parser<'a> {
         // Handle &self or &mut self syntax
         if self.check(TokenKind::Amp) {
             self.advance(); // consume '&'
-            let _mutable = self.match_tok(TokenKind::Mut);
+            let mutable = self.match_tok(TokenKind::Mut);
             // Accept both Ident and Self_ for self
             let name = if self.check(TokenKind::Self_) {
                 self.advance();
@@ -579,10 +713,12 @@ impl<'a> Parser<'a> {
             } else {
                 self.expect(TokenKind::Ident)?.lexeme.clone()
             };
-            let ty = Type::Ptr(Box::new(Type::Named("Self".into())));
-            return Ok(Param { name, ty, default: None, span });
+            let ty = Type::Ptr(Box::new(Type::Named("Self".into())), mutable);
+            return Ok(Param { name, ty, default: None, comptime: false, mutable, span });
         }
         
+        let comptime = self.match_tok(TokenKind::Comptime);
+        let mutable = self.match_tok(TokenKind::Mut);
         let name = self.expect(TokenKind::Ident)?.lexeme.clone();
         self.expect(TokenKind::Colon)?;
         let ty = self.parse_type()?;
@@ -591,11 +727,12 @@ impl<'a> Parser<'a> {
         } else {
             None
         };
-        Ok(Param { name, ty, default, span })
+        Ok(Param { name, ty, default, comptime, mutable, span })
     }
     
-    fn parse_func(&mut self, public: bool) -> Result<Decl> {
+    fn parse_func(&mut self, public: bool, constant: bool, attrs: Vec<Attribute>) -> Result<Decl> {
         let span = self.span();
+        let is_unsafe = self.match_tok(TokenKind::Unsafe);
         self.expect(TokenKind::Func)?;
         let name = self.expect(TokenKind::Ident)?.lexeme.clone();
         
@@ -603,7 +740,7 @@ impl<'a> Parser<'a> {
         let generics = if self.match_tok(TokenKind::Lt) {
             let mut gens = Vec::new();
             while !self.check(TokenKind::Gt) {
-                gens.push(self.expect(TokenKind::Ident)?.lexeme.clone());
+                gens.push(self.parse_generic_param()?);
                 if !self.check(TokenKind::Gt) {
                     self.expect(TokenKind::Comma)?;
                 }
@@ -635,10 +772,10 @@ impl<'a> Parser<'a> {
         // Body
         let body = self.parse_block()?;
         
-        Ok(Decl::Func { name, generics, params, ret, body, public, span })
+        Ok(Decl::Func { name, generics, params, ret, body, public, constant, is_unsafe, attrs, span })
     }
     
-    fn parse_struct(&mut self, public: bool) -> Result<Decl> {
+    fn parse_struct(&mut self, public: bool, attrs: Vec<Attribute>) -> Result<Decl> {
         let span = self.span();
         self.expect(TokenKind::Struct)?;
         let name = self.expect(TokenKind::Ident)?.lexeme.clone();
@@ -646,7 +783,7 @@ impl<'a> Parser<'a> {
         let generics = if self.match_tok(TokenKind::Lt) {
             let mut gens = Vec::new();
             while !self.check(TokenKind::Gt) {
-                gens.push(self.expect(TokenKind::Ident)?.lexeme.clone());
+                gens.push(self.parse_generic_param()?);
                 if !self.check(TokenKind::Gt) { self.expect(TokenKind::Comma)?; }
             }
             self.expect(TokenKind::Gt)?;
@@ -668,7 +805,7 @@ impl<'a> Parser<'a> {
         }
         self.expect(TokenKind::RBrace)?;
         
-        Ok(Decl::Struct { name, generics, fields, public, span })
+        Ok(Decl::Struct { name, generics, fields, public, attrs, span })
     }
     
     fn parse_enum(&mut self, public: bool) -> Result<Decl> {
@@ -679,7 +816,7 @@ impl<'a> Parser<'a> {
         let generics = if self.match_tok(TokenKind::Lt) {
             let mut gens = Vec::new();
             while !self.check(TokenKind::Gt) {
-                gens.push(self.expect(TokenKind::Ident)?.lexeme.clone());
+                gens.push(self.parse_generic_param()?);
                 if !self.check(TokenKind::Gt) { self.expect(TokenKind::Comma)?; }
             }
             self.expect(TokenKind::Gt)?;
@@ -742,8 +879,11 @@ impl<'a> Parser<'a> {
         Ok(Decl::Const { name, ty, value, public, span })
     }
     
-    fn parse_static(&mut self, public: bool) -> Result<Decl> {
+    fn parse_static(&mut self, public: bool, attrs: Vec<Attribute>) -> Result<Decl> {
         let span = self.span();
+        if let Some(attr) = attrs.iter().find(|a| a.name != "thread_local") {
+            return Err(anyhow!("Attribute #[{}] is not allowed on a static at line {}", attr.name, attr.span.line));
+        }
         self.expect(TokenKind::Let)?;
         let mutable = self.match_tok(TokenKind::Mut);
         let name = self.expect(TokenKind::Ident)?.lexeme.clone();
@@ -757,22 +897,64 @@ impl<'a> Parser<'a> {
         } else {
             None
         };
-        Ok(Decl::Static { name, ty, value, mutable, public, span })
+        Ok(Decl::Static { name, ty, value, mutable, public, attrs, span })
     }
     
-    fn parse_decl(&mut self) -> Result<Decl> {
+    fn parse_attributes(&mut self) -> Result<Vec<Attribute>> {
+        let mut attrs = Vec::new();
+        while self.check(TokenKind::Hash) {
+            let span = self.span();
+            self.advance();
+            self.expect(TokenKind::LBrack)?;
+            let name = self.expect(TokenKind::Ident)?.lexeme.clone();
+            let mut args = Vec::new();
+            if self.match_tok(TokenKind::LParen) {
+                while !self.check(TokenKind::RParen) && !self.check(TokenKind::Eof) {
+                    let tok = self.advance();
+                    args.push(tok.string_value.unwrap_or(tok.lexeme));
+                    if !self.check(TokenKind::RParen) {
+                        self.expect(TokenKind::Comma)?;
+                    }
+                }
+                self.expect(TokenKind::RParen)?;
+            }
+            self.expect(TokenKind::RBrack)?;
+            attrs.push(Attribute { name, args, span });
+        }
+        Ok(attrs)
+    }
+    
+    pub fn parse_decl(&mut self) -> Result<Decl> {
+        let attrs = self.parse_attributes()?;
         let public = self.match_tok(TokenKind::Pub);
         
+        // const func
+        let const_func = self.check(TokenKind::Const) && self.peek_second_kind() == TokenKind::Func;
+        if const_func {
+            self.advance();
+        }
+        
+        let unsafe_func = self.check(TokenKind::Unsafe) && self.peek_second_kind() == TokenKind::Func;
+        if !attrs.is_empty() && !unsafe_func && !matches!(self.peek_kind(), TokenKind::Func | TokenKind::Struct | TokenKind::Let) {
+            return Err(anyhow!("Attribute #[{}] is not allowed here at line {}", attrs[0].name, attrs[0].span.line));
+        }
+        
         match self.peek_kind() {
-            TokenKind::Func => self.parse_func(public),
-            TokenKind::Struct => self.parse_struct(public),
+            TokenKind::Func => self.parse_func(public, const_func, attrs),
+            TokenKind::Unsafe if unsafe_func => self.parse_func(public, const_func, attrs),
+            TokenKind::Struct => self.parse_struct(public, attrs),
             TokenKind::Enum => self.parse_enum(public),
             TokenKind::Import => self.parse_import(),
             TokenKind::Const => self.parse_const(public),
-            TokenKind::Let => self.parse_static(public),
+            TokenKind::Let => self.parse_static(public, attrs),
             TokenKind::Trait => self.parse_trait(public),
-            TokenKind::Impl => self.parse_impl(),
+            TokenKind::Impl => self.parse_impl(false),
+            TokenKind::Unsafe if self.peek_second_kind() == TokenKind::Impl => {
+                self.advance();
+                self.parse_impl(true)
+            }
             TokenKind::Type => self.parse_type_alias(public),
+            TokenKind::Extern => self.parse_extern(),
             _ => Err(anyhow!("Expected declaration at line {}", self.peek().line)),
         }
     }
@@ -785,7 +967,7 @@ impl<'a> Parser<'a> {
         let generics = if self.match_tok(TokenKind::Lt) {
             let mut gens = Vec::new();
             while !self.check(TokenKind::Gt) {
-                gens.push(self.expect(TokenKind::Ident)?.lexeme.clone());
+                gens.push(self.parse_generic_param()?);
                 if !self.check(TokenKind::Gt) { self.expect(TokenKind::Comma)?; }
             }
             self.expect(TokenKind::Gt)?;
@@ -797,21 +979,21 @@ impl<'a> Parser<'a> {
         self.expect(TokenKind::LBrace)?;
         let mut methods = Vec::new();
         while !self.check(TokenKind::RBrace) {
-            methods.push(self.parse_func(false)?);
+            methods.push(self.parse_func(false, false, Vec::new())?);
         }
         self.expect(TokenKind::RBrace)?;
         
         Ok(Decl::Trait { name, generics, methods, public, span })
     }
     
-    fn parse_impl(&mut self) -> Result<Decl> {
+    fn parse_impl(&mut self, is_unsafe: bool) -> Result<Decl> {
         let span = self.span();
         self.expect(TokenKind::Impl)?;
         
         let generics = if self.match_tok(TokenKind::Lt) {
             let mut gens = Vec::new();
             while !self.check(TokenKind::Gt) {
-                gens.push(self.expect(TokenKind::Ident)?.lexeme.clone());
+                gens.push(self.parse_generic_param()?);
                 if !self.check(TokenKind::Gt) { self.expect(TokenKind::Comma)?; }
             }
             self.expect(TokenKind::Gt)?;
@@ -820,18 +1002,74 @@ impl<'a> Parser<'a> {
             Vec::new()
         };
         
-        let type_name = self.expect(TokenKind::Ident)?.lexeme.clone();
+        // impl Trait for Type
+        let mut type_name = self.expect(TokenKind::Ident)?.lexeme.clone();
+        let mut trait_name = None;
+        if self.match_tok(TokenKind::For) {
+            trait_name = Some(type_name);
+            type_name = self.expect(TokenKind::Ident)?.lexeme.clone();
+        }
         
         self.expect(TokenKind::LBrace)?;
         let mut methods = Vec::new();
         while !self.check(TokenKind::RBrace) {
             // Methods inside impl can be pub
             let is_pub = self.match_tok(TokenKind::Pub);
-            methods.push(self.parse_func(is_pub)?);
+            methods.push(self.parse_func(is_pub, false, Vec::new())?);
+        }
+        self.expect(TokenKind::RBrace)?;
+        
+        Ok(Decl::Impl { trait_name, type_name, generics, methods, is_unsafe, span })
+    }
+    
+    fn parse_extern(&mut self) -> Result<Decl> {
+        let span = self.span();
+        self.expect(TokenKind::Extern)?;
+        
+        // Optional ABI string: extern "C" { ... }
+        let abi = if self.check(TokenKind::String) {
+            self.advance().string_value
+        } else {
+            None
+        };
+        
+        self.expect(TokenKind::LBrace)?;
+        let mut funcs = Vec::new();
+        while !self.check(TokenKind::RBrace) && !self.check(TokenKind::Eof) {
+            let func_span = self.span();
+            self.expect(TokenKind::Func)?;
+            let name = self.expect(TokenKind::Ident)?.lexeme.clone();
+            
+            self.expect(TokenKind::LParen)?;
+            let mut params = Vec::new();
+            let mut variadic = false;
+            while !self.check(TokenKind::RParen) {
+                // Varargs: ...
+                if self.match_tok(TokenKind::Dot) {
+                    self.expect(TokenKind::Dot)?;
+                    self.expect(TokenKind::Dot)?;
+                    variadic = true;
+                    break;
+                }
+                params.push(self.parse_param()?);
+                if !self.check(TokenKind::RParen) {
+                    self.expect(TokenKind::Comma)?;
+                }
+            }
+            self.expect(TokenKind::RParen)?;
+            
+            let ret = if self.match_tok(TokenKind::Arrow) {
+                Some(self.parse_type()?)
+            } else {
+                None
+            };
+            self.match_tok(TokenKind::Semi);
+            
+            funcs.push(ExternFunc { name, params, ret, variadic, span: func_span });
         }
         self.expect(TokenKind::RBrace)?;
         
-        Ok(Decl::Impl { trait_name: None, type_name, generics, methods, span })
+        Ok(Decl::Extern { abi, funcs, span })
     }
     
     fn parse_type_alias(&mut self, public: bool) -> Result<Decl> {
@@ -842,7 +1080,7 @@ impl<'a> Parser<'a> {
         let generics = if self.match_tok(TokenKind::Lt) {
             let mut gens = Vec::new();
             while !self.check(TokenKind::Gt) {
-                gens.push(self.expect(TokenKind::Ident)?.lexeme.clone());
+                gens.push(self.parse_generic_param()?);
                 if !self.check(TokenKind::Gt) { self.expect(TokenKind::Comma)?; }
             }
             self.expect(TokenKind::Gt)?;
@@ -47,9 +47,13 @@ impl Runtime {
             }
         }
         
-        // Allocate new from heap
+        // Allocate new from heap, growing it when full
         let ptr = self.heap_ptr;
         self.heap_ptr += aligned_size;
+        if self.heap_ptr > self.heap.len() {
+            let new_len = self.heap_ptr.max(self.heap.len() * 2);
+            self.heap.resize(new_len, 0);
+        }
         self.block_sizes.insert(ptr, aligned_size);
         ptr
     }
@@ -64,6 +68,29 @@ impl Runtime {
         }
     }
     
+    /// Borrow `len` heap bytes at `addr`, if they lie inside the heap
+    pub fn bytes(&self, addr: usize, len: usize) -> Option<&[u8]> {
+        self.heap.get(addr..addr.checked_add(len)?)
+    }
+    
+    /// Mutable view of `len` heap bytes at `addr`
+    pub fn bytes_mut(&mut self, addr: usize, len: usize) -> Option<&mut [u8]> {
+        self.heap.get_mut(addr..addr.checked_add(len)?)
+    }
+    
+    /// Load a little-endian integer of `width` bytes (1, 2, 4 or 8), zero-extended
+    pub fn load(&self, addr: usize, width: usize) -> Option<u64> {
+        let mut buf = [0u8; 8];
+        buf[..width].copy_from_slice(self.bytes(addr, width)?);
+        Some(u64::from_le_bytes(buf))
+    }
+    
+    /// Store the low `width` bytes of `value` little-endian
+    pub fn store(&mut self, addr: usize, width: usize, value: u64) -> Option<()> {
+        self.bytes_mut(addr, width)?.copy_from_slice(&value.to_le_bytes()[..width]);
+        Some(())
+    }
+    
     /// Reset allocator - instant cleanup
     pub fn reset(&mut self) {
         self.heap_ptr = 0;
@@ -0,0 +1,1392 @@
+//! Aether Bindgen - C header to Aether binding generator
+//! Parses a practical subset of C (functions, structs, unions, enums,
+//! typedefs, integer `#define`s) and emits `extern` blocks plus structs.
+//! Aether structs are one 8-byte word per field, so records C lays out any
+//! other way are emitted opaque, with their LP64 size and field offsets.
+
+use std::collections::HashMap;
+use std::path::Path;
+use anyhow::{anyhow, Result};
+
+// ============================================================================
+// C TYPES
+// ============================================================================
+
+/// C type as written in the header
+#[derive(Debug, Clone)]
+pub enum CType {
+    Void,
+    Bool,
+    /// Integer of `size` bytes
+    Int { size: usize, signed: bool },
+    /// Floating point of `size` bytes
+    Float(usize),
+    Ptr(Box<CType>),
+    /// Fixed array; `None` for flexible/unsized arrays
+    Array(Box<CType>, Option<usize>),
+    /// Function type (only behind pointers in practice)
+    Func(Vec<CType>, Box<CType>, bool),
+    /// Reference to a typedef name
+    Typedef(String),
+    Struct(String),
+    Union(String),
+    Enum(String),
+}
+
+/// Struct or union field
+#[derive(Debug, Clone)]
+pub struct CField {
+    pub name: String,
+    pub ty: CType,
+}
+
+/// Struct or union definition
+#[derive(Debug, Clone)]
+pub struct CRecord {
+    pub name: String,
+    pub fields: Vec<CField>,
+    pub is_union: bool,
+}
+
+/// Enum definition
+#[derive(Debug, Clone)]
+pub struct CEnum {
+    pub name: String,
+    pub values: Vec<(String, i64)>,
+}
+
+/// Function parameter; unnamed in abstract declarators
+pub type CParam = (Option<String>, CType);
+
+/// Function prototype
+#[derive(Debug, Clone)]
+pub struct CFunc {
+    pub name: String,
+    pub params: Vec<CParam>,
+    pub ret: CType,
+    pub variadic: bool,
+}
+
+/// Everything collected from one header
+#[derive(Debug, Default)]
+pub struct Bindings {
+    pub consts: Vec<(String, i64)>,
+    pub records: Vec<CRecord>,
+    pub enums: Vec<CEnum>,
+    pub typedefs: Vec<(String, CType)>,
+    pub funcs: Vec<CFunc>,
+    /// Declarations that were skipped, emitted as comments
+    pub warnings: Vec<String>,
+}
+
+impl Bindings {
+    fn record(&self, name: &str) -> Option<&CRecord> {
+        self.records.iter().find(|r| r.name == name)
+    }
+
+    fn typedef(&self, name: &str) -> Option<&CType> {
+        self.typedefs.iter().find(|(n, _)| n == name).map(|(_, t)| t)
+    }
+
+    /// Size and alignment of a type under the LP64 data model
+    pub fn layout(&self, ty: &CType) -> (usize, usize) {
+        match ty {
+            CType::Void => (0, 1),
+            CType::Bool => (1, 1),
+            CType::Int { size, .. } => (*size, *size),
+            CType::Float(size) => (*size, *size),
+            CType::Ptr(_) | CType::Func(..) => (8, 8),
+            CType::Array(elem, n) => {
+                let (size, align) = self.layout(elem);
+                (size * n.unwrap_or(0), align)
+            }
+            CType::Enum(_) => (4, 4),
+            CType::Typedef(name) => match self.typedef(name) {
+                Some(t) => self.layout(t),
+                None => (8, 8),
+            },
+            CType::Struct(name) | CType::Union(name) => match self.record(name) {
+                Some(r) => self.record_layout(r),
+                None => (0, 1),
+            },
+        }
+    }
+
+    /// Size and alignment of a struct or union, with trailing padding
+    pub fn record_layout(&self, record: &CRecord) -> (usize, usize) {
+        let mut size = 0;
+        let mut align = 1;
+        for field in &record.fields {
+            let (fs, fa) = self.layout(&field.ty);
+            align = align.max(fa);
+            if record.is_union {
+                size = size.max(fs);
+            } else {
+                size = align_to(size, fa) + fs;
+            }
+        }
+        (align_to(size, align), align)
+    }
+
+    /// Byte offset of each field (all 0 in a union)
+    pub fn field_offsets(&self, record: &CRecord) -> Vec<usize> {
+        let mut end = 0;
+        record.fields.iter()
+            .map(|field| {
+                if record.is_union {
+                    return 0;
+                }
+                let (size, align) = self.layout(&field.ty);
+                let offset = align_to(end, align);
+                end = offset + size;
+                offset
+            })
+            .collect()
+    }
+
+    /// Whether C lays the record out like an Aether struct: one 8-byte
+    /// scalar or pointer per field, in declaration order
+    pub fn word_layout(&self, record: &CRecord) -> bool {
+        !record.is_union && !record.fields.is_empty() && record.fields.iter().all(|f| self.is_word(&f.ty))
+    }
+
+    fn is_word(&self, ty: &CType) -> bool {
+        match ty {
+            CType::Int { size: 8, .. } | CType::Float(8) | CType::Ptr(_) => true,
+            CType::Typedef(name) => self.typedef(name).is_some_and(|t| self.is_word(t)),
+            _ => false,
+        }
+    }
+
+    /// C type in `ty` that Aether has no equivalent for
+    pub fn unsupported(&self, ty: &CType) -> Option<&'static str> {
+        match ty {
+            CType::Float(16) => Some("long double"),
+            CType::Ptr(inner) | CType::Array(inner, _) => self.unsupported(inner),
+            CType::Func(params, ret, _) => params.iter().chain([ret.as_ref()]).find_map(|t| self.unsupported(t)),
+            CType::Typedef(name) => self.typedef(name).and_then(|t| self.unsupported(t)),
+            _ => None,
+        }
+    }
+
+    /// Follow typedefs to the underlying type
+    fn resolve<'a>(&'a self, mut ty: &'a CType) -> &'a CType {
+        // Bounded in case of cyclic typedefs
+        for _ in 0..64 {
+            match ty {
+                CType::Typedef(name) => match self.typedef(name) {
+                    Some(t) => ty = t,
+                    None => break,
+                },
+                _ => break,
+            }
+        }
+        ty
+    }
+
+    /// Map a C type to the Aether type with the same size and alignment.
+    /// Aether struct values are pointers to their records, so a pointer to
+    /// a record with `word_layout` is the struct type itself.
+    pub fn aether_type(&self, ty: &CType) -> String {
+        match ty {
+            CType::Void => "UInt8".into(),
+            CType::Bool => "Bool".into(),
+            CType::Int { size, signed } => {
+                format!("{}{}", if *signed { "Int" } else { "UInt" }, size * 8)
+            }
+            CType::Float(4) => "Float32".into(),
+            CType::Float(_) => "Float".into(),
+            CType::Ptr(inner) => match self.resolve(inner) {
+                CType::Func(params, ret, _) => {
+                    let params: Vec<String> = params.iter().map(|p| self.aether_type(p)).collect();
+                    match ret.as_ref() {
+                        CType::Void => format!("func({})", params.join(", ")),
+                        r => format!("func({}) -> {}", params.join(", "), self.aether_type(r)),
+                    }
+                }
+                CType::Struct(name) if self.record(name).is_some_and(|r| self.word_layout(r)) => self.aether_type(inner),
+                _ => format!("*{}", self.aether_type(inner)),
+            },
+            CType::Array(elem, Some(n)) => format!("[{}; {}]", self.aether_type(elem), n),
+            CType::Array(elem, None) => format!("[{}]", self.aether_type(elem)),
+            CType::Func(..) => self.aether_type(&CType::Ptr(Box::new(ty.clone()))),
+            CType::Typedef(name) | CType::Struct(name) | CType::Union(name) => name.clone(),
+            CType::Enum(_) => "Int32".into(),
+        }
+    }
+}
+
+fn align_to(n: usize, align: usize) -> usize {
+    n.div_ceil(align) * align
+}
+
+// ============================================================================
+// C LEXER
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+enum CTok {
+    Ident(String),
+    Int(i64),
+    Float,
+    Str,
+    Punct(&'static str),
+}
+
+impl std::fmt::Display for CTok {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CTok::Ident(name) => write!(f, "'{}'", name),
+            CTok::Int(n) => write!(f, "'{}'", n),
+            CTok::Float => write!(f, "a floating-point literal"),
+            CTok::Str => write!(f, "a string literal"),
+            CTok::Punct(p) => write!(f, "'{}'", p),
+        }
+    }
+}
+
+/// A token for diagnostics, or the end of the header
+fn describe(tok: Option<&CTok>) -> String {
+    tok.map_or_else(|| "end of header".to_string(), |t| t.to_string())
+}
+
+#[derive(Debug, Clone)]
+struct CToken {
+    tok: CTok,
+    line: usize,
+}
+
+const PUNCTS: &[&str] = &[
+    "...", "<<", ">>", "->", "&&", "||", "==", "!=", "<=", ">=", "##",
+    "(", ")", "{", "}", "[", "]", ";", ",", "*", "&", "=", ":", "+", "-",
+    "/", "%", "|", "^", "~", "!", "<", ">", "?", ".", "#",
+];
+
+/// Strip comments and join backslash-continued lines
+fn strip_comments(src: &str) -> String {
+    let mut out = String::with_capacity(src.len());
+    let mut chars = src.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'/') => {
+                while let Some(&n) = chars.peek() {
+                    if n == '\n' { break; }
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                for n in chars.by_ref() {
+                    if n == '\n' { out.push('\n'); }
+                    if prev == '*' && n == '/' { break; }
+                    prev = n;
+                }
+                out.push(' ');
+            }
+            '"' | '\'' => {
+                out.push(c);
+                while let Some(n) = chars.next() {
+                    out.push(n);
+                    if n == '\\' {
+                        if let Some(e) = chars.next() { out.push(e); }
+                    } else if n == c || n == '\n' {
+                        break;
+                    }
+                }
+            }
+            '\\' if chars.peek() == Some(&'\n') => {
+                // Line continuation: join with the next line
+                chars.next();
+                out.push(' ');
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Parse a C integer literal (decimal, hex, octal, with u/l suffixes)
+fn parse_c_int(text: &str) -> Option<i64> {
+    let digits = text.trim_end_matches(['u', 'U', 'l', 'L']);
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).ok()?
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        u64::from_str_radix(&digits[1..], 8).ok()?
+    } else {
+        digits.parse::<u64>().ok()?
+    };
+    Some(value as i64)
+}
+
+fn tokenize_c(src: &str, first_line: usize) -> Vec<CToken> {
+    let mut tokens = Vec::new();
+    let bytes: Vec<char> = src.chars().collect();
+    let mut i = 0;
+    let mut line = first_line;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c == '\n' {
+            line += 1;
+            i += 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == '_') {
+                i += 1;
+            }
+            let text: String = bytes[start..i].iter().collect();
+            // String prefixes (L"...", u8"...") belong to the literal
+            if i < bytes.len() && bytes[i] == '"' && matches!(text.as_str(), "L" | "u" | "U" | "u8") {
+                continue;
+            }
+            tokens.push(CToken { tok: CTok::Ident(text), line });
+            continue;
+        }
+
+        if c.is_ascii_digit() || (c == '.' && bytes.get(i + 1).is_some_and(|d| d.is_ascii_digit())) {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == '.' || bytes[i] == '_') {
+                // Exponent sign: 1e-5
+                if (bytes[i] == 'e' || bytes[i] == 'E') && !bytes[start..i].iter().any(|&b| b == 'x' || b == 'X') {
+                    if let Some('+') | Some('-') = bytes.get(i + 1) {
+                        i += 1;
+                    }
+                }
+                i += 1;
+            }
+            let text: String = bytes[start..i].iter().collect();
+            let tok = match parse_c_int(&text) {
+                Some(v) => CTok::Int(v),
+                None => CTok::Float,
+            };
+            tokens.push(CToken { tok, line });
+            continue;
+        }
+
+        if c == '"' || c == '\'' {
+            let start = i;
+            i += 1;
+            while i < bytes.len() && bytes[i] != c && bytes[i] != '\n' {
+                if bytes[i] == '\\' { i += 1; }
+                i += 1;
+            }
+            i += 1;
+            if c == '"' {
+                tokens.push(CToken { tok: CTok::Str, line });
+            } else {
+                let body: String = bytes[start + 1..(i - 1).min(bytes.len())].iter().collect();
+                let value = match body.as_str() {
+                    "\\n" => 10,
+                    "\\t" => 9,
+                    "\\r" => 13,
+                    "\\0" => 0,
+                    "\\\\" => 92,
+                    "\\'" => 39,
+                    _ => body.chars().last().map_or(0, |ch| ch as i64),
+                };
+                tokens.push(CToken { tok: CTok::Int(value), line });
+            }
+            continue;
+        }
+
+        let rest: String = bytes[i..(i + 3).min(bytes.len())].iter().collect();
+        match PUNCTS.iter().find(|p| rest.starts_with(**p)) {
+            Some(p) => {
+                tokens.push(CToken { tok: CTok::Punct(p), line });
+                i += p.len();
+            }
+            None => i += 1,
+        }
+    }
+
+    tokens
+}
+
+// ============================================================================
+// C PARSER
+// ============================================================================
+
+/// Declarator suffix/prefix operations, applied to the base type in order
+#[derive(Debug, Clone)]
+enum DeclOp {
+    Ptr,
+    Array(Option<usize>),
+    Func(Vec<CParam>, bool),
+}
+
+/// Qualifiers and storage classes that carry no layout information
+const IGNORED_WORDS: &[&str] = &[
+    "const", "volatile", "restrict", "__restrict", "__restrict__", "register",
+    "inline", "__inline", "__inline__", "__extension__", "_Noreturn", "auto",
+    "__const", "__volatile__", "_Nullable", "_Nonnull", "__unaligned",
+];
+
+struct CParser {
+    tokens: Vec<CToken>,
+    pos: usize,
+    out: Bindings,
+    /// Known typedef names (needed to tell types from declarator names)
+    typedef_names: HashMap<String, ()>,
+    anon_counter: usize,
+}
+
+/// Parsed declaration specifiers
+struct Specifiers {
+    ty: CType,
+    is_typedef: bool,
+    is_static: bool,
+}
+
+impl CParser {
+    fn new(tokens: Vec<CToken>) -> Self {
+        let mut typedef_names = HashMap::new();
+        for (name, _) in builtin_typedefs() {
+            typedef_names.insert(name.to_string(), ());
+        }
+        CParser {
+            tokens,
+            pos: 0,
+            out: Bindings::default(),
+            typedef_names,
+            anon_counter: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<&CTok> {
+        self.tokens.get(self.pos).map(|t| &t.tok)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&CTok> {
+        self.tokens.get(self.pos + offset).map(|t| &t.tok)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens.get(self.pos)
+            .or_else(|| self.tokens.last())
+            .map_or(0, |t| t.line)
+    }
+
+    fn advance(&mut self) -> Option<CTok> {
+        let tok = self.peek().cloned();
+        self.pos += 1;
+        tok
+    }
+
+    fn is_punct(&self, p: &str) -> bool {
+        matches!(self.peek(), Some(CTok::Punct(q)) if *q == p)
+    }
+
+    fn is_word(&self, w: &str) -> bool {
+        matches!(self.peek(), Some(CTok::Ident(i)) if i == w)
+    }
+
+    fn match_punct(&mut self, p: &str) -> bool {
+        if self.is_punct(p) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_punct(&mut self, p: &str) -> Result<()> {
+        if self.match_punct(p) {
+            Ok(())
+        } else {
+            Err(anyhow!("Expected '{}', got {} at line {}", p, describe(self.peek()), self.line()))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.advance() {
+            Some(CTok::Ident(name)) => Ok(name),
+            other => Err(anyhow!("Expected identifier, got {} at line {}", describe(other.as_ref()), self.line())),
+        }
+    }
+
+    /// Skip a balanced (), [] or {} group starting at the current token
+    fn skip_group(&mut self) {
+        let (open, close) = match self.peek() {
+            Some(CTok::Punct("(")) => ("(", ")"),
+            Some(CTok::Punct("[")) => ("[", "]"),
+            Some(CTok::Punct("{")) => ("{", "}"),
+            _ => return,
+        };
+        let mut depth = 0;
+        while let Some(tok) = self.advance() {
+            if tok == CTok::Punct(open) {
+                depth += 1;
+            } else if tok == CTok::Punct(close) {
+                depth -= 1;
+                if depth == 0 { break; }
+            }
+        }
+    }
+
+    /// Skip to the end of the current declaration (past `;` or a body)
+    fn skip_declaration(&mut self) {
+        while let Some(tok) = self.peek() {
+            match tok {
+                CTok::Punct(";") => {
+                    self.pos += 1;
+                    return;
+                }
+                CTok::Punct("{") | CTok::Punct("(") | CTok::Punct("[") => {
+                    let is_body = self.is_punct("{");
+                    self.skip_group();
+                    if is_body && !self.is_punct(";") && !self.is_punct(",") {
+                        return;
+                    }
+                }
+                _ => self.pos += 1,
+            }
+        }
+    }
+
+    /// Skip `__attribute__((...))`, `__declspec(...)` and `__asm__("...")`
+    fn skip_attributes(&mut self) {
+        while let Some(CTok::Ident(w)) = self.peek() {
+            if matches!(w.as_str(), "__attribute__" | "__attribute" | "__declspec" | "__asm__" | "__asm" | "asm") {
+                self.pos += 1;
+                self.skip_group();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Skip attribute-like macros after a declarator (`__THROW`, `__nonnull((1))`).
+    /// Only `;`, `,`, `=`, `:` or a body can legally follow a declarator.
+    /// Returns the skipped names, each with whether it took arguments.
+    fn skip_trailing_macros(&mut self) -> Vec<(String, bool)> {
+        let mut skipped = Vec::new();
+        while let Some(CTok::Ident(name)) = self.peek() {
+            let name = name.clone();
+            self.pos += 1;
+            let call = self.is_punct("(");
+            self.skip_group();
+            skipped.push((name, call));
+        }
+        skipped
+    }
+    
+    // ========== SPECIFIERS ==========
+
+    fn parse_specifiers(&mut self) -> Result<Specifiers> {
+        let mut is_typedef = false;
+        let mut is_static = false;
+        let mut signed: Option<bool> = None;
+        let mut longs = 0;
+        let mut base: Option<&str> = None;
+        let mut ty: Option<CType> = None;
+        // Unknown identifier taken as a type name (from a header we didn't see)
+        let mut guessed = false;
+
+        loop {
+            self.skip_attributes();
+            let word = match self.peek() {
+                Some(CTok::Ident(w)) => w.clone(),
+                _ => break,
+            };
+            match word.as_str() {
+                "typedef" => is_typedef = true,
+                "static" => is_static = true,
+                "extern" => {}
+                w if IGNORED_WORDS.contains(&w) => {}
+                "signed" | "__signed__" => signed = Some(true),
+                "unsigned" => signed = Some(false),
+                "long" => longs += 1,
+                "short" => base = Some("short"),
+                "char" => base = Some("char"),
+                "int" => {
+                    if base.is_none() { base = Some("int"); }
+                }
+                "float" => base = Some("float"),
+                "double" => base = Some("double"),
+                "void" => base = Some("void"),
+                "_Bool" | "bool" => base = Some("bool"),
+                "struct" | "union" => {
+                    self.pos += 1;
+                    ty = Some(self.parse_record(word == "union")?);
+                    continue;
+                }
+                "enum" => {
+                    self.pos += 1;
+                    ty = Some(self.parse_enum()?);
+                    continue;
+                }
+                w if ty.is_none() && base.is_none() && signed.is_none() && longs == 0
+                    && self.typedef_names.contains_key(w) => {
+                    ty = Some(resolve_builtin_typedef(w).unwrap_or_else(|| CType::Typedef(word.clone())));
+                }
+                _ if ty.is_none() && base.is_none() && signed.is_none() && longs == 0
+                    && matches!(self.peek_at(1), Some(CTok::Ident(_)) | Some(CTok::Punct("*"))) => {
+                    ty = Some(CType::Typedef(word.clone()));
+                    guessed = true;
+                }
+                _ => break,
+            }
+            self.pos += 1;
+        }
+
+        // A guessed name followed by real type keywords was a macro, not a type
+        if guessed && (base.is_some() || signed.is_some() || longs > 0) {
+            ty = None;
+        }
+
+        let ty = match ty {
+            Some(t) => t,
+            None => match (base, longs) {
+                (Some("void"), _) => CType::Void,
+                (Some("bool"), _) => CType::Bool,
+                (Some("char"), _) => CType::Int { size: 1, signed: signed.unwrap_or(true) },
+                (Some("short"), _) => CType::Int { size: 2, signed: signed.unwrap_or(true) },
+                (Some("float"), _) => CType::Float(4),
+                (Some("double"), 0) => CType::Float(8),
+                (Some("double"), _) => CType::Float(16),
+                (_, 0) if base.is_some() || signed.is_some() => {
+                    CType::Int { size: 4, signed: signed.unwrap_or(true) }
+                }
+                (_, 0) => {
+                    return Err(anyhow!("Expected type, got {} at line {}", describe(self.peek()), self.line()));
+                }
+                _ => CType::Int { size: 8, signed: signed.unwrap_or(true) },
+            },
+        };
+
+        Ok(Specifiers { ty, is_typedef, is_static })
+    }
+
+    fn anon_name(&mut self, kind: &str) -> String {
+        self.anon_counter += 1;
+        format!("__anon_{}{}", kind, self.anon_counter)
+    }
+
+    /// Parse `struct [tag] [{ fields }]` after the keyword
+    fn parse_record(&mut self, is_union: bool) -> Result<CType> {
+        self.skip_attributes();
+        let tag = if let Some(CTok::Ident(_)) = self.peek() {
+            Some(self.ident()?)
+        } else {
+            None
+        };
+        self.skip_attributes();
+
+        let name = match tag {
+            Some(t) => t,
+            None => self.anon_name(if is_union { "union" } else { "struct" }),
+        };
+        let make = |n: String| if is_union { CType::Union(n) } else { CType::Struct(n) };
+
+        if !self.match_punct("{") {
+            return Ok(make(name));
+        }
+
+        let mut fields = Vec::new();
+        while !self.is_punct("}") && self.peek().is_some() {
+            if self.match_punct(";") {
+                continue;
+            }
+            let spec = self.parse_specifiers()?;
+
+            // Anonymous nested struct/union member
+            if self.is_punct(";") {
+                if let CType::Struct(n) | CType::Union(n) = &spec.ty {
+                    if n.starts_with("__anon_") {
+                        let field_name = format!("_{}", n.trim_start_matches("__anon_"));
+                        fields.push(CField { name: field_name, ty: spec.ty.clone() });
+                    }
+                }
+                self.pos += 1;
+                continue;
+            }
+
+            loop {
+                let (field_name, ops) = self.parse_declarator()?;
+                self.skip_trailing_macros();
+                if self.match_punct(":") {
+                    // Bitfields have no portable layout; make the whole record opaque
+                    if !fields.iter().any(|f| f.name == "__bitfield") {
+                        self.out.warnings.push(format!("{} {}: bitfields are not supported, emitted as opaque",
+                            if is_union { "union" } else { "struct" }, name));
+                    }
+                    while !self.is_punct(";") && self.peek().is_some() { self.pos += 1; }
+                    fields.push(CField { name: "__bitfield".into(), ty: CType::Void });
+                    break;
+                }
+                let field_name = field_name
+                    .ok_or_else(|| anyhow!("Expected field name at line {}", self.line()))?;
+                fields.push(CField { name: field_name, ty: apply_ops(spec.ty.clone(), ops) });
+                if !self.match_punct(",") {
+                    break;
+                }
+            }
+            self.expect_punct(";")?;
+        }
+        self.expect_punct("}")?;
+        self.skip_attributes();
+
+        if fields.iter().any(|f| f.name == "__bitfield") {
+            fields.clear();
+        }
+        self.out.records.retain(|r| r.name != name);
+        self.out.records.push(CRecord { name: name.clone(), fields, is_union });
+        Ok(make(name))
+    }
+
+    /// Parse `enum [tag] [{ A, B = expr }]` after the keyword
+    fn parse_enum(&mut self) -> Result<CType> {
+        self.skip_attributes();
+        let name = if let Some(CTok::Ident(_)) = self.peek() {
+            self.ident()?
+        } else {
+            self.anon_name("enum")
+        };
+
+        // C23 fixed underlying type: enum E : uint8_t { ... }
+        if self.match_punct(":") {
+            self.parse_specifiers()?;
+        }
+
+        if !self.match_punct("{") {
+            return Ok(CType::Enum(name));
+        }
+
+        let mut values = Vec::new();
+        let mut next = 0i64;
+        while !self.is_punct("}") && self.peek().is_some() {
+            let item = self.ident()?;
+            self.skip_attributes();
+            if self.match_punct("=") {
+                let start = self.pos;
+                while !self.is_punct(",") && !self.is_punct("}") && self.peek().is_some() {
+                    self.pos += 1;
+                }
+                let line = self.line();
+                next = eval_const(&self.tokens[start..self.pos], &self.out.consts)
+                    .ok_or_else(|| anyhow!("Cannot evaluate value of enumerator {} at line {}", item, line))?;
+            }
+            values.push((item.clone(), next));
+            self.out.consts.push((item, next));
+            next = next.wrapping_add(1);
+            if !self.match_punct(",") {
+                break;
+            }
+        }
+        self.expect_punct("}")?;
+
+        self.out.enums.retain(|e| e.name != name);
+        self.out.enums.push(CEnum { name: name.clone(), values });
+        Ok(CType::Enum(name))
+    }
+
+    // ========== DECLARATORS ==========
+
+    /// Parse a (possibly abstract) declarator, returning its name and the
+    /// operations to apply to the base type, innermost last
+    fn parse_declarator(&mut self) -> Result<(Option<String>, Vec<DeclOp>)> {
+        self.skip_attributes();
+        let mut pointers = 0;
+        while self.match_punct("*") {
+            pointers += 1;
+            while let Some(CTok::Ident(w)) = self.peek() {
+                if IGNORED_WORDS.contains(&w.as_str()) { self.pos += 1; } else { break; }
+            }
+            self.skip_attributes();
+        }
+
+        // Grouped declarator: (*name)(...)
+        let grouped = self.is_punct("(")
+            && matches!(self.peek_at(1), Some(CTok::Punct("*")) | Some(CTok::Punct("^")) | Some(CTok::Punct("(")));
+        let (name, inner) = if grouped {
+            self.pos += 1;
+            if self.is_punct("^") {
+                // Clang blocks are pointer-sized
+                self.tokens[self.pos].tok = CTok::Punct("*");
+            }
+            let inner = self.parse_declarator()?;
+            self.expect_punct(")")?;
+            inner
+        } else if let Some(CTok::Ident(w)) = self.peek() {
+            if IGNORED_WORDS.contains(&w.as_str()) {
+                self.pos += 1;
+                return self.parse_declarator().map(|(n, mut ops)| {
+                    ops.splice(0..0, std::iter::repeat_n(DeclOp::Ptr, pointers));
+                    (n, ops)
+                });
+            }
+            (Some(self.ident()?), Vec::new())
+        } else {
+            (None, Vec::new())
+        };
+
+        let mut suffixes = Vec::new();
+        loop {
+            self.skip_attributes();
+            if self.match_punct("[") {
+                let start = self.pos;
+                while !self.is_punct("]") && self.peek().is_some() {
+                    self.pos += 1;
+                }
+                let size = if start == self.pos {
+                    None
+                } else {
+                    let line = self.line();
+                    let n = eval_const(&self.tokens[start..self.pos], &self.out.consts)
+                        .ok_or_else(|| anyhow!("Cannot evaluate array size at line {}", line))?;
+                    Some(n as usize)
+                };
+                self.expect_punct("]")?;
+                suffixes.push(DeclOp::Array(size));
+            } else if self.is_punct("(") {
+                self.pos += 1;
+                let (params, variadic) = self.parse_params()?;
+                suffixes.push(DeclOp::Func(params, variadic));
+            } else {
+                break;
+            }
+        }
+
+        let mut ops: Vec<DeclOp> = std::iter::repeat_n(DeclOp::Ptr, pointers).collect();
+        ops.extend(suffixes.into_iter().rev());
+        ops.extend(inner);
+        Ok((name, ops))
+    }
+
+    /// Parse a parameter list after the opening paren
+    fn parse_params(&mut self) -> Result<(Vec<CParam>, bool)> {
+        let mut params = Vec::new();
+        let mut variadic = false;
+
+        // (void) means no parameters
+        if self.is_word("void") && matches!(self.peek_at(1), Some(CTok::Punct(")"))) {
+            self.pos += 2;
+            return Ok((params, false));
+        }
+
+        while !self.is_punct(")") && self.peek().is_some() {
+            if self.match_punct("...") {
+                variadic = true;
+                continue;
+            }
+            let spec = self.parse_specifiers()?;
+            let (name, ops) = self.parse_declarator()?;
+            // Arrays and functions decay to pointers in parameter position
+            let ty = match apply_ops(spec.ty, ops) {
+                CType::Array(elem, _) => CType::Ptr(elem),
+                f @ CType::Func(..) => CType::Ptr(Box::new(f)),
+                t => t,
+            };
+            params.push((name, ty));
+            if !self.match_punct(",") {
+                break;
+            }
+        }
+        self.expect_punct(")")?;
+        Ok((params, variadic))
+    }
+
+    // ========== TOP LEVEL ==========
+
+    fn parse_external_decl(&mut self) -> Result<()> {
+        // extern "C" { ... } wrappers: drop the wrapper, keep the contents
+        if self.is_word("extern") && matches!(self.peek_at(1), Some(CTok::Str)) {
+            self.pos += 2;
+            self.match_punct("{");
+            return Ok(());
+        }
+        if self.match_punct(";") || self.match_punct("}") {
+            return Ok(());
+        }
+        if self.is_word("_Static_assert") || self.is_word("static_assert") {
+            self.skip_declaration();
+            return Ok(());
+        }
+
+        let spec = self.parse_specifiers()?;
+        if self.match_punct(";") {
+            return Ok(());
+        }
+
+        loop {
+            let line = self.line();
+            let (name, mut ops) = self.parse_declarator()?;
+            let macros = self.skip_trailing_macros();
+            let name = name.ok_or_else(|| anyhow!("Expected declarator name at line {}", self.line()))?;
+
+            // The last operation decides what is declared: `int *f(int a)` is a function
+            let func = match ops.last() {
+                Some(DeclOp::Func(..)) if !spec.is_typedef => ops.pop(),
+                _ => None,
+            };
+
+            if let Some(DeclOp::Func(params, variadic)) = func {
+                let ret = apply_ops(spec.ty.clone(), ops);
+                if self.is_punct("{") {
+                    // Inline definition: no exported symbol when static
+                    self.skip_group();
+                    if spec.is_static {
+                        return Ok(());
+                    }
+                }
+                if !spec.is_static && !self.out.funcs.iter().any(|f| f.name == name) {
+                    self.out.funcs.push(CFunc { name, params, ret, variadic });
+                }
+                if !self.is_punct(",") && !self.is_punct(";") {
+                    return Ok(());
+                }
+            } else if spec.is_typedef {
+                let ty = apply_ops(spec.ty.clone(), ops);
+                self.typedef_names.insert(name.clone(), ());
+                // typedef struct { ... } Name: give the anonymous record the typedef's name
+                match &ty {
+                    CType::Struct(tag) | CType::Union(tag) if tag.starts_with("__anon_") => {
+                        if let Some(r) = self.out.records.iter_mut().find(|r| &r.name == tag) {
+                            r.name = name.clone();
+                        }
+                        rename_record(&mut self.out, tag, &name);
+                    }
+                    CType::Enum(tag) if tag.starts_with("__anon_") => {
+                        if let Some(e) = self.out.enums.iter_mut().find(|e| &e.name == tag) {
+                            e.name = name.clone();
+                        }
+                    }
+                    _ => self.out.typedefs.push((name.clone(), ty)),
+                }
+            } else if let Some(call) = macros.iter().position(|(_, call)| *call) {
+                // `int ZEXPORT deflate OF((z_streamp strm, int flush))`: the
+                // prototype is hidden behind macros we do not expand, and the
+                // name is the word before the first macro call
+                let mut words: Vec<String> = std::iter::once(name).chain(macros.into_iter().map(|(m, _)| m)).collect();
+                let func = words.remove(call);
+                self.out.warnings.push(format!("declaration {} at line {}: wrapped in macros {}, skipped (preprocess the header with `cc -E` first)",
+                    func, line, words.join(", ")));
+            } else {
+                self.out.warnings.push(format!("global variable {}: not supported, skipped", name));
+                if self.match_punct("=") {
+                    while !self.is_punct(",") && !self.is_punct(";") && self.peek().is_some() {
+                        if self.is_punct("{") { self.skip_group(); } else { self.pos += 1; }
+                    }
+                }
+            }
+
+            if !self.match_punct(",") {
+                break;
+            }
+        }
+        self.expect_punct(";")
+    }
+}
+
+/// Retarget typedefs and fields that referred to an anonymous record
+fn rename_record(out: &mut Bindings, from: &str, to: &str) {
+    fn rename(ty: &mut CType, from: &str, to: &str) {
+        match ty {
+            CType::Struct(n) | CType::Union(n) if n == from => *n = to.to_string(),
+            CType::Ptr(inner) | CType::Array(inner, _) => rename(inner, from, to),
+            CType::Func(params, ret, _) => {
+                for p in params { rename(p, from, to); }
+                rename(ret, from, to);
+            }
+            _ => {}
+        }
+    }
+    for r in &mut out.records {
+        for f in &mut r.fields { rename(&mut f.ty, from, to); }
+    }
+    for (_, t) in &mut out.typedefs {
+        rename(t, from, to);
+    }
+}
+
+/// Add empty records for structs that are only ever used behind pointers
+fn declare_opaque_records(out: &mut Bindings) {
+    fn collect(ty: &CType, names: &mut Vec<(String, bool)>) {
+        match ty {
+            CType::Struct(n) => names.push((n.clone(), false)),
+            CType::Union(n) => names.push((n.clone(), true)),
+            CType::Ptr(inner) | CType::Array(inner, _) => collect(inner, names),
+            CType::Func(params, ret, _) => {
+                for p in params { collect(p, names); }
+                collect(ret, names);
+            }
+            _ => {}
+        }
+    }
+
+    let mut names = Vec::new();
+    for f in &out.funcs {
+        for (_, t) in &f.params { collect(t, &mut names); }
+        collect(&f.ret, &mut names);
+    }
+    for (_, t) in &out.typedefs {
+        collect(t, &mut names);
+    }
+    for r in &out.records {
+        for f in &r.fields { collect(&f.ty, &mut names); }
+    }
+
+    for (name, is_union) in names {
+        if out.record(&name).is_none() {
+            out.records.push(CRecord { name, fields: Vec::new(), is_union });
+        }
+    }
+}
+
+fn apply_ops(base: CType, ops: Vec<DeclOp>) -> CType {
+    ops.into_iter().fold(base, |ty, op| match op {
+        DeclOp::Ptr => CType::Ptr(Box::new(ty)),
+        DeclOp::Array(n) => CType::Array(Box::new(ty), n),
+        DeclOp::Func(params, variadic) => {
+            CType::Func(params.into_iter().map(|(_, t)| t).collect(), Box::new(ty), variadic)
+        }
+    })
+}
+
+/// Fixed-width typedefs from <stdint.h>/<stddef.h> that need no definition
+fn builtin_typedefs() -> Vec<(&'static str, CType)> {
+    let int = |size, signed| CType::Int { size, signed };
+    vec![
+        ("int8_t", int(1, true)), ("int16_t", int(2, true)),
+        ("int32_t", int(4, true)), ("int64_t", int(8, true)),
+        ("uint8_t", int(1, false)), ("uint16_t", int(2, false)),
+        ("uint32_t", int(4, false)), ("uint64_t", int(8, false)),
+        ("size_t", int(8, false)), ("ssize_t", int(8, true)),
+        ("ptrdiff_t", int(8, true)), ("intptr_t", int(8, true)),
+        ("uintptr_t", int(8, false)), ("off_t", int(8, true)),
+        ("intmax_t", int(8, true)), ("uintmax_t", int(8, false)),
+        ("pid_t", int(4, true)), ("uid_t", int(4, false)), ("gid_t", int(4, false)),
+        ("mode_t", int(4, false)), ("socklen_t", int(4, false)),
+        ("time_t", int(8, true)), ("wchar_t", int(4, true)),
+        ("va_list", CType::Ptr(Box::new(CType::Void))),
+        ("__builtin_va_list", CType::Ptr(Box::new(CType::Void))),
+        ("FILE", CType::Struct("FILE".into())),
+    ]
+}
+
+fn resolve_builtin_typedef(name: &str) -> Option<CType> {
+    builtin_typedefs().into_iter().find(|(n, _)| *n == name).map(|(_, t)| t)
+}
+
+// ============================================================================
+// CONSTANT EXPRESSIONS
+// ============================================================================
+
+/// Evaluate an integer constant expression (for #define, enums, array sizes)
+fn eval_const(tokens: &[CToken], consts: &[(String, i64)]) -> Option<i64> {
+    let mut pos = 0;
+    let value = eval_binary(tokens, &mut pos, consts, 0)?;
+    if pos == tokens.len() { Some(value) } else { None }
+}
+
+fn binop_prec(p: &str) -> Option<u8> {
+    Some(match p {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | "<=" | ">" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        _ => return None,
+    })
+}
+
+fn eval_binary(tokens: &[CToken], pos: &mut usize, consts: &[(String, i64)], min_prec: u8) -> Option<i64> {
+    let mut left = eval_unary(tokens, pos, consts)?;
+    while let Some(CTok::Punct(p)) = tokens.get(*pos).map(|t| &t.tok) {
+        let prec = match binop_prec(p) {
+            Some(prec) if prec >= min_prec => prec,
+            _ => break,
+        };
+        let op = *p;
+        *pos += 1;
+        let right = eval_binary(tokens, pos, consts, prec + 1)?;
+        left = match op {
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" => left.checked_div(right)?,
+            "%" => left.checked_rem(right)?,
+            "<<" => left.wrapping_shl(right as u32),
+            ">>" => left.wrapping_shr(right as u32),
+            "&" => left & right,
+            "|" => left | right,
+            "^" => left ^ right,
+            "==" => (left == right) as i64,
+            "!=" => (left != right) as i64,
+            "<" => (left < right) as i64,
+            "<=" => (left <= right) as i64,
+            ">" => (left > right) as i64,
+            ">=" => (left >= right) as i64,
+            "&&" => (left != 0 && right != 0) as i64,
+            "||" => (left != 0 || right != 0) as i64,
+            _ => return None,
+        };
+    }
+    Some(left)
+}
+
+fn eval_unary(tokens: &[CToken], pos: &mut usize, consts: &[(String, i64)]) -> Option<i64> {
+    let tok = tokens.get(*pos)?.tok.clone();
+    *pos += 1;
+    match tok {
+        CTok::Int(v) => Some(v),
+        CTok::Ident(name) => consts.iter().rev().find(|(n, _)| *n == name).map(|(_, v)| *v),
+        CTok::Punct("-") => eval_unary(tokens, pos, consts).map(i64::wrapping_neg),
+        CTok::Punct("+") => eval_unary(tokens, pos, consts),
+        CTok::Punct("~") => eval_unary(tokens, pos, consts).map(|v| !v),
+        CTok::Punct("!") => eval_unary(tokens, pos, consts).map(|v| (v == 0) as i64),
+        CTok::Punct("(") => {
+            // Integer casts: (int)X, (unsigned long)X
+            let is_cast = matches!(tokens.get(*pos).map(|t| &t.tok), Some(CTok::Ident(w))
+                if matches!(w.as_str(), "int" | "unsigned" | "signed" | "long" | "short" | "char")
+                    || resolve_builtin_typedef(w).is_some());
+            if is_cast {
+                while !matches!(tokens.get(*pos).map(|t| &t.tok), Some(CTok::Punct(")")) | None) {
+                    *pos += 1;
+                }
+                *pos += 1;
+                return eval_unary(tokens, pos, consts);
+            }
+            let v = eval_binary(tokens, pos, consts, 1)?;
+            match tokens.get(*pos).map(|t| &t.tok) {
+                Some(CTok::Punct(")")) => {
+                    *pos += 1;
+                    Some(v)
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// ============================================================================
+// PREPROCESSOR
+// ============================================================================
+
+/// Split the header into declaration text and `#define` constants.
+/// Conditional directives are not evaluated: both branches are kept.
+fn preprocess(src: &str, out: &mut Bindings) -> String {
+    let stripped = strip_comments(src);
+    let mut code = String::with_capacity(stripped.len());
+
+    for (idx, line) in stripped.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if let Some(directive) = trimmed.strip_prefix('#') {
+            let directive = directive.trim_start();
+            if let Some(rest) = directive.strip_prefix("define") {
+                let tokens = tokenize_c(rest, idx + 1);
+                if let Some(CToken { tok: CTok::Ident(name), .. }) = tokens.first() {
+                    // Function-like macros have '(' glued to the name; we cannot tell
+                    // spacing from tokens, so check the raw text.
+                    let rest = rest.trim_start();
+                    let function_like = rest[name.len()..].starts_with('(');
+                    if !function_like && tokens.len() > 1 {
+                        if let Some(v) = eval_const(&tokens[1..], &out.consts) {
+                            out.consts.push((name.clone(), v));
+                        }
+                    }
+                }
+            }
+            code.push('\n');
+        } else {
+            code.push_str(line);
+            code.push('\n');
+        }
+    }
+
+    code
+}
+
+// ============================================================================
+// PUBLIC API
+// ============================================================================
+
+/// Parse a C header into bindings
+pub fn parse_header(src: &str) -> Result<Bindings> {
+    let mut out = Bindings::default();
+    let code = preprocess(src, &mut out);
+    let define_count = out.consts.len();
+
+    let mut parser = CParser::new(tokenize_c(&code, 1));
+    parser.out = out;
+    while parser.peek().is_some() {
+        let start = parser.pos;
+        if let Err(e) = parser.parse_external_decl() {
+            // Skip declarations we don't understand instead of failing the whole header
+            parser.out.warnings.push(e.to_string());
+            parser.pos = start;
+            parser.skip_declaration();
+        }
+        if parser.pos == start {
+            parser.pos += 1;
+        }
+    }
+
+    let mut out = parser.out;
+    // Enumerators are emitted with their enum; keep only #define values in consts
+    out.consts.truncate(define_count);
+    declare_opaque_records(&mut out);
+    skip_unsupported(&mut out);
+    Ok(out)
+}
+
+/// Drop functions that take or return a type Aether cannot represent, and
+/// note typedefs of one (`generate` leaves those out)
+fn skip_unsupported(out: &mut Bindings) {
+    let mut warnings = Vec::new();
+    for (name, ty) in &out.typedefs {
+        if let Some(what) = out.unsupported(ty) {
+            warnings.push(format!("typedef {}: {} has no Aether equivalent, skipped", name, what));
+        }
+    }
+    let funcs = std::mem::take(&mut out.funcs);
+    for f in funcs {
+        let skipped = f.params.iter().map(|(_, t)| t).chain([&f.ret]).find_map(|t| match out.resolve(t) {
+            // C passes records by value in registers or on the stack, Aether by pointer
+            CType::Struct(_) | CType::Union(_) => Some("a struct or union passed by value"),
+            _ => out.unsupported(t),
+        });
+        match skipped {
+            Some(what) => warnings.push(format!("function {}: {} has no Aether equivalent, skipped", f.name, what)),
+            None => out.funcs.push(f),
+        }
+    }
+    out.warnings.extend(warnings);
+}
+
+/// Aether keywords that cannot be used as parameter or field names
+const AETHER_KEYWORDS: &[&str] = &[
+    "func", "let", "mut", "const", "if", "else", "while", "for", "in", "return",
+    "break", "continue", "struct", "enum", "trait", "impl", "type", "import",
+    "pub", "self", "true", "false", "match", "parallel", "spawn", "extern",
+];
+
+fn aether_ident(name: &str) -> String {
+    if AETHER_KEYWORDS.contains(&name) {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Render bindings as Aether source
+pub fn generate(bindings: &Bindings, source: &str) -> String {
+    let mut out = String::new();
+    out.push_str("// Auto-generated by aetherc bindgen\n");
+    out.push_str(&format!("// Source: {}\n\n", source));
+
+    for w in &bindings.warnings {
+        out.push_str(&format!("// bindgen: {}\n", w));
+    }
+    if !bindings.warnings.is_empty() {
+        out.push('\n');
+    }
+
+    if !bindings.consts.is_empty() {
+        out.push_str("// Constants\n");
+        for (name, value) in &bindings.consts {
+            out.push_str(&format!("const {}: Int = {}\n", name, value));
+        }
+        out.push('\n');
+    }
+
+    for e in &bindings.enums {
+        out.push_str(&format!("// enum {}\n", e.name));
+        if !e.name.starts_with("__anon_") {
+            out.push_str(&format!("type {} = Int32\n", e.name));
+        }
+        for (name, value) in &e.values {
+            out.push_str(&format!("const {}: Int = {}\n", name, value));
+        }
+        out.push('\n');
+    }
+
+    for r in &bindings.records {
+        let (size, align) = bindings.record_layout(r);
+        let kind = if r.is_union { "union" } else { "struct" };
+        if r.fields.is_empty() {
+            out.push_str(&format!("// {} {}: opaque\n", kind, r.name));
+            out.push_str(&format!("struct {} {{\n}}\n\n", r.name));
+        } else if bindings.word_layout(r) {
+            out.push_str(&format!("// {} {}: size {}, align {}\n", kind, r.name, size, align));
+            out.push_str(&format!("struct {} {{\n", r.name));
+            for f in &r.fields {
+                out.push_str(&format!("    {}: {},\n", aether_ident(&f.name), bindings.aether_type(&f.ty)));
+            }
+            out.push_str("}\n\n");
+        } else {
+            // Aether structs are one word per field; anything else is reached by offset
+            out.push_str(&format!("// {} {}: size {}, align {}; its fields are not 8-byte words, so it is\n", kind, r.name, size, align));
+            out.push_str("// opaque here: reach them at these offsets with __builtin_loadN/__builtin_storeN\n");
+            out.push_str(&format!("struct {} {{\n}}\n", r.name));
+            out.push_str(&format!("const {}_SIZE: Int = {}\n", r.name, size));
+            for (f, offset) in r.fields.iter().zip(bindings.field_offsets(r)) {
+                out.push_str(&format!("const {}_{}_OFFSET: Int = {}\n", r.name, f.name, offset));
+            }
+            out.push('\n');
+        }
+    }
+
+    if !bindings.typedefs.is_empty() {
+        for (name, ty) in &bindings.typedefs {
+            // typedef struct foo foo; is implicit in Aether
+            let target = bindings.aether_type(ty);
+            if target != *name && bindings.unsupported(ty).is_none() {
+                out.push_str(&format!("type {} = {}\n", name, target));
+            }
+        }
+        out.push('\n');
+    }
+
+    if !bindings.funcs.is_empty() {
+        out.push_str("extern \"C\" {\n");
+        for f in &bindings.funcs {
+            let mut params: Vec<String> = f.params.iter().enumerate()
+                .map(|(i, (name, ty))| {
+                    let name = name.as_deref().map(aether_ident).unwrap_or_else(|| format!("arg{}", i));
+                    format!("{}: {}", name, bindings.aether_type(ty))
+                })
+                .collect();
+            if f.variadic {
+                params.push("...".into());
+            }
+            out.push_str(&format!("    func {}({})", f.name, params.join(", ")));
+            if !matches!(f.ret, CType::Void) {
+                out.push_str(&format!(" -> {}", bindings.aether_type(&f.ret)));
+            }
+            out.push('\n');
+        }
+        out.push_str("}\n");
+    }
+
+    out
+}
+
+/// Generate bindings for `input` and write them to `output`
+pub fn run(input: &Path, output: &Path) -> Result<()> {
+    let src = std::fs::read_to_string(input)?;
+    let bindings = parse_header(&src)?;
+    let name = input.file_name().map_or_else(|| input.display().to_string(), |n| n.to_string_lossy().into_owned());
+    std::fs::write(output, generate(&bindings, &name))?;
+
+    println!("✓ Bindings written to: {} ({} functions, {} structs, {} constants)",
+        output.display(), bindings.funcs.len(), bindings.records.len(), bindings.consts.len());
+    for w in &bindings.warnings {
+        println!("  ! {}", w);
+    }
+    Ok(())
+}
@@ -0,0 +1,974 @@
+//! Aether Formatter - Canonical source printer and `aetherc fmt`
+//!
+//! Prints an `ast::Module` back to source. When formatting a file, comments
+//! come from the lexer's trivia tokens and are re-attached by position.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, bail, Result};
+use serde_json::Value;
+use crate::ast::*;
+use crate::lexer::{self, Token, TokenKind};
+use crate::parser;
+
+const INDENT: &str = "    ";
+/// Lines longer than this are wrapped at call arguments, operators and
+/// conditions, and one-line blocks are broken up
+const MAX_WIDTH: usize = 100;
+
+/// Token position (line, col), as stored in tokens and spans
+type Pos = (usize, usize);
+
+fn pos(span: Span) -> Pos {
+    (span.line, span.col)
+}
+
+/// First source line of a (possibly multi-line) comment
+fn comment_line(tok: &Token) -> usize {
+    tok.line - tok.lexeme.matches('\n').count()
+}
+
+/// Pretty printer state
+pub struct Printer<'a> {
+    out: String,
+    indent: usize,
+    /// Comments in source order; those before `next_comment` are printed
+    comments: Vec<Token>,
+    next_comment: usize,
+    /// Source lines, to keep blank lines between items
+    lines: Vec<&'a str>,
+    /// `{` position -> matching `}` position
+    braces: HashMap<Pos, Pos>,
+    /// Every `{` position, in source order
+    lbraces: Vec<Pos>,
+    /// Integer literals spelled in hex
+    hex: HashMap<Pos, String>,
+    /// Print the next one-line block (or `if` chain) over several lines
+    break_block: bool,
+}
+
+impl Default for Printer<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Printer<'a> {
+    /// Printer for a bare AST (no comments, canonical layout)
+    pub fn new() -> Self {
+        Printer {
+            out: String::new(),
+            indent: 0,
+            comments: Vec::new(),
+            next_comment: 0,
+            lines: Vec::new(),
+            braces: HashMap::new(),
+            lbraces: Vec::new(),
+            hex: HashMap::new(),
+            break_block: false,
+        }
+    }
+
+    /// Printer that keeps comments, blank lines and one-line bodies of `source`
+    pub fn with_source(source: &'a str, tokens: &[Token]) -> Self {
+        let mut printer = Printer::new();
+        printer.lines = source.lines().collect();
+        let mut stack = Vec::new();
+        for tok in tokens {
+            let at = (tok.line, tok.col);
+            match tok.kind {
+                TokenKind::Comment => printer.comments.push(tok.clone()),
+                TokenKind::LBrace => {
+                    stack.push(at);
+                    printer.lbraces.push(at);
+                }
+                TokenKind::RBrace => {
+                    if let Some(open) = stack.pop() {
+                        printer.braces.insert(open, at);
+                    }
+                }
+                TokenKind::Int if tok.lexeme.starts_with("0x") => {
+                    printer.hex.insert(at, tok.lexeme.clone());
+                }
+                _ => {}
+            }
+        }
+        printer
+    }
+
+    /// Print a whole module
+    pub fn module(mut self, module: &Module) -> String {
+        self.items(&module.decls, None);
+        self.flush_comments((usize::MAX, usize::MAX));
+        self.out
+    }
+
+    // ========== LAYOUT HELPERS ==========
+
+    fn pad(&self) -> String {
+        INDENT.repeat(self.indent)
+    }
+
+    fn push_line(&mut self, text: &str) {
+        let pad = self.pad();
+        self.out.push_str(&pad);
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn blank_before(&self, line: usize) -> bool {
+        line >= 2 && self.lines.get(line - 2).is_some_and(|l| l.trim().is_empty())
+    }
+
+    fn ensure_blank(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") && !self.out.ends_with("{\n") {
+            self.out.push('\n');
+        }
+    }
+
+    /// Keep at most one blank line where the source had some
+    fn blank_if_source(&mut self, line: usize) {
+        if self.blank_before(line) {
+            self.ensure_blank();
+        }
+    }
+
+    fn has_comments_before(&self, at: Pos) -> bool {
+        self.comments.get(self.next_comment).is_some_and(|c| (c.line, c.col) < at)
+    }
+
+    /// Print all pending comments that precede `at` on their own lines
+    fn flush_comments(&mut self, at: Pos) {
+        while self.has_comments_before(at) {
+            let c = self.comments[self.next_comment].clone();
+            self.next_comment += 1;
+            self.blank_if_source(comment_line(&c));
+            self.push_line(&c.lexeme);
+        }
+    }
+
+    /// Take a comment that starts on `line` and fits on it
+    fn take_trailing(&mut self, line: usize) -> Option<Token> {
+        let c = self.comments.get(self.next_comment)?;
+        if c.line != line || c.lexeme.contains('\n') {
+            return None;
+        }
+        self.next_comment += 1;
+        Some(c.clone())
+    }
+
+    /// Append a same-line comment to the last printed line, keeping its column if possible
+    fn attach_trailing(&mut self, line: usize) {
+        let Some(c) = self.take_trailing(line) else { return };
+        self.out.pop();
+        let code_len = self.out.rsplit('\n').next().map_or(0, |l| l.chars().count());
+        let start_col = c.col.saturating_sub(c.lexeme.chars().count());
+        let gap = start_col.saturating_sub(1 + code_len).max(2);
+        self.out.push_str(&" ".repeat(gap));
+        self.out.push_str(&c.lexeme);
+        self.out.push('\n');
+    }
+
+    /// Print a line starting at column `col`; if it passes MAX_WIDTH, print it
+    /// again with its first one-line block broken up
+    fn fit_blocks(&mut self, col: usize, print: impl Fn(&mut Self) -> String) -> String {
+        let mark = self.next_comment;
+        let text = print(self);
+        if text.contains('\n') || col + text.len() <= MAX_WIDTH {
+            return text;
+        }
+        self.next_comment = mark;
+        self.break_block = true;
+        let text = print(self);
+        self.break_block = false;
+        text
+    }
+
+    /// First `{` at or after `at` and its matching `}`
+    fn brace_after(&self, at: Pos) -> Option<(Pos, Pos)> {
+        let i = self.lbraces.partition_point(|p| *p < at);
+        let open = *self.lbraces.get(i)?;
+        Some((open, *self.braces.get(&open)?))
+    }
+
+    /// `{ ... }` with `body` printed one level deeper
+    fn braced(&mut self, open: Option<Pos>, close: Option<Pos>, body: impl FnOnce(&mut Self)) -> String {
+        let header = open.and_then(|o| self.take_trailing(o.0));
+        let saved = std::mem::take(&mut self.out);
+        self.indent += 1;
+        body(self);
+        if let Some(close) = close {
+            self.flush_comments(close);
+        }
+        self.indent -= 1;
+        let inner = std::mem::replace(&mut self.out, saved);
+        if inner.is_empty() && header.is_none() {
+            return "{}".into();
+        }
+        let header = header.map_or(String::new(), |c| format!("  {}", c.lexeme));
+        format!("{{{}\n{}{}}}", header, inner, self.pad())
+    }
+
+    // ========== DECLARATIONS ==========
+
+    /// Print a list of declarations, separating multi-line items with a blank line
+    fn items(&mut self, decls: &[Decl], close: Option<Pos>) {
+        let mut prev_multi = false;
+        for (i, decl) in decls.iter().enumerate() {
+            let start = match decl.attrs().first() {
+                Some(attr) => pos(attr.span),
+                None => pos(decl_span(decl)),
+            };
+            let lead_start = self.next_comment;
+            while self.has_comments_before(start) {
+                self.next_comment += 1;
+            }
+            let leading = self.comments[lead_start..self.next_comment].to_vec();
+
+            let text = self.decl(decl);
+            let multi = text.contains('\n');
+            if i > 0 && (prev_multi || multi) {
+                self.ensure_blank();
+            }
+            for c in &leading {
+                self.blank_if_source(comment_line(c));
+                self.push_line(&c.lexeme);
+            }
+            self.blank_if_source(start.0);
+            self.push_line(&text);
+            if !multi {
+                self.attach_trailing(start.0);
+            }
+            prev_multi = multi;
+        }
+        if let Some(close) = close {
+            self.flush_comments(close);
+        }
+    }
+
+    fn decl(&mut self, decl: &Decl) -> String {
+        match decl {
+            Decl::Func { name, generics, params, ret, body, public, constant, is_unsafe, attrs, .. } => {
+                let mut text = String::new();
+                for attr in attrs {
+                    text.push_str(&format!("{}\n{}", attribute(attr), self.pad()));
+                }
+                let params: Vec<String> = params.iter().map(|p| self.param(p)).collect();
+                let ret = ret.as_ref().map_or(String::new(), |r| format!(" -> {}", r));
+                let constness = if *constant { "const " } else { "" };
+                let unsafety = if *is_unsafe { "unsafe " } else { "" };
+                let head = format!("{}{}{}func {}{}(", vis(*public), constness, unsafety, name, generic_list(generics));
+                let mut params = params.join(", ");
+                if self.pad().len() + head.len() + params.len() + ret.len() + 3 > MAX_WIDTH {
+                    // One parameter per line
+                    let inner = format!("{}{}", self.pad(), INDENT);
+                    params = format!("\n{}{}\n{}", inner, params.replace(", ", &format!(",\n{}", inner)), self.pad());
+                }
+                let col = self.pad().len();
+                text.push_str(&self.fit_blocks(col, |p| format!("{}{}){} {}", head, params, ret, p.block(body))));
+                text
+            }
+            Decl::Struct { name, generics, fields, public, attrs, span } => {
+                let mut text = String::new();
+                for attr in attrs {
+                    text.push_str(&format!("{}\n{}", attribute(attr), self.pad()));
+                }
+                let (open, close) = self.brace_after(pos(*span)).unzip();
+                let body = self.braced(open, close, |p| {
+                    for field in fields {
+                        p.flush_comments(pos(field.span));
+                        p.blank_if_source(field.span.line);
+                        p.push_line(&format!("{}{}: {},", vis(field.public), field.name, field.ty));
+                        p.attach_trailing(field.span.line);
+                    }
+                });
+                text.push_str(&format!("{}struct {}{} {}", vis(*public), name, generic_list(generics), body));
+                text
+            }
+            Decl::Enum { name, generics, variants, public, span } => {
+                let (open, close) = self.brace_after(pos(*span)).unzip();
+                let body = self.braced(open, close, |p| {
+                    for variant in variants {
+                        p.flush_comments(pos(variant.span));
+                        p.blank_if_source(variant.span.line);
+                        if variant.fields.is_empty() {
+                            p.push_line(&format!("{},", variant.name));
+                        } else {
+                            let fields: Vec<String> = variant.fields.iter().map(|t| t.to_string()).collect();
+                            p.push_line(&format!("{}({}),", variant.name, fields.join(", ")));
+                        }
+                        p.attach_trailing(variant.span.line);
+                    }
+                });
+                format!("{}enum {}{} {}", vis(*public), name, generic_list(generics), body)
+            }
+            Decl::Trait { name, generics, methods, public, span } => {
+                let (open, close) = self.brace_after(pos(*span)).unzip();
+                let body = self.braced(open, close, |p| p.items(methods, None));
+                format!("{}trait {}{} {}", vis(*public), name, generic_list(generics), body)
+            }
+            Decl::Impl { trait_name, type_name, generics, methods, is_unsafe, span } => {
+                let (open, close) = self.brace_after(pos(*span)).unzip();
+                let body = self.braced(open, close, |p| p.items(methods, None));
+                let trait_part = trait_name.as_ref().map_or(String::new(), |t| format!("{} for ", t));
+                let unsafe_part = if *is_unsafe { "unsafe " } else { "" };
+                format!("{}impl{} {}{} {}", unsafe_part, generic_list(generics), trait_part, type_name, body)
+            }
+            Decl::Const { name, ty, value, public, .. } => {
+                self.fit(&format!("{}const {}: {} = ", vis(*public), name, ty), value)
+            }
+            Decl::TypeAlias { name, generics, ty, public, .. } => {
+                format!("{}type {}{} = {}", vis(*public), name, generic_list(generics), ty)
+            }
+            Decl::Import { path, alias, .. } => match alias {
+                Some(alias) => format!("import {} as {}", path.join("."), alias),
+                None => format!("import {}", path.join(".")),
+            },
+            Decl::Static { name, ty, value, mutable, public, attrs, .. } => {
+                let mut text = String::new();
+                for attr in attrs {
+                    text.push_str(&format!("{}\n{}", attribute(attr), self.pad()));
+                }
+                text.push_str(&format!("{}let {}{}", vis(*public), if *mutable { "mut " } else { "" }, name));
+                if let Some(ty) = ty {
+                    text.push_str(&format!(": {}", ty));
+                }
+                if let Some(value) = value {
+                    text.push_str(&format!(" = {}", self.expr(value)));
+                }
+                text
+            }
+            Decl::Extern { abi, funcs, span } => {
+                let (open, close) = self.brace_after(pos(*span)).unzip();
+                let body = self.braced(open, close, |p| {
+                    for func in funcs {
+                        p.flush_comments(pos(func.span));
+                        p.blank_if_source(func.span.line);
+                        let mut params: Vec<String> = func.params.iter().map(|x| p.param(x)).collect();
+                        if func.variadic {
+                            params.push("...".into());
+                        }
+                        let ret = func.ret.as_ref().map_or(String::new(), |r| format!(" -> {}", r));
+                        p.push_line(&format!("func {}({}){}", func.name, params.join(", "), ret));
+                        p.attach_trailing(func.span.line);
+                    }
+                });
+                match abi {
+                    Some(abi) => format!("extern {} {}", quote(abi), body),
+                    None => format!("extern {}", body),
+                }
+            }
+        }
+    }
+
+    fn param(&mut self, param: &Param) -> String {
+        if param.name == "self" && matches!(&param.ty, Type::Ptr(inner, _) if matches!(inner.as_ref(), Type::Named(n) if n == "Self")) {
+            return if param.mutable { "&mut self" } else { "&self" }.into();
+        }
+        let comptime = if param.comptime { "comptime " } else { "" };
+        let mutable = if param.mutable { "mut " } else { "" };
+        match &param.default {
+            Some(default) => format!("{}{}{}: {} = {}", comptime, mutable, param.name, param.ty, self.expr(default)),
+            None => format!("{}{}{}: {}", comptime, mutable, param.name, param.ty),
+        }
+    }
+
+    // ========== STATEMENTS ==========
+
+    fn block(&mut self, block: &Block) -> String {
+        self.block_with(block, false)
+    }
+
+    /// Was `block` written as `{ stmt }` on one line?
+    fn one_line_source(&self, block: &Block) -> bool {
+        let open = pos(block.span);
+        block.stmts.len() == 1 && self.braces.get(&open).is_some_and(|close| close.0 == open.0)
+    }
+
+    fn block_with(&mut self, block: &Block, force_multiline: bool) -> String {
+        let force_multiline = force_multiline || std::mem::take(&mut self.break_block);
+        let open = pos(block.span);
+        let close = self.braces.get(&open).copied();
+
+        // Keep `{ stmt }` on one line when the source had it that way
+        if let (Some(close), [stmt], false) = (close, block.stmts.as_slice(), force_multiline) {
+            if close.0 == open.0 && !self.has_comments_before(close) {
+                let text = self.stmt_text(stmt);
+                if !text.contains('\n') {
+                    return format!("{{ {} }}", text);
+                }
+            }
+        }
+
+        let open = close.map(|_| open);
+        self.braced(open, close, |p| {
+            for stmt in &block.stmts {
+                p.stmt(stmt);
+            }
+        })
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        let span = stmt_span(stmt);
+        self.flush_comments(pos(span));
+        self.blank_if_source(span.line);
+        let col = self.pad().len();
+        let text = self.fit_blocks(col, |p| p.stmt_text(stmt));
+        self.push_line(&text);
+        if !text.contains('\n') {
+            self.attach_trailing(span.line);
+        }
+    }
+
+    fn stmt_text(&mut self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Let { name, ty, init, mutable, .. } => {
+                let mut text = format!("let {}{}", if *mutable { "mut " } else { "" }, name);
+                if let Some(ty) = ty {
+                    text.push_str(&format!(": {}", ty));
+                }
+                if let Some(init) = init {
+                    text.push_str(" = ");
+                    text = self.fit(&text, init);
+                }
+                text
+            }
+            Stmt::Expr(expr, _) => self.fit("", expr),
+            Stmt::Assign(target, value, span) => {
+                let target_text = self.expr(target);
+                // `x += v` is parsed as `x = x + v` with the statement's span
+                if let Expr::Binary(op @ (BinOp::Add | BinOp::Sub), left, right, op_span) = value {
+                    if pos(*op_span) == pos(*span) && self.expr(left) == target_text {
+                        let sym = if *op == BinOp::Add { "+=" } else { "-=" };
+                        return self.fit(&format!("{} {} ", target_text, sym), right);
+                    }
+                }
+                self.fit(&format!("{} = ", target_text), value)
+            }
+            Stmt::Return(Some(value), _) => self.fit("return ", value),
+            Stmt::Return(None, _) => "return".into(),
+            Stmt::If(cond, then_block, else_block, _) => {
+                let col = self.pad().len();
+                self.if_chain(cond, then_block, else_block.as_ref(), false, col)
+            }
+            Stmt::While(cond, body, _) => {
+                let cond = self.condition("while", cond, self.pad().len());
+                format!("{}{}", cond, self.block(body))
+            }
+            Stmt::For(var, iter, body, _) => format!("for {} in {} {}", var, self.expr(iter), self.block(body)),
+            Stmt::Break(_) => "break".into(),
+            Stmt::Continue(_) => "continue".into(),
+            Stmt::Block(block, _) => self.block(block),
+        }
+    }
+
+    /// `keyword cond ` starting at column `col`, ready for the block. A condition
+    /// too long for the line is wrapped, and the block's `{` goes on its own line
+    fn condition(&mut self, keyword: &str, cond: &Expr, col: usize) -> String {
+        // Leave room for ` {`
+        let cond = self.wrapped(cond, col + keyword.len() + 3);
+        if cond.contains('\n') {
+            format!("{} {}\n{}", keyword, cond, self.pad())
+        } else {
+            format!("{} {} ", keyword, cond)
+        }
+    }
+
+    /// `if`/`else if`/`else` starting at column `col`: one-line blocks only if
+    /// every block of the chain was one line
+    fn if_chain(&mut self, cond: &Expr, then_block: &Block, else_block: Option<&Block>, force_multiline: bool, col: usize) -> String {
+        let mut force = force_multiline || std::mem::take(&mut self.break_block) || !self.one_line_source(then_block);
+        let mut rest = else_block;
+        while let Some(block) = rest {
+            match else_if(block) {
+                Some((_, then_block, else_block)) => {
+                    force |= !self.one_line_source(then_block);
+                    rest = else_block;
+                }
+                None => {
+                    force |= !self.one_line_source(block);
+                    rest = None;
+                }
+            }
+        }
+
+        // A chain of one-line blocks must fit on one line
+        if !force {
+            let mark = self.next_comment;
+            let text = self.chain(cond, then_block, else_block, false, col);
+            if !text.contains('\n') && col + text.len() <= MAX_WIDTH {
+                return text;
+            }
+            self.next_comment = mark;
+        }
+        self.chain(cond, then_block, else_block, true, col)
+    }
+
+    fn chain(&mut self, cond: &Expr, then_block: &Block, else_block: Option<&Block>, force: bool, col: usize) -> String {
+        let mut text = self.condition("if", cond, col);
+        text.push_str(&self.block_with(then_block, force));
+        if let Some(block) = else_block {
+            let line_start = if text.contains('\n') { 0 } else { col };
+            let tail_col = line_start + text.rsplit('\n').next().map_or(0, str::len) + " else ".len();
+            let tail = match else_if(block) {
+                Some((cond, then_block, else_block)) => self.chain(cond, then_block, else_block, force, tail_col),
+                None => self.block_with(block, force),
+            };
+            text.push_str(&format!(" else {}", tail));
+        }
+        text
+    }
+
+    // ========== EXPRESSIONS ==========
+
+    fn expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Int(v, span) => self.hex.get(&pos(*span)).cloned().unwrap_or_else(|| v.to_string()),
+            Expr::Float(v, _) => {
+                let text = v.to_string();
+                if text.contains('.') || !v.is_finite() { text } else { format!("{}.0", text) }
+            }
+            Expr::String(s, _) => quote(s),
+            Expr::Bool(b, _) => b.to_string(),
+            Expr::Ident(name, _) => name.clone(),
+            Expr::Binary(op, left, right, _) => {
+                let prec = binop_prec(*op);
+                let mut l = self.expr(left);
+                if matches!(left.as_ref(), Expr::Binary(lop, ..) if binop_prec(*lop) < prec || clarify(*op, *lop)) {
+                    l = format!("({})", l);
+                }
+                let mut r = self.expr(right);
+                if matches!(right.as_ref(), Expr::Binary(rop, ..) if binop_prec(*rop) <= prec || clarify(*op, *rop)) {
+                    r = format!("({})", r);
+                }
+                format!("{} {} {}", l, binop_str(*op), r)
+            }
+            Expr::Unary(op, operand, _) => {
+                let sym = match op {
+                    UnOp::Neg => "-",
+                    UnOp::Not => "!",
+                    UnOp::BitNot => "~",
+                    UnOp::Ref => "&",
+                    UnOp::RefMut => "&mut ",
+                    UnOp::Deref => "*",
+                };
+                let mut text = self.expr(operand);
+                if matches!(operand.as_ref(), Expr::Binary(..)) {
+                    text = format!("({})", text);
+                }
+                format!("{}{}", sym, text)
+            }
+            Expr::Call(callee, args, _) => {
+                let callee = self.receiver(callee);
+                format!("{}({})", callee, self.expr_list(args))
+            }
+            Expr::MethodCall(recv, method, args, _) => {
+                let recv = self.receiver(recv);
+                format!("{}.{}({})", recv, method, self.expr_list(args))
+            }
+            Expr::Field(recv, field, _) => format!("{}.{}", self.receiver(recv), field),
+            Expr::Index(recv, index, _) => {
+                let recv = self.receiver(recv);
+                format!("{}[{}]", recv, self.expr(index))
+            }
+            Expr::Array(elems, _) => format!("[{}]", self.expr_list(elems)),
+            Expr::Struct(name, fields, _) => {
+                if fields.is_empty() {
+                    return format!("{} {{}}", name);
+                }
+                let fields: Vec<String> = fields.iter()
+                    .map(|(f, v)| format!("{}: {}", f, self.expr(v)))
+                    .collect();
+                format!("{} {{ {} }}", name, fields.join(", "))
+            }
+            Expr::If(cond, then_block, else_block, _) => {
+                let col = self.pad().len();
+                self.if_chain(cond, then_block, else_block.as_deref(), false, col)
+            }
+            Expr::Lambda(params, ret, body, _) => {
+                let params: Vec<String> = params.iter()
+                    .map(|p| if matches!(p.ty, Type::Infer) { p.name.clone() } else { format!("{}: {}", p.name, p.ty) })
+                    .collect();
+                let ret = ret.as_ref().map_or(String::new(), |r| format!(" -> {}", r));
+                format!("|{}|{} {}", params.join(", "), ret, self.body_expr(body))
+            }
+            Expr::Match(scrutinee, arms, _) => {
+                let mut text = format!("match {} {{\n", self.expr(scrutinee));
+                self.indent += 1;
+                for arm in arms {
+                    let guard = match &arm.guard {
+                        Some(g) => format!(" if {}", self.expr(g)),
+                        None => String::new(),
+                    };
+                    let body = self.body_expr(&arm.body);
+                    text.push_str(&format!("{}{}{} => {},\n", self.pad(), self.pattern(&arm.pattern), guard, body));
+                }
+                self.indent -= 1;
+                text.push_str(&format!("{}}}", self.pad()));
+                text
+            }
+            Expr::Path(path, _) => path.join("::"),
+            Expr::Spawn(func, args, _) => {
+                let func = self.receiver(func);
+                format!("spawn {}({})", func, self.expr_list(args))
+            }
+            Expr::Comptime(block, _) => format!("comptime {}", self.block(block)),
+            Expr::Unsafe(block, _) => format!("unsafe {}", self.block(block)),
+        }
+    }
+
+    /// `prefix` followed by `expr`, wrapped if the line gets too long
+    fn fit(&mut self, prefix: &str, expr: &Expr) -> String {
+        let col = self.pad().len() + prefix.len();
+        format!("{}{}", prefix, self.wrapped(expr, col))
+    }
+
+    /// Print `expr` starting at column `col`, breaking calls and operator chains past MAX_WIDTH
+    fn wrapped(&mut self, expr: &Expr, col: usize) -> String {
+        let mark = self.next_comment;
+        let flat = self.expr(expr);
+        if flat.contains('\n') || col + flat.len() <= MAX_WIDTH {
+            return flat;
+        }
+        self.next_comment = mark;
+        let pad = self.pad();
+        let inner_col = pad.len() + INDENT.len();
+        match expr {
+            Expr::Call(_, args, _) | Expr::MethodCall(_, _, args, _) if !args.is_empty() => {
+                let head = match expr {
+                    Expr::Call(callee, ..) => self.receiver(callee),
+                    Expr::MethodCall(recv, method, ..) => format!("{}.{}", self.receiver(recv), method),
+                    _ => unreachable!(),
+                };
+                self.indent += 1;
+                let args: Vec<String> = args.iter().map(|a| self.wrapped(a, inner_col)).collect();
+                self.indent -= 1;
+                let sep = format!(",\n{}{}", pad, INDENT);
+                format!("{}(\n{}{}{}\n{})", head, pad, INDENT, args.join(&sep), pad)
+            }
+            Expr::Binary(op, ..) => {
+                // Split a left-nested chain of equal precedence: a + b + c
+                let mut operands = Vec::new();
+                let mut ops = Vec::new();
+                let mut cur = expr;
+                while let Expr::Binary(o, left, right, _) = cur {
+                    if binop_prec(*o) != binop_prec(*op) {
+                        break;
+                    }
+                    operands.push(right.as_ref());
+                    ops.push(*o);
+                    cur = left;
+                }
+                operands.push(cur);
+                operands.reverse();
+                ops.reverse();
+                let mut parts = Vec::new();
+                for (i, operand) in operands.iter().enumerate() {
+                    let part_col = if i == 0 { col } else { inner_col };
+                    let mut text = self.expr(operand);
+                    let parent = if i == 0 { ops[0] } else { ops[i - 1] };
+                    let needs_parens = match operand {
+                        Expr::Binary(child, ..) => {
+                            binop_prec(*child) < binop_prec(parent) || (i > 0 && binop_prec(*child) == binop_prec(parent))
+                                || clarify(parent, *child)
+                        }
+                        _ => false,
+                    };
+                    if needs_parens {
+                        // A parenthesized chain that is still too long wraps too
+                        if part_col + text.len() + 2 > MAX_WIDTH {
+                            text = self.wrapped(operand, part_col + 1);
+                        }
+                        text = format!("({})", text);
+                    }
+                    parts.push(text);
+                }
+                let mut out = parts[0].clone();
+                for (op, part) in ops.iter().zip(&parts[1..]) {
+                    out.push_str(&format!(" {}\n{}{}{}", binop_str(*op), pad, INDENT, part));
+                }
+                out
+            }
+            Expr::If(cond, then_block, else_block, _) => self.if_chain(cond, then_block, else_block.as_deref(), true, col),
+            _ => flat,
+        }
+    }
+
+    /// Expression in postfix position (callee, receiver, indexed value)
+    fn receiver(&mut self, expr: &Expr) -> String {
+        let text = self.expr(expr);
+        match expr {
+            Expr::Binary(..) | Expr::Unary(..) | Expr::Lambda(..) | Expr::If(..) => format!("({})", text),
+            _ => text,
+        }
+    }
+
+    fn expr_list(&mut self, exprs: &[Expr]) -> String {
+        let parts: Vec<String> = exprs.iter().map(|e| self.expr(e)).collect();
+        parts.join(", ")
+    }
+
+    /// Closure and match arm bodies: `{ ... }` is parsed as `if true { ... }`
+    fn body_expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::If(cond, block, None, _) if matches!(cond.as_ref(), Expr::Bool(true, _)) => self.block(block),
+            _ => self.expr(expr),
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) -> String {
+        match pattern {
+            Pattern::Wildcard => "_".into(),
+            Pattern::Ident(name) => name.clone(),
+            Pattern::Literal(expr) => self.expr(expr),
+            Pattern::Tuple(items) => {
+                let items: Vec<String> = items.iter().map(|p| self.pattern(p)).collect();
+                format!("({})", items.join(", "))
+            }
+            Pattern::Struct(name, fields) => {
+                let fields: Vec<String> = fields.iter()
+                    .map(|(f, p)| format!("{}: {}", f, self.pattern(p)))
+                    .collect();
+                format!("{} {{ {} }}", name, fields.join(", "))
+            }
+            Pattern::Enum(ty, variant, items) if items.is_empty() => format!("{}::{}", ty, variant),
+            Pattern::Enum(ty, variant, items) => {
+                let items: Vec<String> = items.iter().map(|p| self.pattern(p)).collect();
+                format!("{}::{}({})", ty, variant, items.join(", "))
+            }
+        }
+    }
+}
+
+// ============================================================================
+// SYNTAX TABLES
+// ============================================================================
+
+/// Binding strength, matching the parser's precedence climbing
+fn binop_prec(op: BinOp) -> u8 {
+    match op {
+        BinOp::Or => 1,
+        BinOp::And => 2,
+        BinOp::BitOr => 3,
+        BinOp::BitXor => 4,
+        BinOp::BitAnd => 5,
+        BinOp::Eq | BinOp::Ne => 6,
+        BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 7,
+        BinOp::Shl | BinOp::Shr => 8,
+        BinOp::Add | BinOp::Sub => 9,
+        BinOp::Mul | BinOp::Div | BinOp::Mod => 10,
+    }
+}
+
+/// Keep parentheses that precedence makes redundant but readers rely on:
+/// mixed arithmetic/shift/bitwise operators, `%` next to other arithmetic,
+/// and `&&` inside `||`
+fn clarify(parent: BinOp, child: BinOp) -> bool {
+    fn family(op: BinOp) -> u8 {
+        match op {
+            BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Mod => 0,
+            BinOp::Shl | BinOp::Shr => 1,
+            BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor => 2,
+            BinOp::And | BinOp::Or => 3,
+            _ => 4,
+        }
+    }
+    let (pf, cf) = (family(parent), family(child));
+    match (pf, cf) {
+        (0..=2, 0..=2) => {
+            pf != cf || (pf == 2 && parent != child) || (parent != child && (parent == BinOp::Mod || child == BinOp::Mod))
+        }
+        (3, 3) => parent != child,
+        _ => false,
+    }
+}
+
+fn binop_str(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "+", BinOp::Sub => "-", BinOp::Mul => "*", BinOp::Div => "/", BinOp::Mod => "%",
+        BinOp::Eq => "==", BinOp::Ne => "!=", BinOp::Lt => "<", BinOp::Le => "<=", BinOp::Gt => ">", BinOp::Ge => ">=",
+        BinOp::And => "&&", BinOp::Or => "||", BinOp::BitAnd => "&", BinOp::BitOr => "|", BinOp::BitXor => "^",
+        BinOp::Shl => "<<", BinOp::Shr => ">>",
+    }
+}
+
+fn vis(public: bool) -> &'static str {
+    if public { "pub " } else { "" }
+}
+
+fn generic_list(generics: &[String]) -> String {
+    if generics.is_empty() { String::new() } else { format!("<{}>", generics.join(", ")) }
+}
+
+fn attribute(attr: &Attribute) -> String {
+    if attr.args.is_empty() {
+        return format!("#[{}]", attr.name);
+    }
+    let args: Vec<String> = attr.args.iter()
+        .map(|a| if a.chars().all(|c| c.is_alphanumeric() || c == '_') { a.clone() } else { quote(a) })
+        .collect();
+    format!("#[{}({})]", attr.name, args.join(", "))
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The `if` inside an `else { if ... }` block, as the parser builds `else if`
+fn else_if(block: &Block) -> Option<(&Expr, &Block, Option<&Block>)> {
+    match block.stmts.as_slice() {
+        [Stmt::If(cond, then_block, else_block, _)] => Some((cond, then_block, else_block.as_ref())),
+        [Stmt::Expr(Expr::If(cond, then_block, else_block, _), _)] => Some((cond, then_block, else_block.as_deref())),
+        _ => None,
+    }
+}
+
+fn decl_span(decl: &Decl) -> Span {
+    match decl {
+        Decl::Func { span, .. } | Decl::Struct { span, .. } | Decl::Enum { span, .. } |
+        Decl::Trait { span, .. } | Decl::Impl { span, .. } | Decl::Const { span, .. } |
+        Decl::TypeAlias { span, .. } | Decl::Import { span, .. } | Decl::Static { span, .. } |
+        Decl::Extern { span, .. } => *span,
+    }
+}
+
+fn stmt_span(stmt: &Stmt) -> Span {
+    match stmt {
+        Stmt::Let { span, .. } => *span,
+        Stmt::Expr(_, s) | Stmt::Assign(_, _, s) | Stmt::Return(_, s) | Stmt::If(_, _, _, s) |
+        Stmt::While(_, _, s) | Stmt::For(_, _, _, s) | Stmt::Break(s) | Stmt::Continue(s) |
+        Stmt::Block(_, s) => *s,
+    }
+}
+
+// ============================================================================
+// FORMATTING FILES
+// ============================================================================
+
+/// Print a module without source information
+pub fn print_module(module: &Module) -> String {
+    Printer::new().module(module)
+}
+
+/// Print a single expression (array lengths in types, diagnostics)
+pub fn print_expr(expr: &Expr) -> String {
+    Printer::new().expr(expr)
+}
+
+/// Reject syntax the parser accepts but drops, so formatting never loses code
+fn check_lossless(tokens: &[Token]) -> Result<()> {
+    if let Some(tok) = tokens.iter().find(|t| t.kind == TokenKind::Parallel) {
+        bail!("Cannot format `parallel` at line {}: the parser does not preserve it yet", tok.line);
+    }
+    Ok(())
+}
+
+/// AST as JSON with all positions removed
+fn shape(module: &Module) -> Result<Value> {
+    fn strip(v: &mut Value) {
+        match v {
+            Value::Object(map) => {
+                if map.len() == 2 && map.contains_key("line") && map.contains_key("col") {
+                    *v = Value::Null;
+                    return;
+                }
+                map.remove("span");
+                map.values_mut().for_each(strip);
+            }
+            Value::Array(items) => items.iter_mut().for_each(strip),
+            _ => {}
+        }
+    }
+    let mut value = serde_json::to_value(module)?;
+    strip(&mut value);
+    Ok(value)
+}
+
+/// Format Aether source, keeping comments
+pub fn format_source(source: &str) -> Result<String> {
+    let tokens = lexer::tokenize_with_trivia(source);
+    check_lossless(&tokens)?;
+    let code: Vec<Token> = tokens.iter().filter(|t| t.kind != TokenKind::Comment).cloned().collect();
+    let module = parser::parse(&code)?;
+    let formatted = Printer::with_source(source, &tokens).module(&module);
+
+    // The output must describe the same program
+    let reparsed = parser::parse(&lexer::tokenize(&formatted))
+        .map_err(|e| anyhow!("Formatter produced invalid code: {}", e))?;
+    if shape(&module)? != shape(&reparsed)? {
+        bail!("Formatter changed the meaning of the program (please report this)");
+    }
+    Ok(formatted)
+}
+
+/// Every .aether file under `dir`, sorted
+fn collect_sources(dir: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            collect_sources(&path, out)?;
+        } else if path.extension().is_some_and(|e| e == "aether") {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// `aetherc fmt [--check] PATH` (a file, or a directory of .aether files)
+pub fn run(path: &Path, check: bool) -> Result<()> {
+    let mut files = Vec::new();
+    if path.is_dir() {
+        collect_sources(path, &mut files)?;
+    } else {
+        files.push(path.to_path_buf());
+    }
+
+    let mut unformatted = 0;
+    let mut failed = 0;
+    for path in &files {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))?;
+        let formatted = match format_source(&source) {
+            Ok(formatted) => formatted,
+            Err(e) if files.len() > 1 => {
+                // Keep going so one file doesn't hide the rest
+                eprintln!("! {}: {}", path.display(), e);
+                failed += 1;
+                continue;
+            }
+            Err(e) => return Err(anyhow!("{}: {}", path.display(), e)),
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("Would reformat: {}", path.display());
+            unformatted += 1;
+        } else {
+            std::fs::write(path, formatted)?;
+            println!("✓ Formatted {}", path.display());
+        }
+    }
+    if unformatted > 0 {
+        bail!("{} file(s) need formatting", unformatted);
+    }
+    if failed > 0 {
+        bail!("{} file(s) could not be formatted", failed);
+    }
+    Ok(())
+}
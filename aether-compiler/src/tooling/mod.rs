@@ -1,2 +1,5 @@
 pub mod lsp;
 pub mod apm;
+pub mod bindgen;
+pub mod fmt;
+pub mod repl;
@@ -0,0 +1,270 @@
+//! Aether REPL - Interactive read-eval-print loop
+//! Declarations and statements are type checked and interpreted one input at
+//! a time against a persistent type environment and interpreter state.
+
+use std::io::{BufRead, Write};
+use std::path::Path;
+use anyhow::{anyhow, bail, Result};
+use crate::ast::{Decl, Expr, Stmt, Type};
+use crate::codegen::llvm::LLVMCodeGen;
+use crate::consteval::ConstEvaluator;
+use crate::interp::Interpreter;
+use crate::lexer::{self, TokenKind};
+use crate::parser::{self, Parser};
+use crate::typechecker::{TypeChecker, TypedModule};
+use crate::dump;
+
+const HELP: &str = "\
+Enter declarations (func, struct, const, ...) or statements.
+Commands:
+  :type <expr>     Show the inferred type of an expression
+  :ast <expr>      Show the parsed AST of an expression
+  :ir <func>       Show the LLVM IR of a function
+  :load <file>     Load declarations from a source file
+  :help            Show this help
+  :quit            Exit the REPL";
+
+/// One parsed piece of REPL input
+enum Item {
+    Decl(Decl),
+    Stmt(Stmt),
+}
+
+/// REPL session state
+pub struct Repl {
+    consts: ConstEvaluator,
+    checker: TypeChecker,
+    interp: Interpreter,
+    /// Functions entered so far (latest definition of each), for `:ir`
+    funcs: Vec<Decl>,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Repl {
+            consts: ConstEvaluator::new(),
+            checker: TypeChecker::new(),
+            interp: Interpreter::new(),
+            funcs: Vec::new(),
+        }
+    }
+
+    /// Handle one complete input; returns the text to show, if any
+    pub fn eval(&mut self, input: &str) -> Result<Option<String>> {
+        let input = input.trim();
+        if let Some(cmd) = input.strip_prefix(':') {
+            let (name, arg) = cmd.split_once(char::is_whitespace).unwrap_or((cmd, ""));
+            return self.command(name, arg.trim()).map(Some);
+        }
+
+        // Parse everything first so a syntax error runs nothing
+        let tokens = lexer::tokenize(input);
+        let mut parser = Parser::new(&tokens);
+        let mut items = Vec::new();
+        while !parser.at_end() {
+            if parser.at_decl() {
+                items.push(Item::Decl(parser.parse_decl()?));
+            } else {
+                items.push(Item::Stmt(parser.parse_stmt()?));
+            }
+        }
+
+        let mut out = Vec::new();
+        let mut items = items.into_iter().peekable();
+        while let Some(item) = items.next() {
+            match item {
+                Item::Decl(decl) => {
+                    // Consecutive declarations are checked together so they can refer to each other
+                    let mut decls = vec![decl];
+                    while let Some(Item::Decl(_)) = items.peek() {
+                        if let Some(Item::Decl(d)) = items.next() {
+                            decls.push(d);
+                        }
+                    }
+                    out.extend(self.declare(decls)?);
+                }
+                Item::Stmt(mut stmt) => {
+                    self.consts.fold_stmt(&mut stmt)?;
+                    out.extend(self.statement(&stmt)?);
+                }
+            }
+        }
+        Ok(if out.is_empty() { None } else { Some(out.join("\n")) })
+    }
+
+    fn command(&mut self, name: &str, arg: &str) -> Result<String> {
+        let needs_arg = |what: &str| -> Result<()> {
+            if arg.is_empty() {
+                bail!("Usage: :{} <{}>", name, what);
+            }
+            Ok(())
+        };
+        match name {
+            "type" | "t" => {
+                needs_arg("expr")?;
+                let expr = parse_expr(arg)?;
+                Ok(self.checker.clone().infer(&expr)?.to_string())
+            }
+            "ast" => {
+                needs_arg("expr")?;
+                let expr = parse_expr(arg)?;
+                Ok(dump::to_tree(&expr)?.trim_end().to_string())
+            }
+            "ir" => {
+                needs_arg("func")?;
+                let decl = self.funcs.iter()
+                    .find(|d| matches!(d, Decl::Func { name, .. } if name == arg))
+                    .ok_or_else(|| anyhow!("No function named {}", arg))?;
+                let mut gen = LLVMCodeGen::new();
+                gen.declare_export(decl);
+                gen.gen_function(decl);
+                let ir = gen.get_ir();
+                let lines: Vec<&str> = ir.lines()
+                    .filter(|l| !l.starts_with("; Auto-generated"))
+                    .collect();
+                Ok(lines.join("\n").trim().to_string())
+            }
+            "load" | "l" => {
+                needs_arg("file")?;
+                self.load(Path::new(arg))
+            }
+            "help" | "h" | "?" => Ok(HELP.to_string()),
+            _ => bail!("Unknown command :{} (try :help)", name),
+        }
+    }
+
+    fn load(&mut self, path: &Path) -> Result<String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))?;
+        let module = parser::parse(&lexer::tokenize(&source))?;
+        let count = module.decls.len();
+        self.declare(module.decls)?;
+        Ok(format!("Loaded {} declarations from {}", count, path.display()))
+    }
+
+    /// Check and register declarations; a failed check leaves the session unchanged
+    fn declare(&mut self, mut decls: Vec<Decl>) -> Result<Vec<String>> {
+        self.consts.fold_decls(&mut decls)?;
+        let saved = self.checker.clone();
+        let typed = match self.checker.check_decls(&decls) {
+            Ok(typed) => typed,
+            Err(e) => {
+                self.checker = saved;
+                return Err(e);
+            }
+        };
+        let shown = typed.iter()
+            .filter(|t| matches!(t.decl, Decl::Func { .. } | Decl::Const { .. } | Decl::Static { .. }))
+            .filter_map(|t| t.decl.name().map(|n| format!("{}: {}", n, t.ty)))
+            .collect();
+        self.interp.load_module(&TypedModule { decls: typed })?;
+
+        for decl in decls.into_iter().filter(|d| matches!(d, Decl::Func { .. })) {
+            self.funcs.retain(|f| f.name() != decl.name());
+            self.funcs.push(decl);
+        }
+        Ok(shown)
+    }
+
+    /// Check and run a statement; expression statements show their value and type
+    fn statement(&mut self, stmt: &Stmt) -> Result<Option<String>> {
+        let ty = match stmt {
+            Stmt::Expr(expr, _) => Some(self.checker.clone().infer(expr)?),
+            _ => None,
+        };
+        let saved = self.checker.clone();
+        let result = self.checker.check_statement(stmt)
+            .and_then(|_| self.interp.exec(stmt));
+        let value = match result {
+            Ok(value) => value,
+            Err(e) => {
+                self.checker = saved;
+                return Err(e);
+            }
+        };
+        Ok(match ty {
+            None | Some(Type::Unit) => None,
+            Some(Type::Infer) => Some(value.to_string()),
+            Some(ty) => Some(format!("{}: {}", self.show(value, &ty), ty)),
+        })
+    }
+
+    /// Render a value according to its type
+    fn show(&self, value: i64, ty: &Type) -> String {
+        match ty {
+            Type::Named(n) if n == "Bool" => (value != 0).to_string(),
+            Type::Named(n) if n == "String" => match self.interp.read_string(value) {
+                Ok(s) => format!("{:?}", s),
+                Err(_) => format!("<string {:#x}>", value),
+            },
+            _ => value.to_string(),
+        }
+    }
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parse the argument of `:type` / `:ast`
+fn parse_expr(source: &str) -> Result<Expr> {
+    let tokens = lexer::tokenize(source);
+    let mut parser = Parser::new(&tokens);
+    let expr = parser.parse_expr()?;
+    if !parser.at_end() {
+        bail!("Unexpected input after expression");
+    }
+    Ok(expr)
+}
+
+/// Input is complete once every bracket is closed
+fn is_complete(input: &str) -> bool {
+    let depth: i64 = lexer::tokenize(input).iter()
+        .map(|t| match t.kind {
+            TokenKind::LBrace | TokenKind::LParen | TokenKind::LBrack => 1,
+            TokenKind::RBrace | TokenKind::RParen | TokenKind::RBrack => -1,
+            _ => 0,
+        })
+        .sum();
+    depth <= 0
+}
+
+/// Run the REPL on stdin until EOF or `:quit`
+pub fn run() -> Result<()> {
+    println!("Aether REPL v1.0.0 - :help for commands, :quit to exit");
+    let mut repl = Repl::new();
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut input = String::new();
+
+    loop {
+        print!("{}", if input.is_empty() { "aether> " } else { "   ...> " });
+        std::io::stdout().flush()?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => {
+                println!();
+                return Ok(());
+            }
+        };
+        input.push_str(&line);
+        input.push('\n');
+        if !is_complete(&input) {
+            continue;
+        }
+
+        let entry = std::mem::take(&mut input);
+        match entry.trim() {
+            "" => continue,
+            ":quit" | ":q" | ":exit" => return Ok(()),
+            _ => {}
+        }
+        match repl.eval(&entry) {
+            Ok(Some(out)) => println!("{}", out),
+            Ok(None) => {}
+            Err(e) => println!("error: {}", e),
+        }
+    }
+}
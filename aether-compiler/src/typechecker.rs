@@ -3,6 +3,7 @@
 //! Handles generics, polymorphism, and type safety
 
 use std::collections::HashMap;
+use serde::Serialize;
 use crate::ast::*;
 use anyhow::{anyhow, Result};
 
@@ -61,6 +62,11 @@ impl TypeEnv {
             .or_else(|| self.parent.as_ref().and_then(|p| p.lookup_func(name)))
     }
     
+    pub fn lookup_struct(&self, name: &str) -> Option<&Vec<(String, Type)>> {
+        self.structs.get(name)
+            .or_else(|| self.parent.as_ref().and_then(|p| p.lookup_struct(name)))
+    }
+    
     pub fn define_var(&mut self, name: String, ty: Type) {
         self.vars.insert(name, ty);
     }
@@ -71,18 +77,19 @@ impl TypeEnv {
 }
 
 /// Typed AST
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct TypedModule {
     pub decls: Vec<TypedDecl>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct TypedDecl {
     pub decl: Decl,
     pub ty: Type,
 }
 
 /// Type checker
+#[derive(Clone)]
 pub struct TypeChecker {
     env: TypeEnv,
     errors: Vec<String>,
@@ -100,6 +107,28 @@ impl TypeChecker {
         self.errors.push(msg);
     }
     
+    /// Report and clear the errors collected so far
+    fn take_errors(&mut self) -> Result<()> {
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        let errors = std::mem::take(&mut self.errors);
+        Err(anyhow!("Type errors:\n{}", errors.join("\n")))
+    }
+    
+    /// Infer the type of a standalone expression in the current scope
+    pub fn infer(&mut self, expr: &Expr) -> Result<Type> {
+        let ty = self.infer_expr(expr);
+        self.take_errors()?;
+        Ok(ty)
+    }
+    
+    /// Check a statement in the current scope, keeping its bindings (REPL input)
+    pub fn check_statement(&mut self, stmt: &Stmt) -> Result<()> {
+        self.check_stmt(stmt);
+        self.take_errors()
+    }
+    
     fn infer_expr(&mut self, expr: &Expr) -> Type {
         match expr {
             Expr::Int(_, _) => Type::Named("Int".into()),
@@ -136,10 +165,11 @@ impl TypeChecker {
                     UnOp::Neg => t,
                     UnOp::Not => Type::Named("Bool".into()),
                     UnOp::BitNot => t,
-                    UnOp::Ref => Type::Ptr(Box::new(t)),
+                    UnOp::Ref => Type::Ptr(Box::new(t), false),
+                    UnOp::RefMut => Type::Ptr(Box::new(t), true),
                     UnOp::Deref => {
-                        if let Type::Ptr(inner) = t {
-                            *inner
+                        if let Some(inner) = t.pointee() {
+                            inner.clone()
                         } else {
                             self.error("Cannot dereference non-pointer".into());
                             Type::Infer
@@ -166,9 +196,13 @@ impl TypeChecker {
             }
             
             Expr::Field(obj, field, span) => {
-                let obj_ty = self.infer_expr(obj);
-                if let Type::Named(name) = &obj_ty {
-                    if let Some(fields) = self.env.structs.get(name) {
+                // Fields are reached through references too
+                let mut obj_ty = self.infer_expr(obj);
+                while let Some(inner) = obj_ty.pointee() {
+                    obj_ty = inner.clone();
+                }
+                if let Type::Named(name) | Type::Generic(name, _) = &obj_ty {
+                    if let Some(fields) = self.env.lookup_struct(name) {
                         for (fn_, ft) in fields {
                             if fn_ == field {
                                 return ft.clone();
@@ -180,6 +214,17 @@ impl TypeChecker {
                 Type::Infer
             }
             
+            Expr::Struct(name, fields, span) => {
+                let known = self.env.lookup_struct(name).cloned();
+                for (field, value) in fields {
+                    self.infer_expr(value);
+                    if known.as_ref().is_some_and(|k| !k.iter().any(|(f, _)| f == field)) {
+                        self.error(format!("Unknown field {} in {} literal at line {}", field, name, span.line));
+                    }
+                }
+                Type::Named(name.clone())
+            }
+            
             Expr::Index(arr, _, _) => {
                 let arr_ty = self.infer_expr(arr);
                 if let Type::Array(elem, _) = arr_ty {
@@ -198,6 +243,20 @@ impl TypeChecker {
                 }
             }
             
+            Expr::Unsafe(block, _) => {
+                let old_env = self.env.clone();
+                self.env = self.env.child();
+                let mut ty = Type::Unit;
+                for (i, s) in block.stmts.iter().enumerate() {
+                    match s {
+                        Stmt::Expr(e, _) if i + 1 == block.stmts.len() => ty = self.infer_expr(e),
+                        _ => self.check_stmt(s),
+                    }
+                }
+                self.env = old_env;
+                ty
+            }
+            
             Expr::If(_, then_block, _, _) => {
                 if let Some(last) = then_block.stmts.last() {
                     if let Stmt::Expr(e, _) = last {
@@ -216,6 +275,7 @@ impl TypeChecker {
             Stmt::Let { name, ty, init, .. } => {
                 let inferred = init.as_ref().map(|e| self.infer_expr(e));
                 let final_ty = ty.clone().or(inferred).unwrap_or(Type::Infer);
+                let final_ty = final_ty.atomic_value().unwrap_or(final_ty);
                 self.env.define_var(name.clone(), final_ty);
             }
             Stmt::Assign(target, value, span) => {
@@ -310,8 +370,11 @@ impl TypeChecker {
                     ty: Type::Named(name.clone()),
                 }
             }
-            Decl::Const { name, ty, value, .. } => {
+            Decl::Const { name, ty, value, span, .. } => {
                 let inferred = self.infer_expr(value);
+                if !const_compatible(ty, &inferred) {
+                    self.error(format!("Constant {} is declared {} but its value is {} at line {}", name, ty, inferred, span.line));
+                }
                 self.env.define_var(name.clone(), ty.clone());
                 
                 TypedDecl {
@@ -319,6 +382,30 @@ impl TypeChecker {
                     ty: ty.clone(),
                 }
             }
+            Decl::Static { name, ty, value, span, .. } => {
+                let inferred = value.as_ref().map(|v| self.infer_expr(v));
+                let ty = match (ty, inferred) {
+                    (Some(ty), Some(inferred)) => {
+                        let value_ty = ty.atomic_value().unwrap_or_else(|| ty.clone());
+                        if !const_compatible(&value_ty, &inferred) {
+                            self.error(format!("Static {} is declared {} but its value is {} at line {}", name, ty, inferred, span.line));
+                        }
+                        ty.clone()
+                    }
+                    (Some(ty), None) => ty.clone(),
+                    (None, Some(inferred)) => inferred,
+                    (None, None) => {
+                        self.error(format!("Static {} needs a type or an initializer at line {}", name, span.line));
+                        Type::Infer
+                    }
+                };
+                self.env.define_var(name.clone(), ty.atomic_value().unwrap_or_else(|| ty.clone()));
+                
+                TypedDecl {
+                    decl: decl.clone(),
+                    ty,
+                }
+            }
             _ => TypedDecl {
                 decl: decl.clone(),
                 ty: Type::Unit,
@@ -327,26 +414,54 @@ impl TypeChecker {
     }
     
     pub fn check_module(&mut self, module: &Module) -> Result<TypedModule> {
+        let decls = self.check_decls(&module.decls)?;
+        Ok(TypedModule { decls })
+    }
+    
+    /// Check declarations against everything declared so far
+    pub fn check_decls(&mut self, decls: &[Decl]) -> Result<Vec<TypedDecl>> {
         let mut typed_decls = Vec::new();
         
         // First pass: collect signatures
-        for decl in &module.decls {
-            if let Decl::Func { name, params, ret, .. } = decl {
-                let param_types: Vec<Type> = params.iter().map(|p| p.ty.clone()).collect();
-                self.env.define_func(name.clone(), param_types, ret.clone());
+        for decl in decls {
+            match decl {
+                Decl::Func { name, params, ret, .. } => {
+                    let param_types: Vec<Type> = params.iter().map(|p| p.ty.clone()).collect();
+                    self.env.define_func(name.clone(), param_types, ret.clone());
+                }
+                // Statics may be used by functions declared before them
+                Decl::Static { name, ty: Some(ty), .. } => {
+                    self.env.define_var(name.clone(), ty.atomic_value().unwrap_or_else(|| ty.clone()));
+                }
+                Decl::Extern { funcs, .. } => {
+                    // Variadic externs stay unregistered: their arity is only known per call
+                    for f in funcs.iter().filter(|f| !f.variadic) {
+                        let param_types: Vec<Type> = f.params.iter().map(|p| p.ty.clone()).collect();
+                        self.env.define_func(f.name.clone(), param_types, f.ret.clone());
+                    }
+                }
+                _ => {}
             }
         }
         
         // Second pass: type check bodies
-        for decl in &module.decls {
+        for decl in decls {
             typed_decls.push(self.check_decl(decl));
         }
         
-        if !self.errors.is_empty() {
-            return Err(anyhow!("Type errors:\n{}", self.errors.join("\n")));
-        }
-        
-        Ok(TypedModule { decls: typed_decls })
+        self.take_errors()?;
+        Ok(typed_decls)
+    }
+}
+
+/// Whether a constant's (folded) value fits its declared type
+fn const_compatible(declared: &Type, value: &Type) -> bool {
+    let is_int = |n: &str| matches!(n, "Int" | "Int8" | "Int16" | "Int32" | "Int64" |
+        "UInt8" | "UInt16" | "UInt32" | "UInt64" | "Char");
+    match (declared, value) {
+        (Type::Named(d), Type::Named(v)) if v == "Int" => is_int(d) || d == "Float",
+        (Type::Named(d), Type::Named(v)) if v == "Bool" => d == "Bool",
+        _ => true,
     }
 }
 
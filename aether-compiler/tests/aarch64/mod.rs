@@ -0,0 +1,648 @@
+//! A user-mode AArch64 Linux emulator, enough to run the native backend's
+//! statically linked executables on hosts without an AArch64 machine or qemu
+//!
+//! It covers the integer and double-precision instructions the backend and
+//! its runtime emit and the system calls a single-threaded program makes;
+//! anything else stops the run with an error naming the instruction.
+
+use std::path::Path;
+
+/// Instructions to run before giving up on a program that does not exit
+const STEP_LIMIT: u64 = 200_000_000;
+const STACK_TOP: u64 = 0x7fff_0000_0000;
+const STACK_SIZE: u64 = 1 << 20;
+const MMAP_BASE: u64 = 0x7000_0000_0000;
+const ENOSYS: i64 = -38;
+
+struct Region {
+    start: u64,
+    bytes: Vec<u8>,
+}
+
+struct Machine {
+    x: [u64; 31],
+    /// The low 64 bits of the SIMD and floating-point registers
+    d: [u64; 32],
+    sp: u64,
+    pc: u64,
+    n: bool,
+    z: bool,
+    c: bool,
+    v: bool,
+    tpidr: u64,
+    memory: Vec<Region>,
+    next_map: u64,
+    stdout: Vec<u8>,
+}
+
+/// Run an executable; its exit code and what it wrote to stdout
+pub fn run(path: &Path) -> Result<(i32, String), String> {
+    let image = std::fs::read(path).map_err(|e| e.to_string())?;
+    let mut machine = Machine::load(&image)?;
+    for _ in 0..STEP_LIMIT {
+        if let Some(code) = machine.step()? {
+            return Ok((code, String::from_utf8_lossy(&machine.stdout).into_owned()));
+        }
+    }
+    Err(format!("no exit after {} instructions", STEP_LIMIT))
+}
+
+fn read_u16(b: &[u8], at: usize) -> u64 {
+    u16::from_le_bytes(b[at..at + 2].try_into().unwrap()) as u64
+}
+
+fn read_u32(b: &[u8], at: usize) -> u64 {
+    u32::from_le_bytes(b[at..at + 4].try_into().unwrap()) as u64
+}
+
+fn read_u64(b: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+}
+
+fn bits(insn: u32, hi: u32, lo: u32) -> u32 {
+    (insn >> lo) & ((1u32 << (hi - lo + 1)) - 1)
+}
+
+/// Sign-extend the low `width` bits of `value`
+fn sext(value: u64, width: u32) -> i64 {
+    ((value << (64 - width)) as i64) >> (64 - width)
+}
+
+fn ones(n: u32) -> u64 {
+    if n >= 64 { u64::MAX } else { (1u64 << n) - 1 }
+}
+
+/// The `DecodeBitMasks` immediate of the logical instructions
+fn bitmask(n: u32, imms: u32, immr: u32, width: u32) -> Result<u64, String> {
+    let combined = (n << 6) | (!imms & 0x3f);
+    if combined == 0 {
+        return Err("reserved bitmask immediate".into());
+    }
+    let size = 1u32 << (31 - combined.leading_zeros());
+    let s = imms & (size - 1);
+    let r = immr & (size - 1);
+    let element = ones(s + 1);
+    let element = if r == 0 { element } else { ((element >> r) | (element << (size - r))) & ones(size) };
+    let mut mask = 0u64;
+    let mut at = 0;
+    while at < width {
+        mask |= element << at;
+        at += size;
+    }
+    Ok(mask & ones(width))
+}
+
+impl Machine {
+    fn load(image: &[u8]) -> Result<Machine, String> {
+        if image.len() < 64 || &image[..4] != b"\x7fELF" || read_u16(image, 18) != 183 {
+            return Err("not an AArch64 ELF executable".into());
+        }
+        let entry = read_u64(image, 24);
+        let phoff = read_u64(image, 32) as usize;
+        let phnum = read_u16(image, 56) as usize;
+        let mut machine = Machine {
+            x: [0; 31],
+            d: [0; 32],
+            sp: 0,
+            pc: entry,
+            n: false,
+            z: false,
+            c: false,
+            v: false,
+            tpidr: 0,
+            memory: Vec::new(),
+            next_map: MMAP_BASE,
+            stdout: Vec::new(),
+        };
+        let mut phdr = 0;
+        for i in 0..phnum {
+            let at = phoff + i * 56;
+            if read_u32(image, at) != 1 {
+                continue;
+            }
+            let offset = read_u64(image, at + 8) as usize;
+            let vaddr = read_u64(image, at + 16);
+            let filesz = read_u64(image, at + 32) as usize;
+            let memsz = read_u64(image, at + 40) as usize;
+            let mut bytes = vec![0; memsz];
+            bytes[..filesz].copy_from_slice(&image[offset..offset + filesz]);
+            if offset == 0 {
+                phdr = vaddr + phoff as u64;
+            }
+            machine.memory.push(Region { start: vaddr, bytes });
+        }
+        machine.memory.push(Region { start: STACK_TOP - STACK_SIZE, bytes: vec![0; STACK_SIZE as usize] });
+
+        // argc, argv, envp and the auxiliary vector with AT_PHDR and AT_PHNUM
+        let name = STACK_TOP - 16;
+        machine.write_bytes(name, b"program\0")?;
+        let words = [1, name, 0, 0, 3, phdr, 5, phnum as u64, 0, 0];
+        machine.sp = (name - 8 * words.len() as u64) & !15;
+        for (i, word) in words.iter().enumerate() {
+            machine.write(machine.sp + 8 * i as u64, 8, *word)?;
+        }
+        Ok(machine)
+    }
+
+    fn region(&mut self, addr: u64, len: u64) -> Result<(&mut Vec<u8>, usize), String> {
+        for region in &mut self.memory {
+            if addr >= region.start && addr + len <= region.start + region.bytes.len() as u64 {
+                return Ok((&mut region.bytes, (addr - region.start) as usize));
+            }
+        }
+        Err(format!("access to unmapped address {:#x}", addr))
+    }
+
+    fn read(&mut self, addr: u64, size: u64) -> Result<u64, String> {
+        let (bytes, at) = self.region(addr, size)?;
+        let mut word = [0u8; 8];
+        word[..size as usize].copy_from_slice(&bytes[at..at + size as usize]);
+        Ok(u64::from_le_bytes(word))
+    }
+
+    fn write(&mut self, addr: u64, size: u64, value: u64) -> Result<(), String> {
+        let (bytes, at) = self.region(addr, size)?;
+        bytes[at..at + size as usize].copy_from_slice(&value.to_le_bytes()[..size as usize]);
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, addr: u64, data: &[u8]) -> Result<(), String> {
+        let (bytes, at) = self.region(addr, data.len() as u64)?;
+        bytes[at..at + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Register `r`, where 31 is the zero register
+    fn reg(&self, r: u32) -> u64 {
+        if r == 31 { 0 } else { self.x[r as usize] }
+    }
+
+    /// Register `r`, where 31 is the stack pointer
+    fn reg_sp(&self, r: u32) -> u64 {
+        if r == 31 { self.sp } else { self.x[r as usize] }
+    }
+
+    fn set(&mut self, r: u32, value: u64) {
+        if r != 31 {
+            self.x[r as usize] = value;
+        }
+    }
+
+    fn set_sp(&mut self, r: u32, value: u64) {
+        if r == 31 { self.sp = value } else { self.x[r as usize] = value }
+    }
+
+    /// `a + b + carry` in `width` bits, setting NZCV when `flags`
+    fn add_with_carry(&mut self, a: u64, b: u64, carry: bool, width: u32, flags: bool) -> u64 {
+        let (a, b) = (a & ones(width), b & ones(width));
+        let wide = a as u128 + b as u128 + carry as u128;
+        let result = (wide as u64) & ones(width);
+        if flags {
+            let sign = 1u64 << (width - 1);
+            self.n = result & sign != 0;
+            self.z = result == 0;
+            self.c = wide >> width != 0;
+            self.v = (a & sign == b & sign) && (result & sign != a & sign);
+        }
+        result
+    }
+
+    fn set_logic_flags(&mut self, result: u64, width: u32) {
+        self.n = result >> (width - 1) & 1 != 0;
+        self.z = result == 0;
+        self.c = false;
+        self.v = false;
+    }
+
+    fn condition(&self, cond: u32) -> bool {
+        let holds = match cond >> 1 {
+            0 => self.z,
+            1 => self.c,
+            2 => self.n,
+            3 => self.v,
+            4 => self.c && !self.z,
+            5 => self.n == self.v,
+            6 => !self.z && self.n == self.v,
+            _ => true,
+        };
+        if cond & 1 == 1 && cond != 15 { !holds } else { holds }
+    }
+
+    fn shift(value: u64, kind: u32, amount: u32, width: u32) -> u64 {
+        let value = value & ones(width);
+        let amount = amount % width;
+        let result = match kind {
+            0 => value << amount,
+            1 => value >> amount,
+            2 => (sext(value, width) >> amount) as u64,
+            _ => if amount == 0 { value } else { (value >> amount) | (value << (width - amount)) },
+        };
+        result & ones(width)
+    }
+
+    fn extend(value: u64, option: u32, amount: u32) -> u64 {
+        let value = match option {
+            0 => value & 0xff,
+            1 => value & 0xffff,
+            2 => value & 0xffff_ffff,
+            4 => sext(value, 8) as u64,
+            5 => sext(value, 16) as u64,
+            6 => sext(value, 32) as u64,
+            _ => value,
+        };
+        value << amount
+    }
+
+    /// Execute one instruction; the exit code once the program exits
+    fn step(&mut self) -> Result<Option<i32>, String> {
+        let pc = self.pc;
+        let insn = self.read(pc, 4)? as u32;
+        self.pc = pc + 4;
+        let unsupported = || format!("unsupported instruction {:#010x} at {:#x}", insn, pc);
+        match bits(insn, 28, 25) {
+            0b1000 | 0b1001 => self.immediate(insn, pc).ok_or_else(unsupported)?,
+            0b1010 | 0b1011 => return self.branch(insn, pc)?.ok_or_else(unsupported),
+            0b0100 | 0b0110 | 0b1100 | 0b1110 => self.load_store(insn)?.ok_or_else(unsupported)?,
+            0b0101 | 0b1101 => self.register(insn).ok_or_else(unsupported)?,
+            0b0111 | 0b1111 => self.float(insn).ok_or_else(unsupported)?,
+            _ => return Err(unsupported()),
+        }
+        Ok(None)
+    }
+
+    fn immediate(&mut self, insn: u32, pc: u64) -> Option<()> {
+        let sf = bits(insn, 31, 31) == 1;
+        let width = if sf { 64 } else { 32 };
+        let (rn, rd) = (bits(insn, 9, 5), bits(insn, 4, 0));
+        match bits(insn, 25, 23) {
+            // adr, adrp
+            0b000 | 0b001 => {
+                let imm = sext(((bits(insn, 23, 5) << 2) | bits(insn, 30, 29)) as u64, 21);
+                let value = if sf { (pc & !0xfff).wrapping_add((imm << 12) as u64) } else { pc.wrapping_add(imm as u64) };
+                self.set(rd, value);
+            }
+            // add, adds, sub, subs
+            0b010 => {
+                let imm = (bits(insn, 21, 10) as u64) << (12 * bits(insn, 22, 22));
+                let flags = bits(insn, 29, 29) == 1;
+                let a = self.reg_sp(rn);
+                let result = if bits(insn, 30, 30) == 1 {
+                    self.add_with_carry(a, !imm, true, width, flags)
+                } else {
+                    self.add_with_carry(a, imm, false, width, flags)
+                };
+                if flags { self.set(rd, result) } else { self.set_sp(rd, result) }
+            }
+            // and, orr, eor, ands
+            0b100 => {
+                let imm = bitmask(bits(insn, 22, 22), bits(insn, 15, 10), bits(insn, 21, 16), width).ok()?;
+                let a = self.reg(rn) & ones(width);
+                let result = match bits(insn, 30, 29) {
+                    0 | 3 => a & imm,
+                    1 => a | imm,
+                    _ => a ^ imm,
+                };
+                if bits(insn, 30, 29) == 3 {
+                    self.set_logic_flags(result, width);
+                    self.set(rd, result);
+                } else {
+                    self.set_sp(rd, result);
+                }
+            }
+            // movn, movz, movk
+            0b101 => {
+                let shift = 16 * bits(insn, 22, 21);
+                let imm = (bits(insn, 20, 5) as u64) << shift;
+                let value = match bits(insn, 30, 29) {
+                    0 => !imm & ones(width),
+                    2 => imm,
+                    3 => (self.reg(rd) & !(0xffffu64 << shift) | imm) & ones(width),
+                    _ => return None,
+                };
+                self.set(rd, value);
+            }
+            // sbfm, ubfm: the shifts and extensions
+            0b110 => {
+                let (immr, imms) = (bits(insn, 21, 16), bits(insn, 15, 10));
+                let src = self.reg(rn) & ones(width);
+                let signed = match bits(insn, 30, 29) {
+                    0 => true,
+                    2 => false,
+                    _ => return None,
+                };
+                let value = if imms >= immr {
+                    let field = (src >> immr) & ones(imms - immr + 1);
+                    if signed { sext(field, imms - immr + 1) as u64 } else { field }
+                } else {
+                    let field = src & ones(imms + 1);
+                    let field = if signed { sext(field, imms + 1) as u64 } else { field };
+                    field << (width - immr)
+                };
+                self.set(rd, value & ones(width));
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn branch(&mut self, insn: u32, pc: u64) -> Result<Option<Option<i32>>, String> {
+        let rt = bits(insn, 4, 0);
+        if bits(insn, 30, 26) == 0b00101 {
+            // b, bl
+            if bits(insn, 31, 31) == 1 {
+                self.x[30] = pc + 4;
+            }
+            self.pc = pc.wrapping_add((sext(bits(insn, 25, 0) as u64, 26) << 2) as u64);
+        } else if bits(insn, 31, 24) == 0b0101_0100 && bits(insn, 4, 4) == 0 {
+            // b.cond
+            if self.condition(bits(insn, 3, 0)) {
+                self.pc = pc.wrapping_add((sext(bits(insn, 23, 5) as u64, 19) << 2) as u64);
+            }
+        } else if bits(insn, 30, 25) == 0b011010 {
+            // cbz, cbnz
+            let width = if bits(insn, 31, 31) == 1 { 64 } else { 32 };
+            let zero = self.reg(rt) & ones(width) == 0;
+            if zero != (bits(insn, 24, 24) == 1) {
+                self.pc = pc.wrapping_add((sext(bits(insn, 23, 5) as u64, 19) << 2) as u64);
+            }
+        } else if bits(insn, 30, 25) == 0b011011 {
+            // tbz, tbnz
+            let bit = (bits(insn, 31, 31) << 5) | bits(insn, 23, 19);
+            let set = self.reg(rt) >> bit & 1 == 1;
+            if set == (bits(insn, 24, 24) == 1) {
+                self.pc = pc.wrapping_add((sext(bits(insn, 18, 5) as u64, 14) << 2) as u64);
+            }
+        } else if bits(insn, 31, 25) == 0b1101011 {
+            // br, blr, ret
+            let target = self.reg(bits(insn, 9, 5));
+            match bits(insn, 24, 21) {
+                0 | 2 => {}
+                1 => self.x[30] = pc + 4,
+                _ => return Ok(None),
+            }
+            self.pc = target;
+        } else if insn & 0xffe0_001f == 0xd400_0001 {
+            return Ok(Some(self.syscall()?));
+        } else if insn & 0xffff_ffe0 == 0xd53b_d040 {
+            self.set(rt, self.tpidr);
+        } else if insn & 0xffff_ffe0 == 0xd51b_d040 {
+            self.tpidr = self.reg(rt);
+        } else if insn >> 12 == 0xd5033 || insn >> 12 == 0xd5032 {
+            // hints and barriers
+        } else {
+            return Ok(None);
+        }
+        Ok(Some(None))
+    }
+
+    fn load_store(&mut self, insn: u32) -> Result<Option<()>, String> {
+        let (rn, rt) = (bits(insn, 9, 5), bits(insn, 4, 0));
+        if bits(insn, 26, 26) == 1 {
+            return Ok(None);
+        }
+        if bits(insn, 29, 24) == 0b001000 {
+            // Exclusive and ordered accesses; with one thread every
+            // exclusive store succeeds
+            let size = 1u64 << bits(insn, 31, 30);
+            let addr = self.reg_sp(rn);
+            if bits(insn, 22, 22) == 1 {
+                let value = self.read(addr, size)?;
+                self.set(rt, value);
+            } else {
+                self.write(addr, size, self.reg(rt))?;
+                if bits(insn, 23, 23) == 0 {
+                    self.set(bits(insn, 20, 16), 0);
+                }
+            }
+            return Ok(Some(()));
+        }
+        if bits(insn, 29, 27) == 0b101 {
+            // ldp, stp
+            let mode = bits(insn, 24, 23);
+            let (size, signed) = match bits(insn, 31, 30) {
+                0 => (4, false),
+                1 => (4, true),
+                2 => (8, false),
+                _ => return Ok(None),
+            };
+            let offset = (sext(bits(insn, 21, 15) as u64, 7) * size as i64) as u64;
+            let base = self.reg_sp(rn);
+            let addr = if mode == 1 { base } else { base.wrapping_add(offset) };
+            let rt2 = bits(insn, 14, 10);
+            if bits(insn, 22, 22) == 1 {
+                let (a, b) = (self.read(addr, size)?, self.read(addr + size, size)?);
+                let (a, b) = if signed { (sext(a, 32) as u64, sext(b, 32) as u64) } else { (a, b) };
+                self.set(rt, a);
+                self.set(rt2, b);
+            } else {
+                self.write(addr, size, self.reg(rt))?;
+                self.write(addr + size, size, self.reg(rt2))?;
+            }
+            if mode == 1 || mode == 3 {
+                self.set_sp(rn, base.wrapping_add(offset));
+            }
+            return Ok(Some(()));
+        }
+        if bits(insn, 29, 27) == 0b011 {
+            // ldr (literal)
+            let addr = self.pc.wrapping_sub(4).wrapping_add((sext(bits(insn, 23, 5) as u64, 19) << 2) as u64);
+            let value = match bits(insn, 31, 30) {
+                0 => self.read(addr, 4)?,
+                1 => self.read(addr, 8)?,
+                2 => sext(self.read(addr, 4)?, 32) as u64,
+                _ => return Ok(Some(())),
+            };
+            self.set(rt, value);
+            return Ok(Some(()));
+        }
+        if bits(insn, 29, 27) != 0b111 {
+            return Ok(None);
+        }
+        let scale = bits(insn, 31, 30);
+        let size = 1u64 << scale;
+        let base = self.reg_sp(rn);
+        let mut writeback = None;
+        let addr = if bits(insn, 25, 24) == 1 {
+            base.wrapping_add((bits(insn, 21, 10) as u64) << scale)
+        } else if bits(insn, 21, 21) == 1 && bits(insn, 11, 10) == 2 {
+            let amount = if bits(insn, 12, 12) == 1 { scale } else { 0 };
+            base.wrapping_add(Self::extend(self.reg(bits(insn, 20, 16)), bits(insn, 15, 13), amount))
+        } else if bits(insn, 21, 21) == 0 {
+            let offset = sext(bits(insn, 20, 12) as u64, 9) as u64;
+            match bits(insn, 11, 10) {
+                0 => base.wrapping_add(offset),
+                1 => {
+                    writeback = Some(base.wrapping_add(offset));
+                    base
+                }
+                3 => {
+                    writeback = Some(base.wrapping_add(offset));
+                    base.wrapping_add(offset)
+                }
+                _ => return Ok(None),
+            }
+        } else {
+            return Ok(None);
+        };
+        match bits(insn, 23, 22) {
+            0 => self.write(addr, size, self.reg(rt))?,
+            1 => {
+                let value = self.read(addr, size)?;
+                self.set(rt, value);
+            }
+            2 if scale < 3 => {
+                let value = sext(self.read(addr, size)?, 8 * size as u32) as u64;
+                self.set(rt, value);
+            }
+            3 if scale < 2 => {
+                let value = sext(self.read(addr, size)?, 8 * size as u32) as u64 & ones(32);
+                self.set(rt, value);
+            }
+            // prfm
+            2 => {}
+            _ => return Ok(None),
+        }
+        if let Some(address) = writeback {
+            self.set_sp(rn, address);
+        }
+        Ok(Some(()))
+    }
+
+    fn register(&mut self, insn: u32) -> Option<()> {
+        let width = if bits(insn, 31, 31) == 1 { 64 } else { 32 };
+        let (rm, rn, rd) = (bits(insn, 20, 16), bits(insn, 9, 5), bits(insn, 4, 0));
+        let flags = bits(insn, 29, 29) == 1;
+        let sub = bits(insn, 30, 30) == 1;
+        if bits(insn, 28, 24) == 0b01010 {
+            // and, bic, orr, orn, eor, eon, ands, bics
+            let mut b = Self::shift(self.reg(rm), bits(insn, 23, 22), bits(insn, 15, 10), width);
+            if bits(insn, 21, 21) == 1 {
+                b = !b & ones(width);
+            }
+            let a = self.reg(rn) & ones(width);
+            let result = match bits(insn, 30, 29) {
+                0 | 3 => a & b,
+                1 => a | b,
+                _ => a ^ b,
+            };
+            if bits(insn, 30, 29) == 3 {
+                self.set_logic_flags(result, width);
+            }
+            self.set(rd, result);
+        } else if bits(insn, 28, 24) == 0b01011 {
+            // add, adds, sub, subs with a shifted or extended register
+            let extended = bits(insn, 21, 21) == 1;
+            let (a, b) = if extended {
+                (self.reg_sp(rn), Self::extend(self.reg(rm), bits(insn, 15, 13), bits(insn, 12, 10)))
+            } else {
+                (self.reg(rn), Self::shift(self.reg(rm), bits(insn, 23, 22), bits(insn, 15, 10), width))
+            };
+            let result = if sub {
+                self.add_with_carry(a, !b, true, width, flags)
+            } else {
+                self.add_with_carry(a, b, false, width, flags)
+            };
+            if extended && !flags { self.set_sp(rd, result) } else { self.set(rd, result) }
+        } else if bits(insn, 28, 21) == 0b1101_0100 {
+            // csel, csinc, csinv, csneg
+            let value = if self.condition(bits(insn, 15, 12)) {
+                self.reg(rn)
+            } else {
+                let b = self.reg(rm);
+                match (sub, bits(insn, 11, 10)) {
+                    (false, 0) => b,
+                    (false, 1) => b.wrapping_add(1),
+                    (true, 0) => !b,
+                    (true, 1) => b.wrapping_neg(),
+                    _ => return None,
+                }
+            };
+            self.set(rd, value & ones(width));
+        } else if bits(insn, 30, 21) == 0b00_1101_0110 {
+            // udiv, sdiv and the variable shifts
+            let (a, b) = (self.reg(rn) & ones(width), self.reg(rm) & ones(width));
+            let value = match bits(insn, 15, 10) {
+                2 => a.checked_div(b).unwrap_or(0),
+                3 => {
+                    let (a, b) = (sext(a, width), sext(b, width));
+                    if b == 0 { 0 } else { a.wrapping_div(b) as u64 }
+                }
+                op @ 8..=11 => Self::shift(a, op - 8, (b % width as u64) as u32, width),
+                _ => return None,
+            };
+            self.set(rd, value & ones(width));
+        } else if bits(insn, 28, 24) == 0b11011 && bits(insn, 23, 21) == 0 {
+            // madd, msub
+            let product = self.reg(rn).wrapping_mul(self.reg(rm));
+            let ra = self.reg(bits(insn, 14, 10));
+            let value = if bits(insn, 15, 15) == 1 { ra.wrapping_sub(product) } else { ra.wrapping_add(product) };
+            self.set(rd, value & ones(width));
+        } else {
+            return None;
+        }
+        Some(())
+    }
+
+    /// Linux system calls by their AArch64 numbers; the exit code on exit
+    /// Scalar double-precision arithmetic, comparisons and conversions
+    fn float(&mut self, insn: u32) -> Option<()> {
+        let (rd, rn, rm) = (bits(insn, 4, 0), bits(insn, 9, 5), bits(insn, 20, 16));
+        let (a, b) = (f64::from_bits(self.d[rn as usize]), f64::from_bits(self.d[rm as usize]));
+        match insn & 0xffff_fc00 {
+            0x9e67_0000 => self.d[rd as usize] = self.reg(rn),
+            0x9e66_0000 => self.set(rd, self.d[rn as usize]),
+            0x9e62_0000 => self.d[rd as usize] = (self.reg(rn) as i64 as f64).to_bits(),
+            0x9e78_0000 => self.set(rd, a as i64 as u64),
+            _ => match insn & 0xffe0_fc00 {
+                0x1e60_0800 => self.d[rd as usize] = (a * b).to_bits(),
+                0x1e60_1800 => self.d[rd as usize] = (a / b).to_bits(),
+                0x1e60_2800 => self.d[rd as usize] = (a + b).to_bits(),
+                0x1e60_3800 => self.d[rd as usize] = (a - b).to_bits(),
+                0x1e60_2000 if rd == 0 => {
+                    // fcmp: NZCV is 0110 when equal, 1000 when less, 0010
+                    // when greater and 0011 when unordered
+                    self.n = a < b;
+                    self.z = a == b;
+                    self.v = a.is_nan() || b.is_nan();
+                    self.c = a >= b || self.v;
+                }
+                _ => return None,
+            },
+        }
+        Some(())
+    }
+
+    fn syscall(&mut self) -> Result<Option<i32>, String> {
+        let [a0, a1, a2] = [self.x[0], self.x[1], self.x[2]];
+        let result = match self.x[8] {
+            // write
+            64 => {
+                let mut data = Vec::with_capacity(a2 as usize);
+                for i in 0..a2 {
+                    data.push(self.read(a1 + i, 1)? as u8);
+                }
+                match a0 {
+                    1 => self.stdout.extend_from_slice(&data),
+                    2 => eprint!("{}", String::from_utf8_lossy(&data)),
+                    _ => {}
+                }
+                a2 as i64
+            }
+            // exit, exit_group
+            93 | 94 => return Ok(Some((a0 & 0xff) as i32)),
+            // mmap: fresh zeroed memory wherever it was asked for
+            222 => {
+                let len = (a1 + 0xfff) & !0xfff;
+                let start = self.next_map;
+                self.next_map += len + 0x1000;
+                self.memory.push(Region { start, bytes: vec![0; len as usize] });
+                start as i64
+            }
+            // munmap
+            215 => 0,
+            _ => ENOSYS,
+        };
+        self.x[0] = result as u64;
+        Ok(None)
+    }
+}
@@ -0,0 +1,159 @@
+//! AArch64 backend: golden assembly, encodings checked against LLVM's
+//! assembler, and programs run on an AArch64 machine, under qemu, or else in
+//! the emulator in `aarch64/`
+//!
+//! Run with `BLESS=1` to rewrite the golden files after an intended change.
+
+mod aarch64;
+mod common;
+
+use common::*;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const TARGET: &str = "aarch64-unknown-linux-gnu";
+
+/// Golden programs, whether they build an executable, and its exit code
+const PROGRAMS: [(&str, Option<i32>); 5] = [
+    // Callee-saved registers across recursive calls
+    ("fib", Some(88)),
+    // More live values than registers, and arguments passed on the stack
+    ("spill", Some(18)),
+    // Struct fields, arrays and statics
+    ("data", Some(23)),
+    // Atomic static updates as exclusive load/store loops
+    ("atomic", Some(106)),
+    // C ABI wrappers for narrow integers, Bool and Float
+    ("export", None),
+];
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/arm64")
+}
+
+/// Build a golden program with `--emit`, leaving `name.s` and the outputs in `dir`
+fn build(dir: &Path, name: &str, bin: bool, emit: &str) {
+    let source = std::fs::read_to_string(golden_dir().join(format!("{}.aether", name))).unwrap();
+    let mut args = vec!["--backend", "native", "--target", TARGET, "--emit", emit, "-o", name];
+    if !bin {
+        args.extend(["--crate-type", "obj"]);
+    }
+    if let Err(out) = compile(dir, &format!("{}.aether", name), &source, &args) {
+        panic!("{} does not build:\n{}", name, out);
+    }
+}
+
+#[test]
+fn assembly_matches_golden() {
+    let dir = scratch("arm64_golden");
+    let bless = std::env::var_os("BLESS").is_some();
+    for (name, exit) in PROGRAMS {
+        build(&dir, name, exit.is_some(), "asm");
+        let asm = std::fs::read_to_string(dir.join(format!("{}.s", name))).unwrap();
+        let golden = golden_dir().join(format!("{}.s", name));
+        if bless {
+            std::fs::write(&golden, &asm).unwrap();
+            continue;
+        }
+        let expected = std::fs::read_to_string(&golden).unwrap_or_default();
+        assert!(asm == expected, "{}.s differs from {} (rerun with BLESS=1 if intended):\n{}", name, golden.display(), asm);
+    }
+}
+
+/// One disassembled instruction: offset, encoding, mnemonic and relocation kind
+struct Inst {
+    offset: String,
+    bytes: String,
+    mnemonic: String,
+    reloc: Option<String>,
+}
+
+fn disassemble(object: &Path) -> Vec<Inst> {
+    let out = Command::new("llvm-objdump").arg("-dr").arg(object).output().unwrap();
+    let mut insts: Vec<Inst> = Vec::new();
+    for line in String::from_utf8_lossy(&out.stdout).lines() {
+        if let Some(at) = line.find("R_AARCH64_") {
+            let kind = line[at..].split_whitespace().next().unwrap().to_string();
+            insts.last_mut().unwrap().reloc = Some(kind);
+        } else if let Some((offset, rest)) = line.trim_start().split_once(": ") {
+            let mut parts = rest.split('\t');
+            let (Some(bytes), Some(mnemonic)) = (parts.next(), parts.next()) else { continue };
+            insts.push(Inst { offset: offset.into(), bytes: bytes.trim().into(), mnemonic: mnemonic.into(), reloc: None });
+        }
+    }
+    insts
+}
+
+/// Compare encodings instruction by instruction. Assemblers may resolve a
+/// call to a local symbol themselves or leave a relocation, so relocated
+/// instructions only need the same mnemonic, and the same relocation when
+/// both objects have one
+fn same_encodings(ours: &[Inst], theirs: &[Inst]) -> Result<(), String> {
+    if ours.len() != theirs.len() {
+        return Err(format!("{} instructions, llvm-mc has {}", ours.len(), theirs.len()));
+    }
+    for (a, b) in ours.iter().zip(theirs) {
+        let same = match (&a.reloc, &b.reloc) {
+            (None, None) => a.bytes == b.bytes,
+            (Some(x), Some(y)) => x == y && a.mnemonic == b.mnemonic,
+            _ => a.mnemonic == b.mnemonic,
+        };
+        if !same || a.offset != b.offset {
+            return Err(format!("at {}: {} {} vs llvm-mc {} {}", a.offset, a.bytes, a.mnemonic, b.bytes, b.mnemonic));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn encodings_match_llvm_mc() {
+    if !has_tool("llvm-mc") || !has_tool("llvm-objdump") {
+        return;
+    }
+    let dir = scratch("arm64_encodings");
+    for (name, exit) in PROGRAMS {
+        build(&dir, name, exit.is_some(), "asm,obj");
+        let ours = dir.join(format!("{}.o", name));
+        let theirs = dir.join(format!("{}.llvm.o", name));
+        let status = Command::new("llvm-mc")
+            .args(["-triple=aarch64-linux-gnu", "-filetype=obj", "-o"])
+            .arg(&theirs)
+            .arg(dir.join(format!("{}.s", name)))
+            .status()
+            .unwrap();
+        assert!(status.success(), "llvm-mc rejects {}.s", name);
+        let ours = disassemble(&ours);
+        assert!(!ours.is_empty(), "{}: no instructions disassembled", name);
+        if let Err(diff) = same_encodings(&ours, &disassemble(&theirs)) {
+            panic!("{}: encoding differs from llvm-mc {}", name, diff);
+        }
+    }
+}
+
+/// How to run an AArch64 Linux executable on this host; None for the emulator
+fn runner() -> Option<Vec<&'static str>> {
+    if cfg!(all(target_os = "linux", target_arch = "aarch64")) {
+        Some(vec![])
+    } else if has_tool("qemu-aarch64") {
+        Some(vec!["qemu-aarch64"])
+    } else {
+        None
+    }
+}
+
+#[test]
+fn programs_run() {
+    let runner = runner();
+    let dir = scratch("arm64_run");
+    for (name, exit) in PROGRAMS {
+        let Some(exit) = exit else { continue };
+        build(&dir, name, true, "bin");
+        let program = dir.join(name);
+        let code = match runner.as_deref().map(|r| r.split_first()) {
+            Some(Some((tool, args))) => Command::new(tool).args(args).arg(&program).status().unwrap().code(),
+            Some(None) => Command::new(&program).status().unwrap().code(),
+            None => Some(aarch64::run(&program).unwrap_or_else(|e| panic!("{}: {}", name, e)).0),
+        };
+        assert_eq!(code, Some(exit), "{} exited with the wrong code", name);
+    }
+}
@@ -0,0 +1,114 @@
+//! Atomic statics: `X = X op v` is one atomic read-modify-write in every
+//! backend, and other updates that read the static are rejected
+
+mod common;
+
+use common::*;
+use std::path::Path;
+
+/// Threads that update shared counters; exits with 62 when no update is lost
+const THREADS: &str = r#"
+let mut HITS: AtomicInt = 0
+let mut DONE: AtomicInt = 0
+let mut BITS: AtomicInt = 255
+
+func work(n: Int) {
+    let mut i = 0
+    while i < n {
+        HITS = HITS + 1
+        HITS = 2 + HITS
+        HITS = HITS - 2
+        i = i + 1
+    }
+    DONE = 1 + DONE
+}
+
+func main() -> Int {
+    spawn work(500000)
+    spawn work(500000)
+    spawn work(500000)
+    spawn work(500000)
+    while DONE < 4 {
+    }
+    BITS = BITS & 60
+    BITS = 3 | BITS
+    BITS = BITS ^ 1
+    if HITS != 2000000 {
+        return 1
+    }
+    BITS
+}
+"#;
+
+fn emit(dir: &Path, args: &[&str], output: &str) -> String {
+    compile(dir, "main.aether", THREADS, args).unwrap();
+    std::fs::read_to_string(dir.join(output)).unwrap()
+}
+
+#[test]
+fn updates_that_are_not_one_instruction_are_rejected() {
+    let dir = scratch("atomics_rejected");
+    let out = reject(&dir, r#"
+let mut HITS: AtomicInt = 0
+func twice(n: Int) -> Int { n * 2 }
+func main() -> Int {
+    HITS = HITS * 2
+    HITS = 1 - HITS
+    HITS = HITS + HITS
+    HITS = twice(HITS)
+    0
+}
+"#);
+    for line in 5..=8 {
+        let message = format!("Update of atomic static HITS at line {} is a separate load and store", line);
+        assert!(out.contains(&message), "{} missing:\n{}", message, out);
+    }
+
+    accept(&dir, r#"
+let mut HITS: AtomicInt = 0
+func main() -> Int {
+    HITS = HITS + 1
+    HITS = 1 + HITS
+    HITS = HITS - 1
+    HITS = HITS & 7
+    HITS = 8 | HITS
+    HITS = HITS ^ 3
+    HITS = 5
+    let mut n = HITS * 2
+    n = n * 2
+    n
+}
+"#);
+}
+
+#[test]
+fn updates_lower_to_atomic_read_modify_writes() {
+    let dir = scratch("atomics_lowering");
+    let c = emit(&dir, &["--target", "c", "-o", "main.c"], "main.c");
+    for line in ["AE_FETCH(add, &ae_HITS, 1);", "AE_FETCH(sub, &ae_HITS, 2);", "AE_FETCH(add, &ae_DONE, 1);",
+        "AE_FETCH(and, &ae_BITS, 60);", "AE_FETCH(or, &ae_BITS, 3);", "AE_FETCH(xor, &ae_BITS, 1);"] {
+        assert!(c.contains(line), "{} missing:\n{}", line, c);
+    }
+    let ir = emit(&dir, &["--backend", "llvm", "--emit=llvm-ir", "-o", "main.ll"], "main.ll");
+    assert!(ir.contains("atomicrmw add i64* @HITS, i64 2 seq_cst"), "{}", ir);
+    assert!(ir.contains("atomicrmw xor i64* @BITS, i64 1 seq_cst"), "{}", ir);
+    let asm = emit(&dir, &["--backend", "native", "--target", "x86_64-unknown-linux-gnu", "--emit", "asm", "-o", "main"], "main.s");
+    for op in ["add", "sub", "and", "or", "xor"] {
+        assert!(asm.contains(&format!("lock {} qword ptr", op)), "lock {} missing:\n{}", op, asm);
+    }
+}
+
+#[test]
+fn threads_never_lose_updates() {
+    if has_tool("cc") {
+        let dir = scratch("atomics_c");
+        compile(&dir, "main.aether", THREADS, &["--target", "c", "-o", "main.c"]).unwrap();
+        cc(&dir, &["-O2", "main.c", "-o", "main", "-lpthread"]).unwrap();
+        assert_eq!(run(&dir.join("main")).0, 62);
+    }
+    if native_host() {
+        let dir = scratch("atomics_native");
+        compile(&dir, "main.aether", THREADS, &["--backend", "native", "-o", "main"]).unwrap();
+        assert_eq!(run(&dir.join("main")).0, 62);
+    }
+}
@@ -0,0 +1,140 @@
+//! `bindgen`: records that are not one 8-byte word per field are bound by
+//! offset, and declarations Aether cannot represent are skipped with a note
+
+mod common;
+
+use common::*;
+use std::path::Path;
+
+const HEADER: &str = r#"
+typedef long double ldbl;
+struct words { long a; double b; char *c; };
+struct mixed { int a; char b; double c; };
+union num { int i; double d; };
+long double ld_add(long double a, long double b);
+ldbl ld_twice(ldbl x);
+struct words words_copy(struct words w);
+struct words *words_new(long a);
+long words_sum(struct words *w);
+long mixed_sum(struct mixed *m);
+double scale(double x);
+"#;
+
+const LIBRARY: &str = r#"
+#include <stdlib.h>
+struct words { long a; double b; char *c; };
+struct mixed { int a; char b; double c; };
+long mixed_sum(struct mixed *m) { return m->a + m->b + (long)m->c; }
+long words_sum(struct words *w) { return w->a + (long)w->b + w->c[0]; }
+struct words *words_new(long a) {
+    struct words *w = malloc(sizeof *w);
+    w->a = a;
+    w->b = 2.0;
+    w->c = "A";
+    return w;
+}
+"#;
+
+/// Fills `struct mixed` by offset and reads `struct words` as an Aether struct
+const PROGRAM: &str = r#"
+func main() -> Int {
+    let m = __builtin_malloc(mixed_SIZE)
+    unsafe {
+        __builtin_store32(m + mixed_a_OFFSET, 40)
+        __builtin_store8(m + mixed_b_OFFSET, 2)
+        __builtin_store64(m + mixed_c_OFFSET, 4613937818241073152)
+    }
+    let w: words = unsafe { words_new(50) }
+    unsafe { mixed_sum(m) + words_sum(w) + w.a }
+}
+"#;
+
+fn bindgen(dir: &Path, header: &str) -> String {
+    std::fs::write(dir.join("h.h"), header).unwrap();
+    let out = aetherc(dir, &["bindgen", "h.h", "-o", "out.aether"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    std::fs::read_to_string(dir.join("out.aether")).unwrap()
+}
+
+#[test]
+fn long_double_and_by_value_records_are_skipped() {
+    let dir = scratch("bindgen_skipped");
+    let out = bindgen(&dir, HEADER);
+    for note in [
+        "// bindgen: typedef ldbl: long double has no Aether equivalent, skipped",
+        "// bindgen: function ld_add: long double has no Aether equivalent, skipped",
+        "// bindgen: function ld_twice: long double has no Aether equivalent, skipped",
+        "// bindgen: function words_copy: a struct or union passed by value has no Aether equivalent, skipped",
+    ] {
+        assert!(out.contains(note), "{} missing:\n{}", note, out);
+    }
+    assert!(!out.contains("type ldbl") && !out.contains("func ld_"), "{}", out);
+    assert!(out.contains("func scale(x: Float) -> Float"), "{}", out);
+}
+
+#[test]
+fn records_are_bound_by_word_or_by_offset() {
+    let dir = scratch("bindgen_records");
+    let out = bindgen(&dir, HEADER);
+    // Pointers to word records are Aether struct values
+    assert!(out.contains("struct words {\n    a: Int64,\n    b: Float,\n    c: *Int8,\n}"), "{}", out);
+    assert!(out.contains("func words_new(a: Int64) -> words"), "{}", out);
+    assert!(out.contains("func mixed_sum(m: *mixed) -> Int64"), "{}", out);
+    for line in [
+        "struct mixed {\n}",
+        "const mixed_SIZE: Int = 16",
+        "const mixed_a_OFFSET: Int = 0",
+        "const mixed_b_OFFSET: Int = 4",
+        "const mixed_c_OFFSET: Int = 8",
+        "struct num {\n}",
+        "const num_SIZE: Int = 8",
+        "const num_i_OFFSET: Int = 0",
+        "const num_d_OFFSET: Int = 0",
+    ] {
+        assert!(out.contains(line), "{} missing:\n{}", line, out);
+    }
+    assert!(!out.contains("_data"), "{}", out);
+}
+
+#[test]
+fn bound_records_match_the_c_layout() {
+    if !has_tool("cc") {
+        return;
+    }
+    let dir = scratch("bindgen_layout");
+    let source = bindgen(&dir, HEADER) + PROGRAM;
+    std::fs::write(dir.join("lib.c"), LIBRARY).unwrap();
+    compile(&dir, "main.aether", &source, &["--target", "c", "-o", "main.c"]).unwrap();
+    cc(&dir, &["main.c", "lib.c", "-o", "main"]).unwrap();
+    // 40 + 2 + 3, then 50 + 2 + 'A', then w.a
+    assert_eq!(run(&dir.join("main")).0, 212);
+}
+
+#[test]
+fn parse_errors_name_the_token() {
+    let dir = scratch("bindgen_errors");
+    let out = bindgen(&dir, "int broken(int *, * y);\nint after(void);\n");
+    assert!(out.contains("// bindgen: Expected type, got '*' at line 1\n"), "{}", out);
+    assert!(out.contains("func after() -> Int32"), "{}", out);
+}
+
+#[test]
+fn macro_wrapped_prototypes_are_reported() {
+    let dir = scratch("bindgen_macros");
+    let out = bindgen(&dir, r#"
+#define ZEXTERN extern
+#define ZEXPORT
+#define OF(args) args
+ZEXTERN const char * ZEXPORT zlibVersion OF((void));
+ZEXTERN int ZEXPORT deflate OF((int level, int flush));
+extern int errno_value __THROW;
+int plain(int x);
+"#);
+    for name in ["zlibVersion at line 5", "deflate at line 6"] {
+        let note = format!("// bindgen: declaration {}: wrapped in macros ZEXPORT, OF, skipped", name);
+        assert!(out.contains(&note), "{} missing:\n{}", note, out);
+    }
+    // Trailing attribute macros without arguments still leave a variable
+    assert!(out.contains("// bindgen: global variable errno_value: not supported, skipped"), "{}", out);
+    assert!(out.contains("func plain(x: Int32) -> Int32") && !out.contains("ZEXPORT:"), "{}", out);
+}
@@ -0,0 +1,260 @@
+//! Borrow checker diagnostics on whole programs
+
+mod common;
+
+use common::*;
+
+#[test]
+fn shared_references_reject_writes() {
+    let dir = scratch("borrowck_shared_refs");
+    let out = reject(&dir, r#"
+struct P { x: Int }
+impl P {
+    func bump(&mut self) { self.x = self.x + 1 }
+}
+func set(p: &P) { p.x = 5 }
+func store(p: &Int) { *p = 5 }
+func call(p: &P) { p.bump() }
+func local() {
+    let mut a = P { x: 1 }
+    let r = &a
+    r.x = 2
+}
+func main() -> Int { return 0 }
+"#);
+    assert!(out.contains("Cannot assign to p.x through shared reference p at line 6"), "{}", out);
+    assert!(out.contains("Cannot assign to *p through shared reference p at line 7"), "{}", out);
+    assert!(out.contains("Cannot call bump, which takes `&mut self`, on p through shared reference p at line 8"), "{}", out);
+    assert!(out.contains("Cannot assign to r.x through shared reference r at line 12"), "{}", out);
+}
+
+#[test]
+fn mutable_references_allow_writes() {
+    let dir = scratch("borrowck_mut_refs");
+    accept(&dir, r#"
+struct P { x: Int }
+impl P {
+    func bump(&mut self) { self.x = self.x + 1 }
+}
+func set(p: &mut P) {
+    p.x = 5
+    p.bump()
+}
+func store(p: &mut Int) { *p = 5 }
+func local() {
+    let mut a = P { x: 1 }
+    let m = &mut a
+    m.x = 3
+}
+func main() -> Int { return 0 }
+"#);
+}
+
+#[test]
+fn spawn_reports_references_apart_from_raw_pointers() {
+    let dir = scratch("borrowck_send_fields");
+    let out = reject(&dir, r#"
+struct Holder { p: &Int }
+struct Raw { q: *Int }
+struct Outer { inner: Holder }
+func work(h: Holder) { }
+func raw(r: Raw) { }
+func outer(o: Outer) { }
+func main() -> Int {
+    let x = 1
+    spawn work(Holder { p: &x })
+    spawn raw(Raw { q: 0 })
+    spawn outer(Outer { inner: Holder { p: &x } })
+    return 0
+}
+"#);
+    assert!(out.contains("Holder is not Send, as it holds a reference in field p that may outlive what it points at"), "{}", out);
+    assert!(out.contains("Raw is not Send, as it holds a raw pointer in field q"), "{}", out);
+    assert!(out.contains("Outer is not Send, as it holds a reference in field inner.p"), "{}", out);
+    assert!(!out.contains("Holder is not Send, as it holds a raw pointer"), "{}", out);
+}
+
+#[test]
+fn escaping_borrow_is_reported_once() {
+    let dir = scratch("borrowck_escape_once");
+    let out = reject(&dir, r#"
+func pick(c: Bool) -> &Int {
+    let x = 1
+    return if c { &x } else { &x }
+}
+func both(c: Bool) -> &Int {
+    let x = 1
+    let y = 2
+    return if c { &x } else { &y }
+}
+func main() -> Int { return 0 }
+"#);
+    assert_eq!(out.matches("Cannot return a reference to local variable x at line 4").count(), 1, "{}", out);
+    assert_eq!(out.matches("Cannot return a reference to local variable x at line 9").count(), 1, "{}", out);
+    assert_eq!(out.matches("Cannot return a reference to local variable y at line 9").count(), 1, "{}", out);
+}
+
+#[test]
+fn borrows_end_at_their_last_use() {
+    let dir = scratch("borrowck_last_use");
+    accept(&dir, r#"
+func main() -> Int {
+    let mut x = 1
+    let r = &x
+    let y = *r
+    x = 2
+    x + y
+}
+"#);
+    let out = reject(&dir, r#"
+func main() -> Int {
+    let mut x = 1
+    let r = &x
+    x = 2
+    let y = *r
+    y
+}
+"#);
+    assert!(out.contains("Cannot assign to x because it is borrowed at line 5\n  borrow of x created at line 4\n  borrow later used at line 6"), "{}", out);
+}
+
+#[test]
+fn borrows_conflict_across_loop_iterations() {
+    let dir = scratch("borrowck_loop");
+    // `r` borrows `x` at the end of one iteration and is read in the next
+    let out = reject(&dir, r#"
+func main() -> Int {
+    let mut x = 1
+    let y = 5
+    let mut r = &y
+    let mut total = 0
+    let mut i = 0
+    while i < 3 {
+        x = i
+        total = total + *r
+        r = &x
+        i = i + 1
+    }
+    total
+}
+"#);
+    assert!(out.contains("Cannot assign to x because it is borrowed at line 9\n  borrow of x created at line 11\n  borrow later used at line 10"), "{}", out);
+}
+
+#[test]
+fn method_arguments_may_use_the_receiver() {
+    let dir = scratch("borrowck_two_phase");
+    accept(&dir, r#"
+func main() -> Int {
+    let mut v: Vec<Int> = Vec::new()
+    v.push(v.len())
+    v.push(v.len())
+    0
+}
+"#);
+}
+
+#[test]
+fn moved_values_cannot_be_used() {
+    let dir = scratch("borrowck_use_after_move");
+    let out = reject(&dir, r#"
+func take(s: String) -> Int { 0 }
+func main() -> Int {
+    let s: String = "a"
+    take(s)
+    let r = &s
+    take(s)
+}
+"#);
+    assert!(out.contains("Cannot borrow moved value: s at line 6\n  value moved at line 5"), "{}", out);
+    assert!(out.contains("Value already moved: s at line 7\n  value moved at line 5"), "{}", out);
+}
+
+#[test]
+fn moves_in_loops_and_branches_are_tracked() {
+    let dir = scratch("borrowck_move_paths");
+    let out = reject(&dir, r#"
+func take(s: String) -> Int { 0 }
+func main() -> Int {
+    let s: String = "a"
+    let mut i = 0
+    while i < 2 {
+        take(s)
+        i = i + 1
+    }
+    0
+}
+"#);
+    assert!(out.contains("Value already moved: s at line 7\n  value moved at line 7, in the previous iteration of the loop"), "{}", out);
+
+    let out = reject(&dir, r#"
+func take(s: String) -> Int { 0 }
+func main() -> Int {
+    let s: String = "a"
+    if 1 > 0 {
+        take(s)
+    }
+    take(s)
+}
+"#);
+    assert!(out.contains("Value already moved: s at line 8\n  value moved at line 6"), "{}", out);
+
+    // One move on each branch is fine
+    accept(&dir, r#"
+func take(s: String) -> Int { 0 }
+func main() -> Int {
+    let s: String = "a"
+    if 1 > 0 {
+        take(s)
+    } else {
+        take(s)
+    }
+    0
+}
+"#);
+}
+
+#[test]
+fn borrowed_values_cannot_be_moved() {
+    let dir = scratch("borrowck_move_borrowed");
+    let out = reject(&dir, r#"
+func take(s: String) -> Int { 0 }
+func main() -> Int {
+    let s: String = "a"
+    let r = &s
+    take(s)
+    let n = r
+    0
+}
+"#);
+    assert!(out.contains("Cannot move out of s because it is borrowed at line 6\n  borrow of s created at line 5\n  borrow later used at line 7"), "{}", out);
+}
+
+#[test]
+fn disjoint_fields_borrow_independently() {
+    let dir = scratch("borrowck_fields");
+    accept(&dir, r#"
+struct P { a: Int, b: Int }
+func set(dst: &mut Int, v: Int) {
+    *dst = v
+}
+func main() -> Int {
+    let mut p = P { a: 1, b: 2 }
+    let x = &mut p.a
+    let y = &p.b
+    set(x, *y)
+    p.a
+}
+"#);
+    let out = reject(&dir, r#"
+struct P { a: Int, b: Int }
+func main() -> Int {
+    let mut p = P { a: 1, b: 2 }
+    let y = &p.a
+    let x = &mut p
+    let z = *y
+    z
+}
+"#);
+    assert!(out.contains("Cannot borrow p as mutable because p.a is also borrowed as immutable at line 6\n  borrow of p.a created at line 5\n  borrow later used at line 7"), "{}", out);
+}
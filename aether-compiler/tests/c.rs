@@ -0,0 +1,60 @@
+//! C backend: the program's functions and statics get symbols of their own,
+//! so they cannot replace libc functions of the same name
+
+mod common;
+
+use common::*;
+
+/// Functions and statics named like libc's, next to builtins that call libc
+const PROGRAM: &str = r#"
+let mut errno: Int = 3
+pub const abs: Int = 4
+
+struct Buf { len: Int }
+
+impl Buf {
+    func free(&self) -> Int {
+        self.len
+    }
+}
+
+func strlen(s: String) -> Int {
+    100
+}
+
+func puts(n: Int) -> Int {
+    n + 1
+}
+
+#[export]
+pub func malloc_count(n: Int32) -> Int32 {
+    n
+}
+
+func main() -> Int {
+    let ae_x = 1
+    let s = "hello"
+    let b = Buf { len: 2 }
+    unsafe { __builtin_write(1, s, 5) }
+    __builtin_print(10)
+    strlen(s) + puts(ae_x) + unsafe { errno } + abs + b.free() + malloc_count(0)
+}
+"#;
+
+#[test]
+fn program_symbols_do_not_clash_with_libc() {
+    if !has_tool("cc") {
+        return;
+    }
+    let dir = scratch("c_symbols");
+    compile(&dir, "main.aether", PROGRAM, &["--target", "c", "-o", "main.c"]).unwrap();
+    let source = std::fs::read_to_string(dir.join("main.c")).unwrap();
+    for symbol in ["ae_strlen(", "ae_puts(", "ae_errno", "ae_Buf__free(", "ae_malloc_count__body("] {
+        assert!(source.contains(symbol), "{} missing:\n{}", symbol, source);
+    }
+    // `#[export]` keeps its name for C callers, and `main` is the C entry point
+    assert!(source.contains("int32_t malloc_count(int32_t n)"), "{}", source);
+    assert!(source.contains("int main("), "{}", source);
+    cc(&dir, &["-Werror", "main.c", "-o", "main"]).unwrap();
+    assert_eq!(run(&dir.join("main")), (111, "hello\n".to_string()));
+}
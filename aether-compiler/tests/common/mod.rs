@@ -0,0 +1,77 @@
+//! Shared helpers for the integration tests: write a program, run the
+//! compiler binary on it and run what it produced
+
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Fresh directory for one test
+pub fn scratch(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Run `aetherc` with `args` inside `dir`
+pub fn aetherc(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_aether-compiler"))
+        .args(args)
+        .current_dir(dir)
+        .env("RUST_BACKTRACE", "0")
+        .output()
+        .expect("cannot run aetherc")
+}
+
+/// Write `source` to `dir/file` and compile it with `args`; `Err` holds the
+/// compiler's output when it fails
+pub fn compile(dir: &Path, file: &str, source: &str, args: &[&str]) -> Result<String, String> {
+    std::fs::write(dir.join(file), source).unwrap();
+    let mut all = vec![file];
+    all.extend_from_slice(args);
+    let out = aetherc(dir, &all);
+    let text = format!("{}{}", String::from_utf8_lossy(&out.stdout), String::from_utf8_lossy(&out.stderr));
+    if out.status.success() { Ok(text) } else { Err(text) }
+}
+
+/// Compile a program that must be rejected and return the diagnostics
+pub fn reject(dir: &Path, source: &str) -> String {
+    match compile(dir, "main.aether", source, &["--emit=typed-ast"]) {
+        Ok(out) => panic!("program was accepted:\n{}", out),
+        Err(out) => out,
+    }
+}
+
+/// Compile a program that must pass every check
+pub fn accept(dir: &Path, source: &str) {
+    if let Err(out) = compile(dir, "main.aether", source, &["--emit=typed-ast"]) {
+        panic!("program was rejected:\n{}", out);
+    }
+}
+
+/// Run a program and return its exit code and standard output
+pub fn run(program: &Path) -> (i32, String) {
+    let out = Command::new(program).output().expect("cannot run program");
+    (out.status.code().unwrap_or(-1), String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// Whether a host tool is on PATH; tests that need one are skipped without it
+pub fn has_tool(tool: &str) -> bool {
+    let found = Command::new(tool).arg("--version").output().is_ok();
+    if !found {
+        eprintln!("skipping: {} not found", tool);
+    }
+    found
+}
+
+/// Run `cc` on C sources and return its diagnostics on failure
+pub fn cc(dir: &Path, args: &[&str]) -> Result<(), String> {
+    let out = Command::new("cc").args(args).current_dir(dir).output().expect("cannot run cc");
+    if out.status.success() { Ok(()) } else { Err(String::from_utf8_lossy(&out.stderr).into_owned()) }
+}
+
+/// Whether native executables for this host can be built and run
+pub fn native_host() -> bool {
+    cfg!(all(target_os = "linux", target_arch = "x86_64"))
+}
@@ -0,0 +1,49 @@
+//! `comptime` results spliced into the program
+
+mod common;
+
+use common::*;
+
+#[test]
+fn allocations_cannot_escape_to_run_time() {
+    let dir = scratch("comptime_allocations");
+    let out = reject(&dir, r#"
+func main() -> Int {
+    let p = comptime { __builtin_malloc(8) }
+    return 0
+}
+"#);
+    assert!(out.contains("Compile-time value at line 3 is a *UInt8 pointing into compile-time memory"), "{}", out);
+
+    let out = reject(&dir, r#"
+func main() -> Int {
+    let p: Int = comptime {
+        let buf = __builtin_malloc(16)
+        buf
+    }
+    return 0
+}
+"#);
+    assert!(out.contains("Compile-time value at line 3 is a *UInt8"), "{}", out);
+}
+
+#[test]
+fn data_results_are_spliced() {
+    let dir = scratch("comptime_data");
+    accept(&dir, r#"
+func letters() -> String {
+    let buf: String = __builtin_malloc(3)
+    unsafe { __builtin_store8(buf, 104) }
+    unsafe { __builtin_store8(buf + 1, 105) }
+    unsafe { __builtin_store8(buf + 2, 0) }
+    buf
+}
+
+func main() -> Int {
+    let n: Int = comptime { 6 * 7 }
+    let s = comptime { letters() }
+    let a = comptime { [1, 2, 3] }
+    return n
+}
+"#);
+}
@@ -0,0 +1,69 @@
+//! Constant evaluation: errors in `const` initializers are reported at
+//! compile time, and array lengths may be constant expressions
+
+mod common;
+
+use common::*;
+
+#[test]
+fn overflow_and_division_by_zero_are_rejected() {
+    let dir = scratch("consteval_arithmetic");
+    let out = reject(&dir, r#"
+const BIG: Int = 9223372036854775807 + 1
+func main() -> Int { 0 }
+"#);
+    assert!(out.contains("Integer overflow in constant expression 9223372036854775807 + 1 at line 2"), "{}", out);
+
+    let out = reject(&dir, r#"
+const ZERO: Int = 0
+const RATIO: Int = 10 / ZERO
+func main() -> Int { 0 }
+"#);
+    assert!(out.contains("Division by zero in constant expression 10 / ZERO at line 3"), "{}", out);
+}
+
+#[test]
+fn shifts_wider_than_a_word_are_rejected() {
+    let dir = scratch("consteval_shifts");
+    let out = reject(&dir, r#"
+const MASK: Int = 1 << 64
+func main() -> Int { 0 }
+"#);
+    assert!(out.contains("Shift amount 64 out of range in 1 << 64 at line 2"), "{}", out);
+}
+
+#[test]
+fn cycles_and_runaway_recursion_are_rejected() {
+    let dir = scratch("consteval_cycles");
+    let out = reject(&dir, r#"
+const A: Int = B + 1
+const B: Int = A * 2
+func main() -> Int { 0 }
+"#);
+    assert!(out.contains("Constant A depends on itself (A -> B -> A) at line 2"), "{}", out);
+
+    let out = reject(&dir, r#"
+const func down(n: Int) -> Int {
+    down(n + 1)
+}
+const X: Int = down(0)
+func main() -> Int { 0 }
+"#);
+    assert!(out.contains("const func calls nested deeper than 256 in down at line 3"), "{}", out);
+}
+
+#[test]
+fn array_lengths_are_constant_expressions() {
+    let dir = scratch("consteval_lengths");
+    accept(&dir, r#"
+const N: Int = 4
+const func double(n: Int) -> Int {
+    n * 2
+}
+func main() -> Int {
+    let a: [Int; N * 2] = [1, 2, 3, 4, 5, 6, 7, 8]
+    let b: [Int; double(N)] = a
+    b[7] - 8
+}
+"#);
+}
@@ -0,0 +1,91 @@
+//! `--crate-type`: libraries need no `main`, and only `pub` functions are
+//! global symbols in what each crate type produces
+
+mod common;
+
+use common::*;
+use std::path::Path;
+use std::process::Command;
+
+const LIB: &str = r#"
+pub func visible(a: Int) -> Int {
+    hidden(a) + 1
+}
+
+func hidden(a: Int) -> Int {
+    a * 2
+}
+"#;
+
+/// `nm` output for a file in `dir`
+fn nm(dir: &Path, args: &[&str]) -> String {
+    let out = Command::new("nm").args(args).current_dir(dir).output().unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    String::from_utf8_lossy(&out.stdout).into_owned()
+}
+
+/// Whether `nm` lists `name` as a global (upper-case) or local (lower-case) text symbol
+fn text_symbol(symbols: &str, name: &str, global: bool) -> bool {
+    let kind = if global { " T " } else { " t " };
+    symbols.lines().any(|l| l.ends_with(&format!("{}{}", kind, name)))
+}
+
+#[test]
+fn native_objects_and_archives_export_pub_functions() {
+    if !native_host() || !has_tool("nm") {
+        return;
+    }
+    let dir = scratch("crate_types_native");
+    for (crate_type, output) in [("obj", "lib.o"), ("staticlib", "liblib.a")] {
+        compile(&dir, "lib.aether", LIB, &["--backend", "native", "--crate-type", crate_type, "-o", output]).unwrap();
+        let symbols = nm(&dir, &[output]);
+        assert!(text_symbol(&symbols, "visible", true), "{}: {}", crate_type, symbols);
+        assert!(text_symbol(&symbols, "hidden", false), "{}: {}", crate_type, symbols);
+    }
+    let out = compile(&dir, "lib.aether", LIB, &["--backend", "native", "--crate-type", "cdylib", "-o", "liblib.so"]).unwrap_err();
+    assert!(out.contains("The native backend cannot build shared libraries yet"), "{}", out);
+}
+
+#[test]
+fn llvm_libraries_give_private_functions_internal_linkage() {
+    let dir = scratch("crate_types_llvm");
+    for crate_type in ["obj", "staticlib", "cdylib"] {
+        compile(&dir, "lib.aether", LIB, &["--crate-type", crate_type, "--emit=llvm-ir", "-o", "lib"]).unwrap();
+        let ir = std::fs::read_to_string(dir.join("lib.ll")).unwrap();
+        assert!(ir.contains("define i64 @visible(") && ir.contains("define internal i64 @hidden("), "{}: {}", crate_type, ir);
+    }
+    if !native_host() || !has_tool("llc") || !has_tool("cc") {
+        return;
+    }
+    // The shared library's dynamic symbol table has only the pub function
+    let llc = Command::new("llc")
+        .args(["-mtriple=x86_64-pc-linux-gnu", "-relocation-model=pic", "-filetype=obj", "lib.ll", "-o", "lib.o"])
+        .current_dir(&dir)
+        .status()
+        .unwrap();
+    assert!(llc.success());
+    cc(&dir, &["-shared", "lib.o", "-o", "liblib.so"]).unwrap();
+    let dynamic = nm(&dir, &["-D", "--defined-only", "liblib.so"]);
+    assert!(text_symbol(&dynamic, "visible", true) && !dynamic.contains("hidden"), "{}", dynamic);
+}
+
+#[test]
+fn c_libraries_make_private_functions_static() {
+    if !has_tool("cc") || !has_tool("nm") {
+        return;
+    }
+    let dir = scratch("crate_types_c");
+    compile(&dir, "lib.aether", LIB, &["--target", "c", "--crate-type", "staticlib", "-o", "lib.c"]).unwrap();
+    cc(&dir, &["-c", "lib.c", "-o", "lib.o"]).unwrap();
+    let symbols = nm(&dir, &["lib.o"]);
+    assert!(text_symbol(&symbols, "ae_visible", true), "{}", symbols);
+    assert!(!text_symbol(&symbols, "ae_hidden", true), "{}", symbols);
+}
+
+#[test]
+fn only_executables_need_main() {
+    let dir = scratch("crate_types_main");
+    let out = compile(&dir, "lib.aether", LIB, &["--backend", "native", "-o", "lib"]).unwrap_err();
+    assert!(out.contains("No main function found"), "{}", out);
+    compile(&dir, "lib.aether", LIB, &["--backend", "native", "--crate-type", "obj", "-o", "lib.o"]).unwrap();
+}
@@ -0,0 +1,88 @@
+//! Native ELF output: static libraries are `ar` archives whose object
+//! carries the public symbols, and executables link without a loader
+
+mod common;
+
+use common::*;
+use std::path::Path;
+use std::process::Command;
+
+const LIB: &str = r#"
+pub func add(a: Int, b: Int) -> Int {
+    a + b
+}
+
+pub func twice(a: Int) -> Int {
+    add(a, a)
+}
+"#;
+
+const CALLER: &str = r#"
+#include <stdint.h>
+
+int64_t add(int64_t a, int64_t b);
+int64_t twice(int64_t a);
+
+int main(void) {
+    return (int)(add(2, 3) + twice(20));
+}
+"#;
+
+/// Standard output of a host tool run in `dir`
+fn tool(dir: &Path, name: &str, args: &[&str]) -> String {
+    let out = Command::new(name).args(args).current_dir(dir).output().unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    String::from_utf8_lossy(&out.stdout).into_owned()
+}
+
+/// Program header types of an ELF64 file
+fn segment_types(image: &[u8]) -> Vec<u32> {
+    let word = |at: usize, n: usize| image[at..at + n].iter().rev().fold(0u64, |v, b| v << 8 | *b as u64);
+    let (phoff, phentsize, phnum) = (word(32, 8) as usize, word(54, 2) as usize, word(56, 2) as usize);
+    (0..phnum).map(|i| word(phoff + i * phentsize, 4) as u32).collect()
+}
+
+#[test]
+fn staticlib_archives_name_the_member_and_list_symbols() {
+    if !native_host() || !has_tool("ar") || !has_tool("nm") {
+        return;
+    }
+    let dir = scratch("elf_staticlib");
+    // Only the leading `lib` is dropped from the member name
+    compile(&dir, "lib.aether", LIB, &["--backend", "native", "--crate-type", "staticlib", "-o", "libliberty.a"]).unwrap();
+    assert_eq!(tool(&dir, "ar", &["t", "libliberty.a"]).trim(), "liberty.o");
+    let symbols = tool(&dir, "nm", &["libliberty.a"]);
+    for name in ["add", "twice"] {
+        assert!(symbols.lines().any(|l| l.ends_with(&format!(" T {}", name))), "{}", symbols);
+    }
+}
+
+#[test]
+fn staticlib_links_into_a_static_executable() {
+    if !native_host() || !has_tool("cc") {
+        return;
+    }
+    let dir = scratch("elf_static_link");
+    compile(&dir, "lib.aether", LIB, &["--backend", "native", "--crate-type", "staticlib", "-o", "libliberty.a"]).unwrap();
+    std::fs::write(dir.join("main.c"), CALLER).unwrap();
+    if let Err(e) = cc(&dir, &["-static", "main.c", "libliberty.a", "-o", "main"]) {
+        eprintln!("skipping: no static libc: {}", e);
+        return;
+    }
+    assert_eq!(run(&dir.join("main")).0, 45);
+}
+
+#[test]
+fn executables_are_static() {
+    if !native_host() {
+        return;
+    }
+    let dir = scratch("elf_static_exe");
+    let main = "func main() -> Int {\n    __builtin_print(104)\n    __builtin_print(10)\n    7\n}\n";
+    compile(&dir, "main.aether", main, &["--backend", "native", "-o", "main"]).unwrap();
+    let image = std::fs::read(dir.join("main")).unwrap();
+    // No PT_INTERP or PT_DYNAMIC: nothing needs loading before the entry point
+    let types = segment_types(&image);
+    assert!(types.contains(&1) && !types.contains(&2) && !types.contains(&3), "{:?}", types);
+    assert_eq!(run(&dir.join("main")), (7, "h\n".to_string()));
+}
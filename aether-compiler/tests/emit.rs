@@ -0,0 +1,115 @@
+//! `--emit`: each stage writes its own file next to the output, several
+//! stages may be requested at once, and the frontend dumps are stable JSON
+//! or a readable tree
+
+mod common;
+
+use common::*;
+use serde_json::Value;
+use std::path::Path;
+
+const MAIN: &str = r#"
+func main() -> Int {
+    let x = 40
+    x + 2
+}
+"#;
+
+/// Parse a JSON dump written by the compiler
+fn json(path: &Path) -> Value {
+    let text = std::fs::read_to_string(path).unwrap();
+    serde_json::from_str(&text).unwrap_or_else(|e| panic!("{}: {}\n{}", path.display(), e, text))
+}
+
+#[test]
+fn frontend_stages_dump_json() {
+    let dir = scratch("emit_json");
+    compile(&dir, "main.aether", MAIN, &["--emit=tokens,ast,typed-ast"]).unwrap();
+    // Only the requested stages are written
+    assert!(!dir.join("main").exists() && !dir.join("a.out").exists());
+
+    let tokens = json(&dir.join("main.tokens.json"));
+    let first = &tokens[0];
+    assert_eq!((first["kind"].as_str(), first["lexeme"].as_str(), first["line"].as_u64()), (Some("Func"), Some("func"), Some(2)));
+    assert!(tokens.as_array().unwrap().iter().any(|t| t["int_value"] == 40), "{}", tokens);
+
+    let ast = json(&dir.join("main.ast.json"));
+    let func = &ast["decls"][0]["Func"];
+    assert_eq!(func["name"], "main");
+    assert_eq!(func["ret"]["Named"], "Int");
+    assert_eq!(func["body"]["stmts"][0]["Let"]["name"], "x");
+
+    // The typed AST pairs each declaration with its type
+    let typed = json(&dir.join("main.typed-ast.json"));
+    assert_eq!(typed["decls"][0]["decl"]["Func"]["name"], "main");
+    assert_eq!(typed["decls"][0]["ty"]["Func"][1]["Named"], "Int");
+}
+
+#[test]
+fn json_dumps_are_stable() {
+    let dir = scratch("emit_stable");
+    let mut dumps = Vec::new();
+    for _ in 0..2 {
+        compile(&dir, "main.aether", MAIN, &["--emit=tokens,ast,typed-ast"]).unwrap();
+        let read = |ext: &str| std::fs::read_to_string(dir.join(format!("main.{}", ext))).unwrap();
+        dumps.push([read("tokens.json"), read("ast.json"), read("typed-ast.json")]);
+    }
+    assert_eq!(dumps[0], dumps[1]);
+}
+
+#[test]
+fn tree_format_writes_text_dumps() {
+    let dir = scratch("emit_tree");
+    compile(&dir, "main.aether", MAIN, &["--emit=tokens,ast,typed-ast", "--emit-format=tree", "-o", "prog"]).unwrap();
+    let read = |ext: &str| std::fs::read_to_string(dir.join(format!("prog.{}", ext))).unwrap();
+
+    let tokens = read("tokens.txt");
+    let first = tokens.lines().next().unwrap();
+    assert!(first.starts_with("2:") && first.contains("Func") && first.ends_with("\"func\""), "{}", tokens);
+
+    let ast = read("ast.txt");
+    assert!(ast.starts_with("decls:\n  Func\n"), "{}", ast);
+    for line in ["name: \"main\"", "Let", "Int [40, @3:", "Named \"Int\""] {
+        assert!(ast.contains(line), "missing {:?} in\n{}", line, ast);
+    }
+    let typed = read("typed-ast.txt");
+    assert!(typed.contains("decl:") && typed.contains("ty:"), "{}", typed);
+    assert!(!dir.join("prog.ast.json").exists());
+}
+
+#[test]
+fn native_backend_writes_assembly_object_and_binary() {
+    if !native_host() {
+        return;
+    }
+    let dir = scratch("emit_native");
+    compile(&dir, "main.aether", MAIN, &["--backend", "native", "--emit=asm,obj,bin", "-o", "out"]).unwrap();
+    let asm = std::fs::read_to_string(dir.join("out.s")).unwrap();
+    assert!(asm.contains(".globl main") && asm.contains("main:"), "{}", asm);
+    let obj = std::fs::read(dir.join("out.o")).unwrap();
+    assert_eq!(&obj[..4], b"\x7fELF");
+    assert_eq!(run(&dir.join("out")).0, 42);
+
+    // Without bin only the intermediate files are written
+    compile(&dir, "main.aether", MAIN, &["--backend", "native", "--emit=asm", "-o", "asm_only"]).unwrap();
+    assert!(dir.join("asm_only.s").exists() && !dir.join("asm_only").exists() && !dir.join("asm_only.o").exists());
+
+    let out = compile(&dir, "main.aether", MAIN, &["--backend", "native", "--emit=llvm-ir"]).unwrap_err();
+    assert!(out.contains("--emit=llvm-ir needs --backend=llvm"), "{}", out);
+}
+
+#[test]
+fn llvm_ir_is_written_with_the_frontend_dumps() {
+    let dir = scratch("emit_llvm");
+    compile(&dir, "main.aether", MAIN, &["--emit=tokens,llvm-ir", "-o", "prog"]).unwrap();
+    let ir = std::fs::read_to_string(dir.join("prog.ll")).unwrap();
+    assert!(ir.contains("define i64 @main()"), "{}", ir);
+    assert!(dir.join("prog.tokens.json").exists() && !dir.join("prog").exists());
+}
+
+#[test]
+fn unknown_stages_are_rejected() {
+    let dir = scratch("emit_unknown");
+    let out = compile(&dir, "main.aether", MAIN, &["--emit=tokens,mir"]).unwrap_err();
+    assert!(out.contains("invalid value 'mir'"), "{}", out);
+}
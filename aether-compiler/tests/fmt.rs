@@ -0,0 +1,98 @@
+//! `aetherc fmt`: idempotence, line width and reference types
+
+mod common;
+
+use std::path::Path;
+use common::*;
+
+const MAX_WIDTH: usize = 100;
+
+/// Copy every .aether file under `from` into `to`
+fn copy_sources(from: &Path, to: &Path) {
+    std::fs::create_dir_all(to).unwrap();
+    for entry in std::fs::read_dir(from).unwrap() {
+        let path = entry.unwrap().path();
+        let dest = to.join(path.file_name().unwrap());
+        if path.is_dir() {
+            copy_sources(&path, &dest);
+        } else if path.extension().is_some_and(|e| e == "aether") {
+            std::fs::copy(&path, dest).unwrap();
+        }
+    }
+}
+
+/// Code lines (not comments or string literals) longer than MAX_WIDTH
+fn long_lines(dir: &Path, out: &mut Vec<String>) {
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            long_lines(&path, out);
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        for (i, line) in text.lines().enumerate() {
+            if line.len() > MAX_WIDTH && !line.contains("//") && !line.contains('"') {
+                out.push(format!("{}:{}: {}", path.display(), i + 1, line));
+            }
+        }
+    }
+}
+
+fn fmt(dir: &Path, args: &[&str]) -> (bool, String) {
+    let out = aetherc(dir, args);
+    (out.status.success(), format!("{}{}", String::from_utf8_lossy(&out.stdout), String::from_utf8_lossy(&out.stderr)))
+}
+
+#[test]
+fn repo_sources_are_idempotent_and_fit() {
+    let dir = scratch("fmt_repo");
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+    for tree in ["compiler", "runtime", "examples"] {
+        copy_sources(&root.join(tree), &dir.join(tree));
+    }
+    let (ok, out) = fmt(&dir, &["fmt", "."]);
+    assert!(ok, "{}", out);
+    let (ok, out) = fmt(&dir, &["fmt", "--check", "."]);
+    assert!(ok, "formatting is not idempotent:\n{}", out);
+    let mut long = Vec::new();
+    long_lines(&dir, &mut long);
+    assert!(long.is_empty(), "lines over {} columns:\n{}", MAX_WIDTH, long.join("\n"));
+}
+
+#[test]
+fn wraps_long_conditions_and_one_line_blocks() {
+    let dir = scratch("fmt_width");
+    let source = r#"func classify(kind: Int, first_operand: Int, second_operand: Int) -> Int {
+    if kind == 1 || kind == 2 || kind == 3 || kind == 4 || kind == 5 || kind == 6 || kind == 7 || kind == 8 { return 1 }
+    if kind == 9 { return first_operand } else if kind == 10 { return second_operand } else if kind == 11 { return 0 }
+    while first_operand + second_operand < 100000 && first_operand - second_operand > 0 && kind != 12 && kind != 13 {
+        return 2
+    }
+    0
+}
+func store(entry: Int, count: Int) { unsafe { __builtin_store64(entry + 8, count + first_operand_offset()) } }
+func first_operand_offset() -> Int { 8 }
+"#;
+    std::fs::write(dir.join("wide.aether"), source).unwrap();
+    let (ok, out) = fmt(&dir, &["fmt", "wide.aether"]);
+    assert!(ok, "{}", out);
+    let formatted = std::fs::read_to_string(dir.join("wide.aether")).unwrap();
+    for line in formatted.lines() {
+        assert!(line.len() <= MAX_WIDTH, "line too long:\n{}\nin:\n{}", line, formatted);
+    }
+    assert!(formatted.contains("    if kind == 1 ||\n        kind == 2 ||"), "{}", formatted);
+    assert!(formatted.contains("        kind == 8\n    {\n        return 1\n    }"), "{}", formatted);
+    assert!(formatted.contains("    if kind == 9 {\n        return first_operand\n    } else if kind == 10 {"), "{}", formatted);
+    assert!(formatted.contains("func store(entry: Int, count: Int) {\n    unsafe {"), "{}", formatted);
+    let (ok, out) = fmt(&dir, &["fmt", "--check", "wide.aether"]);
+    assert!(ok, "formatting is not idempotent:\n{}", out);
+}
+
+#[test]
+fn keeps_reference_types() {
+    let dir = scratch("fmt_refs");
+    let source = "func get(p: &Int) -> Int { *p }\n\nfunc set<'a>(p: &'a mut Int, v: &mut Int) -> &'a Int {\n    *p = *v\n    p\n}\n";
+    std::fs::write(dir.join("refs.aether"), source).unwrap();
+    let (ok, out) = fmt(&dir, &["fmt", "--check", "refs.aether"]);
+    assert!(ok, "{}", out);
+}
@@ -0,0 +1,98 @@
+// AArch64 assembly generated by Aether Compiler
+    .text
+
+    .balign 4
+    .globl bump
+    .type bump, %function
+bump:
+    stp x29, x30, [sp, #-16]!
+    mov x29, sp
+    mov x9, x0
+    adrp x10, HITS
+    add x10, x10, :lo12:HITS
+.Lrmw0_1:
+    ldaxr x8, [x10]
+    add x8, x8, x9
+    stlxr w30, x8, [x10]
+    cbnz w30, .Lrmw0_1
+    mov x9, #1
+    adrp x10, HITS
+    add x10, x10, :lo12:HITS
+.Lrmw0_4:
+    ldaxr x8, [x10]
+    sub x8, x8, x9
+    stlxr w30, x8, [x10]
+    cbnz w30, .Lrmw0_4
+    mov x0, #0
+.Lret0:
+    mov sp, x29
+    ldp x29, x30, [sp], #16
+    ret
+    .size bump, .-bump
+
+    .balign 4
+    .globl main
+    .type main, %function
+main:
+    stp x29, x30, [sp, #-16]!
+    mov x29, sp
+    mov x0, #5
+    bl bump
+    mov x9, #60
+    adrp x10, BITS
+    add x10, x10, :lo12:BITS
+.Lrmw1_4:
+    ldaxr x8, [x10]
+    and x8, x8, x9
+    stlxr w30, x8, [x10]
+    cbnz w30, .Lrmw1_4
+    mov x9, #3
+    adrp x10, BITS
+    add x10, x10, :lo12:BITS
+.Lrmw1_7:
+    ldaxr x8, [x10]
+    orr x8, x8, x9
+    stlxr w30, x8, [x10]
+    cbnz w30, .Lrmw1_7
+    mov x9, #1
+    adrp x10, BITS
+    add x10, x10, :lo12:BITS
+.Lrmw1_10:
+    ldaxr x8, [x10]
+    eor x8, x8, x9
+    stlxr w30, x8, [x10]
+    cbnz w30, .Lrmw1_10
+    adrp x9, HITS
+    add x9, x9, :lo12:HITS
+    ldar x9, [x9]
+    adrp x10, BITS
+    add x10, x10, :lo12:BITS
+    ldar x10, [x10]
+    add x0, x9, x10
+.Lret1:
+    mov sp, x29
+    ldp x29, x30, [sp], #16
+    ret
+    .size main, .-main
+
+    .globl _start
+    .type _start, %function
+_start:
+    mov x29, #0
+    mov x30, #0
+    ldr x0, [sp]
+    add x1, sp, #8
+    bl main
+    bl exit
+
+    .data
+    .balign 8
+    .type HITS, %object
+HITS:
+    .quad 40
+    .balign 8
+    .type BITS, %object
+BITS:
+    .quad 255
+
+    .section .note.GNU-stack,"",%progbits
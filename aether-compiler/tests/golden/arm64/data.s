@@ -0,0 +1,105 @@
+// AArch64 assembly generated by Aether Compiler
+    .text
+
+    .balign 4
+    .globl area
+    .type area, %function
+area:
+    mov x9, x0
+    ldr x10, [x9]
+    ldr x9, [x9, #8]
+    mul x0, x10, x9
+.Lret0:
+    ret
+    .size area, .-area
+
+    .balign 4
+    .globl sum
+    .type sum, %function
+sum:
+    mov x9, x0
+    mov x10, #0
+    mov x11, #0
+.L0:
+    cmp x11, #4
+    b.ge .L1
+    lsl x12, x11, #3
+    add x12, x9, x12
+    ldr x12, [x12]
+    add x10, x10, x12
+    add x11, x11, #1
+    b .L0
+.L1:
+    mov x0, x10
+.Lret1:
+    ret
+    .size sum, .-sum
+
+    .balign 4
+    .globl main
+    .type main, %function
+main:
+    stp x29, x30, [sp, #-16]!
+    mov x29, sp
+    sub sp, sp, #16
+    stp x19, x20, [sp, #0]
+    mov x0, #16
+    bl malloc
+    mov x19, x0
+    mov x9, #3
+    str x9, [x19]
+    mov x9, #4
+    str x9, [x19, #8]
+    adrp x9, COUNT
+    add x9, x9, :lo12:COUNT
+    ldr x9, [x9]
+    add x9, x9, #1
+    adrp x10, COUNT
+    add x10, x10, :lo12:COUNT
+    str x9, [x10]
+    mov x0, #32
+    bl malloc
+    mov x20, x0
+    mov x9, #1
+    str x9, [x20]
+    mov x9, #2
+    str x9, [x20, #8]
+    mov x9, #3
+    str x9, [x20, #16]
+    mov x9, #4
+    str x9, [x20, #24]
+    mov x0, x19
+    bl area
+    mov x19, x0
+    mov x0, x20
+    bl sum
+    mov x9, x0
+    add x9, x19, x9
+    adrp x10, COUNT
+    add x10, x10, :lo12:COUNT
+    ldr x10, [x10]
+    add x0, x9, x10
+.Lret2:
+    ldp x19, x20, [sp, #0]
+    mov sp, x29
+    ldp x29, x30, [sp], #16
+    ret
+    .size main, .-main
+
+    .globl _start
+    .type _start, %function
+_start:
+    mov x29, #0
+    mov x30, #0
+    ldr x0, [sp]
+    add x1, sp, #8
+    bl main
+    bl exit
+
+    .data
+    .balign 8
+    .type COUNT, %object
+COUNT:
+    .quad 0
+
+    .section .note.GNU-stack,"",%progbits
@@ -0,0 +1,72 @@
+// AArch64 assembly generated by Aether Compiler
+    .text
+
+    .balign 4
+    .type clamp.body, %function
+clamp.body:
+    mov x9, x0
+    mov x10, x1
+    mov x11, x2
+    cmp x9, x10
+    b.ge .L0
+    mov x0, x10
+    b .Lret0
+.L0:
+.L1:
+    cmp x9, x11
+    b.le .L2
+    mov x0, x11
+    b .Lret0
+.L2:
+.L3:
+    mov x0, x9
+.Lret0:
+    ret
+    .size clamp.body, .-clamp.body
+
+    .balign 4
+    .type pick.body, %function
+pick.body:
+    mov x9, x0
+    mov x10, x1
+    mov x11, x2
+    cbz x9, .L4
+    mov x0, x10
+    b .Lret1
+.L4:
+.L5:
+    mov x0, x11
+.Lret1:
+    ret
+    .size pick.body, .-pick.body
+
+    .balign 4
+    .globl clamp
+    .type clamp, %function
+clamp:
+    stp x29, x30, [sp, #-16]!
+    mov x29, sp
+    sxtw x2, w2
+    sxtw x1, w1
+    sxtw x0, w0
+    bl clamp.body
+    ldp x29, x30, [sp], #16
+    ret
+    .size clamp, .-clamp
+
+    .balign 4
+    .globl pick
+    .type pick, %function
+pick:
+    stp x29, x30, [sp, #-16]!
+    mov x29, sp
+    fmov x2, d1
+    fmov x1, d0
+    and x0, x0, #0xff
+    bl pick.body
+    fmov d0, x0
+    ldp x29, x30, [sp], #16
+    ret
+    .size pick, .-pick
+
+    .section .note.GNU-stack,"",%progbits
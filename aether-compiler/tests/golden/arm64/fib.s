@@ -0,0 +1,73 @@
+// AArch64 assembly generated by Aether Compiler
+    .text
+
+    .balign 4
+    .globl fib
+    .type fib, %function
+fib:
+    stp x29, x30, [sp, #-16]!
+    mov x29, sp
+    sub sp, sp, #16
+    stp x19, x20, [sp, #0]
+    mov x19, x0
+    cmp x19, #2
+    b.ge .L0
+    mov x0, x19
+    b .Lret0
+.L0:
+.L1:
+    sub x0, x19, #1
+    bl fib
+    mov x20, x0
+    sub x0, x19, #2
+    bl fib
+    mov x9, x0
+    add x0, x20, x9
+.Lret0:
+    ldp x19, x20, [sp, #0]
+    mov sp, x29
+    ldp x29, x30, [sp], #16
+    ret
+    .size fib, .-fib
+
+    .balign 4
+    .globl main
+    .type main, %function
+main:
+    stp x29, x30, [sp, #-16]!
+    mov x29, sp
+    sub sp, sp, #16
+    stp x19, x20, [sp, #0]
+    mov x19, #0
+    mov x20, #0
+.L2:
+    cmp x20, #10
+    b.ge .L3
+    mov x0, x20
+    bl fib
+    mov x9, x0
+    add x19, x19, x9
+    add x20, x20, #1
+    b .L2
+.L3:
+    mov x17, #256
+    sdiv x8, x19, x17
+    msub x0, x8, x17, x19
+.Lret1:
+    ldp x19, x20, [sp, #0]
+    mov sp, x29
+    ldp x29, x30, [sp], #16
+    ret
+    .size main, .-main
+
+    .globl _start
+    .type _start, %function
+_start:
+    mov x29, #0
+    mov x30, #0
+    ldr x0, [sp]
+    add x1, sp, #8
+    bl main
+    bl exit
+
+    .section .note.GNU-stack,"",%progbits
@@ -0,0 +1,152 @@
+// AArch64 assembly generated by Aether Compiler
+    .text
+
+    .balign 4
+    .globl mix
+    .type mix, %function
+mix:
+    stp x29, x30, [sp, #-16]!
+    mov x29, sp
+    sub sp, sp, #32
+    stp x19, x20, [sp, #0]
+    str x21, [sp, #16]
+    mov x9, x0
+    mov x10, x1
+    mov x11, x2
+    mov x12, x3
+    mov x13, x4
+    mov x14, x5
+    mov x15, x6
+    mov x19, x7
+    ldr x16, [x29, #16]
+    mov x20, x16
+    ldr x16, [x29, #24]
+    mov x21, x16
+    mov x17, #2
+    mul x10, x10, x17
+    add x9, x9, x10
+    mov x17, #3
+    mul x10, x11, x17
+    add x9, x9, x10
+    mov x17, #4
+    mul x10, x12, x17
+    add x9, x9, x10
+    mov x17, #5
+    mul x10, x13, x17
+    add x9, x9, x10
+    mov x17, #6
+    mul x10, x14, x17
+    add x9, x9, x10
+    mov x17, #7
+    mul x10, x15, x17
+    add x9, x9, x10
+    mov x17, #8
+    mul x10, x19, x17
+    add x9, x9, x10
+    mov x17, #9
+    mul x10, x20, x17
+    add x9, x9, x10
+    mov x17, #10
+    mul x10, x21, x17
+    add x0, x9, x10
+.Lret0:
+    ldp x19, x20, [sp, #0]
+    ldr x21, [sp, #16]
+    mov sp, x29
+    ldp x29, x30, [sp], #16
+    ret
+    .size mix, .-mix
+
+    .balign 4
+    .globl pressure
+    .type pressure, %function
+pressure:
+    stp x29, x30, [sp, #-16]!
+    mov x29, sp
+    sub sp, sp, #112
+    stp x19, x20, [sp, #16]
+    stp x21, x22, [sp, #32]
+    stp x23, x24, [sp, #48]
+    stp x25, x26, [sp, #64]
+    stp x27, x28, [sp, #80]
+    mov x9, x0
+    add x19, x9, #1
+    add x20, x9, #2
+    add x21, x9, #3
+    add x22, x9, #4
+    add x23, x9, #5
+    add x24, x9, #6
+    add x25, x9, #7
+    add x26, x9, #8
+    add x27, x9, #9
+    add x28, x9, #10
+    add x16, x9, #11
+    str x16, [sp, #96]
+    add x16, x9, #12
+    str x16, [sp, #104]
+    mov x0, x19
+    mov x1, x20
+    mov x2, x21
+    mov x3, x22
+    mov x4, x23
+    mov x5, x24
+    mov x6, x25
+    mov x7, x26
+    str x27, [sp, #0]
+    str x28, [sp, #8]
+    bl mix
+    mov x9, x0
+    add x10, x19, x20
+    add x10, x10, x21
+    add x10, x10, x22
+    add x10, x10, x23
+    add x10, x10, x24
+    add x10, x10, x25
+    add x10, x10, x26
+    add x10, x10, x27
+    add x10, x10, x28
+    ldr x17, [sp, #96]
+    add x10, x10, x17
+    ldr x17, [sp, #104]
+    add x10, x10, x17
+    add x0, x10, x9
+.Lret1:
+    ldp x19, x20, [sp, #16]
+    ldp x21, x22, [sp, #32]
+    ldp x23, x24, [sp, #48]
+    ldp x25, x26, [sp, #64]
+    ldp x27, x28, [sp, #80]
+    mov sp, x29
+    ldp x29, x30, [sp], #16
+    ret
+    .size pressure, .-pressure
+
+    .balign 4
+    .globl main
+    .type main, %function
+main:
+    stp x29, x30, [sp, #-16]!
+    mov x29, sp
+    mov x0, #1
+    bl pressure
+    mov x9, x0
+    mov x17, #256
+    sdiv x8, x9, x17
+    msub x0, x8, x17, x9
+.Lret2:
+    mov sp, x29
+    ldp x29, x30, [sp], #16
+    ret
+    .size main, .-main
+
+    .globl _start
+    .type _start, %function
+_start:
+    mov x29, #0
+    mov x30, #0
+    ldr x0, [sp]
+    add x1, sp, #8
+    bl main
+    bl exit
+
+    .section .note.GNU-stack,"",%progbits
@@ -0,0 +1,94 @@
+//! `--emit=c-header` against the layout the backends actually produce
+
+mod common;
+
+use common::*;
+
+const LIB: &str = r#"
+#[export]
+pub struct Pair {
+    a: Int32,
+    b: Float,
+    flag: Bool,
+    next: Pair,
+}
+
+#[export]
+pub func make(a: Int32, b: Float) -> Pair {
+    let end = Pair { a: 0, b: 0.0, flag: false, next: 0 }
+    Pair { a: a, b: b, flag: true, next: end }
+}
+
+#[export]
+pub func total(p: Pair) -> Int32 {
+    p.a + p.next.a
+}
+"#;
+
+/// Reads every field through the header and calls back with a modified struct
+const CALLER: &str = r#"
+#include "lib.h"
+
+int main(void) {
+    Pair *p = make(40, 2.5);
+    if (p->a != 40 || p->b != 2.5 || !p->flag) return 1;
+    if (p->next->a != 0 || p->next->flag) return 2;
+    p->next->a = 2;
+    return total(p) == 42 ? 0 : 3;
+}
+"#;
+
+#[test]
+fn struct_fields_are_words_and_structs_are_pointers() {
+    let dir = scratch("header_layout");
+    compile(&dir, "lib.aether", LIB, &["--emit=c-header", "-o", "lib"]).unwrap();
+    let header = std::fs::read_to_string(dir.join("lib.h")).unwrap();
+    assert!(header.contains("struct Pair {\n    int64_t a;\n    double b;\n    uint64_t flag;\n    Pair *next;\n};"), "{}", header);
+    assert!(header.contains("Pair *make(int32_t a, double b);"), "{}", header);
+    assert!(header.contains("int32_t total(Pair *p);"), "{}", header);
+}
+
+#[test]
+fn c_caller_matches_c_backend() {
+    if !has_tool("cc") {
+        return;
+    }
+    let dir = scratch("header_c_backend");
+    compile(&dir, "lib.aether", LIB, &["--emit=c-header", "-o", "lib"]).unwrap();
+    compile(&dir, "lib.aether", LIB, &["--target", "c", "--crate-type", "staticlib", "-o", "lib.c"]).unwrap();
+    std::fs::write(dir.join("main.c"), CALLER).unwrap();
+    cc(&dir, &["main.c", "lib.c", "-o", "main"]).unwrap();
+    assert_eq!(run(&dir.join("main")).0, 0);
+}
+
+#[test]
+fn c_caller_matches_native_backend() {
+    if !native_host() || !has_tool("cc") {
+        return;
+    }
+    let dir = scratch("header_native_backend");
+    compile(&dir, "lib.aether", LIB, &["--emit=c-header", "-o", "lib"]).unwrap();
+    compile(&dir, "lib.aether", LIB, &["--backend", "native", "--crate-type", "staticlib", "-o", "liblib.a"]).unwrap();
+    std::fs::write(dir.join("main.c"), CALLER).unwrap();
+    cc(&dir, &["main.c", "liblib.a", "-o", "main"]).unwrap();
+    assert_eq!(run(&dir.join("main")).0, 0);
+}
+
+#[test]
+fn c_caller_matches_llvm_backend() {
+    if !native_host() || !has_tool("llc") || !has_tool("cc") {
+        return;
+    }
+    let dir = scratch("header_llvm_backend");
+    compile(&dir, "lib.aether", LIB, &["--emit=c-header", "-o", "lib"]).unwrap();
+    compile(&dir, "lib.aether", LIB, &["--crate-type", "obj", "--emit=llvm-ir", "-o", "lib"]).unwrap();
+    let llc = std::process::Command::new("llc")
+        .args(["-mtriple=x86_64-pc-linux-gnu", "-relocation-model=pic", "-filetype=obj", "lib.ll", "-o", "lib.o"])
+        .current_dir(&dir)
+        .status()
+        .unwrap();
+    assert!(llc.success());
+    std::fs::write(dir.join("main.c"), CALLER).unwrap();
+    cc(&dir, &["main.c", "lib.o", "-o", "main"]).unwrap();
+    assert_eq!(run(&dir.join("main")).0, 0);
+}
@@ -0,0 +1,274 @@
+//! `run --interp`: the interpreter runs structs, methods, enums, closures and
+//! `for` loops, and agrees with the compiled program
+
+mod aarch64;
+mod common;
+
+use common::*;
+use std::path::Path;
+
+/// Every feature the backends lower; the exit code is the first failing check
+const FEATURES: &str = r#"
+struct Point { x: Int, y: Int }
+
+impl Point {
+    func new(x: Int, y: Int) -> Self {
+        Point { y: y, x: x }
+    }
+
+    func sum(&self) -> Int {
+        self.x + self.y
+    }
+
+    func shift(&mut self, d: Int) {
+        self.x = self.x + d
+    }
+
+    func scaled(&self, k: Int) -> Self {
+        let p: Self = Point { x: self.x * k, y: self.y * k }
+        p
+    }
+}
+
+enum Shape { Circle(Int), Rect(Int, Int), Empty }
+
+enum Color { Red, Green, Blue }
+
+let mut COUNT: Int = 0
+
+func area(s: Shape) -> Int {
+    match s {
+        Shape::Circle(r) if r > 10 => 1000,
+        Shape::Circle(r) => 3 * r * r,
+        Shape::Rect(w, h) => w * h,
+        Shape::Empty => 0,
+    }
+}
+
+func code(c: Color) -> Int {
+    match c {
+        Color::Red => 1,
+        Color::Green => 2,
+        _ => 3,
+    }
+}
+
+func word(s: String) -> Int {
+    match s {
+        "one" => 1,
+        "two" => 2,
+        _ => 0,
+    }
+}
+
+func twice(f: func(Int) -> Int, x: Int) -> Int {
+    f(f(x))
+}
+
+func inc(x: Int) -> Int {
+    x + 1
+}
+
+func bump(p: &mut Int, d: Int) {
+    *p = *p + d
+}
+
+func main() -> Int {
+    let mut p = Point::new(3, 4)
+    p.shift(10)
+    let q = p.scaled(2)
+    let r = &p
+    let base = 7
+    let add = |v: Int| v + base
+    let get = || r.sum()
+    let mut total = 0
+    for v in [1, 2, 3, 4, 5] {
+        if v == 3 {
+            continue
+        }
+        if v == 5 {
+            break
+        }
+        total = total + v
+    }
+    let mut n = 1
+    bump(&n, 5)
+    let mut xs = [5, 6, 7]
+    bump(&xs[1], 4)
+    bump(&p.y, 1)
+    unsafe { bump(&COUNT, 3) }
+    let f = 1.5
+    let g = f * 2.0
+    let m = Point { x: 1, y: 2 }
+    let got = match m {
+        Point { x: 1, y } => y,
+        _ => 0,
+    }
+    __builtin_print(48 + code(Color::Green))
+    __builtin_print(10)
+    let checks = [
+        p.sum() == 18,
+        q.x == 26 && q.y == 8,
+        get() == 18,
+        area(Shape::Circle(2)) == 12,
+        area(Shape::Circle(11)) == 1000,
+        area(Shape::Rect(3, 5)) == 15,
+        area(Shape::Empty) == 0,
+        code(Color::Blue) == 3,
+        word("two") == 2 && word("six") == 0,
+        add(1) == 8,
+        twice(inc, 5) == 7 && twice(add, 0) == 14,
+        total == 7,
+        n == 6 && xs[1] == 10 && unsafe { COUNT } == 3,
+        f == 1.5 && f > 1.0,
+        g == 3.0 && -f < 0.0 && 7.5 % 2.0 == 1.5 && f + 1 == 2.5 && g / 4.0 < f - 0.5,
+        got == 2,
+    ]
+    let mut failed = 0
+    let mut i = 0
+    for ok in checks {
+        i = i + 1
+        if ok == 0 && failed == 0 {
+            failed = i
+        }
+    }
+    failed
+}
+"#;
+
+/// Exit code and output of `run --interp`, and the error on failure
+fn interpret(dir: &Path, source: &str) -> (i32, String, String) {
+    std::fs::write(dir.join("main.aether"), source).unwrap();
+    let out = aetherc(dir, &["run", "--interp", "main.aether"]);
+    (out.status.code().unwrap_or(-1), String::from_utf8_lossy(&out.stdout).into_owned(),
+     String::from_utf8_lossy(&out.stderr).into_owned())
+}
+
+#[test]
+fn interpreter_runs_every_feature() {
+    let dir = scratch("interp_features");
+    let (code, stdout, stderr) = interpret(&dir, FEATURES);
+    assert_eq!((code, stdout.as_str()), (0, "2\n"), "{}", stderr);
+}
+
+#[test]
+fn interpreter_agrees_with_the_c_backend() {
+    if !has_tool("cc") {
+        return;
+    }
+    let dir = scratch("interp_agrees");
+    let interpreted = interpret(&dir, FEATURES);
+    compile(&dir, "main.aether", FEATURES, &["--target", "c", "-o", "main.c"]).unwrap();
+    cc(&dir, &["main.c", "-o", "main"]).unwrap();
+    assert_eq!(run(&dir.join("main")), (interpreted.0, interpreted.1));
+}
+
+/// Closures returned from functions and nested in one another, and a value no arm matches
+const CLOSURES: &str = r#"
+func adder(n: Int) -> func(Int) -> Int {
+    |x: Int| x + n
+}
+
+func compose(k: Int) -> Int {
+    let outer = |a: Int| {
+        let inner = |b: Int| a * b + k
+        inner(2)
+    }
+    outer(5)
+}
+
+func pick(n: Int) -> Int {
+    match n {
+        1 => 10,
+        2 => 20,
+    }
+}
+
+func main() -> Int {
+    let add3 = adder(3)
+    if add3(4) + compose(1) != 18 {
+        return 1
+    }
+    pick(3)
+}
+"#;
+
+#[test]
+fn native_backends_run_every_feature() {
+    let dir = scratch("interp_native");
+    if native_host() {
+        compile(&dir, "main.aether", FEATURES, &["--backend", "native", "-o", "main"]).unwrap();
+        assert_eq!(run(&dir.join("main")), (0, "2\n".to_string()));
+        compile(&dir, "closures.aether", CLOSURES, &["--backend", "native", "-o", "closures"]).unwrap();
+        assert_eq!(run(&dir.join("closures")).0, 134);
+    }
+    let args = ["--backend", "native", "--target", "aarch64-unknown-linux-gnu", "-o"];
+    compile(&dir, "main.aether", FEATURES, &[&args[..], &["arm"]].concat()).unwrap();
+    assert_eq!(aarch64::run(&dir.join("arm")), Ok((0, "2\n".to_string())));
+    compile(&dir, "closures.aether", CLOSURES, &[&args[..], &["arm_closures"]].concat()).unwrap();
+    assert_eq!(aarch64::run(&dir.join("arm_closures")).map(|r| r.0), Ok(134));
+}
+
+#[test]
+fn interpreter_does_float_arithmetic_on_doubles() {
+    let dir = scratch("interp_floats");
+    let (code, _, stderr) = interpret(&dir, r#"
+func half(x: Float) -> Float {
+    x / 2
+}
+
+func main() -> Int {
+    let g = 1.5 * 2.0
+    let h = -g + 0.5
+    let checks = [
+        g > 2.5 && g < 3.5,
+        g == 3.0,
+        h == -2.5 && h < 0.0,
+        half(5.0) == 2.5,
+        1.0 / 0.0 > 1000000.0,
+        7.5 % 2.0 == 1.5,
+    ]
+    let mut failed = 0
+    let mut i = 0
+    for ok in checks {
+        i = i + 1
+        if ok == 0 && failed == 0 {
+            failed = i
+        }
+    }
+    failed
+}
+"#);
+    assert_eq!(code, 0, "{}", stderr);
+}
+
+#[test]
+fn interpreter_reports_unmatched_values_and_unknown_loops() {
+    let dir = scratch("interp_errors");
+    let (code, _, stderr) = interpret(&dir, r#"
+func pick(n: Int) -> Int {
+    match n {
+        1 => 10,
+        2 => 20,
+    }
+}
+
+func main() -> Int {
+    pick(3)
+}
+"#);
+    assert_ne!(code, 0);
+    assert!(stderr.contains("No match arm matched at line 3"), "{}", stderr);
+
+    let (code, _, stderr) = interpret(&dir, r#"
+func main() -> Int {
+    let n = 4
+    for i in n {
+        __builtin_print(i)
+    }
+    0
+}
+"#);
+    assert_ne!(code, 0);
+    assert!(stderr.contains("for loops need an array of known length or a Vec at line 4"), "{}", stderr);
+}
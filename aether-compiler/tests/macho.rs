@@ -0,0 +1,267 @@
+//! Mach-O objects read back: load commands, symbol table and relocations
+//! for arm64 and x86_64
+
+mod common;
+
+use common::*;
+use std::process::Command;
+
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+const MH_OBJECT: u32 = 1;
+const CPU_TYPE_X86_64: u32 = 0x0100_0007;
+const CPU_TYPE_ARM64: u32 = 0x0100_000c;
+const LC_SYMTAB: u32 = 0x2;
+const LC_DYSYMTAB: u32 = 0xb;
+const LC_SEGMENT_64: u32 = 0x19;
+const LC_BUILD_VERSION: u32 = 0x32;
+const N_EXT: u8 = 0x1;
+const N_SECT: u8 = 0xe;
+
+/// Calls a local and an external function, and addresses a static and a string
+const PROGRAM: &str = r#"
+let mut COUNT: Int = 0
+
+func helper(n: Int) -> Int {
+    unsafe { COUNT = COUNT + n }
+    n * 2
+}
+
+func main() -> Int {
+    let s = "hello\n"
+    unsafe { __builtin_write(1, s, 6) }
+    return helper(21)
+}
+"#;
+
+struct Section {
+    segment: String,
+    name: String,
+    addr: u64,
+    size: u64,
+    offset: u32,
+    relocs: Vec<Reloc>,
+}
+
+struct Reloc {
+    address: u32,
+    symbol: u32,
+    pcrel: bool,
+    length: u32,
+    external: bool,
+    kind: u8,
+}
+
+struct Symbol {
+    name: String,
+    kind: u8,
+    section: u8,
+    value: u64,
+}
+
+struct MachO {
+    cputype: u32,
+    filetype: u32,
+    commands: Vec<u32>,
+    sections: Vec<Section>,
+    symbols: Vec<Symbol>,
+    /// ilocalsym, nlocalsym, iextdefsym, nextdefsym, iundefsym, nundefsym
+    dysymtab: [u32; 6],
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+fn name_at(data: &[u8], at: usize, len: usize) -> String {
+    let bytes = &data[at..at + len];
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(len);
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Parse a 64-bit Mach-O object, checking every range against the file
+fn parse(data: &[u8]) -> MachO {
+    assert_eq!(u32_at(data, 0), MH_MAGIC_64, "bad magic");
+    let ncmds = u32_at(data, 16) as usize;
+    let sizeofcmds = u32_at(data, 20) as usize;
+    let mut macho = MachO {
+        cputype: u32_at(data, 4),
+        filetype: u32_at(data, 12),
+        commands: Vec::new(),
+        sections: Vec::new(),
+        symbols: Vec::new(),
+        dysymtab: [0; 6],
+    };
+    let mut at = 32;
+    for _ in 0..ncmds {
+        let (cmd, size) = (u32_at(data, at), u32_at(data, at + 4) as usize);
+        assert!(size >= 8 && size % 8 == 0, "load command {:#x} has size {}", cmd, size);
+        macho.commands.push(cmd);
+        match cmd {
+            LC_SEGMENT_64 => {
+                let nsects = u32_at(data, at + 64) as usize;
+                assert_eq!(size, 72 + 80 * nsects, "segment command size");
+                for s in 0..nsects {
+                    let h = at + 72 + 80 * s;
+                    let (reloff, nreloc) = (u32_at(data, h + 56) as usize, u32_at(data, h + 60) as usize);
+                    let relocs = (0..nreloc).map(|r| {
+                        let e = reloff + 8 * r;
+                        let info = u32_at(data, e + 4);
+                        Reloc {
+                            address: u32_at(data, e),
+                            symbol: info & 0xff_ffff,
+                            pcrel: info >> 24 & 1 == 1,
+                            length: info >> 25 & 3,
+                            external: info >> 27 & 1 == 1,
+                            kind: (info >> 28) as u8,
+                        }
+                    }).collect();
+                    let section = Section {
+                        name: name_at(data, h, 16),
+                        segment: name_at(data, h + 16, 16),
+                        addr: u64_at(data, h + 32),
+                        size: u64_at(data, h + 40),
+                        offset: u32_at(data, h + 48),
+                        relocs,
+                    };
+                    assert!(section.offset as u64 + section.size <= data.len() as u64, "section {} past the end", section.name);
+                    macho.sections.push(section);
+                }
+            }
+            LC_SYMTAB => {
+                let (symoff, nsyms) = (u32_at(data, at + 8) as usize, u32_at(data, at + 12) as usize);
+                let (stroff, strsize) = (u32_at(data, at + 16) as usize, u32_at(data, at + 20) as usize);
+                assert!(symoff + 16 * nsyms <= data.len() && stroff + strsize <= data.len(), "symtab past the end");
+                for i in 0..nsyms {
+                    let e = symoff + 16 * i;
+                    let strx = u32_at(data, e) as usize;
+                    assert!(strx < strsize, "symbol {} name outside the string table", i);
+                    macho.symbols.push(Symbol {
+                        name: name_at(data, stroff + strx, strsize - strx),
+                        kind: data[e + 4],
+                        section: data[e + 5],
+                        value: u64_at(data, e + 8),
+                    });
+                }
+            }
+            LC_DYSYMTAB => {
+                for (i, field) in macho.dysymtab.iter_mut().enumerate() {
+                    *field = u32_at(data, at + 8 + 4 * i);
+                }
+            }
+            _ => {}
+        }
+        at += size;
+    }
+    assert_eq!(at - 32, sizeofcmds, "sizeofcmds does not match the load commands");
+    macho
+}
+
+/// Build the program as a Mach-O object for `target` and read it back
+fn object(target: &str) -> (MachO, Vec<u8>) {
+    let dir = scratch(&format!("macho_{}", target));
+    let args = ["--backend", "native", "--target", target, "--crate-type", "obj", "-o", "main.o"];
+    if let Err(out) = compile(&dir, "main.aether", PROGRAM, &args) {
+        panic!("{}", out);
+    }
+    let path = dir.join("main.o");
+    if has_tool("llvm-objdump") {
+        let out = Command::new("llvm-objdump").args(["--macho", "--private-headers", "-r", "-t"]).arg(&path).output().unwrap();
+        assert!(out.status.success(), "llvm-objdump rejects the object: {}", String::from_utf8_lossy(&out.stderr));
+    }
+    let data = std::fs::read(path).unwrap();
+    (parse(&data), data)
+}
+
+/// Checks shared by both architectures; returns each relocation's kind and
+/// target name in file order
+fn check_common(macho: &MachO) -> Vec<(u8, String)> {
+    assert_eq!(macho.filetype, MH_OBJECT);
+    assert_eq!(macho.commands, [LC_SEGMENT_64, LC_BUILD_VERSION, LC_SYMTAB, LC_DYSYMTAB]);
+    let names: Vec<(&str, &str)> = macho.sections.iter().map(|s| (s.segment.as_str(), s.name.as_str())).collect();
+    assert_eq!(names, [("__TEXT", "__text"), ("__TEXT", "__const"), ("__DATA", "__data")]);
+
+    // Symbols: locals, then external definitions, then undefined, as LC_DYSYMTAB says
+    let [ilocal, nlocal, iextdef, nextdef, iundef, nundef] = macho.dysymtab;
+    assert_eq!((ilocal, iextdef, iundef), (0, nlocal, nlocal + nextdef));
+    assert_eq!((iundef + nundef) as usize, macho.symbols.len());
+    let find = |name: &str| macho.symbols.iter().position(|s| s.name == name).unwrap_or_else(|| panic!("no symbol {}", name)) as u32;
+    for (name, section, kind) in [("_helper", 1, N_SECT), ("_COUNT", 3, N_SECT), ("_main", 1, N_SECT | N_EXT), ("_write", 0, N_EXT)] {
+        let i = find(name);
+        let sym = &macho.symbols[i as usize];
+        assert_eq!((sym.section, sym.kind), (section, kind), "{}", name);
+        let (first, count) = if kind == N_EXT { (iundef, nundef) } else if kind & N_EXT != 0 { (iextdef, nextdef) } else { (ilocal, nlocal) };
+        assert!((first..first + count).contains(&i), "{} is out of its LC_DYSYMTAB group", name);
+        if section > 0 {
+            let s = &macho.sections[section as usize - 1];
+            assert!((s.addr..s.addr + s.size).contains(&sym.value), "{} lies outside {}", name, s.name);
+        }
+    }
+
+    // Only code is relocated, and every entry names a symbol in range
+    assert!(macho.sections[1..].iter().all(|s| s.relocs.is_empty()));
+    let text = &macho.sections[0];
+    text.relocs.iter().map(|r| {
+        assert!((r.address as u64) + 4 <= text.size, "relocation at {:#x} past __text", r.address);
+        assert!(r.external && r.length == 2, "relocation at {:#x} is not a 4-byte external one", r.address);
+        (r.kind, macho.symbols[r.symbol as usize].name.clone())
+    }).collect()
+}
+
+#[test]
+fn arm64_object_round_trips() {
+    let (macho, data) = object("aarch64-apple-darwin");
+    assert_eq!(macho.cputype, CPU_TYPE_ARM64);
+    let relocs = check_common(&macho);
+    const BRANCH26: u8 = 2;
+    const PAGE21: u8 = 3;
+    const PAGEOFF12: u8 = 4;
+    let expected = [
+        (BRANCH26, "_helper"), (BRANCH26, "_write"),
+        (PAGEOFF12, "ltmp1"), (PAGE21, "ltmp1"),
+        (PAGEOFF12, "_COUNT"), (PAGE21, "_COUNT"),
+        (PAGEOFF12, "_COUNT"), (PAGE21, "_COUNT"),
+    ];
+    let relocs: Vec<(u8, &str)> = relocs.iter().map(|(k, n)| (*k, n.as_str())).collect();
+    assert_eq!(relocs, expected);
+
+    // Each relocation sits on the instruction it patches
+    let text = &macho.sections[0];
+    for r in &text.relocs {
+        assert_eq!(r.pcrel, r.kind != PAGEOFF12, "pcrel flag at {:#x}", r.address);
+        let word = u32_at(&data, (text.offset + r.address) as usize);
+        let fits = match r.kind {
+            BRANCH26 => word & 0xfc00_0000 == 0x9400_0000,
+            PAGE21 => word & 0x9f00_0000 == 0x9000_0000,
+            // add or ldr/str with an unsigned 12-bit offset
+            _ => word & 0x7f80_0000 == 0x1100_0000 || word & 0x3b00_0000 == 0x3900_0000,
+        };
+        assert!(fits, "relocation {} at {:#x} is on instruction {:#010x}", r.kind, r.address, word);
+    }
+}
+
+#[test]
+fn x86_64_object_round_trips() {
+    let (macho, data) = object("x86_64-apple-darwin");
+    assert_eq!(macho.cputype, CPU_TYPE_X86_64);
+    let relocs = check_common(&macho);
+    const SIGNED: u8 = 1;
+    const BRANCH: u8 = 2;
+    let relocs: Vec<(u8, &str)> = relocs.iter().map(|(k, n)| (*k, n.as_str())).collect();
+    assert_eq!(relocs, [(BRANCH, "_helper"), (BRANCH, "_write"), (SIGNED, "ltmp1"), (SIGNED, "_COUNT"), (SIGNED, "_COUNT")]);
+
+    let text = &macho.sections[0];
+    for r in &text.relocs {
+        assert!(r.pcrel, "relocation at {:#x} is not pc-relative", r.address);
+        let at = (text.offset + r.address) as usize;
+        match r.kind {
+            // call rel32
+            BRANCH => assert_eq!(data[at - 1], 0xe8, "BRANCH at {:#x} is not on a call", r.address),
+            // RIP-relative ModRM: mod 00, r/m 101
+            _ => assert_eq!(data[at - 1] & 0xc7, 0x05, "SIGNED at {:#x} is not RIP-relative", r.address),
+        }
+    }
+}
@@ -0,0 +1,340 @@
+//! PE/COFF output read back, and the Microsoft x64 calling convention run
+//! on the host against a stand-in for kernel32
+
+mod common;
+
+use common::*;
+use std::process::Command;
+
+const IMAGE_FILE_MACHINE_AMD64: u16 = 0x8664;
+const PE32_PLUS: u16 = 0x20b;
+const IMAGE_SUBSYSTEM_WINDOWS_CUI: u16 = 3;
+const IMAGE_DIRECTORY_ENTRY_IMPORT: usize = 1;
+const IMAGE_REL_AMD64_REL32: u16 = 4;
+const IMAGE_SYM_CLASS_EXTERNAL: u8 = 2;
+const IMAGE_SYM_CLASS_STATIC: u8 = 3;
+
+/// Prints its arguments one per line in brackets, then checks calls with
+/// more arguments than registers, into Aether and out to C
+const PROGRAM: &str = r#"
+extern "C" {
+    func mix(a: Int, b: Int, c: Int, d: Int, e: Int, f: Int) -> Int
+}
+
+func sum6(a: Int, b: Int, c: Int, d: Int, e: Int, f: Int) -> Int {
+    return a + 2 * b + 3 * c + 4 * d + 5 * e + 6 * f
+}
+
+func show(arg: Int) -> Int {
+    let mut len = 0
+    while unsafe { __builtin_load8(arg + len) } != 0 {
+        len = len + 1
+    }
+    unsafe {
+        __builtin_write(1, "[", 1)
+        __builtin_write(1, arg, len)
+        __builtin_write(1, "]\n", 2)
+    }
+    return len
+}
+
+func main(argc: Int, argv: Int) -> Int {
+    let mut i = 0
+    while i < argc {
+        show(unsafe { __builtin_load64(argv + 8 * i) })
+        i = i + 1
+    }
+    if unsafe { __builtin_load64(argv + 8 * argc) } != 0 {
+        return 1
+    }
+    if sum6(1, 2, 3, 4, 5, 6) != 91 {
+        return 2
+    }
+    if unsafe { mix(1, 2, 3, 4, 5, 6) } != 123456 {
+        return 3
+    }
+    return 0
+}
+"#;
+
+/// Executables link only the runtime, so this one calls nothing else
+const ARGC: &str = r#"
+func main(argc: Int, argv: Int) -> Int {
+    return argc
+}
+"#;
+
+/// kernel32 as far as the runtime uses it, with `ms_abi` entry points; the
+/// command line is the process's first argument
+const KERNEL32: &str = r#"
+#include <stdint.h>
+#include <stdlib.h>
+#include <unistd.h>
+#define W __attribute__((ms_abi))
+static uint16_t line[4096];
+W static void *GetProcessHeap(void) { return (void *)1; }
+W static void *HeapAlloc(void *heap, uint32_t flags, size_t n) { return malloc(n); }
+W static int HeapFree(void *heap, uint32_t flags, void *p) { free(p); return 1; }
+W static void ExitProcess(uint32_t code) { _exit(code); }
+W static uint16_t *GetCommandLineW(void) { return line; }
+W static int WideCharToMultiByte(uint32_t page, uint32_t flags, const uint16_t *w, int n, char *out, int size, void *d, void *u) {
+    int len = 0;
+    while (w[len]) len++;
+    if (size == 0) return len + 1;
+    if (page != 65001 || n != -1 || size < len + 1) abort();
+    for (int i = 0; i <= len; i++) out[i] = (char)w[i];
+    return len + 1;
+}
+W static void *GetStdHandle(uint32_t n) { return (void *)(intptr_t)(int32_t)n; }
+W static int WriteFile(void *handle, const void *buf, uint32_t n, uint32_t *done, void *o) {
+    if ((intptr_t)handle != -11) abort();
+    *done = write(1, buf, n);
+    return 1;
+}
+W static int Unused(void) { abort(); }
+W int64_t mix(int64_t a, int64_t b, int64_t c, int64_t d, int64_t e, int64_t f) {
+    return a * 100000 + b * 10000 + c * 1000 + d * 100 + e * 10 + f;
+}
+void *__imp_GetProcessHeap = GetProcessHeap, *__imp_HeapAlloc = HeapAlloc, *__imp_HeapFree = HeapFree,
+     *__imp_ExitProcess = ExitProcess, *__imp_GetCommandLineW = GetCommandLineW,
+     *__imp_WideCharToMultiByte = WideCharToMultiByte, *__imp_GetStdHandle = GetStdHandle,
+     *__imp_WriteFile = WriteFile, *__imp_ReadFile = Unused, *__imp_CreateThread = Unused,
+     *__imp_CloseHandle = Unused, *__imp_ExitThread = Unused, *__imp_CreateFileA = Unused,
+     *__imp_SetFilePointerEx = Unused, *__imp_DeleteFileA = Unused, *__imp_RemoveDirectoryA = Unused,
+     *__imp_CreateDirectoryA = Unused, *__imp_MoveFileExA = Unused;
+W void __aether_start(void);
+int main(int argc, char **argv) {
+    for (int i = 0; argv[1][i]; i++) line[i] = (unsigned char)argv[1][i];
+    __aether_start();
+}
+"#;
+
+const RUNTIME: &str = include_str!("../src/binary/runtime/x86_64-windows.s");
+
+fn u16_at(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(data[at..at + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn c_string(data: &[u8], at: usize) -> String {
+    let end = data[at..].iter().position(|&b| b == 0).expect("unterminated name");
+    String::from_utf8_lossy(&data[at..at + end]).into_owned()
+}
+
+/// An 8-byte name field, NUL-padded
+fn short_name(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(8);
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+struct Section {
+    name: String,
+    rva: u32,
+    size: u32,
+    raw: u32,
+    relocs: Vec<(u32, u32, u16)>,
+}
+
+/// Section table of a COFF file header at `at`, with each section's
+/// relocations as (offset, symbol index, type)
+fn sections(data: &[u8], at: usize) -> Vec<Section> {
+    let count = u16_at(data, at + 2) as usize;
+    let table = at + 20 + u16_at(data, at + 16) as usize;
+    (0..count).map(|i| {
+        let h = table + 40 * i;
+        let (relocs_at, nrelocs) = (u32_at(data, h + 24) as usize, u16_at(data, h + 32) as usize);
+        let section = Section {
+            name: short_name(&data[h..h + 8]),
+            rva: u32_at(data, h + 12),
+            size: u32_at(data, h + 8).max(u32_at(data, h + 16)),
+            raw: u32_at(data, h + 20),
+            relocs: (0..nrelocs).map(|r| {
+                let e = relocs_at + 10 * r;
+                (u32_at(data, e), u32_at(data, e + 4), u16_at(data, e + 8))
+            }).collect(),
+        };
+        assert!((section.raw as usize) + (u32_at(data, h + 16) as usize) <= data.len(), "section {} past the end", section.name);
+        section
+    }).collect()
+}
+
+/// Build `source` with `args` for Windows and return the output file
+fn build(name: &str, source: &str, args: &[&str]) -> (std::path::PathBuf, Vec<u8>) {
+    let dir = scratch(name);
+    let mut all = vec!["--backend", "native", "--target", "x86_64-pc-windows-msvc"];
+    all.extend_from_slice(args);
+    if let Err(out) = compile(&dir, "main.aether", source, &all) {
+        panic!("{}", out);
+    }
+    let path = dir.join(all.last().unwrap());
+    let data = std::fs::read(&path).unwrap();
+    (dir, data)
+}
+
+#[test]
+fn executable_round_trips() {
+    let (dir, data) = build("pe_exe", ARGC, &["-o", "main.exe"]);
+    assert_eq!(&data[..2], b"MZ");
+    let pe = u32_at(&data, 0x3c) as usize;
+    assert_eq!(&data[pe..pe + 4], b"PE\0\0");
+    let coff = pe + 4;
+    assert_eq!(u16_at(&data, coff), IMAGE_FILE_MACHINE_AMD64);
+    let optional = coff + 20;
+    assert_eq!(u16_at(&data, optional), PE32_PLUS);
+    assert_eq!(u16_at(&data, optional + 68), IMAGE_SUBSYSTEM_WINDOWS_CUI);
+
+    // The entry point lies in the code section
+    let sections = sections(&data, coff);
+    let text = sections.iter().find(|s| s.name == ".text").expect("no .text");
+    let entry = u32_at(&data, optional + 16);
+    assert!((text.rva..text.rva + text.size).contains(&entry), "entry {:#x} outside .text", entry);
+
+    // One import descriptor, for KERNEL32.dll, naming what the runtime calls
+    let file_of = |rva: u32| -> usize {
+        let s = sections.iter().find(|s| (s.rva..s.rva + s.size).contains(&rva)).unwrap_or_else(|| panic!("rva {:#x} is in no section", rva));
+        (rva - s.rva + s.raw) as usize
+    };
+    let import = file_of(u32_at(&data, optional + 112 + 8 * IMAGE_DIRECTORY_ENTRY_IMPORT));
+    assert_eq!(c_string(&data, file_of(u32_at(&data, import + 12))), "KERNEL32.dll");
+    assert_eq!(u32_at(&data, import + 20 + 12), 0, "import directory is not terminated");
+    let mut lookup = file_of(u32_at(&data, import));
+    let mut names = Vec::new();
+    while u32_at(&data, lookup) != 0 {
+        names.push(c_string(&data, file_of(u32_at(&data, lookup)) + 2));
+        lookup += 8;
+    }
+    for name in ["GetCommandLineW", "WideCharToMultiByte", "GetProcessHeap", "HeapAlloc", "WriteFile", "ExitProcess"] {
+        assert!(names.iter().any(|n| n == name), "{} is not imported: {:?}", name, names);
+    }
+
+    if has_tool("llvm-readobj") {
+        let out = Command::new("llvm-readobj").args(["--file-headers", "--coff-imports"]).arg(dir.join("main.exe")).output().unwrap();
+        assert!(out.status.success(), "llvm-readobj rejects the executable: {}", String::from_utf8_lossy(&out.stderr));
+        assert!(String::from_utf8_lossy(&out.stdout).contains("GetCommandLineW"));
+    }
+}
+
+#[test]
+fn object_round_trips() {
+    let (_, data) = build("pe_obj", PROGRAM, &["--crate-type", "obj", "-o", "main.obj"]);
+    assert_eq!(u16_at(&data, 0), IMAGE_FILE_MACHINE_AMD64);
+    let (symbols_at, nsymbols) = (u32_at(&data, 8) as usize, u32_at(&data, 12) as usize);
+    let strtab = symbols_at + 18 * nsymbols;
+    let symbol = |i: usize| -> (String, i16, u8) {
+        let e = symbols_at + 18 * i;
+        let name = if u32_at(&data, e) == 0 {
+            c_string(&data, strtab + u32_at(&data, e + 4) as usize)
+        } else {
+            short_name(&data[e..e + 8])
+        };
+        (name, u16_at(&data, e + 12) as i16, data[e + 16])
+    };
+
+    let sections = sections(&data, 0);
+    let names: Vec<&str> = sections.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, [".text", ".rdata"]);
+    let all: Vec<(String, i16, u8)> = (0..nsymbols).map(symbol).collect();
+    for (name, section, class) in [
+        ("sum6", 1, IMAGE_SYM_CLASS_STATIC),
+        ("main", 1, IMAGE_SYM_CLASS_EXTERNAL),
+        ("mix", 0, IMAGE_SYM_CLASS_EXTERNAL),
+        ("write", 0, IMAGE_SYM_CLASS_EXTERNAL),
+    ] {
+        let found = all.iter().find(|s| s.0 == name).unwrap_or_else(|| panic!("no symbol {}", name));
+        assert_eq!((found.1, found.2), (section, class), "{}", name);
+    }
+
+    // Calls and the string addresses are REL32 on the instruction they patch
+    let text = &sections[0];
+    let mut targets = Vec::new();
+    for &(offset, index, kind) in &text.relocs {
+        assert_eq!(kind, IMAGE_REL_AMD64_REL32);
+        let at = (text.raw + offset) as usize;
+        let name = symbol(index as usize).0;
+        if name == ".rdata" {
+            assert_eq!(data[at - 1] & 0xc7, 0x05, "{} at {:#x} is not RIP-relative", name, offset);
+        } else {
+            assert_eq!(data[at - 1], 0xe8, "{} at {:#x} is not on a call", name, offset);
+        }
+        targets.push(name);
+    }
+    for name in ["show", "sum6", "mix", "write", ".rdata"] {
+        assert!(targets.iter().any(|t| t == name), "no relocation against {}: {:?}", name, targets);
+    }
+}
+
+#[test]
+fn calls_reserve_shadow_space() {
+    let dir = scratch("pe_asm");
+    let args = ["--backend", "native", "--target", "x86_64-pc-windows-msvc", "--emit=asm", "-o", "main"];
+    if let Err(out) = compile(&dir, "main.aether", PROGRAM, &args) {
+        panic!("{}", out);
+    }
+    let asm = std::fs::read_to_string(dir.join("main.s")).unwrap();
+    let lines: Vec<&str> = asm.lines().map(str::trim).collect();
+    // Functions that call reserve the shadow space and the stack arguments
+    // at the bottom of their frame, below the pushed callee-saved registers
+    let main = &lines[lines.iter().position(|l| *l == "main:").unwrap()..];
+    let main = &main[..main.iter().position(|l| *l == "ret").unwrap()];
+    let frame: i64 = main.iter().find_map(|l| l.strip_prefix("sub rsp, ")).expect("main has no frame").parse().unwrap();
+    let pushed = main.iter().take_while(|l| !l.starts_with("sub rsp")).filter(|l| l.starts_with("push ") && **l != "push rbp").count() as i64;
+    assert!(frame >= 32 + 16, "frame of {} bytes has no room for the shadow space and two stack arguments", frame);
+    assert_eq!((8 * pushed + frame) % 16, 0, "rsp is not 16-byte aligned at calls");
+    for call in ["call sum6", "call mix@PLT"] {
+        let at = main.iter().position(|l| *l == call).unwrap_or_else(|| panic!("no `{}`", call));
+        for slot in ["[rsp + 32]", "[rsp + 40]"] {
+            assert!(main[..at].iter().rev().take(8).any(|l| l.contains(slot)), "fifth and sixth arguments of `{}` are not above the shadow space", call);
+        }
+    }
+    // Parameters arrive in rcx, rdx, r8 and r9, then on the stack above the shadow space
+    let sum6 = &lines[lines.iter().position(|l| *l == "sum6:").unwrap()..];
+    let sum6 = &sum6[..sum6.iter().position(|l| *l == "ret").unwrap()];
+    for reg in ["rcx", "rdx", "r8", "r9"] {
+        assert!(sum6.iter().any(|l| l.starts_with("mov ") && l.ends_with(&format!(", {}", reg))), "no parameter is read from {}", reg);
+    }
+    for at in ["qword ptr [rbp + 48]", "qword ptr [rbp + 56]"] {
+        assert!(sum6.iter().any(|l| l.ends_with(at)), "no parameter is read from {}", at);
+    }
+    assert!(!lines.contains(&"_start:"), "Windows executables start in the runtime");
+}
+
+/// The generated code and the runtime, assembled for the host and linked
+/// against `KERNEL32`: arguments, the command line and calls all follow the
+/// Microsoft convention end to end
+#[test]
+fn programs_run_with_the_microsoft_convention() {
+    if !native_host() || !has_tool("objcopy") {
+        return;
+    }
+    let dir = scratch("pe_run");
+    let args = ["--backend", "native", "--target", "x86_64-pc-windows-msvc", "--emit=asm", "-o", "main"];
+    if let Err(out) = compile(&dir, "main.aether", PROGRAM, &args) {
+        panic!("{}", out);
+    }
+    std::fs::write(dir.join("runtime.s"), RUNTIME).unwrap();
+    std::fs::write(dir.join("kernel32.c"), KERNEL32).unwrap();
+    cc(&dir, &["-c", "main.s", "runtime.s"]).unwrap();
+
+    // The runtime's C library functions and main must not meet the host's
+    let mut rename = vec!["--redefine-sym".to_string(), "main=aether_main".to_string()];
+    for line in RUNTIME.lines() {
+        if let Some(name) = line.trim().strip_prefix(".globl ").filter(|n| *n != "__aether_start") {
+            rename.push("--redefine-sym".to_string());
+            rename.push(format!("{}=aether_{}", name, name));
+        }
+    }
+    for object in ["main.o", "runtime.o"] {
+        let out = Command::new("objcopy").args(&rename).arg(object).current_dir(&dir).output().unwrap();
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    }
+    cc(&dir, &["-o", "main", "kernel32.c", "main.o", "runtime.o", "-z", "noexecstack"]).unwrap();
+
+    let line = r#"prog a   "b c" d\"e "x""y" f\\\\"g h" i\\j k\\\\\"l"#;
+    let out = Command::new(dir.join("main")).arg(line).output().unwrap();
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert_eq!(out.status.code(), Some(0), "{}", stdout);
+    assert_eq!(stdout, "[prog]\n[a]\n[b c]\n[d\"e]\n[x\"y]\n[f\\\\g h]\n[i\\\\j]\n[k\\\\\"l]\n");
+}
@@ -0,0 +1,96 @@
+//! `aetherc repl`: inputs read from stdin share one session, multi-line
+//! input waits for closing brackets, and `:` commands inspect the session
+
+mod common;
+
+use common::*;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Feed `input` to the REPL in `dir`; returns what it printed for each input
+fn repl(dir: &Path, input: &str) -> Vec<String> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_aether-compiler"))
+        .arg("repl")
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("cannot run aetherc repl");
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let text = String::from_utf8_lossy(&out.stdout).replace("   ...> ", "");
+    // The banner comes first; every later chunk follows a prompt
+    text.split("aether> ").skip(1).map(|s| s.trim_end().to_string()).filter(|s| !s.is_empty()).collect()
+}
+
+#[test]
+fn state_persists_across_inputs() {
+    let dir = scratch("repl_state");
+    let out = repl(&dir, r#"let mut n = 4
+func add(a: Int,
+         b: Int) -> Int {
+    a + b
+}
+add(n, 1)
+n = n + 10
+n
+n == 14
+let s = "hi"
+s
+"#);
+    assert_eq!(out, ["add: func(Int, Int) -> Int", "5: Int", "14: Int", "true: Bool", "\"hi\": String"]);
+}
+
+#[test]
+fn errors_leave_the_session_unchanged() {
+    let dir = scratch("repl_errors");
+    let out = repl(&dir, r#"struct P { x: Int }
+let n = 3
+const C: Int = 1 / 0
+C
+let p = P { y: n }
+p
+let = 3
+n
+"#);
+    assert_eq!(out, [
+        "error: Division by zero in constant expression 1 / 0 at line 1",
+        "error: Undefined variable C at line 1",
+        "error: Type errors:\nUnknown field y in P literal at line 1",
+        "error: Undefined variable p at line 1",
+        "error: Expected Ident, got Eq at line 1",
+        "3: Int",
+    ]);
+}
+
+#[test]
+fn commands_inspect_the_session() {
+    let dir = scratch("repl_commands");
+    std::fs::write(dir.join("defs.aether"), "func sq(x: Int) -> Int {\n    x * x\n}\n").unwrap();
+    let out = repl(&dir, r#"func add(a: Int, b: Int) -> Int { a + b }
+:type add(1, 2)
+:t 1 > 0
+:ast 1 + x
+:ir add
+:load defs.aether
+sq(add(1, 2))
+:type
+:bogus
+:help
+:quit
+add(1, 1)
+"#);
+    assert_eq!(out[1..4], ["Int", "Bool", "Binary\n  \"Add\"\n  Int [1, @1:2]\n  Ident [\"x\", @1:6]\n  @1:4"]);
+    assert!(out[4].starts_with("define i64 @add(i64 %a, i64 %b) {") && out[4].ends_with('}'), "{}", out[4]);
+    assert_eq!(out[5..9], [
+        "Loaded 1 declarations from defs.aether",
+        "9: Int",
+        "error: Usage: :type <expr>",
+        "error: Unknown command :bogus (try :help)",
+    ]);
+    assert!(out[9].starts_with("Enter declarations") && out[9].contains(":load <file>"), "{}", out[9]);
+    // Nothing after :quit runs
+    assert_eq!(out.len(), 10, "{:?}", out);
+}
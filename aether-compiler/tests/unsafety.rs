@@ -0,0 +1,149 @@
+//! Unsafe code: raw memory, raw pointers, foreign calls, unsafe functions
+//! and mutable statics need an unsafe context, and `--forbid-unsafe`
+//! rejects every unsafe block, function and impl
+
+mod common;
+
+use common::*;
+
+/// One of each operation that needs an unsafe context
+const OPERATIONS: &str = r#"
+extern "C" {
+    func abs(x: Int) -> Int
+}
+let mut M: Int = 0
+struct Buf { p: Int }
+impl Buf {
+    unsafe func peek(&self) -> Int { __builtin_load8(self.p) }
+}
+unsafe func raw(p: Int) -> Int {
+    __builtin_load8(p)
+}
+func first(p: *Int) -> Int {
+    *p
+}
+func main() -> Int {
+    let b = Buf { p: __builtin_malloc(8) }
+    __builtin_store8(b.p, 1)
+    let f = __builtin_ffi_call0(0)
+    let a = abs(0 - 3)
+    let r = raw(b.p)
+    let k = b.peek()
+    M = 1
+    a + r + k + f
+}
+"#;
+
+#[test]
+fn unsafe_operations_need_an_unsafe_block() {
+    let dir = scratch("unsafety_operations");
+    let out = reject(&dir, OPERATIONS);
+    for msg in [
+        "Dereference of raw pointer p requires an unsafe block at line 14",
+        "Raw memory access with __builtin_store8 requires an unsafe block at line 18",
+        "Foreign call with __builtin_ffi_call0 requires an unsafe block at line 19",
+        "Call to foreign function abs requires an unsafe block at line 20",
+        "Call to unsafe function raw requires an unsafe block at line 21",
+        "Call to unsafe method peek requires an unsafe block at line 22",
+        "Use of mutable static M requires an unsafe block at line 23 (or give it an atomic type such as AtomicInt)",
+    ] {
+        assert!(out.contains(msg), "missing {:?} in\n{}", msg, out);
+    }
+    // Bodies of unsafe functions and methods are already unsafe contexts
+    assert!(!out.contains("line 8") && !out.contains("line 11"), "{}", out);
+}
+
+#[test]
+fn mutable_statics_are_reported_once_per_statement() {
+    let dir = scratch("unsafety_statics");
+    let out = reject(&dir, r#"
+let mut M: Int = 0
+func main() -> Int {
+    M = M + 1
+    let n = M * M
+    n
+}
+"#);
+    for line in [4, 5] {
+        let message = format!("Use of mutable static M requires an unsafe block at line {}", line);
+        assert_eq!(out.matches(&message).count(), 1, "{}", out);
+    }
+}
+
+#[test]
+fn unsafe_blocks_cover_only_what_they_enclose() {
+    let dir = scratch("unsafety_blocks");
+    accept(&dir, r#"
+let mut M: Int = 0
+func first(p: *Int) -> Int {
+    unsafe { *p }
+}
+func main() -> Int {
+    let p = __builtin_malloc(8)
+    unsafe {
+        __builtin_store8(p, 1)
+        M = 1
+    }
+    unsafe { __builtin_load8(p) + M }
+}
+"#);
+    let out = reject(&dir, r#"
+func main() -> Int {
+    let p = __builtin_malloc(8)
+    unsafe { __builtin_store8(p, 1) }
+    __builtin_load8(p)
+}
+"#);
+    assert!(out.contains("Raw memory access with __builtin_load8 requires an unsafe block at line 5"), "{}", out);
+    assert!(!out.contains("__builtin_store8"), "{}", out);
+}
+
+#[test]
+fn only_marker_traits_take_unsafe_impl() {
+    let dir = scratch("unsafety_impls");
+    let out = reject(&dir, r#"
+struct Handle { p: *Int }
+unsafe impl Send for Handle {}
+impl Sync for Handle {}
+trait Show {
+    func show(&self) -> Int { 0 }
+}
+unsafe impl Show for Handle {
+    func show(&self) -> Int { 1 }
+}
+func main() -> Int { 0 }
+"#);
+    assert!(out.contains("Implementing Sync for Handle requires `unsafe impl` at line 4"), "{}", out);
+    assert!(out.contains("`unsafe impl` is only allowed for the marker traits Send and Sync at line 8"), "{}", out);
+    assert!(!out.contains("line 3"), "{}", out);
+}
+
+#[test]
+fn forbid_unsafe_rejects_all_unsafe_code() {
+    let dir = scratch("unsafety_forbid");
+    let source = r#"
+struct Handle { p: *Int }
+unsafe impl Send for Handle {}
+unsafe func raw(p: Int) -> Int {
+    __builtin_load8(p)
+}
+func main() -> Int {
+    let p = __builtin_malloc(8)
+    unsafe { __builtin_store8(p, 1) }
+    0
+}
+"#;
+    accept(&dir, source);
+    let out = compile(&dir, "main.aether", source, &["--emit=typed-ast", "--forbid-unsafe"]).unwrap_err();
+    for msg in [
+        "Unsafe impl for Handle is forbidden by --forbid-unsafe at line 3",
+        "Unsafe function raw is forbidden by --forbid-unsafe at line 4",
+        "Unsafe block is forbidden by --forbid-unsafe at line 9",
+    ] {
+        assert!(out.contains(msg), "missing {:?} in\n{}", msg, out);
+    }
+
+    // Safe code is unaffected
+    let safe = "func main() -> Int {\n    let x = 2\n    x * 21\n}\n";
+    compile(&dir, "safe.aether", safe, &["--emit=typed-ast", "--forbid-unsafe"]).unwrap();
+}
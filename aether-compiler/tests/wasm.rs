@@ -0,0 +1,195 @@
+//! WebAssembly modules export only the public interface: `pub` functions,
+//! `#[export]` wrappers, memory and the entry point, and WASI commands run
+//! under node
+
+mod common;
+
+use common::*;
+use std::path::Path;
+
+const EXPORT_SECTION: u8 = 7;
+const EXPORT_FUNC: u8 = 0;
+const EXPORT_MEMORY: u8 = 2;
+
+/// Private helpers and a method next to the public interface
+const PROGRAM: &str = r#"
+struct Counter { n: Int }
+
+impl Counter {
+    func bump(&mut self) {
+        self.n = self.n + 1
+    }
+}
+
+func helper(n: Int) -> Int {
+    n * 2
+}
+
+pub func double(n: Int) -> Int {
+    helper(n)
+}
+
+#[export]
+pub func triple(n: Int32) -> Int32 {
+    n * 3
+}
+
+func main() -> Int {
+    let mut c = Counter { n: 1 }
+    c.bump()
+    double(c.n) + triple(1)
+}
+"#;
+
+fn uleb(bytes: &[u8], pos: &mut usize) -> u32 {
+    let (mut value, mut shift) = (0u32, 0);
+    loop {
+        let b = bytes[*pos];
+        *pos += 1;
+        value |= ((b & 0x7f) as u32) << shift;
+        if b & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+/// Names and kinds in the export section
+fn exports(module: &[u8]) -> Vec<(String, u8)> {
+    assert_eq!(&module[..8], b"\0asm\x01\0\0\0");
+    let mut pos = 8;
+    while pos < module.len() {
+        let id = module[pos];
+        pos += 1;
+        let size = uleb(module, &mut pos) as usize;
+        let end = pos + size;
+        if id == EXPORT_SECTION {
+            let count = uleb(module, &mut pos);
+            let mut out = Vec::new();
+            for _ in 0..count {
+                let len = uleb(module, &mut pos) as usize;
+                let name = String::from_utf8(module[pos..pos + len].to_vec()).unwrap();
+                pos += len;
+                let kind = module[pos];
+                pos += 1;
+                uleb(module, &mut pos);
+                out.push((name, kind));
+            }
+            assert_eq!(pos, end);
+            return out;
+        }
+        pos = end;
+    }
+    Vec::new()
+}
+
+fn build(dir: &Path, args: &[&str]) -> Vec<(String, u8)> {
+    compile(dir, "main.aether", PROGRAM, args).unwrap();
+    let mut names = exports(&std::fs::read(dir.join("main")).unwrap());
+    names.sort();
+    names
+}
+
+fn funcs(names: &[&str]) -> Vec<(String, u8)> {
+    let mut out: Vec<_> = names.iter().map(|n| (n.to_string(), EXPORT_FUNC)).collect();
+    out.push(("memory".into(), EXPORT_MEMORY));
+    out.sort();
+    out
+}
+
+#[test]
+fn commands_export_start_and_public_functions() {
+    let dir = scratch("wasm_wasi_exports");
+    let names = build(&dir, &["--target", "wasm32-wasi", "-o", "main"]);
+    assert_eq!(names, funcs(&["_start", "double", "triple"]));
+}
+
+#[test]
+fn modules_export_main_and_public_functions() {
+    let dir = scratch("wasm_bin_exports");
+    let names = build(&dir, &["--target", "wasm32-unknown-unknown", "-o", "main"]);
+    assert_eq!(names, funcs(&["double", "main", "triple"]));
+}
+
+#[test]
+fn libraries_export_public_functions() {
+    let dir = scratch("wasm_cdylib_exports");
+    let names = build(&dir, &["--target", "wasm32-unknown-unknown", "--crate-type", "cdylib", "-o", "main"]);
+    assert_eq!(names, funcs(&["double", "triple"]));
+}
+
+/// Records in linear memory, match, closures, doubles and output through `fd_write`
+const RUN: &str = r#"
+struct Point { x: Int, y: Int }
+
+enum Shape { Circle(Int), Rect(Int, Int), Empty }
+
+func area(s: Shape) -> Int {
+    match s {
+        Shape::Circle(r) if r > 10 => 1000,
+        Shape::Circle(r) => 3 * r * r,
+        Shape::Rect(w, h) => w * h,
+        Shape::Empty => 0,
+    }
+}
+
+func apply(f: func(Int) -> Int, x: Int) -> Int {
+    f(x)
+}
+
+func inc(x: Int) -> Int {
+    x + 1
+}
+
+func main() -> Int {
+    let p = Point { x: 3, y: 4 }
+    let k = 10
+    let add = |v: Int| v + k + p.x
+    let shapes = [Shape::Circle(2), Shape::Rect(3, 5), Shape::Empty, Shape::Circle(11)]
+    let half = 1.5 * 3 / 9.0
+    let mut total = 0
+    for s in shapes {
+        total = total + area(s)
+    }
+    for c in [111, 107, 10] {
+        __builtin_print(c)
+    }
+    if total != 1027 || apply(add, 1) != 14 || apply(inc, 1) != 2 {
+        return 1
+    }
+    if half != 0.5 || -half > 0.0 || 7.5 % half != 0.0 {
+        return 3
+    }
+    match p {
+        Point { x: 3, y } => y + 40,
+        _ => 2,
+    }
+}
+"#;
+
+/// Runs a WASI command with node's `node:wasi` and exits with its code
+const NODE_WASI: &str = r#"
+import { WASI } from 'node:wasi';
+import { readFileSync } from 'node:fs';
+const wasi = new WASI({ version: 'preview1', args: ['main'], returnOnExit: true });
+const module = await WebAssembly.compile(readFileSync(process.argv[2]));
+const instance = await WebAssembly.instantiate(module, wasi.getImportObject());
+process.exitCode = wasi.start(instance);
+"#;
+
+#[test]
+fn wasi_commands_run_under_node() {
+    if !has_tool("node") {
+        return;
+    }
+    let dir = scratch("wasm_node");
+    compile(&dir, "main.aether", RUN, &["--target", "wasm32-wasi", "-o", "main.wasm"]).unwrap();
+    std::fs::write(dir.join("run.mjs"), NODE_WASI).unwrap();
+    let out = std::process::Command::new("node")
+        .args(["--no-warnings", "run.mjs", "main.wasm"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "ok\n", "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(out.status.code(), Some(44));
+}